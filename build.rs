@@ -0,0 +1,44 @@
+//! Compiles the companion eBPF program of `stream::ebpf` when the `ebpf` feature is enabled.
+//!
+//! `clang` (with the `bpf` target) and the linux UAPI headers are required, set `BOOMNET_BPF_CLANG`
+//! to use a different compiler or `BOOMNET_BPF_OBJECT` to embed a prebuilt object instead.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{env, fs};
+
+const SOURCE: &str = "src/stream/ebpf/sock_latency.bpf.c";
+const OBJECT: &str = "sock_latency.bpf.o";
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    if env::var_os("CARGO_FEATURE_EBPF").is_none() || env::var("CARGO_CFG_TARGET_OS").as_deref() != Ok("linux") {
+        return;
+    }
+    println!("cargo:rerun-if-changed={SOURCE}");
+    println!("cargo:rerun-if-env-changed=BOOMNET_BPF_CLANG");
+    println!("cargo:rerun-if-env-changed=BOOMNET_BPF_OBJECT");
+
+    let object = PathBuf::from(env::var_os("OUT_DIR").expect("OUT_DIR is set by cargo")).join(OBJECT);
+    if let Some(prebuilt) = env::var_os("BOOMNET_BPF_OBJECT") {
+        println!("cargo:rerun-if-changed={}", Path::new(&prebuilt).display());
+        fs::copy(&prebuilt, &object).unwrap_or_else(|err| panic!("unable to copy {prebuilt:?}: {err}"));
+        return;
+    }
+
+    let clang = env::var_os("BOOMNET_BPF_CLANG").unwrap_or_else(|| "clang".into());
+    let mut command = Command::new(&clang);
+    command
+        .args(["-O2", "-g", "-target", "bpf", "-c", SOURCE, "-o"])
+        .arg(&object);
+    // asm/types.h of the UAPI headers lives under the multiarch include directory on debian based systems
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+    let multiarch = format!("/usr/include/{arch}-linux-gnu");
+    if Path::new(&multiarch).is_dir() {
+        command.arg(format!("-I{multiarch}"));
+    }
+    let status = command.status().unwrap_or_else(|err| {
+        panic!("unable to run {clang:?} to compile {SOURCE} ({err}), set BOOMNET_BPF_OBJECT to use a prebuilt object")
+    });
+    assert!(status.success(), "{clang:?} failed to compile {SOURCE}");
+}
@@ -30,10 +30,9 @@ fn main() -> anyhow::Result<()> {
 
     loop {
         let batch = ws.read_batch_ts()?;
-        let rx = batch.rx_timestamps().unwrap_or_default();
         let read_ns = clock_realtime_ns();
         for frame in batch.iter() {
-            if let WebsocketFrame::Text(_fin, body) = frame? {
+            if let (WebsocketFrame::Text(_fin, body), rx) = frame? {
                 let ready_ns = clock_realtime_ns();
                 let nic_to_kernel_ns = if rx.hw_raw_ns != 0 && read_ns != 0 {
                     read_ns.saturating_sub(rx.hw_raw_ns)
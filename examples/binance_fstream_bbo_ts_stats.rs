@@ -95,10 +95,9 @@ fn main() -> anyhow::Result<()> {
             let conn = unsafe { &mut *conns[idx].get() };
 
             let batch = conn.ws.read_batch_ts()?;
-            let rx = batch.rx_timestamps().unwrap_or_default();
             let read_ns = clock_realtime_ns();
             for frame in batch.iter() {
-                if let WebsocketFrame::Text(_fin, _body) = frame? {
+                if let (WebsocketFrame::Text(_fin, _body), rx) = frame? {
                     let ready_ns = clock_realtime_ns();
                     let nic_to_kernel_ns = if rx.hw_raw_ns != 0 && read_ns != 0 {
                         read_ns.saturating_sub(rx.hw_raw_ns) as i64
@@ -63,6 +63,10 @@ impl Selectable for TradeConnection {
     fn make_readable(&mut self) -> std::io::Result<()> {
         self.ws.make_readable()
     }
+
+    fn take_read_activity(&mut self) -> bool {
+        self.ws.take_read_activity()
+    }
 }
 
 #[cfg(feature = "ktls")]
@@ -0,0 +1,433 @@
+//! End-to-end latency benchmark of the host against a websocket venue.
+//!
+//! [`LatencyBench`] opens `conns` tuned TLS websocket connections subscribed to the same streams,
+//! spins over them on a pinned cpu and measures, for every text or binary frame, the time between the
+//! NIC hardware timestamp (`SCM_TIMESTAMPING`), the socket read and the decoded frame. Socket tuning
+//! (busy polling, rx cpu affinity, driver timestamping), memory locking and real time scheduling are
+//! applied on a best effort basis, every step that fails (or is skipped in [restricted](crate::syscalls)
+//! mode) is logged and the campaign continues so that results are always produced.
+//!
+//! Frames slower than the [outlier threshold](LatencyBench::with_outlier_capture) are additionally
+//! captured together with their context (see [`OutlierRecord`]). The scheduler activity of the
+//! benchmark thread is sampled after every batch (see [`SchedMonitor`]) so that outliers can be
+//! correlated with preemptions and cpu migrations.
+//!
+//! ## Examples
+//! ```no_run
+//! use boomnet::bench::LatencyBench;
+//!
+//! let results = LatencyBench::new("fstream.binance.com", &["btcusdt@bookTicker", "ethusdt@bookTicker"], 8, (2, 3))
+//!     .run()
+//!     .unwrap();
+//! println!("{results}");
+//! ```
+
+use crate::metrics::{FrameSample, OutlierCapture, OutlierRecord, SchedDelta, SchedMonitor, kernel_backlog};
+use crate::preset::{Preset, Tuning};
+use crate::stream::ConnectionInfo;
+use crate::stream::tcp::TcpStream;
+use crate::stream::timestamping::{TimestampingStream, configure_hwtstamp, enable_rx_timestamping};
+use crate::stream::tls::{IntoTlsStream, TlsStream};
+use crate::syscalls;
+use crate::ws::{IntoWebsocket, Websocket, WebsocketFrame};
+use core_affinity::CoreId;
+use socket2::Socket;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::os::fd::{AsRawFd, RawFd};
+use std::time::{Duration, Instant, SystemTime};
+
+const BUSY_POLL_BUDGET: libc::c_int = 64;
+const SCHED_FIFO_PRIORITY: libc::c_int = 80;
+
+type BenchWebsocket = Websocket<TlsStream<TimestampingStream<TcpStream>>>;
+
+/// Latency benchmark campaign configuration.
+#[derive(Debug, Clone)]
+pub struct LatencyBench {
+    host: String,
+    port: u16,
+    path: String,
+    conns: usize,
+    rx_cpu: usize,
+    app_cpu: usize,
+    samples: usize,
+    warmup: usize,
+    timeout: Duration,
+    net_iface: Option<String>,
+    tuning: Tuning,
+    outliers: Option<(Duration, usize)>,
+}
+
+impl LatencyBench {
+    /// Benchmark `conns` connections to the `host` subscribed to the `streams` (combined stream path).
+    /// The `cpus` are `(rx_cpu, app_cpu)`, the rx cpu is used for `SO_INCOMING_CPU` and should be the
+    /// one handling the NIC interrupts, the benchmark thread is pinned to the app cpu.
+    pub fn new(host: impl AsRef<str>, streams: &[&str], conns: usize, cpus: (usize, usize)) -> LatencyBench {
+        Self {
+            host: host.as_ref().to_owned(),
+            port: 443,
+            path: format!("/stream?streams={}", streams.join("/")),
+            conns: conns.max(1),
+            rx_cpu: cpus.0,
+            app_cpu: cpus.1,
+            samples: 200_000,
+            warmup: 1_000,
+            timeout: Duration::from_secs(600),
+            net_iface: None,
+            tuning: Preset::LowLatency.tuning(),
+            outliers: None,
+        }
+    }
+
+    /// Use different port (default 443).
+    pub fn with_port(self, port: u16) -> Self {
+        Self { port, ..self }
+    }
+
+    /// Use custom websocket path instead of the combined stream path.
+    pub fn with_path(self, path: impl AsRef<str>) -> Self {
+        Self {
+            path: path.as_ref().to_owned(),
+            ..self
+        }
+    }
+
+    /// Number of frames to measure (default 200k).
+    pub fn with_samples(self, samples: usize) -> Self {
+        Self { samples, ..self }
+    }
+
+    /// Number of frames to discard before measuring (default 1k).
+    pub fn with_warmup(self, warmup: usize) -> Self {
+        Self { warmup, ..self }
+    }
+
+    /// Maximum duration of the campaign, results are returned for the frames measured so far (default 10min).
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self { timeout, ..self }
+    }
+
+    /// Enable driver level hardware timestamping on the network interface (requires `CAP_NET_ADMIN`).
+    pub fn with_net_iface(self, net_iface: impl AsRef<str>) -> Self {
+        Self {
+            net_iface: Some(net_iface.as_ref().to_owned()),
+            ..self
+        }
+    }
+
+    /// Override the socket tuning (default [`Preset::LowLatency`]), `SO_BUSY_POLL` is best effort.
+    pub fn with_tuning(self, tuning: Tuning) -> Self {
+        Self { tuning, ..self }
+    }
+
+    /// Capture context of up to `capacity` most recent frames with `nic_to_userspace` latency above
+    /// the `threshold`.
+    pub fn with_outlier_capture(self, threshold: Duration, capacity: usize) -> Self {
+        Self {
+            outliers: Some((threshold, capacity)),
+            ..self
+        }
+    }
+
+    /// Run the campaign on the current thread.
+    pub fn run(self) -> io::Result<BenchResults> {
+        if !core_affinity::set_for_current(CoreId { id: self.app_cpu }) {
+            log::warn!("unable to pin benchmark thread to cpu {}", self.app_cpu);
+        }
+        tune_process();
+
+        let mut conns = (0..self.conns)
+            .map(|_| self.connect())
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let mut nic_to_kernel = Vec::with_capacity(self.samples);
+        let mut tls_to_userspace = Vec::with_capacity(self.samples);
+        let mut nic_to_userspace = Vec::with_capacity(self.samples);
+        let mut outliers = self
+            .outliers
+            .map(|(threshold, capacity)| OutlierCapture::new(threshold, capacity));
+        let mut sched = SchedMonitor::new()
+            .inspect_err(|err| log::warn!("scheduler activity will not be monitored: {err}"))
+            .ok();
+        let mut batch_ready = Vec::new();
+        let mut warmup = self.warmup;
+        let mut missing_hw = 0;
+        let mut messages = 0;
+
+        let start = Instant::now();
+        'campaign: while messages < self.samples && start.elapsed() < self.timeout {
+            for (conn, (ws, fd)) in conns.iter_mut().enumerate() {
+                let batch = ws.read_batch_ts()?;
+                let read_ns = clock_realtime_ns();
+                batch_ready.clear();
+                for frame in batch {
+                    if let (WebsocketFrame::Text(..) | WebsocketFrame::Binary(..), rx) = frame? {
+                        let ready_ns = clock_realtime_ns();
+                        if warmup > 0 {
+                            warmup -= 1;
+                            continue;
+                        }
+                        if rx.hw_raw_ns != 0 {
+                            nic_to_kernel.push(read_ns.saturating_sub(rx.hw_raw_ns));
+                            nic_to_userspace.push(ready_ns.saturating_sub(rx.hw_raw_ns));
+                        } else {
+                            missing_hw += 1;
+                        }
+                        tls_to_userspace.push(ready_ns.saturating_sub(read_ns));
+                        batch_ready.push((rx.hw_raw_ns, ready_ns));
+                        messages += 1;
+                        if messages >= self.samples {
+                            break;
+                        }
+                    }
+                }
+                let delta = sched.as_mut().and_then(|sched| sched.sample().ok());
+                // batch size is only known once the batch has been consumed
+                if let Some(outliers) = outliers.as_mut() {
+                    if let Some(delta) = delta {
+                        outliers.observe_sched(delta);
+                    }
+                    let fd = *fd;
+                    for (rx_ns, ready_ns) in batch_ready.iter().copied() {
+                        let sample = FrameSample {
+                            connection: conn as u64,
+                            rx_ns,
+                            user_ns: ready_ns,
+                            batch_size: batch_ready.len(),
+                        };
+                        outliers.observe(sample, || kernel_backlog(fd).ok());
+                    }
+                }
+                if messages >= self.samples {
+                    break 'campaign;
+                }
+            }
+        }
+
+        Ok(BenchResults {
+            host: self.host,
+            conns: self.conns,
+            messages,
+            missing_hw,
+            elapsed: start.elapsed(),
+            nic_to_kernel: LatencyStats::from_samples(&mut nic_to_kernel),
+            tls_to_userspace: LatencyStats::from_samples(&mut tls_to_userspace),
+            nic_to_userspace: LatencyStats::from_samples(&mut nic_to_userspace),
+            outliers: outliers.map_or_else(Vec::new, |outliers| outliers.records().copied().collect()),
+            sched: sched.as_ref().map(SchedMonitor::total),
+            disturbed_batches: sched.as_ref().map_or(0, SchedMonitor::disturbed),
+        })
+    }
+
+    fn connect(&self) -> io::Result<(BenchWebsocket, RawFd)> {
+        // SO_BUSY_POLL is set on a best effort basis below rather than failing the connection
+        let stream = ConnectionInfo::new(&self.host, self.port)
+            .with_cpu(self.rx_cpu)
+            .with_tuning(self.tuning.without_busy_poll())
+            .with_socket_config(tune_busy_poll)
+            .into_tcp_stream()?;
+        let fd = stream.as_raw_fd();
+        if let Some(busy_poll) = self.tuning.busy_poll() {
+            let micros = busy_poll.as_micros().min(libc::c_int::MAX as u128) as libc::c_int;
+            set_socket_option(fd, "setsockopt(SO_BUSY_POLL)", libc::SO_BUSY_POLL, micros);
+        }
+        if let Some(net_iface) = self.net_iface.as_deref() {
+            if let Err(err) = configure_hwtstamp(fd, net_iface) {
+                log::warn!("hardware timestamping not configured for {net_iface}: {err}");
+            }
+        }
+        if let Err(err) = enable_rx_timestamping(fd) {
+            log::warn!("rx timestamping not enabled: {err}");
+        }
+        let ws = TimestampingStream::new(stream)
+            .into_tls_stream()?
+            .into_websocket(&self.path);
+        Ok((ws, fd))
+    }
+}
+
+/// Busy poll budget and preference on top of the `SO_BUSY_POLL` from the tuning.
+fn tune_busy_poll(socket: &Socket) -> io::Result<()> {
+    let fd = socket.as_raw_fd();
+    for (name, option, value) in [
+        ("setsockopt(SO_BUSY_POLL_BUDGET)", libc::SO_BUSY_POLL_BUDGET, BUSY_POLL_BUDGET),
+        ("setsockopt(SO_PREFER_BUSY_POLL)", libc::SO_PREFER_BUSY_POLL, 1),
+        ("setsockopt(SO_RCVLOWAT)", libc::SO_RCVLOWAT, 1),
+    ] {
+        set_socket_option(fd, name, option, value);
+    }
+    Ok(())
+}
+
+/// Set the `SOL_SOCKET` level `option` (best effort), skipped in restricted mode.
+fn set_socket_option(fd: RawFd, name: &str, option: libc::c_int, value: libc::c_int) {
+    if syscalls::is_restricted() {
+        return;
+    }
+    // SAFETY: value points to a valid c_int
+    let rc = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            option,
+            (&value as *const libc::c_int).cast(),
+            size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if rc < 0 {
+        log::warn!("{}", syscalls::blocked(name, io::Error::last_os_error()));
+    }
+}
+
+/// Lock memory and switch to real time scheduling (best effort).
+fn tune_process() {
+    if syscalls::is_restricted() {
+        log::debug!("restricted mode: skipping mlockall and SCHED_FIFO");
+        return;
+    }
+    // SAFETY: no pointers involved
+    if unsafe { libc::mlockall(libc::MCL_CURRENT | libc::MCL_FUTURE) } != 0 {
+        log::warn!("{}", syscalls::blocked("mlockall", io::Error::last_os_error()));
+    }
+    let param = libc::sched_param {
+        sched_priority: SCHED_FIFO_PRIORITY,
+    };
+    // SAFETY: param is a valid sched_param
+    if unsafe { libc::sched_setscheduler(0, libc::SCHED_FIFO, &param) } != 0 {
+        log::warn!("{}", syscalls::blocked("sched_setscheduler", io::Error::last_os_error()));
+    }
+}
+
+#[inline]
+fn clock_realtime_ns() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+}
+
+/// Summary statistics of latency samples in nanoseconds.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct LatencyStats {
+    pub count: usize,
+    pub min: u64,
+    pub mean: f64,
+    pub stddev: f64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub p999: u64,
+    pub max: u64,
+}
+
+impl LatencyStats {
+    /// Compute statistics ignoring zero samples (the `samples` are sorted in place).
+    pub fn from_samples(samples: &mut Vec<u64>) -> LatencyStats {
+        samples.retain(|sample| *sample > 0);
+        if samples.is_empty() {
+            return LatencyStats::default();
+        }
+        samples.sort_unstable();
+        let count = samples.len();
+        let mean = samples.iter().map(|sample| *sample as f64).sum::<f64>() / count as f64;
+        let variance = samples
+            .iter()
+            .map(|sample| (*sample as f64 - mean).powi(2))
+            .sum::<f64>()
+            / count as f64;
+        let percentile = |quantile: f64| samples[((quantile * (count - 1) as f64).round() as usize).min(count - 1)];
+        LatencyStats {
+            count,
+            min: samples[0],
+            mean,
+            stddev: variance.sqrt(),
+            p50: percentile(0.5),
+            p90: percentile(0.9),
+            p99: percentile(0.99),
+            p999: percentile(0.999),
+            max: samples[count - 1],
+        }
+    }
+}
+
+impl Display for LatencyStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "n={} min={} mean={:.1} stddev={:.1} p50={} p90={} p99={} p99.9={} max={}",
+            self.count, self.min, self.mean, self.stddev, self.p50, self.p90, self.p99, self.p999, self.max
+        )
+    }
+}
+
+/// Results of the [`LatencyBench`] campaign, all latencies are in nanoseconds.
+#[derive(Debug, Clone)]
+pub struct BenchResults {
+    pub host: String,
+    pub conns: usize,
+    /// Number of measured frames.
+    pub messages: usize,
+    /// Number of measured frames without hardware timestamp.
+    pub missing_hw: usize,
+    pub elapsed: Duration,
+    /// NIC hardware timestamp to the socket read returning.
+    pub nic_to_kernel: LatencyStats,
+    /// Socket read (including decryption) returning to the decoded frame.
+    pub tls_to_userspace: LatencyStats,
+    /// NIC hardware timestamp to the decoded frame.
+    pub nic_to_userspace: LatencyStats,
+    /// Context of the slowest frames, oldest first (empty unless outlier capture is enabled).
+    pub outliers: Vec<OutlierRecord>,
+    /// Scheduler activity of the benchmark thread over the campaign (`None` if it could not be monitored).
+    pub sched: Option<SchedDelta>,
+    /// Number of batches after which the benchmark thread was found preempted or migrated.
+    pub disturbed_batches: u64,
+}
+
+impl Display for BenchResults {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "host={} conns={} messages={} missing_hw={} elapsed={:?}",
+            self.host, self.conns, self.messages, self.missing_hw, self.elapsed
+        )?;
+        writeln!(f, "nic_to_kernel_ns: {}", self.nic_to_kernel)?;
+        writeln!(f, "tls_to_userspace_ns: {}", self.tls_to_userspace)?;
+        write!(f, "nic_to_userspace_ns: {}", self.nic_to_userspace)?;
+        if let Some(sched) = self.sched {
+            write!(
+                f,
+                "\nsched: disturbed_batches={} preemptions={} voluntary_switches={} migrations={}",
+                self.disturbed_batches, sched.involuntary_switches, sched.voluntary_switches, sched.migrations
+            )?;
+        }
+        for outlier in &self.outliers {
+            write!(f, "\noutlier: {outlier}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_compute_latency_stats() {
+        let mut samples = (0..=1000).rev().collect::<Vec<u64>>();
+        let stats = LatencyStats::from_samples(&mut samples);
+        assert_eq!(1000, stats.count);
+        assert_eq!(1, stats.min);
+        assert_eq!(1000, stats.max);
+        assert_eq!(500.5, stats.mean);
+        assert_eq!(501, stats.p50);
+        assert_eq!(900, stats.p90);
+        assert_eq!(990, stats.p99);
+        assert_eq!(999, stats.p999);
+    }
+
+    #[test]
+    fn should_handle_no_samples() {
+        assert_eq!(LatencyStats::default(), LatencyStats::from_samples(&mut vec![0, 0]));
+    }
+}
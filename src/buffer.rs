@@ -10,15 +10,32 @@ use std::{io, ptr};
 // re-export
 pub use pool::*;
 
+#[cfg(all(feature = "fence", debug_assertions))]
+pub mod fence;
+
 const DEFAULT_INITIAL_CAPACITY: usize = 32768;
 
+#[cfg(not(all(feature = "fence", debug_assertions)))]
+type Bytes = Vec<u8>;
+#[cfg(all(feature = "fence", debug_assertions))]
+type Bytes = fence::FencedBytes;
+
 #[derive(Debug)]
 pub struct ReadBuffer<const CHUNK_SIZE: usize, const INITIAL_CAPACITY: usize = DEFAULT_INITIAL_CAPACITY> {
-    inner: Vec<u8>,
+    inner: Bytes,
     head: usize,
     tail: usize,
 }
 
+#[inline]
+fn zeroed(len: usize) -> Bytes {
+    #[cfg(not(all(feature = "fence", debug_assertions)))]
+    let bytes = vec![0u8; len];
+    #[cfg(all(feature = "fence", debug_assertions))]
+    let bytes = Bytes::zeroed(len);
+    bytes
+}
+
 /// Reading mode that controls [ReadBuffer::read_from] data limit.
 trait ReadMode {
     fn read_buffer(buffer: &mut [u8], offset: usize, chunk_size: usize, available: usize) -> &'static mut [u8];
@@ -59,7 +76,7 @@ impl<const CHUNK_SIZE: usize, const INITIAL_CAPACITY: usize> ReadBuffer<CHUNK_SI
             "CHUNK_SIZE ({CHUNK_SIZE}) must be less or equal than {INITIAL_CAPACITY}"
         );
         Self {
-            inner: vec![0u8; INITIAL_CAPACITY],
+            inner: zeroed(INITIAL_CAPACITY),
             head: 0,
             tail: 0,
         }
@@ -68,7 +85,7 @@ impl<const CHUNK_SIZE: usize, const INITIAL_CAPACITY: usize> ReadBuffer<CHUNK_SI
     #[inline]
     pub const fn empty() -> Self {
         Self {
-            inner: Vec::new(),
+            inner: Bytes::new(),
             head: 0,
             tail: 0,
         }
@@ -81,6 +98,8 @@ impl<const CHUNK_SIZE: usize, const INITIAL_CAPACITY: usize> ReadBuffer<CHUNK_SI
             "CHUNK_SIZE ({CHUNK_SIZE}) must be less or equal than {INITIAL_CAPACITY}"
         );
         assert!(bytes.len() >= INITIAL_CAPACITY, "bytes len must be equal or greater than {INITIAL_CAPACITY}");
+        #[cfg(all(feature = "fence", debug_assertions))]
+        let bytes = Bytes::from(bytes);
         ReadBuffer {
             inner: bytes,
             head: 0,
@@ -90,7 +109,11 @@ impl<const CHUNK_SIZE: usize, const INITIAL_CAPACITY: usize> ReadBuffer<CHUNK_SI
 
     #[inline]
     pub fn into_vec(self) -> Vec<u8> {
-        self.inner
+        #[cfg(not(all(feature = "fence", debug_assertions)))]
+        let bytes = self.inner;
+        #[cfg(all(feature = "fence", debug_assertions))]
+        let bytes = self.inner.to_vec();
+        bytes
     }
 
     #[inline]
@@ -117,7 +140,7 @@ impl<const CHUNK_SIZE: usize, const INITIAL_CAPACITY: usize> ReadBuffer<CHUNK_SI
     #[inline]
     fn read_from_with_mode<S: Read, M: ReadMode>(&mut self, stream: &mut S) -> io::Result<()> {
         #[cold]
-        fn grow(buf: &mut Vec<u8>) {
+        fn grow(buf: &mut Bytes) {
             buf.resize(buf.len() * 2, 0u8);
         }
 
@@ -130,6 +153,9 @@ impl<const CHUNK_SIZE: usize, const INITIAL_CAPACITY: usize> ReadBuffer<CHUNK_SI
             buf.head = 0;
         }
 
+        #[cfg(all(feature = "fence", debug_assertions))]
+        let stale_tail = self.tail;
+
         // compact
         if self.head > 0 && self.available() > 0 {
             compact(self);
@@ -141,6 +167,10 @@ impl<const CHUNK_SIZE: usize, const INITIAL_CAPACITY: usize> ReadBuffer<CHUNK_SI
             self.tail = 0;
         }
 
+        // views handed out before this read must not be used anymore, make them visibly stale
+        #[cfg(all(feature = "fence", debug_assertions))]
+        fence::poison(&mut self.inner[self.tail..stale_tail]);
+
         // ensure capacity for at least one chunk
         if self.tail + CHUNK_SIZE > self.inner.capacity() {
             grow(&mut self.inner);
@@ -181,6 +211,20 @@ impl<const CHUNK_SIZE: usize, const INITIAL_CAPACITY: usize> ReadBuffer<CHUNK_SI
         }
     }
 
+    /// Same as [`ReadBuffer::consume_next_unchecked`] but the view can be modified in place (e.g. to
+    /// unmask the payload).
+    ///
+    /// # Safety
+    /// This function should only be called after `available` bytes are known.
+    #[inline]
+    pub unsafe fn consume_next_mut_unchecked(&mut self, len: usize) -> &'static mut [u8] {
+        unsafe {
+            let consumed_view = &mut *ptr::slice_from_raw_parts_mut(self.inner.as_mut_ptr().add(self.head), len);
+            self.head += len;
+            consumed_view
+        }
+    }
+
     #[inline]
     pub const fn consume_next_byte(&mut self) -> Option<u8> {
         match self.available() >= 1 {
@@ -529,6 +573,20 @@ mod tests {
         assert_eq!(16, buf.inner.len());
     }
 
+    #[test]
+    #[cfg(all(feature = "fence", debug_assertions))]
+    fn should_poison_consumed_bytes_on_next_read() {
+        let mut buf = ReadBuffer::<8>::new();
+        let mut stream = Cursor::new(b"hello world!");
+        buf.read_from(&mut stream).unwrap();
+        let stale = buf.consume_next(8).unwrap();
+        assert_eq!(b"hello wo", stale);
+        buf.read_from(&mut stream).unwrap();
+        assert_eq!(b"rld!", buf.view());
+        // the part of the stale view not overwritten by the read
+        assert_eq!([fence::POISON; 4], stale[4..]);
+    }
+
     #[test]
     fn should_handle_reader_with_no_data() {
         struct StreamWithNoData;
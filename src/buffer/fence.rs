@@ -0,0 +1,214 @@
+//! Guard page fenced storage for [`ReadBuffer`](crate::buffer::ReadBuffer), enabled with the `fence`
+//! feature in debug builds.
+//!
+//! Every buffer gets its own mapping with an inaccessible guard page on either side and the data placed
+//! right against the trailing guard page, so reading or writing past the end of the buffer faults with
+//! `SIGSEGV` on the very first byte. Memory released by the buffer (when it grows or goes back to the
+//! pool) is never handed out again but made inaccessible, so that a frame view held across a read that
+//! reallocated the buffer faults instead of reading another connection's bytes. On every read the bytes
+//! consumed by the previous batches that the read does not overwrite are filled with [`POISON`], which
+//! makes stale views into the same buffer easy to spot.
+
+use std::fmt::{Debug, Formatter};
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::ptr::NonNull;
+
+/// Pattern written over the consumed bytes, as a websocket frame header it has reserved bits and op code
+/// set so that decoding stale bytes fails straight away.
+pub const POISON: u8 = 0xDB;
+
+/// Byte storage placed between two guard pages.
+pub struct FencedBytes {
+    // the whole mapping including the guard pages, null when empty
+    map: *mut u8,
+    map_len: usize,
+    ptr: *mut u8,
+    len: usize,
+}
+
+// SAFETY: the storage is uniquely owned, just like `Vec<u8>`
+unsafe impl Send for FencedBytes {}
+unsafe impl Sync for FencedBytes {}
+
+impl Debug for FencedBytes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FencedBytes")
+            .field("ptr", &self.ptr)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl FencedBytes {
+    pub const fn new() -> Self {
+        Self {
+            map: ptr::null_mut(),
+            map_len: 0,
+            ptr: NonNull::dangling().as_ptr(),
+            len: 0,
+        }
+    }
+
+    pub fn zeroed(len: usize) -> Self {
+        if len == 0 {
+            return Self::new();
+        }
+        let page = page_size();
+        let data_len = len.div_ceil(page) * page;
+        let map_len = data_len + 2 * page;
+        unsafe {
+            let map = libc::mmap(
+                ptr::null_mut(),
+                map_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            assert_ne!(libc::MAP_FAILED, map, "unable to map fenced buffer");
+            let map = map as *mut u8;
+            protect(map, page);
+            protect(map.add(page + data_len), page);
+            Self {
+                map,
+                map_len,
+                ptr: map.add(page + data_len - len),
+                len,
+            }
+        }
+    }
+
+    #[inline]
+    pub const fn as_ptr(&self) -> *const u8 {
+        self.ptr
+    }
+
+    #[inline]
+    pub const fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.ptr
+    }
+
+    #[inline]
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    pub const fn capacity(&self) -> usize {
+        self.len
+    }
+
+    /// Move the bytes to a new mapping of `len` bytes, the current one is retired.
+    pub fn resize(&mut self, len: usize, value: u8) {
+        let mut resized = Self::zeroed(len);
+        let copied = self.len.min(len);
+        resized[..copied].copy_from_slice(&self[..copied]);
+        resized[copied..].fill(value);
+        *self = resized;
+    }
+}
+
+impl Default for FencedBytes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for FencedBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: `ptr` is valid for `len` bytes (or dangling with zero length)
+        unsafe { &*ptr::slice_from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl DerefMut for FencedBytes {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: `ptr` is valid for `len` bytes (or dangling with zero length)
+        unsafe { &mut *ptr::slice_from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl From<Vec<u8>> for FencedBytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        let mut fenced = Self::zeroed(bytes.len());
+        fenced.copy_from_slice(&bytes);
+        fenced
+    }
+}
+
+impl Drop for FencedBytes {
+    fn drop(&mut self) {
+        if !self.map.is_null() {
+            // the address range stays reserved so that dangling views fault rather than alias a new
+            // mapping, only the physical pages are given back
+            unsafe {
+                libc::madvise(self.map.cast(), self.map_len, libc::MADV_DONTNEED);
+                protect(self.map, self.map_len);
+            }
+        }
+    }
+}
+
+/// Overwrite bytes that must not be read anymore.
+#[inline]
+pub fn poison(bytes: &mut [u8]) {
+    bytes.fill(POISON);
+}
+
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+unsafe fn protect(addr: *mut u8, len: usize) {
+    let result = unsafe { libc::mprotect(addr.cast(), len, libc::PROT_NONE) };
+    assert_eq!(0, result, "unable to protect fenced buffer");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // runs `access` in a forked child and returns the signal it has been killed with
+    fn fault_signal(access: impl FnOnce()) -> Option<i32> {
+        unsafe {
+            match libc::fork() {
+                0 => {
+                    access();
+                    libc::_exit(0);
+                }
+                pid => {
+                    let mut status = 0;
+                    assert_eq!(pid, libc::waitpid(pid, &mut status, 0));
+                    libc::WIFSIGNALED(status).then(|| libc::WTERMSIG(status))
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn should_fault_on_overrun_and_after_release() {
+        let mut bytes = FencedBytes::zeroed(100);
+        bytes[99] = 1;
+        assert_eq!(None, fault_signal(|| unsafe { ptr::write_volatile(bytes.as_mut_ptr().add(99), 2) }));
+        let end = unsafe { bytes.as_mut_ptr().add(100) };
+        assert_eq!(Some(libc::SIGSEGV), fault_signal(|| unsafe { ptr::write_volatile(end, 1) }));
+
+        let start = bytes.as_ptr();
+        bytes.resize(200, 0);
+        assert_eq!(1, bytes[99]);
+        assert_eq!(
+            Some(libc::SIGSEGV),
+            fault_signal(|| {
+                let _ = unsafe { ptr::read_volatile(start) };
+            })
+        );
+    }
+}
@@ -0,0 +1,281 @@
+//! Report of what this particular build can do on this particular host.
+//!
+//! [`capabilities`] lists the crate features the build has been compiled with and probes the host for
+//! the platform backends that depend on it, so applications (and support scripts) can branch on the
+//! result instead of failing deep inside the connection setup:
+//! - `ktls`: the kernel `tls` upper layer protocol is available (`modprobe tls`),
+//! - `timestamping`: RX timestamping can be enabled on a socket (`SO_TIMESTAMPING`), together with the
+//!   network interfaces that support hardware RX timestamping,
+//! - `ebpf`: the thread holds the capabilities required to load the socket latency tracer.
+//!
+//! Probes that would issue an optional system call are not run in [restricted](crate::syscalls) mode,
+//! the backend is reported as unavailable instead.
+//!
+//! ## Examples
+//! ```
+//! let report = boomnet::capabilities();
+//! println!("{report}");
+//! if report.ktls.is_available() {
+//!     // offload the tls record layer to the kernel
+//! }
+//! assert_eq!(report.features.contains(&"ws"), cfg!(feature = "ws"));
+//! ```
+
+use std::fmt::{Display, Formatter, Write};
+
+/// Features the crate can be compiled with.
+const FEATURES: &[(&str, bool)] = &[
+    ("mio", cfg!(feature = "mio")),
+    ("rustls-native", cfg!(feature = "rustls-native")),
+    ("rustls-webpki", cfg!(feature = "rustls-webpki")),
+    ("openssl", cfg!(feature = "openssl")),
+    ("ktls", cfg!(feature = "ktls")),
+    ("http", cfg!(feature = "http")),
+    ("ws", cfg!(feature = "ws")),
+    ("deflate", cfg!(feature = "deflate")),
+    ("ext", cfg!(feature = "ext")),
+    ("timestamping", cfg!(feature = "timestamping")),
+    ("protobuf", cfg!(feature = "protobuf")),
+    ("keylog", cfg!(feature = "keylog")),
+    ("profile", cfg!(feature = "profile")),
+    ("fence", cfg!(feature = "fence")),
+    ("usdt", cfg!(feature = "usdt")),
+    ("ebpf", cfg!(feature = "ebpf")),
+    ("model", cfg!(feature = "model")),
+    ("parquet", cfg!(feature = "parquet")),
+    ("clickhouse", cfg!(feature = "clickhouse")),
+];
+
+/// Availability of a platform backend.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Support {
+    /// Compiled in and usable on this host.
+    Available,
+    /// Compiled in, but not usable on this host for the given reason.
+    Unavailable(String),
+    /// Not compiled in, either the feature is disabled or the platform is not supported.
+    NotCompiled,
+}
+
+impl Support {
+    /// Returns `true` if the backend can be used.
+    pub const fn is_available(&self) -> bool {
+        matches!(self, Support::Available)
+    }
+
+    fn to_json(&self) -> String {
+        match self {
+            Support::Available => r#"{"available":true}"#.to_owned(),
+            Support::Unavailable(reason) => format!(r#"{{"available":false,"reason":"{}"}}"#, escape(reason)),
+            Support::NotCompiled => r#"{"available":false,"compiled":false}"#.to_owned(),
+        }
+    }
+}
+
+impl Display for Support {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Support::Available => write!(f, "available"),
+            Support::Unavailable(reason) => write!(f, "unavailable ({reason})"),
+            Support::NotCompiled => write!(f, "not compiled"),
+        }
+    }
+}
+
+/// Compiled features and probed platform backends, see the [module](self) documentation.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CapabilityReport {
+    /// Crate version.
+    pub version: &'static str,
+    /// Target operating system, such as `linux`.
+    pub os: &'static str,
+    /// Target architecture, such as `x86_64`.
+    pub arch: &'static str,
+    /// Enabled crate features.
+    pub features: Vec<&'static str>,
+    /// Restricted mode was enabled when the report was created.
+    pub restricted: bool,
+    /// Kernel TLS offload.
+    pub ktls: Support,
+    /// Socket RX timestamping.
+    pub timestamping: Support,
+    /// Network interfaces that support hardware RX timestamping (only probed with `timestamping`).
+    pub hw_timestamping: Vec<String>,
+    /// eBPF socket latency tracer.
+    pub ebpf: Support,
+}
+
+impl CapabilityReport {
+    /// Render the report as a json object.
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        let _ = write!(
+            json,
+            r#"{{"version":"{}","os":"{}","arch":"{}","features":{},"restricted":{},"ktls":{},"timestamping":{},"hw_timestamping":{},"ebpf":{}}}"#,
+            self.version,
+            self.os,
+            self.arch,
+            json_list(self.features.iter().copied()),
+            self.restricted,
+            self.ktls.to_json(),
+            self.timestamping.to_json(),
+            json_list(self.hw_timestamping.iter().map(String::as_str)),
+            self.ebpf.to_json(),
+        );
+        json
+    }
+}
+
+impl Display for CapabilityReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "boomnet {} ({}-{})", self.version, self.os, self.arch)?;
+        writeln!(f, "features: {}", self.features.join(", "))?;
+        writeln!(f, "restricted: {}", self.restricted)?;
+        writeln!(f, "ktls: {}", self.ktls)?;
+        writeln!(f, "timestamping: {}", self.timestamping)?;
+        writeln!(f, "hw timestamping: {}", self.hw_timestamping.join(", "))?;
+        write!(f, "ebpf: {}", self.ebpf)
+    }
+}
+
+/// Report the compiled features and probe the platform backends.
+pub fn capabilities() -> CapabilityReport {
+    CapabilityReport {
+        version: env!("CARGO_PKG_VERSION"),
+        os: std::env::consts::OS,
+        arch: std::env::consts::ARCH,
+        features: FEATURES
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(feature, _)| *feature)
+            .collect(),
+        restricted: crate::syscalls::is_restricted(),
+        ktls: probe_ktls(),
+        timestamping: probe_timestamping(),
+        hw_timestamping: probe_hw_timestamping(),
+        ebpf: probe_ebpf(),
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn json_list<'a>(items: impl Iterator<Item = &'a str>) -> String {
+    let items = items.map(|item| format!(r#""{}""#, escape(item))).collect::<Vec<_>>();
+    format!("[{}]", items.join(","))
+}
+
+#[cfg(all(target_os = "linux", feature = "ktls"))]
+fn probe_ktls() -> Support {
+    match std::fs::read_to_string("/proc/sys/net/ipv4/tcp_available_ulp") {
+        Ok(ulps) if ulps.split_whitespace().any(|ulp| ulp == "tls") => Support::Available,
+        Ok(_) => Support::Unavailable("tls kernel module is not loaded".to_owned()),
+        Err(err) => Support::Unavailable(format!("unable to read available ulps: {err}")),
+    }
+}
+
+#[cfg(not(all(target_os = "linux", feature = "ktls")))]
+const fn probe_ktls() -> Support {
+    Support::NotCompiled
+}
+
+#[cfg(all(target_os = "linux", feature = "timestamping"))]
+fn probe_timestamping() -> Support {
+    use std::os::fd::AsRawFd;
+
+    if crate::syscalls::is_restricted() {
+        return Support::Unavailable("not probed in restricted mode".to_owned());
+    }
+    let socket = match std::net::UdpSocket::bind(("127.0.0.1", 0)) {
+        Ok(socket) => socket,
+        Err(err) => return Support::Unavailable(format!("unable to create socket: {err}")),
+    };
+    match crate::stream::timestamping::enable_rx_timestamping(socket.as_raw_fd()) {
+        Ok(()) => Support::Available,
+        Err(err) => Support::Unavailable(err.to_string()),
+    }
+}
+
+#[cfg(not(all(target_os = "linux", feature = "timestamping")))]
+const fn probe_timestamping() -> Support {
+    Support::NotCompiled
+}
+
+#[cfg(all(target_os = "linux", feature = "timestamping"))]
+fn probe_hw_timestamping() -> Vec<String> {
+    if crate::syscalls::is_restricted() {
+        return Vec::new();
+    }
+    let Ok(entries) = std::fs::read_dir("/sys/class/net") else {
+        return Vec::new();
+    };
+    let mut net_ifaces = entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|net_iface| crate::host::hw_timestamping_supported(net_iface).unwrap_or(false))
+        .collect::<Vec<_>>();
+    net_ifaces.sort_unstable();
+    net_ifaces
+}
+
+#[cfg(not(all(target_os = "linux", feature = "timestamping")))]
+const fn probe_hw_timestamping() -> Vec<String> {
+    Vec::new()
+}
+
+#[cfg(all(target_os = "linux", feature = "ebpf"))]
+fn probe_ebpf() -> Support {
+    use crate::privilege::{Capabilities, Capability};
+
+    match Capabilities::current() {
+        Ok(caps)
+            if caps.is_effective(Capability::SysAdmin)
+                || (caps.is_effective(Capability::Bpf) && caps.is_effective(Capability::NetAdmin)) =>
+        {
+            Support::Available
+        }
+        Ok(_) => Support::Unavailable("CAP_BPF and CAP_NET_ADMIN (or CAP_SYS_ADMIN) are required".to_owned()),
+        Err(err) => Support::Unavailable(err.to_string()),
+    }
+}
+
+#[cfg(not(all(target_os = "linux", feature = "ebpf")))]
+const fn probe_ebpf() -> Support {
+    Support::NotCompiled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_report_compiled_features() {
+        let report = capabilities();
+        assert_eq!(env!("CARGO_PKG_VERSION"), report.version);
+        assert_eq!(cfg!(feature = "ws"), report.features.contains(&"ws"));
+        assert_eq!(cfg!(feature = "mio"), report.features.contains(&"mio"));
+        if !cfg!(feature = "ktls") {
+            assert_eq!(Support::NotCompiled, report.ktls);
+        }
+        assert!(report.to_string().starts_with("boomnet "));
+    }
+
+    #[test]
+    fn should_render_report_as_json() {
+        let report = CapabilityReport {
+            version: "1.0.0",
+            os: "linux",
+            arch: "x86_64",
+            features: vec!["mio", "ws"],
+            restricted: false,
+            ktls: Support::Unavailable("tls kernel module is not loaded".to_owned()),
+            timestamping: Support::Available,
+            hw_timestamping: vec!["eth0".to_owned()],
+            ebpf: Support::NotCompiled,
+        };
+        assert_eq!(
+            r#"{"version":"1.0.0","os":"linux","arch":"x86_64","features":["mio","ws"],"restricted":false,"ktls":{"available":false,"reason":"tls kernel module is not loaded"},"timestamping":{"available":true},"hw_timestamping":["eth0"],"ebpf":{"available":false,"compiled":false}}"#,
+            report.to_json()
+        );
+    }
+}
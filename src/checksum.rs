@@ -0,0 +1,304 @@
+//! Payload checksums for detecting silent corruption or divergent content between redundant paths.
+//!
+//! [`xxh64`] is a fast non-cryptographic checksum (XXH64) that can be computed over every delivered
+//! payload on the hot path. [`ChecksumComparator`] pairs the checksums of two paths that are expected
+//! to carry the same frames in the same order, such as the primary and backup connection to a
+//! redundant feed, or a journal replayed against the live connection, and reports the first frames
+//! whose content differs. The paths do not have to be in lock step, the checksums of the path that
+//! is ahead are kept (up to `max_pending`) until the other path catches up.
+//!
+//! Frames that are expected to differ between the paths (heartbeats, per connection acks) have to be
+//! filtered out before they are compared. With websockets the comparison is usually installed as the
+//! last [`Checksum`](crate::ws::middleware::Checksum) layer of the middleware chain.
+//!
+//! ## Examples
+//! ```
+//! use boomnet::checksum::{ChecksumComparator, Path};
+//!
+//! let mut comparator = ChecksumComparator::new().with_divergence_handler(|divergence| {
+//!     eprintln!("feed content diverged: {divergence}");
+//! });
+//!
+//! assert!(comparator.compare(Path::Primary, b"trade 1").is_none());
+//! assert!(comparator.compare(Path::Primary, b"trade 2").is_none());
+//! assert!(comparator.compare(Path::Backup, b"trade 1").is_none());
+//! assert!(comparator.compare(Path::Backup, b"trade 3").is_some());
+//! assert_eq!((2, 1), (comparator.compared(), comparator.divergences()));
+//! ```
+
+use log::warn;
+use std::collections::VecDeque;
+use std::fmt::{Debug, Display, Formatter};
+
+const PRIME_1: u64 = 0x9E3779B185EBCA87;
+const PRIME_2: u64 = 0xC2B2AE3D27D4EB4F;
+const PRIME_3: u64 = 0x165667B19E3779F9;
+const PRIME_4: u64 = 0x85EBCA77C2B2AE63;
+const PRIME_5: u64 = 0x27D4EB2F165667C5;
+
+const DEFAULT_MAX_PENDING: usize = 1024;
+
+/// XXH64 checksum of the `data` with the `seed`.
+pub fn xxh64(data: &[u8], seed: u64) -> u64 {
+    let mut rest = data;
+    let mut hash = if data.len() >= 32 {
+        let mut lanes = [
+            seed.wrapping_add(PRIME_1).wrapping_add(PRIME_2),
+            seed.wrapping_add(PRIME_2),
+            seed,
+            seed.wrapping_sub(PRIME_1),
+        ];
+        while rest.len() >= 32 {
+            for (lane, chunk) in lanes.iter_mut().zip(rest[..32].chunks_exact(8)) {
+                *lane = round(*lane, read_u64(chunk));
+            }
+            rest = &rest[32..];
+        }
+        let mut hash = lanes[0]
+            .rotate_left(1)
+            .wrapping_add(lanes[1].rotate_left(7))
+            .wrapping_add(lanes[2].rotate_left(12))
+            .wrapping_add(lanes[3].rotate_left(18));
+        for lane in lanes {
+            hash = (hash ^ round(0, lane)).wrapping_mul(PRIME_1).wrapping_add(PRIME_4);
+        }
+        hash
+    } else {
+        seed.wrapping_add(PRIME_5)
+    };
+    hash = hash.wrapping_add(data.len() as u64);
+
+    while rest.len() >= 8 {
+        hash ^= round(0, read_u64(rest));
+        hash = hash.rotate_left(27).wrapping_mul(PRIME_1).wrapping_add(PRIME_4);
+        rest = &rest[8..];
+    }
+    if rest.len() >= 4 {
+        hash ^= (u32::from_le_bytes(rest[..4].try_into().unwrap()) as u64).wrapping_mul(PRIME_1);
+        hash = hash.rotate_left(23).wrapping_mul(PRIME_2).wrapping_add(PRIME_3);
+        rest = &rest[4..];
+    }
+    for byte in rest {
+        hash ^= (*byte as u64).wrapping_mul(PRIME_5);
+        hash = hash.rotate_left(11).wrapping_mul(PRIME_1);
+    }
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(PRIME_2);
+    hash ^= hash >> 29;
+    hash = hash.wrapping_mul(PRIME_3);
+    hash ^ (hash >> 32)
+}
+
+#[inline]
+const fn round(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(PRIME_2))
+        .rotate_left(31)
+        .wrapping_mul(PRIME_1)
+}
+
+#[inline]
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+/// One of the two compared paths.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Path {
+    /// Primary connection (or the journal).
+    Primary,
+    /// Backup connection (or the live connection).
+    Backup,
+}
+
+/// Frame whose checksum differs between the paths.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Divergence {
+    /// Position of the frame on both paths, starting at zero.
+    pub sequence: u64,
+    /// Checksum of the frame delivered by the primary path.
+    pub primary: u64,
+    /// Checksum of the frame delivered by the backup path.
+    pub backup: u64,
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "frame {} primary checksum {:016x} backup checksum {:016x}", self.sequence, self.primary, self.backup)
+    }
+}
+
+/// Pairs the checksums delivered by two paths in order, see the [module](self) documentation.
+pub struct ChecksumComparator {
+    // checksums of the path that is ahead
+    pending: VecDeque<u64>,
+    leading: Path,
+    max_pending: usize,
+    sequence: u64,
+    divergences: u64,
+    dropped: u64,
+    on_divergence: Option<Box<dyn FnMut(Divergence)>>,
+}
+
+impl Debug for ChecksumComparator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChecksumComparator")
+            .field("pending", &self.pending.len())
+            .field("leading", &self.leading)
+            .field("max_pending", &self.max_pending)
+            .field("sequence", &self.sequence)
+            .field("divergences", &self.divergences)
+            .field("dropped", &self.dropped)
+            .finish()
+    }
+}
+
+impl Default for ChecksumComparator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChecksumComparator {
+    /// Create comparator that keeps up to 1024 checksums of the path that is ahead.
+    pub fn new() -> ChecksumComparator {
+        Self {
+            pending: VecDeque::with_capacity(DEFAULT_MAX_PENDING),
+            leading: Path::Primary,
+            max_pending: DEFAULT_MAX_PENDING,
+            sequence: 0,
+            divergences: 0,
+            dropped: 0,
+            on_divergence: None,
+        }
+    }
+
+    /// Maximum number of checksums kept while the other path catches up, the oldest ones are dropped
+    /// (and never compared) above it.
+    pub fn with_max_pending(self, max_pending: usize) -> ChecksumComparator {
+        Self {
+            max_pending: max_pending.max(1),
+            ..self
+        }
+    }
+
+    /// Invoke the `handler` with every [`Divergence`].
+    pub fn with_divergence_handler<F>(self, handler: F) -> ChecksumComparator
+    where
+        F: FnMut(Divergence) + 'static,
+    {
+        Self {
+            on_divergence: Some(Box::new(handler)),
+            ..self
+        }
+    }
+
+    /// Compute the checksum of the `payload` delivered by the `path` and compare it.
+    #[inline]
+    pub fn compare(&mut self, path: Path, payload: &[u8]) -> Option<Divergence> {
+        self.record(path, xxh64(payload, 0))
+    }
+
+    /// Compare the `checksum` of the next frame delivered by the `path` with the checksum of the same
+    /// frame delivered by the other path, returns `Some` if they differ. The checksum is kept if the
+    /// other path has not delivered the frame yet.
+    pub fn record(&mut self, path: Path, checksum: u64) -> Option<Divergence> {
+        if self.pending.is_empty() || self.leading == path {
+            if self.pending.len() == self.max_pending {
+                self.pending.pop_front();
+                self.sequence += 1;
+                self.dropped += 1;
+                if self.dropped == 1 {
+                    warn!("{path:?} path is more than {} frames ahead, checksums dropped", self.max_pending);
+                }
+            }
+            self.leading = path;
+            self.pending.push_back(checksum);
+            return None;
+        }
+        let expected = self.pending.pop_front()?;
+        let sequence = self.sequence;
+        self.sequence += 1;
+        if expected == checksum {
+            return None;
+        }
+        let (primary, backup) = match path {
+            Path::Primary => (checksum, expected),
+            Path::Backup => (expected, checksum),
+        };
+        let divergence = Divergence {
+            sequence,
+            primary,
+            backup,
+        };
+        self.divergences += 1;
+        if let Some(handler) = self.on_divergence.as_mut() {
+            handler(divergence);
+        }
+        Some(divergence)
+    }
+
+    /// Number of frames compared (or dropped) so far.
+    #[inline]
+    pub const fn compared(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Number of frames whose checksum differed.
+    #[inline]
+    pub const fn divergences(&self) -> u64 {
+        self.divergences
+    }
+
+    /// Number of checksums dropped over `max_pending`.
+    #[inline]
+    pub const fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Number of checksums waiting for the other path.
+    #[inline]
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_compute_xxh64() {
+        assert_eq!(0xEF46DB3751D8E999, xxh64(b"", 0));
+        assert_eq!(0xD24EC4F1A98C6E5B, xxh64(b"a", 0));
+        assert_eq!(0x44BC2CF5AD770999, xxh64(b"abc", 0));
+        assert_eq!(0xFBCEA83C8A378BF1, xxh64(b"Nobody inspects the spammish repetition", 0));
+        assert_ne!(xxh64(b"abc", 0), xxh64(b"abc", 1));
+    }
+
+    #[test]
+    fn should_report_divergence_between_paths() {
+        let mut comparator = ChecksumComparator::new().with_max_pending(2);
+
+        assert!(comparator.compare(Path::Backup, b"1").is_none());
+        assert!(comparator.compare(Path::Primary, b"1").is_none());
+        assert!(comparator.compare(Path::Primary, b"2").is_none());
+        let divergence = comparator.compare(Path::Backup, b"x").unwrap();
+        assert_eq!(
+            Divergence {
+                sequence: 1,
+                primary: xxh64(b"2", 0),
+                backup: xxh64(b"x", 0),
+            },
+            divergence
+        );
+
+        // the oldest checksum is dropped once the backup is too far behind
+        for payload in [b"3", b"4", b"5"] {
+            assert!(comparator.compare(Path::Primary, payload).is_none());
+        }
+        assert_eq!((2, 1), (comparator.pending(), comparator.dropped()));
+        assert!(comparator.compare(Path::Backup, b"4").is_none());
+        assert_eq!((4, 1), (comparator.compared(), comparator.divergences()));
+    }
+}
@@ -0,0 +1,389 @@
+//! Zero-copy CBOR accessors.
+//!
+//! Values are decoded lazily from the input buffer. Byte and text strings, arrays and maps borrow
+//! from the input and nested containers are only decoded when accessed. Semantic tags are skipped
+//! and the tagged value is returned as is. Indefinite length arrays and maps are supported,
+//! indefinite length (chunked) strings are not, as they cannot be exposed without copying.
+//!
+//! ## Examples
+//! ```
+//! use boomnet::codec::cbor;
+//!
+//! // {"s": "BTCUSDT", "p": 25.5, "q": 3}
+//! let msg = b"\xa3\x61s\x67BTCUSDT\x61p\xfb\x40\x39\x80\x00\x00\x00\x00\x00\x61q\x03";
+//!
+//! assert_eq!(Some("BTCUSDT"), cbor::get(msg, b"s").and_then(|v| v.as_str()));
+//! assert_eq!(Some(25.5), cbor::get(msg, b"p").and_then(|v| v.as_f64()));
+//! assert_eq!(Some(3), cbor::get(msg, b"q").and_then(|v| v.as_u64()));
+//! ```
+
+const MAX_DEPTH: usize = 64;
+const BREAK: u8 = 0xff;
+
+/// Decoded CBOR value borrowing from the input buffer.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Value<'a> {
+    Null,
+    Undefined,
+    Bool(bool),
+    UInt(u64),
+    Int(i64),
+    Float(f64),
+    Text(&'a [u8]),
+    Bytes(&'a [u8]),
+    Array(Array<'a>),
+    Map(Map<'a>),
+    Simple(u8),
+}
+
+impl<'a> Value<'a> {
+    /// Returns the value as `bool`.
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Value::Bool(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as `u64` if it is a non-negative integer.
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Value::UInt(value) => Some(value),
+            Value::Int(value) => u64::try_from(value).ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as `i64` if it is an integer that fits.
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Value::UInt(value) => i64::try_from(value).ok(),
+            Value::Int(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as `f64` if it is a float or an integer.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Float(value) => Some(value),
+            Value::UInt(value) => Some(value as f64),
+            Value::Int(value) => Some(value as f64),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as `&str` if it is a valid UTF-8 text string.
+    pub fn as_str(&self) -> Option<&'a str> {
+        match *self {
+            Value::Text(bytes) => std::str::from_utf8(bytes).ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns raw bytes of a text or byte string value.
+    pub fn as_bytes(&self) -> Option<&'a [u8]> {
+        match *self {
+            Value::Text(bytes) | Value::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as [`Array`].
+    pub fn as_array(&self) -> Option<Array<'a>> {
+        match *self {
+            Value::Array(array) => Some(array),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as [`Map`].
+    pub fn as_map(&self) -> Option<Map<'a>> {
+        match *self {
+            Value::Map(map) => Some(map),
+            _ => None,
+        }
+    }
+}
+
+/// Array of encoded values.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Array<'a> {
+    len: usize,
+    raw: &'a [u8],
+}
+
+impl<'a> Array<'a> {
+    /// Number of items.
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the array has no items.
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterate over the items.
+    pub fn iter(&self) -> Items<'a> {
+        Items {
+            raw: self.raw,
+            pos: 0,
+            remaining: self.len,
+        }
+    }
+
+    /// Get item at `index`.
+    pub fn get(&self, index: usize) -> Option<Value<'a>> {
+        self.iter().nth(index)
+    }
+}
+
+/// Map of encoded key/value pairs.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Map<'a> {
+    len: usize,
+    raw: &'a [u8],
+}
+
+impl<'a> Map<'a> {
+    /// Number of entries.
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the map has no entries.
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterate over the `(key, value)` pairs.
+    pub fn fields(&self) -> Fields<'a> {
+        Fields {
+            items: Items {
+                raw: self.raw,
+                pos: 0,
+                remaining: self.len * 2,
+            },
+        }
+    }
+
+    /// Get value of the entry with text `key`.
+    pub fn get(&self, key: &[u8]) -> Option<Value<'a>> {
+        self.fields()
+            .find(|(k, _)| matches!(k, Value::Text(k) if *k == key))
+            .map(|(_, v)| v)
+    }
+}
+
+/// Iterator over encoded values.
+pub struct Items<'a> {
+    raw: &'a [u8],
+    pos: usize,
+    remaining: usize,
+}
+
+impl<'a> Iterator for Items<'a> {
+    type Item = Value<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        match decode_at(self.raw, self.pos, 0) {
+            Some((value, end)) => {
+                self.pos = end;
+                self.remaining -= 1;
+                Some(value)
+            }
+            None => {
+                self.remaining = 0;
+                None
+            }
+        }
+    }
+}
+
+/// Iterator over map `(key, value)` pairs.
+pub struct Fields<'a> {
+    items: Items<'a>,
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = (Value<'a>, Value<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some((self.items.next()?, self.items.next()?))
+    }
+}
+
+/// Decode the first value in `buf`, returning it together with the remaining bytes.
+pub fn decode(buf: &[u8]) -> Option<(Value<'_>, &[u8])> {
+    let (value, end) = decode_at(buf, 0, 0)?;
+    Some((value, &buf[end..]))
+}
+
+/// Iterate over fields of the top level map. Yields nothing if `buf` does not start with a map.
+pub fn fields(buf: &[u8]) -> Fields<'_> {
+    match decode(buf) {
+        Some((Value::Map(map), _)) => map.fields(),
+        _ => Fields {
+            items: Items {
+                raw: buf,
+                pos: 0,
+                remaining: 0,
+            },
+        },
+    }
+}
+
+/// Get value of the top level map entry with text `key`.
+pub fn get<'a>(buf: &'a [u8], key: &[u8]) -> Option<Value<'a>> {
+    decode(buf)?.0.as_map()?.get(key)
+}
+
+#[inline]
+fn read_be(buf: &[u8], pos: usize, len: usize) -> Option<u64> {
+    let bytes = buf.get(pos..pos + len)?;
+    Some(bytes.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64))
+}
+
+/// Reads the argument of the initial byte. Returns `None` as argument for indefinite length.
+#[inline]
+fn read_arg(buf: &[u8], pos: usize, info: u8) -> Option<(Option<u64>, usize)> {
+    match info {
+        0..=23 => Some((Some(info as u64), pos)),
+        24..=27 => {
+            let width = 1 << (info - 24);
+            Some((Some(read_be(buf, pos, width)?), pos + width))
+        }
+        31 => Some((None, pos)),
+        _ => None,
+    }
+}
+
+fn read_seq(buf: &[u8], pos: usize, count: Option<u64>, depth: usize) -> Option<(usize, &[u8], usize)> {
+    let mut end = pos;
+    match count {
+        Some(count) => {
+            for _ in 0..count {
+                end = decode_at(buf, end, depth + 1)?.1;
+            }
+            Some((count as usize, &buf[pos..end], end))
+        }
+        None => {
+            let mut count = 0;
+            while *buf.get(end)? != BREAK {
+                end = decode_at(buf, end, depth + 1)?.1;
+                count += 1;
+            }
+            Some((count, &buf[pos..end], end + 1))
+        }
+    }
+}
+
+fn decode_at(buf: &[u8], pos: usize, depth: usize) -> Option<(Value<'_>, usize)> {
+    if depth > MAX_DEPTH {
+        return None;
+    }
+    let initial = *buf.get(pos)?;
+    let (major, info) = (initial >> 5, initial & 0x1f);
+    let (arg, pos) = read_arg(buf, pos + 1, info)?;
+    let value = match major {
+        0 => (Value::UInt(arg?), pos),
+        1 => (Value::Int(-1 - i64::try_from(arg?).ok()?), pos),
+        2 | 3 => {
+            let end = pos.checked_add(usize::try_from(arg?).ok()?)?;
+            let bytes = buf.get(pos..end)?;
+            let value = if major == 2 {
+                Value::Bytes(bytes)
+            } else {
+                Value::Text(bytes)
+            };
+            (value, end)
+        }
+        4 => {
+            let (len, raw, end) = read_seq(buf, pos, arg, depth)?;
+            (Value::Array(Array { len, raw }), end)
+        }
+        5 => {
+            let (len, raw, end) = match arg {
+                Some(len) => read_seq(buf, pos, Some(len.checked_mul(2)?), depth)?,
+                None => read_seq(buf, pos, None, depth)?,
+            };
+            if len % 2 != 0 {
+                return None;
+            }
+            (Value::Map(Map { len: len / 2, raw }), end)
+        }
+        6 => {
+            arg?;
+            decode_at(buf, pos, depth + 1)?
+        }
+        _ => match info {
+            20 => (Value::Bool(false), pos),
+            21 => (Value::Bool(true), pos),
+            22 => (Value::Null, pos),
+            23 => (Value::Undefined, pos),
+            24 => (Value::Simple(arg? as u8), pos),
+            25 => (Value::Float(f16_to_f64(arg? as u16)), pos),
+            26 => (Value::Float(f32::from_bits(arg? as u32) as f64), pos),
+            27 => (Value::Float(f64::from_bits(arg?)), pos),
+            0..=19 => (Value::Simple(info), pos),
+            _ => return None,
+        },
+    };
+    Some(value)
+}
+
+fn f16_to_f64(bits: u16) -> f64 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exp = ((bits >> 10) & 0x1f) as i32;
+    let mant = (bits & 0x3ff) as f64;
+    let magnitude = match exp {
+        0 => mant * 2f64.powi(-24),
+        31 if mant == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (1.0 + mant / 1024.0) * 2f64.powi(exp - 15),
+    };
+    sign * magnitude
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_decode_scalars() {
+        assert_eq!(Some((Value::Int(-1), &[][..])), decode(b"\x20"));
+        assert_eq!(Some((Value::Int(-1000), &[][..])), decode(b"\x39\x03\xe7"));
+        assert_eq!(Some((Value::UInt(1_000_000), &[][..])), decode(b"\x1a\x00\x0f\x42\x40"));
+        assert_eq!(Some((Value::Float(1.5), &[][..])), decode(b"\xf9\x3e\x00"));
+        assert_eq!(Some((Value::Float(-4.0), &[][..])), decode(b"\xf9\xc4\x00"));
+        assert_eq!(Some((Value::Bytes(b"ab"), &[][..])), decode(b"\x42ab"));
+        assert_eq!(Some((Value::UInt(1363896240), &[][..])), decode(b"\xc1\x1a\x51\x4b\x67\xb0"));
+        assert_eq!(Some((Value::Null, &b"\x01"[..])), decode(b"\xf6\x01"));
+        assert_eq!(None, decode(b"\x7f\x61a\xff"));
+        assert_eq!(None, decode(b"\x65abc"));
+    }
+
+    #[test]
+    fn should_access_nested_containers() {
+        // {"b": [_ 1, [2, 3]], "a": {_ "x": true}}
+        let msg = b"\xa2\x61b\x9f\x01\x82\x02\x03\xff\x61a\xbf\x61x\xf5\xff";
+        let b = get(msg, b"b").and_then(|v| v.as_array()).unwrap();
+        assert_eq!(2, b.len());
+        assert_eq!(
+            Some(3),
+            b.get(1)
+                .and_then(|v| v.as_array())
+                .and_then(|a| a.get(1))
+                .and_then(|v| v.as_u64())
+        );
+        let a = get(msg, b"a").and_then(|v| v.as_map()).unwrap();
+        assert_eq!(1, a.len());
+        assert_eq!(Some(true), a.get(b"x").and_then(|v| v.as_bool()));
+        assert_eq!(2, fields(msg).count());
+        assert_eq!(None, get(msg, b"c"));
+    }
+}
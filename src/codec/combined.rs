@@ -0,0 +1,227 @@
+//! Routing of combined stream messages (`{"stream":"<name>","data":{...}}`) to per-stream handlers.
+//!
+//! Stream names are interned into a [`SymbolTable`], so the handlers are keyed (and invoked) with the
+//! same [`SymbolId`] the rest of the application uses for the stream.
+//!
+//! ## Examples
+//! ```
+//! use boomnet::codec::combined::StreamRouter;
+//!
+//! let mut router = StreamRouter::new();
+//! let btc = router
+//!     .register("btcusdt@bookTicker", |_id, data: &[u8]| {
+//!         println!("btc: {}", String::from_utf8_lossy(data));
+//!         Ok(())
+//!     })
+//!     .unwrap();
+//!
+//! let msg = br#"{"stream":"btcusdt@bookTicker","data":{"u":400900217,"s":"BTCUSDT"}}"#;
+//! assert_eq!(Some(btc), router.route(msg).unwrap());
+//! ```
+
+use crate::codec::json;
+use crate::symbol::{SymbolId, SymbolTable};
+use std::io;
+
+const STREAM_PREFIX: &[u8] = br#"{"stream":""#;
+const DATA_INFIX: &[u8] = br#"","data":"#;
+
+/// Number of streams the router created with [`StreamRouter::new`] can hold.
+pub const DEFAULT_CAPACITY: usize = 4096;
+
+/// Borrowed view of the combined stream message.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Envelope<'a> {
+    /// Stream name, without quotes.
+    pub stream: &'a [u8],
+    /// Raw `data` payload.
+    pub data: &'a [u8],
+}
+
+impl<'a> Envelope<'a> {
+    /// Parse combined stream envelope. Compact payloads with `stream` as the first field (as sent by
+    /// the venue) take the fast path, any other layout falls back to the generic JSON scanner.
+    pub fn parse(payload: &'a [u8]) -> Option<Self> {
+        Self::parse_compact(payload).or_else(|| Self::parse_generic(payload))
+    }
+
+    #[inline]
+    fn parse_compact(payload: &'a [u8]) -> Option<Self> {
+        let rest = payload.strip_prefix(STREAM_PREFIX)?;
+        let end = find_quote(rest)?;
+        let stream = &rest[..end];
+        let rest = rest[end..].strip_prefix(DATA_INFIX)?;
+        // anything but the closing brace after the data (e.g. another field) is left to the generic path
+        let data_end = json::skip_value(rest, 0)?;
+        match &rest[data_end..] {
+            b"}" => Some(Self {
+                stream,
+                data: &rest[..data_end],
+            }),
+            _ => None,
+        }
+    }
+
+    fn parse_generic(payload: &'a [u8]) -> Option<Self> {
+        let mut stream = None;
+        let mut data = None;
+        for (key, value) in json::fields(payload) {
+            match key {
+                b"stream" => stream = json::unquote(value),
+                b"data" => data = Some(value),
+                _ => {}
+            }
+        }
+        Some(Self {
+            stream: stream?,
+            data: data?,
+        })
+    }
+}
+
+#[inline]
+fn find_quote(buf: &[u8]) -> Option<usize> {
+    buf.iter().position(|b| *b == b'"')
+}
+
+/// Handler invoked with the `data` payload of the combined stream message.
+pub trait StreamHandler {
+    fn on_data(&mut self, id: SymbolId, data: &[u8]) -> io::Result<()>;
+}
+
+impl<F> StreamHandler for F
+where
+    F: FnMut(SymbolId, &[u8]) -> io::Result<()>,
+{
+    fn on_data(&mut self, id: SymbolId, data: &[u8]) -> io::Result<()> {
+        self(id, data)
+    }
+}
+
+/// Routes combined stream messages to the handler registered for the stream name.
+pub struct StreamRouter<H> {
+    symbols: SymbolTable,
+    // indexed by the symbol id, streams interned without a handler have none
+    handlers: Vec<Option<H>>,
+}
+
+impl<H> Default for StreamRouter<H> {
+    fn default() -> Self {
+        Self {
+            symbols: SymbolTable::with_capacity(DEFAULT_CAPACITY),
+            handlers: Vec::new(),
+        }
+    }
+}
+
+impl<H: StreamHandler> StreamRouter<H> {
+    /// Create empty router that can hold up to [`DEFAULT_CAPACITY`] streams.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create router on top of the `symbols` table, the stream names already interned keep their ids
+    /// and the names registered with the router are interned into it.
+    pub fn with_symbols(symbols: SymbolTable) -> Self {
+        Self {
+            symbols,
+            handlers: Vec::new(),
+        }
+    }
+
+    /// Register `handler` for the `stream` name, returns the id the name is interned under. If the
+    /// stream is already registered its handler is replaced. Fails if the symbol table is full.
+    pub fn register(&mut self, stream: &str, handler: H) -> io::Result<SymbolId> {
+        let id = self.symbols.intern(stream)?;
+        if self.handlers.len() <= id.as_usize() {
+            self.handlers.resize_with(id.as_usize() + 1, || None);
+        }
+        self.handlers[id.as_usize()] = Some(handler);
+        Ok(id)
+    }
+
+    /// Lookup id of the interned `stream`.
+    pub fn id(&self, stream: impl AsRef<[u8]>) -> Option<SymbolId> {
+        self.symbols.get(stream)
+    }
+
+    /// Symbol table the stream names are interned into.
+    pub const fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// Get handler registered under `id`.
+    pub fn handler_mut(&mut self, id: SymbolId) -> Option<&mut H> {
+        self.handlers.get_mut(id.as_usize())?.as_mut()
+    }
+
+    /// Parse the combined stream `payload` and dispatch its `data` to the registered handler.
+    /// Returns `Ok(None)` if no handler is registered for the stream and an error if the payload
+    /// is not a valid combined stream message.
+    pub fn route(&mut self, payload: &[u8]) -> io::Result<Option<SymbolId>> {
+        let envelope = Envelope::parse(payload)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid combined stream envelope"))?;
+        let Some(id) = self.symbols.get(envelope.stream) else {
+            return Ok(None);
+        };
+        match self.handler_mut(id) {
+            Some(handler) => {
+                handler.on_data(id, envelope.data)?;
+                Ok(Some(id))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_compact_and_generic_envelope() {
+        let compact = br#"{"stream":"ethusdt@bookTicker","data":{"s":"ETHUSDT"}}"#;
+        let generic = br#"{ "data": {"s":"ETHUSDT"}, "stream": "ethusdt@bookTicker" }"#;
+        let expected = Envelope {
+            stream: b"ethusdt@bookTicker",
+            data: br#"{"s":"ETHUSDT"}"#,
+        };
+        assert_eq!(Some(expected), Envelope::parse(compact));
+        assert_eq!(Some(expected), Envelope::parse(generic));
+        assert_eq!(None, Envelope::parse(br#"{"result":null,"id":1}"#));
+
+        // the compact layout followed by another field is parsed by the generic scanner
+        let trailing = br#"{"stream":"ethusdt@bookTicker","data":{"s":"ETHUSDT"},"id":1}"#;
+        assert_eq!(None, Envelope::parse_compact(trailing));
+        assert_eq!(Some(expected), Envelope::parse(trailing));
+    }
+
+    #[test]
+    fn should_route_to_registered_handler() {
+        let mut symbols = SymbolTable::with_capacity(4);
+        let sol = symbols.intern("solusdt@trade").unwrap();
+        let mut router = StreamRouter::<Box<dyn FnMut(SymbolId, &[u8]) -> io::Result<()>>>::with_symbols(symbols);
+        let mut received = Vec::new();
+        let eth = router.register("ethusdt@trade", Box::new(|_, _| Ok(()))).unwrap();
+        let btc = router
+            .register(
+                "btcusdt@trade",
+                Box::new(|_, data| {
+                    assert_eq!(b"{}", data);
+                    Ok(())
+                }),
+            )
+            .unwrap();
+        assert_ne!(eth, btc);
+        // interned without a handler
+        assert_eq!(Some(sol), router.id("solusdt@trade"));
+        for msg in [
+            &br#"{"stream":"btcusdt@trade","data":{}}"#[..],
+            br#"{"stream":"solusdt@trade","data":{}}"#,
+        ] {
+            received.push(router.route(msg).unwrap());
+        }
+        assert_eq!(vec![Some(btc), None], received);
+        assert!(router.route(b"{}").is_err());
+    }
+}
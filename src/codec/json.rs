@@ -0,0 +1,228 @@
+//! Minimal zero-copy JSON scanner.
+//!
+//! The scanner does not build any document model. Instead, it walks the top level object and yields
+//! raw key/value slices borrowed from the input, leaving the actual value parsing to the caller.
+//! Malformed input terminates the iteration.
+//!
+//! ## Examples
+//! ```
+//! use boomnet::codec::json;
+//!
+//! let msg = br#"{"e":"bookTicker","s":"BTCUSDT","b":"25.35190000","a":[1,2]}"#;
+//!
+//! assert_eq!(Some(&b"\"BTCUSDT\""[..]), json::get(msg, b"s"));
+//! assert_eq!(Some(&b"BTCUSDT"[..]), json::get(msg, b"s").and_then(json::unquote));
+//! assert_eq!(Some(&b"[1,2]"[..]), json::get(msg, b"a"));
+//! ```
+
+/// Iterator over top level `(key, value)` pairs of a JSON object. Keys are returned without quotes
+/// (escape sequences are not decoded) and values are returned as raw slices, including quotes for strings.
+pub struct Fields<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    done: bool,
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = (&'a [u8], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let next = self.next_field();
+        if next.is_none() {
+            self.done = true;
+        }
+        next
+    }
+}
+
+impl<'a> Fields<'a> {
+    fn next_field(&mut self) -> Option<(&'a [u8], &'a [u8])> {
+        let buf = self.buf;
+        let mut pos = skip_ws(buf, self.pos);
+        match buf.get(pos)? {
+            b'}' => return None,
+            b',' => pos = skip_ws(buf, pos + 1),
+            _ => {}
+        }
+        let key_end = skip_string(buf, pos)?;
+        let key = &buf[pos + 1..key_end - 1];
+        pos = skip_ws(buf, key_end);
+        if *buf.get(pos)? != b':' {
+            return None;
+        }
+        let value_start = skip_ws(buf, pos + 1);
+        let value_end = skip_value(buf, value_start)?;
+        self.pos = value_end;
+        Some((key, &buf[value_start..value_end]))
+    }
+}
+
+/// Iterate over top level fields of the JSON object.
+pub fn fields(json: &[u8]) -> Fields<'_> {
+    let pos = skip_ws(json, 0);
+    let valid = json.get(pos) == Some(&b'{');
+    Fields {
+        buf: json,
+        pos: pos + 1,
+        done: !valid,
+    }
+}
+
+/// Get raw value of the top level field with the given `key`.
+pub fn get<'a>(json: &'a [u8], key: &[u8]) -> Option<&'a [u8]> {
+    fields(json).find(|(k, _)| *k == key).map(|(_, v)| v)
+}
+
+/// Iterator over the raw values of a JSON array, malformed input terminates the iteration.
+pub struct Elements<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    done: bool,
+}
+
+impl<'a> Iterator for Elements<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let next = self.next_element();
+        if next.is_none() {
+            self.done = true;
+        }
+        next
+    }
+}
+
+impl<'a> Elements<'a> {
+    fn next_element(&mut self) -> Option<&'a [u8]> {
+        let buf = self.buf;
+        let mut pos = skip_ws(buf, self.pos);
+        match buf.get(pos)? {
+            b']' => return None,
+            b',' => pos = skip_ws(buf, pos + 1),
+            _ => {}
+        }
+        let end = skip_value(buf, pos)?;
+        self.pos = end;
+        Some(&buf[pos..end])
+    }
+}
+
+/// Iterate over the raw values of the JSON `array`.
+pub fn elements(array: &[u8]) -> Elements<'_> {
+    let pos = skip_ws(array, 0);
+    let valid = array.get(pos) == Some(&b'[');
+    Elements {
+        buf: array,
+        pos: pos + 1,
+        done: !valid,
+    }
+}
+
+/// Strip quotes from raw string `value`. Returns `None` if the value is not a string.
+pub fn unquote(value: &[u8]) -> Option<&[u8]> {
+    match value {
+        [b'"', inner @ .., b'"'] => Some(inner),
+        _ => None,
+    }
+}
+
+#[inline]
+fn skip_ws(buf: &[u8], mut pos: usize) -> usize {
+    while let Some(b' ' | b'\t' | b'\n' | b'\r') = buf.get(pos) {
+        pos += 1;
+    }
+    pos
+}
+
+/// Returns position just after the closing quote of the string starting at `pos`.
+#[inline]
+fn skip_string(buf: &[u8], pos: usize) -> Option<usize> {
+    if *buf.get(pos)? != b'"' {
+        return None;
+    }
+    let mut pos = pos + 1;
+    loop {
+        match buf.get(pos)? {
+            b'"' => return Some(pos + 1),
+            b'\\' => pos += 2,
+            _ => pos += 1,
+        }
+    }
+}
+
+/// Returns position just after the value starting at `pos`.
+pub(crate) fn skip_value(buf: &[u8], pos: usize) -> Option<usize> {
+    match buf.get(pos)? {
+        b'"' => skip_string(buf, pos),
+        b'{' | b'[' => {
+            let mut depth = 0usize;
+            let mut pos = pos;
+            loop {
+                match buf.get(pos)? {
+                    b'"' => {
+                        pos = skip_string(buf, pos)?;
+                        continue;
+                    }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(pos + 1);
+                        }
+                    }
+                    _ => {}
+                }
+                pos += 1;
+            }
+        }
+        _ => {
+            let len = buf[pos..]
+                .iter()
+                .position(|b| matches!(b, b',' | b'}' | b']' | b' ' | b'\t' | b'\n' | b'\r'))
+                .unwrap_or(buf.len() - pos);
+            if len == 0 { None } else { Some(pos + len) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_iterate_fields() {
+        let json = br#" { "a" : 1, "b":"x\"}y", "c":{"d":[1,{"e":"]"}]} , "f":true }"#;
+        let fields = fields(json).collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (&b"a"[..], &b"1"[..]),
+                (&b"b"[..], &br#""x\"}y""#[..]),
+                (&b"c"[..], &br#"{"d":[1,{"e":"]"}]}"#[..]),
+                (&b"f"[..], &b"true"[..]),
+            ],
+            fields
+        );
+    }
+
+    #[test]
+    fn should_iterate_elements() {
+        let json = br#" [ ["1.5","2"], {"a":"]"} ,3 ,"x"]"#;
+        let values = elements(json).collect::<Vec<_>>();
+        assert_eq!(vec![&br#"["1.5","2"]"#[..], br#"{"a":"]"}"#, b"3", br#""x""#], values);
+        assert_eq!(0, elements(b"[]").count());
+        assert_eq!(0, elements(br#"{"a":1}"#).count());
+    }
+
+    #[test]
+    fn should_stop_on_malformed_input() {
+        assert_eq!(0, fields(b"[1,2]").count());
+        assert_eq!(1, fields(br#"{"a":1,"b""#).count());
+        assert_eq!(None, get(br#"{"a":"#, b"a"));
+    }
+}
@@ -0,0 +1,8 @@
+//! Zero-copy helpers for decoding venue payloads received over the wire.
+
+pub mod cbor;
+pub mod combined;
+pub mod json;
+pub mod msgpack;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
@@ -0,0 +1,458 @@
+//! Zero-copy MessagePack accessors.
+//!
+//! Values are decoded lazily from the input buffer. Strings, binaries, arrays and maps borrow from
+//! the input and nested containers are only decoded when accessed: [`get`] and [`fields`] do not walk
+//! the message up front, reaching an entry only skips over the entries before it. As a consequence a
+//! truncated or malformed container is not detected until the broken item is reached, [`decode`] (which
+//! has to find the end of the value) validates the whole value.
+//!
+//! ## Examples
+//! ```
+//! use boomnet::codec::msgpack;
+//!
+//! // {"s": "BTCUSDT", "p": 25.5, "q": 3}
+//! let msg = b"\x83\xa1s\xa7BTCUSDT\xa1p\xcb\x40\x39\x80\x00\x00\x00\x00\x00\xa1q\x03";
+//!
+//! assert_eq!(Some("BTCUSDT"), msgpack::get(msg, b"s").and_then(|v| v.as_str()));
+//! assert_eq!(Some(25.5), msgpack::get(msg, b"p").and_then(|v| v.as_f64()));
+//! assert_eq!(Some(3), msgpack::get(msg, b"q").and_then(|v| v.as_u64()));
+//! ```
+
+use std::fmt::{Debug, Formatter};
+
+const MAX_DEPTH: usize = 64;
+
+/// Decoded MessagePack value borrowing from the input buffer.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Value<'a> {
+    Nil,
+    Bool(bool),
+    UInt(u64),
+    Int(i64),
+    F32(f32),
+    F64(f64),
+    Str(&'a [u8]),
+    Bin(&'a [u8]),
+    Array(Array<'a>),
+    Map(Map<'a>),
+    Ext(i8, &'a [u8]),
+}
+
+impl<'a> Value<'a> {
+    /// Returns the value as `bool`.
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Value::Bool(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as `u64` if it is a non-negative integer.
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Value::UInt(value) => Some(value),
+            Value::Int(value) => u64::try_from(value).ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as `i64` if it is an integer that fits.
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Value::UInt(value) => i64::try_from(value).ok(),
+            Value::Int(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as `f64` if it is a float or an integer.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::F32(value) => Some(value as f64),
+            Value::F64(value) => Some(value),
+            Value::UInt(value) => Some(value as f64),
+            Value::Int(value) => Some(value as f64),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as `&str` if it is a valid UTF-8 string.
+    pub fn as_str(&self) -> Option<&'a str> {
+        std::str::from_utf8(self.as_bytes()?).ok()
+    }
+
+    /// Returns raw bytes of a string or binary value.
+    pub fn as_bytes(&self) -> Option<&'a [u8]> {
+        match *self {
+            Value::Str(bytes) | Value::Bin(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as [`Array`].
+    pub fn as_array(&self) -> Option<Array<'a>> {
+        match *self {
+            Value::Array(array) => Some(array),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as [`Map`].
+    pub fn as_map(&self) -> Option<Map<'a>> {
+        match *self {
+            Value::Map(map) => Some(map),
+            _ => None,
+        }
+    }
+}
+
+/// Array of encoded values.
+#[derive(Copy, Clone)]
+pub struct Array<'a> {
+    len: usize,
+    // starts with the first item and runs to the end of the input
+    raw: &'a [u8],
+}
+
+impl Debug for Array<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl PartialEq for Array<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<'a> Array<'a> {
+    /// Number of items.
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the array has no items.
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterate over the items.
+    pub fn iter(&self) -> Items<'a> {
+        Items {
+            raw: self.raw,
+            pos: 0,
+            remaining: self.len,
+            returned: false,
+        }
+    }
+
+    /// Get item at `index`.
+    pub fn get(&self, index: usize) -> Option<Value<'a>> {
+        self.iter().nth(index)
+    }
+}
+
+/// Map of encoded key/value pairs.
+#[derive(Copy, Clone)]
+pub struct Map<'a> {
+    len: usize,
+    // starts with the first key and runs to the end of the input
+    raw: &'a [u8],
+}
+
+impl Debug for Map<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.fields()).finish()
+    }
+}
+
+impl PartialEq for Map<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.fields().eq(other.fields())
+    }
+}
+
+impl<'a> Map<'a> {
+    /// Number of entries.
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the map has no entries.
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterate over the `(key, value)` pairs.
+    pub fn fields(&self) -> Fields<'a> {
+        Fields {
+            items: Items {
+                raw: self.raw,
+                pos: 0,
+                remaining: self.len * 2,
+                returned: false,
+            },
+        }
+    }
+
+    /// Get value of the entry with string `key`.
+    pub fn get(&self, key: &[u8]) -> Option<Value<'a>> {
+        self.fields()
+            .find(|(k, _)| matches!(k, Value::Str(k) if *k == key))
+            .map(|(_, v)| v)
+    }
+}
+
+/// Iterator over encoded values.
+pub struct Items<'a> {
+    raw: &'a [u8],
+    pos: usize,
+    remaining: usize,
+    // the item at `pos` has been returned, it is skipped only once the next one is requested
+    returned: bool,
+}
+
+impl<'a> Iterator for Items<'a> {
+    type Item = Value<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        if self.returned {
+            match skip_at(self.raw, self.pos, 0) {
+                Some(end) => self.pos = end,
+                None => {
+                    self.remaining = 0;
+                    return None;
+                }
+            }
+        }
+        match decode_lazy(self.raw, self.pos) {
+            Some(value) => {
+                self.remaining -= 1;
+                self.returned = true;
+                Some(value)
+            }
+            None => {
+                self.remaining = 0;
+                None
+            }
+        }
+    }
+}
+
+/// Iterator over map `(key, value)` pairs.
+pub struct Fields<'a> {
+    items: Items<'a>,
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = (Value<'a>, Value<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some((self.items.next()?, self.items.next()?))
+    }
+}
+
+/// Decode the first value in `buf`, returning it together with the remaining bytes.
+pub fn decode(buf: &[u8]) -> Option<(Value<'_>, &[u8])> {
+    let value = decode_lazy(buf, 0)?;
+    let end = skip_at(buf, 0, 0)?;
+    Some((value, &buf[end..]))
+}
+
+/// Iterate over fields of the top level map. Yields nothing if `buf` does not start with a map.
+pub fn fields(buf: &[u8]) -> Fields<'_> {
+    match decode_lazy(buf, 0) {
+        Some(Value::Map(map)) => map.fields(),
+        _ => Fields {
+            items: Items {
+                raw: buf,
+                pos: 0,
+                remaining: 0,
+                returned: false,
+            },
+        },
+    }
+}
+
+/// Get value of the top level map entry with string `key`.
+pub fn get<'a>(buf: &'a [u8], key: &[u8]) -> Option<Value<'a>> {
+    decode_lazy(buf, 0)?.as_map()?.get(key)
+}
+
+#[inline]
+fn read_be(buf: &[u8], pos: usize, len: usize) -> Option<u64> {
+    let bytes = buf.get(pos..pos + len)?;
+    Some(bytes.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64))
+}
+
+#[inline]
+fn read_slice(buf: &[u8], pos: usize, len: usize) -> Option<(&[u8], usize)> {
+    let end = pos.checked_add(len)?;
+    Some((buf.get(pos..end)?, end))
+}
+
+/// Value at a position, containers are not decoded beyond their header.
+enum Head<'a> {
+    /// Scalar value and the position just after it.
+    Scalar(Value<'a>, usize),
+    /// Array with the number of items and the position of the first one.
+    Array(usize, usize),
+    /// Map with the number of entries and the position of the first key.
+    Map(usize, usize),
+}
+
+/// Decode the value at `pos`, containers are returned with the position of their first item.
+fn decode_head(buf: &[u8], pos: usize) -> Option<Head<'_>> {
+    let tag = *buf.get(pos)?;
+    let pos = pos + 1;
+    let value = match tag {
+        0x00..=0x7f => (Value::UInt(tag as u64), pos),
+        0x80..=0x8f => return Some(Head::Map((tag & 0x0f) as usize, pos)),
+        0x90..=0x9f => return Some(Head::Array((tag & 0x0f) as usize, pos)),
+        0xa0..=0xbf => {
+            let (bytes, end) = read_slice(buf, pos, (tag & 0x1f) as usize)?;
+            (Value::Str(bytes), end)
+        }
+        0xc0 => (Value::Nil, pos),
+        0xc2 => (Value::Bool(false), pos),
+        0xc3 => (Value::Bool(true), pos),
+        0xc4..=0xc6 => {
+            let width = 1 << (tag - 0xc4);
+            let (bytes, end) = read_slice(buf, pos + width, read_be(buf, pos, width)? as usize)?;
+            (Value::Bin(bytes), end)
+        }
+        0xc7..=0xc9 => {
+            let width = 1 << (tag - 0xc7);
+            let len = read_be(buf, pos, width)? as usize;
+            let ext_type = *buf.get(pos + width)? as i8;
+            let (bytes, end) = read_slice(buf, pos + width + 1, len)?;
+            (Value::Ext(ext_type, bytes), end)
+        }
+        0xca => (Value::F32(f32::from_bits(read_be(buf, pos, 4)? as u32)), pos + 4),
+        0xcb => (Value::F64(f64::from_bits(read_be(buf, pos, 8)?)), pos + 8),
+        0xcc..=0xcf => {
+            let width = 1 << (tag - 0xcc);
+            (Value::UInt(read_be(buf, pos, width)?), pos + width)
+        }
+        0xd0..=0xd3 => {
+            let width = 1 << (tag - 0xd0);
+            let raw = read_be(buf, pos, width)?;
+            let shift = 64 - width * 8;
+            (Value::Int(((raw << shift) as i64) >> shift), pos + width)
+        }
+        0xd4..=0xd8 => {
+            let ext_type = *buf.get(pos)? as i8;
+            let (bytes, end) = read_slice(buf, pos + 1, 1 << (tag - 0xd4))?;
+            (Value::Ext(ext_type, bytes), end)
+        }
+        0xd9..=0xdb => {
+            let width = 1 << (tag - 0xd9);
+            let (bytes, end) = read_slice(buf, pos + width, read_be(buf, pos, width)? as usize)?;
+            (Value::Str(bytes), end)
+        }
+        0xdc | 0xdd => {
+            let width = if tag == 0xdc { 2 } else { 4 };
+            return Some(Head::Array(read_be(buf, pos, width)? as usize, pos + width));
+        }
+        0xde | 0xdf => {
+            let width = if tag == 0xde { 2 } else { 4 };
+            return Some(Head::Map(read_be(buf, pos, width)? as usize, pos + width));
+        }
+        0xe0..=0xff => (Value::Int(tag as i8 as i64), pos),
+        0xc1 => return None,
+    };
+    Some(Head::Scalar(value.0, value.1))
+}
+
+/// Decode the value at `pos` without looking past the header of a container.
+fn decode_lazy(buf: &[u8], pos: usize) -> Option<Value<'_>> {
+    Some(match decode_head(buf, pos)? {
+        Head::Scalar(value, _) => value,
+        Head::Array(len, start) => Value::Array(Array {
+            len,
+            raw: &buf[start..],
+        }),
+        Head::Map(len, start) => Value::Map(Map {
+            len,
+            raw: &buf[start..],
+        }),
+    })
+}
+
+/// Returns the position just after the value at `pos`.
+fn skip_at(buf: &[u8], pos: usize, depth: usize) -> Option<usize> {
+    if depth > MAX_DEPTH {
+        return None;
+    }
+    match decode_head(buf, pos)? {
+        Head::Scalar(_, end) => Some(end),
+        Head::Array(len, start) => skip_seq(buf, start, len, depth),
+        Head::Map(len, start) => skip_seq(buf, start, len.checked_mul(2)?, depth),
+    }
+}
+
+fn skip_seq(buf: &[u8], pos: usize, count: usize, depth: usize) -> Option<usize> {
+    let mut end = pos;
+    for _ in 0..count {
+        end = skip_at(buf, end, depth + 1)?;
+    }
+    Some(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_decode_scalars() {
+        assert_eq!(Some((Value::Int(-1), &[][..])), decode(b"\xff"));
+        assert_eq!(Some((Value::Int(-128), &[][..])), decode(b"\xd0\x80"));
+        assert_eq!(Some((Value::Int(-2), &[][..])), decode(b"\xd1\xff\xfe"));
+        assert_eq!(Some((Value::UInt(0xffff), &[][..])), decode(b"\xcd\xff\xff"));
+        assert_eq!(Some((Value::F32(1.5), &[][..])), decode(b"\xca\x3f\xc0\x00\x00"));
+        assert_eq!(Some((Value::Bin(b"ab"), &[][..])), decode(b"\xc4\x02ab"));
+        assert_eq!(Some((Value::Ext(5, b"x"), &[][..])), decode(b"\xd4\x05x"));
+        assert_eq!(Some((Value::Nil, &b"\x01"[..])), decode(b"\xc0\x01"));
+        assert_eq!(None, decode(b"\xd9\x05abc"));
+    }
+
+    #[test]
+    fn should_access_nested_containers() {
+        // {"b": [1, [2, 3]], "a": {"x": true}}
+        let msg = b"\x82\xa1b\x92\x01\x92\x02\x03\xa1a\x81\xa1x\xc3";
+        let b = get(msg, b"b").and_then(|v| v.as_array()).unwrap();
+        assert_eq!(2, b.len());
+        assert_eq!(
+            Some(3),
+            b.get(1)
+                .and_then(|v| v.as_array())
+                .and_then(|a| a.get(1))
+                .and_then(|v| v.as_u64())
+        );
+        let a = get(msg, b"a").and_then(|v| v.as_map()).unwrap();
+        assert_eq!(Some(true), a.get(b"x").and_then(|v| v.as_bool()));
+        assert_eq!(2, fields(msg).count());
+        assert_eq!(None, get(msg, b"c"));
+    }
+
+    #[test]
+    fn should_not_walk_containers_before_access() {
+        // {"a": 1, "b": [<truncated>]}, the first entry is reachable without decoding the broken array
+        let msg = b"\x82\xa1a\x01\xa1b\x93\x01";
+        assert_eq!(Some(1), get(msg, b"a").and_then(|v| v.as_u64()));
+        let b = get(msg, b"b").and_then(|v| v.as_array()).unwrap();
+        assert_eq!(3, b.len());
+        assert_eq!(vec![Value::UInt(1)], b.iter().collect::<Vec<_>>());
+        // the end of the value has to be found
+        assert_eq!(None, decode(msg));
+
+        // containers compare by their items rather than by the trailing input
+        let (first, rest) = decode(b"\x91\x01\x91\x01\xc0").unwrap();
+        assert_eq!(Some(first), decode(rest).map(|(value, _)| value));
+        assert_eq!("[UInt(1)]", format!("{:?}", first.as_array().unwrap()));
+    }
+}
@@ -0,0 +1,357 @@
+//! Protobuf decoding via [`prost`].
+//!
+//! Messages are decoded directly from the borrowed frame (or read buffer) without any intermediate
+//! allocation. For raw TCP endpoints carrying varint length delimited messages use [`LengthDelimited`].
+//!
+//! ## Examples
+//!
+//! Decode binary websocket frames.
+//! ```no_run
+//! use std::io::{Read, Write};
+//! use boomnet::ws::Websocket;
+//!
+//! fn consume_batch<S: Read + Write, M: prost::Message + Default>(ws: &mut Websocket<S>) -> std::io::Result<()> {
+//!    for frame in ws.read_batch()? {
+//!      if let Some(msg) = frame?.decode_protobuf::<M>()? {
+//!        println!("{msg:?}");
+//!      }
+//!    }
+//!    Ok(())
+//! }
+//! ```
+//!
+//! Receive length delimited messages from a TCP stream.
+//! ```no_run
+//! use boomnet::codec::protobuf::IntoLengthDelimited;
+//! use boomnet::stream::ConnectionInfo;
+//!
+//! #[derive(Clone, PartialEq, prost::Message)]
+//! struct Quote {
+//!     #[prost(uint64, tag = "1")]
+//!     bid: u64,
+//! }
+//!
+//! let mut stream = ConnectionInfo::new("127.0.0.1", 9000)
+//!     .into_tcp_stream()
+//!     .unwrap()
+//!     .into_length_delimited();
+//!
+//! loop {
+//!     while let Some(quote) = stream.receive_next::<Quote>().unwrap() {
+//!         println!("{}", quote.bid);
+//!     }
+//! }
+//! ```
+
+use crate::buffer::ReadBuffer;
+use crate::service::select::Selectable;
+use crate::stream::{ConnectionInfo, ConnectionInfoProvider};
+#[cfg(feature = "mio")]
+use mio::{Interest, Registry, Token, event::Source};
+use prost::Message;
+use std::io;
+use std::io::{Read, Write};
+
+/// Default read buffer chunk size used by [`LengthDelimited`].
+pub const DEFAULT_CHUNK_SIZE: usize = 4096;
+
+/// Default upper bound of the message body accepted by [`LengthDelimited`].
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+const MAX_VARINT_LEN: usize = 10;
+
+/// Decode message of type `M` from `buf`.
+#[inline]
+pub fn decode<M: Message + Default>(buf: &[u8]) -> io::Result<M> {
+    M::decode(buf).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+#[cfg(feature = "ws")]
+impl crate::ws::WebsocketFrame {
+    /// Decode protobuf message of type `M` from the binary frame payload. Returns `Ok(None)` for any
+    /// other frame type and an error for fragmented frames.
+    #[inline]
+    pub fn decode_protobuf<M: Message + Default>(&self) -> io::Result<Option<M>> {
+        match self {
+            crate::ws::WebsocketFrame::Binary(true, payload) => decode(payload).map(Some),
+            crate::ws::WebsocketFrame::Binary(false, _) => {
+                Err(io::Error::new(io::ErrorKind::InvalidData, "fragmented binary frame cannot be decoded as protobuf"))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Decode varint length prefix. Returns `Ok(None)` if more bytes are needed, otherwise the
+/// decoded length together with the number of bytes the prefix occupies.
+fn decode_length_prefix(buf: &[u8]) -> io::Result<Option<(usize, usize)>> {
+    let mut value = 0u64;
+    for (i, byte) in buf.iter().take(MAX_VARINT_LEN).enumerate() {
+        value |= ((byte & 0x7f) as u64) << (i * 7);
+        if byte & 0x80 == 0 {
+            let len =
+                usize::try_from(value).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "length overflow"))?;
+            return Ok(Some((len, i + 1)));
+        }
+    }
+    if buf.len() >= MAX_VARINT_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid varint length prefix"));
+    }
+    Ok(None)
+}
+
+/// Stream of varint length delimited protobuf messages (as produced by
+/// [`Message::encode_length_delimited`]). A length prefix above the maximum message size (see
+/// [`LengthDelimited::with_max_message_size`]) fails the receive with [`io::ErrorKind::InvalidData`]
+/// before the body is buffered.
+pub struct LengthDelimited<S, const CHUNK_SIZE: usize = DEFAULT_CHUNK_SIZE> {
+    inner: S,
+    buffer: ReadBuffer<CHUNK_SIZE>,
+    pending: Option<usize>,
+    encode_buffer: Vec<u8>,
+    max_message_size: usize,
+}
+
+impl<S: Read + Write, const CHUNK_SIZE: usize> LengthDelimited<S, CHUNK_SIZE> {
+    /// Wrap `stream`.
+    pub fn new(stream: S) -> Self {
+        Self {
+            inner: stream,
+            buffer: ReadBuffer::new(),
+            pending: None,
+            encode_buffer: Vec::new(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// Maximum size of the message body (without the length prefix), [`DEFAULT_MAX_MESSAGE_SIZE`]
+    /// by default.
+    pub fn with_max_message_size(self, max_message_size: usize) -> Self {
+        Self {
+            max_message_size,
+            ..self
+        }
+    }
+
+    /// Receive the next raw message body (without the length prefix). Reads from the underlying stream
+    /// only when the buffered data does not contain a complete message. Returns `Ok(None)` if the
+    /// message is not complete yet.
+    pub fn receive_next_raw(&mut self) -> io::Result<Option<&'static [u8]>> {
+        if let Some(body) = self.try_consume()? {
+            return Ok(Some(body));
+        }
+        self.buffer.read_all_from(&mut self.inner)?;
+        self.try_consume()
+    }
+
+    /// Receive and decode the next message of type `M`. Returns `Ok(None)` if the message is not complete yet.
+    pub fn receive_next<M: Message + Default>(&mut self) -> io::Result<Option<M>> {
+        match self.receive_next_raw()? {
+            Some(body) => decode(body).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Encode `message` with the varint length prefix and write it to the underlying stream.
+    pub fn send<M: Message>(&mut self, message: &M) -> io::Result<()> {
+        self.encode_buffer.clear();
+        message
+            .encode_length_delimited(&mut self.encode_buffer)
+            .map_err(io::Error::other)?;
+        self.inner.write_all(&self.encode_buffer)?;
+        self.inner.flush()
+    }
+
+    /// Get reference to the underlying stream.
+    pub const fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Get mutable reference to the underlying stream.
+    pub const fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    fn try_consume(&mut self) -> io::Result<Option<&'static [u8]>> {
+        let len = match self.pending {
+            Some(len) => len,
+            None => match decode_length_prefix(self.buffer.view())? {
+                Some((len, _)) if len > self.max_message_size => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("message of {len} bytes exceeds the maximum of {} bytes", self.max_message_size),
+                    ));
+                }
+                Some((len, prefix_len)) => {
+                    self.buffer.consume_next(prefix_len);
+                    self.pending = Some(len);
+                    len
+                }
+                None => return Ok(None),
+            },
+        };
+        match self.buffer.consume_next(len) {
+            Some(body) => {
+                self.pending = None;
+                Ok(Some(body))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+pub trait IntoLengthDelimited<S> {
+    fn into_length_delimited(self) -> LengthDelimited<S>;
+}
+
+impl<S: Read + Write> IntoLengthDelimited<S> for S {
+    fn into_length_delimited(self) -> LengthDelimited<S> {
+        LengthDelimited::new(self)
+    }
+}
+
+impl<S: ConnectionInfoProvider, const CHUNK_SIZE: usize> ConnectionInfoProvider for LengthDelimited<S, CHUNK_SIZE> {
+    fn connection_info(&self) -> &ConnectionInfo {
+        self.inner.connection_info()
+    }
+}
+
+impl<S: Selectable, const CHUNK_SIZE: usize> Selectable for LengthDelimited<S, CHUNK_SIZE> {
+    fn connected(&mut self) -> io::Result<bool> {
+        self.inner.connected()
+    }
+
+    fn make_writable(&mut self) -> io::Result<()> {
+        self.inner.make_writable()
+    }
+
+    fn make_readable(&mut self) -> io::Result<()> {
+        self.inner.make_readable()
+    }
+
+    fn take_read_activity(&mut self) -> bool {
+        self.inner.take_read_activity()
+    }
+
+    fn take_bytes_read(&mut self) -> u64 {
+        self.inner.take_bytes_read()
+    }
+
+    fn take_bytes_written(&mut self) -> u64 {
+        self.inner.take_bytes_written()
+    }
+
+    fn set_read_paused(&mut self, paused: bool) {
+        self.inner.set_read_paused(paused)
+    }
+}
+
+#[cfg(feature = "mio")]
+impl<S: Source, const CHUNK_SIZE: usize> Source for LengthDelimited<S, CHUNK_SIZE> {
+    fn register(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+        registry.register(&mut self.inner, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+        registry.reregister(&mut self.inner, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        registry.deregister(&mut self.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct Quote {
+        #[prost(uint64, tag = "1")]
+        bid: u64,
+        #[prost(string, tag = "2")]
+        symbol: String,
+    }
+
+    struct Chunked {
+        data: Cursor<Vec<u8>>,
+        chunk: usize,
+    }
+
+    impl Read for Chunked {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.chunk);
+            match self.data.read(&mut buf[..len])? {
+                0 => Err(io::ErrorKind::WouldBlock.into()),
+                n => Ok(n),
+            }
+        }
+    }
+
+    impl Write for Chunked {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_decode_length_delimited_messages_across_reads() {
+        let quotes = (0..200)
+            .map(|i| Quote {
+                bid: i * 1000,
+                symbol: format!("SYM{i}"),
+            })
+            .collect::<Vec<_>>();
+        let mut data = Vec::new();
+        for quote in &quotes {
+            quote.encode_length_delimited(&mut data).unwrap();
+        }
+
+        let mut stream = Chunked {
+            data: Cursor::new(data),
+            chunk: 3,
+        }
+        .into_length_delimited();
+
+        let mut received = Vec::new();
+        for _ in 0..10_000 {
+            if let Some(quote) = stream.receive_next::<Quote>().unwrap() {
+                received.push(quote);
+            }
+        }
+        assert_eq!(quotes, received);
+    }
+
+    #[test]
+    fn should_reject_message_above_max_size() {
+        let quote = Quote {
+            bid: 1,
+            symbol: "SYM".repeat(10),
+        };
+        let mut data = Vec::new();
+        quote.encode_length_delimited(&mut data).unwrap();
+        let len = quote.encoded_len();
+
+        let stream = |data: &[u8]| Chunked {
+            data: Cursor::new(data.to_vec()),
+            chunk: 4096,
+        };
+        let mut accepted = stream(&data).into_length_delimited().with_max_message_size(len);
+        assert_eq!(Some(quote), accepted.receive_next::<Quote>().unwrap());
+        let mut rejected = stream(&data).into_length_delimited().with_max_message_size(len - 1);
+        let err = rejected.receive_next::<Quote>().unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn should_reject_invalid_length_prefix() {
+        assert!(decode_length_prefix(&[0xff; 10]).is_err());
+        assert_eq!(None, decode_length_prefix(&[0xff; 3]).unwrap());
+        assert_eq!(Some((300, 2)), decode_length_prefix(&[0xac, 0x02, 0x00]).unwrap());
+    }
+}
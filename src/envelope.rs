@@ -0,0 +1,396 @@
+//! Compact binary envelope for captured frames.
+//!
+//! Every captured frame (or raw stream chunk) is stored as a little endian header followed by the
+//! payload, so the data can be written and scanned without any serialization step and the captures
+//! are interoperable across the offline and online tooling. The header starts with a fixed part:
+//!
+//! | offset | size | field                                                     |
+//! |--------|------|-----------------------------------------------------------|
+//! | 0      | 2    | magic `BN`                                                |
+//! | 2      | 1    | version ([`VERSION`])                                     |
+//! | 3      | 1    | websocket op code, [`RAW`] for a raw stream chunk         |
+//! | 4      | 1    | flags: bit 0 outbound, bit 1 final fragment               |
+//! | 5      | 1    | header length in 8 byte words (fixed part included)       |
+//! | 6      | 2    | feature bits                                              |
+//! | 8      | 4    | connection id (e.g. the selector token of the endpoint)   |
+//! | 12     | 4    | payload length                                            |
+//! | 16     | 8    | timestamp in nanoseconds (from the service time source)   |
+//! | 24     | 8    | hardware RX timestamp in nanoseconds, zero if unavailable |
+//!
+//! followed by the extension fields of the features set in the header, in the order of their bits:
+//!
+//! | feature              | size | field                                   |
+//! |----------------------|------|-----------------------------------------|
+//! | [`FEATURE_SEQUENCE`] | 8    | sequence number of the envelope         |
+//!
+//! ## Schema evolution
+//! Additive changes take the next free feature bit and append their fields to the extension. The low
+//! byte of the feature bits holds the optional features: a reader that does not know them reads the
+//! fields it knows and skips the rest of the header, as its length is part of the fixed part. The high
+//! byte holds the features that change how the payload has to be interpreted, envelopes carrying an
+//! unknown one are rejected with [`EnvelopeError::UnsupportedFeatures`]. The version only changes
+//! when the fixed part does, the envelopes of every older version are still decoded (version 1 had
+//! no extension and the header length and feature bytes were reserved), so older captures stay
+//! replayable and can be rewritten in the current format with [`migrate`].
+//!
+//! ## Examples
+//! ```no_run
+//! use std::fs::File;
+//! use std::io::{BufReader, BufWriter};
+//! use boomnet::envelope::{Direction, Envelope, EnvelopeReader};
+//!
+//! let mut writer = BufWriter::new(File::create("capture.bin").unwrap());
+//! Envelope::new(7, Direction::Inbound, 0x1)
+//!     .with_timestamp_ns(1_700_000_000_000_000_000)
+//!     .with_sequence(1)
+//!     .write(br#"{"e":"trade"}"#, &mut writer)
+//!     .unwrap();
+//!
+//! let mut reader = EnvelopeReader::new(BufReader::new(File::open("capture.bin").unwrap()));
+//! while let Some((envelope, payload)) = reader.read_next().unwrap() {
+//!     println!("{} {}", envelope.connection_id, String::from_utf8_lossy(payload));
+//! }
+//! ```
+
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use thiserror::Error;
+
+/// Current version of the envelope format.
+pub const VERSION: u8 = 2;
+
+/// Length of the fixed part of the envelope header in bytes.
+pub const HEADER_LEN: usize = 32;
+
+/// Length of the longest header written by this version (all the known features set).
+pub const MAX_HEADER_LEN: usize = HEADER_LEN + 8;
+
+/// Op code of a raw stream chunk (not a websocket frame).
+pub const RAW: u8 = 0xFF;
+
+/// The header carries the sequence number of the envelope.
+pub const FEATURE_SEQUENCE: u16 = 0x0001;
+
+const MAGIC: [u8; 2] = *b"BN";
+const OUTBOUND: u8 = 0b01;
+const FIN: u8 = 0b10;
+const KNOWN_FEATURES: u16 = FEATURE_SEQUENCE;
+const REQUIRED_FEATURES: u16 = 0xFF00;
+
+// envelope, its payload and the bytes that follow
+type Split<'a> = (Envelope, &'a [u8], &'a [u8]);
+
+#[derive(Debug, Error)]
+pub enum EnvelopeError {
+    #[error("invalid envelope magic")]
+    InvalidMagic,
+    #[error("unsupported envelope version: {0}")]
+    UnsupportedVersion(u8),
+    #[error("unsupported envelope features: {0:#06x}")]
+    UnsupportedFeatures(u16),
+    #[error("invalid envelope header length: {0}")]
+    InvalidHeaderLength(usize),
+}
+
+impl From<EnvelopeError> for io::Error {
+    fn from(value: EnvelopeError) -> Self {
+        io::Error::new(ErrorKind::InvalidData, value)
+    }
+}
+
+/// Direction of the captured data.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// Envelope header, the payload length is taken from the payload when written.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Envelope {
+    pub connection_id: u32,
+    pub direction: Direction,
+    pub op_code: u8,
+    pub fin: bool,
+    pub timestamp_ns: u64,
+    pub hw_timestamp_ns: u64,
+    pub sequence: Option<u64>,
+}
+
+impl Envelope {
+    /// Envelope of the final (or only) fragment with no timestamps.
+    pub const fn new(connection_id: u32, direction: Direction, op_code: u8) -> Self {
+        Self {
+            connection_id,
+            direction,
+            op_code,
+            fin: true,
+            timestamp_ns: 0,
+            hw_timestamp_ns: 0,
+            sequence: None,
+        }
+    }
+
+    pub const fn with_fin(self, fin: bool) -> Self {
+        Self { fin, ..self }
+    }
+
+    pub const fn with_timestamp_ns(self, timestamp_ns: u64) -> Self {
+        Self { timestamp_ns, ..self }
+    }
+
+    pub const fn with_hw_timestamp_ns(self, hw_timestamp_ns: u64) -> Self {
+        Self {
+            hw_timestamp_ns,
+            ..self
+        }
+    }
+
+    pub const fn with_sequence(self, sequence: u64) -> Self {
+        Self {
+            sequence: Some(sequence),
+            ..self
+        }
+    }
+
+    /// Encode the header of the envelope carrying `len` bytes of payload in the current version,
+    /// returns the header length.
+    pub fn encode(&self, len: u32, header: &mut [u8; MAX_HEADER_LEN]) -> usize {
+        let mut header_len = HEADER_LEN;
+        let mut features = 0;
+        if let Some(sequence) = self.sequence {
+            header[header_len..header_len + 8].copy_from_slice(&sequence.to_le_bytes());
+            header_len += 8;
+            features |= FEATURE_SEQUENCE;
+        }
+        header[0..2].copy_from_slice(&MAGIC);
+        header[2] = VERSION;
+        header[3] = self.op_code;
+        header[4] = match self.direction {
+            Direction::Inbound => 0,
+            Direction::Outbound => OUTBOUND,
+        };
+        if self.fin {
+            header[4] |= FIN;
+        }
+        header[5] = (header_len / 8) as u8;
+        header[6..8].copy_from_slice(&features.to_le_bytes());
+        header[8..12].copy_from_slice(&self.connection_id.to_le_bytes());
+        header[12..16].copy_from_slice(&len.to_le_bytes());
+        header[16..24].copy_from_slice(&self.timestamp_ns.to_le_bytes());
+        header[24..32].copy_from_slice(&self.hw_timestamp_ns.to_le_bytes());
+        header_len
+    }
+
+    /// Length of the header (extension included) that starts with the `fixed` part, written by this
+    /// or any older version.
+    pub fn header_len(fixed: &[u8; HEADER_LEN]) -> Result<usize, EnvelopeError> {
+        if fixed[0..2] != MAGIC {
+            return Err(EnvelopeError::InvalidMagic);
+        }
+        match fixed[2] {
+            1 => Ok(HEADER_LEN),
+            2 => match fixed[5] as usize * 8 {
+                header_len if header_len < HEADER_LEN => Err(EnvelopeError::InvalidHeaderLength(header_len)),
+                header_len => Ok(header_len),
+            },
+            version => Err(EnvelopeError::UnsupportedVersion(version)),
+        }
+    }
+
+    /// Decode the `header` of [`Envelope::header_len`] bytes, returns the envelope and the payload
+    /// length. Optional features not known to this version are skipped.
+    pub fn decode(header: &[u8]) -> Result<(Envelope, u32), EnvelopeError> {
+        let Some(fixed) = header.first_chunk::<HEADER_LEN>() else {
+            return Err(EnvelopeError::InvalidHeaderLength(header.len()));
+        };
+        let header_len = Self::header_len(fixed)?;
+        if header.len() < header_len {
+            return Err(EnvelopeError::InvalidHeaderLength(header.len()));
+        }
+        let features = match fixed[2] {
+            1 => 0,
+            _ => u16::from_le_bytes([fixed[6], fixed[7]]),
+        };
+        let unsupported = features & REQUIRED_FEATURES & !KNOWN_FEATURES;
+        if unsupported != 0 {
+            return Err(EnvelopeError::UnsupportedFeatures(unsupported));
+        }
+        // the fields of the optional features not known to this version follow the known ones
+        let extension = &header[HEADER_LEN..header_len];
+        let sequence = match features & FEATURE_SEQUENCE {
+            0 => None,
+            _ => match extension.first_chunk::<8>() {
+                Some(bytes) => Some(u64::from_le_bytes(*bytes)),
+                None => return Err(EnvelopeError::InvalidHeaderLength(header_len)),
+            },
+        };
+        let direction = match fixed[4] & OUTBOUND {
+            0 => Direction::Inbound,
+            _ => Direction::Outbound,
+        };
+        let envelope = Envelope {
+            connection_id: u32::from_le_bytes(fixed[8..12].try_into().unwrap()),
+            direction,
+            op_code: fixed[3],
+            fin: fixed[4] & FIN != 0,
+            timestamp_ns: u64::from_le_bytes(fixed[16..24].try_into().unwrap()),
+            hw_timestamp_ns: u64::from_le_bytes(fixed[24..32].try_into().unwrap()),
+            sequence,
+        };
+        Ok((envelope, u32::from_le_bytes(fixed[12..16].try_into().unwrap())))
+    }
+
+    /// Split the first envelope off the `buf` (e.g. a shared memory region), returns the envelope, its
+    /// payload and the remaining bytes, or `None` if the envelope is not complete yet.
+    pub fn split(buf: &[u8]) -> Result<Option<Split<'_>>, EnvelopeError> {
+        let Some(fixed) = buf.first_chunk::<HEADER_LEN>() else {
+            return Ok(None);
+        };
+        let header_len = Self::header_len(fixed)?;
+        if buf.len() < header_len {
+            return Ok(None);
+        }
+        let (header, rest) = buf.split_at(header_len);
+        let (envelope, len) = Self::decode(header)?;
+        if rest.len() < len as usize {
+            return Ok(None);
+        }
+        let (payload, rest) = rest.split_at(len as usize);
+        Ok(Some((envelope, payload, rest)))
+    }
+
+    /// Write the envelope followed by the `payload`.
+    pub fn write<W: Write>(&self, payload: &[u8], writer: &mut W) -> io::Result<()> {
+        let len =
+            u32::try_from(payload.len()).map_err(|_| io::Error::new(ErrorKind::InvalidInput, "payload too large"))?;
+        let mut header = [0u8; MAX_HEADER_LEN];
+        let header_len = self.encode(len, &mut header);
+        writer.write_all(&header[..header_len])?;
+        writer.write_all(payload)
+    }
+}
+
+/// Reads the envelopes one by one, whichever version they have been written with. The header and
+/// payload buffers are reused.
+#[derive(Debug)]
+pub struct EnvelopeReader<R> {
+    inner: R,
+    header: Vec<u8>,
+    payload: Vec<u8>,
+}
+
+impl<R: Read> EnvelopeReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            header: Vec::with_capacity(MAX_HEADER_LEN),
+            payload: Vec::new(),
+        }
+    }
+
+    /// Next envelope and its payload, `None` once the input has been fully consumed. Input that ends
+    /// in the middle of an envelope fails with [`ErrorKind::UnexpectedEof`].
+    pub fn read_next(&mut self) -> io::Result<Option<(Envelope, &[u8])>> {
+        let mut fixed = [0u8; HEADER_LEN];
+        let mut read = 0;
+        while read < HEADER_LEN {
+            match self.inner.read(&mut fixed[read..]) {
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => return Err(io::Error::new(ErrorKind::UnexpectedEof, "truncated envelope header")),
+                Ok(len) => read += len,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        let header_len = Envelope::header_len(&fixed)?;
+        self.header.clear();
+        self.header.extend_from_slice(&fixed);
+        self.header.resize(header_len, 0);
+        self.inner.read_exact(&mut self.header[HEADER_LEN..])?;
+        let (envelope, len) = Envelope::decode(&self.header)?;
+        self.payload.resize(len as usize, 0);
+        self.inner.read_exact(&mut self.payload)?;
+        Ok(Some((envelope, &self.payload)))
+    }
+}
+
+/// Rewrite the envelopes read from the `reader` (written by this or any older version) in the current
+/// version, returns the number of envelopes written. The fields of the optional features not known
+/// to this version are dropped.
+pub fn migrate<R: Read, W: Write>(reader: R, writer: &mut W) -> io::Result<u64> {
+    let mut reader = EnvelopeReader::new(reader);
+    let mut count = 0;
+    while let Some((envelope, payload)) = reader.read_next()? {
+        envelope.write(payload, writer)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_write_and_read_envelopes() {
+        let text = Envelope::new(7, Direction::Inbound, 0x1)
+            .with_timestamp_ns(1_000)
+            .with_hw_timestamp_ns(999);
+        let chunk = Envelope::new(u32::MAX, Direction::Outbound, RAW)
+            .with_fin(false)
+            .with_sequence(42);
+        let mut buf = vec![];
+        text.write(b"hello", &mut buf).unwrap();
+        chunk.write(b"", &mut buf).unwrap();
+        assert_eq!(HEADER_LEN + 5 + MAX_HEADER_LEN, buf.len());
+
+        let mut reader = EnvelopeReader::new(buf.as_slice());
+        assert_eq!(Some((text, &b"hello"[..])), reader.read_next().unwrap());
+        assert_eq!(Some((chunk, &b""[..])), reader.read_next().unwrap());
+        assert!(reader.read_next().unwrap().is_none());
+
+        let (envelope, payload, rest) = Envelope::split(&buf).unwrap().unwrap();
+        assert_eq!((text, &b"hello"[..]), (envelope, payload));
+        assert_eq!(MAX_HEADER_LEN, rest.len());
+        assert!(Envelope::split(&buf[..HEADER_LEN + 4]).unwrap().is_none());
+
+        let truncated = &buf[..HEADER_LEN - 1];
+        let err = EnvelopeReader::new(truncated).read_next().unwrap_err();
+        assert_eq!(ErrorKind::UnexpectedEof, err.kind());
+        buf[2] = VERSION + 1;
+        assert!(matches!(Envelope::split(&buf), Err(EnvelopeError::UnsupportedVersion(3))));
+    }
+
+    #[test]
+    fn should_read_and_migrate_envelopes_of_older_versions() {
+        let text = Envelope::new(7, Direction::Inbound, 0x1).with_timestamp_ns(1_000);
+        let mut capture = vec![];
+        text.write(b"hello", &mut capture).unwrap();
+        // version 1 had the header length and feature bytes reserved
+        capture[2] = 1;
+        capture[5] = 0;
+        // optional feature unknown to this version, its field follows the sequence number
+        let mut header = [0u8; MAX_HEADER_LEN];
+        let header_len = text.with_sequence(1).encode(3, &mut header);
+        header[5] += 1;
+        header[6] |= 0x02;
+        capture.extend_from_slice(&header[..header_len]);
+        capture.extend_from_slice(&[0xFF; 8]);
+        capture.extend_from_slice(b"bye");
+
+        let mut migrated = vec![];
+        assert_eq!(2, migrate(capture.as_slice(), &mut migrated).unwrap());
+        let mut reader = EnvelopeReader::new(migrated.as_slice());
+        assert_eq!(Some((text, &b"hello"[..])), reader.read_next().unwrap());
+        assert_eq!(Some((text.with_sequence(1), &b"bye"[..])), reader.read_next().unwrap());
+        assert_eq!(VERSION, migrated[2]);
+
+        // unknown feature that changes the payload
+        header[7] |= 0x01;
+        assert!(matches!(
+            Envelope::decode(&[&header[..header_len], &[0; 8]].concat()),
+            Err(EnvelopeError::UnsupportedFeatures(0x0100))
+        ));
+    }
+}
@@ -0,0 +1,411 @@
+//! Startup validation of host configuration (kernel tunables, CPU isolation, clock sync).
+//!
+//! Many latency problems are caused by host misconfiguration rather than the application itself.
+//! [`HostCheck`] inspects the relevant settings and reports concrete mismatches against the selected
+//! [`Tuning`], together with the action required to fix them.
+//!
+//! ## Examples
+//! ```no_run
+//! use boomnet::host::HostCheck;
+//! use boomnet::preset::Preset;
+//!
+//! let report = HostCheck::new(Preset::LowLatency.tuning())
+//!     .with_cpu(2)
+//!     .with_net_iface("eth0")
+//!     .run();
+//!
+//! if !report.is_ok() {
+//!     eprintln!("{report}");
+//! }
+//! ```
+
+use crate::preset::{Preset, Tuning};
+use crate::syscalls;
+use log::{info, warn};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+
+const PROC_SYS_NET_CORE: &str = "/proc/sys/net/core";
+const SYS_DEVICES_CPU: &str = "/sys/devices/system/cpu";
+
+/// Severity of the reported [`Finding`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Severity {
+    /// Setting could not be inspected (missing file, insufficient permissions, etc.).
+    Info,
+    /// Setting does not match what the selected tuning expects.
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// Single result of the host check.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Finding {
+    severity: Severity,
+    subject: String,
+    message: String,
+}
+
+impl Finding {
+    fn info(subject: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Info,
+            subject: subject.into(),
+            message: message.into(),
+        }
+    }
+
+    fn warning(subject: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            subject: subject.into(),
+            message: message.into(),
+        }
+    }
+
+    /// Finding severity.
+    pub const fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    /// Setting the finding relates to, such as `net.core.rmem_max` or `cpu2.scaling_governor`.
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    /// Description of the mismatch and the suggested action.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] {}: {}", self.severity, self.subject, self.message)
+    }
+}
+
+/// Outcome of [`HostCheck::run`].
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    preset: Preset,
+    findings: Vec<Finding>,
+}
+
+impl Report {
+    /// Returns `true` if no warnings were reported.
+    pub fn is_ok(&self) -> bool {
+        !self.findings.iter().any(|f| f.severity == Severity::Warning)
+    }
+
+    /// Iterate over all findings.
+    pub fn iter(&self) -> impl Iterator<Item = &Finding> {
+        self.findings.iter()
+    }
+
+    /// Iterate over warnings only.
+    pub fn warnings(&self) -> impl Iterator<Item = &Finding> {
+        self.findings.iter().filter(|f| f.severity == Severity::Warning)
+    }
+
+    /// Log all findings using the `log` crate.
+    pub fn log(&self) {
+        for finding in &self.findings {
+            match finding.severity {
+                Severity::Info => info!("{finding}"),
+                Severity::Warning => warn!("{finding}"),
+            }
+        }
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "host check against '{}' preset: {} finding(s)", self.preset, self.findings.len())?;
+        for finding in &self.findings {
+            writeln!(f, "  {finding}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Inspects host configuration against the provided [`Tuning`].
+#[derive(Debug, Clone)]
+pub struct HostCheck {
+    tuning: Tuning,
+    cpus: Vec<usize>,
+    net_iface: Option<String>,
+}
+
+impl HostCheck {
+    /// Create a new check for the given `tuning`.
+    pub fn new(tuning: Tuning) -> Self {
+        Self {
+            tuning,
+            cpus: Vec::new(),
+            net_iface: None,
+        }
+    }
+
+    /// Add CPU the IO thread is pinned to. Isolation and frequency governor will be checked for it.
+    pub fn with_cpu(mut self, cpu: usize) -> Self {
+        self.cpus.push(cpu);
+        self
+    }
+
+    /// Network interface the connections will use. Hardware timestamping support will be checked for it.
+    pub fn with_net_iface(self, net_iface: impl AsRef<str>) -> Self {
+        Self {
+            net_iface: Some(net_iface.as_ref().to_owned()),
+            ..self
+        }
+    }
+
+    /// Run all checks and return the [`Report`].
+    pub fn run(&self) -> Report {
+        let mut findings = Vec::new();
+        self.check_busy_poll(&mut findings);
+        self.check_socket_buffers(&mut findings);
+        self.check_clock_sync(&mut findings);
+        self.check_cpus(&mut findings);
+        self.check_timestamping(&mut findings);
+        Report {
+            preset: self.tuning.preset(),
+            findings,
+        }
+    }
+
+    fn check_busy_poll(&self, findings: &mut Vec<Finding>) {
+        let Some(busy_poll) = self.tuning.busy_poll() else {
+            return;
+        };
+        let requested = busy_poll.as_micros() as u64;
+        match read_sysctl_u64("busy_read") {
+            Ok(busy_read) if busy_read < requested => findings.push(Finding::warning(
+                "net.core.busy_read",
+                format!(
+                    "SO_BUSY_POLL of {requested}us exceeds net.core.busy_read ({busy_read}us) and requires CAP_NET_ADMIN, \
+                     run `sysctl -w net.core.busy_read={requested}`"
+                ),
+            )),
+            Ok(_) => {}
+            Err(err) => findings.push(Finding::info("net.core.busy_read", format!("unable to read: {err}"))),
+        }
+        match read_sysctl_u64("busy_poll") {
+            Ok(0) => findings.push(Finding::warning(
+                "net.core.busy_poll",
+                format!("epoll busy polling is disabled, run `sysctl -w net.core.busy_poll={requested}`"),
+            )),
+            Ok(_) => {}
+            Err(err) => findings.push(Finding::info("net.core.busy_poll", format!("unable to read: {err}"))),
+        }
+    }
+
+    fn check_socket_buffers(&self, findings: &mut Vec<Finding>) {
+        let buffers = [
+            ("rmem_max", "SO_RCVBUF", self.tuning.recv_buffer_size()),
+            ("wmem_max", "SO_SNDBUF", self.tuning.send_buffer_size()),
+        ];
+        for (sysctl, option, requested) in buffers {
+            let Some(requested) = requested else {
+                continue;
+            };
+            let subject = format!("net.core.{sysctl}");
+            match read_sysctl_u64(sysctl) {
+                Ok(max) if max < requested as u64 => findings.push(Finding::warning(
+                    subject,
+                    format!(
+                        "{option} of {requested} bytes will be capped at {max} bytes, run `sysctl -w net.core.{sysctl}={requested}`"
+                    ),
+                )),
+                Ok(_) => {}
+                Err(err) => findings.push(Finding::info(subject, format!("unable to read: {err}"))),
+            }
+        }
+    }
+
+    fn check_clock_sync(&self, findings: &mut Vec<Finding>) {
+        if syscalls::is_restricted() {
+            findings.push(Finding::info("clock", "skipped in restricted mode (adjtimex)"));
+            return;
+        }
+        let mut tx: libc::timex = unsafe { std::mem::zeroed() };
+        let state = unsafe { libc::adjtimex(&mut tx) };
+        if state < 0 {
+            findings.push(Finding::info("clock", format!("unable to query: {}", io::Error::last_os_error())));
+        } else if state == libc::TIME_ERROR {
+            findings.push(Finding::warning(
+                "clock",
+                "system clock is not synchronised, timestamps will drift (check chronyd/ntpd/ptp4l)",
+            ));
+        }
+    }
+
+    fn check_cpus(&self, findings: &mut Vec<Finding>) {
+        if self.cpus.is_empty() {
+            return;
+        }
+        let strict = self.tuning.preset() == Preset::LowLatency;
+        match fs::read_to_string(format!("{SYS_DEVICES_CPU}/isolated")) {
+            Ok(isolated) => {
+                let isolated = parse_cpu_list(isolated.trim());
+                for cpu in &self.cpus {
+                    if strict && !isolated.contains(cpu) {
+                        findings.push(Finding::warning(
+                            format!("cpu{cpu}"),
+                            "cpu is not isolated, add it to `isolcpus=` (and `nohz_full=`) kernel parameters",
+                        ));
+                    }
+                }
+            }
+            Err(err) => findings.push(Finding::info("cpu.isolated", format!("unable to read: {err}"))),
+        }
+        for cpu in &self.cpus {
+            let subject = format!("cpu{cpu}.scaling_governor");
+            match fs::read_to_string(format!("{SYS_DEVICES_CPU}/cpu{cpu}/cpufreq/scaling_governor")) {
+                Ok(governor) if strict && governor.trim() != "performance" => findings.push(Finding::warning(
+                    subject,
+                    format!("governor is '{}', run `cpupower -c {cpu} frequency-set -g performance`", governor.trim()),
+                )),
+                Ok(_) => {}
+                Err(err) => findings.push(Finding::info(subject, format!("unable to read: {err}"))),
+            }
+        }
+    }
+
+    fn check_timestamping(&self, findings: &mut Vec<Finding>) {
+        let Some(net_iface) = self.net_iface.as_deref() else {
+            return;
+        };
+        let subject = format!("{net_iface}.timestamping");
+        if syscalls::is_restricted() {
+            findings.push(Finding::info(subject, "skipped in restricted mode (ioctl)"));
+            return;
+        }
+        match hw_timestamping_supported(net_iface) {
+            Ok(true) => {}
+            Ok(false) => findings.push(Finding::warning(
+                subject,
+                "interface does not support hardware RX timestamping, only software timestamps will be available",
+            )),
+            Err(err) => findings.push(Finding::info(subject, format!("unable to query: {err}"))),
+        }
+    }
+}
+
+fn read_sysctl_u64(name: &str) -> io::Result<u64> {
+    fs::read_to_string(format!("{PROC_SYS_NET_CORE}/{name}"))?
+        .trim()
+        .parse()
+        .map_err(io::Error::other)
+}
+
+/// Parse kernel cpu list format such as `2-5,7`.
+fn parse_cpu_list(list: &str) -> Vec<usize> {
+    let mut cpus = Vec::new();
+    for part in list.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+        match part.split_once('-') {
+            Some((from, to)) => {
+                if let (Ok(from), Ok(to)) = (from.parse::<usize>(), to.parse::<usize>()) {
+                    cpus.extend(from..=to);
+                }
+            }
+            None => {
+                if let Ok(cpu) = part.parse() {
+                    cpus.push(cpu);
+                }
+            }
+        }
+    }
+    cpus
+}
+
+pub(crate) fn hw_timestamping_supported(net_iface: &str) -> io::Result<bool> {
+    // ---- linux/ethtool.h & linux/net_tstamp.h ----
+    const SIOCETHTOOL: libc::c_ulong = 0x8946;
+    const ETHTOOL_GET_TS_INFO: u32 = 0x41;
+    const SOF_TIMESTAMPING_RX_HARDWARE: u32 = 1 << 2;
+    const SOF_TIMESTAMPING_RAW_HARDWARE: u32 = 1 << 6;
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct EthtoolTsInfo {
+        cmd: u32,
+        so_timestamping: u32,
+        phc_index: i32,
+        tx_types: u32,
+        tx_reserved: [u32; 3],
+        rx_filters: u32,
+        rx_reserved: [u32; 3],
+    }
+
+    if net_iface.is_empty() || net_iface.len() >= libc::IFNAMSIZ {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "bad iface name"));
+    }
+
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut info = EthtoolTsInfo {
+        cmd: ETHTOOL_GET_TS_INFO,
+        ..Default::default()
+    };
+    let mut ifr: libc::ifreq = unsafe { std::mem::zeroed() };
+    for (i, b) in net_iface.as_bytes().iter().enumerate() {
+        ifr.ifr_name[i] = *b as libc::c_char;
+    }
+    ifr.ifr_ifru.ifru_data = (&mut info as *mut EthtoolTsInfo).cast::<libc::c_char>();
+
+    let rc = unsafe { libc::ioctl(fd, SIOCETHTOOL, &mut ifr) };
+    let err = io::Error::last_os_error();
+    unsafe { libc::close(fd) };
+    if rc < 0 {
+        return Err(err);
+    }
+
+    let required = SOF_TIMESTAMPING_RX_HARDWARE | SOF_TIMESTAMPING_RAW_HARDWARE;
+    Ok(info.so_timestamping & required == required)
+}
+
+/// Convenience to run the check for a [`Preset`] and log the findings.
+pub fn check_and_log(preset: Preset, cpus: impl IntoIterator<Item = usize>) -> Report {
+    let check = cpus
+        .into_iter()
+        .fold(HostCheck::new(preset.tuning()), |check, cpu| check.with_cpu(cpu));
+    let report = check.run();
+    report.log();
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_cpu_list() {
+        assert_eq!(vec![2, 3, 4, 5, 7], parse_cpu_list("2-5,7"));
+        assert_eq!(vec![0], parse_cpu_list("0"));
+        assert!(parse_cpu_list("").is_empty());
+    }
+
+    #[test]
+    fn should_report_ok_when_no_warnings() {
+        let report = Report {
+            preset: Preset::LowLatency,
+            findings: vec![Finding::info("clock", "unable to query")],
+        };
+        assert!(report.is_ok());
+        assert_eq!(0, report.warnings().count());
+    }
+}
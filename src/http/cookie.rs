@@ -0,0 +1,191 @@
+//! Session cookies held across requests.
+//!
+//! [`CookieJar`] stores the cookies set by the responses of the [`HttpClient`](crate::http::HttpClient)
+//! (see [`HttpClient::with_cookie_jar`](crate::http::HttpClient::with_cookie_jar)) and sends them back
+//! with the following requests. The jar is cheap to clone and every clone shares the same cookies, so
+//! the session established over HTTP (e.g. a login flow) can be presented in the websocket upgrade with
+//! [`Websocket::with_cookies`](crate::ws::Websocket::with_cookies).
+//!
+//! The jar is meant for a single venue: the `Domain`, `Secure` and `HttpOnly` attributes are ignored,
+//! only `Path` and `Max-Age` are honoured. `Expires` is not parsed, such cookies last for the session.
+//!
+//! ## Examples
+//! ```no_run
+//! use boomnet::http::{ConnectionPool, CookieJar, Method, SingleTlsConnectionPool};
+//! use boomnet::stream::ConnectionInfo;
+//!
+//! let jar = CookieJar::new();
+//! let mut client = SingleTlsConnectionPool::new(ConnectionInfo::new("example.com", 443))
+//!     .into_http_client()
+//!     .with_cookie_jar(jar.clone())
+//!     .with_redirects(5);
+//!
+//! let (status, _, _) = client
+//!     .new_request(Method::POST, "/login", Some(b"user=me"))
+//!     .unwrap()
+//!     .block()
+//!     .unwrap();
+//! println!("{status}, session: {:?}", jar.get("session"));
+//! ```
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Cookies shared by the clones of the jar, see the [module](self) documentation.
+#[derive(Debug, Clone, Default)]
+pub struct CookieJar {
+    cookies: Rc<RefCell<Vec<Cookie>>>,
+}
+
+#[derive(Debug)]
+struct Cookie {
+    name: String,
+    value: String,
+    path: String,
+    expires: Option<Instant>,
+}
+
+impl Cookie {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    /// Path match from RFC 6265 section 5.1.4.
+    fn matches(&self, path: &str) -> bool {
+        match path.strip_prefix(self.path.as_str()) {
+            Some(rest) => rest.is_empty() || self.path.ends_with('/') || rest.starts_with('/'),
+            None => false,
+        }
+    }
+}
+
+impl CookieJar {
+    /// Create an empty jar.
+    pub fn new() -> CookieJar {
+        Self::default()
+    }
+
+    /// Store the cookie from the `Set-Cookie` header value of the response to the request for the
+    /// `request_path`, replacing the one with the same name and path. A cookie with `Max-Age` of zero
+    /// or less is removed instead.
+    pub fn store(&self, set_cookie: &str, request_path: &str) {
+        let mut attributes = set_cookie.split(';');
+        let Some((name, value)) = attributes.next().and_then(|pair| pair.split_once('=')) else {
+            return;
+        };
+        let name = name.trim();
+        if name.is_empty() {
+            return;
+        }
+        let mut path = None;
+        let mut max_age = None;
+        for attribute in attributes {
+            let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+            let (key, value) = (key.trim(), value.trim());
+            if key.eq_ignore_ascii_case("Path") && value.starts_with('/') {
+                path = Some(value);
+            } else if key.eq_ignore_ascii_case("Max-Age") {
+                max_age = value.parse::<i64>().ok();
+            }
+        }
+        let path = path.unwrap_or_else(|| default_path(request_path));
+        let mut cookies = self.cookies.borrow_mut();
+        cookies.retain(|cookie| cookie.name != name || cookie.path != path);
+        let expires = match max_age {
+            Some(seconds) if seconds <= 0 => return,
+            Some(seconds) => Some(Instant::now() + Duration::from_secs(seconds as u64)),
+            None => None,
+        };
+        cookies.push(Cookie {
+            name: name.to_owned(),
+            value: value.trim().to_owned(),
+            path: path.to_owned(),
+            expires,
+        });
+    }
+
+    /// Set the cookie for every path, e.g. a session token obtained out of band.
+    pub fn insert(&self, name: &str, value: &str) {
+        self.store(&format!("{name}={value}; Path=/"), "/");
+    }
+
+    /// Value of the cookie with the `name` (the first one stored if set for several paths).
+    pub fn get(&self, name: &str) -> Option<String> {
+        let now = Instant::now();
+        self.cookies
+            .borrow()
+            .iter()
+            .find(|cookie| cookie.name == name && !cookie.is_expired(now))
+            .map(|cookie| cookie.value.clone())
+    }
+
+    /// Value of the `Cookie` header of the request for the `path` (the query is ignored), `None` if
+    /// no cookie applies.
+    pub fn header(&self, path: &str) -> Option<String> {
+        let path = path.split(['?', '#']).next().unwrap_or_default();
+        let now = Instant::now();
+        let mut cookies = self.cookies.borrow_mut();
+        cookies.retain(|cookie| !cookie.is_expired(now));
+        let mut header = String::new();
+        for cookie in cookies.iter().filter(|cookie| cookie.matches(path)) {
+            if !header.is_empty() {
+                header.push_str("; ");
+            }
+            header.push_str(&cookie.name);
+            header.push('=');
+            header.push_str(&cookie.value);
+        }
+        (!header.is_empty()).then_some(header)
+    }
+
+    /// Number of cookies stored (including the expired ones not purged yet).
+    pub fn len(&self) -> usize {
+        self.cookies.borrow().len()
+    }
+
+    /// Checks if no cookie is stored.
+    pub fn is_empty(&self) -> bool {
+        self.cookies.borrow().is_empty()
+    }
+
+    /// Remove every cookie, e.g. on logout.
+    pub fn clear(&self) {
+        self.cookies.borrow_mut().clear();
+    }
+}
+
+/// Default cookie path from RFC 6265 section 5.1.4, the directory of the request path.
+fn default_path(request_path: &str) -> &str {
+    let path = request_path.split(['?', '#']).next().unwrap_or_default();
+    match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(end) => &path[..end],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_match_cookies_by_path() {
+        let jar = CookieJar::new();
+        jar.store("session=abc; Path=/; HttpOnly; Secure", "/login");
+        jar.store("token = xyz ; path=/api", "/login");
+        jar.store("page=1", "/docs/index.html?v=1");
+        assert_eq!(3, jar.len());
+
+        assert_eq!(Some("session=abc; token=xyz".to_owned()), jar.header("/api/orders?symbol=BTC"));
+        assert_eq!(Some("session=abc".to_owned()), jar.header("/apiv2"));
+        assert_eq!(Some("session=abc; page=1".to_owned()), jar.header("/docs/faq"));
+        assert_eq!(Some("xyz".to_owned()), jar.get("token"));
+
+        // replaced, then removed
+        jar.store("session=def; Path=/", "/");
+        assert_eq!(Some("def".to_owned()), jar.get("session"));
+        jar.store("session=; Path=/; Max-Age=0", "/");
+        assert_eq!(None, jar.get("session"));
+        assert_eq!(None, jar.header("/ws"));
+    }
+}
@@ -35,11 +35,17 @@ use std::io;
 use std::io::{ErrorKind, Read, Write};
 use std::ops::{Index, IndexMut};
 use std::rc::Rc;
+use url::{Position, Url};
 
 // re-export
+pub use crate::http::cookie::CookieJar;
+pub use crate::http::upload::{DEFAULT_UPLOAD_CHUNK_SIZE, HttpUpload};
 pub use http::Method;
 use smallvec::SmallVec;
 
+mod cookie;
+mod upload;
+
 /// Default capacity of the buffer when reading chunks of bytes from the stream.
 pub const DEFAULT_CHUNK_SIZE: usize = 1024;
 
@@ -81,12 +87,6 @@ impl<'a> Headers<'a> {
         self.inner.push((key, value));
     }
 
-    /// Returns `true` if no headers are present.
-    #[inline]
-    fn is_empty(&self) -> bool {
-        self.inner.is_empty()
-    }
-
     /// Returns iterator over the headers.
     #[inline]
     fn iter(&self) -> impl Iterator<Item = &(&str, &str)> {
@@ -105,6 +105,8 @@ impl<'a> Headers<'a> {
 pub struct HttpClient<C: ConnectionPool<CHUNK_SIZE>, const CHUNK_SIZE: usize = DEFAULT_CHUNK_SIZE> {
     connection_pool: Rc<RefCell<C>>,
     headers: Headers<'static>,
+    cookies: Option<CookieJar>,
+    max_redirects: u8,
 }
 
 impl<C: ConnectionPool<CHUNK_SIZE>, const CHUNK_SIZE: usize> HttpClient<C, CHUNK_SIZE> {
@@ -115,9 +117,33 @@ impl<C: ConnectionPool<CHUNK_SIZE>, const CHUNK_SIZE: usize> HttpClient<C, CHUNK
             headers: Headers {
                 inner: SmallVec::with_capacity(32),
             },
+            cookies: None,
+            max_redirects: 0,
         }
     }
 
+    /// Store the cookies set by the responses in the `jar` and send them with every request, see
+    /// [`CookieJar`].
+    pub fn with_cookie_jar(self, jar: CookieJar) -> HttpClient<C, CHUNK_SIZE> {
+        Self {
+            cookies: Some(jar),
+            ..self
+        }
+    }
+
+    /// Follow at most `max_redirects` redirects (`301`, `302`, `303`, `307` and `308`) to the same host
+    /// before the response is returned, the redirects are not followed by default. The `303` response
+    /// (and `301`/`302` to a `POST`) is followed with a `GET` without body. A redirect to another host
+    /// can not be followed through the connection pool and is returned as the response.
+    pub fn with_redirects(self, max_redirects: u8) -> HttpClient<C, CHUNK_SIZE> {
+        Self { max_redirects, ..self }
+    }
+
+    /// Cookie jar of the client, if any.
+    pub fn cookie_jar(&self) -> Option<&CookieJar> {
+        self.cookies.as_ref()
+    }
+
     /// Prepare a request with custom headers and optional body.
     ///
     /// # Examples
@@ -154,7 +180,28 @@ impl<C: ConnectionPool<CHUNK_SIZE>, const CHUNK_SIZE: usize> HttpClient<C, CHUNK
             .borrow_mut()
             .acquire()?
             .ok_or_else(|| io::Error::other("no available connection"))?;
-        let request = HttpRequest::new(method, path, body, &self.headers, conn, self.connection_pool.clone())?;
+        let path = path.as_ref();
+        let cookie = self.cookies.as_ref().and_then(|jar| jar.header(path));
+        let mut request = HttpRequest::new(
+            method.clone(),
+            path,
+            body,
+            &self.headers,
+            cookie.as_deref(),
+            conn,
+            self.connection_pool.clone(),
+        )?;
+        if self.cookies.is_some() || self.max_redirects > 0 {
+            request.followup = Some(Box::new(Followup {
+                method,
+                path: path.to_owned(),
+                body: (self.max_redirects > 0).then(|| body.map(<[u8]>::to_vec)).flatten(),
+                headers: self.headers.inner.to_vec(),
+                cookies: self.cookies.clone(),
+                redirects: self.max_redirects,
+                location: None,
+            }));
+        }
         Ok(request)
     }
 
@@ -271,6 +318,20 @@ pub struct HttpRequest<C: ConnectionPool<CHUNK_SIZE>, const CHUNK_SIZE: usize =
     conn: Option<Connection<C::Stream, CHUNK_SIZE>>,
     pool: Rc<RefCell<C>>,
     state: State,
+    // only with the cookie jar or the redirects enabled
+    followup: Option<Box<Followup>>,
+}
+
+/// Request kept to store the cookies set by its response and to follow the redirects.
+struct Followup {
+    method: Method,
+    path: String,
+    // only kept when the redirects are followed
+    body: Option<Vec<u8>>,
+    headers: Vec<(&'static str, &'static str)>,
+    cookies: Option<CookieJar>,
+    redirects: u8,
+    location: Option<String>,
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -290,52 +351,75 @@ enum State {
 impl<C: ConnectionPool<CHUNK_SIZE>, const CHUNK_SIZE: usize> HttpRequest<C, CHUNK_SIZE> {
     fn new(
         method: Method,
-        path: impl AsRef<str>,
+        path: &str,
         body: Option<&[u8]>,
         headers: &Headers,
+        cookie: Option<&str>,
         mut conn: Connection<C::Stream, CHUNK_SIZE>,
         pool: Rc<RefCell<C>>,
     ) -> io::Result<HttpRequest<C, CHUNK_SIZE>> {
-        conn.write_all(method.as_str().as_bytes())?;
-        conn.write_all(b" ")?;
-        conn.write_all(path.as_ref().as_bytes())?;
-        conn.write_all(b" HTTP/1.1\r\nHost: ")?;
-        conn.write_all(pool.borrow().host().as_bytes())?;
-        if !headers.is_empty() {
-            conn.write_all(b"\r\n")?;
-            for header in headers.iter() {
-                conn.write_all(header.0.as_bytes())?;
-                conn.write_all(b": ")?;
-                conn.write_all(header.1.as_bytes())?;
-                conn.write_all(b"\r\n")?;
-            }
-            if let Some(body) = body {
-                conn.write_all(b"Content-Length: ")?;
-                let mut buf = itoa::Buffer::new();
-                conn.write_all(buf.format(body.len()).as_bytes())?;
-                conn.write_all(b"\r\n")?;
-            }
-            conn.write_all(b"\r\n")?;
-        } else if let Some(body) = body {
-            conn.write_all(b"\r\n")?;
-            conn.write_all(b"Content-Length: ")?;
-            let mut buf = itoa::Buffer::new();
-            conn.write_all(buf.format(body.as_ref().len()).as_bytes())?;
-            conn.write_all(b"\r\n\r\n")?;
-        } else {
-            conn.write_all(b"\r\n\r\n")?;
-        }
-        if let Some(body) = body {
-            conn.write_all(body)?;
-        }
-        conn.flush()?;
+        write_request(&mut conn, pool.borrow().host(), &method, path, body, headers, cookie)?;
         Ok(Self {
             conn: Some(conn),
             pool,
             state: State::ReadingHeaders,
+            followup: None,
         })
     }
 
+    /// Send the request again to the `location` of the redirect response, returns `false` if it can
+    /// not be followed.
+    fn follow(&mut self, status_code: u16, location: &str) -> io::Result<bool> {
+        let Some(followup) = self.followup.as_mut() else {
+            return Ok(false);
+        };
+        let host = self.pool.borrow().host().to_owned();
+        let Ok(base) = Url::parse(&format!("http://{host}{}", followup.path)) else {
+            return Ok(false);
+        };
+        let Ok(target) = base.join(location) else {
+            return Ok(false);
+        };
+        if !target
+            .host_str()
+            .is_some_and(|target| target.eq_ignore_ascii_case(&host))
+        {
+            return Ok(false);
+        }
+        followup.path = target[Position::BeforePath..Position::AfterQuery].to_owned();
+        if status_code == 303 || (matches!(status_code, 301 | 302) && followup.method == Method::POST) {
+            followup.method = Method::GET;
+            followup.body = None;
+        }
+        followup.redirects -= 1;
+        // the redirect response has been read in full, the connection can be reused
+        if let Some(conn) = self.conn.as_mut() {
+            conn.buffer.clear();
+        }
+        self.pool.borrow_mut().release(self.conn.take());
+        let mut conn = self
+            .pool
+            .borrow_mut()
+            .acquire()?
+            .ok_or_else(|| io::Error::other("no available connection"))?;
+        let headers = Headers {
+            inner: followup.headers.iter().copied().collect(),
+        };
+        let cookie = followup.cookies.as_ref().and_then(|jar| jar.header(&followup.path));
+        let result = write_request(
+            &mut conn,
+            &host,
+            &followup.method,
+            &followup.path,
+            followup.body.as_deref(),
+            &headers,
+            cookie.as_deref(),
+        );
+        self.conn = Some(conn);
+        self.state = State::ReadingHeaders;
+        result.map(|()| true)
+    }
+
     /// Block until the full response is available.
     #[inline]
     pub fn block(mut self) -> io::Result<(u16, String, String)> {
@@ -403,7 +487,9 @@ impl<C: ConnectionPool<CHUNK_SIZE>, const CHUNK_SIZE: usize> HttpRequest<C, CHUN
                                                 .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?
                                                 .parse()
                                                 .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
-                                            break;
+                                        }
+                                        if let Some(followup) = self.followup.as_mut() {
+                                            followup.on_header(status_code, header.name, header.value);
                                         }
                                     }
                                     self.state = State::ReadingBody {
@@ -437,6 +523,12 @@ impl<C: ConnectionPool<CHUNK_SIZE>, const CHUNK_SIZE: usize> HttpRequest<C, CHUN
                     header_len,
                     status_code,
                 } => {
+                    if let Some(location) = self.followup.as_mut().and_then(|followup| followup.location.take()) {
+                        if self.follow(status_code, &location)? {
+                            return Ok(None);
+                        }
+                    }
+                    let conn = self.conn.as_mut().expect("connection");
                     let (headers, body) = conn.buffer.split_at(header_len);
                     let headers =
                         std::str::from_utf8(headers).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
@@ -449,6 +541,65 @@ impl<C: ConnectionPool<CHUNK_SIZE>, const CHUNK_SIZE: usize> HttpRequest<C, CHUN
     }
 }
 
+impl Followup {
+    /// Store the cookie and the location of the redirect (if it is to be followed) set by the header.
+    fn on_header(&mut self, status_code: u16, name: &str, value: &[u8]) {
+        let Ok(value) = std::str::from_utf8(value) else {
+            return;
+        };
+        if name.eq_ignore_ascii_case("Set-Cookie") {
+            if let Some(jar) = self.cookies.as_ref() {
+                jar.store(value, &self.path);
+            }
+        } else if name.eq_ignore_ascii_case("Location")
+            && self.redirects > 0
+            && matches!(status_code, 301 | 302 | 303 | 307 | 308)
+        {
+            self.location = Some(value.to_owned());
+        }
+    }
+}
+
+/// Write the request line, the headers and the body (if any) and flush.
+fn write_request<W: Write>(
+    stream: &mut W,
+    host: &str,
+    method: &Method,
+    path: &str,
+    body: Option<&[u8]>,
+    headers: &Headers,
+    cookie: Option<&str>,
+) -> io::Result<()> {
+    stream.write_all(method.as_str().as_bytes())?;
+    stream.write_all(b" ")?;
+    stream.write_all(path.as_bytes())?;
+    stream.write_all(b" HTTP/1.1\r\nHost: ")?;
+    stream.write_all(host.as_bytes())?;
+    stream.write_all(b"\r\n")?;
+    for header in headers.iter() {
+        stream.write_all(header.0.as_bytes())?;
+        stream.write_all(b": ")?;
+        stream.write_all(header.1.as_bytes())?;
+        stream.write_all(b"\r\n")?;
+    }
+    if let Some(cookie) = cookie {
+        stream.write_all(b"Cookie: ")?;
+        stream.write_all(cookie.as_bytes())?;
+        stream.write_all(b"\r\n")?;
+    }
+    if let Some(body) = body {
+        stream.write_all(b"Content-Length: ")?;
+        let mut buf = itoa::Buffer::new();
+        stream.write_all(buf.format(body.len()).as_bytes())?;
+        stream.write_all(b"\r\n")?;
+    }
+    stream.write_all(b"\r\n")?;
+    if let Some(body) = body {
+        stream.write_all(body)?;
+    }
+    stream.flush()
+}
+
 impl<C: ConnectionPool<CHUNK_SIZE>, const CHUNK_SIZE: usize> Drop for HttpRequest<C, CHUNK_SIZE> {
     fn drop(&mut self) {
         if let Some(conn) = self.conn.as_mut() {
@@ -560,4 +711,82 @@ mod tests {
 
         assert!(iter.next().is_none());
     }
+
+    /// Answers every request with the next response.
+    struct Server {
+        requests: Rc<RefCell<Vec<u8>>>,
+        responses: Vec<&'static [u8]>,
+        pending: Option<&'static [u8]>,
+    }
+
+    impl Read for Server {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let response = self.pending.take().ok_or(ErrorKind::WouldBlock)?;
+            buf[..response.len()].copy_from_slice(response);
+            Ok(response.len())
+        }
+    }
+
+    impl Write for Server {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.requests.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.pending = Some(self.responses.remove(0));
+            Ok(())
+        }
+    }
+
+    struct Pool(Option<Connection<Server>>);
+
+    impl ConnectionPool for Pool {
+        type Stream = Server;
+
+        fn host(&self) -> &str {
+            "example.com"
+        }
+
+        fn acquire(&mut self) -> io::Result<Option<Connection<Server>>> {
+            Ok(self.0.take())
+        }
+
+        fn release(&mut self, conn: Option<Connection<Server>>) {
+            self.0 = conn;
+        }
+    }
+
+    #[test]
+    fn should_follow_redirect_with_cookies() {
+        let requests = Rc::new(RefCell::new(vec![]));
+        let server = Server {
+            requests: requests.clone(),
+            responses: vec![
+                b"HTTP/1.1 302 Found\r\nLocation: /home?lang=en\r\n\
+                Set-Cookie: session=abc; Path=/\r\nContent-Length: 0\r\n\r\n",
+                b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok",
+            ],
+            pending: None,
+        };
+        let jar = CookieJar::new();
+        let mut client = Pool(Some(Connection::new(server)))
+            .into_http_client()
+            .with_cookie_jar(jar.clone())
+            .with_redirects(1);
+
+        let (status, _, body) = client
+            .new_request(Method::POST, "/login", Some(b"user=me"))
+            .unwrap()
+            .block()
+            .unwrap();
+        assert_eq!((200, "ok"), (status, body.as_str()));
+        assert_eq!(Some("abc".to_owned()), jar.get("session"));
+        let requests = String::from_utf8(requests.borrow().clone()).unwrap();
+        assert_eq!(
+            "POST /login HTTP/1.1\r\nHost: example.com\r\nContent-Length: 7\r\n\r\nuser=me\
+            GET /home?lang=en HTTP/1.1\r\nHost: example.com\r\nCookie: session=abc\r\n\r\n",
+            requests
+        );
+    }
 }
@@ -0,0 +1,349 @@
+//! Request bodies streamed from a reader with the chunked transfer encoding.
+//!
+//! [`HttpUpload`] sends the body as it is read (e.g. from a file of batch orders) without knowing its
+//! length upfront or holding it in memory. Every [`HttpUpload::poll`] writes as much as the stream
+//! accepts: once the stream would block (or the reader has no data available yet) the upload resumes
+//! on the next poll where it left off, so a slow peer never blocks the caller. The progress callback
+//! is invoked with the number of body bytes sent after every chunk.
+//!
+//! ## Examples
+//! ```no_run
+//! use boomnet::http::{ConnectionPool, Method, SingleTlsConnectionPool};
+//! use boomnet::stream::ConnectionInfo;
+//! use std::fs::File;
+//!
+//! let mut client = SingleTlsConnectionPool::new(ConnectionInfo::new("example.com", 443)).into_http_client();
+//! let orders = File::open("orders.jsonl").unwrap();
+//! let mut upload = client
+//!     .new_upload(Method::POST, "/batch", orders)
+//!     .unwrap()
+//!     .with_chunk_size(64 * 1024)
+//!     .with_progress(|sent| println!("{sent} bytes uploaded"));
+//!
+//! loop {
+//!     if let Some((status, _, body)) = upload.poll().unwrap() {
+//!         println!("{status}: {body}");
+//!         break;
+//!     }
+//! }
+//! ```
+
+use crate::http::{ConnectionPool, Headers, HttpClient, HttpRequest, Method};
+use std::io;
+use std::io::ErrorKind::{Interrupted, WouldBlock, WriteZero};
+use std::io::{Read, Write};
+
+/// Default number of body bytes per chunk.
+pub const DEFAULT_UPLOAD_CHUNK_SIZE: usize = 16 * 1024;
+
+// chunk size in hex (at most 16 digits) followed by CRLF
+const MAX_CHUNK_HEADER_LEN: usize = 18;
+
+const LAST_CHUNK: &[u8] = b"0\r\n\r\n";
+
+impl<C: ConnectionPool<CHUNK_SIZE>, const CHUNK_SIZE: usize> HttpClient<C, CHUNK_SIZE> {
+    /// Prepare a request with custom headers and the `body` streamed from the reader with the chunked
+    /// transfer encoding, see [`HttpUpload`].
+    pub fn new_upload_with_headers<R, F>(
+        &mut self,
+        method: Method,
+        path: impl AsRef<str>,
+        body: R,
+        builder: F,
+    ) -> io::Result<HttpUpload<C, R, CHUNK_SIZE>>
+    where
+        R: Read,
+        F: FnOnce(&mut Headers),
+    {
+        let mut request = self.new_request_with_headers(method, path, None, |headers| {
+            builder(headers);
+            headers.insert("Transfer-Encoding", "chunked");
+        })?;
+        // the body streamed from the reader can not be sent again
+        if let Some(followup) = request.followup.as_mut() {
+            followup.redirects = 0;
+        }
+        Ok(HttpUpload::new(request, body))
+    }
+
+    /// Prepare a request with no additional headers and the `body` streamed from the reader with the
+    /// chunked transfer encoding, see [`HttpUpload`].
+    pub fn new_upload<R: Read>(
+        &mut self,
+        method: Method,
+        path: impl AsRef<str>,
+        body: R,
+    ) -> io::Result<HttpUpload<C, R, CHUNK_SIZE>> {
+        self.new_upload_with_headers(method, path, body, |_| {})
+    }
+}
+
+/// HTTP request whose body is being uploaded, see the [module](self) documentation.
+pub struct HttpUpload<C: ConnectionPool<CHUNK_SIZE>, R, const CHUNK_SIZE: usize> {
+    request: HttpRequest<C, CHUNK_SIZE>,
+    body: R,
+    // chunk being written, the header is written right before the data at `MAX_CHUNK_HEADER_LEN`
+    chunk: Vec<u8>,
+    chunk_size: usize,
+    position: usize,
+    end: usize,
+    sent: u64,
+    state: UploadState,
+    progress: Option<Box<dyn FnMut(u64)>>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum UploadState {
+    Body,
+    LastChunk,
+    Sent,
+}
+
+impl<C: ConnectionPool<CHUNK_SIZE>, R: Read, const CHUNK_SIZE: usize> HttpUpload<C, R, CHUNK_SIZE> {
+    fn new(request: HttpRequest<C, CHUNK_SIZE>, body: R) -> Self {
+        Self {
+            request,
+            body,
+            chunk: Vec::new(),
+            chunk_size: DEFAULT_UPLOAD_CHUNK_SIZE,
+            position: 0,
+            end: 0,
+            sent: 0,
+            state: UploadState::Body,
+            progress: None,
+        }
+    }
+
+    /// Number of body bytes read per chunk, `16KB` by default.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        // the connection is discarded on drop, so the upload can not be rebuilt with the struct update syntax
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Invoke `progress` with the number of body bytes sent so far after every chunk.
+    pub fn with_progress<F>(mut self, progress: F) -> Self
+    where
+        F: FnMut(u64) + 'static,
+    {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Number of body bytes sent so far.
+    pub const fn sent(&self) -> u64 {
+        self.sent
+    }
+
+    /// Checks if the whole body has been sent (the response may still be pending).
+    pub fn is_sent(&self) -> bool {
+        self.state == UploadState::Sent
+    }
+
+    /// Block until the body has been sent and the full response is available.
+    pub fn block(mut self) -> io::Result<(u16, String, String)> {
+        loop {
+            if let Some((status_code, headers, body)) = self.poll()? {
+                return Ok((status_code, headers.to_owned(), body.to_owned()));
+            }
+        }
+    }
+
+    /// Send as much of the body as the stream accepts, then read the response once the body has been
+    /// sent. Returns the response when complete.
+    pub fn poll(&mut self) -> io::Result<Option<(u16, &str, &str)>> {
+        if self.state != UploadState::Sent {
+            if let Err(err) = self.upload() {
+                if let Some(conn) = self.request.conn.as_mut() {
+                    conn.disconnected = true;
+                }
+                return Err(err);
+            }
+            if self.state != UploadState::Sent {
+                return Ok(None);
+            }
+        }
+        self.request.poll()
+    }
+
+    fn upload(&mut self) -> io::Result<()> {
+        let conn = self
+            .request
+            .conn
+            .as_mut()
+            .ok_or_else(|| io::Error::other("no connection"))?;
+        loop {
+            while self.position < self.end {
+                match conn.write(&self.chunk[self.position..self.end]) {
+                    Ok(0) => return Err(io::Error::from(WriteZero)),
+                    Ok(written) => self.position += written,
+                    Err(err) if err.kind() == WouldBlock => return Ok(()),
+                    Err(err) if err.kind() == Interrupted => {}
+                    Err(err) => return Err(err),
+                }
+            }
+            match self.state {
+                UploadState::Body => {}
+                UploadState::LastChunk => {
+                    conn.flush()?;
+                    self.state = UploadState::Sent;
+                    return Ok(());
+                }
+                UploadState::Sent => return Ok(()),
+            }
+            if self.end > 0 {
+                // the previous chunk has been written
+                self.end = 0;
+                if let Some(progress) = self.progress.as_mut() {
+                    progress(self.sent);
+                }
+            }
+            let data = MAX_CHUNK_HEADER_LEN;
+            self.chunk.resize(data + self.chunk_size + 2, 0);
+            let read = match self.body.read(&mut self.chunk[data..data + self.chunk_size]) {
+                Ok(read) => read,
+                Err(err) if err.kind() == WouldBlock => {
+                    // the body is not available yet, hand over what has been written so far
+                    conn.flush()?;
+                    return Ok(());
+                }
+                Err(err) if err.kind() == Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            if read == 0 {
+                self.chunk[..LAST_CHUNK.len()].copy_from_slice(LAST_CHUNK);
+                self.position = 0;
+                self.end = LAST_CHUNK.len();
+                self.state = UploadState::LastChunk;
+                continue;
+            }
+            self.position = data - write_chunk_header(&mut self.chunk[..data], read);
+            self.end = data + read + 2;
+            self.chunk[data + read..self.end].copy_from_slice(b"\r\n");
+            self.sent += read as u64;
+        }
+    }
+}
+
+impl<C: ConnectionPool<CHUNK_SIZE>, R, const CHUNK_SIZE: usize> Drop for HttpUpload<C, R, CHUNK_SIZE> {
+    fn drop(&mut self) {
+        // the request is incomplete, the connection can not be reused
+        if self.state != UploadState::Sent {
+            if let Some(conn) = self.request.conn.as_mut() {
+                conn.disconnected = true;
+            }
+        }
+    }
+}
+
+/// Write the `len` in hex followed by CRLF at the end of the `buf`, returns the length of the header.
+fn write_chunk_header(buf: &mut [u8], len: usize) -> usize {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+    let mut end = buf.len() - 2;
+    buf[end..].copy_from_slice(b"\r\n");
+    let mut len = len;
+    loop {
+        end -= 1;
+        buf[end] = HEX[len & 0xf];
+        len >>= 4;
+        if len == 0 {
+            break;
+        }
+    }
+    buf.len() - end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Connection;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Accepts every other write of the body and answers with the canned response.
+    struct Peer {
+        written: Rc<RefCell<Vec<u8>>>,
+        would_block: bool,
+        response: io::Cursor<&'static [u8]>,
+    }
+
+    impl Read for Peer {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.response.read(buf)? {
+                0 => Err(io::Error::from(WouldBlock)),
+                read => Ok(read),
+            }
+        }
+    }
+
+    impl Write for Peer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            // the request head is written with `write_all` before the upload starts
+            let body = self.written.borrow().ends_with(b"\r\n\r\n");
+            self.would_block = body && !self.would_block;
+            if self.would_block {
+                return Err(io::Error::from(WouldBlock));
+            }
+            self.written.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct Pool(Option<Connection<Peer>>);
+
+    impl ConnectionPool for Pool {
+        type Stream = Peer;
+
+        fn host(&self) -> &str {
+            "example.com"
+        }
+
+        fn acquire(&mut self) -> io::Result<Option<Connection<Peer>>> {
+            Ok(self.0.take())
+        }
+
+        fn release(&mut self, conn: Option<Connection<Peer>>) {
+            self.0 = conn;
+        }
+    }
+
+    #[test]
+    fn should_upload_chunked_body() {
+        let written = Rc::new(RefCell::new(vec![]));
+        let peer = Peer {
+            written: written.clone(),
+            would_block: false,
+            response: io::Cursor::new(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok"),
+        };
+        let mut client = Pool(Some(Connection::new(peer))).into_http_client();
+        let progress = Rc::new(RefCell::new(vec![]));
+        let mut upload = client
+            .new_upload(Method::POST, "/batch", &b"abcdefghijklmnopqrs"[..])
+            .unwrap()
+            .with_chunk_size(16)
+            .with_progress({
+                let progress = progress.clone();
+                move |sent| progress.borrow_mut().push(sent)
+            });
+
+        let mut polls = 0;
+        let response = loop {
+            polls += 1;
+            if let Some((status, _, body)) = upload.poll().unwrap() {
+                break (status, body.to_owned());
+            }
+        };
+        assert_eq!((200, "ok".to_owned()), response);
+        // every write that would block has been resumed on the next poll
+        assert!(polls > 2);
+        assert_eq!(vec![16, 19], *progress.borrow());
+        assert_eq!(19, upload.sent());
+        let request = String::from_utf8(written.borrow().clone()).unwrap();
+        assert!(request.starts_with("POST /batch HTTP/1.1\r\nHost: example.com\r\nTransfer-Encoding: chunked\r\n\r\n"));
+        assert!(request.ends_with("\r\n\r\n10\r\nabcdefghijklmnop\r\n3\r\nqrs\r\n0\r\n\r\n"));
+    }
+}
@@ -0,0 +1,410 @@
+//! Correlation id generators and venue specific id formatting.
+//!
+//! Order entry and request/response protocols correlate responses by an id chosen by the client,
+//! which has to stay unique across restarts (the venue remembers the ids of the open orders) and
+//! across the threads sending on behalf of the same account. The [`IdGenerator`] is injected wherever
+//! ids are assigned (e.g. [`OrderSession`](crate::service::order::OrderSession)), [`Monotonic`] is a plain
+//! counter, [`SharedMonotonic`] a counter shared across threads and [`Snowflake`] derives the ids from
+//! the clock and a worker id, so processes and threads need no coordination other than distinct
+//! worker ids.
+//!
+//! Venues restrict the client order id length and charset, [`IdFormat`] writes the numeric id as a
+//! (prefixed) string accepted by the venue and parses it back from the response, without allocating.
+//!
+//! ## Examples
+//! ```
+//! use boomnet::id::{IdFormat, IdGenerator, Snowflake};
+//!
+//! let mut ids = Snowflake::new(7).unwrap();
+//! let format = IdFormat::BINANCE.with_prefix("bn");
+//! let id = ids.next_id();
+//!
+//! let mut request = br#"{"newClientOrderId":""#.to_vec();
+//! let start = request.len();
+//! format.write(id, &mut request);
+//! assert_eq!(Some(id), format.parse(&request[start..]));
+//! request.extend_from_slice(br#""}"#);
+//! ```
+
+use crate::service::time::{SystemTimeClockSource, TimeSource};
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Source of the correlation ids.
+pub trait IdGenerator {
+    /// Next id, the ids returned by the same generator must be unique and strictly increasing.
+    fn next_id(&mut self) -> u64;
+}
+
+impl<F: FnMut() -> u64> IdGenerator for F {
+    fn next_id(&mut self) -> u64 {
+        self()
+    }
+}
+
+/// Sequential ids.
+#[derive(Debug, Clone)]
+pub struct Monotonic {
+    next: u64,
+}
+
+impl Default for Monotonic {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl Monotonic {
+    /// Create generator starting at the `first` id.
+    pub const fn new(first: u64) -> Self {
+        Self { next: first }
+    }
+
+    /// Create generator starting at the current time in nanoseconds, so the ids of the restarted process
+    /// do not collide with the previous run unless it has generated more than one id per nanosecond.
+    pub fn from_time_source<TS: TimeSource>(time_source: &TS) -> Self {
+        Self::new(time_source.current_time_nanos())
+    }
+}
+
+impl IdGenerator for Monotonic {
+    #[inline]
+    fn next_id(&mut self) -> u64 {
+        let id = self.next;
+        self.next += 1;
+        id
+    }
+}
+
+/// Sequential ids shared by all the clones of the generator, e.g. by sessions on different threads.
+#[derive(Debug, Clone)]
+pub struct SharedMonotonic(Arc<AtomicU64>);
+
+impl Default for SharedMonotonic {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl SharedMonotonic {
+    /// Create generator starting at the `first` id.
+    pub fn new(first: u64) -> Self {
+        Self(Arc::new(AtomicU64::new(first)))
+    }
+
+    /// Create generator starting at the current time in nanoseconds, see [`Monotonic::from_time_source`].
+    pub fn from_time_source<TS: TimeSource>(time_source: &TS) -> Self {
+        Self::new(time_source.current_time_nanos())
+    }
+}
+
+impl IdGenerator for SharedMonotonic {
+    #[inline]
+    fn next_id(&mut self) -> u64 {
+        self.0.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+/// Largest worker id accepted by [`Snowflake`].
+pub const MAX_WORKER_ID: u16 = (1 << WORKER_BITS) - 1;
+
+const WORKER_BITS: u32 = 10;
+const SEQUENCE_BITS: u32 = 12;
+const MAX_SEQUENCE: u64 = (1 << SEQUENCE_BITS) - 1;
+// 2020-01-01T00:00:00Z, leaves 41 bits of milliseconds until 2089
+const DEFAULT_EPOCH: Duration = Duration::from_millis(1_577_836_800_000);
+
+/// Time based ids made of 41 bits of milliseconds since the epoch, 10 bits of worker id and 12 bits
+/// of sequence within the millisecond. Ids of generators with distinct worker ids never collide, more
+/// than 4096 ids within a millisecond (or the clock going backwards) borrow from the following
+/// milliseconds instead of blocking, so the ids remain unique and increasing.
+#[derive(Debug, Clone)]
+pub struct Snowflake<TS = SystemTimeClockSource> {
+    worker: u64,
+    epoch_ms: u64,
+    last_ms: u64,
+    sequence: u64,
+    time_source: TS,
+}
+
+impl Snowflake {
+    /// Create generator for the `worker_id` (at most [`MAX_WORKER_ID`]), which must be unique among
+    /// the threads and processes sharing the id space.
+    pub fn new(worker_id: u16) -> io::Result<Self> {
+        Self::new_with_time_source(worker_id, SystemTimeClockSource)
+    }
+}
+
+impl<TS: TimeSource> Snowflake<TS> {
+    /// Create generator using custom [`TimeSource`].
+    pub fn new_with_time_source(worker_id: u16, time_source: TS) -> io::Result<Self> {
+        if worker_id > MAX_WORKER_ID {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("worker id {worker_id} exceeds {MAX_WORKER_ID}"),
+            ));
+        }
+        Ok(Self {
+            worker: worker_id as u64,
+            epoch_ms: DEFAULT_EPOCH.as_millis() as u64,
+            last_ms: 0,
+            sequence: 0,
+            time_source,
+        })
+    }
+
+    /// Count the milliseconds from the custom `epoch` (since the Unix epoch) instead of 2020-01-01.
+    pub fn with_epoch(self, epoch: Duration) -> Self {
+        Self {
+            epoch_ms: epoch.as_millis() as u64,
+            ..self
+        }
+    }
+
+    /// Worker id the generator has been created for.
+    pub const fn worker_id(&self) -> u16 {
+        self.worker as u16
+    }
+}
+
+impl<TS: TimeSource> IdGenerator for Snowflake<TS> {
+    #[inline]
+    fn next_id(&mut self) -> u64 {
+        let now_ms = (self.time_source.current_time_nanos() / 1_000_000).saturating_sub(self.epoch_ms);
+        if now_ms > self.last_ms {
+            self.last_ms = now_ms;
+            self.sequence = 0;
+        } else if self.sequence == MAX_SEQUENCE {
+            self.last_ms += 1;
+            self.sequence = 0;
+        } else {
+            self.sequence += 1;
+        }
+        (self.last_ms << (WORKER_BITS + SEQUENCE_BITS)) | (self.worker << SEQUENCE_BITS) | self.sequence
+    }
+}
+
+/// Characters the id is written with, digits are in ASCII order so ids of equal length sort as numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    /// `0-9`
+    Decimal,
+    /// `0-9a-f`
+    Hex,
+    /// `0-9a-z`
+    Base36,
+    /// `0-9A-Za-z`
+    Base62,
+}
+
+impl Charset {
+    const fn radix(self) -> u64 {
+        match self {
+            Charset::Decimal => 10,
+            Charset::Hex => 16,
+            Charset::Base36 => 36,
+            Charset::Base62 => 62,
+        }
+    }
+
+    /// Length of the longest id (`u64::MAX`) written with the charset.
+    pub const fn max_digits(self) -> usize {
+        match self {
+            Charset::Decimal => 20,
+            Charset::Hex => 16,
+            Charset::Base36 => 13,
+            Charset::Base62 => 11,
+        }
+    }
+
+    #[inline]
+    const fn digit(self, value: u8) -> u8 {
+        match value {
+            0..10 => b'0' + value,
+            10..36 if matches!(self, Charset::Base62) => b'A' + value - 10,
+            10..36 => b'a' + value - 10,
+            _ => b'a' + value - 36,
+        }
+    }
+
+    #[inline]
+    const fn value(self, digit: u8) -> Option<u64> {
+        let value = match (self, digit) {
+            (_, b'0'..=b'9') => digit - b'0',
+            (Charset::Base62, b'A'..=b'Z') => digit - b'A' + 10,
+            (Charset::Base62, b'a'..=b'z') => digit - b'a' + 36,
+            (Charset::Hex | Charset::Base36, b'a'..=b'z') => digit - b'a' + 10,
+            _ => return None,
+        };
+        if value as u64 >= self.radix() {
+            return None;
+        }
+        Some(value as u64)
+    }
+}
+
+/// Venue specific string form of the id: optional prefix followed by the id written with the charset.
+/// Any `u64` id fits the maximum length, which is checked when the format is created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdFormat {
+    prefix: &'static str,
+    charset: Charset,
+    max_len: usize,
+}
+
+impl IdFormat {
+    /// Plain decimal ids, e.g. for the JSON-RPC `id`.
+    pub const DECIMAL: IdFormat = IdFormat::new(Charset::Decimal, 20);
+    /// Binance `newClientOrderId`, up to 36 characters.
+    pub const BINANCE: IdFormat = IdFormat::new(Charset::Base62, 36);
+    /// Bybit `orderLinkId`, up to 36 characters.
+    pub const BYBIT: IdFormat = IdFormat::new(Charset::Base62, 36);
+    /// OKX `clOrdId`, alphanumeric up to 32 characters.
+    pub const OKX: IdFormat = IdFormat::new(Charset::Base62, 32);
+
+    /// Create format writing the ids with the `charset`, the venue accepts at most `max_len` characters.
+    ///
+    /// # Panics
+    /// If the longest id does not fit the `max_len`.
+    pub const fn new(charset: Charset, max_len: usize) -> Self {
+        assert!(charset.max_digits() <= max_len, "the id does not fit the maximum length");
+        Self {
+            prefix: "",
+            charset,
+            max_len,
+        }
+    }
+
+    /// Start every id with the `prefix` (e.g. to tell the ids of different strategies apart), which
+    /// must only contain characters accepted by the venue.
+    ///
+    /// # Panics
+    /// If the longest id does not fit the maximum length together with the `prefix`.
+    pub const fn with_prefix(self, prefix: &'static str) -> Self {
+        assert!(
+            prefix.len() + self.charset.max_digits() <= self.max_len,
+            "the prefixed id does not fit the maximum length"
+        );
+        Self { prefix, ..self }
+    }
+
+    /// Maximum length of the id accepted by the venue.
+    pub const fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    /// Append the `id` to the `buf`.
+    #[inline]
+    pub fn write(&self, mut id: u64, buf: &mut Vec<u8>) {
+        let radix = self.charset.radix();
+        let mut digits = [0u8; Charset::Decimal.max_digits()];
+        let mut start = digits.len();
+        loop {
+            start -= 1;
+            digits[start] = self.charset.digit((id % radix) as u8);
+            id /= radix;
+            if id == 0 {
+                break;
+            }
+        }
+        buf.extend_from_slice(self.prefix.as_bytes());
+        buf.extend_from_slice(&digits[start..]);
+    }
+
+    /// Id written by [`IdFormat::write`], `None` if the `id` has been written by a different format
+    /// (or by someone else, e.g. an order placed manually).
+    #[inline]
+    pub fn parse(&self, id: &[u8]) -> Option<u64> {
+        let digits = id.strip_prefix(self.prefix.as_bytes())?;
+        if digits.is_empty() {
+            return None;
+        }
+        digits.iter().try_fold(0u64, |id, digit| {
+            id.checked_mul(self.charset.radix())?
+                .checked_add(self.charset.value(*digit)?)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct ManualClock(Rc<Cell<u64>>);
+
+    impl TimeSource for ManualClock {
+        fn current_time_nanos(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    #[test]
+    fn should_generate_unique_increasing_ids() {
+        let mut ids = Monotonic::new(5);
+        assert_eq!((5, 6), (ids.next_id(), ids.next_id()));
+
+        let shared = SharedMonotonic::default();
+        let threads = (0..4)
+            .map(|_| {
+                let mut ids = shared.clone();
+                std::thread::spawn(move || (0..1000).map(|_| ids.next_id()).collect::<Vec<_>>())
+            })
+            .collect::<Vec<_>>();
+        let mut all = vec![];
+        for thread in threads {
+            let ids = thread.join().unwrap();
+            assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+            all.extend(ids);
+        }
+        all.sort();
+        all.dedup();
+        assert_eq!(4000, all.len());
+
+        let clock = ManualClock::default();
+        clock.0.set(3_000_000);
+        let mut ids = Snowflake::new_with_time_source(5, clock.clone())
+            .unwrap()
+            .with_epoch(Duration::ZERO);
+        assert_eq!((3 << 22) | (5 << 12), ids.next_id());
+        assert_eq!((3 << 22) | (5 << 12) | 1, ids.next_id());
+        // the sequence overflow borrows the next millisecond
+        let last = (0..MAX_SEQUENCE).map(|_| ids.next_id()).last().unwrap();
+        assert_eq!((4 << 22) | (5 << 12), last);
+        // clock going backwards keeps the ids increasing
+        clock.0.set(1_000_000);
+        assert_eq!(last + 1, ids.next_id());
+        assert_eq!(5, ids.worker_id());
+        assert!(Snowflake::new(MAX_WORKER_ID + 1).is_err());
+    }
+
+    #[test]
+    fn should_write_and_parse_venue_ids() {
+        let mut buf = vec![];
+        for (format, id, expected) in [
+            (IdFormat::DECIMAL, u64::MAX, "18446744073709551615"),
+            (IdFormat::new(Charset::Hex, 16), 255, "ff"),
+            (IdFormat::new(Charset::Base36, 13), 35, "z"),
+            (IdFormat::BINANCE.with_prefix("bn-"), 0, "bn-0"),
+            (IdFormat::OKX, 62 * 62 - 1, "zz"),
+            (IdFormat::BYBIT, 36, "a"),
+            (IdFormat::OKX, u64::MAX, "LygHa16AHYF"),
+        ] {
+            buf.clear();
+            format.write(id, &mut buf);
+            assert_eq!(expected.as_bytes(), buf);
+            assert!(buf.len() <= format.max_len());
+            assert_eq!(Some(id), format.parse(&buf));
+        }
+        let format = IdFormat::BINANCE.with_prefix("bn-");
+        assert_eq!(None, format.parse(b"web_123"));
+        assert_eq!(None, format.parse(b"bn-"));
+        assert_eq!(None, format.parse(b"bn-1_2"));
+        assert_eq!(None, IdFormat::DECIMAL.parse(b"18446744073709551616"));
+        assert_eq!(None, IdFormat::new(Charset::Hex, 16).parse(b"fg"));
+    }
+}
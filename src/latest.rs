@@ -0,0 +1,252 @@
+//! State-of-the-world cells holding the latest value (e.g. the best bid and offer) per interned symbol.
+//!
+//! The IO thread publishes every update into the cell of its [`SymbolId`] with [`LatestWriter`] and
+//! any number of [`LatestReader`]s sample the most recent value from other threads. Intermediate
+//! updates are overwritten rather than queued, which suits strategies that only ever act on the
+//! latest quote: a slow reader never holds the IO thread back and never has a backlog to catch up on.
+//!
+//! Every cell is a seqlock, the writer never waits for the readers and a read only retries if it
+//! overlaps a publish into the same cell. Each cell occupies its own cache line.
+//!
+//! ## Examples
+//! ```
+//! use boomnet::latest::LatestWriter;
+//! use boomnet::symbol::SymbolTable;
+//!
+//! #[derive(Debug, Copy, Clone, PartialEq)]
+//! struct Quote {
+//!     bid: f64,
+//!     ask: f64,
+//! }
+//!
+//! let mut symbols = SymbolTable::with_capacity(16);
+//! let mut quotes = LatestWriter::<Quote>::with_capacity(symbols.capacity());
+//! let btc = symbols.intern("BTCUSDT").unwrap();
+//!
+//! let reader = quotes.reader();
+//! quotes.publish(btc, Quote { bid: 100.0, ask: 100.5 });
+//! quotes.publish(btc, Quote { bid: 100.1, ask: 100.4 });
+//!
+//! std::thread::spawn(move || {
+//!     assert_eq!(Some(Quote { bid: 100.1, ask: 100.4 }), reader.sample(btc));
+//!     assert_eq!(2, reader.version(btc));
+//! })
+//! .join()
+//! .unwrap();
+//! ```
+
+use crate::symbol::SymbolId;
+use std::cell::UnsafeCell;
+use std::hint::spin_loop;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering, fence};
+
+/// Seqlock protected value, the sequence is odd while a publish is in progress and counts two per
+/// publish.
+#[repr(align(64))]
+struct Slot<T> {
+    seq: AtomicU64,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+struct Cells<T> {
+    cells: Box<[Slot<T>]>,
+}
+
+// SAFETY: the value is only written by the single `LatestWriter` and the readers only hand out
+// copies validated by the sequence.
+unsafe impl<T: Copy + Send> Sync for Cells<T> {}
+
+impl<T: Copy> Cells<T> {
+    #[inline]
+    fn try_read(&self, id: SymbolId) -> Option<Result<(T, u64), ()>> {
+        let cell = self.cells.get(id.as_usize())?;
+        let before = cell.seq.load(Ordering::Acquire);
+        if before == 0 {
+            return None;
+        }
+        if before & 1 == 1 {
+            return Some(Err(()));
+        }
+        // SAFETY: the value may be overwritten concurrently, the copy is discarded unless the
+        // sequence has not moved, in which case it has been fully initialised by the publish
+        let value = unsafe { ptr::read_volatile(cell.value.get()) };
+        fence(Ordering::Acquire);
+        match cell.seq.load(Ordering::Relaxed) == before {
+            true => Some(Ok((unsafe { value.assume_init() }, before / 2))),
+            false => Some(Err(())),
+        }
+    }
+}
+
+/// Writer side of the cells, not `Clone` so that every cell has a single writer. Intended to be
+/// owned by the IO thread.
+pub struct LatestWriter<T> {
+    cells: Arc<Cells<T>>,
+}
+
+impl<T: Copy + Send> LatestWriter<T> {
+    /// Create cells for the symbol ids up to `capacity` (usually the capacity of the symbol table).
+    pub fn with_capacity(capacity: usize) -> Self {
+        let cells = (0..capacity)
+            .map(|_| Slot {
+                seq: AtomicU64::new(0),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            })
+            .collect();
+        Self {
+            cells: Arc::new(Cells { cells }),
+        }
+    }
+
+    /// Replace the value of the symbol `id`.
+    ///
+    /// # Panics
+    /// If the id is out of the capacity.
+    #[inline]
+    pub fn publish(&mut self, id: SymbolId, value: T) {
+        let cell = &self.cells.cells[id.as_usize()];
+        let seq = cell.seq.load(Ordering::Relaxed);
+        cell.seq.store(seq + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        // SAFETY: `&mut self` guarantees a single writer, readers validate their copy with the sequence
+        unsafe { ptr::write_volatile(cell.value.get(), MaybeUninit::new(value)) };
+        cell.seq.store(seq + 2, Ordering::Release);
+    }
+
+    /// Latest value of the symbol `id`, `None` if nothing has been published yet.
+    #[inline]
+    pub fn get(&self, id: SymbolId) -> Option<T> {
+        let cell = self.cells.cells.get(id.as_usize())?;
+        match cell.seq.load(Ordering::Relaxed) {
+            0 => None,
+            // SAFETY: only this writer modifies the value
+            _ => Some(unsafe { (*cell.value.get()).assume_init() }),
+        }
+    }
+
+    /// Number of cells.
+    pub fn capacity(&self) -> usize {
+        self.cells.cells.len()
+    }
+
+    /// Create reader that can be sent to other threads.
+    pub fn reader(&self) -> LatestReader<T> {
+        LatestReader {
+            cells: self.cells.clone(),
+        }
+    }
+}
+
+/// Reader side of the cells.
+pub struct LatestReader<T> {
+    cells: Arc<Cells<T>>,
+}
+
+impl<T> Clone for LatestReader<T> {
+    fn clone(&self) -> Self {
+        Self {
+            cells: self.cells.clone(),
+        }
+    }
+}
+
+impl<T: Copy + Send> LatestReader<T> {
+    /// Latest value of the symbol `id`, `None` if nothing has been published yet or the id is out
+    /// of the capacity.
+    #[inline]
+    pub fn sample(&self, id: SymbolId) -> Option<T> {
+        self.sample_versioned(id).map(|(value, _)| value)
+    }
+
+    /// Latest value of the symbol `id` together with its version (the number of values published so
+    /// far), which tells a reader polling the cell whether the value has changed since its last sample.
+    #[inline]
+    pub fn sample_versioned(&self, id: SymbolId) -> Option<(T, u64)> {
+        loop {
+            match self.cells.try_read(id)? {
+                Ok(sample) => return Some(sample),
+                Err(()) => spin_loop(),
+            }
+        }
+    }
+
+    /// Single attempt to sample the symbol `id`, returns `None` if the attempt overlaps a publish
+    /// (or nothing has been published yet), so it never spins.
+    #[inline]
+    pub fn try_sample(&self, id: SymbolId) -> Option<T> {
+        self.cells.try_read(id)?.ok().map(|(value, _)| value)
+    }
+
+    /// Number of values published into the cell of the symbol `id` so far.
+    #[inline]
+    pub fn version(&self, id: SymbolId) -> u64 {
+        self.cells
+            .cells
+            .get(id.as_usize())
+            .map_or(0, |cell| cell.seq.load(Ordering::Acquire) / 2)
+    }
+
+    /// Number of cells.
+    pub fn capacity(&self) -> usize {
+        self.cells.cells.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbol::SymbolTable;
+    use std::sync::atomic::AtomicBool;
+
+    #[test]
+    fn should_sample_latest_value() {
+        let mut symbols = SymbolTable::with_capacity(3);
+        let (btc, eth) = (symbols.intern("BTCUSDT").unwrap(), symbols.intern("ETHUSDT").unwrap());
+        let sol = symbols.intern("SOLUSDT").unwrap();
+        let mut writer = LatestWriter::<(u64, u64)>::with_capacity(2);
+        let reader = writer.reader();
+
+        assert_eq!((None, 0), (reader.sample(btc), reader.version(btc)));
+        writer.publish(btc, (1, 1));
+        writer.publish(btc, (2, 2));
+        writer.publish(eth, (3, 3));
+        assert_eq!(Some(((2, 2), 2)), reader.sample_versioned(btc));
+        assert_eq!(Some((3, 3)), reader.try_sample(eth));
+        assert_eq!(Some((2, 2)), writer.get(btc));
+        assert_eq!(None, reader.sample(sol));
+    }
+
+    #[test]
+    fn should_never_sample_torn_value() {
+        let mut writer = LatestWriter::<[u64; 8]>::with_capacity(1);
+        let id = SymbolTable::with_capacity(1).intern("BTCUSDT").unwrap();
+        let done = Arc::new(AtomicBool::new(false));
+        let readers = (0..2)
+            .map(|_| {
+                let (reader, done) = (writer.reader(), done.clone());
+                std::thread::spawn(move || {
+                    let mut last = 0;
+                    while !done.load(Ordering::Relaxed) {
+                        if let Some((value, version)) = reader.sample_versioned(id) {
+                            assert!(value.iter().all(|word| *word == value[0]), "torn value {value:?}");
+                            assert_eq!(value[0], version);
+                            assert!(version >= last);
+                            last = version;
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for version in 1..=100_000 {
+            writer.publish(id, [version; 8]);
+        }
+        done.store(true, Ordering::Relaxed);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+}
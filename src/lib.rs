@@ -1,9 +1,38 @@
+#[cfg(all(
+    target_os = "linux",
+    feature = "timestamping",
+    feature = "ws",
+    any(feature = "rustls", feature = "openssl")
+))]
+pub mod bench;
 pub mod buffer;
+pub mod capabilities;
+pub mod checksum;
+pub mod codec;
+pub mod envelope;
+#[cfg(target_os = "linux")]
+pub mod host;
 #[cfg(feature = "http")]
 pub mod http;
+pub mod id;
 pub mod inet;
+pub mod latest;
+pub mod metrics;
+#[cfg(feature = "model")]
+pub mod model;
+pub mod preset;
+#[cfg(target_os = "linux")]
+pub mod privilege;
+#[cfg(feature = "profile")]
+pub mod profile;
 pub mod service;
+pub mod sink;
 pub mod stream;
+pub mod symbol;
+pub mod syscalls;
+pub mod usdt;
 mod util;
 #[cfg(feature = "ws")]
 pub mod ws;
+
+pub use capabilities::capabilities;
@@ -0,0 +1,1204 @@
+//! Constant memory streaming quantile estimators for always-on latency monitoring.
+//!
+//! Unlike collecting every sample and sorting at the end, the estimators below use a fixed amount of
+//! memory regardless of how long they run:
+//! - [`DDSketch`] keeps log spaced buckets and answers any quantile with a bounded *relative* error
+//!   (e.g. 1% of the true value). Memory is bounded by the maximum number of buckets, when exceeded
+//!   the lowest buckets are collapsed so the error guarantee still holds for the upper quantiles.
+//! - [`P2Quantile`] implements the P² algorithm (Jain & Chlamtac) that tracks a single quantile with
+//!   five markers, [`P2Summary`] combines three of them for p50/p99/p99.9.
+//!
+//! Both implement [`QuantileEstimator`] and can be wrapped in [`Windowed`] to be reset on a schedule
+//! while still exposing the summary of the last completed window.
+//!
+//! [`StreamGaps`] applies the same windowed sketches to the inter-arrival times of every logical stream
+//! (e.g. `btcusdt@bookTicker`, regardless of the connection it arrives on), its [snapshot](StreamGaps::snapshot)
+//! makes venue side throttling or conflation changes visible as a shift of the gap distribution.
+//!
+//! [`RttEstimator`] follows the round trip time of a single connection (e.g. measured with websocket
+//! pings, see [`Websocket::rtt`](crate::ws::Websocket::rtt)), together with the jitter between the
+//! consecutive samples.
+//!
+//! [`OutlierCapture`] complements the estimators by keeping a ring of context records for the frames
+//! whose latency exceeded a threshold, so that individual tail spikes can be inspected afterwards.
+//! On linux the [`SchedMonitor`] sampled at batch boundaries tells whether the (pinned) IO thread was
+//! preempted or migrated in between, its deltas can be fed to the capture with
+//! [`OutlierCapture::observe_sched`] to correlate scheduler noise with the outliers.
+//!
+//! ## Examples
+//! ```
+//! use std::time::Duration;
+//! use boomnet::metrics::{DDSketch, QuantileEstimator, Windowed};
+//!
+//! let mut latency = Windowed::new(DDSketch::new(0.01), Duration::from_secs(60));
+//! for (now_ns, value) in [(0, 1_200), (1_000, 1_500), (2_000, 40_000)] {
+//!     latency.record(value, now_ns);
+//! }
+//! let summary = latency.current();
+//! assert_eq!(3, summary.count);
+//! assert!(summary.p50.abs_diff(1_500) <= 15);
+//! ```
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+#[cfg(target_os = "linux")]
+use std::fs::File;
+#[cfg(target_os = "linux")]
+use std::io;
+#[cfg(target_os = "linux")]
+use std::os::fd::RawFd;
+#[cfg(target_os = "linux")]
+use std::os::unix::fs::FileExt;
+use std::time::Duration;
+
+/// Point in time view of the estimated distribution.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Summary {
+    pub count: u64,
+    pub min: u64,
+    pub max: u64,
+    pub p50: u64,
+    pub p99: u64,
+    pub p999: u64,
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "n={} min={} p50={} p99={} p99.9={} max={}",
+            self.count, self.min, self.p50, self.p99, self.p999, self.max
+        )
+    }
+}
+
+/// Streaming estimator of the value distribution.
+pub trait QuantileEstimator {
+    /// Add observation.
+    fn record(&mut self, value: u64);
+
+    /// Number of observations since the last reset.
+    fn count(&self) -> u64;
+
+    /// Estimated p50, p99 and p99.9 together with the exact count, min and max.
+    fn summary(&self) -> Summary;
+
+    /// Discard all observations.
+    fn reset(&mut self);
+}
+
+/// Exact count, min and max shared by the estimators.
+#[derive(Debug, Copy, Clone)]
+struct Extremes {
+    count: u64,
+    min: u64,
+    max: u64,
+}
+
+impl Default for Extremes {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+}
+
+impl Extremes {
+    #[inline]
+    fn record(&mut self, value: u64) {
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    #[inline]
+    const fn min(&self) -> u64 {
+        if self.count == 0 { 0 } else { self.min }
+    }
+}
+
+/// Relative error quantile sketch with a bounded number of buckets.
+#[derive(Debug, Clone)]
+pub struct DDSketch {
+    gamma: f64,
+    ln_gamma: f64,
+    bins: Box<[u64]>,
+    // bucket index of bins[0]
+    offset: i32,
+    // highest non empty bucket index
+    max_index: i32,
+    zero_count: u64,
+    extremes: Extremes,
+}
+
+impl DDSketch {
+    /// Default maximum number of buckets, covers more than 9 orders of magnitude at 1% relative accuracy.
+    pub const DEFAULT_MAX_BINS: usize = 2048;
+
+    /// Create sketch with the `relative_accuracy` (e.g. `0.01` for 1%).
+    pub fn new(relative_accuracy: f64) -> DDSketch {
+        Self::with_max_bins(relative_accuracy, Self::DEFAULT_MAX_BINS)
+    }
+
+    /// Create sketch with the `relative_accuracy` that uses at most `max_bins` buckets.
+    pub fn with_max_bins(relative_accuracy: f64, max_bins: usize) -> DDSketch {
+        let relative_accuracy = relative_accuracy.clamp(f64::EPSILON, 0.5);
+        let gamma = (1.0 + relative_accuracy) / (1.0 - relative_accuracy);
+        Self {
+            gamma,
+            ln_gamma: gamma.ln(),
+            bins: vec![0; max_bins.max(1)].into_boxed_slice(),
+            offset: 0,
+            max_index: i32::MIN,
+            zero_count: 0,
+            extremes: Extremes::default(),
+        }
+    }
+
+    #[inline]
+    fn index(&self, value: u64) -> i32 {
+        ((value as f64).ln() / self.ln_gamma).ceil() as i32
+    }
+
+    #[inline]
+    fn value(&self, index: i32) -> u64 {
+        (2.0 * self.gamma.powi(index) / (self.gamma + 1.0)).round() as u64
+    }
+
+    /// Estimated value at the `quantile` (`0.0..=1.0`).
+    pub fn quantile(&self, quantile: f64) -> u64 {
+        if self.extremes.count == 0 {
+            return 0;
+        }
+        let rank = (quantile.clamp(0.0, 1.0) * (self.extremes.count - 1) as f64).round() as u64;
+        let mut seen = self.zero_count;
+        if rank < seen {
+            return 0;
+        }
+        for (i, count) in self.bins.iter().enumerate() {
+            seen += count;
+            if rank < seen {
+                let value = self.value(self.offset + i as i32);
+                return value.clamp(self.extremes.min(), self.extremes.max);
+            }
+        }
+        self.extremes.max
+    }
+
+    fn shift_right(&mut self, by: usize) {
+        let len = self.bins.len();
+        self.bins.copy_within(0..len - by, by);
+        self.bins[..by].fill(0);
+    }
+
+    fn shift_left(&mut self, by: usize) {
+        // collapse the lowest buckets into the new first one
+        let collapsed = self.bins[..by.min(self.bins.len())].iter().sum::<u64>();
+        if by < self.bins.len() {
+            self.bins.copy_within(by.., 0);
+            let len = self.bins.len();
+            self.bins[len - by..].fill(0);
+            self.bins[0] += collapsed;
+        } else {
+            self.bins.fill(0);
+            self.bins[0] = collapsed;
+        }
+    }
+}
+
+impl QuantileEstimator for DDSketch {
+    fn record(&mut self, value: u64) {
+        self.extremes.record(value);
+        if value == 0 {
+            self.zero_count += 1;
+            return;
+        }
+        let index = self.index(value);
+        let max_bins = self.bins.len() as i32;
+        if self.max_index == i32::MIN {
+            // first value, leave room below for smaller values
+            self.offset = index - max_bins / 2;
+        } else if index < self.offset {
+            let offset = index.max(self.max_index - max_bins + 1);
+            if offset < self.offset {
+                self.shift_right((self.offset - offset) as usize);
+                self.offset = offset;
+            }
+        } else if index >= self.offset + max_bins {
+            let offset = index - max_bins + 1;
+            self.shift_left((offset - self.offset) as usize);
+            self.offset = offset;
+        }
+        self.bins[(index - self.offset).max(0) as usize] += 1;
+        self.max_index = self.max_index.max(index);
+    }
+
+    fn count(&self) -> u64 {
+        self.extremes.count
+    }
+
+    fn summary(&self) -> Summary {
+        Summary {
+            count: self.extremes.count,
+            min: self.extremes.min(),
+            max: self.extremes.max,
+            p50: self.quantile(0.5),
+            p99: self.quantile(0.99),
+            p999: self.quantile(0.999),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.bins.fill(0);
+        self.offset = 0;
+        self.max_index = i32::MIN;
+        self.zero_count = 0;
+        self.extremes = Extremes::default();
+    }
+}
+
+/// P² single quantile estimator using five markers.
+#[derive(Debug, Clone)]
+pub struct P2Quantile {
+    quantile: f64,
+    heights: [f64; 5],
+    positions: [f64; 5],
+    desired: [f64; 5],
+    increments: [f64; 5],
+    extremes: Extremes,
+}
+
+impl P2Quantile {
+    /// Track the `quantile` (`0.0..=1.0`).
+    pub fn new(quantile: f64) -> P2Quantile {
+        let p = quantile.clamp(0.0, 1.0);
+        Self {
+            quantile: p,
+            heights: [0.0; 5],
+            positions: [1.0, 2.0, 3.0, 4.0, 5.0],
+            desired: [1.0, 1.0 + 2.0 * p, 1.0 + 4.0 * p, 3.0 + 2.0 * p, 5.0],
+            increments: [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0],
+            extremes: Extremes::default(),
+        }
+    }
+
+    /// Tracked quantile.
+    pub const fn quantile(&self) -> f64 {
+        self.quantile
+    }
+
+    /// Current estimate of the tracked quantile.
+    pub fn estimate(&self) -> u64 {
+        let count = self.extremes.count as usize;
+        if count == 0 {
+            return 0;
+        }
+        if count < 5 {
+            // not enough observations for the markers, use the exact value
+            let mut initial = [0.0; 5];
+            initial[..count].copy_from_slice(&self.heights[..count]);
+            initial[..count].sort_unstable_by(f64::total_cmp);
+            let rank = (self.quantile * (count - 1) as f64).round() as usize;
+            return initial[rank] as u64;
+        }
+        self.heights[2].round() as u64
+    }
+
+    fn parabolic(&self, i: usize, d: f64) -> f64 {
+        let (q, n) = (&self.heights, &self.positions);
+        q[i] + d / (n[i + 1] - n[i - 1])
+            * ((n[i] - n[i - 1] + d) * (q[i + 1] - q[i]) / (n[i + 1] - n[i])
+                + (n[i + 1] - n[i] - d) * (q[i] - q[i - 1]) / (n[i] - n[i - 1]))
+    }
+
+    fn linear(&self, i: usize, d: f64) -> f64 {
+        let j = if d > 0.0 { i + 1 } else { i - 1 };
+        self.heights[i] + d * (self.heights[j] - self.heights[i]) / (self.positions[j] - self.positions[i])
+    }
+}
+
+impl QuantileEstimator for P2Quantile {
+    fn record(&mut self, value: u64) {
+        let x = value as f64;
+        let count = self.extremes.count as usize;
+        self.extremes.record(value);
+        if count < 5 {
+            self.heights[count] = x;
+            if count == 4 {
+                self.heights.sort_unstable_by(f64::total_cmp);
+            }
+            return;
+        }
+
+        // find the cell and update the extreme markers
+        let k = if x < self.heights[0] {
+            self.heights[0] = x;
+            0
+        } else if x >= self.heights[4] {
+            self.heights[4] = x;
+            3
+        } else {
+            (1..5).find(|&i| x < self.heights[i]).unwrap_or(4) - 1
+        };
+        self.positions[k + 1..].iter_mut().for_each(|position| *position += 1.0);
+        for (desired, increment) in self.desired.iter_mut().zip(self.increments) {
+            *desired += increment;
+        }
+
+        // adjust the middle markers
+        for i in 1..4 {
+            let d = self.desired[i] - self.positions[i];
+            if (d >= 1.0 && self.positions[i + 1] - self.positions[i] > 1.0)
+                || (d <= -1.0 && self.positions[i - 1] - self.positions[i] < -1.0)
+            {
+                let d = d.signum();
+                let height = self.parabolic(i, d);
+                self.heights[i] = if self.heights[i - 1] < height && height < self.heights[i + 1] {
+                    height
+                } else {
+                    self.linear(i, d)
+                };
+                self.positions[i] += d;
+            }
+        }
+    }
+
+    fn count(&self) -> u64 {
+        self.extremes.count
+    }
+
+    fn summary(&self) -> Summary {
+        let estimate = self.estimate();
+        Summary {
+            count: self.extremes.count,
+            min: self.extremes.min(),
+            max: self.extremes.max,
+            p50: estimate,
+            p99: estimate,
+            p999: estimate,
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new(self.quantile);
+    }
+}
+
+/// Three [`P2Quantile`] estimators tracking p50, p99 and p99.9.
+#[derive(Debug, Clone)]
+pub struct P2Summary {
+    p50: P2Quantile,
+    p99: P2Quantile,
+    p999: P2Quantile,
+}
+
+impl Default for P2Summary {
+    fn default() -> Self {
+        Self {
+            p50: P2Quantile::new(0.5),
+            p99: P2Quantile::new(0.99),
+            p999: P2Quantile::new(0.999),
+        }
+    }
+}
+
+impl P2Summary {
+    pub fn new() -> P2Summary {
+        Self::default()
+    }
+}
+
+impl QuantileEstimator for P2Summary {
+    fn record(&mut self, value: u64) {
+        self.p50.record(value);
+        self.p99.record(value);
+        self.p999.record(value);
+    }
+
+    fn count(&self) -> u64 {
+        self.p50.count()
+    }
+
+    fn summary(&self) -> Summary {
+        Summary {
+            p50: self.p50.estimate(),
+            p99: self.p99.estimate(),
+            p999: self.p999.estimate(),
+            ..self.p50.summary()
+        }
+    }
+
+    fn reset(&mut self) {
+        self.p50.reset();
+        self.p99.reset();
+        self.p999.reset();
+    }
+}
+
+/// Resets the wrapped estimator every `interval` and keeps the summary of the last completed window.
+#[derive(Debug, Clone)]
+pub struct Windowed<E> {
+    estimator: E,
+    interval_ns: u64,
+    window_start_ns: Option<u64>,
+    last_window: Option<Summary>,
+}
+
+impl<E: QuantileEstimator> Windowed<E> {
+    pub fn new(estimator: E, interval: Duration) -> Windowed<E> {
+        Self {
+            estimator,
+            interval_ns: interval.as_nanos() as u64,
+            window_start_ns: None,
+            last_window: None,
+        }
+    }
+
+    /// Add observation made at `now_ns`, rolling over to a new window first if the interval has elapsed.
+    #[inline]
+    pub fn record(&mut self, value: u64, now_ns: u64) {
+        self.roll(now_ns);
+        self.estimator.record(value);
+    }
+
+    /// Roll over to a new window if the interval has elapsed, returns `true` if it did.
+    pub fn roll(&mut self, now_ns: u64) -> bool {
+        match self.window_start_ns {
+            Some(start_ns) if now_ns.saturating_sub(start_ns) < self.interval_ns => false,
+            Some(_) => {
+                self.last_window = Some(self.estimator.summary());
+                self.estimator.reset();
+                self.window_start_ns = Some(now_ns);
+                true
+            }
+            None => {
+                self.window_start_ns = Some(now_ns);
+                false
+            }
+        }
+    }
+
+    /// Summary of the current (incomplete) window.
+    pub fn current(&self) -> Summary {
+        self.estimator.summary()
+    }
+
+    /// Summary of the last completed window.
+    pub const fn last_window(&self) -> Option<Summary> {
+        self.last_window
+    }
+
+    /// Get reference to the wrapped estimator.
+    pub const fn estimator(&self) -> &E {
+        &self.estimator
+    }
+}
+
+/// Inter-arrival time distribution of every logical stream, each tracked by a [`Windowed`] [`DDSketch`].
+#[derive(Debug, Clone)]
+pub struct StreamGaps {
+    interval: Duration,
+    relative_accuracy: f64,
+    streams: HashMap<String, StreamGap>,
+}
+
+#[derive(Debug, Clone)]
+struct StreamGap {
+    gaps: Windowed<DDSketch>,
+    last_arrival_ns: u64,
+}
+
+/// Point in time view of the inter-arrival times of a single stream, see [`StreamGaps::snapshot`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct StreamGapSnapshot {
+    pub stream: String,
+    /// Gaps observed in the current (incomplete) window.
+    pub current: Summary,
+    /// Gaps observed in the last completed window.
+    pub last_window: Option<Summary>,
+    pub last_arrival_ns: u64,
+    /// Time since the last arrival, as of the snapshot.
+    pub silence_ns: u64,
+}
+
+impl Display for StreamGapSnapshot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "stream={} silence={} gaps: {}", self.stream, self.silence_ns, self.current)?;
+        if let Some(last_window) = self.last_window {
+            write!(f, " last_window: {last_window}")?;
+        }
+        Ok(())
+    }
+}
+
+impl StreamGaps {
+    /// Track gaps with 1% relative accuracy, resetting the distributions every `interval`.
+    pub fn new(interval: Duration) -> StreamGaps {
+        Self::with_accuracy(interval, 0.01)
+    }
+
+    /// Track gaps with the `relative_accuracy`, resetting the distributions every `interval`.
+    pub fn with_accuracy(interval: Duration, relative_accuracy: f64) -> StreamGaps {
+        Self {
+            interval,
+            relative_accuracy,
+            streams: HashMap::new(),
+        }
+    }
+
+    /// Record arrival of a message on the `stream` at `arrival_ns`, only the first arrival on every
+    /// stream allocates.
+    pub fn record(&mut self, stream: &str, arrival_ns: u64) {
+        match self.streams.get_mut(stream) {
+            Some(gap) => {
+                let gap_ns = arrival_ns.saturating_sub(gap.last_arrival_ns);
+                gap.gaps.record(gap_ns, arrival_ns);
+                gap.last_arrival_ns = arrival_ns;
+            }
+            None => {
+                let mut gaps = Windowed::new(DDSketch::new(self.relative_accuracy), self.interval);
+                gaps.roll(arrival_ns);
+                self.streams.insert(
+                    stream.to_owned(),
+                    StreamGap {
+                        gaps,
+                        last_arrival_ns: arrival_ns,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Summary of the `stream` gaps in the current window.
+    pub fn current(&self, stream: &str) -> Option<Summary> {
+        self.streams.get(stream).map(|gap| gap.gaps.current())
+    }
+
+    /// Roll over the windows as of `now_ns` (so that silent streams are reported too) and return the
+    /// view of every stream, ordered by name.
+    pub fn snapshot(&mut self, now_ns: u64) -> Vec<StreamGapSnapshot> {
+        let mut snapshot = self
+            .streams
+            .iter_mut()
+            .map(|(stream, gap)| {
+                gap.gaps.roll(now_ns);
+                StreamGapSnapshot {
+                    stream: stream.clone(),
+                    current: gap.gaps.current(),
+                    last_window: gap.gaps.last_window(),
+                    last_arrival_ns: gap.last_arrival_ns,
+                    silence_ns: now_ns.saturating_sub(gap.last_arrival_ns),
+                }
+            })
+            .collect::<Vec<_>>();
+        snapshot.sort_unstable_by(|a, b| a.stream.cmp(&b.stream));
+        snapshot
+    }
+
+    /// Stop tracking all streams.
+    pub fn clear(&mut self) {
+        self.streams.clear();
+    }
+}
+
+/// Round trip time of a single connection, see [`RttEstimator`].
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct RttStats {
+    pub samples: u64,
+    pub last: u64,
+    pub min: u64,
+    pub max: u64,
+    /// Exponentially weighted moving average with the gain of 1/8 (as the TCP `srtt`).
+    pub smoothed: u64,
+    /// Smoothed difference between consecutive samples with the gain of 1/16 (as the RTP interarrival
+    /// jitter).
+    pub jitter: u64,
+}
+
+impl Display for RttStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "n={} last={} min={} srtt={} jitter={} max={}",
+            self.samples, self.last, self.min, self.smoothed, self.jitter, self.max
+        )
+    }
+}
+
+/// Tracks the round trip time samples of a single connection with constant memory.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct RttEstimator {
+    stats: RttStats,
+}
+
+impl RttEstimator {
+    pub const fn new() -> RttEstimator {
+        Self {
+            stats: RttStats {
+                samples: 0,
+                last: 0,
+                min: 0,
+                max: 0,
+                smoothed: 0,
+                jitter: 0,
+            },
+        }
+    }
+
+    /// Add round trip time sample.
+    pub fn record(&mut self, rtt_ns: u64) {
+        let stats = &mut self.stats;
+        if stats.samples == 0 {
+            *stats = RttStats {
+                samples: 1,
+                last: rtt_ns,
+                min: rtt_ns,
+                max: rtt_ns,
+                smoothed: rtt_ns,
+                jitter: 0,
+            };
+            return;
+        }
+        let deviation = rtt_ns.abs_diff(stats.last);
+        stats.samples += 1;
+        stats.last = rtt_ns;
+        stats.min = stats.min.min(rtt_ns);
+        stats.max = stats.max.max(rtt_ns);
+        stats.smoothed = stats.smoothed - stats.smoothed / 8 + rtt_ns / 8;
+        stats.jitter = stats.jitter - stats.jitter / 16 + deviation / 16;
+    }
+
+    /// Current statistics, `None` before the first sample.
+    pub const fn stats(&self) -> Option<RttStats> {
+        match self.stats.samples {
+            0 => None,
+            _ => Some(self.stats),
+        }
+    }
+}
+
+/// Single frame observation passed to the [`OutlierCapture`].
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct FrameSample {
+    /// Caller defined connection identifier (e.g. endpoint handle or connection index).
+    pub connection: u64,
+    /// NIC (or earliest available) receive timestamp.
+    pub rx_ns: u64,
+    /// Time the frame reached the user.
+    pub user_ns: u64,
+    /// Number of frames decoded from the same socket read.
+    pub batch_size: usize,
+}
+
+/// Context captured for a frame that exceeded the latency threshold.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct OutlierRecord {
+    pub connection: u64,
+    pub rx_ns: u64,
+    pub user_ns: u64,
+    /// Receive to user latency.
+    pub latency_ns: u64,
+    pub batch_size: usize,
+    /// Bytes still queued in the socket receive buffer when the outlier was observed.
+    pub kernel_backlog: Option<usize>,
+    /// Time since the previous frame received on the same connection.
+    pub gap_ns: Option<u64>,
+    /// Cpu the frame was processed on.
+    pub cpu: Option<usize>,
+    /// Number of cpu migrations since the previous outlier, either reported by the [`SchedMonitor`]
+    /// or detected as a cpu change between two frames.
+    pub cpu_migrations: u64,
+    /// Number of involuntary context switches since the previous outlier (only reported by the
+    /// [`SchedMonitor`]).
+    pub preemptions: u64,
+}
+
+impl Display for OutlierRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "conn={} latency={} batch={} backlog={:?} gap={:?} cpu={:?} migrations={} preemptions={}",
+            self.connection,
+            self.latency_ns,
+            self.batch_size,
+            self.kernel_backlog,
+            self.gap_ns,
+            self.cpu,
+            self.cpu_migrations,
+            self.preemptions
+        )
+    }
+}
+
+/// Ring of [`OutlierRecord`]s for frames with latency above the threshold, the oldest records are
+/// overwritten once the ring is full.
+#[derive(Debug, Clone)]
+pub struct OutlierCapture {
+    threshold_ns: u64,
+    ring: Vec<OutlierRecord>,
+    capacity: usize,
+    next: usize,
+    total: u64,
+    last_rx_ns: HashMap<u64, u64>,
+    last_cpu: Option<usize>,
+    cpu_migrations: u64,
+    preemptions: u64,
+    // migrations are reported by the scheduler monitor rather than detected from cpu changes
+    sched_fed: bool,
+}
+
+impl OutlierCapture {
+    /// Capture up to `capacity` most recent frames with latency above the `threshold`.
+    pub fn new(threshold: Duration, capacity: usize) -> OutlierCapture {
+        let capacity = capacity.max(1);
+        Self {
+            threshold_ns: threshold.as_nanos() as u64,
+            ring: Vec::with_capacity(capacity),
+            capacity,
+            next: 0,
+            total: 0,
+            last_rx_ns: HashMap::new(),
+            last_cpu: None,
+            cpu_migrations: 0,
+            preemptions: 0,
+            sched_fed: false,
+        }
+    }
+
+    /// Account scheduler activity sampled at a batch boundary (see [`SchedMonitor::sample`]), it is
+    /// attributed to the next outlier. Once called, cpu migrations are no longer detected from the
+    /// cpu changes between frames.
+    pub fn observe_sched(&mut self, delta: SchedDelta) {
+        self.sched_fed = true;
+        self.cpu_migrations += delta.migrations;
+        self.preemptions += delta.involuntary_switches;
+    }
+
+    /// Observe the frame and capture its context if the latency exceeds the threshold. The socket
+    /// `backlog` is only queried for outliers (see [`kernel_backlog`]).
+    pub fn observe<F>(&mut self, sample: FrameSample, backlog: F) -> Option<&OutlierRecord>
+    where
+        F: FnOnce() -> Option<usize>,
+    {
+        let gap_ns = self
+            .last_rx_ns
+            .insert(sample.connection, sample.rx_ns)
+            .map(|last_rx_ns| sample.rx_ns.saturating_sub(last_rx_ns));
+        let cpu = current_cpu();
+        if !self.sched_fed && cpu.is_some() && self.last_cpu.is_some() && cpu != self.last_cpu {
+            self.cpu_migrations += 1;
+        }
+        self.last_cpu = cpu;
+
+        let latency_ns = sample.user_ns.saturating_sub(sample.rx_ns);
+        if sample.rx_ns == 0 || latency_ns <= self.threshold_ns {
+            return None;
+        }
+        let record = OutlierRecord {
+            connection: sample.connection,
+            rx_ns: sample.rx_ns,
+            user_ns: sample.user_ns,
+            latency_ns,
+            batch_size: sample.batch_size,
+            kernel_backlog: backlog(),
+            gap_ns,
+            cpu,
+            cpu_migrations: std::mem::take(&mut self.cpu_migrations),
+            preemptions: std::mem::take(&mut self.preemptions),
+        };
+        let index = self.next;
+        if self.ring.len() < self.capacity {
+            self.ring.push(record);
+        } else {
+            self.ring[index] = record;
+        }
+        self.next = (index + 1) % self.capacity;
+        self.total += 1;
+        Some(&self.ring[index])
+    }
+
+    /// Captured records, oldest first.
+    pub fn records(&self) -> impl Iterator<Item = &OutlierRecord> {
+        let (newest, oldest) = self
+            .ring
+            .split_at(if self.ring.len() < self.capacity { 0 } else { self.next });
+        oldest.iter().chain(newest)
+    }
+
+    /// Total number of outliers observed (including the ones already overwritten).
+    pub const fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Discard all captured records.
+    pub fn clear(&mut self) {
+        self.ring.clear();
+        self.next = 0;
+        self.total = 0;
+    }
+}
+
+/// Scheduler activity of the monitored thread between two [`SchedMonitor`] samples.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct SchedDelta {
+    /// Context switches forced by the scheduler (time slice expired or higher priority task).
+    pub involuntary_switches: u64,
+    /// Context switches due to the thread blocking (e.g. in the selector).
+    pub voluntary_switches: u64,
+    pub migrations: u64,
+    /// Cpu the thread was running on when sampled.
+    pub cpu: Option<usize>,
+}
+
+impl SchedDelta {
+    /// Checks if the thread was preempted or migrated.
+    #[inline]
+    pub const fn is_disturbed(&self) -> bool {
+        self.involuntary_switches > 0 || self.migrations > 0
+    }
+}
+
+/// Lightweight monitor of the calling thread context switches (`getrusage(RUSAGE_THREAD)`) and cpu
+/// migrations, meant to be sampled at batch boundaries of the pinned IO thread. It must be created
+/// and sampled on the thread it monitors.
+///
+/// By default migrations are detected as a cpu change between samples (`sched_getcpu`, served by the
+/// vdso), which misses a migration and back. [`SchedMonitor::with_proc_sched`] reads the exact counter
+/// from `/proc/thread-self/sched` instead at the cost of an extra `pread` per sample.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct SchedMonitor {
+    last: SchedCounters,
+    total: SchedDelta,
+    disturbed: u64,
+    proc_sched: Option<(File, Box<[u8]>)>,
+}
+
+#[cfg(target_os = "linux")]
+#[derive(Debug, Copy, Clone, Default)]
+struct SchedCounters {
+    involuntary_switches: u64,
+    voluntary_switches: u64,
+    migrations: Option<u64>,
+    cpu: Option<usize>,
+}
+
+#[cfg(target_os = "linux")]
+impl SchedMonitor {
+    /// Create monitor for the calling thread, not available in [restricted](crate::syscalls) mode.
+    pub fn new() -> io::Result<SchedMonitor> {
+        crate::syscalls::ensure_unrestricted("getrusage")?;
+        let mut monitor = Self {
+            last: SchedCounters::default(),
+            total: SchedDelta::default(),
+            disturbed: 0,
+            proc_sched: None,
+        };
+        monitor.last = monitor.counters()?;
+        Ok(monitor)
+    }
+
+    /// Read exact migration counter from `/proc/thread-self/sched` (requires `CONFIG_SCHED_DEBUG`).
+    pub fn with_proc_sched(mut self) -> io::Result<Self> {
+        let file = File::open("/proc/thread-self/sched")?;
+        self.proc_sched = Some((file, vec![0u8; 8192].into_boxed_slice()));
+        self.last = self.counters()?;
+        if self.last.migrations.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "se.nr_migrations not found in /proc/thread-self/sched",
+            ));
+        }
+        Ok(self)
+    }
+
+    /// Scheduler activity since the previous sample.
+    pub fn sample(&mut self) -> io::Result<SchedDelta> {
+        let counters = self.counters()?;
+        let migrations = match (counters.migrations, self.last.migrations) {
+            (Some(current), Some(last)) => current.saturating_sub(last),
+            _ => (counters.cpu.is_some() && self.last.cpu.is_some() && counters.cpu != self.last.cpu) as u64,
+        };
+        let delta = SchedDelta {
+            involuntary_switches: counters
+                .involuntary_switches
+                .saturating_sub(self.last.involuntary_switches),
+            voluntary_switches: counters.voluntary_switches.saturating_sub(self.last.voluntary_switches),
+            migrations,
+            cpu: counters.cpu,
+        };
+        self.last = counters;
+        self.total.involuntary_switches += delta.involuntary_switches;
+        self.total.voluntary_switches += delta.voluntary_switches;
+        self.total.migrations += delta.migrations;
+        self.total.cpu = delta.cpu;
+        if delta.is_disturbed() {
+            self.disturbed += 1;
+        }
+        Ok(delta)
+    }
+
+    /// Scheduler activity accumulated over all samples.
+    pub const fn total(&self) -> SchedDelta {
+        self.total
+    }
+
+    /// Number of samples in which the thread was preempted or migrated.
+    pub const fn disturbed(&self) -> u64 {
+        self.disturbed
+    }
+
+    fn counters(&mut self) -> io::Result<SchedCounters> {
+        // SAFETY: rusage is plain data, zeroed is a valid value
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        // SAFETY: usage is a valid pointer to rusage
+        if unsafe { libc::getrusage(libc::RUSAGE_THREAD, &mut usage) } != 0 {
+            return Err(crate::syscalls::blocked("getrusage", io::Error::last_os_error()));
+        }
+        let migrations = match self.proc_sched.as_mut() {
+            Some((file, buf)) => {
+                let len = file.read_at(buf, 0)?;
+                parse_nr_migrations(&buf[..len])
+            }
+            None => None,
+        };
+        Ok(SchedCounters {
+            involuntary_switches: usage.ru_nivcsw as u64,
+            voluntary_switches: usage.ru_nvcsw as u64,
+            migrations,
+            cpu: current_cpu(),
+        })
+    }
+}
+
+/// Extract `se.nr_migrations` from the `/proc/<pid>/sched` content.
+#[cfg(target_os = "linux")]
+fn parse_nr_migrations(sched: &[u8]) -> Option<u64> {
+    std::str::from_utf8(sched)
+        .ok()?
+        .lines()
+        .find(|line| line.starts_with("se.nr_migrations"))?
+        .rsplit(':')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+#[cfg(target_os = "linux")]
+fn current_cpu() -> Option<usize> {
+    // SAFETY: no arguments, served by the vdso
+    let cpu = unsafe { libc::sched_getcpu() };
+    (cpu >= 0).then_some(cpu as usize)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn current_cpu() -> Option<usize> {
+    None
+}
+
+/// Number of bytes queued in the socket receive buffer (`FIONREAD`), not available in
+/// [restricted](crate::syscalls) mode.
+#[cfg(target_os = "linux")]
+pub fn kernel_backlog(fd: RawFd) -> io::Result<usize> {
+    crate::syscalls::ensure_unrestricted("ioctl(FIONREAD)")?;
+    let mut pending: libc::c_int = 0;
+    // SAFETY: FIONREAD writes a single c_int
+    let rc = unsafe { libc::ioctl(fd, libc::FIONREAD, &mut pending) };
+    if rc < 0 {
+        return Err(crate::syscalls::blocked("ioctl(FIONREAD)", io::Error::last_os_error()));
+    }
+    Ok(pending.max(0) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // deterministic xorshift so the tests do not depend on rand
+    fn samples(count: usize) -> Vec<u64> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        (0..count)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                1_000 + state % 1_000_000
+            })
+            .collect()
+    }
+
+    fn exact(sorted: &[u64], quantile: f64) -> u64 {
+        sorted[(quantile * (sorted.len() - 1) as f64).round() as usize]
+    }
+
+    #[test]
+    fn should_estimate_within_relative_accuracy() {
+        let values = samples(100_000);
+        let mut sketch = DDSketch::new(0.01);
+        values.iter().for_each(|value| sketch.record(*value));
+        let mut sorted = values.clone();
+        sorted.sort_unstable();
+
+        for quantile in [0.5, 0.9, 0.99, 0.999] {
+            let expected = exact(&sorted, quantile) as f64;
+            let actual = sketch.quantile(quantile) as f64;
+            assert!((actual - expected).abs() <= expected * 0.01 + 1.0, "q={quantile}: {actual} vs {expected}");
+        }
+        assert_eq!(sorted[0], sketch.summary().min);
+        assert_eq!(sorted[sorted.len() - 1], sketch.summary().max);
+    }
+
+    #[test]
+    fn should_collapse_lowest_bins_when_full() {
+        let mut sketch = DDSketch::with_max_bins(0.01, 64);
+        (1..=1_000_000).step_by(97).for_each(|value| sketch.record(value));
+        let max = sketch.summary().max as f64;
+        assert!((sketch.quantile(1.0) as f64 - max).abs() <= max * 0.01);
+        assert!(sketch.quantile(0.0) <= sketch.quantile(0.5));
+    }
+
+    #[test]
+    fn should_estimate_with_p2() {
+        let values = samples(100_000);
+        let mut summary = P2Summary::new();
+        values.iter().for_each(|value| summary.record(*value));
+        let mut sorted = values.clone();
+        sorted.sort_unstable();
+
+        let estimate = summary.summary();
+        assert_eq!(100_000, estimate.count);
+        for (quantile, actual) in [(0.5, estimate.p50), (0.99, estimate.p99)] {
+            let expected = exact(&sorted, quantile) as f64;
+            assert!((actual as f64 - expected).abs() <= expected * 0.02, "q={quantile}: {actual} vs {expected}");
+        }
+    }
+
+    #[test]
+    fn should_use_exact_value_before_markers_are_initialised() {
+        let mut p2 = P2Quantile::new(0.5);
+        [30, 10, 20].iter().for_each(|value| p2.record(*value));
+        assert_eq!(20, p2.estimate());
+    }
+
+    #[test]
+    fn should_roll_over_windows() {
+        let mut windowed = Windowed::new(DDSketch::new(0.01), Duration::from_nanos(100));
+        windowed.record(10, 0);
+        windowed.record(20, 50);
+        assert_eq!(None, windowed.last_window());
+        assert_eq!(2, windowed.current().count);
+
+        windowed.record(30, 100);
+        assert_eq!(Some(2), windowed.last_window().map(|summary| summary.count));
+        assert_eq!(1, windowed.current().count);
+        assert_eq!(30, windowed.current().max);
+    }
+
+    #[test]
+    fn should_track_gaps_per_stream() {
+        let mut gaps = StreamGaps::new(Duration::from_nanos(1_000));
+        for arrival_ns in [0, 100, 200, 300] {
+            gaps.record("btcusdt@bookTicker", arrival_ns);
+        }
+        gaps.record("ethusdt@bookTicker", 50);
+        gaps.record("ethusdt@bookTicker", 550);
+
+        let btc = gaps.current("btcusdt@bookTicker").unwrap();
+        assert_eq!(3, btc.count);
+        assert_eq!(100, btc.min);
+        assert_eq!(100, btc.max);
+        assert_eq!(None, gaps.current("solusdt@bookTicker"));
+
+        let snapshot = gaps.snapshot(1_100);
+        assert_eq!(2, snapshot.len());
+        assert_eq!("btcusdt@bookTicker", snapshot[0].stream);
+        assert_eq!(800, snapshot[0].silence_ns);
+        assert_eq!(Some(3), snapshot[0].last_window.map(|summary| summary.count));
+        assert_eq!(0, snapshot[0].current.count);
+        assert_eq!(500, snapshot[1].last_window.unwrap().max);
+    }
+
+    #[test]
+    fn should_track_rtt_and_jitter() {
+        let mut rtt = RttEstimator::new();
+        assert_eq!(None, rtt.stats());
+
+        rtt.record(1_600);
+        assert_eq!((1_600, 0), rtt.stats().map(|stats| (stats.smoothed, stats.jitter)).unwrap());
+
+        // alternating samples keep the average but show up as jitter
+        for sample in [3_200, 1_600, 3_200, 1_600] {
+            rtt.record(sample);
+        }
+        let stats = rtt.stats().unwrap();
+        assert_eq!((5, 1_600, 1_600, 3_200), (stats.samples, stats.last, stats.min, stats.max));
+        assert!(stats.smoothed > 1_600 && stats.smoothed < 3_200);
+        assert_eq!(365, stats.jitter);
+    }
+
+    #[test]
+    fn should_capture_outliers_into_ring() {
+        let mut capture = OutlierCapture::new(Duration::from_nanos(100), 2);
+        let sample = |rx_ns, latency_ns| FrameSample {
+            connection: 1,
+            rx_ns,
+            user_ns: rx_ns + latency_ns,
+            batch_size: 3,
+        };
+
+        assert!(capture.observe(sample(1_000, 50), || Some(0)).is_none());
+        let record = *capture.observe(sample(1_500, 150), || Some(64)).unwrap();
+        assert_eq!(150, record.latency_ns);
+        assert_eq!(Some(500), record.gap_ns);
+        assert_eq!(Some(64), record.kernel_backlog);
+        assert_eq!(3, record.batch_size);
+
+        capture.observe(sample(2_000, 200), || None);
+        capture.observe(sample(3_000, 300), || None);
+        assert_eq!(3, capture.total());
+        assert_eq!(vec![200, 300], capture.records().map(|record| record.latency_ns).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn should_attribute_sched_activity_to_next_outlier() {
+        let mut capture = OutlierCapture::new(Duration::from_nanos(100), 4);
+        let sample = |rx_ns, latency_ns| FrameSample {
+            connection: 1,
+            rx_ns,
+            user_ns: rx_ns + latency_ns,
+            batch_size: 1,
+        };
+        let delta = SchedDelta {
+            involuntary_switches: 2,
+            migrations: 1,
+            ..SchedDelta::default()
+        };
+        assert!(delta.is_disturbed());
+
+        capture.observe_sched(delta);
+        assert!(capture.observe(sample(1_000, 50), || None).is_none());
+        capture.observe_sched(delta);
+        let record = *capture.observe(sample(2_000, 500), || None).unwrap();
+        assert_eq!(4, record.preemptions);
+        assert_eq!(2, record.cpu_migrations);
+
+        let record = *capture.observe(sample(3_000, 500), || None).unwrap();
+        assert_eq!(0, record.preemptions);
+        assert_eq!(0, record.cpu_migrations);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn should_parse_nr_migrations() {
+        let sched = b"bench (1234, #threads: 2)\n\
+            -------------------------------------------------------------------\n\
+            se.exec_start                                :      12345678.123456\n\
+            se.nr_migrations                             :                   42\n\
+            nr_switches                                  :                  100\n";
+        assert_eq!(Some(42), parse_nr_migrations(sched));
+        assert_eq!(None, parse_nr_migrations(b"nr_switches : 100\n"));
+    }
+}
@@ -0,0 +1,208 @@
+//! Binance spot and USD-M futures market data adapter.
+//!
+//! Decodes `bookTicker`, `trade`, `aggTrade` and `depthUpdate` messages, either raw or wrapped in the
+//! combined stream envelope (`{"stream":"<name>","data":{...}}`).
+
+use crate::codec::combined::Envelope;
+use crate::codec::json;
+use crate::model::{Adapter, Bbo, Decimal, Depth, Event, Levels, Side, Trade, millis_to_nanos, parse_u64};
+
+/// Binance market data [`Adapter`].
+#[derive(Debug, Default)]
+pub struct BinanceAdapter {
+    _private: (),
+}
+
+impl BinanceAdapter {
+    pub fn new() -> BinanceAdapter {
+        Self::default()
+    }
+}
+
+impl Adapter for BinanceAdapter {
+    fn decode<'a>(&mut self, payload: &'a [u8], on_event: &mut dyn FnMut(Event<'a>)) -> usize {
+        let data = match Envelope::parse(payload) {
+            Some(envelope) => envelope.data,
+            None => payload,
+        };
+        match decode(data) {
+            Some(event) => {
+                on_event(event);
+                1
+            }
+            None => 0,
+        }
+    }
+}
+
+/// Raw fields of the message, Binance uses the same single letter keys across the event types.
+#[derive(Default)]
+struct Message<'a> {
+    event_type: Option<&'a [u8]>,
+    event_time: Option<&'a [u8]>,
+    transaction_time: Option<&'a [u8]>,
+    symbol: Option<&'a [u8]>,
+    update_id: Option<&'a [u8]>,
+    first_update_id: Option<&'a [u8]>,
+    bid: Option<&'a [u8]>,
+    bid_qty: Option<&'a [u8]>,
+    ask: Option<&'a [u8]>,
+    ask_qty: Option<&'a [u8]>,
+    price: Option<&'a [u8]>,
+    qty: Option<&'a [u8]>,
+    trade_id: Option<&'a [u8]>,
+    buyer_is_maker: Option<&'a [u8]>,
+}
+
+fn decode(data: &[u8]) -> Option<Event<'_>> {
+    let mut msg = Message::default();
+    for (key, value) in json::fields(data) {
+        let field = match key {
+            b"e" => &mut msg.event_type,
+            b"E" => &mut msg.event_time,
+            b"T" => &mut msg.transaction_time,
+            b"s" => &mut msg.symbol,
+            b"u" => &mut msg.update_id,
+            b"U" => &mut msg.first_update_id,
+            b"b" => &mut msg.bid,
+            b"B" => &mut msg.bid_qty,
+            b"a" => &mut msg.ask,
+            b"A" => &mut msg.ask_qty,
+            b"p" => &mut msg.price,
+            b"q" => &mut msg.qty,
+            b"t" => &mut msg.trade_id,
+            b"m" => &mut msg.buyer_is_maker,
+            _ => continue,
+        };
+        *field = Some(value);
+    }
+    let symbol = json::unquote(msg.symbol?)?;
+    match msg.event_type.and_then(json::unquote) {
+        // spot book ticker does not carry the event type
+        None | Some(b"bookTicker") => Some(Event::Bbo(Bbo {
+            symbol,
+            bid_price: Decimal::parse(msg.bid?)?,
+            bid_qty: Decimal::parse(msg.bid_qty?)?,
+            ask_price: Decimal::parse(msg.ask?)?,
+            ask_qty: Decimal::parse(msg.ask_qty?)?,
+            update_id: parse_u64(msg.update_id?)?,
+            exchange_time_ns: millis_to_nanos(msg.transaction_time.or(msg.event_time)),
+        })),
+        Some(event_type @ (b"trade" | b"aggTrade")) => Some(Event::Trade(Trade {
+            symbol,
+            price: Decimal::parse(msg.price?)?,
+            qty: Decimal::parse(msg.qty?)?,
+            // the buyer is the maker when the seller hits the bid
+            side: match msg.buyer_is_maker? {
+                b"true" => Side::Sell,
+                b"false" => Side::Buy,
+                _ => return None,
+            },
+            // aggregate trades carry their id in the `a` field
+            trade_id: if event_type == b"aggTrade" {
+                msg.ask?
+            } else {
+                msg.trade_id?
+            },
+            exchange_time_ns: millis_to_nanos(msg.transaction_time.or(msg.event_time)),
+        })),
+        Some(b"depthUpdate") => {
+            let last_update_id = parse_u64(msg.update_id?)?;
+            Some(Event::Depth(Depth {
+                symbol,
+                bids: Levels::new(msg.bid?),
+                asks: Levels::new(msg.ask?),
+                snapshot: false,
+                first_update_id: msg.first_update_id.and_then(parse_u64).unwrap_or(last_update_id),
+                last_update_id,
+                exchange_time_ns: millis_to_nanos(msg.transaction_time.or(msg.event_time)),
+            }))
+        }
+        Some(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(payload: &[u8]) -> Vec<Event<'_>> {
+        let mut events = Vec::new();
+        let decoded = BinanceAdapter::new().decode(payload, &mut |event| events.push(event));
+        assert_eq!(decoded, events.len());
+        events
+    }
+
+    #[test]
+    fn should_decode_book_ticker() {
+        let spot =
+            br#"{"u":400900217,"s":"BNBUSDT","b":"25.35190000","B":"31.21000000","a":"25.36520000","A":"40.66000000"}"#;
+        let futures = br#"{"stream":"bnbusdt@bookTicker","data":{"e":"bookTicker","u":400900217,"E":1568014460893,"T":1568014460891,"s":"BNBUSDT","b":"25.35190000","B":"31.21000000","a":"25.36520000","A":"40.66000000"}}"#;
+
+        let expected = Bbo {
+            symbol: b"BNBUSDT",
+            bid_price: Decimal::new(2535190000, 8),
+            bid_qty: Decimal::new(3121000000, 8),
+            ask_price: Decimal::new(2536520000, 8),
+            ask_qty: Decimal::new(4066000000, 8),
+            update_id: 400900217,
+            exchange_time_ns: 0,
+        };
+        assert_eq!(vec![Event::Bbo(expected)], decode_all(spot));
+        assert_eq!(
+            vec![Event::Bbo(Bbo {
+                exchange_time_ns: 1568014460891000000,
+                ..expected
+            })],
+            decode_all(futures)
+        );
+    }
+
+    #[test]
+    fn should_decode_trades() {
+        let trade = br#"{"e":"trade","E":1672515782136,"s":"BNBBTC","t":12345,"p":"0.001","q":"100","T":1672515782136,"m":true,"M":true}"#;
+        let agg_trade = br#"{"e":"aggTrade","E":1672515782136,"s":"BNBBTC","a":12345,"p":"0.001","q":"100","f":100,"l":105,"T":1672515782136,"m":false,"M":true}"#;
+
+        let expected = Trade {
+            symbol: b"BNBBTC",
+            price: Decimal::new(1, 3),
+            qty: Decimal::new(100, 0),
+            side: Side::Sell,
+            trade_id: b"12345",
+            exchange_time_ns: 1672515782136000000,
+        };
+        assert_eq!(vec![Event::Trade(expected)], decode_all(trade));
+        assert_eq!(
+            vec![Event::Trade(Trade {
+                side: Side::Buy,
+                ..expected
+            })],
+            decode_all(agg_trade)
+        );
+    }
+
+    #[test]
+    fn should_decode_depth_update() {
+        let msg = br#"{"e":"depthUpdate","E":1672515782136,"s":"BNBBTC","U":157,"u":160,"b":[["0.0024","10"]],"a":[["0.0026","100"],["0.0027","0"]]}"#;
+
+        let events = decode_all(msg);
+        let Event::Depth(depth) = events[0] else {
+            panic!("expected depth");
+        };
+        assert_eq!((false, 157, 160), (depth.snapshot, depth.first_update_id, depth.last_update_id));
+        assert_eq!(vec![(Decimal::new(24, 4), Decimal::new(10, 0))], depth.bids.iter().collect::<Vec<_>>());
+        assert_eq!(
+            vec![
+                (Decimal::new(26, 4), Decimal::new(100, 0)),
+                (Decimal::new(27, 4), Decimal::ZERO)
+            ],
+            depth.asks.iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn should_ignore_other_messages() {
+        assert!(decode_all(br#"{"result":null,"id":1}"#).is_empty());
+        assert!(decode_all(br#"{"e":"kline","E":1672515782136,"s":"BNBBTC"}"#).is_empty());
+    }
+}
@@ -0,0 +1,201 @@
+//! Bybit v5 public market data adapter.
+//!
+//! Decodes `orderbook.{depth}.{symbol}` and `publicTrade.{symbol}` topics. Level 1 order book pushes
+//! are always full snapshots and are decoded as [`Bbo`], deeper order books as [`Depth`].
+
+use crate::codec::json;
+use crate::model::{Adapter, Bbo, Decimal, Depth, Event, Levels, Side, Trade, millis_to_nanos, parse_u64};
+
+const ORDERBOOK: &[u8] = b"orderbook.";
+const ORDERBOOK_L1: &[u8] = b"orderbook.1.";
+const PUBLIC_TRADE: &[u8] = b"publicTrade.";
+
+/// Bybit market data [`Adapter`].
+#[derive(Debug, Default)]
+pub struct BybitAdapter {
+    _private: (),
+}
+
+impl BybitAdapter {
+    pub fn new() -> BybitAdapter {
+        Self::default()
+    }
+}
+
+impl Adapter for BybitAdapter {
+    fn decode<'a>(&mut self, payload: &'a [u8], on_event: &mut dyn FnMut(Event<'a>)) -> usize {
+        let mut topic = None;
+        let mut snapshot = false;
+        let mut ts = None;
+        let mut data = None;
+        for (key, value) in json::fields(payload) {
+            match key {
+                b"topic" => topic = json::unquote(value),
+                b"type" => snapshot = value == br#""snapshot""#,
+                b"ts" => ts = Some(value),
+                b"data" => data = Some(value),
+                _ => {}
+            }
+        }
+        let (Some(topic), Some(data)) = (topic, data) else {
+            return 0;
+        };
+        if topic.starts_with(PUBLIC_TRADE) {
+            let mut decoded = 0;
+            for trade in json::elements(data).map_while(decode_trade) {
+                on_event(Event::Trade(trade));
+                decoded += 1;
+            }
+            decoded
+        } else if topic.starts_with(ORDERBOOK) {
+            match decode_orderbook(data, snapshot, topic.starts_with(ORDERBOOK_L1), millis_to_nanos(ts)) {
+                Some(event) => {
+                    on_event(event);
+                    1
+                }
+                None => 0,
+            }
+        } else {
+            0
+        }
+    }
+}
+
+fn decode_orderbook(data: &[u8], snapshot: bool, top_of_book: bool, exchange_time_ns: u64) -> Option<Event<'_>> {
+    let mut symbol = None;
+    let mut bids = None;
+    let mut asks = None;
+    let mut update_id = None;
+    for (key, value) in json::fields(data) {
+        match key {
+            b"s" => symbol = json::unquote(value),
+            b"b" => bids = Some(Levels::new(value)),
+            b"a" => asks = Some(Levels::new(value)),
+            b"u" => update_id = parse_u64(value),
+            _ => {}
+        }
+    }
+    let (symbol, bids, asks, update_id) = (symbol?, bids?, asks?, update_id?);
+    if top_of_book {
+        // one sided book can not be represented as bbo
+        if let (Some((bid_price, bid_qty)), Some((ask_price, ask_qty))) = (bids.iter().next(), asks.iter().next()) {
+            return Some(Event::Bbo(Bbo {
+                symbol,
+                bid_price,
+                bid_qty,
+                ask_price,
+                ask_qty,
+                update_id,
+                exchange_time_ns,
+            }));
+        }
+    }
+    Some(Event::Depth(Depth {
+        symbol,
+        bids,
+        asks,
+        snapshot,
+        first_update_id: update_id,
+        last_update_id: update_id,
+        exchange_time_ns,
+    }))
+}
+
+fn decode_trade(trade: &[u8]) -> Option<Trade<'_>> {
+    let mut symbol = None;
+    let mut price = None;
+    let mut qty = None;
+    let mut side = None;
+    let mut trade_id = None;
+    let mut time = None;
+    for (key, value) in json::fields(trade) {
+        match key {
+            b"s" => symbol = json::unquote(value),
+            b"p" => price = Decimal::parse(value),
+            b"v" => qty = Decimal::parse(value),
+            b"S" => {
+                side = match value {
+                    br#""Buy""# => Some(Side::Buy),
+                    br#""Sell""# => Some(Side::Sell),
+                    _ => None,
+                }
+            }
+            b"i" => trade_id = json::unquote(value),
+            b"T" => time = Some(value),
+            _ => {}
+        }
+    }
+    Some(Trade {
+        symbol: symbol?,
+        price: price?,
+        qty: qty?,
+        side: side?,
+        trade_id: trade_id?,
+        exchange_time_ns: millis_to_nanos(time),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(payload: &[u8]) -> Vec<Event<'_>> {
+        let mut events = Vec::new();
+        let decoded = BybitAdapter::new().decode(payload, &mut |event| events.push(event));
+        assert_eq!(decoded, events.len());
+        events
+    }
+
+    #[test]
+    fn should_decode_orderbook() {
+        let top = br#"{"topic":"orderbook.1.BTCUSDT","type":"snapshot","ts":1672304484978,"data":{"s":"BTCUSDT","b":[["16493.50","0.006"]],"a":[["16611.00","0.029"]],"u":18521288,"seq":7961638724},"cts":1672304484976}"#;
+        let delta = br#"{"topic":"orderbook.50.BTCUSDT","type":"delta","ts":1672304484978,"data":{"s":"BTCUSDT","b":[["16493.50","0"]],"a":[],"u":18521289,"seq":7961638725}}"#;
+
+        assert_eq!(
+            vec![Event::Bbo(Bbo {
+                symbol: b"BTCUSDT",
+                bid_price: Decimal::new(1649350, 2),
+                bid_qty: Decimal::new(6, 3),
+                ask_price: Decimal::new(1661100, 2),
+                ask_qty: Decimal::new(29, 3),
+                update_id: 18521288,
+                exchange_time_ns: 1672304484978000000,
+            })],
+            decode_all(top)
+        );
+
+        let events = decode_all(delta);
+        let Event::Depth(depth) = events[0] else {
+            panic!("expected depth");
+        };
+        assert_eq!((false, 18521289, 18521289), (depth.snapshot, depth.first_update_id, depth.last_update_id));
+        assert_eq!(vec![(Decimal::new(1649350, 2), Decimal::ZERO)], depth.bids.iter().collect::<Vec<_>>());
+        assert!(depth.asks.is_empty());
+    }
+
+    #[test]
+    fn should_decode_every_trade_of_the_message() {
+        let msg = br#"{"topic":"publicTrade.BTCUSDT","type":"snapshot","ts":1672304486868,"data":[{"T":1672304486865,"s":"BTCUSDT","S":"Buy","v":"0.001","p":"16578.50","L":"PlusTick","i":"20f43950-d8dd-5b31-9112-a178eb6023af","BT":false},{"T":1672304486866,"s":"BTCUSDT","S":"Sell","v":"0.002","p":"16578.00","L":"MinusTick","i":"7b6d7b53-6a6c-5f3f-9b0c-3c6a4f6a1a2b","BT":false}]}"#;
+
+        let events = decode_all(msg);
+        assert_eq!(2, events.len());
+        assert_eq!(
+            Event::Trade(Trade {
+                symbol: b"BTCUSDT",
+                price: Decimal::new(1657850, 2),
+                qty: Decimal::new(1, 3),
+                side: Side::Buy,
+                trade_id: b"20f43950-d8dd-5b31-9112-a178eb6023af",
+                exchange_time_ns: 1672304486865000000,
+            }),
+            events[0]
+        );
+        assert!(matches!(events[1], Event::Trade(Trade { side: Side::Sell, .. })));
+    }
+
+    #[test]
+    fn should_ignore_other_messages() {
+        assert!(decode_all(br#"{"success":true,"ret_msg":"pong","conn_id":"0970e817","op":"ping"}"#).is_empty());
+        assert!(decode_all(br#"{"topic":"tickers.BTCUSDT","type":"snapshot","data":{"symbol":"BTCUSDT"}}"#).is_empty());
+    }
+}
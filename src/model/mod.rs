@@ -0,0 +1,276 @@
+//! Normalized market data events shared across venues (`model` feature).
+//!
+//! Every venue publishes the same kinds of market data (top of the book, trades, order book updates)
+//! in its own schema. The [`Adapter`] of a venue decodes its raw messages into a single [`Event`] type
+//! ([`Bbo`], [`Trade`] or [`Depth`]), so that multi-venue consumers write the handler once. The events
+//! borrow the symbol and the book levels from the payload and keep prices and quantities as exact
+//! [`Decimal`] values, decoding a message does not allocate.
+//!
+//! The layer is optional, the core crate never depends on it and raw users keep decoding the fields
+//! they need with the [`codec`](crate::codec) modules.
+//!
+//! ## Examples
+//! ```
+//! use boomnet::model::binance::BinanceAdapter;
+//! use boomnet::model::{Adapter, Event};
+//!
+//! let mut adapter = BinanceAdapter::new();
+//! let msg = br#"{"u":400900217,"s":"BNBUSDT","b":"25.35190000","B":"31.21000000","a":"25.36520000","A":"40.66000000"}"#;
+//! let decoded = adapter.decode(msg, &mut |event| {
+//!     if let Event::Bbo(bbo) = event {
+//!         assert_eq!(b"BNBUSDT", bbo.symbol);
+//!         assert_eq!(25.3519, bbo.bid_price.to_f64());
+//!     }
+//! });
+//! assert_eq!(1, decoded);
+//! ```
+
+use crate::codec::json;
+use std::fmt::{Display, Formatter};
+
+pub mod binance;
+pub mod bybit;
+
+/// Maximum number of significant digits of a [`Decimal`].
+const MAX_DIGITS: u32 = 18;
+
+/// Exact decimal number (`mantissa * 10^-scale`) as sent by the venue, e.g. `25.35190000` is kept as
+/// mantissa `2535190000` with scale `8`. Values with a different scale are not equal even if they
+/// represent the same number, compare them with [`Decimal::rescale`].
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub struct Decimal {
+    mantissa: i64,
+    scale: u8,
+}
+
+impl Decimal {
+    pub const ZERO: Decimal = Decimal::new(0, 0);
+
+    pub const fn new(mantissa: i64, scale: u8) -> Decimal {
+        Self { mantissa, scale }
+    }
+
+    /// Parse decimal number (optionally quoted), returns `None` if it is malformed or has more than
+    /// 18 significant digits.
+    pub fn parse(value: &[u8]) -> Option<Decimal> {
+        let value = json::unquote(value).unwrap_or(value);
+        let (negative, digits) = match value {
+            [b'-', digits @ ..] => (true, digits),
+            digits => (false, digits),
+        };
+        let mut mantissa = 0i64;
+        let mut scale = None;
+        let mut significant = 0;
+        for (index, byte) in digits.iter().enumerate() {
+            match byte {
+                b'0'..=b'9' => {
+                    if mantissa > 0 || *byte != b'0' {
+                        significant += 1;
+                    }
+                    if significant > MAX_DIGITS {
+                        return None;
+                    }
+                    mantissa = mantissa * 10 + (byte - b'0') as i64;
+                    scale = scale.map(|scale: u8| scale + 1);
+                }
+                b'.' if scale.is_none() && index > 0 && index + 1 < digits.len() => scale = Some(0),
+                _ => return None,
+            }
+        }
+        if digits.is_empty() {
+            return None;
+        }
+        Some(Self {
+            mantissa: if negative { -mantissa } else { mantissa },
+            scale: scale.unwrap_or(0),
+        })
+    }
+
+    #[inline]
+    pub const fn mantissa(&self) -> i64 {
+        self.mantissa
+    }
+
+    /// Number of digits after the decimal point.
+    #[inline]
+    pub const fn scale(&self) -> u8 {
+        self.scale
+    }
+
+    /// Mantissa of the same number with the `scale`, returns `None` if digits would be lost or the
+    /// mantissa overflows.
+    pub fn rescale(&self, scale: u8) -> Option<i64> {
+        if scale >= self.scale {
+            10i64
+                .checked_pow((scale - self.scale) as u32)
+                .and_then(|factor| self.mantissa.checked_mul(factor))
+        } else {
+            let factor = 10i64.checked_pow((self.scale - scale) as u32)?;
+            (self.mantissa % factor == 0).then(|| self.mantissa / factor)
+        }
+    }
+
+    #[inline]
+    pub fn to_f64(&self) -> f64 {
+        self.mantissa as f64 / 10f64.powi(self.scale as i32)
+    }
+}
+
+impl Display for Decimal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.scale == 0 {
+            return write!(f, "{}", self.mantissa);
+        }
+        let factor = 10u64.pow(self.scale as u32);
+        let abs = self.mantissa.unsigned_abs();
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+        write!(f, "{sign}{}.{:0width$}", abs / factor, abs % factor, width = self.scale as usize)
+    }
+}
+
+/// Side of the taker (aggressor) of the trade.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+/// Best bid and offer.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Bbo<'a> {
+    /// Venue symbol, e.g. `BTCUSDT`.
+    pub symbol: &'a [u8],
+    pub bid_price: Decimal,
+    pub bid_qty: Decimal,
+    pub ask_price: Decimal,
+    pub ask_qty: Decimal,
+    /// Venue update id (sequence number) of the book.
+    pub update_id: u64,
+    /// Venue event time in nanoseconds since epoch, zero if not sent by the venue.
+    pub exchange_time_ns: u64,
+}
+
+/// Public trade.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Trade<'a> {
+    /// Venue symbol, e.g. `BTCUSDT`.
+    pub symbol: &'a [u8],
+    pub price: Decimal,
+    pub qty: Decimal,
+    pub side: Side,
+    /// Venue trade id, numeric or not depending on the venue.
+    pub trade_id: &'a [u8],
+    /// Venue trade time in nanoseconds since epoch, zero if not sent by the venue.
+    pub exchange_time_ns: u64,
+}
+
+/// Order book levels of a [`Depth`] event, decoded as they are iterated.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Levels<'a> {
+    // raw json array of `[price, qty]` arrays
+    raw: &'a [u8],
+}
+
+impl<'a> Levels<'a> {
+    pub(crate) const fn new(raw: &'a [u8]) -> Self {
+        Self { raw }
+    }
+
+    /// Iterate over `(price, qty)` of the levels, a zero quantity removes the level.
+    pub fn iter(&self) -> impl Iterator<Item = (Decimal, Decimal)> + 'a {
+        json::elements(self.raw).map_while(|level| {
+            let mut values = json::elements(level);
+            Some((Decimal::parse(values.next()?)?, Decimal::parse(values.next()?)?))
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        json::elements(self.raw).next().is_none()
+    }
+}
+
+/// Order book snapshot or update.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Depth<'a> {
+    /// Venue symbol, e.g. `BTCUSDT`.
+    pub symbol: &'a [u8],
+    pub bids: Levels<'a>,
+    pub asks: Levels<'a>,
+    /// The levels replace the whole book rather than update it.
+    pub snapshot: bool,
+    /// First venue update id covered by the event, equal to `last_update_id` if the venue sends one id.
+    pub first_update_id: u64,
+    pub last_update_id: u64,
+    /// Venue event time in nanoseconds since epoch, zero if not sent by the venue.
+    pub exchange_time_ns: u64,
+}
+
+/// Normalized market data event.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Event<'a> {
+    Bbo(Bbo<'a>),
+    Trade(Trade<'a>),
+    Depth(Depth<'a>),
+}
+
+impl<'a> Event<'a> {
+    /// Venue symbol the event refers to.
+    pub const fn symbol(&self) -> &'a [u8] {
+        match self {
+            Event::Bbo(bbo) => bbo.symbol,
+            Event::Trade(trade) => trade.symbol,
+            Event::Depth(depth) => depth.symbol,
+        }
+    }
+}
+
+/// Decodes the raw messages of a venue into normalized [`Event`]s.
+pub trait Adapter {
+    /// Decode the market data `payload` and pass every event it carries to `on_event`, returns the
+    /// number of events. Messages that are not market data (subscription acks, pongs) or that can not
+    /// be decoded yield no event.
+    fn decode<'a>(&mut self, payload: &'a [u8], on_event: &mut dyn FnMut(Event<'a>)) -> usize;
+}
+
+/// Unsigned integer (optionally quoted).
+#[inline]
+pub(crate) fn parse_u64(value: &[u8]) -> Option<u64> {
+    let value = json::unquote(value).unwrap_or(value);
+    if value.is_empty() {
+        return None;
+    }
+    value.iter().try_fold(0u64, |acc, byte| match byte {
+        b'0'..=b'9' => acc.checked_mul(10)?.checked_add((byte - b'0') as u64),
+        _ => None,
+    })
+}
+
+/// Venue time in milliseconds converted to nanoseconds, zero if missing.
+#[inline]
+pub(crate) fn millis_to_nanos(value: Option<&[u8]>) -> u64 {
+    value
+        .and_then(parse_u64)
+        .map_or(0, |millis| millis.saturating_mul(1_000_000))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_decimal() {
+        assert_eq!(Some(Decimal::new(2535190000, 8)), Decimal::parse(br#""25.35190000""#));
+        assert_eq!(Some(Decimal::new(-15, 1)), Decimal::parse(b"-1.5"));
+        assert_eq!(Some(Decimal::new(42, 0)), Decimal::parse(b"42"));
+        assert_eq!(Some(Decimal::new(1, 20)), Decimal::parse(b"0.00000000000000000001"));
+        for malformed in [&b""[..], b"1.", b".1", b"1.2.3", b"1e5", b"-", b"1234567890123456789"] {
+            assert_eq!(None, Decimal::parse(malformed), "{}", String::from_utf8_lossy(malformed));
+        }
+
+        let price = Decimal::parse(b"25.3500").unwrap();
+        assert_eq!((Some(2535), Some(25350000), None), (price.rescale(2), price.rescale(6), price.rescale(1)));
+        assert_eq!("25.3500", price.to_string());
+        assert_eq!("-0.05", Decimal::new(-5, 2).to_string());
+        assert_eq!(25.35, price.to_f64());
+    }
+}
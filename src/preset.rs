@@ -0,0 +1,329 @@
+//! Configuration presets that bundle coherent connection tuning settings.
+//!
+//! Presets give sane defaults with a single call while still allowing individual knobs to be
+//! overridden via [`Tuning`] builder methods.
+//!
+//! ## Examples
+//!
+//! Apply a preset to the connection.
+//! ```no_run
+//! use boomnet::preset::Preset;
+//! use boomnet::stream::ConnectionInfo;
+//!
+//! let stream = ConnectionInfo::new("stream.binance.com", 9443)
+//!     .with_preset(Preset::LowLatency)
+//!     .into_tcp_stream()
+//!     .unwrap();
+//! ```
+//!
+//! Start from a preset and override individual knobs.
+//! ```no_run
+//! use std::time::Duration;
+//! use boomnet::preset::Preset;
+//! use boomnet::stream::ConnectionInfo;
+//!
+//! let tuning = Preset::LowLatency
+//!     .tuning()
+//!     .with_busy_poll(Duration::from_micros(100))
+//!     .with_recv_buffer_size(1 << 20);
+//!
+//! let stream = ConnectionInfo::new("stream.binance.com", 9443)
+//!     .with_tuning(tuning)
+//!     .into_tcp_stream()
+//!     .unwrap();
+//! ```
+
+#[cfg(target_os = "linux")]
+use crate::syscalls;
+use socket2::Socket;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::time::Duration;
+
+/// Named bundle of tuning settings.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default)]
+pub enum Preset {
+    /// Disables Nagle's algorithm, enables quick ACKs and socket busy polling (best effort, see
+    /// [`Tuning::with_busy_poll`]). Kernel socket buffers are left at their defaults to keep the working
+    /// set small, the websocket writes small batches of queued frames and does not offer compression.
+    LowLatency,
+    /// Allows the kernel to coalesce small writes (Nagle enabled) and uses large socket buffers
+    /// to absorb bursts. No busy polling, the websocket writes all the queued frames at once and
+    /// offers compression.
+    Throughput,
+    /// Matches the behaviour of a connection with no preset applied: Nagle disabled and
+    /// everything else left at the operating system (and websocket) defaults.
+    #[default]
+    Compatible,
+}
+
+impl Preset {
+    /// Returns the [`Tuning`] associated with this preset.
+    pub const fn tuning(self) -> Tuning {
+        match self {
+            Preset::LowLatency => Tuning {
+                preset: self,
+                nodelay: true,
+                quickack: Some(true),
+                busy_poll: Some(Duration::from_micros(50)),
+                recv_buffer_size: None,
+                send_buffer_size: None,
+                batch_limit: Some(16),
+                compression: false,
+            },
+            Preset::Throughput => Tuning {
+                preset: self,
+                nodelay: false,
+                quickack: None,
+                busy_poll: None,
+                recv_buffer_size: Some(4 * 1024 * 1024),
+                send_buffer_size: Some(4 * 1024 * 1024),
+                batch_limit: None,
+                compression: true,
+            },
+            Preset::Compatible => Tuning {
+                preset: self,
+                nodelay: true,
+                quickack: None,
+                busy_poll: None,
+                recv_buffer_size: None,
+                send_buffer_size: None,
+                batch_limit: None,
+                compression: false,
+            },
+        }
+    }
+}
+
+impl Display for Preset {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Preset::LowLatency => write!(f, "low-latency"),
+            Preset::Throughput => write!(f, "throughput"),
+            Preset::Compatible => write!(f, "compatible"),
+        }
+    }
+}
+
+/// Set of tuning knobs applied to the socket before connecting, and to the websocket created on top of
+/// the connection (batching and compression). Usually obtained from [`Preset::tuning`] and then adjusted
+/// using the `with_*` methods.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Tuning {
+    preset: Preset,
+    nodelay: bool,
+    quickack: Option<bool>,
+    busy_poll: Option<Duration>,
+    recv_buffer_size: Option<usize>,
+    send_buffer_size: Option<usize>,
+    batch_limit: Option<usize>,
+    compression: bool,
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Preset::default().tuning()
+    }
+}
+
+impl Tuning {
+    /// Enable or disable `TCP_NODELAY` (disabling Nagle's algorithm when `true`).
+    pub const fn with_nodelay(self, nodelay: bool) -> Self {
+        Self { nodelay, ..self }
+    }
+
+    /// Enable or disable `TCP_QUICKACK` (only on linux).
+    pub const fn with_quickack(self, quickack: bool) -> Self {
+        Self {
+            quickack: Some(quickack),
+            ..self
+        }
+    }
+
+    /// Do not set `TCP_QUICKACK` on the socket.
+    pub const fn without_quickack(self) -> Self {
+        Self { quickack: None, ..self }
+    }
+
+    /// Set `SO_BUSY_POLL` timeout (only on linux). The value is rounded down to microseconds. Values
+    /// above `net.core.busy_read` require `CAP_NET_ADMIN`, the connection is then established without
+    /// busy polling and a warning is logged.
+    pub const fn with_busy_poll(self, busy_poll: Duration) -> Self {
+        Self {
+            busy_poll: Some(busy_poll),
+            ..self
+        }
+    }
+
+    /// Do not set `SO_BUSY_POLL` on the socket.
+    pub const fn without_busy_poll(self) -> Self {
+        Self {
+            busy_poll: None,
+            ..self
+        }
+    }
+
+    /// Set `SO_RCVBUF` size in bytes.
+    pub const fn with_recv_buffer_size(self, size: usize) -> Self {
+        Self {
+            recv_buffer_size: Some(size),
+            ..self
+        }
+    }
+
+    /// Set `SO_SNDBUF` size in bytes.
+    pub const fn with_send_buffer_size(self, size: usize) -> Self {
+        Self {
+            send_buffer_size: Some(size),
+            ..self
+        }
+    }
+
+    /// Write at most `max_frames` of the queued websocket frames per flush, see
+    /// [`Websocket::with_bulk_flush_limit`](crate::ws::Websocket::with_bulk_flush_limit).
+    pub const fn with_batch_limit(self, max_frames: usize) -> Self {
+        Self {
+            batch_limit: Some(max_frames),
+            ..self
+        }
+    }
+
+    /// Write all the queued websocket frames at once.
+    pub const fn without_batch_limit(self) -> Self {
+        Self {
+            batch_limit: None,
+            ..self
+        }
+    }
+
+    /// Offer the permessage-deflate extension when upgrading to a websocket (only with the `deflate`
+    /// feature), see [`Websocket::with_deflate`](crate::ws::Websocket::with_deflate).
+    pub const fn with_compression(self, compression: bool) -> Self {
+        Self { compression, ..self }
+    }
+
+    /// Preset this tuning was derived from.
+    pub const fn preset(&self) -> Preset {
+        self.preset
+    }
+
+    /// Get `TCP_NODELAY` setting.
+    pub const fn nodelay(&self) -> bool {
+        self.nodelay
+    }
+
+    /// Get `TCP_QUICKACK` setting.
+    pub const fn quickack(&self) -> Option<bool> {
+        self.quickack
+    }
+
+    /// Get `SO_BUSY_POLL` setting.
+    pub const fn busy_poll(&self) -> Option<Duration> {
+        self.busy_poll
+    }
+
+    /// Get `SO_RCVBUF` setting.
+    pub const fn recv_buffer_size(&self) -> Option<usize> {
+        self.recv_buffer_size
+    }
+
+    /// Get `SO_SNDBUF` setting.
+    pub const fn send_buffer_size(&self) -> Option<usize> {
+        self.send_buffer_size
+    }
+
+    /// Get the websocket batch limit setting.
+    pub const fn batch_limit(&self) -> Option<usize> {
+        self.batch_limit
+    }
+
+    /// Get the websocket compression setting.
+    pub const fn compression(&self) -> bool {
+        self.compression
+    }
+
+    /// Apply tuning to the `socket`. In [restricted](crate::syscalls::set_restricted) mode `TCP_QUICKACK`
+    /// and `SO_BUSY_POLL` are not applied, `SO_BUSY_POLL` is best effort otherwise.
+    pub fn apply(&self, socket: &Socket) -> io::Result<()> {
+        socket.set_nodelay(self.nodelay)?;
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        // optional syscalls are skipped in restricted mode
+        #[cfg(target_os = "linux")]
+        if !syscalls::is_restricted() {
+            if let Some(quickack) = self.quickack {
+                socket
+                    .set_quickack(quickack)
+                    .map_err(|err| syscalls::blocked("setsockopt(TCP_QUICKACK)", err))?;
+            }
+            if let Some(busy_poll) = self.busy_poll {
+                if let Err(err) = set_busy_poll(socket, busy_poll) {
+                    let err = syscalls::blocked("setsockopt(SO_BUSY_POLL)", err);
+                    // above net.core.busy_read the option needs CAP_NET_ADMIN, the connection works without it
+                    match err.kind() {
+                        io::ErrorKind::PermissionDenied => log::warn!("{err}"),
+                        _ => return Err(err),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_busy_poll(socket: &Socket, busy_poll: Duration) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let micros = busy_poll.as_micros().min(libc::c_int::MAX as u128) as libc::c_int;
+    let rc = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BUSY_POLL,
+            (&micros as *const libc::c_int).cast(),
+            size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_override_preset_knobs() {
+        let tuning = Preset::LowLatency
+            .tuning()
+            .with_nodelay(false)
+            .without_busy_poll()
+            .with_send_buffer_size(1024);
+
+        assert_eq!(Preset::LowLatency, tuning.preset());
+        assert!(!tuning.nodelay());
+        assert_eq!(Some(true), tuning.quickack());
+        assert_eq!(None, tuning.busy_poll());
+        assert_eq!(None, tuning.recv_buffer_size());
+        assert_eq!(Some(1024), tuning.send_buffer_size());
+        assert_eq!(Some(16), tuning.batch_limit());
+        assert!(!tuning.compression());
+
+        let tuning = Preset::Throughput.tuning().with_batch_limit(64).with_compression(false);
+        assert_eq!(None, tuning.quickack());
+        assert_eq!(Some(64), tuning.batch_limit());
+        assert!(!tuning.compression());
+    }
+
+    #[test]
+    fn should_default_to_compatible() {
+        assert_eq!(Preset::Compatible.tuning(), Tuning::default());
+    }
+}
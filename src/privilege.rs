@@ -0,0 +1,258 @@
+//! Capability detection and privileged setup for services that should not run as root.
+//!
+//! Some tuning requires elevated privileges: `SIOCSHWTSTAMP` (driver hardware timestamping) and raising
+//! `SO_BUSY_POLL` need `CAP_NET_ADMIN`, raw and packet sockets need `CAP_NET_RAW`. Instead of running the
+//! whole service as root, grant the binary the capabilities it needs (`setcap cap_net_admin+ep <binary>`),
+//! perform the privileged steps with [`PrivilegedSetup`] at startup and drop all capabilities before any
+//! connection is created.
+//!
+//! Linux capabilities are tracked per thread, so [`PrivilegedSetup::run`] must be called from the main
+//! thread **before** any other thread is spawned, otherwise the other threads keep their capabilities.
+//!
+//! ## Examples
+//! ```no_run
+//! use boomnet::privilege::{Capabilities, Capability, PrivilegedSetup};
+//!
+//! let caps = Capabilities::current().unwrap();
+//! println!("CAP_NET_ADMIN effective: {}", caps.is_effective(Capability::NetAdmin));
+//!
+//! let setup = PrivilegedSetup::new();
+//! #[cfg(feature = "timestamping")]
+//! let setup = setup.with_hwtstamp("eth0");
+//! setup.run().unwrap();
+//!
+//! // capabilities are dropped from here on
+//! assert!(Capabilities::current().unwrap().is_empty());
+//! ```
+
+use crate::syscalls;
+use log::info;
+use std::fmt::{Display, Formatter};
+use std::io;
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// Linux capability relevant to the crate.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Capability {
+    /// `CAP_NET_ADMIN`, required by `SIOCSHWTSTAMP` and to raise `SO_BUSY_POLL`.
+    NetAdmin,
+    /// `CAP_NET_RAW`, required by raw and packet sockets.
+    NetRaw,
+    /// `CAP_SYS_ADMIN`, allows loading eBPF programs on kernels without `CAP_BPF`.
+    SysAdmin,
+    /// `CAP_PERFMON`, required by perf events (e.g. to attach kprobes).
+    PerfMon,
+    /// `CAP_BPF`, required to load eBPF programs and access their maps.
+    Bpf,
+}
+
+impl Capability {
+    const fn bit(self) -> u32 {
+        match self {
+            Capability::NetAdmin => 12,
+            Capability::NetRaw => 13,
+            Capability::SysAdmin => 21,
+            Capability::PerfMon => 38,
+            Capability::Bpf => 39,
+        }
+    }
+
+    const fn setcap_name(self) -> &'static str {
+        match self {
+            Capability::NetAdmin => "cap_net_admin",
+            Capability::NetRaw => "cap_net_raw",
+            Capability::SysAdmin => "cap_sys_admin",
+            Capability::PerfMon => "cap_perfmon",
+            Capability::Bpf => "cap_bpf",
+        }
+    }
+}
+
+impl Display for Capability {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Capability::NetAdmin => write!(f, "CAP_NET_ADMIN"),
+            Capability::NetRaw => write!(f, "CAP_NET_RAW"),
+            Capability::SysAdmin => write!(f, "CAP_SYS_ADMIN"),
+            Capability::PerfMon => write!(f, "CAP_PERFMON"),
+            Capability::Bpf => write!(f, "CAP_BPF"),
+        }
+    }
+}
+
+/// Capability sets of the calling thread.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Capabilities {
+    effective: u64,
+    permitted: u64,
+}
+
+impl Capabilities {
+    /// Read capability sets of the calling thread (`capget`). Not allowed in restricted mode.
+    pub fn current() -> io::Result<Self> {
+        syscalls::ensure_unrestricted("capget")?;
+        let mut header = CapHeader {
+            version: LINUX_CAPABILITY_VERSION_3,
+            pid: 0,
+        };
+        let mut data = [CapData::default(); 2];
+        let rc = unsafe { libc::syscall(libc::SYS_capget, &mut header, data.as_mut_ptr()) };
+        if rc < 0 {
+            return Err(syscalls::blocked("capget", io::Error::last_os_error()));
+        }
+        Ok(Self {
+            effective: data[0].effective as u64 | (data[1].effective as u64) << 32,
+            permitted: data[0].permitted as u64 | (data[1].permitted as u64) << 32,
+        })
+    }
+
+    /// Returns `true` if `capability` is in the effective set, i.e. privileged calls will succeed.
+    pub const fn is_effective(&self, capability: Capability) -> bool {
+        self.effective & (1 << capability.bit()) != 0
+    }
+
+    /// Returns `true` if `capability` is in the permitted set.
+    pub const fn is_permitted(&self, capability: Capability) -> bool {
+        self.permitted & (1 << capability.bit()) != 0
+    }
+
+    /// Returns `true` if the thread holds no capabilities.
+    pub const fn is_empty(&self) -> bool {
+        self.effective == 0 && self.permitted == 0
+    }
+
+    /// Fails with [`io::ErrorKind::PermissionDenied`] describing how to grant `capability` if it is not effective.
+    pub fn require(&self, capability: Capability) -> io::Result<()> {
+        if self.is_effective(capability) {
+            return Ok(());
+        }
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "{capability} is required, grant it to the binary with `setcap {}+ep <binary>` instead of running as root",
+                capability.setcap_name()
+            ),
+        ))
+    }
+}
+
+/// Drop all capabilities (effective, permitted and inheritable) of the calling thread (`capset`).
+/// Not allowed in restricted mode.
+pub fn drop_all() -> io::Result<()> {
+    syscalls::ensure_unrestricted("capset")?;
+    let mut header = CapHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let data = [CapData::default(); 2];
+    let rc = unsafe { libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()) };
+    if rc < 0 {
+        return Err(syscalls::blocked("capset", io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+type Step = Box<dyn FnOnce() -> io::Result<()>>;
+
+/// Privileged startup stage. Checks the required capabilities, runs the privileged steps and then drops
+/// all capabilities, so the rest of the process runs unprivileged.
+#[derive(Default)]
+pub struct PrivilegedSetup {
+    required: Vec<Capability>,
+    steps: Vec<(String, Step)>,
+}
+
+impl PrivilegedSetup {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add privileged `step` that requires `capability`.
+    pub fn with_step<F>(mut self, name: impl Into<String>, capability: Capability, step: F) -> Self
+    where
+        F: FnOnce() -> io::Result<()> + 'static,
+    {
+        if !self.required.contains(&capability) {
+            self.required.push(capability);
+        }
+        self.steps.push((name.into(), Box::new(step)));
+        self
+    }
+
+    /// Enable driver hardware RX timestamping on `net_iface` (`SIOCSHWTSTAMP`).
+    #[cfg(feature = "timestamping")]
+    pub fn with_hwtstamp(self, net_iface: impl Into<String>) -> Self {
+        let net_iface = net_iface.into();
+        let name = format!("hwtstamp({net_iface})");
+        self.with_step(name, Capability::NetAdmin, move || {
+            let socket = socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::DGRAM, None)?;
+            crate::stream::timestamping::configure_hwtstamp(std::os::fd::AsRawFd::as_raw_fd(&socket), &net_iface)
+        })
+    }
+
+    /// Run all steps and drop capabilities. Capabilities are dropped even if a step fails. Must be called
+    /// before any other thread is spawned.
+    pub fn run(self) -> io::Result<()> {
+        let result = self.run_steps();
+        drop_all()?;
+        info!("dropped all capabilities");
+        result
+    }
+
+    fn run_steps(self) -> io::Result<()> {
+        if !self.steps.is_empty() {
+            let capabilities = Capabilities::current()?;
+            for capability in &self.required {
+                capabilities.require(*capability)?;
+            }
+        }
+        for (name, step) in self.steps {
+            step().map_err(|err| io::Error::new(err.kind(), format!("privileged step {name} failed: {err}")))?;
+            info!("privileged step {name} completed");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_describe_missing_capability() {
+        let caps = Capabilities {
+            effective: 1 << Capability::NetRaw.bit(),
+            permitted: 1 << Capability::NetRaw.bit(),
+        };
+        assert!(caps.require(Capability::NetRaw).is_ok());
+        let err = caps.require(Capability::NetAdmin).unwrap_err();
+        assert_eq!(io::ErrorKind::PermissionDenied, err.kind());
+        assert!(err.to_string().contains("setcap cap_net_admin+ep"));
+    }
+
+    #[test]
+    fn should_drop_capabilities_after_setup() {
+        // run in a dedicated thread as capabilities are per thread
+        std::thread::spawn(|| {
+            PrivilegedSetup::new().run().unwrap();
+            assert!(Capabilities::current().unwrap().is_empty());
+        })
+        .join()
+        .unwrap();
+    }
+}
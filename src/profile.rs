@@ -0,0 +1,404 @@
+//! Built-in cycle accounting per pipeline stage (`recv`, `decrypt`, `decode`, `dispatch`).
+//!
+//! A [`Profiler`] is a cheap, clonable (single threaded) handle that aggregates `rdtsc` deltas into a
+//! log2 [`Histogram`] per [`Stage`]. The same handle is shared by the pieces of the pipeline:
+//! - [`ProfiledStream`] measures reads of the wrapped stream. Wrapped around the TCP stream it accounts
+//!   the `recv` stage, wrapped around the TLS stream the `decrypt` stage (time spent in the nested
+//!   profiled reads is subtracted so stages do not overlap).
+//! - [`Websocket::with_profiler`](crate::ws::Websocket::with_profiler) accounts the `decode` stage.
+//! - [`Profiler::measure`] accounts any user code, typically the `dispatch` stage.
+//!
+//! Cycles are read with `rdtsc` on `x86_64` and with a monotonic clock (nanoseconds) elsewhere.
+//!
+//! ## Examples
+//! ```no_run
+//! use boomnet::profile::{IntoProfiledStream, Profiler, Stage};
+//! use boomnet::stream::ConnectionInfo;
+//! use boomnet::stream::tls::IntoTlsStream;
+//! use boomnet::ws::{IntoWebsocket, WebsocketFrame};
+//!
+//! let profiler = Profiler::new();
+//! let mut ws = ConnectionInfo::new("stream.binance.com", 9443)
+//!     .into_tcp_stream().unwrap()
+//!     .into_profiled_stream(Stage::Recv, &profiler)
+//!     .into_tls_stream().unwrap()
+//!     .into_profiled_stream(Stage::Decrypt, &profiler)
+//!     .into_websocket("/ws")
+//!     .with_profiler(&profiler);
+//!
+//! for frame in ws.read_batch().unwrap() {
+//!     if let WebsocketFrame::Text(_, body) = frame.unwrap() {
+//!         profiler.measure(Stage::Dispatch, || println!("{}", String::from_utf8_lossy(body)));
+//!     }
+//! }
+//!
+//! for stage in Stage::ALL {
+//!     let histogram = profiler.histogram(stage);
+//!     println!("{stage:?}: count={} p50={} p99={}", histogram.count(), histogram.percentile(0.5), histogram.percentile(0.99));
+//! }
+//! ```
+
+use crate::service::select::Selectable;
+use crate::stream::{ConnectionInfo, ConnectionInfoProvider, PendingWrites, RxTimestamped, RxTimestamps};
+#[cfg(feature = "mio")]
+use mio::{Interest, Registry, Token, event::Source};
+use std::cell::{Cell, RefCell};
+use std::io;
+use std::io::{IoSlice, Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::rc::Rc;
+
+const BUCKETS: usize = 64;
+
+/// Pipeline stage the cycles are accounted to.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Stage {
+    /// Reading bytes from the socket.
+    Recv,
+    /// Decrypting TLS records (excluding the nested `Recv`).
+    Decrypt,
+    /// Decoding protocol frames.
+    Decode,
+    /// Handling decoded frames in the user code.
+    Dispatch,
+}
+
+impl Stage {
+    /// All stages in the pipeline order.
+    pub const ALL: [Stage; 4] = [Stage::Recv, Stage::Decrypt, Stage::Decode, Stage::Dispatch];
+
+    #[inline]
+    const fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Read current cycle counter.
+#[inline]
+pub fn cycles() -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    {
+        // SAFETY: rdtsc is available on every x86_64 cpu
+        unsafe { core::arch::x86_64::_rdtsc() }
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        use std::sync::OnceLock;
+        use std::time::Instant;
+
+        static EPOCH: OnceLock<Instant> = OnceLock::new();
+        EPOCH.get_or_init(Instant::now).elapsed().as_nanos() as u64
+    }
+}
+
+/// Histogram with power of two buckets, bucket `i` holds values in `[2^(i-1), 2^i)`.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    buckets: [u64; BUCKETS],
+    count: u64,
+    sum: u64,
+    min: u64,
+    max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: [0; BUCKETS],
+            count: 0,
+            sum: 0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+}
+
+impl Histogram {
+    #[inline]
+    pub fn record(&mut self, value: u64) {
+        let bucket = (u64::BITS - value.leading_zeros()) as usize;
+        self.buckets[bucket.min(BUCKETS - 1)] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(value);
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    pub const fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub const fn min(&self) -> u64 {
+        if self.count == 0 { 0 } else { self.min }
+    }
+
+    pub const fn max(&self) -> u64 {
+        self.max
+    }
+
+    pub const fn mean(&self) -> u64 {
+        match self.sum.checked_div(self.count) {
+            Some(mean) => mean,
+            None => 0,
+        }
+    }
+
+    /// Upper bound of the bucket holding the `quantile` (`0.0..=1.0`), capped at the observed maximum.
+    pub fn percentile(&self, quantile: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let rank = ((self.count as f64 * quantile.clamp(0.0, 1.0)).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let upper = if bucket == 0 { 0 } else { u64::MAX >> (64 - bucket) };
+                return upper.min(self.max);
+            }
+        }
+        self.max
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    histograms: RefCell<[Histogram; 4]>,
+    // cycles spent in profiled reads, used to exclude nested stages
+    nested: Cell<u64>,
+}
+
+/// Clonable handle to the per connection stage histograms.
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    state: Rc<State>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Account `cycles` to the `stage`.
+    #[inline]
+    pub fn record(&self, stage: Stage, cycles: u64) {
+        self.state.histograms.borrow_mut()[stage.index()].record(cycles);
+    }
+
+    /// Run `action` and account the cycles it took to the `stage`.
+    #[inline]
+    pub fn measure<T>(&self, stage: Stage, action: impl FnOnce() -> T) -> T {
+        let _span = self.span(stage);
+        action()
+    }
+
+    /// Start a [`Span`] that accounts the cycles until it is dropped to the `stage`.
+    #[inline]
+    pub fn span(&self, stage: Stage) -> Span<'_> {
+        Span {
+            profiler: self,
+            stage,
+            start: cycles(),
+        }
+    }
+
+    /// Snapshot of the `stage` histogram.
+    pub fn histogram(&self, stage: Stage) -> Histogram {
+        self.state.histograms.borrow()[stage.index()].clone()
+    }
+
+    /// Clear all histograms.
+    pub fn reset(&self) {
+        self.state.histograms.borrow_mut().iter_mut().for_each(Histogram::reset);
+    }
+}
+
+/// Accounts the cycles from [`Profiler::span`] until it is dropped to the stage.
+#[derive(Debug)]
+pub struct Span<'a> {
+    profiler: &'a Profiler,
+    stage: Stage,
+    start: u64,
+}
+
+impl Drop for Span<'_> {
+    #[inline]
+    fn drop(&mut self) {
+        self.profiler.record(self.stage, cycles().wrapping_sub(self.start));
+    }
+}
+
+/// Stream that accounts the cycles spent in every non empty read to a [`Stage`].
+#[derive(Debug)]
+pub struct ProfiledStream<S> {
+    inner: S,
+    stage: Stage,
+    profiler: Profiler,
+}
+
+impl<S> ProfiledStream<S> {
+    pub fn new(inner: S, stage: Stage, profiler: &Profiler) -> ProfiledStream<S> {
+        Self {
+            inner,
+            stage,
+            profiler: profiler.clone(),
+        }
+    }
+}
+
+impl<S: Read> Read for ProfiledStream<S> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let nested = &self.profiler.state.nested;
+        let nested_before = nested.get();
+        let start = cycles();
+        let read = self.inner.read(buf)?;
+        let elapsed = cycles().wrapping_sub(start);
+        let inner = nested.get().wrapping_sub(nested_before);
+        nested.set(nested_before.wrapping_add(elapsed));
+        if read > 0 {
+            self.profiler.record(self.stage, elapsed.saturating_sub(inner));
+        }
+        Ok(read)
+    }
+}
+
+impl<S: Write> Write for ProfiledStream<S> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    #[inline]
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.inner.write_vectored(bufs)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: PendingWrites> PendingWrites for ProfiledStream<S> {
+    fn has_pending_writes(&self) -> bool {
+        self.inner.has_pending_writes()
+    }
+
+    fn drive_writes(&mut self) -> io::Result<bool> {
+        self.inner.drive_writes()
+    }
+}
+
+impl<S: ConnectionInfoProvider> ConnectionInfoProvider for ProfiledStream<S> {
+    fn connection_info(&self) -> &ConnectionInfo {
+        self.inner.connection_info()
+    }
+}
+
+impl<S: RxTimestamped> RxTimestamped for ProfiledStream<S> {
+    fn last_rx_timestamps(&self) -> Option<RxTimestamps> {
+        self.inner.last_rx_timestamps()
+    }
+
+    fn take_last_rx_timestamps(&mut self) -> Option<RxTimestamps> {
+        self.inner.take_last_rx_timestamps()
+    }
+}
+
+impl<S: AsRawFd> AsRawFd for ProfiledStream<S> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+impl<S: Selectable> Selectable for ProfiledStream<S> {
+    fn connected(&mut self) -> io::Result<bool> {
+        self.inner.connected()
+    }
+
+    fn make_writable(&mut self) -> io::Result<()> {
+        self.inner.make_writable()
+    }
+
+    fn make_readable(&mut self) -> io::Result<()> {
+        self.inner.make_readable()
+    }
+
+    fn take_read_activity(&mut self) -> bool {
+        self.inner.take_read_activity()
+    }
+
+    fn take_bytes_read(&mut self) -> u64 {
+        self.inner.take_bytes_read()
+    }
+
+    fn take_bytes_written(&mut self) -> u64 {
+        self.inner.take_bytes_written()
+    }
+
+    fn set_read_paused(&mut self, paused: bool) {
+        self.inner.set_read_paused(paused)
+    }
+}
+
+#[cfg(feature = "mio")]
+impl<S: Source> Source for ProfiledStream<S> {
+    fn register(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+        registry.register(&mut self.inner, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+        registry.reregister(&mut self.inner, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        registry.deregister(&mut self.inner)
+    }
+}
+
+/// Trait to convert any stream into [`ProfiledStream`].
+pub trait IntoProfiledStream {
+    fn into_profiled_stream(self, stage: Stage, profiler: &Profiler) -> ProfiledStream<Self>
+    where
+        Self: Sized,
+    {
+        ProfiledStream::new(self, stage, profiler)
+    }
+}
+
+impl<T: Read> IntoProfiledStream for T {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_compute_percentiles() {
+        let mut histogram = Histogram::default();
+        (1..=100).for_each(|value| histogram.record(value));
+        assert_eq!(100, histogram.count());
+        assert_eq!(1, histogram.min());
+        assert_eq!(100, histogram.max());
+        assert_eq!(50, histogram.mean());
+        assert_eq!(63, histogram.percentile(0.5));
+        assert_eq!(100, histogram.percentile(0.99));
+    }
+
+    #[test]
+    fn should_exclude_nested_stage() {
+        let profiler = Profiler::new();
+        let mut stream = (&b"foo"[..])
+            .into_profiled_stream(Stage::Recv, &profiler)
+            .into_profiled_stream(Stage::Decrypt, &profiler);
+        let mut buf = [0u8; 8];
+        assert_eq!(3, stream.read(&mut buf).unwrap());
+        assert_eq!(1, profiler.histogram(Stage::Recv).count());
+        assert_eq!(1, profiler.histogram(Stage::Decrypt).count());
+        assert_eq!(0, profiler.histogram(Stage::Decode).count());
+    }
+}
@@ -0,0 +1,443 @@
+//! Load based placement of streams across the connections of a multi-connection subscription.
+//!
+//! When the same venue is consumed over several connections (endpoints registered with the same
+//! `IOService`) the stream message rates are rarely even, a single hot symbol can make one connection
+//! decode several times more than the others. [`StreamBalancer`] measures the message rate of every
+//! stream and periodically moves streams from the most loaded connection onto the least loaded one
+//! (picking the stream that evens the two out best) until the connections are within the configured
+//! tolerance.
+//!
+//! Every move is gapless: the stream is first subscribed on the target connection while the source keeps
+//! delivering it. Once the first message arrives on the target the source is unsubscribed and its
+//! remaining messages for that stream are reported as stale by [`on_message`](StreamBalancer::on_message).
+//! Messages received on both connections around the takeover should be deduplicated by the protocol
+//! sequence numbers. A move the target does not confirm within the rebalance interval is rolled back.
+//!
+//! ## Examples
+//! ```no_run
+//! use std::time::Duration;
+//! use boomnet::service::balance::{Rebalance, StreamBalancer};
+//! use boomnet::service::Handle;
+//!
+//! fn run(connections: &[Handle], streams: &[&str]) -> std::io::Result<()> {
+//!     let mut balancer = StreamBalancer::new()
+//!         .with_interval(Duration::from_secs(10))
+//!         .with_tolerance(1.25);
+//!     connections.iter().for_each(|connection| balancer.add_connection(*connection));
+//!     for stream in streams {
+//!         // initial placement on the least loaded connection
+//!         let _connection = balancer.place(*stream);
+//!     }
+//!
+//!     loop {
+//!         // io_service.poll(|ws, endpoint| { ... for each message:
+//!         //     if balancer.on_message(endpoint.handle, stream) { dispatch(message) }
+//!         // })
+//!         balancer.poll(|action| {
+//!             match action {
+//!                 // io_service.dispatch(connection, |ws, endpoint| subscribe and sync the endpoint `SubscriptionCache`)
+//!                 Rebalance::Subscribe { connection, stream } => println!("{connection:?} +{stream}"),
+//!                 Rebalance::Unsubscribe { connection, stream } => println!("{connection:?} -{stream}"),
+//!             }
+//!             Ok(())
+//!         })?;
+//!     }
+//! }
+//! ```
+
+use crate::service::Handle;
+use crate::service::time::{SystemTimeClockSource, TimeSource};
+use log::{info, warn};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io;
+use std::time::Duration;
+
+/// Subscription change to be applied on a connection.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Rebalance {
+    /// Subscribe the `stream` on the `connection`.
+    Subscribe { connection: Handle, stream: String },
+    /// Unsubscribe the `stream` from the `connection`.
+    Unsubscribe { connection: Handle, stream: String },
+}
+
+#[derive(Debug)]
+struct StreamLoad {
+    connection: Handle,
+    messages: u64,
+    rate: f64,
+    // target connection and start time of the move in progress
+    moving: Option<(Handle, u64)>,
+}
+
+/// Measures per stream message rates and plans the moves that keep the connections evenly loaded.
+#[derive(Debug)]
+pub struct StreamBalancer<TS = SystemTimeClockSource> {
+    connections: BTreeSet<Handle>,
+    streams: BTreeMap<String, StreamLoad>,
+    actions: VecDeque<Rebalance>,
+    interval_ns: u64,
+    tolerance: f64,
+    max_moves: usize,
+    window_start_ns: u64,
+    moves: u64,
+    time_source: TS,
+}
+
+impl StreamBalancer {
+    /// Create balancer that rebalances every minute with `1.5` tolerance and one move per round.
+    pub fn new() -> Self {
+        Self::new_with_time_source(SystemTimeClockSource)
+    }
+}
+
+impl Default for StreamBalancer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<TS: TimeSource> StreamBalancer<TS> {
+    /// Create balancer using custom [`TimeSource`].
+    pub fn new_with_time_source(time_source: TS) -> Self {
+        Self {
+            connections: BTreeSet::new(),
+            streams: BTreeMap::new(),
+            actions: VecDeque::new(),
+            interval_ns: Duration::from_secs(60).as_nanos() as u64,
+            tolerance: 1.5,
+            max_moves: 1,
+            window_start_ns: time_source.current_time_nanos(),
+            moves: 0,
+            time_source,
+        }
+    }
+
+    /// Interval over which the message rates are measured, the moves are planned at the end of it.
+    pub fn with_interval(self, interval: Duration) -> Self {
+        Self {
+            interval_ns: interval.as_nanos() as u64,
+            ..self
+        }
+    }
+
+    /// Ratio of the most to the least loaded connection that is still considered balanced.
+    pub fn with_tolerance(self, tolerance: f64) -> Self {
+        Self {
+            tolerance: tolerance.max(1.0),
+            ..self
+        }
+    }
+
+    /// Maximum number of streams moved per rebalance round.
+    pub fn with_max_moves(self, max_moves: usize) -> Self {
+        Self { max_moves, ..self }
+    }
+
+    /// Add connection the streams can be placed on.
+    pub fn add_connection(&mut self, connection: Handle) {
+        self.connections.insert(connection);
+    }
+
+    /// Remove connection, any move from or to it is abandoned. Returns the streams that lived on it
+    /// so they can be [placed](Self::place) again.
+    pub fn remove_connection(&mut self, connection: Handle) -> Vec<String> {
+        self.connections.remove(&connection);
+        self.actions.retain(|action| match action {
+            Rebalance::Subscribe { connection: on, .. } | Rebalance::Unsubscribe { connection: on, .. } => {
+                *on != connection
+            }
+        });
+        let mut orphaned = vec![];
+        self.streams.retain(|stream, load| {
+            if load.connection == connection {
+                match load.moving.take() {
+                    // the target already subscribes, let it take over straight away
+                    Some((to, _)) => load.connection = to,
+                    None => {
+                        orphaned.push(stream.clone());
+                        return false;
+                    }
+                }
+            } else if load.moving.is_some_and(|(to, _)| to == connection) {
+                load.moving = None;
+            }
+            true
+        });
+        orphaned
+    }
+
+    /// Record `stream` as subscribed on the `connection`.
+    pub fn assign(&mut self, stream: impl Into<String>, connection: Handle) {
+        self.streams.insert(
+            stream.into(),
+            StreamLoad {
+                connection,
+                messages: 0,
+                rate: 0.0,
+                moving: None,
+            },
+        );
+    }
+
+    /// Assign `stream` to the least loaded connection (by measured rate, then by number of streams)
+    /// and return it, `None` if there are no connections.
+    pub fn place(&mut self, stream: impl Into<String>) -> Option<Handle> {
+        let loads = self.loads();
+        let connection = self
+            .connections
+            .iter()
+            .map(|connection| (*connection, loads.get(connection).copied().unwrap_or_default()))
+            .min_by(|(_, a), (_, b)| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)))
+            .map(|(connection, _)| connection)?;
+        self.assign(stream, connection);
+        Some(connection)
+    }
+
+    /// Remove `stream` from the balancer, the caller unsubscribes it from its connection(s).
+    pub fn remove(&mut self, stream: &str) {
+        self.streams.remove(stream);
+    }
+
+    /// Connection currently delivering the `stream`.
+    pub fn connection(&self, stream: &str) -> Option<Handle> {
+        self.streams.get(stream).map(|load| load.connection)
+    }
+
+    /// Measured message rate (per second) of the `stream` over the last interval.
+    pub fn rate(&self, stream: &str) -> Option<f64> {
+        self.streams.get(stream).map(|load| load.rate)
+    }
+
+    /// Number of moves completed so far.
+    pub const fn moves(&self) -> u64 {
+        self.moves
+    }
+
+    /// Must be called for every message of the `stream` received on the `connection`. Returns `false`
+    /// if the message is stale, i.e. it has been received on the previous connection of the stream
+    /// after the new one has taken over, and should not be dispatched.
+    #[inline]
+    pub fn on_message(&mut self, connection: Handle, stream: &str) -> bool {
+        let Some(load) = self.streams.get_mut(stream) else {
+            return true;
+        };
+        if load.connection == connection {
+            load.messages += 1;
+            return true;
+        }
+        match load.moving {
+            Some((to, _)) if to == connection => {
+                self.actions.push_back(Rebalance::Unsubscribe {
+                    connection: load.connection,
+                    stream: stream.to_owned(),
+                });
+                load.connection = to;
+                load.moving = None;
+                load.messages += 1;
+                self.moves += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Roll back moves the target has not confirmed in time and, once the interval has elapsed,
+    /// update the rates and plan the next moves. Every resulting action is handed to `apply` and
+    /// the number of actions is returned.
+    pub fn poll<F>(&mut self, mut apply: F) -> io::Result<usize>
+    where
+        F: FnMut(Rebalance) -> io::Result<()>,
+    {
+        let now = self.time_source.current_time_nanos();
+        for (stream, load) in self.streams.iter_mut() {
+            if let Some((to, started_ns)) = load.moving {
+                if now.saturating_sub(started_ns) > self.interval_ns {
+                    warn!("{stream} has not been delivered by {to:?} in time, cancelling the move");
+                    load.moving = None;
+                    self.actions.push_back(Rebalance::Unsubscribe {
+                        connection: to,
+                        stream: stream.clone(),
+                    });
+                }
+            }
+        }
+
+        let elapsed_ns = now.saturating_sub(self.window_start_ns);
+        if elapsed_ns >= self.interval_ns {
+            let elapsed = Duration::from_nanos(elapsed_ns).as_secs_f64().max(f64::EPSILON);
+            for load in self.streams.values_mut() {
+                load.rate = load.messages as f64 / elapsed;
+                load.messages = 0;
+            }
+            self.window_start_ns = now;
+            // one round of moves at a time
+            if self.streams.values().all(|load| load.moving.is_none()) {
+                self.plan(now);
+            }
+        }
+
+        let mut applied = 0;
+        while let Some(action) = self.actions.pop_front() {
+            apply(action)?;
+            applied += 1;
+        }
+        Ok(applied)
+    }
+
+    fn plan(&mut self, now: u64) {
+        let mut loads = self
+            .connections
+            .iter()
+            .map(|connection| (*connection, 0.0))
+            .collect::<BTreeMap<_, f64>>();
+        for load in self.streams.values() {
+            if let Some(rate) = loads.get_mut(&load.connection) {
+                *rate += load.rate;
+            }
+        }
+        for _ in 0..self.max_moves {
+            let Some((hot, hot_load)) = loads.iter().max_by(|a, b| a.1.total_cmp(b.1)).map(|(c, l)| (*c, *l)) else {
+                return;
+            };
+            let Some((cool, cool_load)) = loads.iter().min_by(|a, b| a.1.total_cmp(b.1)).map(|(c, l)| (*c, *l)) else {
+                return;
+            };
+            if hot == cool || hot_load <= cool_load * self.tolerance {
+                return;
+            }
+            // stream closest to half of the gap, it must leave the target below the current maximum
+            let gap = hot_load - cool_load;
+            let Some((stream, load)) = self
+                .streams
+                .iter_mut()
+                .filter(|(_, load)| load.connection == hot && load.rate > 0.0 && load.rate < gap)
+                .min_by(|a, b| (gap / 2.0 - a.1.rate).abs().total_cmp(&(gap / 2.0 - b.1.rate).abs()))
+            else {
+                return;
+            };
+            info!("moving {stream} ({:.1} msg/s) from {hot:?} to {cool:?}", load.rate);
+            load.moving = Some((cool, now));
+            loads.insert(hot, hot_load - load.rate);
+            loads.insert(cool, cool_load + load.rate);
+            self.actions.push_back(Rebalance::Subscribe {
+                connection: cool,
+                stream: stream.clone(),
+            });
+        }
+    }
+
+    fn loads(&self) -> BTreeMap<Handle, (f64, usize)> {
+        let mut loads = BTreeMap::<Handle, (f64, usize)>::new();
+        for load in self.streams.values() {
+            let entry = loads.entry(load.connection).or_default();
+            entry.0 += load.rate;
+            entry.1 += 1;
+        }
+        loads
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    const SECOND: u64 = 1_000_000_000;
+
+    #[derive(Clone, Default)]
+    struct ManualClock(Rc<Cell<u64>>);
+
+    impl TimeSource for ManualClock {
+        fn current_time_nanos(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    fn collect(actions: &mut Vec<Rebalance>) -> impl FnMut(Rebalance) -> io::Result<()> + '_ {
+        move |action| {
+            actions.push(action);
+            Ok(())
+        }
+    }
+
+    fn publish(balancer: &mut StreamBalancer<ManualClock>, connection: Handle, stream: &str, count: usize) {
+        (0..count).for_each(|_| assert!(balancer.on_message(connection, stream)));
+    }
+
+    #[test]
+    fn should_move_hot_stream_with_overlap() {
+        let clock = ManualClock::default();
+        let mut balancer = StreamBalancer::new_with_time_source(clock.clone()).with_interval(Duration::from_secs(1));
+        let (first, second) = (Handle(0), Handle(1));
+        balancer.add_connection(first);
+        balancer.add_connection(second);
+        balancer.assign("btcusdt", first);
+        balancer.assign("ethusdt", first);
+        balancer.assign("solusdt", first);
+        assert_eq!(Some(second), balancer.place("xrpusdt"));
+
+        publish(&mut balancer, first, "btcusdt", 100);
+        publish(&mut balancer, first, "ethusdt", 60);
+        publish(&mut balancer, first, "solusdt", 30);
+        publish(&mut balancer, second, "xrpusdt", 10);
+        clock.0.set(SECOND);
+        let mut actions = vec![];
+        assert_eq!(1, balancer.poll(collect(&mut actions)).unwrap());
+        // 190 vs 10 msg/s, the hottest stream evens the connections out best
+        let subscribe = Rebalance::Subscribe {
+            connection: second,
+            stream: "btcusdt".to_owned(),
+        };
+        assert_eq!(vec![subscribe], actions);
+        assert_eq!(Some(100.0), balancer.rate("btcusdt"));
+
+        // source keeps delivering until the target takes over
+        assert!(balancer.on_message(first, "btcusdt"));
+        assert!(balancer.on_message(second, "btcusdt"));
+        assert!(!balancer.on_message(first, "btcusdt"));
+        assert_eq!(Some(second), balancer.connection("btcusdt"));
+        assert_eq!(1, balancer.moves());
+
+        let mut actions = vec![];
+        assert_eq!(1, balancer.poll(collect(&mut actions)).unwrap());
+        let unsubscribe = Rebalance::Unsubscribe {
+            connection: first,
+            stream: "btcusdt".to_owned(),
+        };
+        assert_eq!(vec![unsubscribe], actions);
+    }
+
+    #[test]
+    fn should_cancel_move_not_confirmed_in_time() {
+        let clock = ManualClock::default();
+        let mut balancer = StreamBalancer::new_with_time_source(clock.clone()).with_interval(Duration::from_secs(1));
+        let (first, second) = (Handle(0), Handle(1));
+        balancer.add_connection(first);
+        balancer.add_connection(second);
+        balancer.assign("btcusdt", first);
+        balancer.assign("ethusdt", first);
+        balancer.assign("xrpusdt", second);
+
+        publish(&mut balancer, first, "btcusdt", 100);
+        publish(&mut balancer, first, "ethusdt", 60);
+        publish(&mut balancer, second, "xrpusdt", 20);
+        clock.0.set(SECOND);
+        assert_eq!(1, balancer.poll(|_| Ok(())).unwrap());
+
+        // the target never delivers, the move is rolled back (no traffic, so nothing else is planned)
+        clock.0.set(2 * SECOND + 1);
+        let mut actions = vec![];
+        assert_eq!(1, balancer.poll(collect(&mut actions)).unwrap());
+        let unsubscribe = Rebalance::Unsubscribe {
+            connection: second,
+            stream: "ethusdt".to_owned(),
+        };
+        assert_eq!(vec![unsubscribe], actions);
+        assert_eq!(Some(first), balancer.connection("ethusdt"));
+        assert!(balancer.on_message(first, "ethusdt"));
+        assert!(!balancer.on_message(second, "ethusdt"));
+        assert_eq!(0, balancer.moves());
+    }
+}
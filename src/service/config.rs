@@ -0,0 +1,233 @@
+//! Read-mostly configuration shared across endpoints and updated at runtime.
+//!
+//! [`ConfigRegistry`] holds the current configuration (rate limits, symbol universe, feature toggles)
+//! and can be updated from any thread, e.g. an admin or control plane thread. Every endpoint keeps its
+//! own [`ConfigHandle`] and calls [`ConfigHandle::refresh`] at the batch boundary (before reading the
+//! next batch), so that a configuration never changes in the middle of a batch. The refresh is a
+//! single atomic load while the configuration is unchanged, the lock protecting the configuration is
+//! only taken once per update to pick up the new one.
+//!
+//! ## Examples
+//! ```
+//! use boomnet::service::config::ConfigRegistry;
+//!
+//! #[derive(Debug, Clone)]
+//! struct Limits {
+//!     max_orders_per_second: u32,
+//!     trading_enabled: bool,
+//! }
+//!
+//! let registry = ConfigRegistry::new(Limits {
+//!     max_orders_per_second: 10,
+//!     trading_enabled: true,
+//! });
+//! // owned by the endpoint
+//! let mut config = registry.handle();
+//!
+//! // from the control thread
+//! let control = registry.clone();
+//! std::thread::spawn(move || {
+//!     control.update(|limits| Limits {
+//!         trading_enabled: false,
+//!         ..limits.clone()
+//!     })
+//! })
+//! .join()
+//! .unwrap();
+//!
+//! // at the next batch boundary
+//! assert!(config.refresh());
+//! assert!(!config.trading_enabled);
+//! ```
+
+use std::fmt::{Debug, Formatter};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+struct Shared<T> {
+    version: AtomicU64,
+    current: Mutex<Arc<T>>,
+}
+
+impl<T> Shared<T> {
+    #[inline]
+    fn current(&self) -> MutexGuard<'_, Arc<T>> {
+        // the configuration is replaced as a whole, a panicking writer can not leave it half updated
+        self.current.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Current configuration shared by the endpoints, cheap to clone and to send to other threads.
+pub struct ConfigRegistry<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Clone for ConfigRegistry<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T: Debug> Debug for ConfigRegistry<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConfigRegistry")
+            .field("version", &self.version())
+            .field("current", &self.load())
+            .finish()
+    }
+}
+
+impl<T> ConfigRegistry<T> {
+    /// Create registry holding the `initial` configuration (version `0`).
+    pub fn new(initial: T) -> ConfigRegistry<T> {
+        Self {
+            shared: Arc::new(Shared {
+                version: AtomicU64::new(0),
+                current: Mutex::new(Arc::new(initial)),
+            }),
+        }
+    }
+
+    /// Replace the configuration, returns its version. The endpoints pick it up at their next refresh.
+    pub fn publish(&self, config: T) -> u64 {
+        self.replace(|_| config)
+    }
+
+    /// Derive the new configuration from the current one, concurrent updates are applied one after
+    /// another. Returns the version of the new configuration.
+    pub fn update<F>(&self, update: F) -> u64
+    where
+        F: FnOnce(&T) -> T,
+    {
+        self.replace(update)
+    }
+
+    #[inline]
+    fn replace<F>(&self, update: F) -> u64
+    where
+        F: FnOnce(&T) -> T,
+    {
+        let mut current = self.shared.current();
+        *current = Arc::new(update(&current));
+        // the version is bumped while holding the lock, so it never runs ahead of the configuration
+        self.shared.version.fetch_add(1, Ordering::Release) + 1
+    }
+
+    /// Current configuration.
+    pub fn load(&self) -> Arc<T> {
+        self.shared.current().clone()
+    }
+
+    /// Version of the current configuration, incremented by every update.
+    pub fn version(&self) -> u64 {
+        self.shared.version.load(Ordering::Acquire)
+    }
+
+    /// Create handle holding the current configuration, to be owned by the endpoint.
+    pub fn handle(&self) -> ConfigHandle<T> {
+        let current = self.shared.current();
+        ConfigHandle {
+            version: self.shared.version.load(Ordering::Acquire),
+            config: current.clone(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+/// Configuration snapshot held by a single endpoint, dereferences to the configuration.
+pub struct ConfigHandle<T> {
+    shared: Arc<Shared<T>>,
+    config: Arc<T>,
+    version: u64,
+}
+
+impl<T: Debug> Debug for ConfigHandle<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConfigHandle")
+            .field("version", &self.version)
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl<T> ConfigHandle<T> {
+    /// Pick up the latest configuration, returns `true` if it has changed since the previous refresh.
+    /// Intended to be called at the batch boundary.
+    #[inline]
+    pub fn refresh(&mut self) -> bool {
+        if self.shared.version.load(Ordering::Acquire) == self.version {
+            return false;
+        }
+        self.reload();
+        true
+    }
+
+    #[cold]
+    fn reload(&mut self) {
+        let current = self.shared.current();
+        self.version = self.shared.version.load(Ordering::Acquire);
+        self.config = current.clone();
+    }
+
+    /// Version of the configuration held by the handle.
+    #[inline]
+    pub const fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Configuration held by the handle, shared with the other handles of the same version.
+    #[inline]
+    pub fn get(&self) -> &Arc<T> {
+        &self.config
+    }
+}
+
+impl<T> Clone for ConfigHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            config: self.config.clone(),
+            version: self.version,
+        }
+    }
+}
+
+impl<T> Deref for ConfigHandle<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_pick_up_config_on_refresh() {
+        let registry = ConfigRegistry::new(vec!["BTCUSDT"]);
+        let mut first = registry.handle();
+        let mut second = registry.handle();
+        assert!(!first.refresh());
+
+        assert_eq!(1, registry.publish(vec!["BTCUSDT", "ETHUSDT"]));
+        // the snapshot does not change until refreshed
+        assert_eq!(vec!["BTCUSDT"], *first);
+        assert!(first.refresh());
+        assert!(!first.refresh());
+        assert_eq!((1, 2), (first.version(), first.len()));
+
+        let writer = registry.clone();
+        std::thread::spawn(move || writer.update(|symbols| symbols[1..].to_vec()))
+            .join()
+            .unwrap();
+        assert!(second.refresh());
+        assert_eq!((2, vec!["ETHUSDT"]), (second.version(), second.to_vec()));
+        assert!(Arc::ptr_eq(second.get(), &registry.load()));
+    }
+}
@@ -35,7 +35,7 @@
 //! ```
 
 use core_affinity::CoreId;
-use log::info;
+use log::{info, warn};
 use smallstr::SmallString;
 use smallvec::SmallVec;
 use std::fmt::{Display, Formatter};
@@ -46,6 +46,9 @@ use std::sync::mpsc::TryRecvError;
 use std::thread::JoinHandle;
 use std::{io, thread};
 
+pub mod watch;
+mod wire;
+
 const MAX_ADDRS_PER_QUERY: usize = 32;
 const MAX_HOSTNAME_LEN_BEFORE_SPILL: usize = 64;
 
@@ -299,8 +302,16 @@ impl DnsWorker {
         let builder = thread::Builder::new().name("dns-worker".to_owned());
         builder.spawn(move || {
             if let Some(core_id) = core_id {
-                core_affinity::set_for_current(core_id);
-                info!("successfully pinned current thread to core {}", core_id.id);
+                if crate::syscalls::is_restricted() {
+                    info!("restricted mode: dns worker will not be pinned to core {}", core_id.id);
+                } else if core_affinity::set_for_current(core_id) {
+                    info!("successfully pinned current thread to core {}", core_id.id);
+                } else {
+                    warn!(
+                        "unable to pin dns worker to core {}: sched_setaffinity was denied or is blocked by seccomp",
+                        core_id.id
+                    );
+                }
             }
             let mut worker = Self { requests };
             loop {
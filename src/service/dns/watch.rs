@@ -0,0 +1,291 @@
+//! Background re-resolution of watched hosts respecting the record TTL.
+//!
+//! [`DnsWatcher`] re-resolves every watched host on a background thread once its DNS records expire
+//! and emits [`AddressSetChanged`] whenever the resolved address set differs from the previous one.
+//! This lets long-lived services notice that a venue has shifted its gateways and rotate connections
+//! proactively instead of discovering it on the next failure.
+//!
+//! The TTL is obtained by querying the first nameserver from `/etc/resolv.conf` directly. If that is
+//! not possible (no nameserver, truncated response, host only present in `/etc/hosts`, etc.) the system
+//! resolver is used and the host is refreshed every `default_refresh` interval.
+//!
+//! ## Examples
+//! ```no_run
+//! use std::time::Duration;
+//! use boomnet::service::dns::watch::{DnsWatcher, DnsWatcherConfig};
+//!
+//! let watcher = DnsWatcher::new_with_config(DnsWatcherConfig::new().with_min_refresh(Duration::from_secs(5))).unwrap();
+//! watcher.watch("fstream.binance.com", 443).unwrap();
+//!
+//! loop {
+//!     while let Some(event) = watcher.poll() {
+//!         println!("{event}");
+//!         // rotate connections still using addresses not present in `event.current`
+//!     }
+//!     // ... poll io service
+//! }
+//! ```
+
+use crate::service::dns::wire;
+use log::{debug, info};
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+const DEFAULT_MIN_REFRESH: Duration = Duration::from_secs(1);
+const DEFAULT_MAX_REFRESH: Duration = Duration::from_secs(3600);
+const DEFAULT_REFRESH: Duration = Duration::from_secs(60);
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_IDLE: Duration = Duration::from_secs(1);
+
+/// Emitted when the resolved address set of a watched host has changed.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AddressSetChanged {
+    /// Watched host.
+    pub host: String,
+    /// Watched port.
+    pub port: u16,
+    /// Previously resolved addresses (sorted).
+    pub previous: Vec<SocketAddr>,
+    /// Currently resolved addresses (sorted).
+    pub current: Vec<SocketAddr>,
+}
+
+impl AddressSetChanged {
+    /// Addresses that are no longer resolved.
+    pub fn removed(&self) -> impl Iterator<Item = &SocketAddr> {
+        self.previous.iter().filter(|addr| !self.current.contains(addr))
+    }
+
+    /// Newly resolved addresses.
+    pub fn added(&self) -> impl Iterator<Item = &SocketAddr> {
+        self.current.iter().filter(|addr| !self.previous.contains(addr))
+    }
+}
+
+impl Display for AddressSetChanged {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{} address set changed {:?} -> {:?}", self.host, self.port, self.previous, self.current)
+    }
+}
+
+/// Configuration for [`DnsWatcher`].
+#[derive(Debug, Clone)]
+pub struct DnsWatcherConfig {
+    min_refresh: Duration,
+    max_refresh: Duration,
+    default_refresh: Duration,
+}
+
+impl Default for DnsWatcherConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DnsWatcherConfig {
+    /// Create config with the default refresh bounds.
+    pub fn new() -> Self {
+        Self {
+            min_refresh: DEFAULT_MIN_REFRESH,
+            max_refresh: DEFAULT_MAX_REFRESH,
+            default_refresh: DEFAULT_REFRESH,
+        }
+    }
+
+    /// Lower bound for the refresh interval, protects against very small (or zero) TTL (default 1s).
+    pub fn with_min_refresh(self, min_refresh: Duration) -> Self {
+        Self { min_refresh, ..self }
+    }
+
+    /// Upper bound for the refresh interval (default 1h).
+    pub fn with_max_refresh(self, max_refresh: Duration) -> Self {
+        Self { max_refresh, ..self }
+    }
+
+    /// Refresh interval used when the TTL is not known (default 60s).
+    pub fn with_default_refresh(self, default_refresh: Duration) -> Self {
+        Self {
+            default_refresh,
+            ..self
+        }
+    }
+
+    fn refresh_interval(&self, ttl: Option<Duration>) -> Duration {
+        ttl.unwrap_or(self.default_refresh)
+            .clamp(self.min_refresh, self.max_refresh.max(self.min_refresh))
+    }
+}
+
+/// Watches hosts for address set changes on a background thread.
+pub struct DnsWatcher {
+    requests: SyncSender<(String, u16)>,
+    events: Receiver<AddressSetChanged>,
+    _handle: JoinHandle<()>,
+}
+
+impl DnsWatcher {
+    /// Create watcher with the default configuration.
+    pub fn new() -> io::Result<Self> {
+        Self::new_with_config(DnsWatcherConfig::default())
+    }
+
+    /// Create watcher using the provided configuration.
+    pub fn new_with_config(config: DnsWatcherConfig) -> io::Result<Self> {
+        let (requests_tx, requests_rx) = std::sync::mpsc::sync_channel(256);
+        let (events_tx, events_rx) = std::sync::mpsc::sync_channel(1024);
+        let handle = thread::Builder::new().name("dns-watcher".to_owned()).spawn(move || {
+            let mut worker = WatchWorker {
+                config,
+                requests: requests_rx,
+                events: events_tx,
+                watched: Vec::new(),
+            };
+            worker.run()
+        })?;
+        Ok(Self {
+            requests: requests_tx,
+            events: events_rx,
+            _handle: handle,
+        })
+    }
+
+    /// Start watching `host:port`. The initial resolution does not emit any event.
+    pub fn watch(&self, host: impl AsRef<str>, port: u16) -> io::Result<()> {
+        self.requests
+            .try_send((host.as_ref().to_owned(), port))
+            .map_err(io::Error::other)
+    }
+
+    /// Return the next address set change, if any (non-blocking).
+    pub fn poll(&self) -> Option<AddressSetChanged> {
+        self.events.try_recv().ok()
+    }
+}
+
+struct Watched {
+    host: String,
+    port: u16,
+    addrs: Option<Vec<SocketAddr>>,
+    next_refresh: Instant,
+}
+
+struct WatchWorker {
+    config: DnsWatcherConfig,
+    requests: Receiver<(String, u16)>,
+    events: SyncSender<AddressSetChanged>,
+    watched: Vec<Watched>,
+}
+
+impl WatchWorker {
+    fn run(&mut self) {
+        loop {
+            let now = Instant::now();
+            let idle = self
+                .watched
+                .iter()
+                .map(|watched| watched.next_refresh.saturating_duration_since(now))
+                .min()
+                .unwrap_or(MAX_IDLE)
+                .min(MAX_IDLE);
+            match self.requests.recv_timeout(idle) {
+                Ok((host, port)) => self.watched.push(Watched {
+                    host,
+                    port,
+                    addrs: None,
+                    next_refresh: Instant::now(),
+                }),
+                Err(RecvTimeoutError::Timeout) => {}
+                // watcher has been dropped
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+            if !self.refresh_due() {
+                return;
+            }
+        }
+    }
+
+    /// Re-resolve hosts that are due. Returns `false` if the watcher has been dropped.
+    fn refresh_due(&mut self) -> bool {
+        let now = Instant::now();
+        for watched in self.watched.iter_mut().filter(|watched| watched.next_refresh <= now) {
+            let (addrs, ttl) = match resolve(&watched.host, watched.port) {
+                Ok(resolved) => resolved,
+                Err(err) => {
+                    debug!("unable to re-resolve {}:{}: {err}", watched.host, watched.port);
+                    watched.next_refresh = now + self.config.refresh_interval(None);
+                    continue;
+                }
+            };
+            watched.next_refresh = now + self.config.refresh_interval(ttl);
+            let previous = watched.addrs.replace(addrs.clone());
+            if let Some(previous) = previous.filter(|previous| previous != &addrs) {
+                let event = AddressSetChanged {
+                    host: watched.host.clone(),
+                    port: watched.port,
+                    previous,
+                    current: addrs,
+                };
+                info!("{event}");
+                if let Err(TrySendError::Disconnected(_)) = self.events.try_send(event) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+/// Resolve sorted address set together with the TTL (if known).
+fn resolve(host: &str, port: u16) -> io::Result<(Vec<SocketAddr>, Option<Duration>)> {
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok((vec![SocketAddr::new(ip, port)], Some(Duration::MAX)));
+    }
+    let (mut addrs, ttl) = match wire::resolve(host, QUERY_TIMEOUT) {
+        Ok(answer) if !answer.addrs.is_empty() => (
+            answer
+                .addrs
+                .into_iter()
+                .map(|ip| SocketAddr::new(ip, port))
+                .collect::<Vec<_>>(),
+            Some(answer.ttl),
+        ),
+        _ => ((host, port).to_socket_addrs()?.collect(), None),
+    };
+    addrs.sort();
+    addrs.dedup();
+    Ok((addrs, ttl))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_clamp_refresh_interval() {
+        let config = DnsWatcherConfig::new()
+            .with_min_refresh(Duration::from_secs(5))
+            .with_max_refresh(Duration::from_secs(600))
+            .with_default_refresh(Duration::from_secs(30));
+        assert_eq!(Duration::from_secs(5), config.refresh_interval(Some(Duration::ZERO)));
+        assert_eq!(Duration::from_secs(120), config.refresh_interval(Some(Duration::from_secs(120))));
+        assert_eq!(Duration::from_secs(600), config.refresh_interval(Some(Duration::MAX)));
+        assert_eq!(Duration::from_secs(30), config.refresh_interval(None));
+    }
+
+    #[test]
+    fn should_describe_address_set_change() {
+        let event = AddressSetChanged {
+            host: "example.com".to_owned(),
+            port: 443,
+            previous: vec!["10.0.0.1:443".parse().unwrap(), "10.0.0.2:443".parse().unwrap()],
+            current: vec!["10.0.0.2:443".parse().unwrap(), "10.0.0.3:443".parse().unwrap()],
+        };
+        assert_eq!(vec![&event.previous[0]], event.removed().collect::<Vec<_>>());
+        assert_eq!(vec![&event.current[1]], event.added().collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,185 @@
+//! Minimal DNS client used to obtain record TTL (not exposed by `getaddrinfo`).
+
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, SystemTime};
+
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+const DNS_PORT: u16 = 53;
+const HEADER_LEN: usize = 12;
+const MAX_RESPONSE_LEN: usize = 1232;
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const FLAG_TRUNCATED: u16 = 0x0200;
+
+/// Resolved addresses together with the lowest TTL of the answer records.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct Answer {
+    pub(crate) addrs: Vec<IpAddr>,
+    pub(crate) ttl: Duration,
+}
+
+/// Query the first nameserver from `/etc/resolv.conf` for A records (AAAA if there are none).
+pub(crate) fn resolve(host: &str, timeout: Duration) -> io::Result<Answer> {
+    let nameserver = nameserver()?;
+    let socket = UdpSocket::bind(match nameserver {
+        IpAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        IpAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    })?;
+    socket.connect((nameserver, DNS_PORT))?;
+    socket.set_read_timeout(Some(timeout))?;
+
+    let answer = query(&socket, host, TYPE_A)?;
+    if !answer.addrs.is_empty() {
+        return Ok(answer);
+    }
+    query(&socket, host, TYPE_AAAA)
+}
+
+fn nameserver() -> io::Result<IpAddr> {
+    fs::read_to_string(RESOLV_CONF)?
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .find_map(|addr| addr.trim().parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no nameserver configured"))
+}
+
+fn query(socket: &UdpSocket, host: &str, record_type: u16) -> io::Result<Answer> {
+    let id = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|now| now.subsec_nanos() as u16)
+        .unwrap_or_default();
+    socket.send(&encode_query(id, host, record_type)?)?;
+    let mut buf = [0u8; MAX_RESPONSE_LEN];
+    loop {
+        let len = socket.recv(&mut buf)?;
+        // ignore stale responses to previous queries
+        if len >= 2 && u16::from_be_bytes([buf[0], buf[1]]) == id {
+            return decode_response(&buf[..len], record_type);
+        }
+    }
+}
+
+fn encode_query(id: u16, host: &str, record_type: u16) -> io::Result<Vec<u8>> {
+    let mut query = Vec::with_capacity(HEADER_LEN + host.len() + 6);
+    query.extend_from_slice(&id.to_be_bytes());
+    // recursion desired, one question
+    query.extend_from_slice(&[0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    for label in host.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid host name: {host}")));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&record_type.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid dns response: {msg}"))
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> io::Result<u16> {
+    buf.get(offset..offset + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or_else(|| invalid("truncated"))
+}
+
+/// Skip (possibly compressed) name and return offset past it.
+fn skip_name(buf: &[u8], mut offset: usize) -> io::Result<usize> {
+    loop {
+        let len = *buf.get(offset).ok_or_else(|| invalid("truncated name"))?;
+        match len {
+            0 => return Ok(offset + 1),
+            len if len & 0xc0 == 0xc0 => return Ok(offset + 2),
+            len => offset += 1 + len as usize,
+        }
+    }
+}
+
+fn decode_response(buf: &[u8], record_type: u16) -> io::Result<Answer> {
+    let flags = read_u16(buf, 2)?;
+    if flags & FLAG_TRUNCATED != 0 {
+        return Err(invalid("truncated"));
+    }
+    match flags & 0x000f {
+        0 => {}
+        3 => return Err(io::Error::new(io::ErrorKind::NotFound, "host not found")),
+        rcode => return Err(invalid(&format!("rcode {rcode}"))),
+    }
+    let questions = read_u16(buf, 4)?;
+    let answers = read_u16(buf, 6)?;
+
+    let mut offset = HEADER_LEN;
+    for _ in 0..questions {
+        offset = skip_name(buf, offset)? + 4;
+    }
+
+    let mut addrs = Vec::new();
+    let mut ttl = u32::MAX;
+    for _ in 0..answers {
+        offset = skip_name(buf, offset)?;
+        let rtype = read_u16(buf, offset)?;
+        let record_ttl = u32::from(read_u16(buf, offset + 4)?) << 16 | u32::from(read_u16(buf, offset + 6)?);
+        let rdlen = read_u16(buf, offset + 8)? as usize;
+        let rdata = buf
+            .get(offset + 10..offset + 10 + rdlen)
+            .ok_or_else(|| invalid("truncated record"))?;
+        offset += 10 + rdlen;
+
+        // cname chain records also limit how long the answer is valid
+        ttl = ttl.min(record_ttl);
+        match (rtype, rdata.len()) {
+            (TYPE_A, 4) if rtype == record_type => {
+                addrs.push(IpAddr::from(<[u8; 4]>::try_from(rdata).unwrap()));
+            }
+            (TYPE_AAAA, 16) if rtype == record_type => {
+                addrs.push(IpAddr::from(<[u8; 16]>::try_from(rdata).unwrap()));
+            }
+            _ => {}
+        }
+    }
+    if addrs.is_empty() {
+        ttl = 0;
+    }
+    Ok(Answer {
+        addrs,
+        ttl: Duration::from_secs(ttl as u64),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_decode_answer_with_cname_chain() {
+        let mut response = encode_query(0x1234, "fstream.binance.com", TYPE_A).unwrap();
+        // response flags, 3 answers
+        response[2..4].copy_from_slice(&0x8180u16.to_be_bytes());
+        response[6..8].copy_from_slice(&3u16.to_be_bytes());
+        // CNAME pointing back to the question name (ttl 300)
+        response.extend_from_slice(&[
+            0xc0, 0x0c, 0x00, 0x05, 0x00, 0x01, 0x00, 0x00, 0x01, 0x2c, 0x00, 0x02, 0xc0, 0x0c,
+        ]);
+        // A records (ttl 60 and 30)
+        response.extend_from_slice(&[
+            0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x3c, 0x00, 0x04, 10, 0, 0, 1,
+        ]);
+        response.extend_from_slice(&[
+            0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x1e, 0x00, 0x04, 10, 0, 0, 2,
+        ]);
+
+        let answer = decode_response(&response, TYPE_A).unwrap();
+        assert_eq!(vec![IpAddr::from([10, 0, 0, 1]), IpAddr::from([10, 0, 0, 2])], answer.addrs);
+        assert_eq!(Duration::from_secs(30), answer.ttl);
+
+        response[3] = 0x83;
+        assert_eq!(io::ErrorKind::NotFound, decode_response(&response, TYPE_A).unwrap_err().kind());
+    }
+}
@@ -1,5 +1,6 @@
 //! Entry point for the application logic.
 
+use crate::service::task::Tasks;
 use crate::stream::ConnectionInfoProvider;
 use std::fmt::{Debug, Display};
 use std::io;
@@ -18,7 +19,7 @@ pub trait Endpoint: ConnectionInfoProvider {
 
     /// Upon disconnection `IOService` will query the endpoint if the connection can be
     /// recreated, passing the disconnect `reason`. If `false` is returned it will cause
-    /// program to panic.
+    /// program to panic (except for [`DisconnectReason::Duplicate`] which terminates the endpoint).
     fn can_recreate(&mut self, _reason: DisconnectReason) -> bool {
         true
     }
@@ -29,6 +30,54 @@ pub trait Endpoint: ConnectionInfoProvider {
     fn can_auto_disconnect(&mut self) -> bool {
         true
     }
+
+    /// Budget from connect to the first application frame. If [`has_first_frame`](Self::has_first_frame)
+    /// does not return `true` within the budget the connection is aborted with
+    /// [`DisconnectReason::FirstFrameTimeout`] and the next attempt will prefer a different address.
+    fn first_frame_budget(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Queried after every poll while the first frame budget is being enforced. Should return `true`
+    /// once the first application frame has been received on the current connection.
+    fn has_first_frame(&self) -> bool {
+        true
+    }
+
+    /// Time the endpoint is given to flush its [`last_words`](Self::last_words) before a deliberate
+    /// disconnect (auto disconnect, first frame or read timeout, quiet period) or
+    /// [`shutdown`](crate::service::IOService::shutdown). With `None` (default) the connection is
+    /// dropped straight away.
+    fn last_words_deadline(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Send the "last words" (e.g. cancel-all on an order entry session) on the `target` that is about
+    /// to be deliberately disconnected. The service keeps calling it without blocking (`first` is only
+    /// set on the first call) until it returns `Ok(true)` once everything has been handed to the
+    /// kernel, it returns an error or the [deadline](Self::last_words_deadline) expires.
+    fn last_words(&mut self, _target: &mut Self::Target, _first: bool) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    /// One-shot and periodic [`Tasks`] (e.g. `listenKey` refresh) the service runs on the target
+    /// before every poll of the connected endpoint.
+    fn tasks(&mut self) -> Option<&mut Tasks<Self::Target>> {
+        None
+    }
+
+    /// Logical identity (fingerprint) of the connection, e.g. `"binance:btcusdt@depth"`. With the
+    /// [duplicate guard](crate::service::IOService::with_duplicate_guard) enabled the service never
+    /// keeps two live connections with the same identity.
+    fn identity(&self) -> Option<&str> {
+        None
+    }
+
+    /// Tenant (e.g. strategy or team) the endpoint belongs to, the service accounts the endpoint
+    /// under the tenant and enforces its [quota](crate::service::IOService::with_tenant_quota).
+    fn tenant(&self) -> Option<&str> {
+        None
+    }
 }
 
 /// Marker trait to be applied on user defined `struct` that is registered with 'IOService'
@@ -59,6 +108,55 @@ pub trait EndpointWithContext<C>: ConnectionInfoProvider {
     fn can_auto_disconnect(&mut self, _context: &mut C) -> bool {
         true
     }
+
+    /// Budget from connect to the first application frame. If [`has_first_frame`](Self::has_first_frame)
+    /// does not return `true` within the budget the connection is aborted with
+    /// [`DisconnectReason::FirstFrameTimeout`] and the next attempt will prefer a different address.
+    fn first_frame_budget(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Queried after every poll while the first frame budget is being enforced. Should return `true`
+    /// once the first application frame has been received on the current connection.
+    fn has_first_frame(&self) -> bool {
+        true
+    }
+
+    /// Time the endpoint is given to flush its [`last_words`](Self::last_words) before a deliberate
+    /// disconnect (auto disconnect, first frame or read timeout, quiet period) or
+    /// [`shutdown`](crate::service::IOService::shutdown). With `None` (default) the connection is
+    /// dropped straight away.
+    fn last_words_deadline(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Send the "last words" (e.g. cancel-all on an order entry session) on the `target` that is about
+    /// to be deliberately disconnected, passing user provided `Context`. The service keeps calling it
+    /// without blocking (`first` is only set on the first call) until it returns `Ok(true)` once
+    /// everything has been handed to the kernel, it returns an error or the
+    /// [deadline](Self::last_words_deadline) expires.
+    fn last_words(&mut self, _target: &mut Self::Target, _first: bool, _context: &mut C) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    /// One-shot and periodic [`Tasks`] (e.g. `listenKey` refresh) the service runs on the target
+    /// before every poll of the connected endpoint.
+    fn tasks(&mut self) -> Option<&mut Tasks<Self::Target>> {
+        None
+    }
+
+    /// Logical identity (fingerprint) of the connection, e.g. `"binance:btcusdt@depth"`. With the
+    /// [duplicate guard](crate::service::IOService::with_duplicate_guard) enabled the service never
+    /// keeps two live connections with the same identity.
+    fn identity(&self) -> Option<&str> {
+        None
+    }
+
+    /// Tenant (e.g. strategy or team) the endpoint belongs to, the service accounts the endpoint
+    /// under the tenant and enforces its [quota](crate::service::IOService::with_tenant_quota).
+    fn tenant(&self) -> Option<&str> {
+        None
+    }
 }
 
 /// Disconnect reason passed into `can_recreate()` service call.
@@ -67,6 +165,15 @@ pub enum DisconnectReason {
     AutoDisconnect(Duration),
     /// IO error has occurred such as reaching EOF or peer disconnect.
     IO(io::Error),
+    /// First application frame has not been received within the endpoint budget.
+    FirstFrameTimeout(Duration),
+    /// No bytes have been received within the endpoint read timeout.
+    ReadTimeout(Duration),
+    /// Endpoint has been paused for the scheduled quiet period, it will reconnect once it ends.
+    Maintenance,
+    /// Another live connection has the same [`identity`](Endpoint::identity). Returning `false`
+    /// from `can_recreate()` terminates the endpoint, `true` retries once the other connection is gone.
+    Duplicate(String),
 }
 
 impl Display for DisconnectReason {
@@ -79,6 +186,20 @@ impl Display for DisconnectReason {
             DisconnectReason::IO(err) => {
                 write!(f, "{err}")
             }
+            DisconnectReason::FirstFrameTimeout(budget) => {
+                write!(f, "first frame not received within ")?;
+                budget.fmt(f)
+            }
+            DisconnectReason::ReadTimeout(timeout) => {
+                write!(f, "no data received within ")?;
+                timeout.fmt(f)
+            }
+            DisconnectReason::Maintenance => {
+                write!(f, "paused for the scheduled quiet period")
+            }
+            DisconnectReason::Duplicate(identity) => {
+                write!(f, "duplicate of live connection {identity}")
+            }
         }
     }
 }
@@ -91,6 +212,27 @@ impl DisconnectReason {
     pub(crate) fn other(err: io::Error) -> DisconnectReason {
         DisconnectReason::IO(err)
     }
+
+    pub(crate) fn first_frame_timeout(budget: Duration) -> DisconnectReason {
+        DisconnectReason::FirstFrameTimeout(budget)
+    }
+
+    pub(crate) fn read_timeout(timeout: Duration) -> DisconnectReason {
+        DisconnectReason::ReadTimeout(timeout)
+    }
+
+    pub(crate) fn duplicate(identity: &str) -> DisconnectReason {
+        DisconnectReason::Duplicate(identity.to_owned())
+    }
+
+    pub(crate) const fn is_first_frame_timeout(&self) -> bool {
+        matches!(self, DisconnectReason::FirstFrameTimeout(_))
+    }
+
+    /// Checks if the disconnect has been initiated by the service rather than caused by an IO error.
+    pub(crate) const fn is_deliberate(&self) -> bool {
+        !matches!(self, DisconnectReason::IO(_))
+    }
 }
 
 #[cfg(all(feature = "ext", feature = "ws", any(feature = "rustls", feature = "openssl")))]
@@ -98,16 +240,18 @@ pub mod ws {
     use std::io;
     use std::io::{Read, Write};
     use std::net::SocketAddr;
+    use std::time::Duration;
 
     use crate::service::endpoint::{DisconnectReason, Endpoint, EndpointWithContext};
-    use crate::stream::ConnectionInfoProvider;
+    use crate::service::task::Tasks;
     use crate::stream::tls::TlsStream;
+    use crate::stream::{ConnectionInfoProvider, PendingWrites};
     use crate::ws::Websocket;
 
     pub type TlsWebsocket<S> = Websocket<TlsStream<S>>;
 
     pub trait TlsWebsocketEndpoint: ConnectionInfoProvider {
-        type Stream: Read + Write;
+        type Stream: Read + Write + PendingWrites;
 
         fn create_websocket(&mut self, addr: SocketAddr) -> io::Result<Option<Websocket<TlsStream<Self::Stream>>>>;
 
@@ -118,6 +262,36 @@ pub mod ws {
         fn can_auto_disconnect(&mut self) -> bool {
             true
         }
+
+        fn first_frame_budget(&self) -> Option<Duration> {
+            None
+        }
+
+        fn has_first_frame(&self) -> bool {
+            true
+        }
+
+        /// Text frame (e.g. cancel-all) sent before the websocket is deliberately closed, the frame
+        /// and the close frame are flushed within the [`last_words_deadline`](Self::last_words_deadline).
+        fn last_words(&self) -> Option<&[u8]> {
+            None
+        }
+
+        fn last_words_deadline(&self) -> Option<Duration> {
+            None
+        }
+
+        fn tasks(&mut self) -> Option<&mut Tasks<TlsWebsocket<Self::Stream>>> {
+            None
+        }
+
+        fn identity(&self) -> Option<&str> {
+            None
+        }
+
+        fn tenant(&self) -> Option<&str> {
+            None
+        }
     }
 
     impl<T> Endpoint for T
@@ -140,10 +314,44 @@ pub mod ws {
         fn can_auto_disconnect(&mut self) -> bool {
             self.can_auto_disconnect()
         }
+
+        #[inline]
+        fn first_frame_budget(&self) -> Option<Duration> {
+            TlsWebsocketEndpoint::first_frame_budget(self)
+        }
+
+        #[inline]
+        fn has_first_frame(&self) -> bool {
+            TlsWebsocketEndpoint::has_first_frame(self)
+        }
+
+        #[inline]
+        fn last_words_deadline(&self) -> Option<Duration> {
+            TlsWebsocketEndpoint::last_words_deadline(self)
+        }
+
+        #[inline]
+        fn tasks(&mut self) -> Option<&mut Tasks<Self::Target>> {
+            TlsWebsocketEndpoint::tasks(self)
+        }
+
+        #[inline]
+        fn identity(&self) -> Option<&str> {
+            TlsWebsocketEndpoint::identity(self)
+        }
+
+        #[inline]
+        fn tenant(&self) -> Option<&str> {
+            TlsWebsocketEndpoint::tenant(self)
+        }
+
+        fn last_words(&mut self, target: &mut Self::Target, first: bool) -> io::Result<bool> {
+            say_last_words(target, first, TlsWebsocketEndpoint::last_words(self))
+        }
     }
 
     pub trait TlsWebsocketEndpointWithContext<C>: ConnectionInfoProvider {
-        type Stream: Read + Write;
+        type Stream: Read + Write + PendingWrites;
 
         fn create_websocket(
             &mut self,
@@ -158,6 +366,36 @@ pub mod ws {
         fn can_auto_disconnect(&mut self, _ctx: &mut C) -> bool {
             true
         }
+
+        fn first_frame_budget(&self) -> Option<Duration> {
+            None
+        }
+
+        fn has_first_frame(&self) -> bool {
+            true
+        }
+
+        /// Text frame (e.g. cancel-all) sent before the websocket is deliberately closed, the frame
+        /// and the close frame are flushed within the [`last_words_deadline`](Self::last_words_deadline).
+        fn last_words(&self) -> Option<&[u8]> {
+            None
+        }
+
+        fn last_words_deadline(&self) -> Option<Duration> {
+            None
+        }
+
+        fn tasks(&mut self) -> Option<&mut Tasks<TlsWebsocket<Self::Stream>>> {
+            None
+        }
+
+        fn identity(&self) -> Option<&str> {
+            None
+        }
+
+        fn tenant(&self) -> Option<&str> {
+            None
+        }
     }
 
     impl<T, C> EndpointWithContext<C> for T
@@ -180,5 +418,52 @@ pub mod ws {
         fn can_auto_disconnect(&mut self, context: &mut C) -> bool {
             self.can_auto_disconnect(context)
         }
+
+        #[inline]
+        fn first_frame_budget(&self) -> Option<Duration> {
+            TlsWebsocketEndpointWithContext::first_frame_budget(self)
+        }
+
+        #[inline]
+        fn has_first_frame(&self) -> bool {
+            TlsWebsocketEndpointWithContext::has_first_frame(self)
+        }
+
+        #[inline]
+        fn last_words_deadline(&self) -> Option<Duration> {
+            TlsWebsocketEndpointWithContext::last_words_deadline(self)
+        }
+
+        #[inline]
+        fn tasks(&mut self) -> Option<&mut Tasks<Self::Target>> {
+            TlsWebsocketEndpointWithContext::tasks(self)
+        }
+
+        #[inline]
+        fn identity(&self) -> Option<&str> {
+            TlsWebsocketEndpointWithContext::identity(self)
+        }
+
+        #[inline]
+        fn tenant(&self) -> Option<&str> {
+            TlsWebsocketEndpointWithContext::tenant(self)
+        }
+
+        fn last_words(&mut self, target: &mut Self::Target, first: bool, _context: &mut C) -> io::Result<bool> {
+            say_last_words(target, first, TlsWebsocketEndpointWithContext::last_words(self))
+        }
+    }
+
+    /// Queue the `frame` followed by the close frame and drive them towards the kernel.
+    fn say_last_words<S>(ws: &mut TlsWebsocket<S>, first: bool, frame: Option<&[u8]>) -> io::Result<bool>
+    where
+        S: Read + Write + PendingWrites,
+    {
+        if first {
+            if let Some(frame) = frame {
+                ws.send_text(true, Some(frame))?;
+            }
+        }
+        Ok(ws.poll_close()?)
     }
 }
@@ -0,0 +1,468 @@
+//! Endpoint defined over an ordered set of hosts with health based primary election.
+//!
+//! [`HostSet`] keeps health information (consecutive failures and connect latency) for every host
+//! and elects the primary the endpoint connects to. The primary is demoted after too many consecutive
+//! failures and a standby is promoted when it is measurably faster. Failed hosts become eligible again
+//! after the failure cooldown.
+//!
+//! The round trip time measured on the established connection (e.g. with the websocket keepalive pings,
+//! see [`Websocket::rtt`](crate::ws::Websocket::rtt)) can be fed with [`HostSet::on_rtt`]. Unlike the
+//! time since the last message it does not depend on how active the subscribed streams are, so a
+//! primary whose round trip time or jitter exceed the configured limits is treated as failing even
+//! though the data keeps flowing.
+//!
+//! [`HostSet`] implements [`ConnectionInfoProvider`] returning the current primary, so the endpoint
+//! simply delegates to it and `IOService` will always resolve and connect to the elected host.
+//!
+//! ## Examples
+//! ```no_run
+//! use std::io;
+//! use std::net::SocketAddr;
+//! use std::time::{Duration, Instant};
+//! use boomnet::service::endpoint::{DisconnectReason, Endpoint};
+//! use boomnet::service::failover::HostSet;
+//! use boomnet::stream::tcp::TcpStream;
+//! use boomnet::stream::{ConnectionInfo, ConnectionInfoProvider};
+//!
+//! struct FeedEndpoint {
+//!     hosts: HostSet,
+//! }
+//!
+//! impl ConnectionInfoProvider for FeedEndpoint {
+//!     fn connection_info(&self) -> &ConnectionInfo {
+//!         self.hosts.connection_info()
+//!     }
+//! }
+//!
+//! impl Endpoint for FeedEndpoint {
+//!     type Target = TcpStream;
+//!
+//!     fn create_target(&mut self, addr: SocketAddr) -> io::Result<Option<Self::Target>> {
+//!         let start = Instant::now();
+//!         let stream = self.connection_info().clone().into_tcp_stream_with_addr(addr)?;
+//!         self.hosts.on_connected(start.elapsed());
+//!         Ok(Some(stream))
+//!     }
+//!
+//!     fn can_recreate(&mut self, _reason: DisconnectReason) -> bool {
+//!         // next connection attempt will use the (possibly) newly elected primary
+//!         self.hosts.on_failure();
+//!         true
+//!     }
+//! }
+//!
+//! let mut hosts = HostSet::new(ConnectionInfo::new("fstream.binance.com", 443))
+//!     .with_host(ConnectionInfo::new("fstream-mm.binance.com", 443))
+//!     .with_max_failures(2)
+//!     .with_failure_cooldown(Duration::from_secs(60));
+//!
+//! // measure standby connect latency (blocking, call it outside the hot path)
+//! hosts.probe(Duration::from_secs(1));
+//! ```
+
+use crate::metrics::RttStats;
+use crate::service::time::{SystemTimeClockSource, TimeSource};
+use crate::stream::{ConnectionInfo, ConnectionInfoProvider};
+use log::{info, warn};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+const DEFAULT_MAX_FAILURES: u32 = 3;
+const DEFAULT_FAILURE_COOLDOWN: Duration = Duration::from_secs(30);
+const DEFAULT_LATENCY_MARGIN: Duration = Duration::from_millis(1);
+
+/// Health of a single host.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct HostHealth {
+    consecutive_failures: u32,
+    last_failure_ns: u64,
+    latency_ns: Option<u64>,
+    rtt: Option<RttStats>,
+}
+
+impl HostHealth {
+    /// Number of failures since the last successful connection.
+    pub const fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures
+    }
+
+    /// Smoothed connect latency, `None` if it has not been measured yet.
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency_ns.map(Duration::from_nanos)
+    }
+
+    /// Round trip time last measured on the connection, `None` if it has not been recorded yet.
+    pub const fn rtt(&self) -> Option<RttStats> {
+        self.rtt
+    }
+
+    fn record_success(&mut self, latency: Duration) {
+        let latency_ns = latency.as_nanos() as u64;
+        self.consecutive_failures = 0;
+        // exponentially weighted moving average (alpha = 1/4)
+        self.latency_ns = Some(match self.latency_ns {
+            Some(prev) => prev - prev / 4 + latency_ns / 4,
+            None => latency_ns,
+        });
+    }
+
+    fn record_failure(&mut self, now_ns: u64) {
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        self.last_failure_ns = now_ns;
+    }
+}
+
+/// Ordered set of distinct hosts with the elected primary. The first host is the initial primary.
+pub struct HostSet<TS = SystemTimeClockSource> {
+    hosts: Vec<(ConnectionInfo, HostHealth)>,
+    primary: usize,
+    max_failures: u32,
+    failure_cooldown_ns: u64,
+    latency_margin_ns: u64,
+    max_rtt_ns: Option<u64>,
+    max_jitter_ns: Option<u64>,
+    time_source: TS,
+}
+
+impl HostSet {
+    /// Create host set with the initial `primary` host.
+    pub fn new(primary: impl Into<ConnectionInfo>) -> Self {
+        Self {
+            hosts: vec![(primary.into(), HostHealth::default())],
+            primary: 0,
+            max_failures: DEFAULT_MAX_FAILURES,
+            failure_cooldown_ns: DEFAULT_FAILURE_COOLDOWN.as_nanos() as u64,
+            latency_margin_ns: DEFAULT_LATENCY_MARGIN.as_nanos() as u64,
+            max_rtt_ns: None,
+            max_jitter_ns: None,
+            time_source: SystemTimeClockSource,
+        }
+    }
+}
+
+impl<TS: TimeSource> HostSet<TS> {
+    /// Add standby `host`, hosts earlier in the order are preferred. Will panic if the same host
+    /// and port is already present.
+    pub fn with_host(mut self, host: impl Into<ConnectionInfo>) -> Self {
+        let host = host.into();
+        assert!(
+            !self
+                .hosts
+                .iter()
+                .any(|(info, _)| info.host() == host.host() && info.port() == host.port()),
+            "duplicate host: {host}"
+        );
+        self.hosts.push((host, HostHealth::default()));
+        self
+    }
+
+    /// Number of consecutive failures after which the primary is demoted (default 3).
+    pub fn with_max_failures(self, max_failures: u32) -> Self {
+        Self {
+            max_failures: max_failures.max(1),
+            ..self
+        }
+    }
+
+    /// Time after which a failed host becomes eligible for election again (default 30s).
+    pub fn with_failure_cooldown(self, cooldown: Duration) -> Self {
+        Self {
+            failure_cooldown_ns: cooldown.as_nanos() as u64,
+            ..self
+        }
+    }
+
+    /// How much faster a standby must be to replace a healthy primary (default 1ms).
+    pub fn with_latency_margin(self, margin: Duration) -> Self {
+        Self {
+            latency_margin_ns: margin.as_nanos() as u64,
+            ..self
+        }
+    }
+
+    /// Smoothed round trip time above which the primary is considered degraded, see [`HostSet::on_rtt`].
+    pub fn with_max_rtt(self, max_rtt: Duration) -> Self {
+        Self {
+            max_rtt_ns: Some(max_rtt.as_nanos() as u64),
+            ..self
+        }
+    }
+
+    /// Round trip time jitter above which the primary is considered degraded, see [`HostSet::on_rtt`].
+    pub fn with_max_jitter(self, max_jitter: Duration) -> Self {
+        Self {
+            max_jitter_ns: Some(max_jitter.as_nanos() as u64),
+            ..self
+        }
+    }
+
+    /// Specify custom [`TimeSource`] instead of the default system time source.
+    pub fn with_time_source<T: TimeSource>(self, time_source: T) -> HostSet<T> {
+        HostSet {
+            hosts: self.hosts,
+            primary: self.primary,
+            max_failures: self.max_failures,
+            failure_cooldown_ns: self.failure_cooldown_ns,
+            latency_margin_ns: self.latency_margin_ns,
+            max_rtt_ns: self.max_rtt_ns,
+            max_jitter_ns: self.max_jitter_ns,
+            time_source,
+        }
+    }
+
+    /// Currently elected primary host.
+    pub fn primary(&self) -> &ConnectionInfo {
+        &self.hosts[self.primary].0
+    }
+
+    /// Index of the currently elected primary host.
+    pub const fn primary_index(&self) -> usize {
+        self.primary
+    }
+
+    /// Number of hosts in the set.
+    pub fn len(&self) -> usize {
+        self.hosts.len()
+    }
+
+    /// Always `false` as the set contains at least the initial primary.
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+
+    /// Iterate over all hosts (in order) together with their health.
+    pub fn hosts(&self) -> impl Iterator<Item = (&ConnectionInfo, &HostHealth)> {
+        self.hosts.iter().map(|(info, health)| (info, health))
+    }
+
+    /// Record successful connection to the primary and its connect `latency`.
+    pub fn on_connected(&mut self, latency: Duration) {
+        self.hosts[self.primary].1.record_success(latency);
+    }
+
+    /// Record failure (connection error or disconnect) of the primary and re-elect. Returns `true`
+    /// if a different primary has been elected.
+    pub fn on_failure(&mut self) -> bool {
+        let now_ns = self.time_source.current_time_nanos();
+        self.hosts[self.primary].1.record_failure(now_ns);
+        self.elect()
+    }
+
+    /// Record the round trip time measured on the connection to the primary. Every update of the
+    /// statistics with the smoothed round trip time or the jitter above its limit counts as a failure
+    /// of the primary (so it is demoted after `max_failures` of them), the same statistics are only
+    /// accounted once. Returns `true` if a different primary has been elected.
+    pub fn on_rtt(&mut self, rtt: RttStats) -> bool {
+        let health = &mut self.hosts[self.primary].1;
+        if health.rtt == Some(rtt) {
+            return false;
+        }
+        health.rtt = Some(rtt);
+        let degraded = self.max_rtt_ns.is_some_and(|max_rtt_ns| rtt.smoothed > max_rtt_ns)
+            || self
+                .max_jitter_ns
+                .is_some_and(|max_jitter_ns| rtt.jitter > max_jitter_ns);
+        if !degraded {
+            return false;
+        }
+        warn!("primary host {} degraded: {rtt}", self.hosts[self.primary].0);
+        self.on_failure()
+    }
+
+    /// Record connect `latency` measured for host at `index`.
+    pub fn record_latency(&mut self, index: usize, latency: Duration) {
+        self.hosts[index].1.record_success(latency);
+    }
+
+    /// Record failure of the host at `index` (e.g. failed standby probe).
+    pub fn record_failure(&mut self, index: usize) {
+        let now_ns = self.time_source.current_time_nanos();
+        self.hosts[index].1.record_failure(now_ns);
+    }
+
+    /// Measure connect latency of every standby host with a blocking TCP connect (bounded by `timeout`)
+    /// and re-elect. This should be called periodically outside the hot path. Returns `true` if a
+    /// different primary has been elected.
+    pub fn probe(&mut self, timeout: Duration) -> bool {
+        for index in 0..self.hosts.len() {
+            if index == self.primary {
+                continue;
+            }
+            match probe_host(&self.hosts[index].0, timeout) {
+                Ok(latency) => self.record_latency(index, latency),
+                Err(err) => {
+                    warn!("standby host {} probe failed: {err}", self.hosts[index].0);
+                    self.record_failure(index);
+                }
+            }
+        }
+        self.elect()
+    }
+
+    /// Elect primary based on the current health. Returns `true` if a different primary has been elected.
+    ///
+    /// The primary is kept while it is eligible (fewer than `max_failures` consecutive failures or past
+    /// the failure cooldown) unless an eligible standby is faster by more than the latency margin. When
+    /// the primary is not eligible the fastest eligible host is elected (the first eligible one in order
+    /// if no latency has been measured). If no host is eligible the next host in order is tried.
+    pub fn elect(&mut self) -> bool {
+        let now_ns = self.time_source.current_time_nanos();
+        let eligible = |health: &HostHealth| {
+            health.consecutive_failures < self.max_failures
+                || now_ns.saturating_sub(health.last_failure_ns) >= self.failure_cooldown_ns
+        };
+
+        // fastest eligible host, ties and unmeasured hosts resolved by order
+        let fastest = self
+            .hosts
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, health))| eligible(health))
+            .min_by_key(|(index, (_, health))| (health.latency_ns.unwrap_or(u64::MAX), *index))
+            .map(|(index, _)| index);
+
+        let current = &self.hosts[self.primary].1;
+        let elected = match fastest {
+            Some(candidate) if eligible(current) => {
+                let candidate_latency = self.hosts[candidate].1.latency_ns;
+                match (current.latency_ns, candidate_latency) {
+                    (Some(current), Some(candidate_latency))
+                        if candidate_latency.saturating_add(self.latency_margin_ns) < current =>
+                    {
+                        candidate
+                    }
+                    _ => self.primary,
+                }
+            }
+            Some(candidate) => candidate,
+            None => (self.primary + 1) % self.hosts.len(),
+        };
+
+        if elected != self.primary {
+            info!(
+                "elected {} as primary (previous {}, consecutive failures {})",
+                self.hosts[elected].0, self.hosts[self.primary].0, current.consecutive_failures
+            );
+            self.primary = elected;
+            return true;
+        }
+        false
+    }
+}
+
+impl<TS> ConnectionInfoProvider for HostSet<TS> {
+    fn connection_info(&self) -> &ConnectionInfo {
+        &self.hosts[self.primary].0
+    }
+}
+
+fn probe_host(info: &ConnectionInfo, timeout: Duration) -> std::io::Result<Duration> {
+    let addr = info
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| std::io::Error::other("dns resolution did not return any address"))?;
+    let start = Instant::now();
+    let _stream = TcpStream::connect_timeout(&addr, timeout)?;
+    Ok(start.elapsed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct ManualClock(Rc<Cell<u64>>);
+
+    impl TimeSource for ManualClock {
+        fn current_time_nanos(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    fn host_set(clock: &ManualClock) -> HostSet<ManualClock> {
+        HostSet::new(("a", 443))
+            .with_host(("b", 443))
+            .with_host(("c", 443))
+            .with_max_failures(2)
+            .with_failure_cooldown(Duration::from_secs(10))
+            .with_time_source(clock.clone())
+    }
+
+    #[test]
+    fn should_demote_primary_after_max_failures_and_restore_after_cooldown() {
+        let clock = ManualClock::default();
+        let mut hosts = host_set(&clock);
+
+        assert!(!hosts.on_failure());
+        assert_eq!("a", hosts.primary().host());
+        assert!(hosts.on_failure());
+        assert_eq!("b", hosts.primary().host());
+
+        // once failed host is eligible again it is preferred only by latency
+        clock.0.set(Duration::from_secs(11).as_nanos() as u64);
+        hosts.on_connected(Duration::from_millis(20));
+        hosts.record_latency(0, Duration::from_millis(5));
+        assert!(hosts.elect());
+        assert_eq!("a", hosts.primary().host());
+    }
+
+    #[test]
+    fn should_promote_faster_standby_only_beyond_margin() {
+        let clock = ManualClock::default();
+        let mut hosts = host_set(&clock).with_latency_margin(Duration::from_millis(2));
+
+        hosts.on_connected(Duration::from_millis(10));
+        hosts.record_latency(2, Duration::from_millis(9));
+        assert!(!hosts.elect());
+
+        hosts.record_latency(1, Duration::from_millis(3));
+        assert!(hosts.elect());
+        assert_eq!(1, hosts.primary_index());
+    }
+
+    #[test]
+    fn should_rotate_when_no_host_is_eligible() {
+        let clock = ManualClock::default();
+        let mut hosts = host_set(&clock);
+        for index in 0..hosts.len() {
+            hosts.record_failure(index);
+            hosts.record_failure(index);
+        }
+        assert!(hosts.elect());
+        assert_eq!(1, hosts.primary_index());
+    }
+
+    #[test]
+    fn should_demote_primary_with_degraded_rtt() {
+        let clock = ManualClock::default();
+        let mut hosts = host_set(&clock)
+            .with_max_rtt(Duration::from_millis(5))
+            .with_max_jitter(Duration::from_millis(1));
+        let rtt = |samples, smoothed, jitter| RttStats {
+            samples,
+            last: smoothed,
+            min: smoothed,
+            max: smoothed,
+            smoothed,
+            jitter,
+        };
+
+        assert!(!hosts.on_rtt(rtt(1, 2_000_000, 0)));
+        assert!(!hosts.on_rtt(rtt(2, 2_000_000, 1_500_000)));
+        // the same statistics are not a new failure
+        assert!(!hosts.on_rtt(rtt(2, 2_000_000, 1_500_000)));
+        assert_eq!(1, hosts.hosts().next().unwrap().1.consecutive_failures());
+
+        assert!(hosts.on_rtt(rtt(3, 6_000_000, 500_000)));
+        assert_eq!("b", hosts.primary().host());
+        assert_eq!(Some(rtt(3, 6_000_000, 500_000)), hosts.hosts().next().unwrap().1.rtt());
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate host")]
+    fn should_reject_duplicate_host() {
+        let _ = HostSet::new(("a", 443)).with_host(("a", 443));
+    }
+}
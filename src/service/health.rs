@@ -0,0 +1,272 @@
+//! Readiness and liveness probes for process supervisors.
+//!
+//! The service is *live* when its event loop has iterated within the configured timeout and *ready*
+//! when, in addition, all critical endpoints are connected and report that they are in sync. The
+//! event loop [reports](IOService::probe) to a shared [`Health`] on every iteration, which can be
+//! queried from any thread (e.g. to drive a watchdog) or served to orchestrators over a Unix domain
+//! [`ProbeSocket`].
+//!
+//! The probe socket answers every connection with a single status line and closes it, so that a
+//! supervisor can query it with `socat - UNIX-CONNECT:<path>` or similar:
+//!
+//! ```text
+//! live=1 ready=0 last_poll_us=12
+//! ```
+//!
+//! ## Examples
+//! ```no_run
+//! use std::time::Duration;
+//! use boomnet::service::health::{Health, ProbeSocket};
+//!
+//! let health = Health::new(Duration::from_millis(100));
+//! let probes = ProbeSocket::bind("/run/feed/health.sock").unwrap();
+//! // on every iteration of the event loop:
+//! // io_service.poll(...)?;
+//! // io_service.probe(&health);
+//! probes.poll(&health).unwrap();
+//! ```
+
+use crate::service::IOService;
+use crate::service::dns::DnsResolver;
+use crate::service::select::Selector;
+use crate::service::time::{SystemTimeClockSource, TimeSource};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Endpoint contribution to the service readiness.
+pub trait EndpointHealth {
+    /// Critical endpoints must be connected and synced for the service to be ready, every endpoint
+    /// is critical by default.
+    fn is_critical(&self) -> bool {
+        true
+    }
+
+    /// Whether the endpoint is in sync with the remote (e.g. snapshot received and no sequence
+    /// gap pending). Only asked while the endpoint is connected.
+    fn is_synced(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Default)]
+struct Probes {
+    // zero until the first report
+    last_poll_ns: AtomicU64,
+    ready: AtomicBool,
+}
+
+/// Readiness and liveness signals shared between the event loop and the supervisor.
+#[derive(Debug)]
+pub struct Health<TS = SystemTimeClockSource> {
+    probes: Arc<Probes>,
+    liveness_timeout_ns: u64,
+    time_source: TS,
+}
+
+impl<TS: Clone> Clone for Health<TS> {
+    fn clone(&self) -> Self {
+        Self {
+            probes: self.probes.clone(),
+            liveness_timeout_ns: self.liveness_timeout_ns,
+            time_source: self.time_source.clone(),
+        }
+    }
+}
+
+impl Health {
+    /// Create health that considers the service live if the event loop has iterated within the
+    /// `liveness_timeout`.
+    pub fn new(liveness_timeout: Duration) -> Self {
+        Self::new_with_time_source(liveness_timeout, SystemTimeClockSource)
+    }
+}
+
+impl<TS: TimeSource> Health<TS> {
+    /// Create health using custom [`TimeSource`].
+    pub fn new_with_time_source(liveness_timeout: Duration, time_source: TS) -> Self {
+        Self {
+            probes: Arc::default(),
+            liveness_timeout_ns: liveness_timeout.as_nanos() as u64,
+            time_source,
+        }
+    }
+
+    /// Record the event loop iteration together with the current readiness.
+    pub fn report(&self, ready: bool) {
+        let now = self.time_source.current_time_nanos();
+        self.probes.ready.store(ready, Ordering::Relaxed);
+        self.probes.last_poll_ns.store(now.max(1), Ordering::Release);
+    }
+
+    /// Time elapsed since the last event loop iteration, `None` if it has not been reported yet.
+    pub fn last_poll_age(&self) -> Option<Duration> {
+        let last_poll_ns = self.probes.last_poll_ns.load(Ordering::Acquire);
+        if last_poll_ns == 0 {
+            return None;
+        }
+        let now = self.time_source.current_time_nanos();
+        Some(Duration::from_nanos(now.saturating_sub(last_poll_ns)))
+    }
+
+    /// Whether the event loop has iterated within the liveness timeout.
+    pub fn is_live(&self) -> bool {
+        self.last_poll_age()
+            .is_some_and(|age| age.as_nanos() as u64 <= self.liveness_timeout_ns)
+    }
+
+    /// Whether the service is live and all critical endpoints were live and synced at the last
+    /// iteration. Readiness reported by a stalled event loop is stale, hence the liveness check.
+    pub fn is_ready(&self) -> bool {
+        self.is_live() && self.probes.ready.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(unix)]
+pub use socket::ProbeSocket;
+
+#[cfg(unix)]
+mod socket {
+    use super::Health;
+    use crate::service::time::TimeSource;
+    use std::io;
+    use std::io::ErrorKind::{Interrupted, NotFound, WouldBlock};
+    use std::io::Write;
+    use std::os::unix::net::UnixListener;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+    use std::{fs, thread};
+
+    /// Non-blocking Unix domain socket that serves the [`Health`] status to supervisors.
+    #[derive(Debug)]
+    pub struct ProbeSocket {
+        listener: UnixListener,
+        path: PathBuf,
+    }
+
+    impl ProbeSocket {
+        /// Bind the socket at the `path`, a stale socket file left behind by a crashed process is
+        /// replaced. The file is removed when the socket is dropped.
+        pub fn bind(path: impl AsRef<Path>) -> io::Result<Self> {
+            let path = path.as_ref().to_path_buf();
+            if let Err(err) = fs::remove_file(&path) {
+                if err.kind() != NotFound {
+                    return Err(err);
+                }
+            }
+            let listener = UnixListener::bind(&path)?;
+            listener.set_nonblocking(true)?;
+            Ok(Self { listener, path })
+        }
+
+        /// Answer all pending probe connections with the current status, returns the number of
+        /// probes answered. Can be called from the event loop or from a dedicated thread.
+        pub fn poll<TS: TimeSource>(&self, health: &Health<TS>) -> io::Result<usize> {
+            let mut answered = 0;
+            loop {
+                match self.listener.accept() {
+                    Ok((mut stream, _)) => {
+                        // the status line fits into the socket buffer so the write never blocks,
+                        // a supervisor that has gone away is not our problem
+                        let _ = stream.write_all(status(health).as_bytes());
+                        answered += 1;
+                    }
+                    Err(err) if err.kind() == WouldBlock => return Ok(answered),
+                    Err(err) if err.kind() == Interrupted => continue,
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+
+        /// Serve the probes from the current thread until an error occurs, checking for new
+        /// connections every `interval`.
+        pub fn serve<TS: TimeSource>(&self, health: &Health<TS>, interval: Duration) -> io::Error {
+            loop {
+                if let Err(err) = self.poll(health) {
+                    return err;
+                }
+                thread::sleep(interval);
+            }
+        }
+    }
+
+    impl Drop for ProbeSocket {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    fn status<TS: TimeSource>(health: &Health<TS>) -> String {
+        let last_poll_us = match health.last_poll_age() {
+            Some(age) => age.as_micros().to_string(),
+            None => "-".to_owned(),
+        };
+        format!("live={} ready={} last_poll_us={}\n", health.is_live() as u8, health.is_ready() as u8, last_poll_us)
+    }
+}
+
+impl<S, E, C, TS, D> IOService<S, E, C, TS, D>
+where
+    S: Selector,
+    E: EndpointHealth,
+    TS: TimeSource,
+    D: DnsResolver,
+{
+    /// Whether all critical endpoints are connected and synced. Critical endpoints that are pending
+    /// (re)connection or parked make the service not ready.
+    pub fn is_ready(&self) -> bool {
+        let mut connected = self.io_nodes.values().map(|io_node| &io_node.as_parts().1.1);
+        let pending = self.pending_endpoints.iter().map(|(_, _, _, endpoint, _)| endpoint);
+        let parked = self.parked_endpoints.iter().map(|(_, endpoint)| endpoint);
+        connected.all(|endpoint| !endpoint.is_critical() || endpoint.is_synced())
+            && pending.chain(parked).all(|endpoint| !endpoint.is_critical())
+    }
+
+    /// Report the event loop iteration and the current readiness to the `health`, should be called
+    /// on every iteration of the event loop.
+    pub fn probe<HTS: TimeSource>(&self, health: &Health<HTS>) {
+        health.report(self.is_ready());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    const MILLI: u64 = 1_000_000;
+
+    #[derive(Clone, Default)]
+    struct ManualClock(Rc<Cell<u64>>);
+
+    impl TimeSource for ManualClock {
+        fn current_time_nanos(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    #[test]
+    fn should_report_liveness_and_readiness() {
+        let clock = ManualClock::default();
+        let health = Health::new_with_time_source(Duration::from_millis(100), clock.clone());
+        let supervisor = health.clone();
+        assert!(!supervisor.is_live());
+        assert!(!supervisor.is_ready());
+
+        clock.0.set(10 * MILLI);
+        health.report(false);
+        assert!(supervisor.is_live());
+        assert!(!supervisor.is_ready());
+
+        health.report(true);
+        clock.0.set(110 * MILLI);
+        assert_eq!(Some(Duration::from_millis(100)), supervisor.last_poll_age());
+        assert!(supervisor.is_ready());
+
+        // stalled event loop is neither live nor ready
+        clock.0.set(110 * MILLI + 1);
+        assert!(!supervisor.is_live());
+        assert!(!supervisor.is_ready());
+    }
+}
@@ -0,0 +1,564 @@
+//! Read batches of all websocket connections merged into a single time ordered batch.
+//!
+//! Strategies that consume several connections (venues, or shards of the same venue) and need the
+//! frames in global time order would otherwise have to merge the per connection batches themselves.
+//! [`IOService::read_all_batches`] performs a single service iteration that reads one batch from every
+//! ready connection and hands out the frames of all of them as one iterator, each tagged with the
+//! [`Handle`] of its connection and ordered by [`MergedFrame::timestamp_ns`].
+//!
+//! The timestamp is the time the batch has been read (using the [`TimeSource`] of the [`MergedBatch`])
+//! unless a [timestamp extractor](MergedBatch::with_timestamp) provides a better one, typically the
+//! event time carried by the message. Frames with equal timestamps keep their read order, so frames of a
+//! single connection are never reordered unless their timestamps say so.
+//!
+//! Frames arriving on different connections are only ordered within a single iteration. A
+//! [reordering window](MergedBatch::with_reorder_window) holds every frame for a short time (e.g.
+//! 50µs) after it has been read, so that a frame with an earlier timestamp read slightly later from
+//! another connection is still delivered ahead of it. [`IOService::read_all_batches_ts`] orders the
+//! frames by the hardware RX timestamp of the read that completed them, which is only comparable
+//! across connections if all of them are received by the same NIC (or NICs with synchronised clocks).
+//!
+//! Just like with a single websocket batch the frames are views into the websocket buffers, the
+//! returned iterator borrows the service so that the frames can not outlive the next read.
+//!
+//! ## Examples
+//! ```no_run
+//! use std::io::{Read, Write};
+//! use boomnet::service::IOService;
+//! use boomnet::service::dns::DnsResolver;
+//! use boomnet::service::endpoint::Endpoint;
+//! use boomnet::service::merge::MergedBatch;
+//! use boomnet::service::select::Selector;
+//! use boomnet::service::time::TimeSource;
+//! use boomnet::ws::{Websocket, WebsocketFrame};
+//!
+//! fn run<S, E, D, TS, T>(io_service: &mut IOService<S, E, (), TS, D>) -> std::io::Result<()>
+//! where
+//!     S: Selector<Target = Websocket<T>>,
+//!     E: Endpoint<Target = S::Target>,
+//!     D: DnsResolver,
+//!     TS: TimeSource,
+//!     T: Read + Write,
+//! {
+//!     let mut merged = MergedBatch::new();
+//!     loop {
+//!         for frame in io_service.read_all_batches(&mut merged)? {
+//!             if let WebsocketFrame::Text(_, body) = frame.frame {
+//!                 println!("{:?} {}: {}", frame.handle, frame.timestamp_ns, String::from_utf8_lossy(body));
+//!             }
+//!         }
+//!     }
+//! }
+//! ```
+
+use crate::service::dns::DnsResolver;
+use crate::service::endpoint::{Context, Endpoint, EndpointWithContext};
+use crate::service::select::Selector;
+use crate::service::time::{SystemTimeClockSource, TimeSource};
+use crate::service::{Handle, IOService};
+use crate::stream::RxTimestamped;
+use crate::ws::{Error, Websocket, WebsocketFrame};
+use std::io;
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::ptr;
+use std::time::Duration;
+use std::vec::Drain;
+
+/// Extracts the timestamp (nanoseconds) from the frame, `None` keeps the default timestamp.
+pub type TimestampExtractor = fn(&WebsocketFrame) -> Option<u64>;
+
+/// Frame of the merged batch.
+pub struct MergedFrame {
+    /// Connection the frame has been received on.
+    pub handle: Handle,
+    /// Timestamp the frames are ordered by.
+    pub timestamp_ns: u64,
+    /// Decoded frame, its payload is only valid until the next service poll.
+    pub frame: WebsocketFrame,
+}
+
+/// Frame copied out of the websocket buffer while it is held in the reordering window.
+struct HeldFrame {
+    handle: Handle,
+    timestamp_ns: u64,
+    read_ns: u64,
+    kind: FrameKind,
+    fin: bool,
+    payload: Range<usize>,
+}
+
+#[derive(Copy, Clone)]
+enum FrameKind {
+    Ping,
+    Pong,
+    Text,
+    Binary,
+    Continuation,
+    Close,
+}
+
+/// Reusable buffer the frames of all connections are merged into.
+pub struct MergedBatch<TS = SystemTimeClockSource> {
+    frames: Vec<MergedFrame>,
+    extractor: Option<TimestampExtractor>,
+    window_ns: u64,
+    held: Vec<HeldFrame>,
+    arena: Vec<u8>,
+    spare: Vec<u8>,
+    time_source: TS,
+}
+
+impl MergedBatch {
+    /// Create merged batch that orders the frames by the time their batch has been read.
+    pub fn new() -> Self {
+        Self::new_with_time_source(SystemTimeClockSource)
+    }
+}
+
+impl Default for MergedBatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<TS: TimeSource> MergedBatch<TS> {
+    /// Create merged batch using custom [`TimeSource`].
+    pub fn new_with_time_source(time_source: TS) -> Self {
+        Self {
+            frames: Vec::new(),
+            extractor: None,
+            window_ns: 0,
+            held: Vec::new(),
+            arena: Vec::new(),
+            spare: Vec::new(),
+            time_source,
+        }
+    }
+
+    /// Order the frames by the timestamp the `extractor` reads from them (e.g. the exchange event time).
+    pub fn with_timestamp(self, extractor: TimestampExtractor) -> Self {
+        Self {
+            extractor: Some(extractor),
+            ..self
+        }
+    }
+
+    /// Hold every frame for up to `window` (e.g. 50µs) after it has been read, so that a frame read
+    /// later from another connection but with an earlier timestamp is still delivered ahead of it.
+    /// Held frames are copied out of the websocket buffers and survive the disconnect of their connection.
+    pub fn with_reorder_window(self, window: Duration) -> Self {
+        Self {
+            window_ns: window.as_nanos() as u64,
+            ..self
+        }
+    }
+
+    /// Number of frames held in the reordering window.
+    pub fn held(&self) -> usize {
+        self.held.len()
+    }
+
+    /// Read single batch from the `websocket`, frames are timestamped with the time of the read.
+    fn read<S: Read + Write>(&mut self, handle: Handle, websocket: &mut Websocket<S>) -> io::Result<usize> {
+        self.read_with(|merged| {
+            let batch = websocket.read_batch()?;
+            let read_ns = merged.time_source.current_time_nanos();
+            let frames = batch.into_iter().map(|frame| frame.map(|frame| (frame, read_ns)));
+            merged.push_all(handle, frames, read_ns)
+        })
+    }
+
+    /// Read single batch from the `websocket`, frames are timestamped with the hardware RX timestamp
+    /// of the read that completed them if available.
+    fn read_ts<S>(&mut self, handle: Handle, websocket: &mut Websocket<S>) -> io::Result<usize>
+    where
+        S: Read + Write + RxTimestamped,
+    {
+        self.read_with(|merged| {
+            let batch = websocket.read_batch_ts()?;
+            let read_ns = merged.time_source.current_time_nanos();
+            let frames = batch.into_iter().map(|frame| {
+                frame.map(|(frame, rx)| match rx.hw_raw_ns {
+                    0 => (frame, read_ns),
+                    rx_ns => (frame, rx_ns),
+                })
+            });
+            merged.push_all(handle, frames, read_ns)
+        })
+    }
+
+    /// On error the frames already decoded are discarded (unless copied into the reordering window)
+    /// as the connection will be dropped together with the buffer they point to.
+    fn read_with<F>(&mut self, read: F) -> io::Result<usize>
+    where
+        F: FnOnce(&mut Self) -> io::Result<usize>,
+    {
+        let start = self.frames.len();
+        let result = read(self);
+        if result.is_err() {
+            self.frames.truncate(start);
+        }
+        result
+    }
+
+    /// Push the `frames` paired with their transport timestamp.
+    fn push_all<I>(&mut self, handle: Handle, frames: I, read_ns: u64) -> io::Result<usize>
+    where
+        I: IntoIterator<Item = Result<(WebsocketFrame, u64), Error>>,
+    {
+        let mut count = 0;
+        for frame in frames {
+            let (frame, timestamp_ns) = frame?;
+            let timestamp_ns = self
+                .extractor
+                .and_then(|extractor| extractor(&frame))
+                .unwrap_or(timestamp_ns);
+            if self.window_ns == 0 {
+                self.frames.push(MergedFrame {
+                    handle,
+                    timestamp_ns,
+                    frame,
+                });
+            } else {
+                let (kind, fin, payload) = into_parts(&frame);
+                let start = self.arena.len();
+                self.arena.extend_from_slice(payload);
+                self.held.push(HeldFrame {
+                    handle,
+                    timestamp_ns,
+                    read_ns,
+                    kind,
+                    fin,
+                    payload: start..self.arena.len(),
+                });
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Drop the payloads of the frames delivered by the previous merge, the views handed out then
+    /// are no longer valid.
+    fn compact(&mut self) {
+        self.spare.clear();
+        for held in self.held.iter_mut() {
+            let start = self.spare.len();
+            self.spare.extend_from_slice(&self.arena[held.payload.clone()]);
+            held.payload = start..self.spare.len();
+        }
+        std::mem::swap(&mut self.arena, &mut self.spare);
+    }
+
+    fn merge<F>(&mut self, disconnected: usize, is_active: F) -> MergedFrames<'_>
+    where
+        F: Fn(Handle) -> bool,
+    {
+        if disconnected > 0 {
+            self.frames.retain(|frame| is_active(frame.handle));
+        }
+        if self.window_ns > 0 {
+            self.release();
+        }
+        // stable, frames with the same timestamp keep the read order
+        self.frames.sort_by_key(|frame| frame.timestamp_ns);
+        MergedFrames {
+            frames: self.frames.drain(..),
+        }
+    }
+
+    /// Release, in the timestamp order, every held frame up to the last one whose window has expired.
+    /// Frames ahead of it are released early rather than out of order.
+    fn release(&mut self) {
+        let now = self.time_source.current_time_nanos();
+        self.held.sort_by_key(|held| held.timestamp_ns);
+        let expired = self
+            .held
+            .iter()
+            .rposition(|held| now.saturating_sub(held.read_ns) >= self.window_ns)
+            .map_or(0, |index| index + 1);
+        for held in self.held.drain(..expired) {
+            // SAFETY: the arena is not modified until the next `compact`, which happens only after
+            // the returned frames have been consumed (the borrow of the service ends)
+            let payload =
+                unsafe { &*ptr::slice_from_raw_parts(self.arena.as_ptr().add(held.payload.start), held.payload.len()) };
+            self.frames.push(MergedFrame {
+                handle: held.handle,
+                timestamp_ns: held.timestamp_ns,
+                frame: from_parts(held.kind, held.fin, payload),
+            });
+        }
+    }
+
+    /// Prepare for the next service iteration.
+    fn clear(&mut self) {
+        self.frames.clear();
+        if self.window_ns > 0 {
+            self.compact();
+        }
+    }
+}
+
+const fn into_parts(frame: &WebsocketFrame) -> (FrameKind, bool, &'static [u8]) {
+    match *frame {
+        WebsocketFrame::Ping(payload) => (FrameKind::Ping, true, payload),
+        WebsocketFrame::Pong(payload) => (FrameKind::Pong, true, payload),
+        WebsocketFrame::Text(fin, payload) => (FrameKind::Text, fin, payload),
+        WebsocketFrame::Binary(fin, payload) => (FrameKind::Binary, fin, payload),
+        WebsocketFrame::Continuation(fin, payload) => (FrameKind::Continuation, fin, payload),
+        WebsocketFrame::Close(payload) => (FrameKind::Close, true, payload),
+    }
+}
+
+const fn from_parts(kind: FrameKind, fin: bool, payload: &'static [u8]) -> WebsocketFrame {
+    match kind {
+        FrameKind::Ping => WebsocketFrame::Ping(payload),
+        FrameKind::Pong => WebsocketFrame::Pong(payload),
+        FrameKind::Text => WebsocketFrame::Text(fin, payload),
+        FrameKind::Binary => WebsocketFrame::Binary(fin, payload),
+        FrameKind::Continuation => WebsocketFrame::Continuation(fin, payload),
+        FrameKind::Close => WebsocketFrame::Close(payload),
+    }
+}
+
+/// Iterator over the frames of all connections in the timestamp order.
+pub struct MergedFrames<'a> {
+    frames: Drain<'a, MergedFrame>,
+}
+
+impl Iterator for MergedFrames<'_> {
+    type Item = MergedFrame;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.frames.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.frames.size_hint()
+    }
+}
+
+impl ExactSizeIterator for MergedFrames<'_> {}
+
+impl<S, E, TS, D> IOService<S, E, (), TS, D>
+where
+    S: Selector,
+    E: Endpoint<Target = S::Target>,
+    TS: TimeSource,
+    D: DnsResolver,
+{
+    /// Performs a single service iteration just like [`poll_once`](Self::poll_once) reading one batch
+    /// from every ready websocket and returns the frames of all of them merged into the timestamp order.
+    /// Frames of a connection that has been dropped during the iteration are discarded.
+    pub fn read_all_batches<'a, T, MTS>(&'a mut self, merged: &'a mut MergedBatch<MTS>) -> io::Result<MergedFrames<'a>>
+    where
+        S: Selector<Target = Websocket<T>>,
+        T: Read + Write,
+        MTS: TimeSource,
+    {
+        merged.clear();
+        let stats = self.poll_once_with_handle(|handle, websocket, _endpoint| merged.read(handle, websocket))?;
+        let io_nodes = &self.io_nodes;
+        Ok(merged.merge(stats.disconnected, |handle| io_nodes.contains_key(&handle.0)))
+    }
+
+    /// Same as [`read_all_batches`](Self::read_all_batches) but the frames are ordered by the hardware
+    /// RX timestamp of their batch (falling back to the read time if not available).
+    pub fn read_all_batches_ts<'a, T, MTS>(
+        &'a mut self,
+        merged: &'a mut MergedBatch<MTS>,
+    ) -> io::Result<MergedFrames<'a>>
+    where
+        S: Selector<Target = Websocket<T>>,
+        T: Read + Write + RxTimestamped,
+        MTS: TimeSource,
+    {
+        merged.clear();
+        let stats = self.poll_once_with_handle(|handle, websocket, _endpoint| merged.read_ts(handle, websocket))?;
+        let io_nodes = &self.io_nodes;
+        Ok(merged.merge(stats.disconnected, |handle| io_nodes.contains_key(&handle.0)))
+    }
+}
+
+impl<S, E, C, TS, D> IOService<S, E, C, TS, D>
+where
+    S: Selector,
+    C: Context,
+    E: EndpointWithContext<C, Target = S::Target>,
+    TS: TimeSource,
+    D: DnsResolver,
+{
+    /// Performs a single service iteration just like [`poll_once`](Self::poll_once), passing the
+    /// [`Context`], reading one batch from every ready websocket and returns the frames of all of them
+    /// merged into the timestamp order. Frames of a connection that has been dropped during the
+    /// iteration are discarded.
+    pub fn read_all_batches<'a, T, MTS>(
+        &'a mut self,
+        ctx: &mut C,
+        merged: &'a mut MergedBatch<MTS>,
+    ) -> io::Result<MergedFrames<'a>>
+    where
+        S: Selector<Target = Websocket<T>>,
+        T: Read + Write,
+        MTS: TimeSource,
+    {
+        merged.clear();
+        let stats =
+            self.poll_once_with_handle(ctx, |handle, websocket, _ctx, _endpoint| merged.read(handle, websocket))?;
+        let io_nodes = &self.io_nodes;
+        Ok(merged.merge(stats.disconnected, |handle| io_nodes.contains_key(&handle.0)))
+    }
+
+    /// Same as [`read_all_batches`](Self::read_all_batches) but the frames are ordered by the hardware
+    /// RX timestamp of their batch (falling back to the read time if not available).
+    pub fn read_all_batches_ts<'a, T, MTS>(
+        &'a mut self,
+        ctx: &mut C,
+        merged: &'a mut MergedBatch<MTS>,
+    ) -> io::Result<MergedFrames<'a>>
+    where
+        S: Selector<Target = Websocket<T>>,
+        T: Read + Write + RxTimestamped,
+        MTS: TimeSource,
+    {
+        merged.clear();
+        let stats =
+            self.poll_once_with_handle(ctx, |handle, websocket, _ctx, _endpoint| merged.read_ts(handle, websocket))?;
+        let io_nodes = &self.io_nodes;
+        Ok(merged.merge(stats.disconnected, |handle| io_nodes.contains_key(&handle.0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::IntoIOService;
+    use crate::service::select::direct::DirectSelector;
+    use crate::stream::tcp::TcpStream;
+    use crate::stream::{ConnectionInfo, ConnectionInfoProvider};
+    use std::cell::Cell;
+    use std::net::{SocketAddr, TcpListener};
+    use std::rc::Rc;
+
+    const SECOND: u64 = 1_000_000_000;
+
+    struct ManualClock(Rc<Cell<u64>>);
+
+    impl TimeSource for ManualClock {
+        fn current_time_nanos(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    struct FeedEndpoint(ConnectionInfo);
+
+    impl ConnectionInfoProvider for FeedEndpoint {
+        fn connection_info(&self) -> &ConnectionInfo {
+            &self.0
+        }
+    }
+
+    impl Endpoint for FeedEndpoint {
+        type Target = Websocket<TcpStream>;
+
+        fn create_target(&mut self, addr: SocketAddr) -> io::Result<Option<Self::Target>> {
+            let stream = self.0.clone().into_tcp_stream_with_addr(addr)?;
+            Ok(Some(Websocket::new_with_handshake_complete(stream)))
+        }
+    }
+
+    fn text(body: &[u8]) -> Vec<u8> {
+        [&[0x81, body.len() as u8][..], body].concat()
+    }
+
+    fn event_time(frame: &WebsocketFrame) -> Option<u64> {
+        match frame {
+            WebsocketFrame::Text(_, body) => std::str::from_utf8(body).ok()?.parse().ok(),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn should_merge_batches_in_timestamp_order() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let clock = Rc::new(Cell::new(SECOND));
+        let mut io_service = DirectSelector::new()
+            .unwrap()
+            .into_io_service()
+            .with_time_source(ManualClock(clock.clone()));
+        let first = io_service
+            .register(FeedEndpoint(ConnectionInfo::new("127.0.0.1", port)))
+            .unwrap();
+        let second = io_service
+            .register(FeedEndpoint(ConnectionInfo::new("127.0.0.1", port)))
+            .unwrap();
+        // endpoints are connected one at a time
+        io_service.poll(|_ws, _endpoint| Ok(())).unwrap();
+        clock.set(3 * SECOND);
+        io_service.poll(|_ws, _endpoint| Ok(())).unwrap();
+        let (mut first_peer, _) = listener.accept().unwrap();
+        let (mut second_peer, _) = listener.accept().unwrap();
+
+        first_peer.write_all(&[text(b"10"), text(b"30")].concat()).unwrap();
+        second_peer.write_all(&[text(b"20"), text(b"40")].concat()).unwrap();
+
+        let mut merged = MergedBatch::new().with_timestamp(event_time);
+        let mut received = vec![];
+        while received.len() < 4 {
+            for frame in io_service.read_all_batches(&mut merged).unwrap() {
+                received.push((frame.handle, frame.timestamp_ns));
+            }
+        }
+        assert_eq!(vec![(first, 10), (second, 20), (first, 30), (second, 40)], received);
+    }
+
+    #[test]
+    fn should_hold_frames_within_reorder_window() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let clock = Rc::new(Cell::new(SECOND));
+        let mut io_service = DirectSelector::new()
+            .unwrap()
+            .into_io_service()
+            .with_time_source(ManualClock(clock.clone()));
+        let first = io_service
+            .register(FeedEndpoint(ConnectionInfo::new("127.0.0.1", port)))
+            .unwrap();
+        let second = io_service
+            .register(FeedEndpoint(ConnectionInfo::new("127.0.0.1", port)))
+            .unwrap();
+        io_service.poll(|_ws, _endpoint| Ok(())).unwrap();
+        clock.set(3 * SECOND);
+        io_service.poll(|_ws, _endpoint| Ok(())).unwrap();
+        let (mut first_peer, _) = listener.accept().unwrap();
+        let (mut second_peer, _) = listener.accept().unwrap();
+
+        let window_clock = Rc::new(Cell::new(0));
+        let mut merged = MergedBatch::new_with_time_source(ManualClock(window_clock.clone()))
+            .with_timestamp(event_time)
+            .with_reorder_window(Duration::from_micros(50));
+
+        // the later event arrives first
+        first_peer.write_all(&text(b"30")).unwrap();
+        while merged.held() < 1 {
+            assert_eq!(0, io_service.read_all_batches(&mut merged).unwrap().len());
+        }
+        window_clock.set(10_000);
+        second_peer.write_all(&text(b"20")).unwrap();
+        while merged.held() < 2 {
+            assert_eq!(0, io_service.read_all_batches(&mut merged).unwrap().len());
+        }
+
+        // window of the first frame expires, the earlier event is released ahead of it
+        window_clock.set(50_000);
+        let received = io_service
+            .read_all_batches(&mut merged)
+            .unwrap()
+            .map(|frame| (frame.handle, frame.timestamp_ns, event_time(&frame.frame)))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(second, 20, Some(20)), (first, 30, Some(30))], received);
+        assert_eq!(0, merged.held());
+    }
+}
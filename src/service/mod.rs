@@ -10,15 +10,37 @@ use std::time::Duration;
 use crate::service::dns::{BlockingDnsResolver, DnsQuery, DnsResolver};
 use crate::service::endpoint::{Context, DisconnectReason, Endpoint, EndpointWithContext};
 use crate::service::node::IONode;
-use crate::service::select::{Selector, SelectorToken};
+use crate::service::schedule::QuietSchedule;
+use crate::service::select::{Selectable, Selector, SelectorToken};
+use crate::service::task::Tasks;
+use crate::service::tenant::{TenantQuota, TenantStats, Tenants};
 use crate::service::time::{SystemTimeClockSource, TimeSource};
 use crate::stream::ConnectionInfoProvider;
+use crate::usdt::probe;
 
+pub mod balance;
+pub mod config;
 pub mod dns;
 pub mod endpoint;
+pub mod failover;
+pub mod health;
+#[cfg(feature = "ws")]
+pub mod merge;
 mod node;
+#[cfg(feature = "ws")]
+pub mod order;
+pub mod resume;
+pub mod schedule;
 pub mod select;
+#[cfg(all(feature = "mio", feature = "ws", any(feature = "rustls", feature = "openssl")))]
+pub mod session;
+pub mod snapshot;
+pub mod standby;
+pub mod subscription;
+pub mod task;
+pub mod tenant;
 pub mod time;
+pub mod token;
 
 const ENDPOINT_CREATION_THROTTLE_NS: u64 = Duration::from_secs(1).as_nanos() as u64;
 
@@ -27,11 +49,109 @@ const ENDPOINT_CREATION_THROTTLE_NS: u64 = Duration::from_secs(1).as_nanos() as
 #[repr(transparent)]
 pub struct Handle(SelectorToken);
 
+/// Pending endpoint with its dns query, query creation time and the address to avoid (if any).
+type PendingEndpoint<Q, E> = (Handle, Q, u64, E, Option<SocketAddr>);
+
+/// Pending endpoint found to duplicate an active one and the reason it is dropped with.
+type Duplicate<Q, E> = (PendingEndpoint<Q, E>, DisconnectReason);
+
+/// Summary of a single [`IOService`] poll iteration, returned by `poll_once` so the caller can run
+/// per-iteration logic (e.g. publish a book) at a well-defined batch boundary.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PollStats {
+    /// Number of active endpoints the `action` has been invoked on.
+    pub endpoints: usize,
+    /// Total number of frames reported as processed by the `action`.
+    pub frames: usize,
+    /// Number of endpoints disconnected during this iteration (queued for reconnect).
+    pub disconnected: usize,
+}
+
+/// Endpoint first frame budget and read timeout (if any).
+type Deadlines = (Option<Duration>, Option<Duration>);
+
+/// Progress of the endpoint last words (see [`Endpoint::last_words`]) against its deadline.
+struct LastWords {
+    deadline: Duration,
+    deadline_ns: u64,
+    first: bool,
+}
+
+impl LastWords {
+    fn new<TS: TimeSource>(deadline: Option<Duration>, time_source: &TS) -> Option<LastWords> {
+        deadline.map(|deadline| Self {
+            deadline,
+            deadline_ns: time_source
+                .current_time_nanos()
+                .saturating_add(deadline.as_nanos() as u64),
+            first: true,
+        })
+    }
+
+    /// Make the next `last_words` call, returns `true` while the endpoint still has something to say.
+    fn poll<TS, F>(&mut self, time_source: &TS, last_words: F) -> bool
+    where
+        TS: TimeSource,
+        F: FnOnce(bool) -> io::Result<bool>,
+    {
+        let first = std::mem::replace(&mut self.first, false);
+        match last_words(first) {
+            Ok(true) => false,
+            Ok(false) if time_source.current_time_nanos() <= self.deadline_ns => true,
+            Ok(false) => {
+                log::warn!("last words not flushed within {:?}", self.deadline);
+                false
+            }
+            Err(err) => {
+                log::warn!("unable to send last words: {err}");
+                false
+            }
+        }
+    }
+}
+
+/// Give the endpoint up to its `deadline` to flush the last words, spinning on the current thread.
+#[cold]
+fn say_last_words<TS, F>(deadline: Option<Duration>, time_source: &TS, mut last_words: F)
+where
+    TS: TimeSource,
+    F: FnMut(bool) -> io::Result<bool>,
+{
+    if let Some(mut progress) = LastWords::new(deadline, time_source) {
+        while progress.poll(time_source, &mut last_words) {}
+    }
+}
+
+/// Run the endpoint `tasks` that are due on its `target`.
+#[inline]
+fn run_tasks<T, TS: TimeSource>(tasks: Option<&mut Tasks<T>>, target: &mut T, time_source: &TS) -> io::Result<()> {
+    match tasks {
+        Some(tasks) if !tasks.is_empty() => tasks.run_due(time_source.current_time_nanos(), target),
+        _ => Ok(()),
+    }
+}
+
+/// Apply the change of the `io_node` read pause (by the user or over the tenant read rate) to the
+/// stream and the selector.
+fn update_read_interest<S: Selector, E>(
+    selector: &mut S,
+    token: SelectorToken,
+    io_node: &mut IONode<S::Target, E>,
+    was_paused: bool,
+) -> io::Result<()> {
+    let paused = io_node.is_read_paused();
+    if paused != was_paused {
+        io_node.as_stream_mut().set_read_paused(paused);
+        selector.set_read_interest(token, io_node, !paused)?;
+    }
+    Ok(())
+}
+
 /// Handles the lifecycle of endpoints (see [`Endpoint`]), which are typically network connections.
 /// It uses `SelectService` pattern for managing asynchronous I/O operations.
 pub struct IOService<S: Selector, E, C, TS, D: DnsResolver> {
     selector: S,
-    pending_endpoints: VecDeque<(Handle, D::Query, u64, E)>,
+    pending_endpoints: VecDeque<PendingEndpoint<D::Query, E>>,
     io_nodes: HashMap<SelectorToken, IONode<S::Target, E>>,
     next_endpoint_create_time_ns: u64,
     context: PhantomData<C>,
@@ -39,6 +159,11 @@ pub struct IOService<S: Selector, E, C, TS, D: DnsResolver> {
     time_source: TS,
     dns_resolver: D,
     dns_query_timeout_ns: Option<u64>,
+    quiet_schedule: Option<QuietSchedule>,
+    quiet: bool,
+    parked_endpoints: Vec<(Handle, E)>,
+    duplicate_guard: bool,
+    tenants: Tenants,
 }
 
 /// Defines how an instance that implements `SelectService` can be transformed
@@ -72,6 +197,11 @@ impl<S: Selector, E, C, TS, D: DnsResolver> IOService<S, E, C, TS, D> {
             time_source,
             dns_resolver,
             dns_query_timeout_ns: None,
+            quiet_schedule: None,
+            quiet: false,
+            parked_endpoints: Vec::new(),
+            duplicate_guard: false,
+            tenants: Tenants::default(),
         }
     }
 
@@ -100,6 +230,69 @@ impl<S: Selector, E, C, TS, D: DnsResolver> IOService<S, E, C, TS, D> {
         }
     }
 
+    /// Specify [`QuietSchedule`] during which the service reduces polling aggressiveness and
+    /// (optionally) the number of connected endpoints.
+    pub fn with_quiet_schedule(self, quiet_schedule: QuietSchedule) -> IOService<S, E, C, TS, D> {
+        Self {
+            quiet_schedule: Some(quiet_schedule),
+            ..self
+        }
+    }
+
+    /// Never keep two live connections with the same [`Endpoint::identity`]. A pending endpoint whose
+    /// identity matches an active one is not connected, instead it is asked to `can_recreate` with
+    /// [`DisconnectReason::Duplicate`] and either retried later (`true`) or terminated (`false`).
+    pub fn with_duplicate_guard(self) -> IOService<S, E, C, TS, D> {
+        Self {
+            duplicate_guard: true,
+            ..self
+        }
+    }
+
+    /// Enforce the [`TenantQuota`] on the endpoints of the `tenant` (see [`Endpoint::tenant`]), so a
+    /// misbehaving tenant can not starve the others sharing the service.
+    pub fn with_tenant_quota(mut self, tenant: &str, quota: TenantQuota) -> IOService<S, E, C, TS, D> {
+        self.tenants.set_quota(tenant.to_owned(), quota);
+        self
+    }
+
+    /// Statistics of the `tenant`, `None` if it has neither a quota nor any endpoint connected yet.
+    pub fn tenant_stats(&self, tenant: &str) -> Option<TenantStats> {
+        self.tenants.position(tenant).map(|index| self.stats_of(index))
+    }
+
+    /// Return iterator over the statistics of all known tenants.
+    pub fn tenants(&self) -> impl Iterator<Item = (&str, TenantStats)> {
+        (0..self.tenants.len()).map(|index| (self.tenants.get(index).name(), self.stats_of(index)))
+    }
+
+    fn stats_of(&self, tenant: usize) -> TenantStats {
+        let connections = self
+            .io_nodes
+            .values()
+            .filter(|io_node| io_node.tenant == Some(tenant))
+            .count();
+        TenantStats {
+            connections,
+            ..self.tenants.get(tenant).stats()
+        }
+    }
+
+    /// Checks if the service is currently in the scheduled quiet period.
+    pub const fn is_quiet(&self) -> bool {
+        self.quiet
+    }
+
+    /// Selector driving the service, e.g. to read its wakeup statistics.
+    pub const fn selector(&self) -> &S {
+        &self.selector
+    }
+
+    /// Mutable reference to the selector driving the service.
+    pub const fn selector_mut(&mut self) -> &mut S {
+        &mut self.selector
+    }
+
     /// Specify custom [`TimeSource`] instead of the default system time source.
     pub fn with_time_source<T: TimeSource>(self, time_source: T) -> IOService<S, E, C, T, D> {
         IOService {
@@ -112,6 +305,11 @@ impl<S: Selector, E, C, TS, D: DnsResolver> IOService<S, E, C, TS, D> {
             selector: self.selector,
             dns_resolver: self.dns_resolver,
             dns_query_timeout_ns: self.dns_query_timeout_ns,
+            quiet_schedule: self.quiet_schedule,
+            quiet: false,
+            parked_endpoints: Default::default(),
+            duplicate_guard: self.duplicate_guard,
+            tenants: self.tenants,
         }
     }
 
@@ -127,6 +325,11 @@ impl<S: Selector, E, C, TS, D: DnsResolver> IOService<S, E, C, TS, D> {
             selector: self.selector,
             dns_resolver,
             dns_query_timeout_ns: self.dns_query_timeout_ns,
+            quiet_schedule: self.quiet_schedule,
+            quiet: false,
+            parked_endpoints: Default::default(),
+            duplicate_guard: self.duplicate_guard,
+            tenants: self.tenants,
         }
     }
 
@@ -138,9 +341,10 @@ impl<S: Selector, E, C, TS, D: DnsResolver> IOService<S, E, C, TS, D> {
     {
         let handle = Handle(self.selector.next_token());
         let info = endpoint.connection_info();
-        let query = self.dns_resolver.new_query(info.host(), info.port())?;
+        let (host, port) = info.connect_address();
+        let query = self.dns_resolver.new_query(host, port)?;
         let now = self.time_source.current_time_nanos();
-        self.pending_endpoints.push_back((handle, query, now, endpoint));
+        self.pending_endpoints.push_back((handle, query, now, endpoint, None));
         Ok(handle)
     }
 
@@ -155,13 +359,15 @@ impl<S: Selector, E, C, TS, D: DnsResolver> IOService<S, E, C, TS, D> {
         let handle = Handle(self.selector.next_token());
         let endpoint = endpoint_factory(handle)?;
         let info = endpoint.connection_info();
-        let query = self.dns_resolver.new_query(info.host(), info.port())?;
+        let (host, port) = info.connect_address();
+        let query = self.dns_resolver.new_query(host, port)?;
         let now = self.time_source.current_time_nanos();
-        self.pending_endpoints.push_back((handle, query, now, endpoint));
+        self.pending_endpoints.push_back((handle, query, now, endpoint, None));
         Ok(handle)
     }
 
-    /// Deregister [`Endpoint`] with the service based on a handle.
+    /// Deregister [`Endpoint`] with the service based on a handle. An active connection is dropped
+    /// straight away without the endpoint last words, see `shutdown` for the graceful alternative.
     pub fn deregister(&mut self, handle: Handle) -> Option<E> {
         match self.io_nodes.remove(&handle.0) {
             Some(io_node) => Some(io_node.into_endpoint().1),
@@ -176,14 +382,62 @@ impl<S: Selector, E, C, TS, D: DnsResolver> IOService<S, E, C, TS, D> {
                 if let Some(index_to_remove) = index_to_remove {
                     self.pending_endpoints
                         .remove(index_to_remove)
-                        .map(|(_, _, _, endpoint)| endpoint)
+                        .map(|(_, _, _, endpoint, _)| endpoint)
                 } else {
-                    None
+                    let index = self.parked_endpoints.iter().position(|(parked, _)| *parked == handle)?;
+                    Some(self.parked_endpoints.swap_remove(index).1)
                 }
             }
         }
     }
 
+    /// Stop reading from the active endpoint connection while keeping it alive, so a consumer that
+    /// falls behind applies backpressure at the TCP level (the kernel receive window closes) instead
+    /// of buffering in userspace. The endpoint is still polled, frames already read are decoded and it
+    /// can keep sending. The [read timeout](crate::stream::ConnectionInfo::with_read_timeout) is not
+    /// enforced while paused.
+    /// Returns `false` if the endpoint is not active, a new connection always starts reading.
+    pub fn pause_reading(&mut self, handle: Handle) -> io::Result<bool> {
+        self.set_read_paused(handle, true)
+    }
+
+    /// Restart reading from the endpoint connection paused by [`pause_reading`](Self::pause_reading).
+    /// Returns `false` if the endpoint is not active.
+    pub fn resume_reading(&mut self, handle: Handle) -> io::Result<bool>
+    where
+        TS: TimeSource,
+    {
+        let now = self.time_source.current_time_nanos();
+        if let Some(io_node) = self.io_nodes.get_mut(&handle.0) {
+            if io_node.read_paused && io_node.read_deadline_ns != u64::MAX {
+                // nothing could have been read while paused, restart the read timeout
+                io_node.read_deadline_ns = now.saturating_add(io_node.read_timeout.as_nanos() as u64);
+            }
+        }
+        self.set_read_paused(handle, false)
+    }
+
+    /// Checks if reading from the active endpoint connection is paused, either by the user or over
+    /// the [tenant read rate](TenantQuota::with_max_read_rate).
+    pub fn is_reading_paused(&self, handle: Handle) -> bool {
+        self.io_nodes
+            .get(&handle.0)
+            .is_some_and(|io_node| io_node.is_read_paused())
+    }
+
+    fn set_read_paused(&mut self, handle: Handle, paused: bool) -> io::Result<bool> {
+        let Some(io_node) = self.io_nodes.get_mut(&handle.0) else {
+            return Ok(false);
+        };
+        if io_node.read_paused != paused {
+            let was_paused = io_node.is_read_paused();
+            io_node.read_paused = paused;
+            update_read_interest(&mut self.selector, handle.0, io_node, was_paused)?;
+            log::debug!("reading from endpoint {handle:?} {}", if paused { "paused" } else { "resumed" });
+        }
+        Ok(true)
+    }
+
     /// Return iterator over active endpoints, additionally exposing handle and the stream.
     #[inline]
     pub fn iter(&self) -> impl Iterator<Item = (Handle, &S::Target, &E)> {
@@ -207,11 +461,16 @@ impl<S: Selector, E, C, TS, D: DnsResolver> IOService<S, E, C, TS, D> {
     pub fn pending(&self) -> impl Iterator<Item = (&Handle, &E)> {
         self.pending_endpoints
             .iter()
-            .map(|(handle, _, _, endpoint)| (handle, endpoint))
+            .map(|(handle, _, _, endpoint, _)| (handle, endpoint))
     }
 
     #[inline]
-    fn resolve_dns(&self, query: &mut impl DnsQuery, created_time_ns: u64) -> io::Result<Option<SocketAddr>>
+    fn resolve_dns(
+        &self,
+        query: &mut impl DnsQuery,
+        created_time_ns: u64,
+        avoid_addr: Option<SocketAddr>,
+    ) -> io::Result<Option<SocketAddr>>
     where
         TS: TimeSource,
     {
@@ -224,10 +483,15 @@ impl<S: Selector, E, C, TS, D: DnsResolver> IOService<S, E, C, TS, D> {
         }
         match query.poll() {
             Ok(addrs) => {
-                let addr = addrs
-                    .into_iter()
-                    .next()
-                    .ok_or_else(|| io::Error::other("dns resolution dio not return any address"))?;
+                // prefer address other than the one to avoid (if there is any)
+                let mut first = None;
+                for addr in addrs {
+                    if Some(addr) != avoid_addr {
+                        return Ok(Some(addr));
+                    }
+                    first.get_or_insert(addr);
+                }
+                let addr = first.ok_or_else(|| io::Error::other("dns resolution dio not return any address"))?;
                 Ok(Some(addr))
             }
             Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(None),
@@ -235,40 +499,216 @@ impl<S: Selector, E, C, TS, D: DnsResolver> IOService<S, E, C, TS, D> {
         }
     }
 
+    /// Enter or leave the quiet period, `pause` is invoked on every endpoint about to be parked and
+    /// should say its last words and return if it can be recreated.
+    #[cold]
+    fn check_quiet_schedule<F>(&mut self, mut pause: F) -> io::Result<()>
+    where
+        E: ConnectionInfoProvider,
+        TS: TimeSource,
+        F: FnMut(&mut S::Target, &mut E, &TS) -> bool,
+    {
+        let Some(schedule) = self.quiet_schedule.as_mut() else {
+            return Ok(());
+        };
+        let now = self.time_source.current_time_nanos();
+        match schedule.poll(now) {
+            Some(true) if !self.quiet => {
+                self.quiet = true;
+                self.selector.set_park_timeout(Some(schedule.park_timeout()));
+                if let Some(maintenance_connections) = schedule.maintenance_connections() {
+                    // keep the earliest registered endpoints connected
+                    let mut tokens = self.io_nodes.keys().copied().collect::<Vec<_>>();
+                    tokens.sort_unstable();
+                    for token in tokens.into_iter().skip(maintenance_connections) {
+                        if let Some(mut io_node) = self.io_nodes.remove(&token) {
+                            let (target, (_, endpoint)) = io_node.as_parts_mut();
+                            let can_recreate = pause(target, endpoint, &self.time_source);
+                            self.selector.unregister(&mut io_node)?;
+                            if !can_recreate {
+                                panic!("unrecoverable error when pausing endpoint");
+                            }
+                            self.parked_endpoints.push(io_node.into_endpoint());
+                        }
+                    }
+                    let pending = maintenance_connections.saturating_sub(self.io_nodes.len());
+                    while self.pending_endpoints.len() > pending {
+                        if let Some((handle, _, _, endpoint, _)) = self.pending_endpoints.pop_back() {
+                            self.parked_endpoints.push((handle, endpoint));
+                        }
+                    }
+                }
+                log::info!("entered quiet period, {} endpoint(s) paused", self.parked_endpoints.len());
+            }
+            Some(false) if self.quiet => {
+                self.quiet = false;
+                self.selector.set_park_timeout(None);
+                log::info!("left quiet period, resuming {} endpoint(s)", self.parked_endpoints.len());
+                self.parked_endpoints.sort_unstable_by_key(|(handle, _)| *handle);
+                for (handle, endpoint) in self.parked_endpoints.drain(..) {
+                    let info = endpoint.connection_info();
+                    let (host, port) = info.connect_address();
+                    let query = self.dns_resolver.new_query(host, port)?;
+                    self.pending_endpoints.push_back((handle, query, now, endpoint, None));
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Drive the last words of all active endpoints concurrently (each within its own deadline),
+    /// then disconnect them and return every endpoint: active, pending and paused (in this order).
+    #[cold]
+    fn shutdown_with<L, W>(&mut self, mut deadline: L, mut last_words: W) -> Vec<E>
+    where
+        TS: TimeSource,
+        L: FnMut(&E) -> Option<Duration>,
+        W: FnMut(&mut S::Target, &mut E, bool) -> io::Result<bool>,
+    {
+        let mut io_nodes = self.io_nodes.drain().map(|(_, io_node)| io_node).collect::<Vec<_>>();
+        io_nodes.sort_unstable_by_key(|io_node| io_node.as_endpoint().0);
+        let mut progress = io_nodes
+            .iter()
+            .map(|io_node| LastWords::new(deadline(&io_node.as_endpoint().1), &self.time_source))
+            .collect::<Vec<_>>();
+        while progress.iter().any(Option::is_some) {
+            for (io_node, progress) in io_nodes.iter_mut().zip(progress.iter_mut()) {
+                if let Some(words) = progress {
+                    let (target, (_, endpoint)) = io_node.as_parts_mut();
+                    if !words.poll(&self.time_source, |first| last_words(target, endpoint, first)) {
+                        *progress = None;
+                    }
+                }
+            }
+        }
+
+        let mut endpoints =
+            Vec::with_capacity(io_nodes.len() + self.pending_endpoints.len() + self.parked_endpoints.len());
+        for mut io_node in io_nodes {
+            if let Err(err) = self.selector.unregister(&mut io_node) {
+                log::warn!("unable to unregister endpoint on shutdown: {err}");
+            }
+            endpoints.push(io_node.into_endpoint().1);
+        }
+        endpoints.extend(self.pending_endpoints.drain(..).map(|(_, _, _, endpoint, _)| endpoint));
+        self.parked_endpoints.sort_unstable_by_key(|(handle, _)| *handle);
+        endpoints.extend(self.parked_endpoints.drain(..).map(|(_, endpoint)| endpoint));
+        endpoints
+    }
+
+    /// Connect the next pending endpoint (if due), a duplicate of an active endpoint (see
+    /// [`with_duplicate_guard`](Self::with_duplicate_guard)) is returned instead for the caller to settle.
+    /// An endpoint of a tenant at its connection limit stays pending.
     #[cold]
-    fn check_pending_endpoints<F>(&mut self, create_target: F) -> io::Result<()>
+    fn check_pending_endpoints<F>(
+        &mut self,
+        identity_of: fn(&E) -> Option<&str>,
+        tenant_of: fn(&E) -> Option<&str>,
+        create_target: F,
+    ) -> io::Result<Option<Duplicate<D::Query, E>>>
     where
         E: ConnectionInfoProvider,
         TS: TimeSource,
-        F: FnOnce(&mut E, SocketAddr) -> io::Result<Option<<S as Selector>::Target>>,
+        F: FnOnce(&mut E, SocketAddr) -> io::Result<Option<(<S as Selector>::Target, Deadlines)>>,
     {
+        let mut duplicate = None;
         let current_time_ns = self.time_source.current_time_nanos();
         if current_time_ns > self.next_endpoint_create_time_ns {
-            if let Some((handle, mut query, query_time_ns, mut endpoint)) = self.pending_endpoints.pop_front() {
-                if let Some(addr) = self.resolve_dns(&mut query, query_time_ns)? {
+            if let Some((handle, mut query, query_time_ns, mut endpoint, avoid_addr)) =
+                self.pending_endpoints.pop_front()
+            {
+                let duplicate_of = match identity_of(&endpoint) {
+                    Some(identity) if self.duplicate_guard => self
+                        .io_nodes
+                        .values()
+                        .any(|io_node| identity_of(&io_node.as_endpoint().1) == Some(identity))
+                        .then(|| DisconnectReason::duplicate(identity)),
+                    _ => None,
+                };
+                let tenant = tenant_of(&endpoint).map(|tenant| self.tenants.index_of(tenant));
+                let deferred = duplicate_of.is_none()
+                    && tenant.is_some_and(|tenant| {
+                        let connections = self
+                            .io_nodes
+                            .values()
+                            .filter(|io_node| io_node.tenant == Some(tenant))
+                            .count();
+                        !self.tenants.get_mut(tenant).can_connect(connections)
+                    });
+                if let Some(reason) = duplicate_of {
+                    log::warn!("endpoint {handle:?} not connected: {reason}");
+                    duplicate = Some(((handle, query, query_time_ns, endpoint, avoid_addr), reason));
+                } else if deferred {
+                    log::debug!("endpoint {handle:?} not connected: tenant connection limit reached");
+                    self.pending_endpoints
+                        .push_back((handle, query, query_time_ns, endpoint, avoid_addr))
+                } else if let Some(addr) = self.resolve_dns(&mut query, query_time_ns, avoid_addr)? {
                     match create_target(&mut endpoint, addr)? {
-                        Some(stream) => {
+                        Some((stream, (first_frame_budget, read_timeout))) => {
                             let ttl = self.auto_disconnect.as_ref().map(|auto_disconnect| auto_disconnect());
-                            let mut io_node = IONode::new(stream, handle, endpoint, ttl, &self.time_source, addr);
+                            let mut io_node = IONode::new(stream, handle, endpoint, ttl, &self.time_source, addr)
+                                .with_first_frame_budget(first_frame_budget, &self.time_source)
+                                .with_read_timeout(read_timeout, &self.time_source)
+                                .with_tenant(tenant);
                             self.selector.register(handle.0, &mut io_node)?;
+                            if tenant.is_some_and(|tenant| self.tenants.get_mut(tenant).is_read_throttled()) {
+                                io_node.read_throttled = true;
+                                update_read_interest(&mut self.selector, handle.0, &mut io_node, false)?;
+                            }
                             self.io_nodes.insert(handle.0, io_node);
                         }
                         None => {
                             // request new dns query
                             let info = endpoint.connection_info();
-                            let query = self.dns_resolver.new_query(info.host(), info.port())?;
+                            let (host, port) = info.connect_address();
+                            let query = self.dns_resolver.new_query(host, port)?;
                             let now = self.time_source.current_time_nanos();
-                            self.pending_endpoints.push_back((handle, query, now, endpoint))
+                            self.pending_endpoints
+                                .push_back((handle, query, now, endpoint, avoid_addr))
                         }
                     }
                 } else {
                     self.pending_endpoints
-                        .push_back((handle, query, query_time_ns, endpoint))
+                        .push_back((handle, query, query_time_ns, endpoint, avoid_addr))
                 }
             }
             self.next_endpoint_create_time_ns = current_time_ns + ENDPOINT_CREATION_THROTTLE_NS;
         }
-        Ok(())
+        Ok(duplicate)
+    }
+
+    /// Pause reading from all endpoints of the tenants over their read rate and resume the endpoints
+    /// of the tenants back within it, the dispatch is suspended for the tenants over their write rate.
+    fn check_tenant_rates(&mut self) -> io::Result<()>
+    where
+        TS: TimeSource,
+    {
+        let now = self.time_source.current_time_nanos();
+        self.tenants.poll_rates(now, |tenant, throttled| {
+            for (token, io_node) in self.io_nodes.iter_mut() {
+                if io_node.tenant == Some(tenant) {
+                    let was_paused = io_node.is_read_paused();
+                    io_node.read_throttled = throttled;
+                    update_read_interest(&mut self.selector, *token, io_node, was_paused)?;
+                    if was_paused && !io_node.is_read_paused() && io_node.read_deadline_ns != u64::MAX {
+                        // nothing could have been read while throttled, restart the read timeout
+                        io_node.read_deadline_ns = now.saturating_add(io_node.read_timeout.as_nanos() as u64);
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Retry the duplicate endpoint later or terminate it, as decided by its `can_recreate`.
+    #[cold]
+    fn settle_duplicate(&mut self, duplicate: PendingEndpoint<D::Query, E>, retry: bool) {
+        if retry {
+            self.pending_endpoints.push_back(duplicate);
+        } else {
+            log::warn!("endpoint {:?} terminated as duplicate", duplicate.0);
+        }
     }
 }
 
@@ -288,9 +728,69 @@ where
     where
         F: FnMut(&mut E::Target, &mut E) -> io::Result<()>,
     {
+        self.poll_once(|target, endpoint| action(target, endpoint).map(|()| 0))
+            .map(|_| ())
+    }
+
+    /// Performs a single service iteration just like [`poll`](Self::poll), but the `action` reports
+    /// how many frames it has processed and the iteration is summarised as [`PollStats`]. Returning
+    /// from this method is the batch boundary at which per-iteration logic can run.
+    ///
+    /// ## Examples
+    /// ```no_run
+    /// use boomnet::service::endpoint::Endpoint;
+    /// use boomnet::service::IOService;
+    /// use boomnet::service::dns::DnsResolver;
+    /// use boomnet::service::select::Selector;
+    /// use boomnet::service::time::TimeSource;
+    ///
+    /// fn run<S, E, D, TS>(io_service: &mut IOService<S, E, (), TS, D>) -> std::io::Result<()>
+    /// where
+    ///     S: Selector,
+    ///     E: Endpoint<Target = S::Target>,
+    ///     D: DnsResolver,
+    ///     TS: TimeSource,
+    /// {
+    ///     loop {
+    ///         let stats = io_service.poll_once(|_target, _endpoint| Ok(0))?;
+    ///         if stats.frames > 0 {
+    ///             // publish book, recalculate signals, etc.
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn poll_once<F>(&mut self, mut action: F) -> io::Result<PollStats>
+    where
+        F: FnMut(&mut E::Target, &mut E) -> io::Result<usize>,
+    {
+        self.poll_once_with_handle(|_handle, target, endpoint| action(target, endpoint))
+    }
+
+    /// Single service iteration passing the `action` the handle of every polled endpoint.
+    fn poll_once_with_handle<F>(&mut self, mut action: F) -> io::Result<PollStats>
+    where
+        F: FnMut(Handle, &mut E::Target, &mut E) -> io::Result<usize>,
+    {
+        let mut stats = PollStats::default();
+
+        // enter or leave scheduled quiet period
+        if self.quiet_schedule.is_some() {
+            self.check_quiet_schedule(|target, endpoint, time_source| {
+                say_last_words(endpoint.last_words_deadline(), time_source, |first| endpoint.last_words(target, first));
+                endpoint.can_recreate(DisconnectReason::Maintenance)
+            })?;
+        }
+
         // check for pending endpoints (one at a time & throttled)
         if !self.pending_endpoints.is_empty() {
-            self.check_pending_endpoints(|endpoint, addr| endpoint.create_target(addr))?;
+            let duplicate = self.check_pending_endpoints(E::identity, E::tenant, |endpoint, addr| {
+                let deadlines = (endpoint.first_frame_budget(), endpoint.connection_info().read_timeout());
+                Ok(endpoint.create_target(addr)?.map(|target| (target, deadlines)))
+            })?;
+            if let Some((mut duplicate, reason)) = duplicate {
+                let retry = duplicate.3.can_recreate(reason);
+                self.settle_duplicate(duplicate, retry);
+            }
         }
 
         // check for readiness events
@@ -304,13 +804,20 @@ where
                 if force_disconnect {
                     // check if we really have to disconnect
                     return if io_node.as_endpoint_mut().1.can_auto_disconnect() {
+                        stats.disconnected += 1;
+                        self.tenants.record_disconnect(io_node.tenant);
+                        let (target, (_, endpoint)) = io_node.as_parts_mut();
+                        say_last_words(endpoint.last_words_deadline(), &self.time_source, |first| {
+                            endpoint.last_words(target, first)
+                        });
                         self.selector.unregister(io_node).unwrap();
                         let (handle, mut endpoint) = io_node.endpoint.take().unwrap();
                         if endpoint.can_recreate(DisconnectReason::auto_disconnect(io_node.ttl)) {
                             let info = endpoint.connection_info();
-                            let query = self.dns_resolver.new_query(info.host(), info.port()).unwrap();
+                            let (host, port) = info.connect_address();
+                            let query = self.dns_resolver.new_query(host, port).unwrap();
                             let now = self.time_source.current_time_nanos();
-                            self.pending_endpoints.push_back((handle, query, now, endpoint));
+                            self.pending_endpoints.push_back((handle, query, now, endpoint, None));
                         } else {
                             panic!("unrecoverable error when polling endpoint");
                         }
@@ -328,15 +835,57 @@ where
 
         // poll endpoints
         self.io_nodes.retain(|_token, io_node| {
-            let (target, (_, endpoint)) = io_node.as_parts_mut();
-            if let Err(err) = action(target, endpoint) {
+            let (target, (handle, endpoint)) = io_node.as_parts_mut();
+            stats.endpoints += 1;
+            let mut result = run_tasks(endpoint.tasks(), target, &self.time_source)
+                .and_then(|()| action(*handle, target, endpoint))
+                .inspect(|frames| stats.frames += frames)
+                .map_err(DisconnectReason::other);
+            if let Some(tenant) = io_node.tenant {
+                let frames = *result.as_ref().unwrap_or(&0);
+                let bytes_read = io_node.stream.take_bytes_read();
+                self.tenants.get_mut(tenant).record_read(bytes_read, frames);
+                self.tenants
+                    .get_mut(tenant)
+                    .record_write(io_node.stream.take_bytes_written());
+            }
+            // enforce first frame latency budget (if any)
+            if result.is_ok() && io_node.first_frame_deadline_ns != u64::MAX {
+                if io_node.as_endpoint().1.has_first_frame() {
+                    io_node.first_frame_deadline_ns = u64::MAX;
+                } else if self.time_source.current_time_nanos() > io_node.first_frame_deadline_ns {
+                    result = Err(DisconnectReason::first_frame_timeout(io_node.first_frame_budget));
+                }
+            }
+            // enforce read timeout (if any)
+            if result.is_ok() && io_node.read_deadline_ns != u64::MAX && !io_node.is_read_paused() {
+                let now = self.time_source.current_time_nanos();
+                if io_node.stream.take_read_activity() {
+                    io_node.read_deadline_ns = now.saturating_add(io_node.read_timeout.as_nanos() as u64);
+                } else if now > io_node.read_deadline_ns {
+                    result = Err(DisconnectReason::read_timeout(io_node.read_timeout));
+                }
+            }
+            if let Err(reason) = result {
+                stats.disconnected += 1;
+                self.tenants.record_disconnect(io_node.tenant);
+                if reason.is_deliberate() {
+                    let (target, (_, endpoint)) = io_node.as_parts_mut();
+                    say_last_words(endpoint.last_words_deadline(), &self.time_source, |first| {
+                        endpoint.last_words(target, first)
+                    });
+                }
                 self.selector.unregister(io_node).unwrap();
                 let (handle, mut endpoint) = io_node.endpoint.take().unwrap();
-                if endpoint.can_recreate(DisconnectReason::other(err)) {
+                // retry on the next address if the first frame did not arrive in time
+                let avoid_addr = reason.is_first_frame_timeout().then_some(io_node.addr);
+                if endpoint.can_recreate(reason) {
                     let info = endpoint.connection_info();
-                    let query = self.dns_resolver.new_query(info.host(), info.port()).unwrap();
+                    let (host, port) = info.connect_address();
+                    let query = self.dns_resolver.new_query(host, port).unwrap();
                     let now = self.time_source.current_time_nanos();
-                    self.pending_endpoints.push_back((handle, query, now, endpoint));
+                    self.pending_endpoints
+                        .push_back((handle, query, now, endpoint, avoid_addr));
                 } else {
                     panic!("unrecoverable error when polling endpoint");
                 }
@@ -345,18 +894,42 @@ where
             true
         });
 
-        Ok(())
+        // throttle or release the tenants over (or back within) their read and write rate
+        if !self.tenants.is_empty() {
+            self.check_tenant_rates()?;
+        }
+
+        probe!(poll_done, stats.endpoints, stats.frames, stats.disconnected);
+        Ok(stats)
+    }
+
+    /// Gracefully stop the service: every active endpoint is given up to its
+    /// [`last_words_deadline`](Endpoint::last_words_deadline) to flush the [`last_words`](Endpoint::last_words)
+    /// before the connection is dropped. All registered endpoints (active, pending and paused) are
+    /// returned and the service can be reused.
+    pub fn shutdown(&mut self) -> Vec<E> {
+        self.shutdown_with(
+            |endpoint| endpoint.last_words_deadline(),
+            |target, endpoint, first| endpoint.last_words(target, first),
+        )
     }
 
     /// Dispatch command to an active endpoint using `handle` and provided `action`. If the
     /// endpoint is currently active `Ok(Some(...))` will be returned and the provided `action` invoked,
-    /// otherwise this method will return `Ok(None)` and no `action` will be invoked.
+    /// otherwise this method will return `Ok(None)` and no `action` will be invoked. Above the
+    /// [tenant dispatch rate](TenantQuota::with_max_dispatch_rate) or the
+    /// [tenant write rate](TenantQuota::with_max_write_rate) the `action` is not invoked either and
+    /// `WouldBlock` error is returned.
     pub fn dispatch<F, T>(&mut self, handle: Handle, mut action: F) -> io::Result<Option<T>>
     where
         F: FnMut(&mut E::Target, &mut E) -> std::io::Result<T>,
     {
         match self.io_nodes.get_mut(&handle.0) {
             Some(io_node) => {
+                if let Some(tenant) = io_node.tenant {
+                    let now = self.time_source.current_time_nanos();
+                    self.tenants.get_mut(tenant).try_dispatch(now)?;
+                }
                 let (stream, (_, endpoint)) = io_node.as_parts_mut();
                 let result = action(stream, endpoint)?;
                 Ok(Some(result))
@@ -383,9 +956,48 @@ where
     where
         F: FnMut(&mut E::Target, &mut C, &mut E) -> io::Result<()>,
     {
+        self.poll_once(ctx, |target, ctx, endpoint| action(target, ctx, endpoint).map(|()| 0))
+            .map(|_| ())
+    }
+
+    /// Performs a single service iteration just like [`poll`](Self::poll), passing the [`Context`],
+    /// but the `action` reports how many frames it has processed and the iteration is summarised as
+    /// [`PollStats`]. Returning from this method is the batch boundary at which per-iteration logic
+    /// can run.
+    pub fn poll_once<F>(&mut self, ctx: &mut C, mut action: F) -> io::Result<PollStats>
+    where
+        F: FnMut(&mut E::Target, &mut C, &mut E) -> io::Result<usize>,
+    {
+        self.poll_once_with_handle(ctx, |_handle, target, ctx, endpoint| action(target, ctx, endpoint))
+    }
+
+    /// Single service iteration passing the `action` the handle of every polled endpoint.
+    fn poll_once_with_handle<F>(&mut self, ctx: &mut C, mut action: F) -> io::Result<PollStats>
+    where
+        F: FnMut(Handle, &mut E::Target, &mut C, &mut E) -> io::Result<usize>,
+    {
+        let mut stats = PollStats::default();
+
+        // enter or leave scheduled quiet period
+        if self.quiet_schedule.is_some() {
+            self.check_quiet_schedule(|target, endpoint, time_source| {
+                say_last_words(endpoint.last_words_deadline(), time_source, |first| {
+                    endpoint.last_words(target, first, ctx)
+                });
+                endpoint.can_recreate(DisconnectReason::Maintenance, ctx)
+            })?;
+        }
+
         // check for pending endpoints (one at a time & throttled)
         if !self.pending_endpoints.is_empty() {
-            self.check_pending_endpoints(|endpoint, addr| endpoint.create_target(addr, ctx))?;
+            let duplicate = self.check_pending_endpoints(E::identity, E::tenant, |endpoint, addr| {
+                let deadlines = (endpoint.first_frame_budget(), endpoint.connection_info().read_timeout());
+                Ok(endpoint.create_target(addr, ctx)?.map(|target| (target, deadlines)))
+            })?;
+            if let Some((mut duplicate, reason)) = duplicate {
+                let retry = duplicate.3.can_recreate(reason, ctx);
+                self.settle_duplicate(duplicate, retry);
+            }
         }
 
         // check for readiness events
@@ -399,13 +1011,20 @@ where
                 if force_disconnect {
                     // check if we really have to disconnect
                     return if io_node.as_endpoint_mut().1.can_auto_disconnect(ctx) {
+                        stats.disconnected += 1;
+                        self.tenants.record_disconnect(io_node.tenant);
+                        let (target, (_, endpoint)) = io_node.as_parts_mut();
+                        say_last_words(endpoint.last_words_deadline(), &self.time_source, |first| {
+                            endpoint.last_words(target, first, ctx)
+                        });
                         self.selector.unregister(io_node).unwrap();
                         let (handle, mut endpoint) = io_node.endpoint.take().unwrap();
                         if endpoint.can_recreate(DisconnectReason::auto_disconnect(io_node.ttl), ctx) {
                             let info = endpoint.connection_info();
-                            let query = self.dns_resolver.new_query(info.host(), info.port()).unwrap();
+                            let (host, port) = info.connect_address();
+                            let query = self.dns_resolver.new_query(host, port).unwrap();
                             let now = self.time_source.current_time_nanos();
-                            self.pending_endpoints.push_back((handle, query, now, endpoint));
+                            self.pending_endpoints.push_back((handle, query, now, endpoint, None));
                         } else {
                             panic!("unrecoverable error when polling endpoint");
                         }
@@ -423,15 +1042,57 @@ where
 
         // poll endpoints
         self.io_nodes.retain(|_token, io_node| {
-            let (target, (_, endpoint)) = io_node.as_parts_mut();
-            if let Err(err) = action(target, ctx, endpoint) {
+            let (target, (handle, endpoint)) = io_node.as_parts_mut();
+            stats.endpoints += 1;
+            let mut result = run_tasks(endpoint.tasks(), target, &self.time_source)
+                .and_then(|()| action(*handle, target, ctx, endpoint))
+                .inspect(|frames| stats.frames += frames)
+                .map_err(DisconnectReason::other);
+            if let Some(tenant) = io_node.tenant {
+                let frames = *result.as_ref().unwrap_or(&0);
+                let bytes_read = io_node.stream.take_bytes_read();
+                self.tenants.get_mut(tenant).record_read(bytes_read, frames);
+                self.tenants
+                    .get_mut(tenant)
+                    .record_write(io_node.stream.take_bytes_written());
+            }
+            // enforce first frame latency budget (if any)
+            if result.is_ok() && io_node.first_frame_deadline_ns != u64::MAX {
+                if io_node.as_endpoint().1.has_first_frame() {
+                    io_node.first_frame_deadline_ns = u64::MAX;
+                } else if self.time_source.current_time_nanos() > io_node.first_frame_deadline_ns {
+                    result = Err(DisconnectReason::first_frame_timeout(io_node.first_frame_budget));
+                }
+            }
+            // enforce read timeout (if any)
+            if result.is_ok() && io_node.read_deadline_ns != u64::MAX && !io_node.is_read_paused() {
+                let now = self.time_source.current_time_nanos();
+                if io_node.stream.take_read_activity() {
+                    io_node.read_deadline_ns = now.saturating_add(io_node.read_timeout.as_nanos() as u64);
+                } else if now > io_node.read_deadline_ns {
+                    result = Err(DisconnectReason::read_timeout(io_node.read_timeout));
+                }
+            }
+            if let Err(reason) = result {
+                stats.disconnected += 1;
+                self.tenants.record_disconnect(io_node.tenant);
+                if reason.is_deliberate() {
+                    let (target, (_, endpoint)) = io_node.as_parts_mut();
+                    say_last_words(endpoint.last_words_deadline(), &self.time_source, |first| {
+                        endpoint.last_words(target, first, ctx)
+                    });
+                }
                 self.selector.unregister(io_node).unwrap();
                 let (handle, mut endpoint) = io_node.endpoint.take().unwrap();
-                if endpoint.can_recreate(DisconnectReason::other(err), ctx) {
+                // retry on the next address if the first frame did not arrive in time
+                let avoid_addr = reason.is_first_frame_timeout().then_some(io_node.addr);
+                if endpoint.can_recreate(reason, ctx) {
                     let info = endpoint.connection_info();
-                    let query = self.dns_resolver.new_query(info.host(), info.port()).unwrap();
+                    let (host, port) = info.connect_address();
+                    let query = self.dns_resolver.new_query(host, port).unwrap();
                     let now = self.time_source.current_time_nanos();
-                    self.pending_endpoints.push_back((handle, query, now, endpoint));
+                    self.pending_endpoints
+                        .push_back((handle, query, now, endpoint, avoid_addr));
                 } else {
                     panic!("unrecoverable error when polling endpoint");
                 }
@@ -440,19 +1101,39 @@ where
             true
         });
 
-        Ok(())
+        // throttle or release the tenants over (or back within) their read and write rate
+        if !self.tenants.is_empty() {
+            self.check_tenant_rates()?;
+        }
+
+        probe!(poll_done, stats.endpoints, stats.frames, stats.disconnected);
+        Ok(stats)
+    }
+
+    /// Gracefully stop the service just like [`shutdown`](IOService::shutdown), passing the [`Context`]
+    /// to [`EndpointWithContext::last_words`].
+    pub fn shutdown(&mut self, ctx: &mut C) -> Vec<E> {
+        self.shutdown_with(
+            |endpoint| endpoint.last_words_deadline(),
+            |target, endpoint, first| endpoint.last_words(target, first, ctx),
+        )
     }
 
     /// Dispatch command to an active endpoint using `handle` and provided `action`. If the
     /// endpoint is currently active `Ok(Some(...))` will be returned and the provided `action` invoked,
     /// otherwise this method will return `Ok(None)` and no `action` will be invoked. This method
-    /// requires `Context` to be passed and exposes it to the provided `action`.
+    /// requires `Context` to be passed and exposes it to the provided `action`. Just like
+    /// [`dispatch`](IOService::dispatch) it fails with `WouldBlock` above the tenant dispatch or write rate.
     pub fn dispatch<F, T>(&mut self, handle: Handle, ctx: &mut C, mut action: F) -> io::Result<Option<T>>
     where
         F: FnMut(&mut E::Target, &mut E, &mut C) -> std::io::Result<T>,
     {
         match self.io_nodes.get_mut(&handle.0) {
             Some(io_node) => {
+                if let Some(tenant) = io_node.tenant {
+                    let now = self.time_source.current_time_nanos();
+                    self.tenants.get_mut(tenant).try_dispatch(now)?;
+                }
                 let (stream, (_, endpoint)) = io_node.as_parts_mut();
                 let result = action(stream, endpoint, ctx)?;
                 Ok(Some(result))
@@ -461,3 +1142,380 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::select::direct::DirectSelector;
+    use crate::stream::ConnectionInfo;
+    use crate::stream::tcp::TcpStream;
+    use std::cell::Cell;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::rc::Rc;
+
+    struct ManualClock(Rc<Cell<u64>>);
+
+    impl TimeSource for ManualClock {
+        fn current_time_nanos(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    struct SilentEndpoint {
+        connection_info: ConnectionInfo,
+        disconnect_reason: Option<String>,
+        first_frame_budget: Option<Duration>,
+        last_words: Option<&'static [u8]>,
+        identity: Option<&'static str>,
+        tenant: Option<&'static str>,
+    }
+
+    impl SilentEndpoint {
+        fn new(port: u16) -> Self {
+            Self {
+                connection_info: ConnectionInfo::new("127.0.0.1", port),
+                disconnect_reason: None,
+                first_frame_budget: None,
+                last_words: None,
+                identity: None,
+                tenant: None,
+            }
+        }
+    }
+
+    impl ConnectionInfoProvider for SilentEndpoint {
+        fn connection_info(&self) -> &ConnectionInfo {
+            &self.connection_info
+        }
+    }
+
+    impl Endpoint for SilentEndpoint {
+        type Target = TcpStream;
+
+        fn create_target(&mut self, addr: SocketAddr) -> io::Result<Option<Self::Target>> {
+            Ok(Some(self.connection_info.clone().into_tcp_stream_with_addr(addr)?))
+        }
+
+        fn can_recreate(&mut self, reason: DisconnectReason) -> bool {
+            let terminate = matches!(reason, DisconnectReason::Duplicate(_));
+            self.disconnect_reason = Some(reason.to_string());
+            !terminate
+        }
+
+        fn first_frame_budget(&self) -> Option<Duration> {
+            self.first_frame_budget
+        }
+
+        fn has_first_frame(&self) -> bool {
+            false
+        }
+
+        fn last_words_deadline(&self) -> Option<Duration> {
+            self.last_words.map(|_| Duration::from_millis(100))
+        }
+
+        fn last_words(&mut self, target: &mut Self::Target, _first: bool) -> io::Result<bool> {
+            if let Some(words) = self.last_words.take() {
+                target.write_all(words)?;
+            }
+            Ok(true)
+        }
+
+        fn identity(&self) -> Option<&str> {
+            self.identity
+        }
+
+        fn tenant(&self) -> Option<&str> {
+            self.tenant
+        }
+    }
+
+    #[test]
+    fn should_abort_connection_when_first_frame_budget_exceeded() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut io_service = DirectSelector::new().unwrap().into_io_service();
+        let handle = io_service
+            .register(SilentEndpoint {
+                first_frame_budget: Some(Duration::from_millis(10)),
+                ..SilentEndpoint::new(port)
+            })
+            .unwrap();
+
+        // first poll connects, the endpoint is back in pending once the budget is exceeded
+        let start = std::time::Instant::now();
+        io_service.poll(|_stream, _endpoint| Ok(())).unwrap();
+        while io_service.pending().next().is_none() {
+            io_service.poll(|_stream, _endpoint| Ok(())).unwrap();
+            assert!(start.elapsed() < Duration::from_secs(5), "connection was not aborted");
+        }
+        assert!(start.elapsed() >= Duration::from_millis(10));
+
+        let endpoint = io_service.deregister(handle).unwrap();
+        assert_eq!(Some("first frame not received within 10ms"), endpoint.disconnect_reason.as_deref());
+    }
+
+    #[test]
+    fn should_abort_connection_when_read_timeout_exceeded() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut io_service = DirectSelector::new().unwrap().into_io_service();
+        let handle = io_service
+            .register(SilentEndpoint {
+                connection_info: ConnectionInfo::new("127.0.0.1", port).with_read_timeout(Duration::from_millis(10)),
+                ..SilentEndpoint::new(port)
+            })
+            .unwrap();
+
+        let start = std::time::Instant::now();
+        io_service.poll(|_stream, _endpoint| Ok(())).unwrap();
+        while io_service.pending().next().is_none() {
+            io_service.poll(|_stream, _endpoint| Ok(())).unwrap();
+            assert!(start.elapsed() < Duration::from_secs(5), "connection was not aborted");
+        }
+        assert!(start.elapsed() >= Duration::from_millis(10));
+
+        let endpoint = io_service.deregister(handle).unwrap();
+        assert_eq!(Some("no data received within 10ms"), endpoint.disconnect_reason.as_deref());
+    }
+
+    #[test]
+    fn should_say_last_words_on_shutdown() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut io_service = DirectSelector::new().unwrap().into_io_service();
+        io_service
+            .register(SilentEndpoint {
+                last_words: Some(b"cancel all"),
+                ..SilentEndpoint::new(port)
+            })
+            .unwrap();
+        io_service.poll(|_stream, _endpoint| Ok(())).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+
+        let endpoints = io_service.shutdown();
+        assert_eq!(1, endpoints.len());
+        assert!(endpoints[0].last_words.is_none());
+        assert!(io_service.iter().next().is_none());
+
+        // the connection is dropped once the last words are flushed
+        let mut received = Vec::new();
+        peer.read_to_end(&mut received).unwrap();
+        assert_eq!(b"cancel all", received.as_slice());
+    }
+
+    #[test]
+    fn should_report_poll_stats() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut io_service = DirectSelector::new().unwrap().into_io_service();
+        io_service.register(SilentEndpoint::new(port)).unwrap();
+
+        let stats = io_service.poll_once(|_stream, _endpoint| Ok(3)).unwrap();
+        assert_eq!(
+            PollStats {
+                endpoints: 1,
+                frames: 3,
+                disconnected: 0
+            },
+            stats
+        );
+    }
+
+    #[test]
+    fn should_pause_and_resume_reading() {
+        const SECOND: u64 = 1_000_000_000;
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let clock = Rc::new(Cell::new(SECOND));
+        let mut io_service = DirectSelector::new()
+            .unwrap()
+            .into_io_service()
+            .with_time_source(ManualClock(clock.clone()));
+        let handle = io_service
+            .register(SilentEndpoint {
+                connection_info: ConnectionInfo::new("127.0.0.1", port).with_read_timeout(Duration::from_millis(10)),
+                ..SilentEndpoint::new(port)
+            })
+            .unwrap();
+        io_service.poll_once(|_stream, _endpoint| Ok(0)).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+
+        assert!(io_service.pause_reading(handle).unwrap());
+        assert!(io_service.is_reading_paused(handle));
+        peer.write_all(b"foo").unwrap();
+
+        // nothing is read while paused and the read timeout is not enforced
+        clock.set(2 * SECOND);
+        let mut buf = [0u8; 8];
+        io_service
+            .poll_once(|stream, _endpoint| {
+                assert_eq!(ErrorKind::WouldBlock, stream.read(&mut buf).unwrap_err().kind());
+                Ok(0)
+            })
+            .unwrap();
+        assert!(io_service.iter().any(|(active, _, _)| active == handle));
+
+        assert!(io_service.resume_reading(handle).unwrap());
+        assert!(!io_service.is_reading_paused(handle));
+        let mut received = 0;
+        while received == 0 {
+            io_service
+                .poll_once(|stream, _endpoint| {
+                    match stream.read(&mut buf) {
+                        Ok(read) => received = read,
+                        Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                        Err(err) => return Err(err),
+                    }
+                    Ok(0)
+                })
+                .unwrap();
+        }
+        assert_eq!(b"foo", &buf[..received]);
+        assert!(!io_service.pause_reading(Handle(42)).unwrap());
+    }
+
+    #[test]
+    fn should_park_endpoints_during_quiet_period() {
+        use crate::service::schedule::{QuietSchedule, QuietWindow};
+
+        const HOUR: u64 = 3600 * 1_000_000_000;
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let clock = Rc::new(Cell::new(HOUR));
+        let schedule = QuietSchedule::new()
+            .with_window(QuietWindow::daily(Duration::ZERO, Duration::from_secs(2 * 3600)))
+            .with_maintenance_connections(1);
+        let mut io_service = DirectSelector::new()
+            .unwrap()
+            .into_io_service()
+            .with_time_source(ManualClock(clock.clone()))
+            .with_quiet_schedule(schedule);
+        let first = io_service.register(SilentEndpoint::new(port)).unwrap();
+        let second = io_service.register(SilentEndpoint::new(port)).unwrap();
+        let is_known = |io_service: &IOService<_, SilentEndpoint, _, _, _>, handle: Handle| {
+            io_service.pending().any(|(pending, _)| *pending == handle)
+                || io_service.iter().any(|(active, _, _)| active == handle)
+        };
+
+        // entering the quiet period pauses all but the first endpoint
+        io_service.poll_once(|_stream, _endpoint| Ok(0)).unwrap();
+        assert!(io_service.is_quiet());
+        assert!(is_known(&io_service, first));
+        assert!(!is_known(&io_service, second));
+
+        // leaving the quiet period resumes the paused endpoint
+        clock.set(3 * HOUR);
+        io_service.poll_once(|_stream, _endpoint| Ok(0)).unwrap();
+        assert!(!io_service.is_quiet());
+        assert!(is_known(&io_service, second));
+    }
+
+    #[test]
+    fn should_terminate_duplicate_connection() {
+        const SECOND: u64 = 1_000_000_000;
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let clock = Rc::new(Cell::new(SECOND));
+        let mut io_service = DirectSelector::new()
+            .unwrap()
+            .into_io_service()
+            .with_time_source(ManualClock(clock.clone()))
+            .with_duplicate_guard();
+        let endpoint = || SilentEndpoint {
+            identity: Some("btcusdt@trade"),
+            ..SilentEndpoint::new(port)
+        };
+        let first = io_service.register(endpoint()).unwrap();
+        let second = io_service.register(endpoint()).unwrap();
+
+        io_service.poll_once(|_stream, _endpoint| Ok(0)).unwrap();
+        assert!(io_service.iter().any(|(active, _, _)| active == first));
+
+        // the second endpoint is refused once the creation throttle lets it through
+        clock.set(3 * SECOND);
+        io_service.poll_once(|_stream, _endpoint| Ok(0)).unwrap();
+        assert!(io_service.pending().next().is_none());
+        assert!(io_service.deregister(second).is_none());
+        assert_eq!(1, io_service.iter().count());
+    }
+
+    #[test]
+    fn should_enforce_tenant_quota() {
+        use crate::service::tenant::TenantQuota;
+
+        const SECOND: u64 = 1_000_000_000;
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let other_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let other_port = other_listener.local_addr().unwrap().port();
+        let clock = Rc::new(Cell::new(SECOND));
+        let quota = TenantQuota::new()
+            .with_max_connections(1)
+            .with_max_read_rate(4)
+            .with_max_dispatch_rate(1, Duration::from_secs(1));
+        let mut io_service = DirectSelector::new()
+            .unwrap()
+            .into_io_service()
+            .with_time_source(ManualClock(clock.clone()))
+            .with_tenant_quota("research", quota);
+        let endpoint = || SilentEndpoint {
+            tenant: Some("research"),
+            ..SilentEndpoint::new(port)
+        };
+        let first = io_service.register(endpoint()).unwrap();
+        let second = io_service.register(endpoint()).unwrap();
+        let other = io_service.register(SilentEndpoint::new(other_port)).unwrap();
+
+        // the second endpoint of the tenant waits for the first one to be released
+        for now in 1..=3 {
+            clock.set(now * 2 * SECOND);
+            io_service.poll_once(|_stream, _endpoint| Ok(0)).unwrap();
+        }
+        assert!(io_service.iter().any(|(active, _, _)| active == first));
+        assert!(io_service.iter().any(|(active, _, _)| active == other));
+        assert!(io_service.pending().any(|(pending, _)| *pending == second));
+        let (mut peer, _) = listener.accept().unwrap();
+        let _other_peer = other_listener.accept().unwrap();
+
+        // reading is paused once the tenant is over its read rate
+        peer.write_all(b"foobar").unwrap();
+        let mut buf = [0u8; 8];
+        while !io_service.is_reading_paused(first) {
+            io_service
+                .poll_once(|stream, _endpoint| match stream.read(&mut buf) {
+                    Ok(read) => Ok(read.min(1)),
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(0),
+                    Err(err) => Err(err),
+                })
+                .unwrap();
+        }
+        assert!(!io_service.is_reading_paused(other));
+        clock.set(clock.get() + SECOND);
+        io_service.poll_once(|_stream, _endpoint| Ok(0)).unwrap();
+        assert!(!io_service.is_reading_paused(first));
+
+        // only the endpoints of the tenant are subject to its dispatch rate
+        assert!(
+            io_service
+                .dispatch(first, |_stream, _endpoint| Ok(()))
+                .unwrap()
+                .is_some()
+        );
+        let err = io_service.dispatch(first, |_stream, _endpoint| Ok(())).unwrap_err();
+        assert_eq!(ErrorKind::WouldBlock, err.kind());
+        assert!(
+            io_service
+                .dispatch(other, |_stream, _endpoint| Ok(()))
+                .unwrap()
+                .is_some()
+        );
+
+        let stats = io_service.tenant_stats("research").unwrap();
+        assert_eq!((1, 6, 1), (stats.connections, stats.bytes_read, stats.frames));
+        assert_eq!((1, 1, 1), (stats.dispatched, stats.dispatch_rejected, stats.read_throttled));
+        assert!(stats.connections_deferred > 0);
+        assert_eq!(vec!["research"], io_service.tenants().map(|(tenant, _)| tenant).collect::<Vec<_>>());
+    }
+}
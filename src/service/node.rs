@@ -9,6 +9,13 @@ pub struct IONode<S, E> {
     pub ttl: Duration,
     pub disconnect_time_ns: u64,
     pub addr: SocketAddr,
+    pub first_frame_budget: Duration,
+    pub first_frame_deadline_ns: u64,
+    pub read_timeout: Duration,
+    pub read_deadline_ns: u64,
+    pub read_paused: bool,
+    pub read_throttled: bool,
+    pub tenant: Option<usize>,
 }
 
 impl<S, E> IONode<S, E> {
@@ -30,9 +37,53 @@ impl<S, E> IONode<S, E> {
             ttl: Duration::from_nanos(ttl),
             disconnect_time_ns: ts.current_time_nanos().saturating_add(ttl),
             addr,
+            first_frame_budget: Duration::ZERO,
+            first_frame_deadline_ns: u64::MAX,
+            read_timeout: Duration::ZERO,
+            read_deadline_ns: u64::MAX,
+            read_paused: false,
+            read_throttled: false,
+            tenant: None,
         }
     }
 
+    pub fn with_first_frame_budget<TS>(self, budget: Option<Duration>, ts: &TS) -> IONode<S, E>
+    where
+        TS: TimeSource,
+    {
+        match budget {
+            Some(budget) => Self {
+                first_frame_budget: budget,
+                first_frame_deadline_ns: ts.current_time_nanos().saturating_add(budget.as_nanos() as u64),
+                ..self
+            },
+            None => self,
+        }
+    }
+
+    pub fn with_read_timeout<TS>(self, timeout: Option<Duration>, ts: &TS) -> IONode<S, E>
+    where
+        TS: TimeSource,
+    {
+        match timeout {
+            Some(timeout) => Self {
+                read_timeout: timeout,
+                read_deadline_ns: ts.current_time_nanos().saturating_add(timeout.as_nanos() as u64),
+                ..self
+            },
+            None => self,
+        }
+    }
+
+    pub fn with_tenant(self, tenant: Option<usize>) -> IONode<S, E> {
+        Self { tenant, ..self }
+    }
+
+    /// Reading has been paused by the user or throttled over the tenant read rate.
+    pub const fn is_read_paused(&self) -> bool {
+        self.read_paused || self.read_throttled
+    }
+
     pub fn as_parts(&self) -> (&S, &(Handle, E)) {
         // SAFETY: safe to call as endpoint will never be None
         unsafe { (&self.stream, self.endpoint.as_ref().unwrap_unchecked()) }
@@ -0,0 +1,463 @@
+//! Order entry session over a websocket with acknowledgment tracking.
+//!
+//! Every order entry integration ends up with the same risky boilerplate: correlation ids, matching
+//! acks and rejects to the requests, noticing the ones the venue never answered, staying within the
+//! venue rate limit and a kill switch that cancels everything. [`OrderSession`] standardises it on top
+//! of the protocol specific [`OrderCodec`], while the websocket itself stays owned by the caller (or the
+//! endpoint) so the session fits both standalone use and [`IOService`](crate::service::IOService).
+//!
+//! [`send`](OrderSession::send) assigns the correlation id (sequential unless another
+//! [`IdGenerator`] has been injected), serializes the order and returns the
+//! [`OrderHandle`] the response will be reported with. An order that is neither acknowledged nor
+//! rejected within the ack timeout is reported as [`SessionEvent::TimedOut`], its state at the venue is
+//! unknown and should be reconciled. The optional rate limit (token bucket) refuses orders before they
+//! reach the venue. The [kill switch](OrderSession::kill) sends the pre-serialized cancel-all request,
+//! which is also available as the endpoint [last words](crate::service::endpoint::Endpoint::last_words),
+//! and refuses any further orders.
+//!
+//! ## Examples
+//! ```no_run
+//! use std::io::{Read, Write};
+//! use std::time::Duration;
+//! use boomnet::codec::json;
+//! use boomnet::service::order::{OrderCodec, OrderSession, Outcome, SessionEvent};
+//! use boomnet::ws::Websocket;
+//!
+//! struct Order {
+//!     symbol: &'static str,
+//!     price: f64,
+//!     quantity: f64,
+//! }
+//!
+//! struct JsonCodec;
+//!
+//! impl OrderCodec for JsonCodec {
+//!     type Order = Order;
+//!
+//!     fn encode(&mut self, id: u64, order: &Order, buf: &mut Vec<u8>) {
+//!         let request = format!(
+//!             r#"{{"id":{id},"op":"new","symbol":"{}","price":{},"qty":{}}}"#,
+//!             order.symbol, order.price, order.quantity
+//!         );
+//!         buf.extend_from_slice(request.as_bytes());
+//!     }
+//!
+//!     fn encode_cancel_all(&mut self, buf: &mut Vec<u8>) {
+//!         buf.extend_from_slice(br#"{"op":"cancel_all"}"#);
+//!     }
+//!
+//!     fn decode<'a>(&mut self, message: &'a [u8]) -> Option<(u64, Outcome<'a>)> {
+//!         let id = std::str::from_utf8(json::get(message, b"id")?).ok()?.parse().ok()?;
+//!         match json::get(message, b"error").and_then(json::unquote) {
+//!             Some(reason) => Some((id, Outcome::Rejected(reason))),
+//!             None => Some((id, Outcome::Accepted)),
+//!         }
+//!     }
+//! }
+//!
+//! fn trade<S: Read + Write>(ws: &mut Websocket<S>) -> Result<(), Box<dyn std::error::Error>> {
+//!     let mut session = OrderSession::new(JsonCodec, Duration::from_millis(500))
+//!         .with_rate_limit(10, Duration::from_secs(1));
+//!     let order = Order { symbol: "BTCUSDT", price: 65000.0, quantity: 0.01 };
+//!     let order = session.send(ws, &order)?;
+//!     loop {
+//!         session.poll(ws, |event| match event {
+//!             SessionEvent::Ack { handle, outcome, .. } if handle == order => println!("{outcome:?}"),
+//!             SessionEvent::TimedOut { handle } => println!("{handle:?} unknown, reconcile"),
+//!             _ => {}
+//!         })?;
+//!         if session.in_flight() > 100 {
+//!             session.kill(ws)?;
+//!         }
+//!     }
+//! }
+//! ```
+
+use crate::id::{IdGenerator, Monotonic};
+use crate::service::time::{RateLimit, SystemTimeClockSource, TimeSource};
+use crate::ws::{Websocket, WebsocketFrame};
+use std::collections::BTreeMap;
+use std::io;
+use std::io::{Read, Write};
+use std::time::Duration;
+use thiserror::Error;
+
+/// Protocol specific serialization of the order entry requests and correlation of the responses.
+pub trait OrderCodec {
+    type Order;
+
+    /// Append the request placing the `order` under the correlation `id` to the `buf`.
+    fn encode(&mut self, id: u64, order: &Self::Order, buf: &mut Vec<u8>);
+
+    /// Append the request cancelling all open orders to the `buf`, sent by the kill switch.
+    fn encode_cancel_all(&mut self, buf: &mut Vec<u8>);
+
+    /// Correlation id and outcome if the `message` is a response to a request, `None` for anything
+    /// else (e.g. executions or account updates).
+    fn decode<'a>(&mut self, message: &'a [u8]) -> Option<(u64, Outcome<'a>)>;
+}
+
+/// Venue response to the order request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome<'a> {
+    Accepted,
+    /// Rejected with the reason reported by the venue.
+    Rejected(&'a [u8]),
+}
+
+/// Correlation handle of the order sent by the session.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct OrderHandle(u64);
+
+impl OrderHandle {
+    /// Correlation id the order has been sent with.
+    pub const fn id(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Event reported by [`OrderSession::poll`].
+#[derive(Debug)]
+pub enum SessionEvent<'a> {
+    /// Response to an in-flight order.
+    Ack {
+        handle: OrderHandle,
+        outcome: Outcome<'a>,
+        /// Time from sending the order to receiving the response.
+        latency_ns: u64,
+    },
+    /// No response within the ack timeout (or the connection has been lost), the order state is unknown.
+    TimedOut { handle: OrderHandle },
+    /// Any message that is not a response to an in-flight order (executions, account updates, late
+    /// responses to timed out orders).
+    Message(&'a [u8]),
+}
+
+/// Reason the order has not been sent.
+#[derive(Error, Debug)]
+pub enum OrderError {
+    #[error("order rate limit exceeded")]
+    RateLimited,
+    #[error("the kill switch has been triggered, no more orders can be sent")]
+    Killed,
+    #[error("websocket error: {0}")]
+    Websocket(#[from] crate::ws::Error),
+}
+
+impl From<OrderError> for io::Error {
+    fn from(value: OrderError) -> Self {
+        io::Error::other(value)
+    }
+}
+
+/// Order entry session, see the [module](self) documentation.
+#[derive(Debug)]
+pub struct OrderSession<C, TS = SystemTimeClockSource, G = Monotonic> {
+    codec: C,
+    ack_timeout_ns: u64,
+    ids: G,
+    // correlation id -> send time, ids grow with the send time so the oldest order comes first
+    in_flight: BTreeMap<u64, u64>,
+    rate_limit: Option<RateLimit>,
+    cancel_all: Vec<u8>,
+    killed: bool,
+    buf: Vec<u8>,
+    time_source: TS,
+}
+
+impl<C: OrderCodec> OrderSession<C> {
+    /// Create session using the `codec`, orders not answered within the `ack_timeout` are reported as
+    /// timed out.
+    pub fn new(codec: C, ack_timeout: Duration) -> Self {
+        Self::new_with_time_source(codec, ack_timeout, SystemTimeClockSource)
+    }
+}
+
+impl<C: OrderCodec, TS: TimeSource> OrderSession<C, TS> {
+    /// Create session using custom [`TimeSource`].
+    pub fn new_with_time_source(mut codec: C, ack_timeout: Duration, time_source: TS) -> Self {
+        let mut cancel_all = Vec::new();
+        codec.encode_cancel_all(&mut cancel_all);
+        Self {
+            codec,
+            ack_timeout_ns: ack_timeout.as_nanos() as u64,
+            ids: Monotonic::default(),
+            in_flight: BTreeMap::new(),
+            rate_limit: None,
+            cancel_all,
+            killed: false,
+            buf: Vec::with_capacity(256),
+            time_source,
+        }
+    }
+
+    /// Correlation id of the first order, e.g. to keep the ids unique across restarts.
+    pub fn with_first_id(self, first_id: u64) -> Self {
+        Self {
+            ids: Monotonic::new(first_id),
+            ..self
+        }
+    }
+}
+
+impl<C: OrderCodec, TS: TimeSource, G: IdGenerator> OrderSession<C, TS, G> {
+    /// Assign the correlation ids with the `ids` generator, e.g. a [`Snowflake`](crate::id::Snowflake)
+    /// to keep them unique across restarts and the sessions of other threads.
+    pub fn with_id_generator<I: IdGenerator>(self, ids: I) -> OrderSession<C, TS, I> {
+        OrderSession {
+            codec: self.codec,
+            ack_timeout_ns: self.ack_timeout_ns,
+            ids,
+            in_flight: self.in_flight,
+            rate_limit: self.rate_limit,
+            cancel_all: self.cancel_all,
+            killed: self.killed,
+            buf: self.buf,
+            time_source: self.time_source,
+        }
+    }
+
+    /// Allow at most `max_orders` per `interval` (refilled evenly), orders over the limit fail with
+    /// [`OrderError::RateLimited`]. The cancel-all request of the kill switch is never limited.
+    pub fn with_rate_limit(self, max_orders: u32, interval: Duration) -> Self {
+        let now = self.time_source.current_time_nanos();
+        Self {
+            rate_limit: Some(RateLimit::new(max_orders, interval, now)),
+            ..self
+        }
+    }
+
+    /// Send the `order` on the `ws`, returns the handle its response will be reported with.
+    pub fn send<S>(&mut self, ws: &mut Websocket<S>, order: &C::Order) -> Result<OrderHandle, OrderError>
+    where
+        S: Read + Write,
+    {
+        if self.killed {
+            return Err(OrderError::Killed);
+        }
+        let now = self.time_source.current_time_nanos();
+        if let Some(rate_limit) = self.rate_limit.as_mut() {
+            if !rate_limit.try_acquire(now) {
+                return Err(OrderError::RateLimited);
+            }
+        }
+        let id = self.ids.next_id();
+        self.buf.clear();
+        self.codec.encode(id, order, &mut self.buf);
+        ws.send_text(true, Some(&self.buf))?;
+        self.in_flight.insert(id, now);
+        Ok(OrderHandle(id))
+    }
+
+    /// Report the orders that have timed out and read a batch from the `ws`, reporting responses to
+    /// the in-flight orders and passing any other message through.
+    pub fn poll<S, F>(&mut self, ws: &mut Websocket<S>, mut handler: F) -> Result<(), crate::ws::Error>
+    where
+        S: Read + Write,
+        F: FnMut(SessionEvent),
+    {
+        self.expire(&mut handler);
+        for frame in ws.read_batch()? {
+            if let WebsocketFrame::Text(true, message) = frame? {
+                handler(self.on_message(message));
+            }
+        }
+        Ok(())
+    }
+
+    /// Correlate the inbound `message`, for the use with a websocket polled elsewhere.
+    pub fn on_message<'a>(&mut self, message: &'a [u8]) -> SessionEvent<'a> {
+        if let Some((id, outcome)) = self.codec.decode(message) {
+            if let Some(sent_ns) = self.in_flight.remove(&id) {
+                let now = self.time_source.current_time_nanos();
+                return SessionEvent::Ack {
+                    handle: OrderHandle(id),
+                    outcome,
+                    latency_ns: now.saturating_sub(sent_ns),
+                };
+            }
+        }
+        SessionEvent::Message(message)
+    }
+
+    /// Report the in-flight orders that have not been answered within the ack timeout.
+    pub fn expire<F: FnMut(SessionEvent)>(&mut self, mut handler: F) {
+        let now = self.time_source.current_time_nanos();
+        while let Some(entry) = self.in_flight.first_entry() {
+            if now.saturating_sub(*entry.get()) < self.ack_timeout_ns {
+                break;
+            }
+            handler(SessionEvent::TimedOut {
+                handle: OrderHandle(entry.remove_entry().0),
+            });
+        }
+    }
+
+    /// Must be called when the connection is lost, the responses to the in-flight orders will never
+    /// arrive so they are all reported as timed out.
+    pub fn on_disconnected<F: FnMut(SessionEvent)>(&mut self, mut handler: F) {
+        while let Some((id, _)) = self.in_flight.pop_first() {
+            handler(SessionEvent::TimedOut {
+                handle: OrderHandle(id),
+            });
+        }
+    }
+
+    /// Trigger the kill switch: send the cancel-all request on the `ws` and refuse any further orders.
+    /// The in-flight orders are still tracked.
+    pub fn kill<S>(&mut self, ws: &mut Websocket<S>) -> Result<(), OrderError>
+    where
+        S: Read + Write,
+    {
+        self.killed = true;
+        log::warn!("kill switch triggered with {} order(s) in flight", self.in_flight.len());
+        ws.send_text(true, Some(&self.cancel_all))?;
+        Ok(())
+    }
+
+    /// Checks if the kill switch has been triggered.
+    pub const fn is_killed(&self) -> bool {
+        self.killed
+    }
+
+    /// Pre-serialized cancel-all request, to be returned as the endpoint last words so that it is also
+    /// sent before any deliberate disconnect.
+    pub fn cancel_all(&self) -> &[u8] {
+        &self.cancel_all
+    }
+
+    /// Number of orders sent and not answered (nor timed out) yet.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Checks if the `order` is still awaiting its response.
+    pub fn is_in_flight(&self, order: OrderHandle) -> bool {
+        self.in_flight.contains_key(&order.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct ManualClock(Rc<Cell<u64>>);
+
+    impl TimeSource for ManualClock {
+        fn current_time_nanos(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    // "<id> <symbol>" requests, "<id> ok" or "<id> <reason>" responses
+    struct TextCodec;
+
+    impl OrderCodec for TextCodec {
+        type Order = &'static str;
+
+        fn encode(&mut self, id: u64, order: &&'static str, buf: &mut Vec<u8>) {
+            buf.extend_from_slice(format!("{id} {order}").as_bytes());
+        }
+
+        fn encode_cancel_all(&mut self, buf: &mut Vec<u8>) {
+            buf.extend_from_slice(b"cancel all");
+        }
+
+        fn decode<'a>(&mut self, message: &'a [u8]) -> Option<(u64, Outcome<'a>)> {
+            let (id, response) = message.split_at(message.iter().position(|b| *b == b' ')?);
+            let id = std::str::from_utf8(id).ok()?.parse().ok()?;
+            match &response[1..] {
+                b"ok" => Some((id, Outcome::Accepted)),
+                reason => Some((id, Outcome::Rejected(reason))),
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct Socket {
+        inbound: io::Cursor<Vec<u8>>,
+        written: Vec<u8>,
+    }
+
+    impl Read for Socket {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.inbound.read(buf)? {
+                0 => Err(io::Error::from(io::ErrorKind::WouldBlock)),
+                read => Ok(read),
+            }
+        }
+    }
+
+    impl Write for Socket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn events(session: &mut OrderSession<TextCodec, ManualClock>, ws: &mut Websocket<Socket>) -> Vec<String> {
+        let mut events = vec![];
+        session
+            .poll(ws, |event| {
+                events.push(match event {
+                    SessionEvent::Ack { handle, outcome, .. } => match outcome {
+                        Outcome::Accepted => format!("{} accepted", handle.id()),
+                        Outcome::Rejected(reason) => {
+                            format!("{} rejected: {}", handle.id(), String::from_utf8_lossy(reason))
+                        }
+                    },
+                    SessionEvent::TimedOut { handle } => format!("{} timed out", handle.id()),
+                    SessionEvent::Message(message) => String::from_utf8_lossy(message).into_owned(),
+                })
+            })
+            .unwrap();
+        events
+    }
+
+    #[test]
+    fn should_track_acks_and_enforce_limits() {
+        let clock = ManualClock::default();
+        let mut session = OrderSession::new_with_time_source(TextCodec, Duration::from_millis(100), clock.clone())
+            .with_rate_limit(2, Duration::from_millis(10));
+        // responses: ack of the first order, reject of the second and an execution report
+        let inbound = [
+            &[0x81, 4][..],
+            b"1 ok",
+            &[0x81, 11],
+            b"2 no margin",
+            &[0x81, 6],
+            b"fill 1",
+        ]
+        .concat();
+        let mut ws = Websocket::new_with_handshake_complete(Socket {
+            inbound: io::Cursor::new(inbound),
+            ..Default::default()
+        });
+
+        let first = session.send(&mut ws, &"BTCUSDT").unwrap();
+        session.send(&mut ws, &"ETHUSDT").unwrap();
+        assert!(matches!(session.send(&mut ws, &"SOLUSDT"), Err(OrderError::RateLimited)));
+        clock.0.set(5_000_000);
+        let third = session.send(&mut ws, &"SOLUSDT").unwrap();
+        assert_eq!(3, session.in_flight());
+        assert!(session.is_in_flight(first));
+
+        assert_eq!(vec!["1 accepted", "2 rejected: no margin", "fill 1"], events(&mut session, &mut ws));
+        assert!(!session.is_in_flight(first));
+        assert_eq!(1, session.in_flight());
+
+        clock.0.set(105_000_000);
+        assert_eq!(vec![format!("{} timed out", third.id())], events(&mut session, &mut ws));
+        assert_eq!(0, session.in_flight());
+
+        session.kill(&mut ws).unwrap();
+        assert!(matches!(session.send(&mut ws, &"BTCUSDT"), Err(OrderError::Killed)));
+        assert!(ws.stream().written.ends_with(b"cancel all"));
+    }
+}
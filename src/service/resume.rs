@@ -0,0 +1,242 @@
+//! Session continuation using resume tokens issued by the gateway.
+//!
+//! Some private gateways hand out a resume token that lets the next connection continue the session
+//! without the full re-authentication (and replay of subscriptions). [`ResumeSession`] is owned by the
+//! endpoint and ties the token to the reconnect lifecycle driven by `IOService`:
+//! - the protocol layer [persists](ResumeSession::persist) the token whenever the gateway issues one,
+//! - `create_target` [presents](ResumeSession::present) it on the next connect (e.g. as a handshake
+//!   header), falling back to the full login when there is no usable token,
+//! - the protocol layer reports if the gateway has [accepted](ResumeSession::on_resumed) the token,
+//! - `can_recreate` reports the [disconnect](ResumeSession::on_disconnected) so that a token the
+//!   gateway did not accept is not presented again.
+//!
+//! ## Examples
+//! ```no_run
+//! use std::io;
+//! use std::io::Write;
+//! use std::net::SocketAddr;
+//! use std::time::Duration;
+//! use boomnet::service::endpoint::{DisconnectReason, Endpoint};
+//! use boomnet::service::resume::ResumeSession;
+//! use boomnet::stream::tcp::TcpStream;
+//! use boomnet::stream::{ConnectionInfo, ConnectionInfoProvider};
+//!
+//! struct GatewayEndpoint {
+//!     connection_info: ConnectionInfo,
+//!     session: ResumeSession,
+//! }
+//!
+//! impl ConnectionInfoProvider for GatewayEndpoint {
+//!     fn connection_info(&self) -> &ConnectionInfo {
+//!         &self.connection_info
+//!     }
+//! }
+//!
+//! impl Endpoint for GatewayEndpoint {
+//!     type Target = TcpStream;
+//!
+//!     fn create_target(&mut self, addr: SocketAddr) -> io::Result<Option<Self::Target>> {
+//!         let mut stream = self.connection_info.clone().into_tcp_stream_with_addr(addr)?;
+//!         // with websocket the token would typically go into `Websocket::with_header`
+//!         match self.session.present() {
+//!             Some(token) => stream.write_all(format!("RESUME {token}\n").as_bytes())?,
+//!             None => stream.write_all(b"LOGIN\n")?,
+//!         }
+//!         Ok(Some(stream))
+//!     }
+//!
+//!     fn can_recreate(&mut self, reason: DisconnectReason) -> bool {
+//!         self.session.on_disconnected(&reason);
+//!         true
+//!     }
+//! }
+//!
+//! let endpoint = GatewayEndpoint {
+//!     connection_info: ConnectionInfo::new("gateway.example.com", 443),
+//!     session: ResumeSession::new().with_ttl(Duration::from_secs(60)),
+//! };
+//! ```
+
+use crate::service::endpoint::DisconnectReason;
+use crate::service::time::{SystemTimeClockSource, TimeSource};
+use log::{info, warn};
+use std::time::Duration;
+
+/// State of the current connection with regard to the session resume.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ResumeState {
+    /// Not connected or connected with the full login.
+    Fresh,
+    /// Token has been presented, waiting for the gateway to accept it.
+    Pending,
+    /// Gateway has accepted the token and the session continues.
+    Resumed,
+}
+
+/// Resume token with its lifetime, owned by the endpoint.
+#[derive(Debug)]
+pub struct ResumeSession<TS = SystemTimeClockSource> {
+    token: Option<String>,
+    issued_ns: u64,
+    ttl_ns: u64,
+    state: ResumeState,
+    resumed: u64,
+    rejected: u64,
+    time_source: TS,
+}
+
+impl ResumeSession {
+    /// Create session without a token, tokens never expire by default.
+    pub fn new() -> Self {
+        Self::new_with_time_source(SystemTimeClockSource)
+    }
+}
+
+impl Default for ResumeSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<TS: TimeSource> ResumeSession<TS> {
+    /// Create session without a token using custom [`TimeSource`].
+    pub fn new_with_time_source(time_source: TS) -> Self {
+        Self {
+            token: None,
+            issued_ns: 0,
+            ttl_ns: u64::MAX,
+            state: ResumeState::Fresh,
+            resumed: 0,
+            rejected: 0,
+            time_source,
+        }
+    }
+
+    /// Time after which the gateway no longer accepts the token, an expired token is never presented.
+    pub fn with_ttl(self, ttl: Duration) -> Self {
+        Self {
+            ttl_ns: ttl.as_nanos() as u64,
+            ..self
+        }
+    }
+
+    /// Store the `token` issued by the gateway, replacing the previous one.
+    pub fn persist(&mut self, token: impl Into<String>) {
+        self.token = Some(token.into());
+        self.issued_ns = self.time_source.current_time_nanos();
+    }
+
+    /// Discard the token, the next connection will perform the full login.
+    pub fn invalidate(&mut self) {
+        self.token = None;
+    }
+
+    /// Token to present on the connection being created, `None` if the full login is required.
+    pub fn present(&mut self) -> Option<&str> {
+        if self.token.is_some() && self.is_expired() {
+            info!("resume token expired, falling back to full login");
+            self.token = None;
+        }
+        self.state = match self.token {
+            Some(_) => ResumeState::Pending,
+            None => ResumeState::Fresh,
+        };
+        self.token.as_deref()
+    }
+
+    /// Must be called once the gateway confirms the session has been resumed.
+    pub fn on_resumed(&mut self) {
+        if self.state == ResumeState::Pending {
+            self.state = ResumeState::Resumed;
+            self.resumed += 1;
+        }
+    }
+
+    /// Must be called if the gateway refuses the presented token, the full login is then required.
+    pub fn on_rejected(&mut self) {
+        if self.state == ResumeState::Pending {
+            warn!("resume token rejected by the gateway");
+            self.state = ResumeState::Fresh;
+            self.token = None;
+            self.rejected += 1;
+        }
+    }
+
+    /// Must be called when the connection is lost (e.g. from `can_recreate`). A token that has been
+    /// presented but not accepted before an IO error is treated as rejected.
+    pub fn on_disconnected(&mut self, reason: &DisconnectReason) {
+        if self.state == ResumeState::Pending && !reason.is_deliberate() {
+            self.on_rejected();
+        }
+        self.state = ResumeState::Fresh;
+    }
+
+    /// Current token, if any (even if expired).
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    /// Resume state of the current connection.
+    pub const fn state(&self) -> ResumeState {
+        self.state
+    }
+
+    /// Number of connections that resumed the session and that had the token rejected.
+    pub const fn stats(&self) -> (u64, u64) {
+        (self.resumed, self.rejected)
+    }
+
+    fn is_expired(&self) -> bool {
+        self.time_source.current_time_nanos().saturating_sub(self.issued_ns) >= self.ttl_ns
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::io;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct ManualClock(Rc<Cell<u64>>);
+
+    impl TimeSource for ManualClock {
+        fn current_time_nanos(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    #[test]
+    fn should_present_token_until_rejected() {
+        let mut session = ResumeSession::new_with_time_source(ManualClock::default());
+        assert_eq!(None, session.present());
+        assert_eq!(ResumeState::Fresh, session.state());
+
+        session.persist("abc");
+        assert_eq!(Some("abc"), session.present());
+        session.on_resumed();
+        assert_eq!(ResumeState::Resumed, session.state());
+
+        // deliberate disconnect keeps the token
+        session.on_disconnected(&DisconnectReason::Maintenance);
+        assert_eq!(Some("abc"), session.present());
+
+        // failure before the gateway accepted the token
+        session.on_disconnected(&DisconnectReason::IO(io::Error::other("reset")));
+        assert_eq!(None, session.present());
+        assert_eq!((1, 1), session.stats());
+    }
+
+    #[test]
+    fn should_not_present_expired_token() {
+        let clock = ManualClock::default();
+        let mut session = ResumeSession::new_with_time_source(clock.clone()).with_ttl(Duration::from_secs(10));
+        session.persist("abc");
+        clock.0.set(Duration::from_secs(9).as_nanos() as u64);
+        assert_eq!(Some("abc"), session.present());
+        clock.0.set(Duration::from_secs(10).as_nanos() as u64);
+        assert_eq!(None, session.present());
+        assert_eq!(None, session.token());
+    }
+}
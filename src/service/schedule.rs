@@ -0,0 +1,197 @@
+//! Scheduled quiet periods (e.g. market close) during which the service saves resources.
+//!
+//! While the [`IOService`](crate::service::IOService) clock is inside one of the [`QuietWindow`]s the
+//! selector is asked to block for up to the configured park timeout instead of busy spinning (see
+//! [`Selector::set_park_timeout`](crate::service::select::Selector::set_park_timeout)) and, optionally,
+//! only the first `maintenance_connections` endpoints (in registration order) stay connected. The
+//! remaining endpoints are disconnected with [`DisconnectReason::Maintenance`](crate::service::endpoint::DisconnectReason::Maintenance)
+//! and reconnected once the window ends, so the window should end early enough for connections and
+//! subscriptions to be fully established before the session opens.
+//!
+//! All times are in UTC.
+//!
+//! ## Examples
+//! ```no_run
+//! use std::time::Duration;
+//! use boomnet::service::schedule::{QuietSchedule, QuietWindow};
+//!
+//! const HOUR: Duration = Duration::from_secs(3600);
+//! const DAY: Duration = Duration::from_secs(24 * 3600);
+//!
+//! let schedule = QuietSchedule::new()
+//!     // every day from 21:15 to 23:45
+//!     .with_window(QuietWindow::daily(21 * HOUR + Duration::from_secs(900), 23 * HOUR + Duration::from_secs(2700)))
+//!     // weekend from Friday 22:00 to Sunday 21:30
+//!     .with_window(QuietWindow::weekly(4 * DAY + 22 * HOUR, 6 * DAY + 21 * HOUR + Duration::from_secs(1800)))
+//!     .with_park_timeout(Duration::from_millis(10))
+//!     .with_maintenance_connections(1);
+//! ```
+
+use std::time::Duration;
+
+const NANOS_PER_DAY: u64 = 24 * 3600 * 1_000_000_000;
+const NANOS_PER_WEEK: u64 = 7 * NANOS_PER_DAY;
+// UNIX epoch was Thursday, Monday 00:00 UTC preceded it by 3 days
+const EPOCH_WEEK_OFFSET_NS: u64 = 3 * NANOS_PER_DAY;
+const CHECK_INTERVAL_NS: u64 = 1_000_000_000;
+
+/// Recurring quiet window, the `end` can be smaller than the `start` if the window wraps around.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct QuietWindow {
+    start_ns: u64,
+    end_ns: u64,
+    period_ns: u64,
+}
+
+impl QuietWindow {
+    /// Window repeated every day, `start` and `end` are offsets from midnight.
+    pub const fn daily(start: Duration, end: Duration) -> QuietWindow {
+        Self::new(start, end, NANOS_PER_DAY)
+    }
+
+    /// Window repeated every week, `start` and `end` are offsets from Monday midnight.
+    pub const fn weekly(start: Duration, end: Duration) -> QuietWindow {
+        Self::new(start, end, NANOS_PER_WEEK)
+    }
+
+    const fn new(start: Duration, end: Duration, period_ns: u64) -> QuietWindow {
+        Self {
+            start_ns: start.as_nanos() as u64 % period_ns,
+            end_ns: end.as_nanos() as u64 % period_ns,
+            period_ns,
+        }
+    }
+
+    /// Checks if the time (nanos since UNIX epoch) falls into the window.
+    pub const fn contains(&self, time_ns: u64) -> bool {
+        let offset = if self.period_ns == NANOS_PER_WEEK {
+            (time_ns + EPOCH_WEEK_OFFSET_NS) % self.period_ns
+        } else {
+            time_ns % self.period_ns
+        };
+        if self.start_ns <= self.end_ns {
+            offset >= self.start_ns && offset < self.end_ns
+        } else {
+            offset >= self.start_ns || offset < self.end_ns
+        }
+    }
+}
+
+/// Set of [`QuietWindow`]s together with the low power settings applied while inside any of them.
+#[derive(Debug, Clone)]
+pub struct QuietSchedule {
+    windows: Vec<QuietWindow>,
+    park_timeout: Duration,
+    maintenance_connections: Option<usize>,
+    next_check_ns: u64,
+}
+
+impl Default for QuietSchedule {
+    fn default() -> Self {
+        Self {
+            windows: Vec::new(),
+            park_timeout: Duration::from_millis(1),
+            maintenance_connections: None,
+            next_check_ns: 0,
+        }
+    }
+}
+
+impl QuietSchedule {
+    pub fn new() -> QuietSchedule {
+        Self::default()
+    }
+
+    /// Add quiet window.
+    pub fn with_window(mut self, window: QuietWindow) -> Self {
+        self.windows.push(window);
+        self
+    }
+
+    /// Maximum time the selector blocks for while quiet (default 1ms).
+    pub fn with_park_timeout(self, park_timeout: Duration) -> Self {
+        Self { park_timeout, ..self }
+    }
+
+    /// Number of endpoints that stay connected while quiet, by default all of them.
+    pub fn with_maintenance_connections(self, maintenance_connections: usize) -> Self {
+        Self {
+            maintenance_connections: Some(maintenance_connections),
+            ..self
+        }
+    }
+
+    #[inline]
+    pub const fn park_timeout(&self) -> Duration {
+        self.park_timeout
+    }
+
+    #[inline]
+    pub const fn maintenance_connections(&self) -> Option<usize> {
+        self.maintenance_connections
+    }
+
+    /// Checks if the time (nanos since UNIX epoch) falls into any of the windows.
+    pub fn is_quiet(&self, time_ns: u64) -> bool {
+        self.windows.iter().any(|window| window.contains(time_ns))
+    }
+
+    /// Returns `Some(quiet)` at most once per second, `None` if the check is not due yet.
+    #[inline]
+    pub(crate) fn poll(&mut self, time_ns: u64) -> Option<bool> {
+        if time_ns < self.next_check_ns {
+            return None;
+        }
+        self.next_check_ns = time_ns.saturating_add(CHECK_INTERVAL_NS);
+        Some(self.is_quiet(time_ns))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: u64 = 3600 * 1_000_000_000;
+    // 2024-01-01 00:00:00 UTC (Monday)
+    const MONDAY: u64 = 1_704_067_200 * 1_000_000_000;
+
+    #[test]
+    fn should_match_daily_window() {
+        let window = QuietWindow::daily(Duration::from_secs(21 * 3600), Duration::from_secs(23 * 3600));
+        assert!(!window.contains(MONDAY + 20 * HOUR));
+        assert!(window.contains(MONDAY + 21 * HOUR));
+        assert!(window.contains(MONDAY + NANOS_PER_DAY + 22 * HOUR));
+        assert!(!window.contains(MONDAY + 23 * HOUR));
+    }
+
+    #[test]
+    fn should_match_window_wrapping_around_midnight() {
+        let window = QuietWindow::daily(Duration::from_secs(22 * 3600), Duration::from_secs(3600));
+        assert!(window.contains(MONDAY + 23 * HOUR));
+        assert!(window.contains(MONDAY + NANOS_PER_DAY));
+        assert!(!window.contains(MONDAY + HOUR));
+        assert!(!window.contains(MONDAY + 12 * HOUR));
+    }
+
+    #[test]
+    fn should_match_weekly_window() {
+        // Friday 22:00 to Sunday 21:00
+        let window =
+            QuietWindow::weekly(Duration::from_secs((4 * 24 + 22) * 3600), Duration::from_secs((6 * 24 + 21) * 3600));
+        assert!(!window.contains(MONDAY + 12 * HOUR));
+        assert!(!window.contains(MONDAY + 4 * NANOS_PER_DAY + 21 * HOUR));
+        assert!(window.contains(MONDAY + 5 * NANOS_PER_DAY));
+        assert!(window.contains(MONDAY + 6 * NANOS_PER_DAY + 20 * HOUR));
+        assert!(!window.contains(MONDAY + 6 * NANOS_PER_DAY + 21 * HOUR));
+        assert!(window.contains(MONDAY + NANOS_PER_WEEK + 5 * NANOS_PER_DAY));
+    }
+
+    #[test]
+    fn should_throttle_checks() {
+        let mut schedule =
+            QuietSchedule::new().with_window(QuietWindow::daily(Duration::ZERO, Duration::from_secs(3600)));
+        assert_eq!(Some(true), schedule.poll(MONDAY));
+        assert_eq!(None, schedule.poll(MONDAY + 1));
+        assert_eq!(Some(false), schedule.poll(MONDAY + 2 * HOUR));
+    }
+}
@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 use std::io;
 use std::marker::PhantomData;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use mio::event::Source;
 use mio::{Events, Interest, Poll, Token};
@@ -15,22 +15,215 @@ use crate::service::{IOService, IntoIOService, IntoIOServiceWithContext};
 
 const NO_WAIT: Option<Duration> = Some(Duration::from_millis(0));
 
+/// Default number of events handled per poll.
+pub const DEFAULT_EVENT_CAPACITY: usize = 1024;
+
+/// Controls whether [`MioSelector`] busy spins or blocks in `epoll_wait`.
+#[derive(Debug, Copy, Clone, Default)]
+pub enum PollPolicy {
+    /// Never block, lowest latency at the cost of a fully utilised core (default).
+    #[default]
+    BusySpin,
+    /// Busy spin while the event rate is high and block with a timeout during quiet periods.
+    Adaptive(AdaptivePoll),
+}
+
+/// Thresholds of the [`PollPolicy::Adaptive`] policy. The event rate is measured over `window`, the
+/// selector starts spinning once a window sees at least `spin_above` events and parks again only when
+/// a window sees fewer than `park_below` events. Keeping `park_below` lower than `spin_above` provides
+/// the hysteresis that stops the selector from flapping around a single threshold.
+///
+/// While parked the selector blocks for up to `park_timeout`, which also delays pending connections and
+/// timeouts enforced by the service, so it should be kept short (milliseconds).
+#[derive(Debug, Copy, Clone)]
+pub struct AdaptivePoll {
+    pub spin_above: u64,
+    pub park_below: u64,
+    pub window: Duration,
+    pub park_timeout: Duration,
+}
+
+impl Default for AdaptivePoll {
+    fn default() -> Self {
+        Self {
+            spin_above: 100,
+            park_below: 10,
+            window: Duration::from_secs(1),
+            park_timeout: Duration::from_millis(1),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct AdaptiveState {
+    config: AdaptivePoll,
+    spinning: bool,
+    events: u64,
+    window_start: Instant,
+}
+
+impl AdaptiveState {
+    fn new(config: AdaptivePoll, now: Instant) -> Self {
+        Self {
+            config,
+            spinning: true,
+            events: 0,
+            window_start: now,
+        }
+    }
+
+    #[inline]
+    const fn timeout(&self) -> Option<Duration> {
+        if self.spinning {
+            NO_WAIT
+        } else {
+            Some(self.config.park_timeout)
+        }
+    }
+
+    #[inline]
+    fn on_poll(&mut self, events: usize, now: Instant) {
+        self.events += events as u64;
+        if now.duration_since(self.window_start) < self.config.window {
+            return;
+        }
+        if self.spinning && self.events < self.config.park_below {
+            log::debug!("event rate dropped to {} per {:?}, parking selector", self.events, self.config.window);
+            self.spinning = false;
+        } else if !self.spinning && self.events >= self.config.spin_above {
+            log::debug!("event rate rose to {} per {:?}, spinning selector", self.events, self.config.window);
+            self.spinning = true;
+        }
+        self.events = 0;
+        self.window_start = now;
+    }
+}
+
+/// Wakeup statistics of the [`MioSelector`] since the last [`MioSelector::take_stats`], a wakeup is a
+/// poll that has returned at least one event.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct SelectorStats {
+    /// Number of polls.
+    pub polls: u64,
+    /// Number of polls that have returned at least one event.
+    pub wakeups: u64,
+    /// Number of events returned.
+    pub events: u64,
+    /// Number of wakeups that have filled the whole event array, the remaining events were left for
+    /// the next poll (consider a larger [event capacity](MioSelector::with_event_capacity)).
+    pub saturated: u64,
+    /// Time over which the statistics have been collected.
+    pub elapsed: Duration,
+}
+
+impl SelectorStats {
+    /// Average number of wakeups per second.
+    pub fn wakeups_per_sec(&self) -> f64 {
+        match self.elapsed.is_zero() {
+            true => 0.0,
+            false => self.wakeups as f64 / self.elapsed.as_secs_f64(),
+        }
+    }
+
+    /// Average number of events per wakeup, how well the events are coalesced into a single wakeup.
+    pub fn events_per_wakeup(&self) -> f64 {
+        match self.wakeups {
+            0 => 0.0,
+            wakeups => self.events as f64 / wakeups as f64,
+        }
+    }
+}
+
 pub struct MioSelector<S> {
     poll: Poll,
     events: Events,
     next_token: u32,
+    adaptive: Option<AdaptiveState>,
+    park_timeout: Option<Duration>,
+    stats: SelectorStats,
+    stats_since: Instant,
+    // tokens to re-arm on the next poll, only in the oneshot mode
+    oneshot: Option<Vec<Token>>,
     phantom: PhantomData<S>,
 }
 
 impl<S> MioSelector<S> {
     pub fn new() -> io::Result<MioSelector<S>> {
+        let poll = Poll::new()?;
+        #[cfg(unix)]
+        debug_assert!(crate::util::is_cloexec(std::os::fd::AsRawFd::as_raw_fd(&poll))?);
         Ok(Self {
-            poll: Poll::new()?,
-            events: Events::with_capacity(1024),
+            poll,
+            events: Events::with_capacity(DEFAULT_EVENT_CAPACITY),
             next_token: 0,
+            adaptive: None,
+            park_timeout: None,
+            stats: SelectorStats::default(),
+            stats_since: Instant::now(),
+            oneshot: None,
             phantom: PhantomData,
         })
     }
+
+    /// Handle at most `capacity` events per poll (`1024` by default), the events that do not fit are
+    /// returned by the next poll. See [`SelectorStats::saturated`] to size it.
+    pub fn with_event_capacity(self, capacity: usize) -> Self {
+        Self {
+            events: Events::with_capacity(capacity.max(1)),
+            ..self
+        }
+    }
+
+    /// Maximum number of events handled per poll.
+    pub fn event_capacity(&self) -> usize {
+        self.events.capacity()
+    }
+
+    /// Re-arm every connection that has reported readiness at the start of the next poll, in the
+    /// manner of `EPOLLONESHOT`. The re-arm (`epoll_ctl` with `EPOLL_CTL_MOD`) makes the kernel report
+    /// the connection again if unread data is left (e.g. the service has stopped reading on its
+    /// budget), which the edge triggered notifications never do, at the cost of one syscall per event.
+    pub fn with_oneshot(self, oneshot: bool) -> Self {
+        Self {
+            oneshot: oneshot.then(Vec::new),
+            ..self
+        }
+    }
+
+    /// Returns `true` if the connections are re-armed after every event.
+    pub fn is_oneshot(&self) -> bool {
+        self.oneshot.is_some()
+    }
+
+    /// Wakeup statistics collected since the selector has been created or the statistics taken.
+    pub fn stats(&self) -> SelectorStats {
+        SelectorStats {
+            elapsed: self.stats_since.elapsed(),
+            ..self.stats
+        }
+    }
+
+    /// Same as [`MioSelector::stats`], the collection then starts over (e.g. per reporting interval).
+    pub fn take_stats(&mut self) -> SelectorStats {
+        let stats = self.stats();
+        self.stats = SelectorStats::default();
+        self.stats_since = Instant::now();
+        stats
+    }
+
+    /// Set the [`PollPolicy`], by default the selector busy spins.
+    pub fn with_poll_policy(self, policy: PollPolicy) -> Self {
+        let adaptive = match policy {
+            PollPolicy::BusySpin => None,
+            PollPolicy::Adaptive(config) => Some(AdaptiveState::new(config, Instant::now())),
+        };
+        Self { adaptive, ..self }
+    }
+
+    /// Returns `true` if the selector currently busy spins.
+    pub fn is_spinning(&self) -> bool {
+        self.park_timeout.is_none() && self.adaptive.as_ref().is_none_or(|adaptive| adaptive.spinning)
+    }
 }
 
 impl<S: Source + Selectable> Selector for MioSelector<S> {
@@ -45,11 +238,42 @@ impl<S: Source + Selectable> Selector for MioSelector<S> {
     }
 
     fn unregister<E>(&mut self, io_node: &mut IONode<Self::Target, E>) -> io::Result<()> {
+        if io_node.is_read_paused() {
+            // already removed from the registry
+            return Ok(());
+        }
         self.poll.registry().deregister(io_node.as_stream_mut())
     }
 
     fn poll<E>(&mut self, io_nodes: &mut HashMap<SelectorToken, IONode<Self::Target, E>>) -> io::Result<()> {
-        self.poll.poll(&mut self.events, NO_WAIT)?;
+        let timeout = match self.park_timeout {
+            Some(timeout) => Some(timeout),
+            None => self.adaptive.as_ref().map_or(NO_WAIT, AdaptiveState::timeout),
+        };
+        if let Some(oneshot) = self.oneshot.as_mut() {
+            for token in oneshot.drain(..) {
+                // gone or removed from the registry in the meantime
+                let Some(io_node) = io_nodes.get_mut(&(token.0 as SelectorToken)) else {
+                    continue;
+                };
+                if !io_node.is_read_paused() {
+                    self.poll
+                        .registry()
+                        .reregister(io_node.as_stream_mut(), token, Interest::READABLE)?;
+                }
+            }
+        }
+        self.poll.poll(&mut self.events, timeout)?;
+        let events = self.events.iter().count();
+        self.stats.polls += 1;
+        if events > 0 {
+            self.stats.wakeups += 1;
+            self.stats.events += events as u64;
+            self.stats.saturated += u64::from(events == self.events.capacity());
+        }
+        if let Some(adaptive) = self.adaptive.as_mut() {
+            adaptive.on_poll(events, Instant::now());
+        }
         for ev in self.events.iter() {
             let token = ev.token();
             let stream = io_nodes
@@ -62,6 +286,9 @@ impl<S: Source + Selectable> Selector for MioSelector<S> {
             }
             if ev.is_readable() {
                 stream.make_readable()?;
+                if let Some(oneshot) = self.oneshot.as_mut() {
+                    oneshot.push(token);
+                }
             }
         }
         Ok(())
@@ -73,6 +300,30 @@ impl<S: Source + Selectable> Selector for MioSelector<S> {
         self.next_token += 1;
         token
     }
+
+    /// The stream is removed from the registry while paused as the interest can not be empty. The
+    /// write interest is restored too, so a connection paused before it has been established still
+    /// gets to flush its buffered writes.
+    fn set_read_interest<E>(
+        &mut self,
+        selector_token: SelectorToken,
+        io_node: &mut IONode<Self::Target, E>,
+        readable: bool,
+    ) -> io::Result<()> {
+        let registry = self.poll.registry();
+        match readable {
+            true => registry.register(
+                io_node.as_stream_mut(),
+                Token(selector_token as usize),
+                Interest::READABLE | Interest::WRITABLE,
+            ),
+            false => registry.deregister(io_node.as_stream_mut()),
+        }
+    }
+
+    fn set_park_timeout(&mut self, timeout: Option<Duration>) {
+        self.park_timeout = timeout;
+    }
 }
 
 impl<E: Endpoint> IntoIOService<E> for MioSelector<E::Target> {
@@ -94,3 +345,73 @@ impl<C: Context, E: EndpointWithContext<C>> IntoIOServiceWithContext<E, C> for M
         IOService::new(self, SystemTimeClockSource, BlockingDnsResolver)
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::util::is_cloexec;
+    use std::os::fd::AsRawFd;
+
+    #[test]
+    fn should_create_poll_with_cloexec() {
+        let selector = MioSelector::<mio::net::TcpStream>::new().unwrap();
+        assert!(is_cloexec(selector.poll.as_raw_fd()).unwrap());
+    }
+
+    #[test]
+    fn should_report_wakeup_stats() {
+        let stats = SelectorStats {
+            polls: 10,
+            wakeups: 4,
+            events: 10,
+            saturated: 1,
+            elapsed: Duration::from_millis(500),
+        };
+        assert_eq!(8.0, stats.wakeups_per_sec());
+        assert_eq!(2.5, stats.events_per_wakeup());
+        assert_eq!(0.0, SelectorStats::default().events_per_wakeup());
+
+        let mut selector = MioSelector::<crate::stream::mio::MioStream>::new()
+            .unwrap()
+            .with_event_capacity(16)
+            .with_oneshot(true);
+        assert_eq!(16, selector.event_capacity());
+        assert!(selector.is_oneshot());
+        selector.poll::<()>(&mut HashMap::new()).unwrap();
+        let stats = selector.take_stats();
+        assert_eq!((1, 0, 0), (stats.polls, stats.wakeups, stats.events));
+        assert_eq!(0, selector.stats().polls);
+    }
+
+    #[test]
+    fn should_switch_between_spin_and_park_with_hysteresis() {
+        let config = AdaptivePoll {
+            spin_above: 100,
+            park_below: 10,
+            window: Duration::from_secs(1),
+            park_timeout: Duration::from_millis(5),
+        };
+        let start = Instant::now();
+        let mut state = AdaptiveState::new(config, start);
+        assert_eq!(NO_WAIT, state.timeout());
+
+        // quiet window parks the selector
+        state.on_poll(5, start + Duration::from_secs(1));
+        assert!(!state.spinning);
+        assert_eq!(Some(Duration::from_millis(5)), state.timeout());
+
+        // rate between the thresholds keeps the current mode
+        state.on_poll(50, start + Duration::from_secs(2));
+        assert!(!state.spinning);
+
+        // busy window (accumulated over several polls) starts spinning
+        state.on_poll(60, start + Duration::from_millis(2500));
+        assert!(!state.spinning);
+        state.on_poll(60, start + Duration::from_secs(3));
+        assert!(state.spinning);
+
+        // rate between the thresholds keeps spinning
+        state.on_poll(50, start + Duration::from_secs(4));
+        assert!(state.spinning);
+    }
+}
@@ -3,6 +3,7 @@
 use crate::service::node::IONode;
 use std::collections::HashMap;
 use std::io;
+use std::time::Duration;
 
 pub mod direct;
 #[cfg(feature = "mio")]
@@ -17,6 +18,31 @@ pub trait Selectable {
     fn make_writable(&mut self) -> io::Result<()>;
 
     fn make_readable(&mut self) -> io::Result<()>;
+
+    /// Returns `true` if any bytes have been received since the previous call, used by the service
+    /// to enforce the [read timeout](crate::stream::ConnectionInfo::with_read_timeout). Wrappers must
+    /// forward it to the stream they read from.
+    fn take_read_activity(&mut self) -> bool;
+
+    /// Stop (`true`) or restart (`false`) reading from the socket, used by the service to apply
+    /// [backpressure](crate::service::IOService::pause_reading). While paused reads fail with
+    /// `WouldBlock` so the received bytes stay in the kernel and the TCP receive window closes.
+    /// Streams that can not pause keep reading.
+    fn set_read_paused(&mut self, _paused: bool) {}
+
+    /// Returns the number of bytes received since the previous call, used by the service to enforce
+    /// the [tenant read rate](crate::service::tenant::TenantQuota::with_max_read_rate). Streams that
+    /// do not count reads report nothing and are never throttled.
+    fn take_bytes_read(&mut self) -> u64 {
+        0
+    }
+
+    /// Returns the number of bytes accepted for sending since the previous call, used by the service to
+    /// enforce the [tenant write rate](crate::service::tenant::TenantQuota::with_max_write_rate). Streams
+    /// that do not count writes report nothing and are never throttled.
+    fn take_bytes_written(&mut self) -> u64 {
+        0
+    }
 }
 
 pub trait Selector {
@@ -29,4 +55,19 @@ pub trait Selector {
     fn poll<E>(&mut self, io_nodes: &mut HashMap<SelectorToken, IONode<Self::Target, E>>) -> io::Result<()>;
 
     fn next_token(&mut self) -> SelectorToken;
+
+    /// Remove (`false`) or restore (`true`) the read interest of the registered `io_node`, the
+    /// service sets [`IONode::read_paused`] or [`IONode::read_throttled`] before calling it.
+    fn set_read_interest<E>(
+        &mut self,
+        _selector_token: SelectorToken,
+        _io_node: &mut IONode<Self::Target, E>,
+        _readable: bool,
+    ) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called by the service when entering (`Some`) or leaving (`None`) a scheduled quiet period.
+    /// Selectors that can block should wait up to `timeout` for events instead of busy spinning.
+    fn set_park_timeout(&mut self, _timeout: Option<Duration>) {}
 }
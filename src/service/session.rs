@@ -0,0 +1,459 @@
+//! Zero-config market data session on top of the [`IOService`].
+//!
+//! Wiring up a venue feed by hand means writing an endpoint, a subscription codec, sharding the symbols
+//! across connections, resubscribing after reconnects, sending the venue heartbeats and extracting the
+//! symbol from every message. [`MarketDataSession`] does all of it from a [`Venue`] preset, the symbol
+//! list and a handler closure, while still running on the same non-blocking internals (mio selector,
+//! TLS websockets, batch reads) as a hand written setup.
+//!
+//! Symbols are given in the venue notation (e.g. `BTCUSDT`), they are interned into the session
+//! [`SymbolTable`] and every [`Event`] carries the [`SymbolId`] of the message together with its raw
+//! payload, so the handler only decodes the fields it needs (see [`codec`](crate::codec)). Messages that
+//! do not belong to a subscribed symbol (subscription acks, pongs) are counted but not delivered.
+//!
+//! ## Examples
+//! ```no_run
+//! use boomnet::codec::json;
+//! use boomnet::service::session::{Feed, MarketDataSession, Venue};
+//!
+//! fn main() -> std::io::Result<()> {
+//!     let symbols = ["BTCUSDT", "ETHUSDT", "SOLUSDT"];
+//!     MarketDataSession::new(Venue::BinanceSpot, symbols, |event| {
+//!         let bid = json::get(event.payload, b"b").and_then(json::unquote).unwrap_or_default();
+//!         println!("{} {}", event.symbol, String::from_utf8_lossy(bid));
+//!     })?
+//!     .with_feed(Feed::BestBidOffer)
+//!     .with_symbols_per_connection(2)
+//!     .run()
+//! }
+//! ```
+
+use crate::codec::json;
+use crate::service::dns::BlockingDnsResolver;
+use crate::service::endpoint::{DisconnectReason, Endpoint};
+use crate::service::select::mio::MioSelector;
+use crate::service::subscription::{SubscriptionCache, SubscriptionCodec};
+use crate::service::time::{SystemTimeClockSource, TimeSource};
+use crate::service::{Handle, IOService, IntoIOService};
+use crate::stream::mio::{IntoMioStream, MioStream};
+use crate::stream::tcp::TcpStream;
+use crate::stream::tls::TlsStream;
+use crate::stream::{ConnectionInfo, ConnectionInfoProvider};
+use crate::symbol::{SymbolId, SymbolTable};
+use crate::ws::util::parse_url;
+use crate::ws::{IntoTlsWebsocket, Websocket, WebsocketFrame};
+use std::fmt::Display;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+type SessionWebsocket = Websocket<TlsStream<MioStream>>;
+type SessionService =
+    IOService<MioSelector<SessionWebsocket>, SessionEndpoint, (), SystemTimeClockSource, BlockingDnsResolver>;
+
+/// Venue connection preset: url, subscription protocol, per connection limits, heartbeat and the
+/// location of the symbol in the messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Venue {
+    /// Binance spot (`wss://stream.binance.com:9443/ws`).
+    BinanceSpot,
+    /// Binance USD-M futures (`wss://fstream.binance.com/ws`).
+    BinanceFutures,
+    /// Bybit spot (`wss://stream.bybit.com/v5/public/spot`).
+    BybitSpot,
+}
+
+/// Market data stream subscribed for every symbol.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Feed {
+    /// Top of the book (default).
+    #[default]
+    BestBidOffer,
+    /// Public trades.
+    Trades,
+}
+
+impl Display for Venue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Venue::BinanceSpot => write!(f, "binance-spot"),
+            Venue::BinanceFutures => write!(f, "binance-futures"),
+            Venue::BybitSpot => write!(f, "bybit-spot"),
+        }
+    }
+}
+
+impl Venue {
+    /// Websocket url of the public market data.
+    pub const fn url(&self) -> &'static str {
+        match self {
+            Venue::BinanceSpot => "wss://stream.binance.com:9443/ws",
+            Venue::BinanceFutures => "wss://fstream.binance.com/ws",
+            Venue::BybitSpot => "wss://stream.bybit.com/v5/public/spot",
+        }
+    }
+
+    /// Maximum number of streams the venue accepts on a single connection.
+    pub const fn max_topics_per_connection(&self) -> usize {
+        match self {
+            Venue::BinanceSpot => 1024,
+            Venue::BinanceFutures => 200,
+            Venue::BybitSpot => 50,
+        }
+    }
+
+    /// Maximum number of streams per subscription request.
+    pub const fn max_topics_per_frame(&self) -> usize {
+        match self {
+            Venue::BinanceSpot | Venue::BinanceFutures => 200,
+            Venue::BybitSpot => 10,
+        }
+    }
+
+    /// Application level heartbeat frame and the interval it has to be sent at (if the venue needs
+    /// one, protocol pings are answered by the websocket).
+    pub const fn heartbeat(&self) -> Option<(&'static [u8], Duration)> {
+        match self {
+            Venue::BinanceSpot | Venue::BinanceFutures => None,
+            Venue::BybitSpot => Some((br#"{"op":"ping"}"#, Duration::from_secs(20))),
+        }
+    }
+
+    /// Time the connection can go without receiving anything (data, pings or heartbeat replies)
+    /// before it is considered stale and reconnected.
+    pub const fn read_timeout(&self) -> Duration {
+        match self {
+            // pings every 20 seconds
+            Venue::BinanceSpot => Duration::from_secs(60),
+            // pings every 3 minutes
+            Venue::BinanceFutures => Duration::from_secs(600),
+            Venue::BybitSpot => Duration::from_secs(60),
+        }
+    }
+
+    /// Stream name of the `feed` for the `symbol`.
+    pub fn topic(&self, feed: Feed, symbol: &str) -> String {
+        match (self, feed) {
+            (Venue::BinanceSpot | Venue::BinanceFutures, Feed::BestBidOffer) => {
+                format!("{}@bookTicker", symbol.to_lowercase())
+            }
+            (Venue::BinanceSpot | Venue::BinanceFutures, Feed::Trades) => format!("{}@trade", symbol.to_lowercase()),
+            (Venue::BybitSpot, Feed::BestBidOffer) => format!("orderbook.1.{symbol}"),
+            (Venue::BybitSpot, Feed::Trades) => format!("publicTrade.{symbol}"),
+        }
+    }
+
+    /// Symbol the market data `message` refers to, `None` for anything else (e.g. subscription acks).
+    pub fn symbol<'a>(&self, message: &'a [u8]) -> Option<&'a [u8]> {
+        match self {
+            Venue::BinanceSpot | Venue::BinanceFutures => json::get(message, b"s").and_then(json::unquote),
+            Venue::BybitSpot => {
+                let topic = json::get(message, b"topic").and_then(json::unquote)?;
+                topic.rsplit(|b| *b == b'.').next()
+            }
+        }
+    }
+}
+
+/// Subscription requests of the venue.
+#[derive(Debug, Clone, Copy)]
+struct VenueCodec(Venue);
+
+impl VenueCodec {
+    fn encode(&self, subscribe: bool, topics: &[&str], buf: &mut Vec<u8>) {
+        let topics = topics
+            .iter()
+            .map(|topic| format!(r#""{topic}""#))
+            .collect::<Vec<_>>()
+            .join(",");
+        let request = match (self.0, subscribe) {
+            (Venue::BinanceSpot | Venue::BinanceFutures, true) => {
+                format!(r#"{{"method":"SUBSCRIBE","params":[{topics}],"id":1}}"#)
+            }
+            (Venue::BinanceSpot | Venue::BinanceFutures, false) => {
+                format!(r#"{{"method":"UNSUBSCRIBE","params":[{topics}],"id":2}}"#)
+            }
+            (Venue::BybitSpot, true) => format!(r#"{{"op":"subscribe","args":[{topics}]}}"#),
+            (Venue::BybitSpot, false) => format!(r#"{{"op":"unsubscribe","args":[{topics}]}}"#),
+        };
+        buf.extend_from_slice(request.as_bytes());
+    }
+}
+
+impl SubscriptionCodec for VenueCodec {
+    fn encode_subscribe(&mut self, topics: &[&str], buf: &mut Vec<u8>) {
+        self.encode(true, topics, buf)
+    }
+
+    fn encode_unsubscribe(&mut self, topics: &[&str], buf: &mut Vec<u8>) {
+        self.encode(false, topics, buf)
+    }
+}
+
+/// Market data message delivered to the session handler.
+#[derive(Debug)]
+pub struct Event<'a> {
+    pub venue: Venue,
+    /// Interned symbol of the message, resolved with [`MarketDataSession::symbols`].
+    pub symbol: SymbolId,
+    /// Connection (shard) the message has been received on.
+    pub connection: Handle,
+    /// Time the batch containing the message has been read.
+    pub received_ns: u64,
+    /// Raw message, only valid within the handler.
+    pub payload: &'a [u8],
+}
+
+/// Session counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SessionStats {
+    /// Number of connections the symbols have been sharded across.
+    pub connections: usize,
+    /// Number of connections currently live.
+    pub live: usize,
+    /// Messages delivered to the handler.
+    pub messages: u64,
+    /// Messages that did not belong to any subscribed symbol (acks, heartbeat replies).
+    pub ignored: u64,
+    /// Number of reconnects across all connections.
+    pub reconnects: u64,
+}
+
+/// Single shard of the session.
+struct SessionEndpoint {
+    venue: Venue,
+    connection_info: ConnectionInfo,
+    path: String,
+    subscriptions: SubscriptionCache<VenueCodec>,
+    next_heartbeat_ns: u64,
+    reconnects: u64,
+}
+
+impl ConnectionInfoProvider for SessionEndpoint {
+    fn connection_info(&self) -> &ConnectionInfo {
+        &self.connection_info
+    }
+}
+
+impl Endpoint for SessionEndpoint {
+    type Target = SessionWebsocket;
+
+    fn create_target(&mut self, addr: SocketAddr) -> io::Result<Option<Self::Target>> {
+        let mut ws = TcpStream::try_from((&self.connection_info, addr))?
+            .into_mio_stream()
+            .into_tls_websocket(&self.path)?;
+        // buffered until the handshake completes
+        self.subscriptions
+            .resubscribe(|frame| Ok(ws.send_text(true, Some(frame))?))?;
+        self.next_heartbeat_ns = 0;
+        Ok(Some(ws))
+    }
+
+    fn can_recreate(&mut self, reason: DisconnectReason) -> bool {
+        log::warn!("{} connection lost: {reason}", self.venue);
+        self.subscriptions.on_disconnected();
+        self.subscriptions.prepare();
+        self.reconnects += 1;
+        true
+    }
+}
+
+impl SessionEndpoint {
+    /// Send the venue heartbeat if due.
+    #[inline]
+    fn heartbeat(&mut self, ws: &mut SessionWebsocket, now_ns: u64) -> io::Result<()> {
+        if let Some((frame, interval)) = self.venue.heartbeat() {
+            if now_ns >= self.next_heartbeat_ns {
+                if self.next_heartbeat_ns != 0 {
+                    ws.send_text(true, Some(frame))?;
+                }
+                self.next_heartbeat_ns = now_ns.saturating_add(interval.as_nanos() as u64);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Market data of a single venue delivered to one handler, see the [module](self) documentation.
+pub struct MarketDataSession<H> {
+    venue: Venue,
+    feed: Feed,
+    symbols_per_connection: usize,
+    symbols: SymbolTable,
+    handler: H,
+    io_service: SessionService,
+    connections: usize,
+    messages: u64,
+    ignored: u64,
+}
+
+impl<H: FnMut(Event<'_>)> MarketDataSession<H> {
+    /// Create session receiving the `symbols` of the `venue` and passing every message to the
+    /// `handler`. Connections are only created on the first [`poll`](Self::poll).
+    pub fn new<I>(venue: Venue, symbols: I, handler: H) -> io::Result<MarketDataSession<H>>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let symbols = symbols.into_iter().collect::<Vec<_>>();
+        if symbols.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no symbols to subscribe"));
+        }
+        let mut table = SymbolTable::with_capacity(symbols.len());
+        for symbol in &symbols {
+            table.intern(&symbol.as_ref().to_uppercase())?;
+        }
+        Ok(Self {
+            venue,
+            feed: Feed::default(),
+            symbols_per_connection: venue.max_topics_per_connection(),
+            symbols: table,
+            handler,
+            io_service: MioSelector::new()?.into_io_service(),
+            connections: 0,
+            messages: 0,
+            ignored: 0,
+        })
+    }
+
+    /// Subscribe to the `feed` instead of the default [`Feed::BestBidOffer`].
+    pub fn with_feed(self, feed: Feed) -> Self {
+        Self { feed, ..self }
+    }
+
+    /// Shard the symbols across connections carrying at most `symbols_per_connection` each (capped by
+    /// the venue limit), by default as few connections as the venue allows.
+    pub fn with_symbols_per_connection(self, symbols_per_connection: usize) -> Self {
+        let symbols_per_connection = symbols_per_connection.clamp(1, self.venue.max_topics_per_connection());
+        Self {
+            symbols_per_connection,
+            ..self
+        }
+    }
+
+    /// Interned symbols of the session.
+    pub const fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// Current session counters.
+    pub fn stats(&self) -> SessionStats {
+        let active = self.io_service.iter().map(|(_, _, endpoint)| endpoint);
+        let pending = self.io_service.pending().map(|(_, endpoint)| endpoint);
+        let reconnects = active.chain(pending).map(|endpoint| endpoint.reconnects).sum();
+        SessionStats {
+            connections: self.connections,
+            live: self.io_service.iter().count(),
+            messages: self.messages,
+            ignored: self.ignored,
+            reconnects,
+        }
+    }
+
+    /// Single session iteration: (re)connect shards, send due heartbeats and deliver the messages of
+    /// one batch from every live connection to the handler.
+    pub fn poll(&mut self) -> io::Result<()> {
+        if self.connections == 0 {
+            self.start()?;
+        }
+        let venue = self.venue;
+        let symbols = &self.symbols;
+        let handler = &mut self.handler;
+        let (messages, ignored) = (&mut self.messages, &mut self.ignored);
+        self.io_service.poll_once_with_handle(|connection, ws, endpoint| {
+            let received_ns = SystemTimeClockSource.current_time_nanos();
+            endpoint.heartbeat(ws, received_ns)?;
+            let mut delivered = 0;
+            for frame in ws.read_batch()? {
+                if let WebsocketFrame::Text(true, payload) = frame? {
+                    match venue.symbol(payload).and_then(|symbol| symbols.get(symbol)) {
+                        Some(symbol) => {
+                            handler(Event {
+                                venue,
+                                symbol,
+                                connection,
+                                received_ns,
+                                payload,
+                            });
+                            delivered += 1;
+                        }
+                        None => *ignored += 1,
+                    }
+                }
+            }
+            *messages += delivered as u64;
+            Ok(delivered)
+        })?;
+        Ok(())
+    }
+
+    /// Keep polling the session until an unrecoverable error.
+    pub fn run(mut self) -> io::Result<()> {
+        loop {
+            self.poll()?;
+        }
+    }
+
+    /// Shard the symbols and register a connection for each shard.
+    #[cold]
+    fn start(&mut self) -> io::Result<()> {
+        let (connection_info, path, _) = parse_url(self.venue.url())?;
+        let connection_info = connection_info.with_read_timeout(self.venue.read_timeout());
+        let reader = self.symbols.reader();
+        let symbols = reader.iter().map(|(_, symbol)| symbol).collect::<Vec<_>>();
+        for shard in symbols.chunks(self.symbols_per_connection) {
+            let mut subscriptions = SubscriptionCache::new(VenueCodec(self.venue))
+                .with_max_topics_per_frame(self.venue.max_topics_per_frame());
+            for symbol in shard {
+                subscriptions.subscribe(self.venue.topic(self.feed, symbol));
+            }
+            subscriptions.prepare();
+            self.io_service.register(SessionEndpoint {
+                venue: self.venue,
+                connection_info: connection_info.clone(),
+                path: path.clone(),
+                subscriptions,
+                next_heartbeat_ns: 0,
+                reconnects: 0,
+            })?;
+            self.connections += 1;
+        }
+        log::info!(
+            "{} session started: {} symbol(s) over {} connection(s)",
+            self.venue,
+            symbols.len(),
+            self.connections
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_apply_venue_preset() {
+        let mut codec = VenueCodec(Venue::BinanceSpot);
+        let topic = Venue::BinanceSpot.topic(Feed::BestBidOffer, "BTCUSDT");
+        assert_eq!("btcusdt@bookTicker", topic);
+        let mut buf = vec![];
+        codec.encode_subscribe(&[&topic, "ethusdt@bookTicker"], &mut buf);
+        assert_eq!(
+            br#"{"method":"SUBSCRIBE","params":["btcusdt@bookTicker","ethusdt@bookTicker"],"id":1}"#,
+            buf.as_slice()
+        );
+        let ticker = br#"{"u":400900217,"s":"BTCUSDT","b":"25.35","B":"31.21","a":"25.36","A":"40.66"}"#;
+        assert_eq!(Some(&b"BTCUSDT"[..]), Venue::BinanceSpot.symbol(ticker));
+        assert_eq!(None, Venue::BinanceSpot.symbol(br#"{"result":null,"id":1}"#));
+
+        let mut codec = VenueCodec(Venue::BybitSpot);
+        let topic = Venue::BybitSpot.topic(Feed::Trades, "BTCUSDT");
+        let mut buf = vec![];
+        codec.encode_unsubscribe(&[&topic], &mut buf);
+        assert_eq!(br#"{"op":"unsubscribe","args":["publicTrade.BTCUSDT"]}"#, buf.as_slice());
+        let trade = br#"{"topic":"publicTrade.BTCUSDT","ts":1672304486868,"type":"snapshot","data":[]}"#;
+        assert_eq!(Some(&b"BTCUSDT"[..]), Venue::BybitSpot.symbol(trade));
+        assert_eq!(None, Venue::BybitSpot.symbol(br#"{"success":true,"ret_msg":"pong","op":"ping"}"#));
+    }
+}
@@ -0,0 +1,309 @@
+//! Periodic snapshot of the endpoint logical state for crash recovery.
+//!
+//! Endpoints that implement [`EndpointState`] serialize their logical state (subscriptions, last
+//! sequence numbers, resume tokens) into a [`SnapshotStore`] that is written to disk at a configurable
+//! interval. On startup the store loads the last snapshot and every endpoint [restores](SnapshotStore::restore)
+//! its state before it is registered, so that a crashed process resumes with a gap request from the
+//! last known sequence number rather than with a cold full resync.
+//!
+//! The snapshot is written to a temporary file that is synced and then atomically renamed over the
+//! previous one, so a crash in the middle of the write never leaves a torn snapshot behind. Since it is
+//! written periodically the restored state can be up to one interval old, which the gap request covers.
+//!
+//! ## Examples
+//! ```no_run
+//! use std::io;
+//! use std::time::Duration;
+//! use boomnet::service::snapshot::{EndpointState, SnapshotStore};
+//!
+//! struct FeedEndpoint {
+//!     name: String,
+//!     last_sequence: u64,
+//! }
+//!
+//! impl EndpointState for FeedEndpoint {
+//!     fn state_key(&self) -> &str {
+//!         &self.name
+//!     }
+//!
+//!     fn save_state(&self, buf: &mut Vec<u8>) {
+//!         buf.extend_from_slice(&self.last_sequence.to_le_bytes());
+//!     }
+//!
+//!     fn restore_state(&mut self, state: &[u8]) -> io::Result<()> {
+//!         let state = state.try_into().map_err(|_| io::Error::other("invalid feed state"))?;
+//!         self.last_sequence = u64::from_le_bytes(state);
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let mut store = SnapshotStore::open("/var/lib/feed/endpoints.snapshot")
+//!     .unwrap()
+//!     .with_interval(Duration::from_secs(1));
+//! let mut endpoint = FeedEndpoint { name: "binance-spot".to_owned(), last_sequence: 0 };
+//! if store.restore(&mut endpoint).unwrap() {
+//!     // request the gap from `endpoint.last_sequence` once connected
+//! }
+//! // io_service.register(endpoint) and then on every iteration of the event loop:
+//! // io_service.snapshot(&mut store)?;
+//! ```
+
+use crate::service::IOService;
+use crate::service::dns::DnsResolver;
+use crate::service::select::Selector;
+use crate::service::time::{SystemTimeClockSource, TimeSource};
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::ErrorKind::{InvalidData, NotFound};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const MAGIC: &[u8; 4] = b"BNSS";
+const VERSION: u8 = 1;
+
+/// Logical state of the endpoint that survives the process restart.
+pub trait EndpointState {
+    /// Key identifying the endpoint across process restarts (the handle is not stable).
+    fn state_key(&self) -> &str;
+
+    /// Append the current state to the `buf`.
+    fn save_state(&self, buf: &mut Vec<u8>);
+
+    /// Restore the state saved by [`save_state`](Self::save_state) by the previous process.
+    fn restore_state(&mut self, state: &[u8]) -> io::Result<()>;
+}
+
+/// Snapshot file of the endpoint states, written periodically.
+#[derive(Debug)]
+pub struct SnapshotStore<TS = SystemTimeClockSource> {
+    path: PathBuf,
+    interval_ns: u64,
+    last_save_ns: Option<u64>,
+    restored: HashMap<String, Vec<u8>>,
+    buf: Vec<u8>,
+    time_source: TS,
+}
+
+impl SnapshotStore {
+    /// Open store at the `path` loading the previous snapshot (if any), snapshot is saved every second by default.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::open_with_time_source(path, SystemTimeClockSource)
+    }
+}
+
+impl<TS: TimeSource> SnapshotStore<TS> {
+    /// Open store at the `path` using custom [`TimeSource`].
+    pub fn open_with_time_source(path: impl AsRef<Path>, time_source: TS) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let restored = match fs::read(&path) {
+            Ok(snapshot) => decode(&snapshot)?,
+            Err(err) if err.kind() == NotFound => HashMap::new(),
+            Err(err) => return Err(err),
+        };
+        if !restored.is_empty() {
+            log::info!("loaded snapshot of {} endpoint(s) from {}", restored.len(), path.display());
+        }
+        Ok(Self {
+            path,
+            interval_ns: Duration::from_secs(1).as_nanos() as u64,
+            last_save_ns: None,
+            restored,
+            buf: Vec::new(),
+            time_source,
+        })
+    }
+
+    /// How often the snapshot is written by [`poll`](Self::poll).
+    pub fn with_interval(self, interval: Duration) -> Self {
+        Self {
+            interval_ns: interval.as_nanos() as u64,
+            ..self
+        }
+    }
+
+    /// Restore the `endpoint` from the loaded snapshot, should be called before the endpoint is
+    /// registered. Returns `false` if there is no saved state for the endpoint.
+    pub fn restore<E: EndpointState>(&mut self, endpoint: &mut E) -> io::Result<bool> {
+        match self.restored.remove(endpoint.state_key()) {
+            Some(state) => {
+                endpoint.restore_state(&state)?;
+                log::info!("restored state of endpoint {}", endpoint.state_key());
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Write the snapshot of the `endpoints` if the interval has elapsed since the last one,
+    /// returns `true` if the snapshot has been written.
+    pub fn poll<'a, E, I>(&mut self, endpoints: I) -> io::Result<bool>
+    where
+        E: EndpointState + 'a,
+        I: IntoIterator<Item = &'a E>,
+    {
+        let now = self.time_source.current_time_nanos();
+        if let Some(last_save_ns) = self.last_save_ns {
+            if now.saturating_sub(last_save_ns) < self.interval_ns {
+                return Ok(false);
+            }
+        }
+        self.save(endpoints)?;
+        Ok(true)
+    }
+
+    /// Write the snapshot of the `endpoints` straight away (e.g. on graceful shutdown).
+    pub fn save<'a, E, I>(&mut self, endpoints: I) -> io::Result<()>
+    where
+        E: EndpointState + 'a,
+        I: IntoIterator<Item = &'a E>,
+    {
+        self.last_save_ns = Some(self.time_source.current_time_nanos());
+        self.buf.clear();
+        self.buf.extend_from_slice(MAGIC);
+        self.buf.push(VERSION);
+        let mut state = Vec::new();
+        for endpoint in endpoints {
+            state.clear();
+            endpoint.save_state(&mut state);
+            put(&mut self.buf, endpoint.state_key().as_bytes());
+            put(&mut self.buf, &state);
+        }
+        let tmp = self.path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&self.buf)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+fn put(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+fn take<'a>(snapshot: &mut &'a [u8]) -> io::Result<&'a [u8]> {
+    let invalid = || io::Error::new(InvalidData, "truncated snapshot");
+    let (len, rest) = snapshot.split_first_chunk::<4>().ok_or_else(invalid)?;
+    let len = u32::from_le_bytes(*len) as usize;
+    if rest.len() < len {
+        return Err(invalid());
+    }
+    let (bytes, rest) = rest.split_at(len);
+    *snapshot = rest;
+    Ok(bytes)
+}
+
+fn decode(snapshot: &[u8]) -> io::Result<HashMap<String, Vec<u8>>> {
+    let Some(mut records) = snapshot
+        .strip_prefix(MAGIC)
+        .and_then(|rest| rest.strip_prefix(&[VERSION]))
+    else {
+        return Err(io::Error::new(InvalidData, "unknown snapshot format"));
+    };
+    let mut restored = HashMap::new();
+    while !records.is_empty() {
+        let key = take(&mut records)?;
+        let key = std::str::from_utf8(key).map_err(|err| io::Error::new(InvalidData, err))?;
+        let state = take(&mut records)?;
+        restored.insert(key.to_owned(), state.to_vec());
+    }
+    Ok(restored)
+}
+
+impl<S, E, C, TS, D> IOService<S, E, C, TS, D>
+where
+    S: Selector,
+    E: EndpointState,
+    TS: TimeSource,
+    D: DnsResolver,
+{
+    /// Write the snapshot of all registered endpoints (active, pending and parked) if the `store`
+    /// interval has elapsed, should be called on every iteration of the event loop. Returns `true`
+    /// if the snapshot has been written.
+    pub fn snapshot<STS: TimeSource>(&self, store: &mut SnapshotStore<STS>) -> io::Result<bool> {
+        let active = self.io_nodes.values().map(|io_node| &io_node.as_parts().1.1);
+        let pending = self.pending_endpoints.iter().map(|(_, _, _, endpoint, _)| endpoint);
+        let parked = self.parked_endpoints.iter().map(|(_, endpoint)| endpoint);
+        store.poll(active.chain(pending).chain(parked))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    const SECOND: u64 = 1_000_000_000;
+
+    #[derive(Clone, Default)]
+    struct ManualClock(Rc<Cell<u64>>);
+
+    impl TimeSource for ManualClock {
+        fn current_time_nanos(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    struct FeedEndpoint {
+        name: &'static str,
+        last_sequence: u64,
+    }
+
+    impl EndpointState for FeedEndpoint {
+        fn state_key(&self) -> &str {
+            self.name
+        }
+
+        fn save_state(&self, buf: &mut Vec<u8>) {
+            buf.extend_from_slice(&self.last_sequence.to_le_bytes());
+        }
+
+        fn restore_state(&mut self, state: &[u8]) -> io::Result<()> {
+            let state = state
+                .try_into()
+                .map_err(|_| io::Error::new(InvalidData, "invalid feed state"))?;
+            self.last_sequence = u64::from_le_bytes(state);
+            Ok(())
+        }
+    }
+
+    fn endpoint(name: &'static str, last_sequence: u64) -> FeedEndpoint {
+        FeedEndpoint { name, last_sequence }
+    }
+
+    #[test]
+    fn should_restore_state_saved_by_previous_process() {
+        let dir = std::env::temp_dir().join(format!("boomnet-snapshot-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("endpoints.snapshot");
+        let _ = fs::remove_file(&path);
+
+        let clock = ManualClock::default();
+        let mut store = SnapshotStore::open_with_time_source(&path, clock.clone()).unwrap();
+        let endpoints = [endpoint("spot", 100), endpoint("futures", 200)];
+        // first snapshot is written straight away, then once per interval
+        assert!(store.poll(&endpoints).unwrap());
+        let endpoints = [endpoint("spot", 150), endpoint("futures", 250)];
+        clock.0.set(SECOND - 1);
+        assert!(!store.poll(&endpoints).unwrap());
+        clock.0.set(SECOND);
+        assert!(store.poll(&endpoints).unwrap());
+
+        let mut store = SnapshotStore::open(&path).unwrap();
+        let mut spot = endpoint("spot", 0);
+        let mut options = endpoint("options", 0);
+        assert!(store.restore(&mut spot).unwrap());
+        assert!(!store.restore(&mut options).unwrap());
+        assert_eq!(150, spot.last_sequence);
+        // state is handed out only once
+        assert!(!store.restore(&mut spot).unwrap());
+
+        fs::write(&path, b"BNSS\x01\x05\x00").unwrap();
+        assert_eq!(InvalidData, SnapshotStore::open(&path).unwrap_err().kind());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,353 @@
+//! Warm standby connections that can be promoted to full subscription in one message.
+//!
+//! Endpoints that belong to the same [`StandbyGroup`] coordinate their role. The first connected member
+//! becomes the primary and subscribes to the full set of streams, every other member stays connected as
+//! a standby with a heartbeat only (or minimal) subscription. When the primary disconnects the first
+//! connected standby is promoted on its next poll, so failover only costs a single subscription message
+//! rather than a new connection and TLS/websocket handshake.
+//!
+//! The group is shared between endpoints polled by the same `IOService` and is therefore not `Send`.
+//!
+//! ## Examples
+//! ```no_run
+//! use std::io;
+//! use std::io::Write;
+//! use std::time::Duration;
+//! use boomnet::service::standby::{StandbyGroup, StandbyMember, StandbySubscriber};
+//!
+//! struct TradeSubscription;
+//!
+//! impl<S: Write> StandbySubscriber<S> for TradeSubscription {
+//!     fn subscribe(&mut self, stream: &mut S) -> io::Result<()> {
+//!         stream.write_all(b"SUBSCRIBE btcusdt@trade btcusdt@depth\n")
+//!     }
+//!
+//!     fn subscribe_standby(&mut self, stream: &mut S) -> io::Result<()> {
+//!         stream.write_all(b"SUBSCRIBE heartbeat\n")
+//!     }
+//!
+//!     fn heartbeat(&mut self, stream: &mut S) -> io::Result<()> {
+//!         stream.write_all(b"PING\n")
+//!     }
+//! }
+//!
+//! struct TradeEndpoint {
+//!     standby: StandbyMember,
+//!     subscription: TradeSubscription,
+//! }
+//!
+//! impl TradeEndpoint {
+//!     fn on_connected<S: Write>(&mut self, stream: &mut S) -> io::Result<()> {
+//!         // full subscription if primary, heartbeat only otherwise
+//!         self.standby.on_connected(stream, &mut self.subscription)?;
+//!         Ok(())
+//!     }
+//!
+//!     fn poll<S: Write>(&mut self, stream: &mut S) -> io::Result<()> {
+//!         // sends promotion or heartbeat when due
+//!         self.standby.poll(stream, &mut self.subscription)?;
+//!         Ok(())
+//!     }
+//!
+//!     fn on_disconnected(&mut self) {
+//!         // promotes the standby if this was the primary
+//!         self.standby.on_disconnected();
+//!     }
+//! }
+//!
+//! let group = StandbyGroup::new(Duration::from_secs(5));
+//! let primary = TradeEndpoint { standby: group.member(), subscription: TradeSubscription };
+//! let standby = TradeEndpoint { standby: group.member(), subscription: TradeSubscription };
+//! ```
+
+use crate::service::time::{SystemTimeClockSource, TimeSource};
+use log::info;
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// Role of the [`StandbyMember`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Role {
+    /// Connected with full subscription.
+    Primary,
+    /// Connected with heartbeat only subscription (or not connected).
+    Standby,
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Role::Primary => write!(f, "primary"),
+            Role::Standby => write!(f, "standby"),
+        }
+    }
+}
+
+/// Protocol specific subscription actions invoked on the member `target` (e.g. websocket).
+pub trait StandbySubscriber<T> {
+    /// Full subscription, sent when the member connects as the primary.
+    fn subscribe(&mut self, target: &mut T) -> io::Result<()>;
+
+    /// Heartbeat only or minimal subscription, sent when the member connects as a standby.
+    fn subscribe_standby(&mut self, _target: &mut T) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Promote connected standby to full subscription, ideally in one message. Defaults to [`StandbySubscriber::subscribe`].
+    fn promote(&mut self, target: &mut T) -> io::Result<()> {
+        self.subscribe(target)
+    }
+
+    /// Keep the standby connection alive, invoked every heartbeat interval while in standby.
+    fn heartbeat(&mut self, _target: &mut T) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct MemberState {
+    connected: bool,
+    role: Role,
+    promotion_pending: bool,
+    next_heartbeat_ns: u64,
+}
+
+struct GroupState<TS> {
+    members: Vec<MemberState>,
+    heartbeat_interval_ns: u64,
+    time_source: TS,
+}
+
+/// Group of endpoints with a single primary and warm standbys.
+pub struct StandbyGroup<TS = SystemTimeClockSource> {
+    state: Rc<RefCell<GroupState<TS>>>,
+}
+
+impl StandbyGroup {
+    /// Create group with standby `heartbeat_interval`.
+    pub fn new(heartbeat_interval: Duration) -> Self {
+        Self::new_with_time_source(heartbeat_interval, SystemTimeClockSource)
+    }
+}
+
+impl<TS: TimeSource> StandbyGroup<TS> {
+    /// Create group with standby `heartbeat_interval` and custom [`TimeSource`].
+    pub fn new_with_time_source(heartbeat_interval: Duration, time_source: TS) -> Self {
+        Self {
+            state: Rc::new(RefCell::new(GroupState {
+                members: Vec::new(),
+                heartbeat_interval_ns: heartbeat_interval.as_nanos() as u64,
+                time_source,
+            })),
+        }
+    }
+
+    /// Add new (not connected) member to the group.
+    pub fn member(&self) -> StandbyMember<TS> {
+        let mut state = self.state.borrow_mut();
+        state.members.push(MemberState {
+            connected: false,
+            role: Role::Standby,
+            promotion_pending: false,
+            next_heartbeat_ns: 0,
+        });
+        StandbyMember {
+            index: state.members.len() - 1,
+            state: self.state.clone(),
+        }
+    }
+
+    /// Index of the current primary member, if any.
+    pub fn primary(&self) -> Option<usize> {
+        self.state
+            .borrow()
+            .members
+            .iter()
+            .position(|member| member.role == Role::Primary)
+    }
+}
+
+/// Member of the [`StandbyGroup`], typically owned by the endpoint.
+pub struct StandbyMember<TS = SystemTimeClockSource> {
+    index: usize,
+    state: Rc<RefCell<GroupState<TS>>>,
+}
+
+impl<TS: TimeSource> StandbyMember<TS> {
+    /// Index of this member within the group.
+    pub const fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Current role of this member.
+    pub fn role(&self) -> Role {
+        self.state.borrow().members[self.index].role
+    }
+
+    /// Returns `true` if this member is the primary.
+    pub fn is_primary(&self) -> bool {
+        self.role() == Role::Primary
+    }
+
+    /// Must be called once the `target` is connected. Becomes the primary (and subscribes in full) if
+    /// there is no other primary, otherwise subscribes as a standby.
+    pub fn on_connected<T>(&self, target: &mut T, subscriber: &mut impl StandbySubscriber<T>) -> io::Result<Role> {
+        let role = {
+            let mut state = self.state.borrow_mut();
+            let has_primary = state
+                .members
+                .iter()
+                .enumerate()
+                .any(|(index, member)| index != self.index && member.role == Role::Primary);
+            let next_heartbeat_ns = state.time_source.current_time_nanos() + state.heartbeat_interval_ns;
+            let member = &mut state.members[self.index];
+            member.connected = true;
+            member.promotion_pending = false;
+            member.next_heartbeat_ns = next_heartbeat_ns;
+            member.role = if has_primary { Role::Standby } else { Role::Primary };
+            member.role
+        };
+        match role {
+            Role::Primary => subscriber.subscribe(target)?,
+            Role::Standby => subscriber.subscribe_standby(target)?,
+        }
+        Ok(role)
+    }
+
+    /// Must be called on every poll. Sends the promotion if this member has just been promoted
+    /// or the heartbeat if it is due. Returns `true` if the promotion has been sent.
+    pub fn poll<T>(&self, target: &mut T, subscriber: &mut impl StandbySubscriber<T>) -> io::Result<bool> {
+        let mut state = self.state.borrow_mut();
+        let now_ns = state.time_source.current_time_nanos();
+        let heartbeat_interval_ns = state.heartbeat_interval_ns;
+        let member = &mut state.members[self.index];
+        if member.promotion_pending {
+            member.promotion_pending = false;
+            drop(state);
+            subscriber.promote(target)?;
+            info!("standby member {} promoted to primary", self.index);
+            return Ok(true);
+        }
+        if member.role == Role::Standby && member.connected && now_ns >= member.next_heartbeat_ns {
+            member.next_heartbeat_ns = now_ns + heartbeat_interval_ns;
+            drop(state);
+            subscriber.heartbeat(target)?;
+        }
+        Ok(false)
+    }
+
+    /// Must be called when the connection is lost (e.g. from `can_recreate`). If this member was the
+    /// primary the first connected standby is promoted. Returns index of the promoted member.
+    pub fn on_disconnected(&self) -> Option<usize> {
+        let mut state = self.state.borrow_mut();
+        let member = &mut state.members[self.index];
+        let was_primary = member.role == Role::Primary;
+        member.connected = false;
+        member.role = Role::Standby;
+        member.promotion_pending = false;
+        if !was_primary {
+            return None;
+        }
+        let (index, standby) = state
+            .members
+            .iter_mut()
+            .enumerate()
+            .find(|(_, member)| member.connected && member.role == Role::Standby)?;
+        standby.role = Role::Primary;
+        standby.promotion_pending = true;
+        info!("primary member {} disconnected, promoting standby member {index}", self.index);
+        Some(index)
+    }
+}
+
+impl<TS> Clone for StandbyMember<TS> {
+    fn clone(&self) -> Self {
+        Self {
+            index: self.index,
+            state: self.state.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[derive(Clone, Default)]
+    struct ManualClock(Rc<Cell<u64>>);
+
+    impl TimeSource for ManualClock {
+        fn current_time_nanos(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    #[derive(Default)]
+    struct Messages(Vec<&'static str>);
+
+    impl StandbySubscriber<()> for Messages {
+        fn subscribe(&mut self, _target: &mut ()) -> io::Result<()> {
+            self.0.push("subscribe");
+            Ok(())
+        }
+
+        fn subscribe_standby(&mut self, _target: &mut ()) -> io::Result<()> {
+            self.0.push("standby");
+            Ok(())
+        }
+
+        fn promote(&mut self, _target: &mut ()) -> io::Result<()> {
+            self.0.push("promote");
+            Ok(())
+        }
+
+        fn heartbeat(&mut self, _target: &mut ()) -> io::Result<()> {
+            self.0.push("heartbeat");
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_promote_standby_on_primary_disconnect() {
+        let clock = ManualClock::default();
+        let group = StandbyGroup::new_with_time_source(Duration::from_secs(1), clock.clone());
+        let (a, b) = (group.member(), group.member());
+        let (mut sub_a, mut sub_b) = (Messages::default(), Messages::default());
+
+        assert_eq!(Role::Primary, a.on_connected(&mut (), &mut sub_a).unwrap());
+        assert_eq!(Role::Standby, b.on_connected(&mut (), &mut sub_b).unwrap());
+        assert!(!b.poll(&mut (), &mut sub_b).unwrap());
+
+        assert_eq!(Some(1), a.on_disconnected());
+        assert_eq!(Some(1), group.primary());
+        assert!(b.poll(&mut (), &mut sub_b).unwrap());
+        assert!(!b.poll(&mut (), &mut sub_b).unwrap());
+
+        // reconnected member becomes the standby
+        assert_eq!(Role::Standby, a.on_connected(&mut (), &mut sub_a).unwrap());
+        assert_eq!(vec!["subscribe", "standby"], sub_a.0);
+        assert_eq!(vec!["standby", "promote"], sub_b.0);
+    }
+
+    #[test]
+    fn should_heartbeat_only_in_standby() {
+        let clock = ManualClock::default();
+        let group = StandbyGroup::new_with_time_source(Duration::from_secs(1), clock.clone());
+        let (a, b) = (group.member(), group.member());
+        let (mut sub_a, mut sub_b) = (Messages::default(), Messages::default());
+        a.on_connected(&mut (), &mut sub_a).unwrap();
+        b.on_connected(&mut (), &mut sub_b).unwrap();
+
+        clock.0.set(Duration::from_millis(1500).as_nanos() as u64);
+        a.poll(&mut (), &mut sub_a).unwrap();
+        b.poll(&mut (), &mut sub_b).unwrap();
+        b.poll(&mut (), &mut sub_b).unwrap();
+
+        assert_eq!(vec!["subscribe"], sub_a.0);
+        assert_eq!(vec!["standby", "heartbeat"], sub_b.0);
+    }
+}
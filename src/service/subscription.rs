@@ -0,0 +1,282 @@
+//! Pre-serialized subscription frames with dirty tracking.
+//!
+//! [`SubscriptionCache`] owns the set of topics an endpoint is subscribed to together with the full
+//! subscription already serialized (by the protocol specific [`SubscriptionCodec`]) into one or more
+//! frames. Resubscribing after a reconnect only hands the cached bytes over to the connection, while
+//! mutations of the set made on a live connection are tracked and sent as deltas (unsubscribe the
+//! removed topics, subscribe the added ones) rather than as the full subscription again. This keeps
+//! both reconnect time and rate limit pressure low.
+//!
+//! ## Examples
+//! ```no_run
+//! use boomnet::service::subscription::{SubscriptionCache, SubscriptionCodec};
+//!
+//! struct BinanceCodec;
+//!
+//! impl BinanceCodec {
+//!     fn encode(method: &str, topics: &[&str], buf: &mut Vec<u8>) {
+//!         buf.extend_from_slice(format!(r#"{{"method":"{method}","params":{topics:?},"id":1}}"#).as_bytes());
+//!     }
+//! }
+//!
+//! impl SubscriptionCodec for BinanceCodec {
+//!     fn encode_subscribe(&mut self, topics: &[&str], buf: &mut Vec<u8>) {
+//!         Self::encode("SUBSCRIBE", topics, buf)
+//!     }
+//!
+//!     fn encode_unsubscribe(&mut self, topics: &[&str], buf: &mut Vec<u8>) {
+//!         Self::encode("UNSUBSCRIBE", topics, buf)
+//!     }
+//! }
+//!
+//! let mut subscriptions = SubscriptionCache::new(BinanceCodec).with_max_topics_per_frame(200);
+//! subscriptions.subscribe("btcusdt@trade");
+//! subscriptions.subscribe("ethusdt@trade");
+//! // serialize ahead of the (re)connect
+//! subscriptions.prepare();
+//!
+//! // once connected: ws.send_text(true, Some(frame))
+//! subscriptions.resubscribe(|frame| Ok(println!("{}", String::from_utf8_lossy(frame)))).unwrap();
+//!
+//! // later on the live connection only the delta is sent
+//! subscriptions.unsubscribe("ethusdt@trade");
+//! subscriptions.subscribe("solusdt@trade");
+//! subscriptions.sync(|frame| Ok(println!("{}", String::from_utf8_lossy(frame)))).unwrap();
+//! ```
+
+use std::collections::BTreeSet;
+use std::io;
+
+/// Protocol specific serialization of the subscription requests.
+pub trait SubscriptionCodec {
+    /// Append request subscribing to all `topics` to the `buf`.
+    fn encode_subscribe(&mut self, topics: &[&str], buf: &mut Vec<u8>);
+
+    /// Append request unsubscribing from all `topics` to the `buf`.
+    fn encode_unsubscribe(&mut self, topics: &[&str], buf: &mut Vec<u8>);
+}
+
+/// Set of subscribed topics with the full subscription cached as pre-built frames.
+#[derive(Debug)]
+pub struct SubscriptionCache<C> {
+    codec: C,
+    topics: BTreeSet<String>,
+    added: BTreeSet<String>,
+    removed: BTreeSet<String>,
+    max_topics_per_frame: usize,
+    frames: Vec<Vec<u8>>,
+    stale: bool,
+    live: bool,
+    scratch: Vec<u8>,
+}
+
+impl<C: SubscriptionCodec> SubscriptionCache<C> {
+    /// Create empty cache using the `codec` to serialize the frames.
+    pub fn new(codec: C) -> SubscriptionCache<C> {
+        Self {
+            codec,
+            topics: BTreeSet::new(),
+            added: BTreeSet::new(),
+            removed: BTreeSet::new(),
+            max_topics_per_frame: usize::MAX,
+            frames: Vec::new(),
+            stale: false,
+            live: false,
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Maximum number of topics per frame (by default unlimited), larger sets are split.
+    pub fn with_max_topics_per_frame(self, max_topics_per_frame: usize) -> Self {
+        Self {
+            max_topics_per_frame: max_topics_per_frame.max(1),
+            stale: true,
+            ..self
+        }
+    }
+
+    /// Add `topic` to the set, returns `false` if already subscribed.
+    pub fn subscribe(&mut self, topic: impl Into<String>) -> bool {
+        let topic = topic.into();
+        if self.topics.contains(&topic) {
+            return false;
+        }
+        self.stale = true;
+        if self.live && !self.removed.remove(&topic) {
+            self.added.insert(topic.clone());
+        }
+        self.topics.insert(topic)
+    }
+
+    /// Remove `topic` from the set, returns `false` if not subscribed.
+    pub fn unsubscribe(&mut self, topic: &str) -> bool {
+        let Some(topic) = self.topics.take(topic) else {
+            return false;
+        };
+        self.stale = true;
+        if self.live && !self.added.remove(&topic) {
+            self.removed.insert(topic);
+        }
+        true
+    }
+
+    /// Subscribed topics in order.
+    pub fn topics(&self) -> impl Iterator<Item = &str> {
+        self.topics.iter().map(String::as_str)
+    }
+
+    /// Checks if there are mutations that have not been sent to the live connection yet.
+    pub fn is_dirty(&self) -> bool {
+        !self.added.is_empty() || !self.removed.is_empty()
+    }
+
+    /// Checks if the subscription has been sent on the current connection.
+    pub const fn is_live(&self) -> bool {
+        self.live
+    }
+
+    /// Cached frames of the full subscription, serialized by [`prepare`](Self::prepare).
+    pub fn frames(&self) -> impl Iterator<Item = &[u8]> {
+        self.frames.iter().map(Vec::as_slice)
+    }
+
+    /// Serialize the full subscription if the set has changed since the last time. Should be called
+    /// off the critical path (e.g. right after disconnect) so the resubscription does not have to.
+    pub fn prepare(&mut self) {
+        if !self.stale {
+            return;
+        }
+        self.frames.clear();
+        let topics = self.topics.iter().map(String::as_str).collect::<Vec<_>>();
+        for chunk in topics.chunks(self.max_topics_per_frame) {
+            let mut frame = Vec::new();
+            self.codec.encode_subscribe(chunk, &mut frame);
+            self.frames.push(frame);
+        }
+        self.stale = false;
+    }
+
+    /// Hand the full subscription to the freshly connected `send`, pending deltas are discarded as
+    /// they are already part of it. Returns the number of frames sent.
+    pub fn resubscribe<F>(&mut self, mut send: F) -> io::Result<usize>
+    where
+        F: FnMut(&[u8]) -> io::Result<()>,
+    {
+        self.prepare();
+        self.added.clear();
+        self.removed.clear();
+        for frame in &self.frames {
+            send(frame)?;
+        }
+        self.live = true;
+        Ok(self.frames.len())
+    }
+
+    /// Send only the mutations made since the last `resubscribe` or `sync` on the live connection.
+    /// Nothing is sent if the connection is not live, the mutations will be part of the next
+    /// `resubscribe`. Returns the number of frames sent.
+    pub fn sync<F>(&mut self, mut send: F) -> io::Result<usize>
+    where
+        F: FnMut(&[u8]) -> io::Result<()>,
+    {
+        if !self.live || !self.is_dirty() {
+            return Ok(0);
+        }
+        let mut sent = 0;
+        let removed = std::mem::take(&mut self.removed);
+        let removed = removed.iter().map(String::as_str).collect::<Vec<_>>();
+        for chunk in removed.chunks(self.max_topics_per_frame) {
+            self.scratch.clear();
+            self.codec.encode_unsubscribe(chunk, &mut self.scratch);
+            send(&self.scratch)?;
+            sent += 1;
+        }
+        let added = std::mem::take(&mut self.added);
+        let added = added.iter().map(String::as_str).collect::<Vec<_>>();
+        for chunk in added.chunks(self.max_topics_per_frame) {
+            self.scratch.clear();
+            self.codec.encode_subscribe(chunk, &mut self.scratch);
+            send(&self.scratch)?;
+            sent += 1;
+        }
+        Ok(sent)
+    }
+
+    /// Must be called when the connection is lost, the next connection needs to `resubscribe`.
+    pub fn on_disconnected(&mut self) {
+        self.live = false;
+        self.added.clear();
+        self.removed.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TextCodec;
+
+    impl SubscriptionCodec for TextCodec {
+        fn encode_subscribe(&mut self, topics: &[&str], buf: &mut Vec<u8>) {
+            buf.extend_from_slice(format!("SUB {}", topics.join(",")).as_bytes());
+        }
+
+        fn encode_unsubscribe(&mut self, topics: &[&str], buf: &mut Vec<u8>) {
+            buf.extend_from_slice(format!("UNSUB {}", topics.join(",")).as_bytes());
+        }
+    }
+
+    fn collect(sent: &mut Vec<String>) -> impl FnMut(&[u8]) -> io::Result<()> + '_ {
+        move |frame| {
+            sent.push(String::from_utf8_lossy(frame).into_owned());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_resubscribe_with_cached_frames() {
+        let mut cache = SubscriptionCache::new(TextCodec).with_max_topics_per_frame(2);
+        cache.subscribe("a");
+        cache.subscribe("b");
+        cache.subscribe("c");
+        assert!(!cache.subscribe("a"));
+        cache.prepare();
+        assert_eq!(vec![&b"SUB a,b"[..], b"SUB c"], cache.frames().collect::<Vec<_>>());
+
+        let mut sent = vec![];
+        assert_eq!(2, cache.resubscribe(collect(&mut sent)).unwrap());
+        assert_eq!(vec!["SUB a,b", "SUB c"], sent);
+        assert!(cache.is_live());
+        assert!(!cache.is_dirty());
+    }
+
+    #[test]
+    fn should_send_only_delta_on_live_connection() {
+        let mut cache = SubscriptionCache::new(TextCodec);
+        cache.subscribe("a");
+        cache.subscribe("b");
+        cache.resubscribe(|_| Ok(())).unwrap();
+
+        cache.unsubscribe("a");
+        cache.subscribe("c");
+        // added and removed again before sync cancels out
+        cache.subscribe("d");
+        cache.unsubscribe("d");
+        assert!(cache.is_dirty());
+
+        let mut sent = vec![];
+        assert_eq!(2, cache.sync(collect(&mut sent)).unwrap());
+        assert_eq!(vec!["UNSUB a", "SUB c"], sent);
+        assert!(!cache.is_dirty());
+        assert_eq!(0, cache.sync(|_| unreachable!()).unwrap());
+
+        // deltas made while disconnected are folded into the next resubscribe
+        cache.on_disconnected();
+        cache.subscribe("e");
+        assert!(!cache.is_dirty());
+        assert_eq!(0, cache.sync(|_| unreachable!()).unwrap());
+        let mut sent = vec![];
+        cache.resubscribe(collect(&mut sent)).unwrap();
+        assert_eq!(vec!["SUB b,c,e"], sent);
+    }
+}
@@ -0,0 +1,277 @@
+//! One-shot and periodic tasks bound to an endpoint.
+//!
+//! Connection housekeeping such as the `listenKey` refresh, a scheduled resubscribe or the end of day
+//! flatten trigger has to run on the connection, at a given interval or time. An endpoint owns its
+//! [`Tasks`] and exposes them from [`Endpoint::tasks`](crate::service::endpoint::Endpoint::tasks), the
+//! [`IOService`](crate::service::IOService) then runs the tasks that are due on the endpoint target just
+//! before the endpoint is polled, on the IO thread and at the poll loop boundary, so no synchronization
+//! is needed. An error returned by a task disconnects the endpoint just like an error returned by
+//! the poll action.
+//!
+//! Tasks only run while the endpoint is connected. The intervals are measured from the first poll of
+//! the connected endpoint after the task has been added, a task that became due while the endpoint
+//! was disconnected runs once it has reconnected (periodic tasks then continue with the next period).
+//! All times are nanoseconds since the UNIX epoch as reported by the service
+//! [`TimeSource`](crate::service::time::TimeSource), daily tasks are scheduled in UTC.
+//!
+//! ## Examples
+//! ```no_run
+//! use std::io::{Read, Write};
+//! use std::time::Duration;
+//! use boomnet::service::task::Tasks;
+//! use boomnet::ws::Websocket;
+//!
+//! const HOUR: Duration = Duration::from_secs(3600);
+//!
+//! fn tasks<S: Read + Write>() -> Tasks<Websocket<S>> {
+//!     let mut tasks = Tasks::new();
+//!     tasks.every(Duration::from_secs(30 * 60), |ws: &mut Websocket<S>| {
+//!         ws.send_text(true, Some(br#"{"method":"userDataStream.ping"}"#))?;
+//!         Ok(())
+//!     });
+//!     // flatten every day at 21:55 UTC
+//!     tasks.daily(21 * HOUR + Duration::from_secs(55 * 60), |ws: &mut Websocket<S>| {
+//!         ws.send_text(true, Some(br#"{"method":"order.cancelAll"}"#))?;
+//!         Ok(())
+//!     });
+//!     tasks
+//! }
+//! ```
+
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::time::Duration;
+
+const NANOS_PER_DAY: u64 = 24 * 3600 * 1_000_000_000;
+
+/// Identifies the task added to [`Tasks`], used to cancel it.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct TaskId(u64);
+
+#[derive(Debug, Copy, Clone)]
+enum Schedule {
+    After(u64),
+    At(u64),
+    Every(u64),
+    Daily(u64),
+}
+
+impl Schedule {
+    /// Due time of the first run, resolved on the first poll after the task has been added.
+    const fn first(self, now_ns: u64) -> u64 {
+        match self {
+            Schedule::After(delay_ns) | Schedule::Every(delay_ns) => now_ns.saturating_add(delay_ns),
+            Schedule::At(time_ns) => time_ns,
+            Schedule::Daily(offset_ns) => next_daily(now_ns, offset_ns),
+        }
+    }
+
+    /// Due time of the run following the one `due_ns`, `None` for one-shot tasks.
+    const fn next(self, due_ns: u64, now_ns: u64) -> Option<u64> {
+        match self {
+            Schedule::After(_) | Schedule::At(_) => None,
+            Schedule::Every(period_ns) => {
+                let next_ns = due_ns.saturating_add(period_ns);
+                // the missed runs are skipped
+                if next_ns <= now_ns {
+                    return Some(now_ns.saturating_add(period_ns));
+                }
+                Some(next_ns)
+            }
+            Schedule::Daily(offset_ns) => Some(next_daily(now_ns, offset_ns)),
+        }
+    }
+}
+
+/// First time after `now_ns` that is `offset_ns` past midnight.
+const fn next_daily(now_ns: u64, offset_ns: u64) -> u64 {
+    let due_ns = now_ns - now_ns % NANOS_PER_DAY + offset_ns;
+    if due_ns <= now_ns {
+        return due_ns + NANOS_PER_DAY;
+    }
+    due_ns
+}
+
+type TaskFn<T> = Box<dyn FnMut(&mut T) -> io::Result<()>>;
+
+struct Task<T> {
+    id: TaskId,
+    schedule: Schedule,
+    due_ns: Option<u64>,
+    run: TaskFn<T>,
+}
+
+/// Tasks run on the endpoint target `T`, see the [module](self) documentation.
+pub struct Tasks<T> {
+    tasks: Vec<Task<T>>,
+    next_id: u64,
+    // earliest due time among the tasks, zero if a task has been added since the last run
+    next_due_ns: u64,
+}
+
+impl<T> Debug for Tasks<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Tasks").field("tasks", &self.tasks.len()).finish()
+    }
+}
+
+impl<T> Default for Tasks<T> {
+    fn default() -> Self {
+        Self {
+            tasks: Vec::new(),
+            next_id: 0,
+            next_due_ns: u64::MAX,
+        }
+    }
+}
+
+impl<T> Tasks<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run the `task` once, `delay` after it has been added.
+    pub fn after<F>(&mut self, delay: Duration, task: F) -> TaskId
+    where
+        F: FnMut(&mut T) -> io::Result<()> + 'static,
+    {
+        self.add(Schedule::After(delay.as_nanos() as u64), task)
+    }
+
+    /// Run the `task` once at the `time` (since the UNIX epoch).
+    pub fn at<F>(&mut self, time: Duration, task: F) -> TaskId
+    where
+        F: FnMut(&mut T) -> io::Result<()> + 'static,
+    {
+        self.add(Schedule::At(time.as_nanos() as u64), task)
+    }
+
+    /// Run the `task` every `interval`, starting one `interval` after it has been added.
+    pub fn every<F>(&mut self, interval: Duration, task: F) -> TaskId
+    where
+        F: FnMut(&mut T) -> io::Result<()> + 'static,
+    {
+        self.add(Schedule::Every((interval.as_nanos() as u64).max(1)), task)
+    }
+
+    /// Run the `task` every day at the `time_of_day` (offset from midnight UTC).
+    pub fn daily<F>(&mut self, time_of_day: Duration, task: F) -> TaskId
+    where
+        F: FnMut(&mut T) -> io::Result<()> + 'static,
+    {
+        self.add(Schedule::Daily(time_of_day.as_nanos() as u64 % NANOS_PER_DAY), task)
+    }
+
+    /// Remove the task, returns `false` if it does not exist (anymore).
+    pub fn cancel(&mut self, id: TaskId) -> bool {
+        let len = self.tasks.len();
+        self.tasks.retain(|task| task.id != id);
+        self.tasks.len() != len
+    }
+
+    /// Number of scheduled tasks.
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Checks if there are no scheduled tasks.
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Run the tasks due at `now_ns` on the `target`, called by the service before every endpoint poll
+    /// (or by the owner of a standalone connection). Returns the first error, the remaining due tasks
+    /// then run on the next call.
+    #[inline]
+    pub fn run_due(&mut self, now_ns: u64, target: &mut T) -> io::Result<()> {
+        if now_ns < self.next_due_ns {
+            return Ok(());
+        }
+        let mut result = Ok(());
+        let mut next_due_ns = u64::MAX;
+        self.tasks.retain_mut(|task| {
+            let due_ns = *task.due_ns.get_or_insert_with(|| task.schedule.first(now_ns));
+            if due_ns > now_ns || result.is_err() {
+                next_due_ns = next_due_ns.min(due_ns);
+                return true;
+            }
+            result = (task.run)(target);
+            match task.schedule.next(due_ns, now_ns) {
+                Some(next_ns) => {
+                    task.due_ns = Some(next_ns);
+                    next_due_ns = next_due_ns.min(next_ns);
+                    true
+                }
+                None => false,
+            }
+        });
+        self.next_due_ns = next_due_ns;
+        result
+    }
+
+    fn add<F>(&mut self, schedule: Schedule, task: F) -> TaskId
+    where
+        F: FnMut(&mut T) -> io::Result<()> + 'static,
+    {
+        let id = TaskId(self.next_id);
+        self.next_id += 1;
+        self.tasks.push(Task {
+            id,
+            schedule,
+            due_ns: None,
+            run: Box::new(task),
+        });
+        // the due time is resolved on the next run
+        self.next_due_ns = 0;
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: u64 = 1_000_000;
+
+    #[test]
+    fn should_run_due_tasks() {
+        let mut tasks = Tasks::new();
+        let refresh = tasks.every(Duration::from_millis(10), |log: &mut Vec<String>| {
+            log.push("refresh".to_owned());
+            Ok(())
+        });
+        tasks.after(Duration::from_millis(15), |log: &mut Vec<String>| {
+            log.push("resubscribe".to_owned());
+            Ok(())
+        });
+        tasks.daily(Duration::from_millis(12), |log: &mut Vec<String>| {
+            log.push("end of day".to_owned());
+            Ok(())
+        });
+        let mut log = vec![];
+
+        // the intervals start with the first run
+        tasks.run_due(NANOS_PER_DAY, &mut log).unwrap();
+        assert!(log.is_empty());
+        tasks.run_due(NANOS_PER_DAY + 10 * MS, &mut log).unwrap();
+        assert_eq!(vec!["refresh"], log);
+        tasks.run_due(NANOS_PER_DAY + 15 * MS, &mut log).unwrap();
+        assert_eq!(vec!["refresh", "resubscribe", "end of day"], log);
+        assert_eq!(2, tasks.len());
+        // missed runs are skipped
+        log.clear();
+        tasks.run_due(NANOS_PER_DAY + 35 * MS, &mut log).unwrap();
+        assert_eq!(vec!["refresh"], log);
+        tasks.run_due(NANOS_PER_DAY + 44 * MS, &mut log).unwrap();
+        assert_eq!(vec!["refresh"], log);
+
+        assert!(tasks.cancel(refresh));
+        assert!(!tasks.cancel(refresh));
+        // due since the epoch, runs straight away
+        tasks.at(Duration::from_millis(40), |_: &mut Vec<String>| Err(io::Error::other("flatten failed")));
+        assert!(tasks.run_due(NANOS_PER_DAY + 45 * MS, &mut log).is_err());
+        assert_eq!(1, tasks.len());
+        tasks.run_due(2 * NANOS_PER_DAY + 12 * MS, &mut log).unwrap();
+        assert_eq!(vec!["refresh", "end of day"], log);
+    }
+}
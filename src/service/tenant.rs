@@ -0,0 +1,435 @@
+//! Isolation of the endpoints that belong to different tenants (e.g. strategies or teams) sharing a
+//! single [`IOService`](crate::service::IOService), and therefore the pinned core it runs on.
+//!
+//! An endpoint is tagged with its tenant by [`Endpoint::tenant`](crate::service::endpoint::Endpoint::tenant),
+//! the service keeps [`TenantStats`] under the tenant name and enforces its [`TenantQuota`]:
+//! - at most `max_connections` endpoints of the tenant are connected at a time, the others stay
+//!   pending until a connection is released,
+//! - once the tenant endpoints have read more than `max_read_rate` bytes per second reading from all of
+//!   them is paused (see [`IOService::pause_reading`](crate::service::IOService::pause_reading)) until
+//!   the tenant is back within its budget, the excess is carried over to the following seconds,
+//! - at most `max_dispatch_rate` commands are [dispatched](crate::service::IOService::dispatch) to the
+//!   tenant endpoints per interval, the dispatch fails with `WouldBlock` above it,
+//! - once the tenant endpoints have written more than `max_write_rate` bytes per second (counting both
+//!   the dispatched commands and the writes of the poll action) the dispatch fails with `WouldBlock`
+//!   until the tenant is back within its budget, the excess is carried over like with the reads. The
+//!   poll action keeps running so the endpoints are still read, its writes are counted but not refused.
+//!
+//! Endpoints without a tenant are never limited, a tenant without a quota is only accounted.
+//!
+//! ## Examples
+//! ```no_run
+//! use std::time::Duration;
+//! use boomnet::service::IOService;
+//! use boomnet::service::dns::DnsResolver;
+//! use boomnet::service::select::Selector;
+//! use boomnet::service::tenant::TenantQuota;
+//!
+//! fn isolate<S: Selector, E, C, TS, D: DnsResolver>(io_service: IOService<S, E, C, TS, D>) -> IOService<S, E, C, TS, D> {
+//!     io_service
+//!         .with_tenant_quota("market-making", TenantQuota::new().with_max_connections(16))
+//!         .with_tenant_quota(
+//!             "research",
+//!             TenantQuota::new()
+//!                 .with_max_connections(4)
+//!                 .with_max_read_rate(8 * 1024 * 1024)
+//!                 .with_max_write_rate(64 * 1024)
+//!                 .with_max_dispatch_rate(10, Duration::from_secs(1)),
+//!         )
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::io;
+use std::time::Duration;
+
+use crate::service::time::RateLimit;
+
+const RATE_WINDOW_NS: u64 = 1_000_000_000;
+
+/// Limits applied to all endpoints of a tenant, unlimited by default.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct TenantQuota {
+    max_connections: Option<usize>,
+    max_read_rate: Option<u64>,
+    max_write_rate: Option<u64>,
+    max_dispatch_rate: Option<(u32, Duration)>,
+}
+
+impl TenantQuota {
+    /// Create quota without any limits.
+    pub const fn new() -> TenantQuota {
+        Self {
+            max_connections: None,
+            max_read_rate: None,
+            max_write_rate: None,
+            max_dispatch_rate: None,
+        }
+    }
+
+    /// Maximum number of connected endpoints.
+    pub const fn with_max_connections(self, max_connections: usize) -> TenantQuota {
+        Self {
+            max_connections: Some(max_connections),
+            ..self
+        }
+    }
+
+    /// Maximum number of bytes read per second by all endpoints of the tenant.
+    pub const fn with_max_read_rate(self, bytes_per_second: u64) -> TenantQuota {
+        Self {
+            max_read_rate: Some(bytes_per_second),
+            ..self
+        }
+    }
+
+    /// Maximum number of bytes written per second by all endpoints of the tenant, the dispatch is
+    /// refused above it.
+    pub const fn with_max_write_rate(self, bytes_per_second: u64) -> TenantQuota {
+        Self {
+            max_write_rate: Some(bytes_per_second),
+            ..self
+        }
+    }
+
+    /// Maximum number of commands dispatched to the endpoints of the tenant per `interval`.
+    pub const fn with_max_dispatch_rate(self, max_commands: u32, interval: Duration) -> TenantQuota {
+        Self {
+            max_dispatch_rate: Some((max_commands, interval)),
+            ..self
+        }
+    }
+}
+
+/// Counters of a single tenant, accumulated since the service has been created.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct TenantStats {
+    /// Number of currently connected endpoints.
+    pub connections: usize,
+    /// Number of bytes read by the endpoints (only counted by the streams that track reads).
+    pub bytes_read: u64,
+    /// Number of bytes written by the endpoints (only counted by the streams that track writes).
+    pub bytes_written: u64,
+    /// Number of frames reported as processed by the poll `action`.
+    pub frames: u64,
+    /// Number of disconnected endpoints.
+    pub disconnected: u64,
+    /// Number of commands dispatched to the endpoints.
+    pub dispatched: u64,
+    /// Number of dispatches refused over the dispatch or write rate.
+    pub dispatch_rejected: u64,
+    /// Number of times reading has been paused over the read rate.
+    pub read_throttled: u64,
+    /// Number of times the dispatch has been suspended over the write rate.
+    pub write_throttled: u64,
+    /// Number of connection attempts deferred over the connection limit.
+    pub connections_deferred: u64,
+}
+
+#[derive(Debug)]
+pub(crate) struct Tenant {
+    name: String,
+    quota: TenantQuota,
+    stats: TenantStats,
+    dispatch_limit: Option<RateLimit>,
+    read_rate: ByteRate,
+    write_rate: ByteRate,
+}
+
+impl Tenant {
+    fn new(name: String, quota: TenantQuota) -> Self {
+        Self {
+            name,
+            quota,
+            stats: TenantStats::default(),
+            dispatch_limit: None,
+            read_rate: ByteRate::default(),
+            write_rate: ByteRate::default(),
+        }
+    }
+
+    #[inline]
+    pub(crate) const fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    #[inline]
+    pub(crate) const fn stats(&self) -> TenantStats {
+        self.stats
+    }
+
+    #[inline]
+    pub(crate) const fn is_read_throttled(&self) -> bool {
+        self.read_rate.throttled
+    }
+
+    /// Checks if another endpoint can be connected while `connections` of them already are.
+    pub(crate) fn can_connect(&mut self, connections: usize) -> bool {
+        let can_connect = self.quota.max_connections.is_none_or(|max| connections < max);
+        if !can_connect {
+            self.stats.connections_deferred += 1;
+        }
+        can_connect
+    }
+
+    #[inline]
+    pub(crate) const fn record_read(&mut self, bytes: u64, frames: usize) {
+        self.stats.bytes_read += bytes;
+        self.stats.frames += frames as u64;
+        self.read_rate.window_bytes += bytes;
+    }
+
+    #[inline]
+    pub(crate) const fn record_write(&mut self, bytes: u64) {
+        self.stats.bytes_written += bytes;
+        self.write_rate.window_bytes += bytes;
+    }
+
+    /// Checks if the command can be dispatched within the write and dispatch rate, fails with
+    /// `WouldBlock` otherwise.
+    pub(crate) fn try_dispatch(&mut self, now_ns: u64) -> io::Result<()> {
+        if self.write_rate.throttled {
+            self.stats.dispatch_rejected += 1;
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, "tenant write rate exceeded"));
+        }
+        let dispatch = match self.quota.max_dispatch_rate {
+            Some((max_commands, interval)) => self
+                .dispatch_limit
+                .get_or_insert_with(|| RateLimit::new(max_commands, interval, now_ns))
+                .try_acquire(now_ns),
+            None => true,
+        };
+        if !dispatch {
+            self.stats.dispatch_rejected += 1;
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, "tenant dispatch rate exceeded"));
+        }
+        self.stats.dispatched += 1;
+        Ok(())
+    }
+
+    /// Start a new read window (if due), returns `Some` with the new state when the tenant has to be
+    /// throttled (`true`) or released (`false`).
+    fn poll_read_rate(&mut self, now_ns: u64) -> Option<bool> {
+        let max_read_rate = self.quota.max_read_rate?;
+        let throttled = self.read_rate.poll(now_ns, max_read_rate)?;
+        if throttled {
+            self.stats.read_throttled += 1;
+            log::warn!("tenant {} exceeded read rate of {} bytes per second", self.name, max_read_rate);
+        } else {
+            log::info!("tenant {} back within read rate", self.name);
+        }
+        Some(throttled)
+    }
+
+    /// Start a new write window (if due), the dispatch is refused while the tenant is throttled.
+    fn poll_write_rate(&mut self, now_ns: u64) {
+        let Some(max_write_rate) = self.quota.max_write_rate else {
+            return;
+        };
+        match self.write_rate.poll(now_ns, max_write_rate) {
+            Some(true) => {
+                self.stats.write_throttled += 1;
+                log::warn!("tenant {} exceeded write rate of {} bytes per second", self.name, max_write_rate);
+            }
+            Some(false) => log::info!("tenant {} back within write rate", self.name),
+            None => {}
+        }
+    }
+}
+
+/// Bytes transferred in one second windows, the excess over the rate is carried over to the following
+/// windows.
+#[derive(Debug, Default)]
+struct ByteRate {
+    window_end_ns: u64,
+    // bytes transferred in the current window, including the excess carried over
+    window_bytes: u64,
+    throttled: bool,
+}
+
+impl ByteRate {
+    /// Start a new window (if due), returns `Some` with the new state when the tenant has to be
+    /// throttled (`true`) or released (`false`).
+    fn poll(&mut self, now_ns: u64, max_rate: u64) -> Option<bool> {
+        if self.window_end_ns == 0 {
+            // the first window starts with the first check
+            self.window_end_ns = now_ns + RATE_WINDOW_NS;
+        } else if now_ns >= self.window_end_ns {
+            let windows = (now_ns - self.window_end_ns) / RATE_WINDOW_NS + 1;
+            self.window_bytes = self.window_bytes.saturating_sub(max_rate.saturating_mul(windows));
+            self.window_end_ns += windows * RATE_WINDOW_NS;
+        }
+        let throttled = self.window_bytes > max_rate;
+        if throttled == self.throttled {
+            return None;
+        }
+        self.throttled = throttled;
+        Some(throttled)
+    }
+}
+
+/// All known tenants, endpoints refer to them by index.
+#[derive(Debug, Default)]
+pub(crate) struct Tenants {
+    tenants: Vec<Tenant>,
+    indices: HashMap<String, usize>,
+}
+
+impl Tenants {
+    pub(crate) fn set_quota(&mut self, name: String, quota: TenantQuota) {
+        match self.indices.get(&name) {
+            Some(index) => self.tenants[*index].quota = quota,
+            None => {
+                self.indices.insert(name.clone(), self.tenants.len());
+                self.tenants.push(Tenant::new(name, quota));
+            }
+        }
+    }
+
+    /// Index of the tenant with the `name`, a tenant without quota is added if not known yet.
+    pub(crate) fn index_of(&mut self, name: &str) -> usize {
+        match self.indices.get(name) {
+            Some(index) => *index,
+            None => {
+                self.set_quota(name.to_owned(), TenantQuota::new());
+                self.tenants.len() - 1
+            }
+        }
+    }
+
+    #[inline]
+    pub(crate) fn record_disconnect(&mut self, tenant: Option<usize>) {
+        if let Some(tenant) = tenant {
+            self.tenants[tenant].stats.disconnected += 1;
+        }
+    }
+
+    pub(crate) fn position(&self, name: &str) -> Option<usize> {
+        self.indices.get(name).copied()
+    }
+
+    #[inline]
+    pub(crate) const fn len(&self) -> usize {
+        self.tenants.len()
+    }
+
+    #[inline]
+    pub(crate) const fn is_empty(&self) -> bool {
+        self.tenants.is_empty()
+    }
+
+    #[inline]
+    pub(crate) fn get(&self, index: usize) -> &Tenant {
+        &self.tenants[index]
+    }
+
+    #[inline]
+    pub(crate) fn get_mut(&mut self, index: usize) -> &mut Tenant {
+        &mut self.tenants[index]
+    }
+
+    /// Check the read and write rate of every tenant, `throttle` is invoked with the index of the tenant
+    /// whose endpoints have to be paused (`true`) or resumed (`false`) reading.
+    pub(crate) fn poll_rates<F>(&mut self, now_ns: u64, mut throttle: F) -> io::Result<()>
+    where
+        F: FnMut(usize, bool) -> io::Result<()>,
+    {
+        for (index, tenant) in self.tenants.iter_mut().enumerate() {
+            tenant.poll_write_rate(now_ns);
+            if let Some(throttled) = tenant.poll_read_rate(now_ns) {
+                throttle(index, throttled)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: u64 = 1_000_000_000;
+
+    #[test]
+    fn should_throttle_reads_over_the_rate_and_carry_the_excess() {
+        let mut tenants = Tenants::default();
+        tenants.set_quota("research".to_owned(), TenantQuota::new().with_max_read_rate(100));
+        let index = tenants.index_of("research");
+        let mut transitions = vec![];
+
+        let mut poll = |tenants: &mut Tenants, now_ns| {
+            tenants
+                .poll_rates(now_ns, |index, throttled| {
+                    transitions.push((index, throttled));
+                    Ok(())
+                })
+                .unwrap();
+            transitions.clone()
+        };
+
+        tenants.get_mut(index).record_read(250, 1);
+        assert_eq!(vec![(0, true)], poll(&mut tenants, SECOND));
+
+        // 150 bytes over the rate are still throttled in the next second
+        assert_eq!(vec![(0, true)], poll(&mut tenants, 2 * SECOND));
+        assert_eq!(vec![(0, true), (0, false)], poll(&mut tenants, 3 * SECOND));
+
+        let stats = tenants.get(index).stats();
+        assert_eq!(250, stats.bytes_read);
+        assert_eq!(1, stats.frames);
+        assert_eq!(1, stats.read_throttled);
+    }
+
+    #[test]
+    fn should_limit_connections_and_dispatch_rate() {
+        let mut tenants = Tenants::default();
+        let quota = TenantQuota::new()
+            .with_max_connections(1)
+            .with_max_dispatch_rate(2, Duration::from_secs(1));
+        tenants.set_quota("research".to_owned(), quota);
+        let research = tenants.index_of("research");
+        let other = tenants.index_of("market-making");
+
+        assert!(tenants.get_mut(research).can_connect(0));
+        assert!(!tenants.get_mut(research).can_connect(1));
+        assert!(tenants.get_mut(other).can_connect(1));
+
+        assert!(tenants.get_mut(research).try_dispatch(SECOND).is_ok());
+        assert!(tenants.get_mut(research).try_dispatch(SECOND).is_ok());
+        assert!(tenants.get_mut(research).try_dispatch(SECOND).is_err());
+        assert!(tenants.get_mut(research).try_dispatch(2 * SECOND).is_ok());
+        assert!((0..10).all(|_| tenants.get_mut(other).try_dispatch(SECOND).is_ok()));
+
+        let stats = tenants.get(research).stats();
+        assert_eq!((3, 1, 1), (stats.dispatched, stats.dispatch_rejected, stats.connections_deferred));
+        assert_eq!((Some(1), "market-making"), (tenants.position("market-making"), tenants.get(other).name()));
+    }
+
+    #[test]
+    fn should_refuse_dispatch_over_the_write_rate() {
+        let mut tenants = Tenants::default();
+        tenants.set_quota("research".to_owned(), TenantQuota::new().with_max_write_rate(100));
+        let index = tenants.index_of("research");
+        let poll = |tenants: &mut Tenants, now_ns| tenants.poll_rates(now_ns, |_, _| Ok(())).unwrap();
+
+        poll(&mut tenants, SECOND);
+        assert!(tenants.get_mut(index).try_dispatch(SECOND).is_ok());
+        // writes of the poll action count against the budget as well
+        tenants.get_mut(index).record_write(250);
+        poll(&mut tenants, SECOND);
+        let err = tenants.get_mut(index).try_dispatch(SECOND).unwrap_err();
+        assert_eq!(io::ErrorKind::WouldBlock, err.kind());
+
+        // 150 bytes over the rate are carried over to the next second
+        poll(&mut tenants, 2 * SECOND);
+        assert!(tenants.get_mut(index).try_dispatch(2 * SECOND).is_err());
+        poll(&mut tenants, 3 * SECOND);
+        assert!(tenants.get_mut(index).try_dispatch(3 * SECOND).is_ok());
+
+        let stats = tenants.get(index).stats();
+        assert_eq!(
+            (250, 1, 2, 2),
+            (stats.bytes_written, stats.write_throttled, stats.dispatched, stats.dispatch_rejected)
+        );
+    }
+}
@@ -1,6 +1,6 @@
 //! Contains time related utilities.
 
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 /// Trait that provides current time since UNIX epoch.
 pub trait TimeSource {
@@ -9,6 +9,7 @@ pub trait TimeSource {
 }
 
 /// Uses `SystemTime` as [`TimeSource`].
+#[derive(Debug, Clone, Copy)]
 pub struct SystemTimeClockSource;
 
 impl TimeSource for SystemTimeClockSource {
@@ -20,3 +21,39 @@ impl TimeSource for SystemTimeClockSource {
             .as_nanos() as u64
     }
 }
+
+/// Token bucket refilled evenly over the interval.
+#[derive(Debug)]
+pub(crate) struct RateLimit {
+    capacity: u64,
+    // time it takes to refill a single token
+    refill_ns: u64,
+    tokens: u64,
+    last_refill_ns: u64,
+}
+
+impl RateLimit {
+    pub(crate) fn new(max_tokens: u32, interval: Duration, now_ns: u64) -> Self {
+        let capacity = max_tokens.max(1) as u64;
+        Self {
+            capacity,
+            refill_ns: (interval.as_nanos() as u64 / capacity).max(1),
+            tokens: capacity,
+            last_refill_ns: now_ns,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn try_acquire(&mut self, now_ns: u64) -> bool {
+        let refilled = now_ns.saturating_sub(self.last_refill_ns) / self.refill_ns;
+        if refilled > 0 {
+            self.tokens = self.capacity.min(self.tokens + refilled);
+            self.last_refill_ns += refilled * self.refill_ns;
+        }
+        if self.tokens == 0 {
+            return false;
+        }
+        self.tokens -= 1;
+        true
+    }
+}
@@ -0,0 +1,388 @@
+//! Bearer tokens for the authenticated streams.
+//!
+//! Private streams are often authenticated with a short lived (e.g. OAuth2) bearer token. The endpoint
+//! asks its [`TokenProvider`] for a fresh token every time it creates the connection, so a reconnect
+//! never presents an expired token. The token is refreshed ahead of its expiry by the task added with
+//! [`schedule_refresh`] to the endpoint [`Tasks`], which also hands the new token to the live connection
+//! (e.g. as a re-authentication message) so that the venue does not drop a long-lived stream once the
+//! token it connected with expires.
+//!
+//! [`ClientCredentials`] implements the OAuth2 client credentials grant with the
+//! [`HttpClient`](crate::http::HttpClient) (requires the `http` feature).
+//!
+//! ## Examples
+//! ```no_run
+//! use std::cell::RefCell;
+//! use std::io;
+//! use std::net::SocketAddr;
+//! use std::rc::Rc;
+//! use std::time::Duration;
+//! use boomnet::http::{ConnectionPool, SingleTlsConnectionPool};
+//! use boomnet::service::endpoint::ws::{TlsWebsocket, TlsWebsocketEndpoint};
+//! use boomnet::service::task::Tasks;
+//! use boomnet::service::token::{ClientCredentials, TokenProvider, schedule_refresh};
+//! use boomnet::stream::tcp::TcpStream;
+//! use boomnet::stream::{ConnectionInfo, ConnectionInfoProvider};
+//! use boomnet::ws::IntoTlsWebsocket;
+//!
+//! type Tokens = Rc<RefCell<ClientCredentials<SingleTlsConnectionPool>>>;
+//!
+//! struct PrivateStream {
+//!     connection_info: ConnectionInfo,
+//!     tokens: Tokens,
+//!     tasks: Tasks<TlsWebsocket<TcpStream>>,
+//! }
+//!
+//! impl PrivateStream {
+//!     fn new() -> Self {
+//!         let client = SingleTlsConnectionPool::new(ConnectionInfo::new("auth.example.com", 443)).into_http_client();
+//!         let tokens = Rc::new(RefCell::new(ClientCredentials::new(client, "/oauth/token", "id", "secret")));
+//!         let mut tasks = Tasks::new();
+//!         // re-authenticate the live stream once the token has been refreshed
+//!         let interval = Duration::from_secs(10);
+//!         schedule_refresh(&mut tasks, tokens.clone(), interval, |ws: &mut TlsWebsocket<TcpStream>, token| {
+//!             Ok(ws.send_text_fmt(format_args!(r#"{{"op":"auth","token":"{token}"}}"#))?)
+//!         });
+//!         Self { connection_info: ConnectionInfo::new("stream.example.com", 443), tokens, tasks }
+//!     }
+//! }
+//!
+//! impl ConnectionInfoProvider for PrivateStream {
+//!     fn connection_info(&self) -> &ConnectionInfo {
+//!         &self.connection_info
+//!     }
+//! }
+//!
+//! impl TlsWebsocketEndpoint for PrivateStream {
+//!     type Stream = TcpStream;
+//!
+//!     fn create_websocket(&mut self, addr: SocketAddr) -> io::Result<Option<TlsWebsocket<Self::Stream>>> {
+//!         let authorization = self.tokens.authorization()?;
+//!         let ws = self
+//!             .connection_info
+//!             .clone()
+//!             .into_tcp_stream_with_addr(addr)?
+//!             .into_tls_websocket("/private")?
+//!             .with_header("Authorization", &authorization);
+//!         Ok(Some(ws))
+//!     }
+//!
+//!     fn tasks(&mut self) -> Option<&mut Tasks<TlsWebsocket<Self::Stream>>> {
+//!         Some(&mut self.tasks)
+//!     }
+//! }
+//! ```
+
+use crate::service::task::{TaskId, Tasks};
+#[cfg(feature = "http")]
+pub use crate::service::token::client_credentials::ClientCredentials;
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// Source of the bearer token presented by the endpoint.
+pub trait TokenProvider {
+    /// Current token, fetched first if there is none or it needs refreshing.
+    fn token(&mut self) -> io::Result<String>;
+
+    /// Checks if the token is about to expire (or there is none), [`TokenProvider::token`] then fetches
+    /// a new one.
+    fn needs_refresh(&self) -> bool {
+        false
+    }
+
+    /// Discard the current token, e.g. once rejected by the venue.
+    fn invalidate(&mut self) {}
+
+    /// Value of the `Authorization` header with the current token.
+    fn authorization(&mut self) -> io::Result<String> {
+        Ok(format!("Bearer {}", self.token()?))
+    }
+}
+
+/// Provider shared by the endpoint and its refresh task.
+impl<P: TokenProvider> TokenProvider for Rc<RefCell<P>> {
+    fn token(&mut self) -> io::Result<String> {
+        self.borrow_mut().token()
+    }
+
+    fn needs_refresh(&self) -> bool {
+        self.borrow().needs_refresh()
+    }
+
+    fn invalidate(&mut self) {
+        self.borrow_mut().invalidate()
+    }
+}
+
+/// Token that never expires, e.g. an API key presented as the bearer token.
+#[derive(Debug, Clone)]
+pub struct StaticToken(pub String);
+
+impl TokenProvider for StaticToken {
+    fn token(&mut self) -> io::Result<String> {
+        Ok(self.0.clone())
+    }
+}
+
+/// Check the `provider` every `check_interval` and once its token needs refreshing fetch a new one and
+/// pass it to `on_refresh` together with the connection. An error (also from fetching the token)
+/// disconnects the endpoint, which then reconnects with a fresh token.
+pub fn schedule_refresh<T, P, F>(tasks: &mut Tasks<T>, provider: P, check_interval: Duration, on_refresh: F) -> TaskId
+where
+    P: TokenProvider + 'static,
+    F: FnMut(&mut T, &str) -> io::Result<()> + 'static,
+{
+    let mut provider = provider;
+    let mut on_refresh = on_refresh;
+    tasks.every(check_interval, move |target| {
+        if provider.needs_refresh() {
+            let token = provider.token()?;
+            on_refresh(target, &token)?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(feature = "http")]
+mod client_credentials {
+    use crate::codec::json;
+    use crate::http::{ConnectionPool, HttpClient, Method};
+    use crate::service::time::{SystemTimeClockSource, TimeSource};
+    use crate::service::token::TokenProvider;
+    use std::fmt::{Debug, Formatter};
+    use std::io;
+    use std::time::Duration;
+    use url::form_urlencoded;
+
+    const DEFAULT_REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+    /// OAuth2 client credentials grant (RFC 6749 section 4.4), the token is requested from the token
+    /// endpoint at the `path` with the blocking [`HttpClient`] and cached until the refresh margin
+    /// before its expiry (`expires_in`).
+    pub struct ClientCredentials<C: ConnectionPool> {
+        client: HttpClient<C>,
+        path: String,
+        form: String,
+        token: Option<String>,
+        expires_at_ns: u64,
+        refresh_margin: Duration,
+        time_source: Box<dyn TimeSource>,
+    }
+
+    impl<C: ConnectionPool> Debug for ClientCredentials<C> {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("ClientCredentials")
+                .field("path", &self.path)
+                .field("expires_at_ns", &self.expires_at_ns)
+                .field("refresh_margin", &self.refresh_margin)
+                .finish()
+        }
+    }
+
+    impl<C: ConnectionPool> ClientCredentials<C> {
+        /// Request the tokens from the token endpoint at the `path` of the `client` host.
+        pub fn new(client: HttpClient<C>, path: &str, client_id: &str, client_secret: &str) -> ClientCredentials<C> {
+            let form = form_urlencoded::Serializer::new(String::new())
+                .append_pair("grant_type", "client_credentials")
+                .append_pair("client_id", client_id)
+                .append_pair("client_secret", client_secret)
+                .finish();
+            Self {
+                client,
+                path: path.to_owned(),
+                form,
+                token: None,
+                expires_at_ns: 0,
+                refresh_margin: DEFAULT_REFRESH_MARGIN,
+                time_source: Box::new(SystemTimeClockSource),
+            }
+        }
+
+        /// Request the token for the `scope` (space separated list).
+        pub fn with_scope(mut self, scope: &str) -> Self {
+            self.form = form_urlencoded::Serializer::for_suffix(self.form, 0)
+                .append_pair("scope", scope)
+                .finish();
+            self
+        }
+
+        /// Refresh the token once it expires within the `refresh_margin` (one minute by default).
+        pub fn with_refresh_margin(self, refresh_margin: Duration) -> Self {
+            Self { refresh_margin, ..self }
+        }
+
+        /// Source of the time the expiry is measured with, the system clock by default.
+        pub fn with_time_source<T: TimeSource + 'static>(self, time_source: T) -> Self {
+            Self {
+                time_source: Box::new(time_source),
+                ..self
+            }
+        }
+
+        fn fetch(&mut self) -> io::Result<()> {
+            let (status, _, body) = self
+                .client
+                .new_request_with_headers(Method::POST, &self.path, Some(self.form.as_bytes()), |headers| {
+                    headers.insert("Content-Type", "application/x-www-form-urlencoded");
+                    headers.insert("Accept", "application/json");
+                })?
+                .block()?;
+            if status != 200 {
+                return Err(io::Error::other(format!("token request failed with status {status}: {body}")));
+            }
+            let body = body.as_bytes();
+            let token = json::get(body, b"access_token")
+                .and_then(json::unquote)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing access_token"))?;
+            let token = String::from_utf8_lossy(token).into_owned();
+            // a token without `expires_in` does not expire
+            let expires_in = json::get(body, b"expires_in")
+                .and_then(|expires_in| std::str::from_utf8(expires_in).ok()?.parse::<u64>().ok());
+            self.expires_at_ns = match expires_in {
+                Some(seconds) => self
+                    .time_source
+                    .current_time_nanos()
+                    .saturating_add(seconds.saturating_mul(1_000_000_000)),
+                None => u64::MAX,
+            };
+            self.token = Some(token);
+            Ok(())
+        }
+    }
+
+    impl<C: ConnectionPool> TokenProvider for ClientCredentials<C> {
+        fn token(&mut self) -> io::Result<String> {
+            if self.needs_refresh() {
+                self.fetch()?;
+            }
+            self.token.clone().ok_or_else(|| io::Error::other("no token available"))
+        }
+
+        fn needs_refresh(&self) -> bool {
+            let refresh_at_ns = self.expires_at_ns.saturating_sub(self.refresh_margin.as_nanos() as u64);
+            self.token.is_none() || self.time_source.current_time_nanos() >= refresh_at_ns
+        }
+
+        fn invalidate(&mut self) {
+            self.token = None;
+        }
+    }
+}
+
+#[cfg(all(test, feature = "http"))]
+mod tests {
+    use super::*;
+    use crate::http::{Connection, ConnectionPool};
+    use crate::service::time::TimeSource;
+    use std::cell::Cell;
+    use std::io::{ErrorKind, Read, Write};
+
+    const SECOND: u64 = 1_000_000_000;
+
+    /// Issues a new token for every request.
+    struct TokenServer {
+        requests: Rc<RefCell<Vec<String>>>,
+        response: Option<Vec<u8>>,
+    }
+
+    impl Read for TokenServer {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let response = self.response.take().ok_or(ErrorKind::WouldBlock)?;
+            buf[..response.len()].copy_from_slice(&response);
+            Ok(response.len())
+        }
+    }
+
+    impl Write for TokenServer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.requests
+                .borrow_mut()
+                .push(String::from_utf8_lossy(buf).into_owned());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            let body = format!(r#"{{"access_token":"token-{}","token_type":"Bearer","expires_in":300}}"#, {
+                self.requests
+                    .borrow()
+                    .iter()
+                    .filter(|request| request.starts_with("POST"))
+                    .count()
+            });
+            self.response =
+                Some(format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{body}", body.len()).into_bytes());
+            Ok(())
+        }
+    }
+
+    struct Pool(Option<Connection<TokenServer>>);
+
+    impl ConnectionPool for Pool {
+        type Stream = TokenServer;
+
+        fn host(&self) -> &str {
+            "auth.example.com"
+        }
+
+        fn acquire(&mut self) -> io::Result<Option<Connection<TokenServer>>> {
+            Ok(self.0.take())
+        }
+
+        fn release(&mut self, conn: Option<Connection<TokenServer>>) {
+            self.0 = conn;
+        }
+    }
+
+    #[derive(Clone)]
+    struct Clock(Rc<Cell<u64>>);
+
+    impl TimeSource for Clock {
+        fn current_time_nanos(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    #[test]
+    fn should_refresh_token_ahead_of_expiry() {
+        let requests = Rc::new(RefCell::new(vec![]));
+        let server = TokenServer {
+            requests: requests.clone(),
+            response: None,
+        };
+        let clock = Clock(Rc::new(Cell::new(1000 * SECOND)));
+        let client = Pool(Some(Connection::new(server))).into_http_client();
+        let mut tokens = Rc::new(RefCell::new(
+            ClientCredentials::new(client, "/oauth/token", "id", "s3cr&t")
+                .with_scope("read trade")
+                .with_time_source(clock.clone()),
+        ));
+
+        assert_eq!("Bearer token-1", tokens.authorization().unwrap());
+        let body = requests.borrow().join("");
+        assert!(body.starts_with("POST /oauth/token HTTP/1.1\r\nHost: auth.example.com\r\n"));
+        assert!(body.ends_with("grant_type=client_credentials&client_id=id&client_secret=s3cr%26t&scope=read+trade"));
+
+        let mut tasks = Tasks::new();
+        schedule_refresh(&mut tasks, tokens.clone(), Duration::from_secs(10), |sent: &mut Vec<String>, token| {
+            sent.push(token.to_owned());
+            Ok(())
+        });
+        let mut sent = vec![];
+        tasks.run_due(clock.0.get(), &mut sent).unwrap();
+        clock.0.set(1200 * SECOND);
+        tasks.run_due(clock.0.get(), &mut sent).unwrap();
+        // cached until a minute before the expiry
+        assert!(sent.is_empty());
+        assert_eq!("token-1", tokens.token().unwrap());
+
+        clock.0.set(1240 * SECOND);
+        tasks.run_due(clock.0.get(), &mut sent).unwrap();
+        assert_eq!(vec!["token-2"], sent);
+        assert_eq!("token-2", tokens.token().unwrap());
+
+        tokens.invalidate();
+        assert_eq!("token-3", tokens.token().unwrap());
+    }
+}
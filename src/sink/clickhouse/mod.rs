@@ -0,0 +1,466 @@
+//! ClickHouse sink inserting the events in batches over the native TCP protocol.
+//!
+//! [`ClickHouseSink`] buffers the events and inserts them once `batch_size` of them have been
+//! buffered or the `interval` has passed since the oldest one (checked on every write, so a
+//! periodic [`Sink::flush`] inserts a partial batch when the events stop). The stream is created
+//! with the `connect` function (e.g. [`ConnectionInfo::into_tcp_stream`]), lazily on the first insert
+//! and again after a failure. The inserts block, the sink is meant to run on its own thread behind
+//! [`BackgroundSink`](crate::sink::BackgroundSink).
+//!
+//! What happens with the batch of a failed insert is decided by the [`Backpressure`].
+//!
+//! ## Examples
+//! ```no_run
+//! use boomnet::sink::BackgroundSink;
+//! use boomnet::sink::clickhouse::{Backpressure, ClickHouseRecord, ClickHouseSink, ColumnWriter};
+//! use boomnet::stream::ConnectionInfo;
+//! use std::time::Duration;
+//!
+//! #[derive(Clone)]
+//! struct Trade {
+//!     time_ns: i64,
+//!     price: f64,
+//!     quantity: f64,
+//! }
+//!
+//! impl ClickHouseRecord for Trade {
+//!     fn columns() -> &'static [(&'static str, &'static str)] {
+//!         &[("time", "DateTime64(9)"), ("price", "Float64"), ("quantity", "Float64")]
+//!     }
+//!
+//!     fn encode_column(records: &[Self], column: usize, writer: &mut ColumnWriter<'_>) {
+//!         for trade in records {
+//!             match column {
+//!                 0 => writer.i64(trade.time_ns),
+//!                 1 => writer.f64(trade.price),
+//!                 _ => writer.f64(trade.quantity),
+//!             }
+//!         }
+//!     }
+//! }
+//!
+//! let connect = || ConnectionInfo::new("127.0.0.1", 9000).into_tcp_stream();
+//! let clickhouse = ClickHouseSink::<Trade, _>::new(connect, "trades")
+//!     .with_credentials("market", "ingest", "secret")
+//!     .with_batch_size(10_000)
+//!     .with_interval(Duration::from_millis(500))
+//!     .with_backpressure(Backpressure::Retry { max_rows: 1_000_000 });
+//! let mut sink = BackgroundSink::spawn("clickhouse", 1 << 16, clickhouse).unwrap();
+//! sink.send(Trade { time_ns: 1, price: 100.5, quantity: 0.1 });
+//! ```
+//!
+//! [`ConnectionInfo::into_tcp_stream`]: crate::stream::ConnectionInfo::into_tcp_stream
+
+mod native;
+
+use crate::service::time::{SystemTimeClockSource, TimeSource};
+use crate::sink::Sink;
+use crate::sink::clickhouse::native::{Connection, Credentials, put_block};
+use log::warn;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::io::{Read, Write};
+use std::time::Duration;
+
+pub use crate::sink::clickhouse::native::ColumnWriter;
+
+const DEFAULT_BATCH_SIZE: usize = 8192;
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Connect<S> = Box<dyn FnMut() -> io::Result<S> + Send>;
+
+/// Event that can be inserted into a ClickHouse table, encoded column by column.
+pub trait ClickHouseRecord: Sized {
+    /// Name and type (e.g. `("price", "Float64")`) of the inserted columns, in order.
+    fn columns() -> &'static [(&'static str, &'static str)];
+
+    /// Write the value of the `column` (index into [`columns`](ClickHouseRecord::columns)) of every one
+    /// of the `records`.
+    fn encode_column(records: &[Self], column: usize, writer: &mut ColumnWriter<'_>);
+}
+
+/// Handling of the batch when the insert fails (connection lost, server unavailable or rejecting it).
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum Backpressure {
+    /// Return the error, which stops the [`BackgroundSink`](crate::sink::BackgroundSink) (default).
+    #[default]
+    Fail,
+    /// Discard the batch (counted by [`ClickHouseSink::dropped`]) and carry on.
+    Drop,
+    /// Keep the rows and insert them together with the next batch, once more than `max_rows` are
+    /// buffered the oldest ones are discarded.
+    Retry { max_rows: usize },
+}
+
+/// Sink inserting the events into a ClickHouse table, see the [module](self) documentation.
+pub struct ClickHouseSink<E, S> {
+    connect: Connect<S>,
+    connection: Option<Connection<S>>,
+    credentials: Credentials,
+    query: String,
+    pending: Vec<E>,
+    batch_size: usize,
+    interval_ns: u64,
+    // receive time of the oldest pending event
+    oldest_ns: Option<u64>,
+    backpressure: Backpressure,
+    timeout: Duration,
+    inserted: u64,
+    dropped: u64,
+    time_source: Box<dyn TimeSource + Send>,
+}
+
+impl<E, S> Debug for ClickHouseSink<E, S> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClickHouseSink")
+            .field("query", &self.query)
+            .field("connected", &self.connection.is_some())
+            .field("pending", &self.pending.len())
+            .field("batch_size", &self.batch_size)
+            .field("interval_ns", &self.interval_ns)
+            .field("backpressure", &self.backpressure)
+            .field("inserted", &self.inserted)
+            .field("dropped", &self.dropped)
+            .finish()
+    }
+}
+
+impl<E: ClickHouseRecord + Clone, S: Read + Write> ClickHouseSink<E, S> {
+    /// Create sink inserting into the `table` of the default database as the `default` user, the
+    /// streams are created with `connect`.
+    pub fn new<F>(connect: F, table: &str) -> ClickHouseSink<E, S>
+    where
+        F: FnMut() -> io::Result<S> + Send + 'static,
+    {
+        let columns = E::columns()
+            .iter()
+            .map(|(name, _)| format!("`{name}`"))
+            .collect::<Vec<_>>();
+        Self {
+            connect: Box::new(connect),
+            connection: None,
+            credentials: Credentials::default(),
+            query: format!("INSERT INTO {table} ({}) VALUES", columns.join(", ")),
+            pending: Vec::with_capacity(DEFAULT_BATCH_SIZE),
+            batch_size: DEFAULT_BATCH_SIZE,
+            interval_ns: DEFAULT_INTERVAL.as_nanos() as u64,
+            oldest_ns: None,
+            backpressure: Backpressure::default(),
+            timeout: DEFAULT_TIMEOUT,
+            inserted: 0,
+            dropped: 0,
+            time_source: Box::new(SystemTimeClockSource),
+        }
+    }
+
+    /// Connect to the `database` as the `user` (the `default` one otherwise).
+    pub fn with_credentials(self, database: &str, user: &str, password: &str) -> ClickHouseSink<E, S> {
+        Self {
+            credentials: Credentials {
+                database: database.to_owned(),
+                user: user.to_owned(),
+                password: password.to_owned(),
+            },
+            ..self
+        }
+    }
+
+    /// Number of events per insert (`8192` by default).
+    pub fn with_batch_size(self, batch_size: usize) -> ClickHouseSink<E, S> {
+        let batch_size = batch_size.max(1);
+        Self {
+            pending: Vec::with_capacity(batch_size),
+            batch_size,
+            ..self
+        }
+    }
+
+    /// Insert a partial batch once its oldest event has been buffered for the `interval` (`1s` by
+    /// default).
+    pub fn with_interval(self, interval: Duration) -> ClickHouseSink<E, S> {
+        Self {
+            interval_ns: interval.as_nanos() as u64,
+            ..self
+        }
+    }
+
+    /// Handling of the batches that fail to insert, [`Backpressure::Fail`] by default.
+    pub fn with_backpressure(self, backpressure: Backpressure) -> ClickHouseSink<E, S> {
+        Self { backpressure, ..self }
+    }
+
+    /// Fail the connect or insert that does not complete within the `timeout` (`10s` by default).
+    pub fn with_timeout(self, timeout: Duration) -> ClickHouseSink<E, S> {
+        Self { timeout, ..self }
+    }
+
+    /// Specify custom [`TimeSource`] for the insert interval instead of the default system time source.
+    pub fn with_time_source<TS>(self, time_source: TS) -> ClickHouseSink<E, S>
+    where
+        TS: TimeSource + Send + 'static,
+    {
+        Self {
+            time_source: Box::new(time_source),
+            ..self
+        }
+    }
+
+    /// Number of events buffered and not inserted yet.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Number of events inserted so far.
+    pub const fn inserted(&self) -> u64 {
+        self.inserted
+    }
+
+    /// Number of events discarded after failed inserts.
+    pub const fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    fn insert_pending(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        match self.insert() {
+            Ok(()) => {
+                self.inserted += self.pending.len() as u64;
+                self.pending.clear();
+                self.oldest_ns = None;
+                Ok(())
+            }
+            Err(err) => {
+                // the connection state is unknown after a failure, the next insert dials again
+                self.connection = None;
+                self.failed(err)
+            }
+        }
+    }
+
+    fn insert(&mut self) -> io::Result<()> {
+        if self.connection.is_none() {
+            let stream = (self.connect)()?;
+            self.connection = Some(Connection::open(stream, &self.credentials, self.timeout)?);
+        }
+        let connection = self.connection.as_mut().unwrap();
+        let (columns, pending) = (E::columns(), &self.pending);
+        connection.insert(&self.query, columns, |buf| {
+            put_block(buf, columns, pending.len(), |column, writer| E::encode_column(pending, column, writer))
+        })
+    }
+
+    fn failed(&mut self, err: io::Error) -> io::Result<()> {
+        match self.backpressure {
+            Backpressure::Fail => Err(err),
+            Backpressure::Drop => {
+                warn!("clickhouse insert of {} events failed, dropped: {err}", self.pending.len());
+                self.dropped += self.pending.len() as u64;
+                self.pending.clear();
+                self.oldest_ns = None;
+                Ok(())
+            }
+            Backpressure::Retry { max_rows } => {
+                let excess = self.pending.len().saturating_sub(max_rows);
+                warn!(
+                    "clickhouse insert of {} events failed, retrying with the next batch ({excess} dropped): {err}",
+                    self.pending.len()
+                );
+                self.pending.drain(..excess);
+                self.dropped += excess as u64;
+                // retry after the next interval rather than on every write
+                self.oldest_ns = Some(self.time_source.current_time_nanos());
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<E: ClickHouseRecord + Clone, S: Read + Write> Sink<E> for ClickHouseSink<E, S> {
+    fn write(&mut self, event: &E) -> io::Result<()> {
+        let now_ns = self.time_source.current_time_nanos();
+        let oldest_ns = *self.oldest_ns.get_or_insert(now_ns);
+        self.pending.push(event.clone());
+        // rows kept by a failed insert are retried once another batch has been buffered
+        if self.pending.len().is_multiple_of(self.batch_size) || now_ns.saturating_sub(oldest_ns) >= self.interval_ns {
+            self.insert_pending()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.insert_pending()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::native::{put_block_header, put_string, put_uvarint, server};
+    use super::*;
+    use std::io::ErrorKind::WouldBlock;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone)]
+    struct Tick {
+        price: f64,
+        symbol: &'static str,
+    }
+
+    impl ClickHouseRecord for Tick {
+        fn columns() -> &'static [(&'static str, &'static str)] {
+            &[("price", "Float64"), ("symbol", "String")]
+        }
+
+        fn encode_column(records: &[Self], column: usize, writer: &mut ColumnWriter<'_>) {
+            for tick in records {
+                match column {
+                    0 => writer.f64(tick.price),
+                    _ => writer.string(tick.symbol),
+                }
+            }
+        }
+    }
+
+    /// Replies with the scripted server packets once the client has written anything.
+    struct Server {
+        replies: io::Cursor<Vec<u8>>,
+        written: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Read for Server {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.replies.read(buf)? {
+                0 => Err(io::Error::from(WouldBlock)),
+                read => Ok(read),
+            }
+        }
+    }
+
+    impl Write for Server {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn hello(replies: &mut Vec<u8>) {
+        put_uvarint(replies, server::HELLO);
+        put_string(replies, b"ClickHouse");
+        put_uvarint(replies, 24);
+        put_uvarint(replies, 8);
+        put_uvarint(replies, 54470);
+        put_string(replies, b"UTC");
+    }
+
+    fn sample_block(replies: &mut Vec<u8>) {
+        put_uvarint(replies, server::DATA);
+        put_string(replies, b"");
+        put_block_header(replies, 2, 0);
+        for (name, data_type) in Tick::columns() {
+            put_string(replies, name.as_bytes());
+            put_string(replies, data_type.as_bytes());
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct ManualClock(Arc<Mutex<u64>>);
+
+    impl TimeSource for ManualClock {
+        fn current_time_nanos(&self) -> u64 {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    #[test]
+    fn should_insert_batches() {
+        let written = Arc::new(Mutex::new(vec![]));
+        let mut replies = vec![];
+        hello(&mut replies);
+        for _ in 0..2 {
+            sample_block(&mut replies);
+            put_uvarint(&mut replies, server::PROGRESS);
+            replies.extend_from_slice(&[2, 40, 0]);
+            put_uvarint(&mut replies, server::END_OF_STREAM);
+        }
+        let connect = {
+            let written = written.clone();
+            move || {
+                Ok(Server {
+                    replies: io::Cursor::new(replies.clone()),
+                    written: written.clone(),
+                })
+            }
+        };
+        let clock = ManualClock::default();
+        let mut sink = ClickHouseSink::<Tick, _>::new(connect, "ticks")
+            .with_batch_size(2)
+            .with_interval(Duration::from_secs(1))
+            .with_time_source(clock.clone());
+
+        let tick = |price| Tick {
+            price,
+            symbol: "BTCUSDT",
+        };
+        sink.write(&tick(1.0)).unwrap();
+        assert_eq!((1, 0), (sink.pending(), sink.inserted()));
+        sink.write(&tick(2.0)).unwrap();
+        assert_eq!((0, 2), (sink.pending(), sink.inserted()));
+        let written_bytes = written.lock().unwrap().clone();
+        let query = b"INSERT INTO ticks (`price`, `symbol`) VALUES";
+        assert!(written_bytes.windows(query.len()).any(|window| window == query));
+        // both prices of the block follow the column name and type
+        let mut column = vec![];
+        put_string(&mut column, b"price");
+        put_string(&mut column, b"Float64");
+        column.extend_from_slice(&1.0f64.to_le_bytes());
+        column.extend_from_slice(&2.0f64.to_le_bytes());
+        assert!(written_bytes.windows(column.len()).any(|window| window == column));
+
+        // partial batch inserted once the interval has passed
+        sink.write(&tick(3.0)).unwrap();
+        *clock.0.lock().unwrap() = 1_000_000_000;
+        sink.write(&tick(4.0)).unwrap();
+        assert_eq!((0, 4), (sink.pending(), sink.inserted()));
+    }
+
+    #[test]
+    fn should_apply_backpressure_on_failure() {
+        let dials = Arc::new(AtomicU32::new(0));
+        let sink = |backpressure| {
+            let dials = dials.clone();
+            let connect = move || {
+                dials.fetch_add(1, Ordering::Relaxed);
+                Err(io::Error::from(io::ErrorKind::ConnectionRefused))
+            };
+            ClickHouseSink::<Tick, Server>::new(connect, "ticks")
+                .with_batch_size(1)
+                .with_backpressure(backpressure)
+        };
+        let tick = Tick {
+            price: 1.0,
+            symbol: "BTCUSDT",
+        };
+
+        let mut fail = sink(Backpressure::Fail);
+        assert!(fail.flush().is_ok());
+        assert!(fail.write(&tick).is_err());
+
+        let mut retry = sink(Backpressure::Retry { max_rows: 2 });
+        for _ in 0..3 {
+            retry.write(&tick).unwrap();
+        }
+        assert_eq!((2, 1), (retry.pending(), retry.dropped()));
+
+        let mut drop = sink(Backpressure::Drop);
+        drop.write(&tick).unwrap();
+        assert_eq!((0, 1), (drop.pending(), drop.dropped()));
+        assert_eq!(5, dials.load(Ordering::Relaxed));
+    }
+}
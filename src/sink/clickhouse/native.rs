@@ -0,0 +1,466 @@
+//! Subset of the ClickHouse native TCP protocol needed to insert blocks: the hello exchange and the
+//! `INSERT` query, without compression. The client announces an old protocol revision so that the
+//! server only sends the packets handled here.
+
+use std::io;
+use std::io::ErrorKind::{InvalidData, NotConnected, TimedOut, WouldBlock, WriteZero};
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+/// Protocol revision announced by the client.
+pub(crate) const CLIENT_REVISION: u64 = 54213;
+const CLIENT_NAME: &str = "boomnet";
+const REVISION_WITH_SERVER_TIMEZONE: u64 = 54058;
+const REVISION_WITH_QUOTA_KEY: u64 = 54060;
+const REVISION_WITH_SERVER_DISPLAY_NAME: u64 = 54372;
+const REVISION_WITH_VERSION_PATCH: u64 = 54401;
+
+pub(crate) mod client {
+    pub const HELLO: u64 = 0;
+    pub const QUERY: u64 = 1;
+    pub const DATA: u64 = 2;
+}
+
+pub(crate) mod server {
+    pub const HELLO: u64 = 0;
+    pub const DATA: u64 = 1;
+    pub const EXCEPTION: u64 = 2;
+    pub const PROGRESS: u64 = 3;
+    pub const PONG: u64 = 4;
+    pub const END_OF_STREAM: u64 = 5;
+    pub const PROFILE_INFO: u64 = 6;
+}
+
+const QUERY_KIND_INITIAL: u8 = 1;
+const INTERFACE_TCP: u8 = 1;
+const STAGE_COMPLETE: u64 = 2;
+const COMPRESSION_DISABLED: u64 = 0;
+
+#[inline]
+pub(crate) fn put_uvarint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+#[inline]
+pub(crate) fn put_string(buf: &mut Vec<u8>, value: &[u8]) {
+    put_uvarint(buf, value.len() as u64);
+    buf.extend_from_slice(value);
+}
+
+/// Block info (not overflows, no bucket) followed by the number of columns and rows.
+pub(crate) fn put_block_header(buf: &mut Vec<u8>, columns: usize, rows: usize) {
+    put_uvarint(buf, 1);
+    buf.push(0);
+    put_uvarint(buf, 2);
+    buf.extend_from_slice(&(-1i32).to_le_bytes());
+    put_uvarint(buf, 0);
+    put_uvarint(buf, columns as u64);
+    put_uvarint(buf, rows as u64);
+}
+
+/// Data packet holding the empty block, which ends the external tables and the inserted data.
+pub(crate) fn put_empty_block(buf: &mut Vec<u8>) {
+    put_uvarint(buf, client::DATA);
+    put_string(buf, b"");
+    put_block_header(buf, 0, 0);
+}
+
+/// Column of a block being encoded, the values of all the rows follow each other in the native
+/// format (fixed width values are little endian).
+pub struct ColumnWriter<'a> {
+    buf: &'a mut Vec<u8>,
+}
+
+impl ColumnWriter<'_> {
+    /// `UInt8` value.
+    #[inline]
+    pub fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    /// `UInt16` value.
+    #[inline]
+    pub fn u16(&mut self, value: u16) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// `UInt32` value.
+    #[inline]
+    pub fn u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// `UInt64` value.
+    #[inline]
+    pub fn u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// `Int32` value.
+    #[inline]
+    pub fn i32(&mut self, value: i32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// `Int64` value, also used for `DateTime64` and `Decimal64`.
+    #[inline]
+    pub fn i64(&mut self, value: i64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// `Float32` value.
+    #[inline]
+    pub fn f32(&mut self, value: f32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// `Float64` value.
+    #[inline]
+    pub fn f64(&mut self, value: f64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// `Bool` value.
+    #[inline]
+    pub fn bool(&mut self, value: bool) {
+        self.buf.push(value as u8);
+    }
+
+    /// `String` value.
+    #[inline]
+    pub fn string(&mut self, value: &str) {
+        self.bytes(value.as_bytes());
+    }
+
+    /// `String` value that is not necessarily UTF-8.
+    #[inline]
+    pub fn bytes(&mut self, value: &[u8]) {
+        put_string(self.buf, value);
+    }
+}
+
+/// Data packet with the `columns` (name and type) of the `rows`, the `encode` function writes the
+/// values of the column with the given index.
+pub(crate) fn put_block<F>(buf: &mut Vec<u8>, columns: &[(&str, &str)], rows: usize, mut encode: F)
+where
+    F: FnMut(usize, &mut ColumnWriter<'_>),
+{
+    put_uvarint(buf, client::DATA);
+    put_string(buf, b"");
+    put_block_header(buf, columns.len(), rows);
+    for (index, (name, data_type)) in columns.iter().enumerate() {
+        put_string(buf, name.as_bytes());
+        put_string(buf, data_type.as_bytes());
+        encode(index, &mut ColumnWriter { buf });
+    }
+}
+
+/// Database, user and password of the connection.
+#[derive(Debug, Clone)]
+pub(crate) struct Credentials {
+    pub database: String,
+    pub user: String,
+    pub password: String,
+}
+
+impl Default for Credentials {
+    fn default() -> Self {
+        Self {
+            database: String::new(),
+            user: "default".to_owned(),
+            password: String::new(),
+        }
+    }
+}
+
+/// Connection that completed the hello exchange. The IO blocks (spinning on a non blocking stream)
+/// for up to the timeout.
+pub(crate) struct Connection<S> {
+    stream: S,
+    inbound: Vec<u8>,
+    position: usize,
+    revision: u64,
+    timeout: Duration,
+    deadline: Instant,
+}
+
+impl<S: Read + Write> Connection<S> {
+    pub fn open(stream: S, credentials: &Credentials, timeout: Duration) -> io::Result<Connection<S>> {
+        let mut connection = Self {
+            stream,
+            inbound: Vec::with_capacity(4096),
+            position: 0,
+            revision: CLIENT_REVISION,
+            timeout,
+            deadline: Instant::now() + timeout,
+        };
+        let mut hello = Vec::with_capacity(128);
+        put_uvarint(&mut hello, client::HELLO);
+        put_string(&mut hello, CLIENT_NAME.as_bytes());
+        put_uvarint(&mut hello, 0);
+        put_uvarint(&mut hello, 1);
+        put_uvarint(&mut hello, CLIENT_REVISION);
+        put_string(&mut hello, credentials.database.as_bytes());
+        put_string(&mut hello, credentials.user.as_bytes());
+        put_string(&mut hello, credentials.password.as_bytes());
+        connection.send(&hello)?;
+
+        match connection.uvarint()? {
+            server::HELLO => {
+                connection.string()?;
+                connection.uvarint()?;
+                connection.uvarint()?;
+                connection.revision = connection.uvarint()?.min(CLIENT_REVISION);
+                if connection.revision >= REVISION_WITH_SERVER_TIMEZONE {
+                    connection.string()?;
+                }
+                if connection.revision >= REVISION_WITH_SERVER_DISPLAY_NAME {
+                    connection.string()?;
+                }
+                if connection.revision >= REVISION_WITH_VERSION_PATCH {
+                    connection.uvarint()?;
+                }
+                connection.consume();
+                Ok(connection)
+            }
+            server::EXCEPTION => Err(connection.exception()?),
+            _ => Err(io::Error::new(InvalidData, "unexpected clickhouse packet, expected hello")),
+        }
+    }
+
+    /// Run the `INSERT` query, the `encode_block` function appends the data packet of the rows. The
+    /// names of the `columns` must match the ones the server expects.
+    pub fn insert<F>(&mut self, query: &str, columns: &[(&str, &str)], encode_block: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut Vec<u8>),
+    {
+        self.deadline = Instant::now() + self.timeout;
+        let mut outbound = Vec::with_capacity(256 + query.len());
+        self.put_query(&mut outbound, query);
+        put_empty_block(&mut outbound);
+        self.send(&outbound)?;
+
+        // the server answers with the sample block of the table
+        loop {
+            match self.uvarint()? {
+                server::DATA => {
+                    let names = self.header_block()?;
+                    if names.len() != columns.len()
+                        || names.iter().zip(columns).any(|(name, (column, _))| name != column)
+                    {
+                        return Err(io::Error::new(
+                            InvalidData,
+                            format!("clickhouse expects columns {names:?}, the records have {columns:?}"),
+                        ));
+                    }
+                    break;
+                }
+                packet => self.skip(packet)?,
+            }
+        }
+
+        outbound.clear();
+        encode_block(&mut outbound);
+        put_empty_block(&mut outbound);
+        self.send(&outbound)?;
+        loop {
+            match self.uvarint()? {
+                server::END_OF_STREAM => {
+                    self.consume();
+                    return Ok(());
+                }
+                server::DATA => {
+                    self.header_block()?;
+                }
+                packet => self.skip(packet)?,
+            }
+        }
+    }
+
+    fn put_query(&self, buf: &mut Vec<u8>, query: &str) {
+        put_uvarint(buf, client::QUERY);
+        put_string(buf, b"");
+        // client info
+        buf.push(QUERY_KIND_INITIAL);
+        put_string(buf, b"");
+        put_string(buf, b"");
+        put_string(buf, b"0.0.0.0:0");
+        buf.push(INTERFACE_TCP);
+        put_string(buf, b"");
+        put_string(buf, b"");
+        put_string(buf, CLIENT_NAME.as_bytes());
+        put_uvarint(buf, 0);
+        put_uvarint(buf, 1);
+        put_uvarint(buf, CLIENT_REVISION);
+        if self.revision >= REVISION_WITH_QUOTA_KEY {
+            put_string(buf, b"");
+        }
+        // no settings
+        put_string(buf, b"");
+        put_uvarint(buf, STAGE_COMPLETE);
+        put_uvarint(buf, COMPRESSION_DISABLED);
+        put_string(buf, query.as_bytes());
+    }
+
+    /// Packets that carry no information the insert needs, an exception fails the insert.
+    fn skip(&mut self, packet: u64) -> io::Result<()> {
+        match packet {
+            server::PROGRESS => {
+                // rows, bytes and total rows
+                for _ in 0..3 {
+                    self.uvarint()?;
+                }
+            }
+            server::PROFILE_INFO => {
+                for _ in 0..3 {
+                    self.uvarint()?;
+                }
+                self.u8()?;
+                self.uvarint()?;
+                self.u8()?;
+            }
+            server::PONG => {}
+            server::EXCEPTION => return Err(self.exception()?),
+            _ => return Err(io::Error::new(InvalidData, format!("unexpected clickhouse packet {packet}"))),
+        }
+        self.consume();
+        Ok(())
+    }
+
+    /// Block without rows, returns the names of its columns.
+    fn header_block(&mut self) -> io::Result<Vec<String>> {
+        self.string()?;
+        loop {
+            match self.uvarint()? {
+                0 => break,
+                1 => {
+                    self.u8()?;
+                }
+                2 => {
+                    self.take(4)?;
+                }
+                _ => return Err(io::Error::new(InvalidData, "unknown clickhouse block info field")),
+            }
+        }
+        let columns = self.uvarint()?;
+        if self.uvarint()? != 0 {
+            return Err(io::Error::new(InvalidData, "unexpected rows in clickhouse block"));
+        }
+        let mut names = Vec::with_capacity(columns as usize);
+        for _ in 0..columns {
+            names.push(String::from_utf8_lossy(self.string()?).into_owned());
+            self.string()?;
+        }
+        self.consume();
+        Ok(names)
+    }
+
+    fn exception(&mut self) -> io::Result<io::Error> {
+        let mut messages = vec![];
+        loop {
+            let code = i32::from_le_bytes(self.take(4)?.try_into().unwrap());
+            self.string()?;
+            let message = String::from_utf8_lossy(self.string()?).into_owned();
+            self.string()?;
+            messages.push(format!("code {code}: {message}"));
+            if self.u8()? == 0 {
+                break;
+            }
+        }
+        self.consume();
+        Ok(io::Error::other(format!("clickhouse exception {}", messages.join(", caused by "))))
+    }
+
+    fn send(&mut self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.stream.write(buf) {
+                Ok(0) => return Err(io::Error::from(WriteZero)),
+                Ok(written) => buf = &buf[written..],
+                // the non blocking connect may still be in progress
+                Err(err) if matches!(err.kind(), WouldBlock | NotConnected) => self.check_deadline()?,
+                Err(err) => return Err(err),
+            }
+        }
+        loop {
+            match self.stream.flush() {
+                Ok(()) => return Ok(()),
+                Err(err) if err.kind() == WouldBlock => self.check_deadline()?,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Next `len` bytes of the packet being parsed.
+    fn take(&mut self, len: usize) -> io::Result<&[u8]> {
+        while self.inbound.len() - self.position < len {
+            let available = self.inbound.len();
+            self.inbound.resize(available + 4096, 0);
+            let read = self.stream.read(&mut self.inbound[available..]);
+            self.inbound.truncate(available + *read.as_ref().unwrap_or(&0));
+            match read {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "clickhouse connection closed")),
+                Ok(_) => {}
+                Err(err) if matches!(err.kind(), WouldBlock | NotConnected) => self.check_deadline()?,
+                Err(err) => return Err(err),
+            }
+        }
+        let bytes = &self.inbound[self.position..self.position + len];
+        self.position += len;
+        Ok(bytes)
+    }
+
+    #[inline]
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn uvarint(&mut self) -> io::Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(io::Error::new(InvalidData, "clickhouse varint overflow"))
+    }
+
+    fn string(&mut self) -> io::Result<&[u8]> {
+        let len = self.uvarint()? as usize;
+        self.take(len)
+    }
+
+    /// Discard the packets parsed so far.
+    fn consume(&mut self) {
+        self.inbound.drain(..self.position);
+        self.position = 0;
+    }
+
+    #[inline]
+    fn check_deadline(&self) -> io::Result<()> {
+        match Instant::now() >= self.deadline {
+            true => Err(io::Error::new(TimedOut, "clickhouse request timed out")),
+            false => {
+                std::hint::spin_loop();
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_encode_uvarint() {
+        let mut buf = vec![];
+        put_uvarint(&mut buf, 54213);
+        assert_eq!(vec![0xc5, 0xa7, 0x03], buf);
+    }
+}
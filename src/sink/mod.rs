@@ -0,0 +1,235 @@
+//! Pluggable persistence of decoded events.
+//!
+//! A [`Sink`] receives the events decoded by the endpoint (ticks, book updates, order acks) and
+//! persists them, e.g. as Parquet files for analytics (`parquet` feature, see [`ParquetSink`]) or into
+//! ClickHouse (`clickhouse` feature, see [`clickhouse::ClickHouseSink`]). Sinks usually perform
+//! blocking IO, so they are run on a background thread by [`BackgroundSink`], which hands the events
+//! over through a bounded queue and never blocks the IO thread: the events that do not fit into the
+//! queue are dropped and counted.
+//!
+//! ## Examples
+//! ```
+//! use boomnet::sink::{BackgroundSink, Sink};
+//!
+//! #[derive(Default)]
+//! struct Count(usize);
+//!
+//! impl Sink<u64> for Count {
+//!     fn write(&mut self, _event: &u64) -> std::io::Result<()> {
+//!         self.0 += 1;
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let mut sink = BackgroundSink::spawn("tick-capture", 1024, Count::default()).unwrap();
+//! for price in 0..100 {
+//!     sink.send(price);
+//! }
+//! assert_eq!(100, sink.close().unwrap().0);
+//! ```
+
+#[cfg(feature = "clickhouse")]
+pub mod clickhouse;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+
+#[cfg(feature = "parquet")]
+pub use crate::sink::parquet::{ParquetRecord, ParquetSink};
+use log::warn;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, SyncSender, TrySendError, sync_channel};
+use std::thread::JoinHandle;
+
+/// Destination of decoded events.
+pub trait Sink<E> {
+    /// Persist (or buffer) the `event`.
+    fn write(&mut self, event: &E) -> io::Result<()>;
+
+    /// Persist the buffered events.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<E, S: Sink<E> + ?Sized> Sink<E> for Box<S> {
+    fn write(&mut self, event: &E) -> io::Result<()> {
+        (**self).write(event)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+}
+
+enum Message<E> {
+    Event(E),
+    Flush,
+}
+
+/// Runs the sink on a dedicated thread, see the [module](self) documentation.
+pub struct BackgroundSink<E, S> {
+    sender: Option<SyncSender<Message<E>>>,
+    handle: Option<JoinHandle<(S, io::Result<()>)>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl<E, S> BackgroundSink<E, S>
+where
+    E: Send + 'static,
+    S: Sink<E> + Send + 'static,
+{
+    /// Spawn the thread `name` that writes the events to the `sink`, up to `capacity` events can be
+    /// queued.
+    pub fn spawn(name: &str, capacity: usize, sink: S) -> io::Result<BackgroundSink<E, S>> {
+        let (sender, receiver) = sync_channel(capacity.max(1));
+        let handle = std::thread::Builder::new()
+            .name(name.to_owned())
+            .spawn(move || run(sink, receiver))?;
+        Ok(Self {
+            sender: Some(sender),
+            handle: Some(handle),
+            dropped: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    /// Queue the `event` without blocking, returns `false` if it has been dropped because the queue is
+    /// full or the sink has failed.
+    #[inline]
+    pub fn send(&mut self, event: E) -> bool {
+        self.try_send(Message::Event(event))
+    }
+
+    #[inline]
+    fn try_send(&mut self, message: Message<E>) -> bool {
+        let Some(sender) = self.sender.as_ref() else {
+            return false;
+        };
+        match sender.try_send(message) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                if self.dropped.fetch_add(1, Ordering::Relaxed) == 0 {
+                    warn!("sink queue is full, events dropped");
+                }
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                // the sink has failed, the error is reported by `close`
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+
+    /// Number of events dropped so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Write the queued events and flush the sink, then stop the thread. Returns the sink or the
+    /// first error it has failed with.
+    pub fn close(mut self) -> io::Result<S> {
+        self.join()
+    }
+
+    fn join(&mut self) -> io::Result<S> {
+        // disconnecting the queue stops the thread once it has been drained
+        self.sender.take();
+        let handle = self
+            .handle
+            .take()
+            .ok_or_else(|| io::Error::other("sink already closed"))?;
+        let (sink, result) = handle.join().map_err(|_| io::Error::other("sink thread panicked"))?;
+        result.map(|()| sink)
+    }
+}
+
+impl<E, S> Sink<E> for BackgroundSink<E, S>
+where
+    E: Clone + Send + 'static,
+    S: Sink<E> + Send + 'static,
+{
+    /// Queue copy of the `event`, dropped events are not reported as an error (see [`BackgroundSink::dropped`]).
+    fn write(&mut self, event: &E) -> io::Result<()> {
+        self.send(event.clone());
+        Ok(())
+    }
+
+    /// Ask the thread to flush the sink once it has written the events queued so far.
+    fn flush(&mut self) -> io::Result<()> {
+        self.try_send(Message::Flush);
+        Ok(())
+    }
+}
+
+impl<E, S> Drop for BackgroundSink<E, S> {
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(handle) = self.handle.take() {
+            match handle.join() {
+                Ok((_, Err(err))) => warn!("sink failed: {err}"),
+                Ok((_, Ok(()))) => {}
+                Err(_) => warn!("sink thread panicked"),
+            }
+        }
+    }
+}
+
+fn run<E, S: Sink<E>>(mut sink: S, receiver: Receiver<Message<E>>) -> (S, io::Result<()>) {
+    for message in receiver.iter() {
+        let result = match message {
+            Message::Event(event) => sink.write(&event),
+            Message::Flush => sink.flush(),
+        };
+        if let Err(err) = result {
+            // stop consuming, the events sent from now on are dropped
+            return (sink, Err(err));
+        }
+    }
+    let result = sink.flush();
+    (sink, result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<u64>,
+        flushes: usize,
+    }
+
+    impl Sink<u64> for Recorder {
+        fn write(&mut self, event: &u64) -> io::Result<()> {
+            if *event == u64::MAX {
+                return Err(io::Error::other("poison"));
+            }
+            self.events.push(*event);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_write_events_on_background_thread() {
+        let mut sink = BackgroundSink::spawn("sink-test", 16, Recorder::default()).unwrap();
+        for event in 0..10 {
+            sink.write(&event).unwrap();
+        }
+        sink.flush().unwrap();
+        let recorder = sink.close().unwrap();
+        assert_eq!((0..10).collect::<Vec<_>>(), recorder.events);
+        // explicit flush and the final one
+        assert_eq!(2, recorder.flushes);
+
+        let mut sink = BackgroundSink::spawn("sink-test", 16, Recorder::default()).unwrap();
+        assert!(sink.send(u64::MAX));
+        assert!(sink.close().is_err());
+    }
+}
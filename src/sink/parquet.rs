@@ -0,0 +1,196 @@
+//! Parquet file sink.
+//!
+//! ## Examples
+//! ```no_run
+//! use arrow_array::{ArrayRef, Float64Array, RecordBatch, UInt64Array};
+//! use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+//! use boomnet::sink::{BackgroundSink, ParquetRecord, ParquetSink};
+//! use std::sync::Arc;
+//!
+//! #[derive(Clone)]
+//! struct Tick {
+//!     time_ns: u64,
+//!     price: f64,
+//! }
+//!
+//! impl ParquetRecord for Tick {
+//!     fn schema() -> SchemaRef {
+//!         Arc::new(Schema::new(vec![
+//!             Field::new("time_ns", DataType::UInt64, false),
+//!             Field::new("price", DataType::Float64, false),
+//!         ]))
+//!     }
+//!
+//!     fn to_batch(records: &[Self]) -> Result<RecordBatch, ArrowError> {
+//!         let time: ArrayRef = Arc::new(records.iter().map(|tick| tick.time_ns).collect::<UInt64Array>());
+//!         let price: ArrayRef = Arc::new(records.iter().map(|tick| tick.price).collect::<Float64Array>());
+//!         RecordBatch::try_new(Self::schema(), vec![time, price])
+//!     }
+//! }
+//!
+//! let parquet = ParquetSink::<Tick>::create("ticks.parquet").unwrap().with_batch_size(65536);
+//! let mut sink = BackgroundSink::spawn("tick-capture", 1 << 16, parquet).unwrap();
+//! sink.send(Tick { time_ns: 1, price: 100.5 });
+//! sink.close().unwrap().finish().unwrap();
+//! ```
+
+use crate::sink::Sink;
+use arrow_array::RecordBatch;
+use arrow_schema::{ArrowError, SchemaRef};
+use log::warn;
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+const DEFAULT_BATCH_SIZE: usize = 8192;
+
+/// Event that can be written to a Parquet file, converted to Arrow columns in batches.
+pub trait ParquetRecord: Sized {
+    /// Schema of the file, must be the same for every batch.
+    fn schema() -> SchemaRef;
+
+    /// Convert the `records` to a batch with the [`schema`](ParquetRecord::schema).
+    fn to_batch(records: &[Self]) -> Result<RecordBatch, ArrowError>;
+}
+
+/// Buffers the events and writes every `batch_size` of them as a row group of the Parquet file.
+pub struct ParquetSink<E> {
+    writer: Option<ArrowWriter<File>>,
+    pending: Vec<E>,
+    batch_size: usize,
+}
+
+impl<E: ParquetRecord + Clone> ParquetSink<E> {
+    /// Create (or truncate) the Parquet file at `path`.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<ParquetSink<E>> {
+        let file = File::create(path)?;
+        let properties = WriterProperties::builder()
+            .set_max_row_group_size(DEFAULT_BATCH_SIZE)
+            .build();
+        let writer = ArrowWriter::try_new(file, E::schema(), Some(properties)).map_err(io::Error::other)?;
+        Ok(Self {
+            writer: Some(writer),
+            pending: Vec::with_capacity(DEFAULT_BATCH_SIZE),
+            batch_size: DEFAULT_BATCH_SIZE,
+        })
+    }
+
+    /// Number of events per row group (`8192` by default).
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        // the writer is finished on drop, so the sink can not be rebuilt with the struct update syntax
+        self.batch_size = batch_size.max(1);
+        self.pending = Vec::with_capacity(self.batch_size);
+        self
+    }
+
+    /// Number of events buffered and not written yet.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn write_pending(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let writer = self
+            .writer
+            .as_mut()
+            .ok_or_else(|| io::Error::other("parquet file already finished"))?;
+        let batch = E::to_batch(&self.pending).map_err(io::Error::other)?;
+        writer.write(&batch).map_err(io::Error::other)?;
+        // close the row group so that it does not span batches
+        writer.flush().map_err(io::Error::other)?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Write the buffered events and the file footer. The file is only readable once finished.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.close()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.write_pending()?;
+        if let Some(writer) = self.writer.take() {
+            writer.close().map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+}
+
+impl<E: ParquetRecord + Clone> Sink<E> for ParquetSink<E> {
+    fn write(&mut self, event: &E) -> io::Result<()> {
+        self.pending.push(event.clone());
+        if self.pending.len() >= self.batch_size {
+            self.write_pending()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_pending()
+    }
+}
+
+impl<E> Drop for ParquetSink<E> {
+    fn drop(&mut self) {
+        if let Some(writer) = self.writer.take() {
+            if !self.pending.is_empty() {
+                warn!("parquet sink dropped with {} events not written", self.pending.len());
+            }
+            if let Err(err) = writer.close() {
+                warn!("unable to finish parquet file: {err}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::{ArrayRef, UInt64Array};
+    use arrow_schema::{DataType, Field, Schema};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::sync::Arc;
+
+    #[derive(Clone)]
+    struct Tick(u64);
+
+    impl ParquetRecord for Tick {
+        fn schema() -> SchemaRef {
+            Arc::new(Schema::new(vec![Field::new("price", DataType::UInt64, false)]))
+        }
+
+        fn to_batch(records: &[Self]) -> Result<RecordBatch, ArrowError> {
+            let price: ArrayRef = Arc::new(records.iter().map(|tick| tick.0).collect::<UInt64Array>());
+            RecordBatch::try_new(Self::schema(), vec![price])
+        }
+    }
+
+    #[test]
+    fn should_write_events_in_row_groups() {
+        let path = std::env::temp_dir().join(format!("boomnet_parquet_{}.parquet", std::process::id()));
+        let mut sink = ParquetSink::<Tick>::create(&path).unwrap().with_batch_size(4);
+        for price in 0..10 {
+            sink.write(&Tick(price)).unwrap();
+        }
+        assert_eq!(2, sink.pending());
+        sink.finish().unwrap();
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(3, reader.metadata().num_row_groups());
+        let prices = reader
+            .build()
+            .unwrap()
+            .flat_map(|batch| {
+                let batch = batch.unwrap();
+                let column = batch.column(0).as_any().downcast_ref::<UInt64Array>().unwrap();
+                column.values().to_vec()
+            })
+            .collect::<Vec<_>>();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((0..10).collect::<Vec<_>>(), prices);
+    }
+}
@@ -1,12 +1,15 @@
 //! Stream that is buffering data written to it.
 
 use crate::service::select::Selectable;
-use crate::stream::{ConnectionInfo, ConnectionInfoProvider, RxTimestamped, RxTimestamps};
+#[cfg(any(feature = "rustls", feature = "openssl"))]
+use crate::stream::tls::{TlsParameters, TlsParametersProvider};
+use crate::stream::{ConnectionInfo, ConnectionInfoProvider, PendingWrites, RxTimestamped, RxTimestamps};
 #[cfg(feature = "mio")]
 use mio::{Interest, Registry, Token, event::Source};
 use std::io;
 use std::io::{ErrorKind, Read, Write};
 use std::mem::MaybeUninit;
+use std::os::fd::{AsRawFd, RawFd};
 
 /// Default buffer size in bytes.
 pub const DEFAULT_BUFFER_SIZE: usize = 1024;
@@ -83,6 +86,28 @@ impl<S: Write, const N: usize> Write for BufferedStream<S, N> {
     }
 }
 
+impl<S: Write + PendingWrites, const N: usize> PendingWrites for BufferedStream<S, N> {
+    fn has_pending_writes(&self) -> bool {
+        self.cursor > 0 || self.inner.has_pending_writes()
+    }
+
+    /// Unlike `flush`, keeps whatever the underlying stream does not accept without blocking.
+    fn drive_writes(&mut self) -> io::Result<bool> {
+        while self.cursor > 0 {
+            match self.inner.write(&self.buffer[..self.cursor]) {
+                Ok(0) => return Err(io::Error::new(ErrorKind::WriteZero, "unable to drain the buffer")),
+                Ok(len) => {
+                    self.buffer.copy_within(len..self.cursor, 0);
+                    self.cursor -= len;
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(err) => return Err(err),
+            }
+        }
+        self.inner.drive_writes()
+    }
+}
+
 impl<S: ConnectionInfoProvider, const N: usize> ConnectionInfoProvider for BufferedStream<S, N> {
     fn connection_info(&self) -> &ConnectionInfo {
         self.inner.connection_info()
@@ -99,6 +124,13 @@ impl<S: RxTimestamped, const N: usize> RxTimestamped for BufferedStream<S, N> {
     }
 }
 
+#[cfg(any(feature = "rustls", feature = "openssl"))]
+impl<S: TlsParametersProvider, const N: usize> TlsParametersProvider for BufferedStream<S, N> {
+    fn tls_parameters(&self) -> Option<TlsParameters> {
+        self.inner.tls_parameters()
+    }
+}
+
 /// Trait to convert any stream into `BufferedStream`.
 pub trait IntoBufferedStream<S> {
     /// Convert into `BufferedStream` and specify buffer length.
@@ -128,6 +160,12 @@ where
     }
 }
 
+impl<S: AsRawFd, const N: usize> AsRawFd for BufferedStream<S, N> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
 impl<S: Selectable> Selectable for BufferedStream<S> {
     fn connected(&mut self) -> io::Result<bool> {
         self.inner.connected()
@@ -140,6 +178,22 @@ impl<S: Selectable> Selectable for BufferedStream<S> {
     fn make_readable(&mut self) -> io::Result<()> {
         self.inner.make_readable()
     }
+
+    fn take_read_activity(&mut self) -> bool {
+        self.inner.take_read_activity()
+    }
+
+    fn take_bytes_read(&mut self) -> u64 {
+        self.inner.take_bytes_read()
+    }
+
+    fn take_bytes_written(&mut self) -> u64 {
+        self.inner.take_bytes_written()
+    }
+
+    fn set_read_paused(&mut self, paused: bool) {
+        self.inner.set_read_paused(paused)
+    }
 }
 
 #[cfg(feature = "mio")]
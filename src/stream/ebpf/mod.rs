@@ -0,0 +1,455 @@
+//! eBPF assisted socket latency tracer.
+//!
+//! [`SocketTracer`] loads the companion programs (`sock_latency.bpf.c`, shipped next to this module and
+//! compiled by the build script with `clang -target bpf`) and attaches them to the network interface
+//! (`tc` ingress) and to the cgroup of the sockets (`sockops`). For every registered socket the programs
+//! store the timestamps of the last delivered segment:
+//! - `netif_receive`: time the segment was received by the network stack (`tc` ingress),
+//! - `tcp_rcv`: time the segment was processed by the TCP stack (`sockops` header option parsing, enabled
+//!   for every socket of the cgroup established after the tracer has been loaded).
+//!
+//! [`TracedStream`] registers the socket with the tracer and, on every non empty read, correlates the
+//! kernel stages with the `SCM_TIMESTAMPING` hardware timestamp and the time the data reached the user
+//! into a [`LatencyRecord`]. The kernel only keeps the last segment per socket, so when a single read
+//! drains several segments the record describes the most recent one.
+//!
+//! Loading the programs requires `CAP_BPF` and `CAP_NET_ADMIN` (or `CAP_SYS_ADMIN`) and is not allowed in
+//! [restricted](crate::syscalls) mode.
+//!
+//! ## Examples
+//! ```no_run
+//! use boomnet::stream::ConnectionInfo;
+//! use boomnet::stream::ebpf::{IntoTracedStream, SocketTracer};
+//! use boomnet::stream::timestamping::TimestampingStream;
+//! use std::io::Read;
+//!
+//! let tracer = SocketTracer::load("eth0", "/sys/fs/cgroup").unwrap();
+//! let stream = ConnectionInfo::new("127.0.0.1", 8080).into_tcp_stream().unwrap();
+//! let mut stream = TimestampingStream::new(stream).into_traced_stream(&tracer).unwrap();
+//!
+//! let mut buf = [0u8; 1024];
+//! if stream.read(&mut buf).unwrap() > 0 {
+//!     if let Some(record) = stream.last_latency_record() {
+//!         println!("nic->kernel: {:?}, kernel->tcp: {:?}, tcp->user: {:?}",
+//!             record.nic_to_netif_ns(), record.netif_to_tcp_ns(), record.tcp_to_user_ns());
+//!     }
+//! }
+//! ```
+#![cfg(target_os = "linux")]
+
+use crate::service::select::Selectable;
+use crate::stream::{ConnectionInfo, ConnectionInfoProvider, PendingWrites, RxTimestamped, RxTimestamps};
+use crate::syscalls;
+use aya::maps::{HashMap, MapData};
+use aya::programs::{CgroupAttachMode, SchedClassifier, SockOps, TcAttachType, tc};
+use aya::{Ebpf, include_bytes_aligned};
+#[cfg(feature = "mio")]
+use mio::{Interest, Registry, Token, event::Source};
+use std::cell::RefCell;
+use std::fs::File;
+use std::io;
+use std::io::{IoSlice, Read, Write};
+use std::mem;
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::Path;
+use std::rc::Rc;
+
+/// Companion eBPF object compiled by the build script.
+static OBJECT: &[u8] = include_bytes_aligned!(concat!(env!("OUT_DIR"), "/sock_latency.bpf.o"));
+
+const NETIF_RECEIVE: &str = "netif_receive";
+const TCP_RCV: &str = "tcp_rcv";
+const SOCKETS: &str = "SOCKETS";
+
+/// Kernel stage timestamps of the last segment delivered to the socket, shared with the eBPF program.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct KernelStages {
+    /// Time the segment was received by the network stack (`CLOCK_MONOTONIC`).
+    pub netif_receive_ns: u64,
+    /// Time the segment was processed by the TCP stack (`CLOCK_MONOTONIC`).
+    pub tcp_rcv_ns: u64,
+    /// Length of the segment.
+    pub len: u32,
+    /// Number of segments seen since the socket has been registered.
+    pub segments: u32,
+}
+
+// SAFETY: plain `repr(C)` struct without padding, valid for any bit pattern
+unsafe impl aya::Pod for KernelStages {}
+
+/// Multi stage latency record of a single read, all timestamps are `CLOCK_REALTIME` nanoseconds
+/// (zero when the stage has not been captured).
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct LatencyRecord {
+    /// NIC hardware timestamp (`SCM_TIMESTAMPING`).
+    pub hw_raw_ns: u64,
+    /// Time the segment was received by the network stack (`tc` ingress).
+    pub netif_receive_ns: u64,
+    /// Time the segment was processed by the TCP stack (`sockops`).
+    pub tcp_rcv_ns: u64,
+    /// Time the read returned to the user.
+    pub user_ns: u64,
+}
+
+impl LatencyRecord {
+    /// Time between the NIC and the kernel receive path.
+    pub const fn nic_to_netif_ns(&self) -> Option<u64> {
+        elapsed(self.hw_raw_ns, self.netif_receive_ns)
+    }
+
+    /// Time spent in the kernel between the receive path and the TCP stack.
+    pub const fn netif_to_tcp_ns(&self) -> Option<u64> {
+        elapsed(self.netif_receive_ns, self.tcp_rcv_ns)
+    }
+
+    /// Time between the TCP stack and the data being returned to the user.
+    pub const fn tcp_to_user_ns(&self) -> Option<u64> {
+        elapsed(self.tcp_rcv_ns, self.user_ns)
+    }
+
+    /// Time between the earliest captured stage and the user.
+    pub const fn total_ns(&self) -> Option<u64> {
+        let start = if self.hw_raw_ns != 0 {
+            self.hw_raw_ns
+        } else if self.netif_receive_ns != 0 {
+            self.netif_receive_ns
+        } else {
+            self.tcp_rcv_ns
+        };
+        elapsed(start, self.user_ns)
+    }
+}
+
+#[inline]
+const fn elapsed(from: u64, to: u64) -> Option<u64> {
+    if from == 0 || to == 0 {
+        None
+    } else {
+        to.checked_sub(from)
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    // keeps the program loaded and attached
+    _ebpf: Ebpf,
+    sockets: HashMap<MapData, u16, KernelStages>,
+    // CLOCK_REALTIME - CLOCK_MONOTONIC at load time
+    monotonic_offset_ns: u64,
+}
+
+/// Clonable handle to the loaded eBPF latency tracer.
+#[derive(Debug, Clone)]
+pub struct SocketTracer {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl SocketTracer {
+    /// Load the companion eBPF programs and attach them to the ingress of the network `interface` and to
+    /// the `cgroup` (path in the cgroup v2 hierarchy, e.g. `/sys/fs/cgroup`) of the traced sockets.
+    pub fn load(interface: &str, cgroup: impl AsRef<Path>) -> io::Result<SocketTracer> {
+        syscalls::ensure_unrestricted("bpf")?;
+        let mut ebpf = Ebpf::load(OBJECT).map_err(|err| syscalls::blocked("bpf", io::Error::other(err)))?;
+
+        // the clsact qdisc may have been added already (e.g. by another tracer)
+        if let Err(err) = tc::qdisc_add_clsact(interface) {
+            if err.kind() != io::ErrorKind::AlreadyExists {
+                return Err(syscalls::blocked("bpf", err));
+            }
+        }
+        let program: &mut SchedClassifier = program_mut(&mut ebpf, NETIF_RECEIVE)?;
+        program
+            .load()
+            .map_err(|err| syscalls::blocked("bpf", io::Error::other(err)))?;
+        program
+            .attach(interface, TcAttachType::Ingress)
+            .map_err(|err| syscalls::blocked("bpf", io::Error::other(err)))?;
+
+        let cgroup = File::open(cgroup)?;
+        let program: &mut SockOps = program_mut(&mut ebpf, TCP_RCV)?;
+        program
+            .load()
+            .map_err(|err| syscalls::blocked("bpf", io::Error::other(err)))?;
+        program
+            .attach(cgroup, CgroupAttachMode::AllowMultiple)
+            .map_err(|err| syscalls::blocked("bpf", io::Error::other(err)))?;
+
+        let map = ebpf
+            .take_map(SOCKETS)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("missing {SOCKETS} map")))?;
+        let sockets = HashMap::try_from(map).map_err(io::Error::other)?;
+        let monotonic_offset_ns = now_ns(libc::CLOCK_REALTIME).saturating_sub(now_ns(libc::CLOCK_MONOTONIC));
+        Ok(Self {
+            inner: Rc::new(RefCell::new(Inner {
+                _ebpf: ebpf,
+                sockets,
+                monotonic_offset_ns,
+            })),
+        })
+    }
+
+    /// Start tracing socket bound to the `local_port`.
+    pub fn register(&self, local_port: u16) -> io::Result<()> {
+        self.inner
+            .borrow_mut()
+            .sockets
+            .insert(local_port, KernelStages::default(), 0)
+            .map_err(io::Error::other)
+    }
+
+    /// Stop tracing socket bound to the `local_port`.
+    pub fn unregister(&self, local_port: u16) -> io::Result<()> {
+        self.inner
+            .borrow_mut()
+            .sockets
+            .remove(&local_port)
+            .map_err(io::Error::other)
+    }
+
+    /// Kernel stages of the last segment delivered to the socket bound to the `local_port`.
+    pub fn stages(&self, local_port: u16) -> Option<KernelStages> {
+        self.inner.borrow().sockets.get(&local_port, 0).ok()
+    }
+
+    /// Correlate kernel stages of the socket bound to the `local_port` with the rx timestamps.
+    pub fn latency_record(&self, local_port: u16, rx_timestamps: Option<RxTimestamps>) -> LatencyRecord {
+        let user_ns = now_ns(libc::CLOCK_REALTIME);
+        let stages = self.stages(local_port).unwrap_or_default();
+        let monotonic_offset_ns = self.inner.borrow().monotonic_offset_ns;
+        let realtime = |monotonic_ns: u64| match monotonic_ns {
+            0 => 0,
+            monotonic_ns => monotonic_ns.saturating_add(monotonic_offset_ns),
+        };
+        LatencyRecord {
+            hw_raw_ns: rx_timestamps.map(|ts| ts.hw_raw_ns).unwrap_or_default(),
+            netif_receive_ns: realtime(stages.netif_receive_ns),
+            tcp_rcv_ns: realtime(stages.tcp_rcv_ns),
+            user_ns,
+        }
+    }
+}
+
+fn program_mut<'a, P>(ebpf: &'a mut Ebpf, name: &str) -> io::Result<&'a mut P>
+where
+    &'a mut P: TryFrom<&'a mut aya::programs::Program, Error = aya::programs::ProgramError>,
+{
+    ebpf.program_mut(name)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("missing {name} program")))?
+        .try_into()
+        .map_err(io::Error::other)
+}
+
+fn now_ns(clock: libc::clockid_t) -> u64 {
+    let mut ts: libc::timespec = unsafe { mem::zeroed() };
+    // SAFETY: valid pointer to timespec
+    unsafe { libc::clock_gettime(clock, &mut ts) };
+    (ts.tv_sec as u64).saturating_mul(1_000_000_000) + (ts.tv_nsec as u64)
+}
+
+fn local_port(fd: RawFd) -> io::Result<u16> {
+    let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    // SAFETY: addr and len describe a valid sockaddr_storage buffer
+    let rc = unsafe { libc::getsockname(fd, (&mut addr as *mut libc::sockaddr_storage).cast(), &mut len) };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the address family determines the layout
+    let port = unsafe {
+        match addr.ss_family as libc::c_int {
+            libc::AF_INET => (*(&addr as *const libc::sockaddr_storage).cast::<libc::sockaddr_in>()).sin_port,
+            libc::AF_INET6 => (*(&addr as *const libc::sockaddr_storage).cast::<libc::sockaddr_in6>()).sin6_port,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "not an inet socket")),
+        }
+    };
+    Ok(u16::from_be(port))
+}
+
+/// Stream that produces [`LatencyRecord`] for every non empty read.
+#[derive(Debug)]
+pub struct TracedStream<S> {
+    inner: S,
+    tracer: SocketTracer,
+    local_port: u16,
+    last: Option<LatencyRecord>,
+}
+
+impl<S: AsRawFd> TracedStream<S> {
+    /// Register the `inner` socket with the `tracer`.
+    pub fn new(inner: S, tracer: &SocketTracer) -> io::Result<TracedStream<S>> {
+        let local_port = local_port(inner.as_raw_fd())?;
+        tracer.register(local_port)?;
+        Ok(Self {
+            inner,
+            tracer: tracer.clone(),
+            local_port,
+            last: None,
+        })
+    }
+}
+
+impl<S> TracedStream<S> {
+    /// Latency record of the last non empty read.
+    pub const fn last_latency_record(&self) -> Option<LatencyRecord> {
+        self.last
+    }
+
+    /// Take latency record of the last non empty read.
+    pub const fn take_last_latency_record(&mut self) -> Option<LatencyRecord> {
+        self.last.take()
+    }
+}
+
+impl<S> Drop for TracedStream<S> {
+    fn drop(&mut self) {
+        if let Err(err) = self.tracer.unregister(self.local_port) {
+            log::warn!("unable to unregister socket {} from the tracer: {err}", self.local_port);
+        }
+    }
+}
+
+impl<S: AsRawFd> AsRawFd for TracedStream<S> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+impl<S: Read + RxTimestamped> Read for TracedStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if read > 0 {
+            let rx_timestamps = self.inner.last_rx_timestamps();
+            self.last = Some(self.tracer.latency_record(self.local_port, rx_timestamps));
+        }
+        Ok(read)
+    }
+}
+
+impl<S: Write> Write for TracedStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.inner.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: PendingWrites> PendingWrites for TracedStream<S> {
+    fn has_pending_writes(&self) -> bool {
+        self.inner.has_pending_writes()
+    }
+
+    fn drive_writes(&mut self) -> io::Result<bool> {
+        self.inner.drive_writes()
+    }
+}
+
+impl<S: ConnectionInfoProvider> ConnectionInfoProvider for TracedStream<S> {
+    fn connection_info(&self) -> &ConnectionInfo {
+        self.inner.connection_info()
+    }
+}
+
+impl<S: RxTimestamped> RxTimestamped for TracedStream<S> {
+    fn last_rx_timestamps(&self) -> Option<RxTimestamps> {
+        self.inner.last_rx_timestamps()
+    }
+
+    fn take_last_rx_timestamps(&mut self) -> Option<RxTimestamps> {
+        self.inner.take_last_rx_timestamps()
+    }
+}
+
+impl<S: Selectable> Selectable for TracedStream<S> {
+    fn connected(&mut self) -> io::Result<bool> {
+        self.inner.connected()
+    }
+
+    fn make_writable(&mut self) -> io::Result<()> {
+        self.inner.make_writable()
+    }
+
+    fn make_readable(&mut self) -> io::Result<()> {
+        self.inner.make_readable()
+    }
+
+    fn take_read_activity(&mut self) -> bool {
+        self.inner.take_read_activity()
+    }
+
+    fn take_bytes_read(&mut self) -> u64 {
+        self.inner.take_bytes_read()
+    }
+
+    fn take_bytes_written(&mut self) -> u64 {
+        self.inner.take_bytes_written()
+    }
+
+    fn set_read_paused(&mut self, paused: bool) {
+        self.inner.set_read_paused(paused)
+    }
+}
+
+#[cfg(feature = "mio")]
+impl<S: Source> Source for TracedStream<S> {
+    fn register(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+        registry.register(&mut self.inner, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+        registry.reregister(&mut self.inner, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        registry.deregister(&mut self.inner)
+    }
+}
+
+/// Trait to convert any socket backed stream into [`TracedStream`].
+pub trait IntoTracedStream {
+    fn into_traced_stream(self, tracer: &SocketTracer) -> io::Result<TracedStream<Self>>
+    where
+        Self: Sized + AsRawFd,
+    {
+        TracedStream::new(self, tracer)
+    }
+}
+
+impl<T: AsRawFd> IntoTracedStream for T {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_compute_stage_latencies() {
+        let record = LatencyRecord {
+            hw_raw_ns: 1_000,
+            netif_receive_ns: 3_000,
+            tcp_rcv_ns: 3_500,
+            user_ns: 10_000,
+        };
+        assert_eq!(Some(2_000), record.nic_to_netif_ns());
+        assert_eq!(Some(500), record.netif_to_tcp_ns());
+        assert_eq!(Some(6_500), record.tcp_to_user_ns());
+        assert_eq!(Some(9_000), record.total_ns());
+    }
+
+    #[test]
+    fn should_skip_missing_stages() {
+        let record = LatencyRecord {
+            tcp_rcv_ns: 3_500,
+            user_ns: 10_000,
+            ..Default::default()
+        };
+        assert_eq!(None, record.nic_to_netif_ns());
+        assert_eq!(None, record.netif_to_tcp_ns());
+        assert_eq!(Some(6_500), record.total_ns());
+    }
+}
@@ -0,0 +1,113 @@
+// SPDX-License-Identifier: MIT OR GPL-2.0-only
+//
+// Companion eBPF programs of `boomnet::stream::ebpf`, compiled by the build script when the `ebpf`
+// feature is enabled. Records kernel stage timestamps of the last segment delivered to every
+// registered socket (keyed by the local port):
+// - `netif_receive` (tc ingress) runs when the network stack receives the segment,
+// - `tcp_rcv` (sockops, `BPF_SOCK_OPS_PARSE_HDR_OPT_CB`) runs when TCP processes the segment.
+//
+// Only UAPI headers are used (no vmlinux.h or libbpf), the helpers are declared below. None of them
+// is GPL only, the program is dual licensed so that it can be distributed with the crate.
+
+#include <linux/bpf.h>
+#include <linux/if_ether.h>
+#include <linux/in.h>
+#include <linux/pkt_cls.h>
+
+#define SEC(name) __attribute__((section(name), used))
+#define __uint(name, val) int (*name)[val]
+#define __type(name, val) typeof(val) *name
+
+#if __BYTE_ORDER__ == __ORDER_LITTLE_ENDIAN__
+#define bpf_htons(x) __builtin_bswap16(x)
+#define bpf_ntohs(x) __builtin_bswap16(x)
+#else
+#define bpf_htons(x) (x)
+#define bpf_ntohs(x) (x)
+#endif
+
+static void *(*bpf_map_lookup_elem)(void *map, const void *key) = (void *)BPF_FUNC_map_lookup_elem;
+static __u64 (*bpf_ktime_get_ns)(void) = (void *)BPF_FUNC_ktime_get_ns;
+static long (*bpf_skb_load_bytes)(const void *skb, __u32 offset, void *to, __u32 len) =
+    (void *)BPF_FUNC_skb_load_bytes;
+static long (*bpf_sock_ops_cb_flags_set)(struct bpf_sock_ops *skops, int flags) =
+    (void *)BPF_FUNC_sock_ops_cb_flags_set;
+
+struct stages {
+    __u64 netif_receive_ns; // bpf_ktime_get_ns() in tc ingress, CLOCK_MONOTONIC
+    __u64 tcp_rcv_ns;       // bpf_ktime_get_ns() in sockops, CLOCK_MONOTONIC
+    __u32 len;
+    __u32 segments;
+};
+
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, 1024);
+    __type(key, __u16);
+    __type(value, struct stages);
+} SOCKETS SEC(".maps");
+
+// destination port of a tcp segment (ipv6 extension headers are not followed)
+static __attribute__((always_inline)) int tcp_dst_port(struct __sk_buff *skb, __u16 *port)
+{
+    __u32 offset;
+    __u8 byte;
+
+    if (skb->protocol == bpf_htons(ETH_P_IP)) {
+        // version and header length, then protocol at offset 9
+        if (bpf_skb_load_bytes(skb, ETH_HLEN, &byte, 1) < 0)
+            return 0;
+        offset = ETH_HLEN + (byte & 0x0f) * 4;
+        if (bpf_skb_load_bytes(skb, ETH_HLEN + 9, &byte, 1) < 0 || byte != IPPROTO_TCP)
+            return 0;
+    } else if (skb->protocol == bpf_htons(ETH_P_IPV6)) {
+        // next header at offset 6 of the fixed 40 bytes header
+        if (bpf_skb_load_bytes(skb, ETH_HLEN + 6, &byte, 1) < 0 || byte != IPPROTO_TCP)
+            return 0;
+        offset = ETH_HLEN + 40;
+    } else {
+        return 0;
+    }
+
+    if (bpf_skb_load_bytes(skb, offset + 2, port, sizeof(*port)) < 0)
+        return 0;
+    *port = bpf_ntohs(*port);
+    return 1;
+}
+
+SEC("classifier")
+int netif_receive(struct __sk_buff *skb)
+{
+    __u16 port;
+    if (!tcp_dst_port(skb, &port))
+        return TC_ACT_OK;
+    struct stages *stages = bpf_map_lookup_elem(&SOCKETS, &port);
+    if (stages)
+        stages->netif_receive_ns = bpf_ktime_get_ns();
+    return TC_ACT_OK;
+}
+
+SEC("sockops")
+int tcp_rcv(struct bpf_sock_ops *skops)
+{
+    switch (skops->op) {
+    case BPF_SOCK_OPS_ACTIVE_ESTABLISHED_CB:
+    case BPF_SOCK_OPS_PASSIVE_ESTABLISHED_CB:
+        // the socket is not registered yet, it is looked up for every received segment instead
+        bpf_sock_ops_cb_flags_set(skops, skops->bpf_sock_ops_cb_flags | BPF_SOCK_OPS_PARSE_ALL_HDR_OPT_CB_FLAG);
+        break;
+    case BPF_SOCK_OPS_PARSE_HDR_OPT_CB: {
+        __u16 port = skops->local_port;
+        struct stages *stages = bpf_map_lookup_elem(&SOCKETS, &port);
+        if (stages) {
+            stages->tcp_rcv_ns = bpf_ktime_get_ns();
+            stages->len = skops->skb_len;
+            __sync_fetch_and_add(&stages->segments, 1);
+        }
+        break;
+    }
+    }
+    return 1;
+}
+
+char LICENSE[] SEC("license") = "Dual MIT/GPL";
@@ -0,0 +1,302 @@
+//! Hand live connections over to another process during a rolling upgrade.
+//!
+//! The old process sends every connected socket (`SCM_RIGHTS`) together with an opaque protocol state
+//! over a Unix domain socket, the new process picks the connections up and carries on where the old one
+//! stopped, so the exchange never sees a disconnect. Once a connection has been sent the old process
+//! must stop using it and only drop (close) its descriptor, calling `shutdown` would tear down the
+//! connection for both processes.
+//!
+//! Only the kernel side of the connection survives the handover. Plain TCP streams (and the websocket
+//! on top of them, see `Websocket::export_state`) can be handed over, user space TLS sessions (`rustls`,
+//! `openssl`) can not as neither backend allows to export and resume the record layer state.
+//!
+//! ## Examples
+//! ```no_run
+//! use boomnet::stream::ConnectionInfo;
+//! use boomnet::stream::handover::{HandoverListener, HandoverSender};
+//! use boomnet::stream::tcp::TcpStream;
+//!
+//! // new process
+//! let listener = HandoverListener::bind("/run/feed/handover.sock").unwrap();
+//! // ... starts the old process shutdown and then
+//! let mut receiver = listener.accept().unwrap();
+//! while let Some(connection) = receiver.recv().unwrap() {
+//!     let stream = connection.into_tcp_stream(ConnectionInfo::new("stream.binance.com", 9443));
+//! }
+//!
+//! // old process
+//! fn hand_over(stream: TcpStream, state: &[u8]) -> std::io::Result<()> {
+//!     let mut sender = HandoverSender::connect("/run/feed/handover.sock")?;
+//!     sender.send("binance-spot", &stream, state)
+//! }
+//! ```
+
+use crate::stream::ConnectionInfo;
+use crate::stream::tcp::TcpStream;
+use std::io::ErrorKind;
+use std::io::ErrorKind::{Interrupted, InvalidData, InvalidInput, NotFound, UnexpectedEof};
+use std::io::{Read, Write};
+use std::mem::size_of;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::{fs, io, mem, ptr};
+
+// key and state lengths, the descriptor is attached to the header
+const HEADER_LEN: usize = 8;
+
+/// Maximum length of the key a connection is handed over under.
+pub const MAX_KEY_LEN: usize = 1024;
+
+/// Maximum length of the protocol state handed over with a connection.
+pub const MAX_STATE_LEN: usize = 64 * 1024 * 1024;
+
+/// Connection received from the previous process.
+#[derive(Debug)]
+pub struct HandedOver {
+    /// Key the connection has been sent under.
+    pub key: String,
+    /// Connected socket, still in the mode (e.g. non-blocking) set by the previous process.
+    pub fd: OwnedFd,
+    /// Protocol state exported by the previous process.
+    pub state: Vec<u8>,
+}
+
+impl HandedOver {
+    /// Wrap the socket into [`TcpStream`].
+    pub fn into_tcp_stream(self, connection_info: ConnectionInfo) -> TcpStream {
+        TcpStream::new(std::net::TcpStream::from(self.fd), connection_info)
+    }
+}
+
+/// Sending side of the handover, used by the process being replaced.
+#[derive(Debug)]
+pub struct HandoverSender {
+    channel: UnixStream,
+}
+
+impl HandoverSender {
+    /// Connect to the [`HandoverListener`] of the new process.
+    pub fn connect(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self {
+            channel: UnixStream::connect(path)?,
+        })
+    }
+
+    /// Send the connected socket `fd` together with the protocol `state` under the `key`, fails with
+    /// [`InvalidInput`] if the key exceeds [`MAX_KEY_LEN`] or the state exceeds [`MAX_STATE_LEN`].
+    pub fn send(&mut self, key: &str, fd: &impl AsRawFd, state: &[u8]) -> io::Result<()> {
+        validate_lengths(key.len(), state.len(), InvalidInput)?;
+        let mut header = [0u8; HEADER_LEN];
+        header[..4].copy_from_slice(&(key.len() as u32).to_le_bytes());
+        header[4..].copy_from_slice(&(state.len() as u32).to_le_bytes());
+        send_with_fd(&self.channel, &header, fd.as_raw_fd())?;
+        self.channel.write_all(key.as_bytes())?;
+        self.channel.write_all(state)
+    }
+}
+
+/// Listening side of the handover, bound by the new process before the old one is told to hand over.
+#[derive(Debug)]
+pub struct HandoverListener {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl HandoverListener {
+    /// Bind the listener at the `path`, a stale socket file is replaced. The file is removed when
+    /// the listener is dropped.
+    pub fn bind(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Err(err) = fs::remove_file(&path) {
+            if err.kind() != NotFound {
+                return Err(err);
+            }
+        }
+        let listener = UnixListener::bind(&path)?;
+        Ok(Self { listener, path })
+    }
+
+    /// Wait for the previous process to connect.
+    pub fn accept(&self) -> io::Result<HandoverReceiver> {
+        let (channel, _) = self.listener.accept()?;
+        Ok(HandoverReceiver { channel })
+    }
+}
+
+impl Drop for HandoverListener {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Receiving side of the handover.
+#[derive(Debug)]
+pub struct HandoverReceiver {
+    channel: UnixStream,
+}
+
+impl HandoverReceiver {
+    /// Wait for the next connection, returns `None` once the previous process has sent all of them
+    /// (closed the channel). Fails with [`InvalidData`] if the announced key or state length exceeds
+    /// [`MAX_KEY_LEN`] or [`MAX_STATE_LEN`], before anything is allocated for them.
+    pub fn recv(&mut self) -> io::Result<Option<HandedOver>> {
+        let mut header = [0u8; HEADER_LEN];
+        let Some(fd) = recv_with_fd(&self.channel, &mut header)? else {
+            return Ok(None);
+        };
+        let key_len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+        let state_len = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+        validate_lengths(key_len, state_len, InvalidData)?;
+        let mut key = vec![0u8; key_len];
+        self.channel.read_exact(&mut key)?;
+        let mut state = vec![0u8; state_len];
+        self.channel.read_exact(&mut state)?;
+        let key = String::from_utf8(key).map_err(|err| io::Error::new(InvalidData, err))?;
+        Ok(Some(HandedOver { key, fd, state }))
+    }
+}
+
+fn validate_lengths(key_len: usize, state_len: usize, kind: ErrorKind) -> io::Result<()> {
+    if key_len > MAX_KEY_LEN {
+        return Err(io::Error::new(kind, format!("handover key of {key_len} bytes exceeds {MAX_KEY_LEN} bytes")));
+    }
+    if state_len > MAX_STATE_LEN {
+        return Err(io::Error::new(kind, format!("handover state of {state_len} bytes exceeds {MAX_STATE_LEN} bytes")));
+    }
+    Ok(())
+}
+
+fn send_with_fd(channel: &UnixStream, bytes: &[u8], fd: RawFd) -> io::Result<()> {
+    // u64 keeps the control buffer aligned for `cmsghdr`
+    let mut control = [0u64; 4];
+    let mut iov = libc::iovec {
+        iov_base: bytes.as_ptr() as *mut libc::c_void,
+        iov_len: bytes.len(),
+    };
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = unsafe { libc::CMSG_SPACE(size_of::<RawFd>() as u32) } as _;
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(size_of::<RawFd>() as u32) as _;
+        ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut RawFd, fd);
+    }
+    let sent = loop {
+        let sent = unsafe { libc::sendmsg(channel.as_raw_fd(), &msg, libc::MSG_NOSIGNAL) };
+        if sent < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        break sent as usize;
+    };
+    // the descriptor went out with the first byte
+    (&*channel).write_all(&bytes[sent..])
+}
+
+fn recv_with_fd(channel: &UnixStream, bytes: &mut [u8; HEADER_LEN]) -> io::Result<Option<OwnedFd>> {
+    let mut control = [0u64; 4];
+    let mut iov = libc::iovec {
+        iov_base: bytes.as_mut_ptr().cast(),
+        iov_len: bytes.len(),
+    };
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+    let received = loop {
+        // the descriptor is received with close-on-exec set, like every other descriptor of the crate
+        let flags = libc::MSG_WAITALL | libc::MSG_CMSG_CLOEXEC;
+        let received = unsafe { libc::recvmsg(channel.as_raw_fd(), &mut msg, flags) };
+        if received < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        break received as usize;
+    };
+    let fd = unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        match cmsg.is_null() {
+            false if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS => {
+                Some(OwnedFd::from_raw_fd(ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const RawFd)))
+            }
+            _ => None,
+        }
+    };
+    if received == 0 && fd.is_none() {
+        return Ok(None);
+    }
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        return Err(io::Error::new(InvalidData, "handover control message truncated"));
+    }
+    if received < HEADER_LEN {
+        return Err(io::Error::new(UnexpectedEof, "handover channel closed mid message"));
+    }
+    fd.map(Some)
+        .ok_or_else(|| io::Error::new(InvalidData, "handover message without descriptor"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::is_cloexec;
+    use std::net::TcpListener;
+
+    #[test]
+    fn should_hand_over_connected_socket() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = std::net::TcpStream::connect(server.local_addr().unwrap()).unwrap();
+        let (mut peer, _) = server.accept().unwrap();
+
+        let (old, new) = UnixStream::pair().unwrap();
+        let mut sender = HandoverSender { channel: old };
+        let mut receiver = HandoverReceiver { channel: new };
+        sender.send("spot", &client, b"state").unwrap();
+        sender.send("empty", &client, b"").unwrap();
+        // the previous process closes its descriptors
+        drop(client);
+        drop(sender);
+
+        let connection = receiver.recv().unwrap().unwrap();
+        assert_eq!("spot", connection.key);
+        assert_eq!(b"state", connection.state.as_slice());
+        assert!(is_cloexec(connection.fd.as_raw_fd()).unwrap());
+        let mut client = std::net::TcpStream::from(connection.fd);
+        client.write_all(b"ping").unwrap();
+        let mut buf = [0u8; 4];
+        peer.read_exact(&mut buf).unwrap();
+        assert_eq!(b"ping", &buf);
+
+        let connection = receiver.recv().unwrap().unwrap();
+        assert_eq!("empty", connection.key);
+        assert!(connection.state.is_empty());
+        assert!(receiver.recv().unwrap().is_none());
+    }
+
+    #[test]
+    fn should_reject_oversized_key_and_state() {
+        let (old, new) = UnixStream::pair().unwrap();
+        let fd = old.try_clone().unwrap();
+        let mut sender = HandoverSender { channel: old };
+        let key = "k".repeat(MAX_KEY_LEN + 1);
+        assert_eq!(InvalidInput, sender.send(&key, &fd, b"").unwrap_err().kind());
+
+        // the lengths announced by a misbehaving peer are rejected before the allocation
+        let mut header = [0u8; HEADER_LEN];
+        header[4..].copy_from_slice(&u32::MAX.to_le_bytes());
+        send_with_fd(&sender.channel, &header, fd.as_raw_fd()).unwrap();
+        let mut receiver = HandoverReceiver { channel: new };
+        assert_eq!(InvalidData, receiver.recv().unwrap_err().kind());
+    }
+}
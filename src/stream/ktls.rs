@@ -3,8 +3,8 @@
 use crate::service::select::Selectable;
 use crate::stream::ktls::error::Error;
 use crate::stream::ktls::net::peer_addr;
-use crate::stream::tls::TlsConfig;
-use crate::stream::{ConnectionInfo, ConnectionInfoProvider};
+use crate::stream::tls::{TlsConfig, TlsParameters, TlsParametersProvider, openssl_parameters};
+use crate::stream::{ConnectionInfo, ConnectionInfoProvider, PendingWrites};
 use foreign_types::ForeignType;
 #[cfg(feature = "mio")]
 use mio::{Interest, Registry, Token, event::Source};
@@ -12,7 +12,7 @@ use openssl::ssl::{ErrorCode, SslOptions};
 use smallstr::SmallString;
 use std::io;
 use std::io::{ErrorKind, Read, Write};
-use std::os::fd::{AsRawFd, BorrowedFd};
+use std::os::fd::{AsRawFd, BorrowedFd, RawFd};
 use std::ptr::slice_from_raw_parts;
 
 /// Offloads TLS to the kernel (KTLS). Uses OpenSSL backend to configure KTLS post handshake (can change in the future).
@@ -143,6 +143,15 @@ enum State {
     Ready,
 }
 
+impl<S> TlsParametersProvider for KtlsStream<S> {
+    fn tls_parameters(&self) -> Option<TlsParameters> {
+        match self.state {
+            State::Connecting | State::Handshake => None,
+            State::Drain(_) | State::Ready => Some(openssl_parameters(&self.ssl)),
+        }
+    }
+}
+
 impl<S: ConnectionInfoProvider> ConnectionInfoProvider for KtlsStream<S> {
     fn connection_info(&self) -> &ConnectionInfo {
         self.stream.connection_info()
@@ -190,7 +199,8 @@ impl<S: AsRawFd> Read for KtlsStream<S> {
                 }
             }
             State::Ready => match self.ssl_read(buf) {
-                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                // `close_notify` or EOF, consistent with the other stream layers
+                Ok(0) => return Ok(0),
                 Ok(len) => return Ok(len),
                 Err(err) if err.code() == ErrorCode::WANT_READ => {}
                 Err(err) if err.code() == ErrorCode::WANT_WRITE => {}
@@ -228,6 +238,28 @@ impl<S: Write> Write for KtlsStream<S> {
     }
 }
 
+impl<S> PendingWrites for KtlsStream<S> {
+    fn has_pending_writes(&self) -> bool {
+        match self.state {
+            State::Ready => false,
+            State::Drain(from) => from < self.buffer.len(),
+            State::Connecting | State::Handshake => !self.buffer.is_empty(),
+        }
+    }
+
+    /// Writes buffered during the handshake are drained on read, once `Ready` the records are
+    /// written by the kernel.
+    fn drive_writes(&mut self) -> io::Result<bool> {
+        Ok(!self.has_pending_writes())
+    }
+}
+
+impl<S: AsRawFd> AsRawFd for KtlsStream<S> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+}
+
 impl<S: Selectable> Selectable for KtlsStream<S> {
     #[inline]
     fn connected(&mut self) -> io::Result<bool> {
@@ -243,6 +275,25 @@ impl<S: Selectable> Selectable for KtlsStream<S> {
     fn make_readable(&mut self) -> io::Result<()> {
         self.stream.make_readable()
     }
+
+    #[inline]
+    fn take_read_activity(&mut self) -> bool {
+        self.stream.take_read_activity()
+    }
+
+    #[inline]
+    fn take_bytes_read(&mut self) -> u64 {
+        self.stream.take_bytes_read()
+    }
+
+    #[inline]
+    fn take_bytes_written(&mut self) -> u64 {
+        self.stream.take_bytes_written()
+    }
+
+    fn set_read_paused(&mut self, paused: bool) {
+        self.stream.set_read_paused(paused)
+    }
 }
 
 #[cfg(feature = "mio")]
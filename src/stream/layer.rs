@@ -0,0 +1,475 @@
+//! Typed builder of the stream stack.
+//!
+//! [`Layered`] composes the stream layers (tcp → timestamping → tls → ws) in a single chain, every
+//! layer is optional and the order is checked at compile time: RX timestamping can only wrap the TCP
+//! socket itself, TLS can only be applied once (the [`Plain`] phase turns [`Secure`]) and the
+//! websocket ends the chain. Recording and buffering can be applied in either phase, below TLS the
+//! recording captures the encrypted bytes and above it the plain ones. Any other layer is applied with
+//! [`Layered::map`].
+//!
+//! To localize a throughput bottleneck [`Layered::counted`] counts the calls and bytes at any point of
+//! the stack into the shared [`LayerCounters`]: below TLS they are the ciphertext exchanged with the
+//! kernel, above it the plaintext, and installed as the websocket [`Middleware`](crate::ws::middleware::Middleware)
+//! the counters see the payload of every frame received.
+//!
+//! ## Examples
+//! ```no_run
+//! use boomnet::stream::layer::Layered;
+//!
+//! // tcp → tls → record → ws
+//! let ws = Layered::connect(("stream.binance.com", 9443))
+//!     .unwrap()
+//!     .tls()
+//!     .unwrap()
+//!     .recorded("binance")
+//!     .unwrap()
+//!     .websocket("/ws");
+//!
+//! // tcp → buffer → ws, without TLS
+//! let ws = Layered::connect(("127.0.0.1", 8080)).unwrap().buffered::<4096>().websocket("/ws");
+//! ```
+//!
+//! Count every layer of the stack.
+//! ```no_run
+//! use boomnet::stream::layer::{LayerCounters, Layered};
+//! use boomnet::ws::middleware::Chain;
+//!
+//! let (kernel, tls, frames) = (LayerCounters::new(), LayerCounters::new(), LayerCounters::new());
+//! let mut ws = Layered::connect(("stream.binance.com", 9443))
+//!     .unwrap()
+//!     .counted(&kernel)
+//!     .tls()
+//!     .unwrap()
+//!     .counted(&tls)
+//!     .websocket("/ws")
+//!     .with_middleware(Chain::new().with(frames.clone()));
+//!
+//! // ... later, e.g. once a second
+//! println!("kernel: {:?}, tls: {:?}, frames: {:?}", kernel.snapshot(), tls.snapshot(), frames.snapshot());
+//! ```
+//!
+//! TLS can not be applied twice.
+//! ```compile_fail
+//! use boomnet::stream::layer::Layered;
+//!
+//! let tls = Layered::connect(("stream.binance.com", 9443)).unwrap().tls().unwrap().tls();
+//! ```
+
+use crate::service::select::Selectable;
+use crate::stream::buffer::{BufferedStream, IntoBufferedStream};
+use crate::stream::record::{RecordedStream, Recorder};
+use crate::stream::tcp::TcpStream;
+#[cfg(all(target_os = "linux", feature = "timestamping"))]
+use crate::stream::timestamping::{TimestampingStream, configure_hwtstamp, enable_rx_timestamping};
+#[cfg(any(feature = "rustls", feature = "openssl"))]
+use crate::stream::tls::{IntoTlsStream, TlsConfig, TlsParameters, TlsParametersProvider, TlsStream};
+use crate::stream::{ConnectionInfo, ConnectionInfoProvider, PendingWrites, RxTimestamped, RxTimestamps};
+#[cfg(feature = "ws")]
+use crate::ws::Websocket;
+#[cfg(feature = "mio")]
+use mio::{Interest, Registry, Token, event::Source};
+use std::cell::Cell;
+#[cfg(any(feature = "rustls", feature = "openssl"))]
+use std::fmt::Debug;
+use std::io;
+use std::io::{IoSlice, Read, Write};
+use std::marker::PhantomData;
+use std::os::fd::{AsRawFd, RawFd};
+use std::rc::Rc;
+
+/// Phase of the stack before TLS has been applied (or without TLS).
+#[derive(Debug)]
+pub struct Plain;
+
+/// Phase of the stack once TLS has been applied.
+#[derive(Debug)]
+pub struct Secure;
+
+/// Stream stack under construction, see the [module](self) documentation.
+#[derive(Debug)]
+pub struct Layered<S, P = Plain> {
+    stream: S,
+    phase: PhantomData<P>,
+}
+
+impl<S> Layered<S> {
+    /// Start the stack with the bottom `stream`.
+    pub const fn new(stream: S) -> Layered<S> {
+        Self {
+            stream,
+            phase: PhantomData,
+        }
+    }
+}
+
+impl Layered<TcpStream> {
+    /// Start the stack with the TCP connection described by the `connection_info`.
+    pub fn connect(connection_info: impl Into<ConnectionInfo>) -> io::Result<Layered<TcpStream>> {
+        Ok(Self::new(connection_info.into().into_tcp_stream()?))
+    }
+
+    /// Capture the software RX timestamps of the socket reads, see
+    /// [`RxTimestamped`](crate::stream::RxTimestamped).
+    #[cfg(all(target_os = "linux", feature = "timestamping"))]
+    pub fn timestamped(self) -> io::Result<Layered<TimestampingStream<TcpStream>>> {
+        enable_rx_timestamping(self.stream.as_raw_fd())?;
+        Ok(Layered::new(TimestampingStream::new(self.stream)))
+    }
+
+    /// Same as [`Layered::timestamped`] with the hardware timestamping enabled on the `net_iface` the
+    /// socket receives on.
+    #[cfg(all(target_os = "linux", feature = "timestamping"))]
+    pub fn hw_timestamped(self, net_iface: &str) -> io::Result<Layered<TimestampingStream<TcpStream>>> {
+        configure_hwtstamp(self.stream.as_raw_fd(), net_iface)?;
+        self.timestamped()
+    }
+}
+
+impl<S, P> Layered<S, P> {
+    /// Apply any other layer (e.g. a user defined stream wrapper) on top of the stack.
+    pub fn map<T, F>(self, layer: F) -> Layered<T, P>
+    where
+        F: FnOnce(S) -> T,
+    {
+        Layered {
+            stream: layer(self.stream),
+            phase: PhantomData,
+        }
+    }
+
+    /// Count the calls and bytes exchanged at this point of the stack into the `counters`.
+    pub fn counted(self, counters: &LayerCounters) -> Layered<Counted<S>, P> {
+        self.map(|stream| Counted {
+            inner: stream,
+            counters: counters.clone(),
+        })
+    }
+
+    /// Reference to the top of the stack.
+    pub const fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Mutable reference to the top of the stack.
+    pub const fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// Finish the stack without the websocket.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S: Read + Write, P> Layered<S, P> {
+    /// Record the bytes exchanged at this point of the stack under the `recording_name`.
+    pub fn recorded(self, recording_name: impl AsRef<str>) -> io::Result<Layered<RecordedStream<S>, P>> {
+        let recorder = Recorder::new(recording_name)?;
+        Ok(self.map(|stream| RecordedStream::new(stream, recorder)))
+    }
+}
+
+impl<S: Read + Write + ConnectionInfoProvider, P> Layered<S, P> {
+    /// Buffer the writes until flushed, see [`BufferedStream`].
+    pub fn buffered<const N: usize>(self) -> Layered<BufferedStream<S, N>, P> {
+        self.map(|stream| stream.into_buffered_stream::<N>())
+    }
+
+    /// Upgrade the top of the stack to the websocket at the `endpoint`, this ends the stack.
+    #[cfg(feature = "ws")]
+    pub fn websocket(self, endpoint: &str) -> Websocket<S> {
+        Websocket::new(self.stream, endpoint)
+    }
+}
+
+#[cfg(any(feature = "rustls", feature = "openssl"))]
+impl<S: Read + Write + Debug + ConnectionInfoProvider> Layered<S, Plain> {
+    /// Apply TLS with the default config, the stack can not be encrypted again.
+    pub fn tls(self) -> io::Result<Layered<TlsStream<S>, Secure>> {
+        self.tls_with_config(|_| {})
+    }
+
+    /// Apply TLS with the config modified by the `builder`, see
+    /// [`IntoTlsStream::into_tls_stream_with_config`].
+    pub fn tls_with_config<F>(self, builder: F) -> io::Result<Layered<TlsStream<S>, Secure>>
+    where
+        F: FnOnce(&mut TlsConfig),
+    {
+        Ok(Layered {
+            stream: self.stream.into_tls_stream_with_config(builder)?,
+            phase: PhantomData,
+        })
+    }
+}
+
+/// Calls and bytes counted by the [`Counted`] layer (or the frames received, when installed as the
+/// websocket middleware). Clones share the counters.
+#[derive(Debug, Clone, Default)]
+pub struct LayerCounters {
+    inner: Rc<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    reads: Cell<u64>,
+    bytes_read: Cell<u64>,
+    writes: Cell<u64>,
+    bytes_written: Cell<u64>,
+}
+
+/// Point in time copy of the [`LayerCounters`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LayerSnapshot {
+    /// Number of read calls (frames received for the websocket middleware), including those that
+    /// would block.
+    pub reads: u64,
+    /// Number of bytes read (payload bytes received for the websocket middleware).
+    pub bytes_read: u64,
+    /// Number of write calls, including those that would block.
+    pub writes: u64,
+    /// Number of bytes written.
+    pub bytes_written: u64,
+}
+
+impl LayerCounters {
+    /// Create the counters starting from zero.
+    pub fn new() -> LayerCounters {
+        Self::default()
+    }
+
+    /// Current value of the counters.
+    pub fn snapshot(&self) -> LayerSnapshot {
+        LayerSnapshot {
+            reads: self.inner.reads.get(),
+            bytes_read: self.inner.bytes_read.get(),
+            writes: self.inner.writes.get(),
+            bytes_written: self.inner.bytes_written.get(),
+        }
+    }
+
+    /// Current value of the counters, which then start from zero again (e.g. per reporting interval).
+    pub fn take(&self) -> LayerSnapshot {
+        LayerSnapshot {
+            reads: self.inner.reads.take(),
+            bytes_read: self.inner.bytes_read.take(),
+            writes: self.inner.writes.take(),
+            bytes_written: self.inner.bytes_written.take(),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn on_read(&self, result: &io::Result<usize>) {
+        add(&self.inner.reads, 1);
+        if let Ok(read) = result {
+            add(&self.inner.bytes_read, *read as u64);
+        }
+    }
+
+    #[inline]
+    fn on_write(&self, result: &io::Result<usize>) {
+        add(&self.inner.writes, 1);
+        if let Ok(written) = result {
+            add(&self.inner.bytes_written, *written as u64);
+        }
+    }
+}
+
+#[inline]
+fn add(counter: &Cell<u64>, value: u64) {
+    counter.set(counter.get().wrapping_add(value));
+}
+
+/// Stream layer that counts the calls and bytes passing through it, see [`Layered::counted`].
+#[derive(Debug)]
+pub struct Counted<S> {
+    inner: S,
+    counters: LayerCounters,
+}
+
+impl<S> Counted<S> {
+    /// Counters of this layer.
+    pub const fn counters(&self) -> &LayerCounters {
+        &self.counters
+    }
+}
+
+impl<S: Read> Read for Counted<S> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = self.inner.read(buf);
+        self.counters.on_read(&result);
+        result
+    }
+}
+
+impl<S: Write> Write for Counted<S> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let result = self.inner.write(buf);
+        self.counters.on_write(&result);
+        result
+    }
+
+    #[inline]
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let result = self.inner.write_vectored(bufs);
+        self.counters.on_write(&result);
+        result
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: ConnectionInfoProvider> ConnectionInfoProvider for Counted<S> {
+    fn connection_info(&self) -> &ConnectionInfo {
+        self.inner.connection_info()
+    }
+}
+
+impl<S: PendingWrites> PendingWrites for Counted<S> {
+    fn has_pending_writes(&self) -> bool {
+        self.inner.has_pending_writes()
+    }
+
+    fn drive_writes(&mut self) -> io::Result<bool> {
+        self.inner.drive_writes()
+    }
+}
+
+impl<S: RxTimestamped> RxTimestamped for Counted<S> {
+    fn last_rx_timestamps(&self) -> Option<RxTimestamps> {
+        self.inner.last_rx_timestamps()
+    }
+
+    fn take_last_rx_timestamps(&mut self) -> Option<RxTimestamps> {
+        self.inner.take_last_rx_timestamps()
+    }
+}
+
+#[cfg(any(feature = "rustls", feature = "openssl"))]
+impl<S: TlsParametersProvider> TlsParametersProvider for Counted<S> {
+    fn tls_parameters(&self) -> Option<TlsParameters> {
+        self.inner.tls_parameters()
+    }
+}
+
+impl<S: AsRawFd> AsRawFd for Counted<S> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+impl<S: Selectable> Selectable for Counted<S> {
+    fn connected(&mut self) -> io::Result<bool> {
+        self.inner.connected()
+    }
+
+    fn make_writable(&mut self) -> io::Result<()> {
+        self.inner.make_writable()
+    }
+
+    fn make_readable(&mut self) -> io::Result<()> {
+        self.inner.make_readable()
+    }
+
+    fn take_read_activity(&mut self) -> bool {
+        self.inner.take_read_activity()
+    }
+
+    fn take_bytes_read(&mut self) -> u64 {
+        self.inner.take_bytes_read()
+    }
+
+    fn take_bytes_written(&mut self) -> u64 {
+        self.inner.take_bytes_written()
+    }
+
+    fn set_read_paused(&mut self, paused: bool) {
+        self.inner.set_read_paused(paused)
+    }
+}
+
+#[cfg(feature = "mio")]
+impl<S: Source> Source for Counted<S> {
+    fn register(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+        registry.register(&mut self.inner, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+        registry.reregister(&mut self.inner, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        registry.deregister(&mut self.inner)
+    }
+}
+
+#[cfg(all(test, feature = "ws"))]
+mod tests {
+    use super::*;
+    use crate::stream::transport::IntoTransport;
+    use crate::ws::WebsocketFrame;
+    use crate::ws::middleware::Chain;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct Pipe {
+        inbound: io::Cursor<Vec<u8>>,
+        outbound: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.inbound.read(buf)? {
+                0 => Err(io::ErrorKind::WouldBlock.into()),
+                read => Ok(read),
+            }
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.outbound.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_compose_optional_layers() {
+        let outbound = Rc::new(RefCell::new(vec![]));
+        let pipe = Pipe {
+            inbound: io::Cursor::new(b"HTTP/1.1 101 Switching Protocols\r\n\r\n\x81\x02hi".to_vec()),
+            outbound: outbound.clone(),
+        };
+        let (below, above, frames) = (LayerCounters::new(), LayerCounters::new(), LayerCounters::new());
+        let mut ws = Layered::new(pipe)
+            .map(|pipe| pipe.into_transport(("stream.example.com", 80)))
+            .counted(&below)
+            .buffered::<512>()
+            .counted(&above)
+            .websocket("/ws")
+            .with_middleware(Chain::new().with(frames.clone()));
+
+        while !ws.handshake_complete() {
+            assert!(ws.read_batch().unwrap().receive_next().is_none());
+        }
+        assert!(matches!(ws.receive_next(), Some(Ok(WebsocketFrame::Text(true, b"hi")))));
+        let (below, above) = (below.take(), above.take());
+        assert_eq!(outbound.borrow().len() as u64, below.bytes_written);
+        assert_eq!(below.bytes_written, above.bytes_written);
+        assert_eq!((40, 40), (below.bytes_read, above.bytes_read));
+        assert_eq!((1, 2), (frames.snapshot().reads, frames.snapshot().bytes_read));
+        // the upgrade request has been flushed through the buffer
+        assert!(
+            outbound
+                .borrow()
+                .starts_with(b"GET /ws HTTP/1.1\r\nHost: stream.example.com\r\n")
+        );
+    }
+}
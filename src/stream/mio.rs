@@ -1,12 +1,13 @@
 //! Stream that can be used together with `MioSelector`.
 
 use crate::service::select::Selectable;
-use crate::stream::{ConnectionInfo, ConnectionInfoProvider};
+use crate::stream::proxy::Tunnel;
+use crate::stream::{ConnectionInfo, ConnectionInfoProvider, PendingWrites};
 use mio::event::Source;
 use mio::net::TcpStream;
 use mio::{Interest, Registry, Token};
 use std::io::ErrorKind::{Interrupted, NotConnected, WouldBlock};
-use std::io::{Read, Write};
+use std::io::{IoSlice, Read, Write};
 use std::os::fd::{AsRawFd, RawFd};
 use std::{io, net};
 
@@ -18,17 +19,27 @@ pub struct MioStream {
     can_read: bool,
     can_write: bool,
     buffer: Vec<u8>,
+    received: bool,
+    bytes_read: u64,
+    bytes_written: u64,
+    read_paused: bool,
+    tunnel: Option<Tunnel>,
 }
 
 impl MioStream {
     fn new(inner: TcpStream, connection_info: ConnectionInfo) -> MioStream {
         Self {
+            tunnel: connection_info.tunnel(),
             inner,
             connection_info,
             connected: false,
             can_read: false,
             can_write: false,
             buffer: Vec::with_capacity(4096),
+            received: false,
+            bytes_read: 0,
+            bytes_written: 0,
+            read_paused: false,
         }
     }
 }
@@ -47,9 +58,16 @@ impl Selectable for MioStream {
         match self.inner.peer_addr() {
             Ok(_) => {
                 self.connected = true;
-                // bypassing `can_write` as we can get to this state
-                // only if the socket is writable
-                self.inner.write_all(&self.buffer)?;
+                match self.tunnel.as_mut() {
+                    // the buffered bytes are held back until the proxy opens the tunnel
+                    Some(tunnel) => {
+                        tunnel.buffer(&self.buffer);
+                        tunnel.drive(&mut self.inner)?;
+                    }
+                    // bypassing `can_write` as we can get to this state
+                    // only if the socket is writable
+                    None => self.inner.write_all(&self.buffer)?,
+                }
                 self.buffer.clear();
                 Ok(true)
             }
@@ -68,6 +86,22 @@ impl Selectable for MioStream {
         self.can_read = true;
         Ok(())
     }
+
+    fn take_read_activity(&mut self) -> bool {
+        std::mem::take(&mut self.received)
+    }
+
+    fn take_bytes_read(&mut self) -> u64 {
+        std::mem::take(&mut self.bytes_read)
+    }
+
+    fn take_bytes_written(&mut self) -> u64 {
+        std::mem::take(&mut self.bytes_written)
+    }
+
+    fn set_read_paused(&mut self, paused: bool) {
+        self.read_paused = paused;
+    }
 }
 
 impl Source for MioStream {
@@ -86,8 +120,25 @@ impl Source for MioStream {
 
 impl Read for MioStream {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if self.can_read {
+        if self.can_read && !self.read_paused {
+            if let Some(tunnel) = self.tunnel.as_mut() {
+                if !tunnel.drive(&mut self.inner)? {
+                    self.can_read = false;
+                    return Err(io::Error::from(WouldBlock));
+                }
+                let forwarded = tunnel.read_forwarded(buf);
+                if tunnel.is_done() {
+                    self.tunnel = None;
+                }
+                if forwarded > 0 {
+                    self.received = true;
+                    self.bytes_read += forwarded as u64;
+                    return Ok(forwarded);
+                }
+            }
             let read = self.inner.read(buf)?;
+            self.received |= read > 0;
+            self.bytes_read += read as u64;
             if read < buf.len() {
                 self.can_read = false;
             }
@@ -97,20 +148,60 @@ impl Read for MioStream {
     }
 }
 
-impl Write for MioStream {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+impl MioStream {
+    #[inline]
+    fn write_through(&mut self, buf: &[u8]) -> io::Result<usize> {
         if !self.can_write {
             self.buffer.extend_from_slice(buf);
             return Ok(buf.len());
         }
+        if let Some(tunnel) = self.tunnel.as_mut() {
+            if !tunnel.drive(&mut self.inner)? || tunnel.has_pending_writes() {
+                return Ok(tunnel.buffer(buf));
+            }
+        }
         self.inner.write(buf)
     }
+}
+
+impl Write for MioStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.write_through(buf)?;
+        self.bytes_written += written as u64;
+        Ok(written)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        if !self.can_write || self.tunnel.is_some() {
+            return bufs.iter().map(|buf| self.write(buf)).sum();
+        }
+        let written = self.inner.write_vectored(bufs)?;
+        self.bytes_written += written as u64;
+        Ok(written)
+    }
 
     fn flush(&mut self) -> io::Result<()> {
         self.inner.flush()
     }
 }
 
+impl PendingWrites for MioStream {
+    fn has_pending_writes(&self) -> bool {
+        !self.buffer.is_empty() || self.tunnel.as_ref().is_some_and(Tunnel::has_pending_writes)
+    }
+
+    /// Writes buffered before the connection is established are flushed once the selector reports
+    /// the stream as connected, or once the proxy tunnel is established.
+    fn drive_writes(&mut self) -> io::Result<bool> {
+        if self.connected {
+            if let Some(tunnel) = self.tunnel.as_mut() {
+                tunnel.drive(&mut self.inner)?;
+            }
+        }
+        Ok(!self.has_pending_writes())
+    }
+}
+
 impl ConnectionInfoProvider for MioStream {
     fn connection_info(&self) -> &ConnectionInfo {
         &self.connection_info
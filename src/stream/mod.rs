@@ -1,27 +1,52 @@
 //! Various stream implementations on top of which protocol can be applied.
+//!
+//! ## File descriptors
+//! Every descriptor created by the crate (sockets, epoll instances as well as recording files) is
+//! opened with the close-on-exec flag set atomically at creation, so exchange connections are never
+//! leaked into child processes spawned with `fork`/`exec`. This is verified in debug builds and by tests.
+//!
+//! ## End of stream
+//! Every stream layer reports the peer closing its side of the connection the same way a TCP stream
+//! does, with a read returning `Ok(0)`. This includes the TLS streams, where a peer that closes the
+//! connection without sending `close_notify` is not treated as an error. Writes are passed through
+//! after the half-close so whatever is still pending can be flushed. `Websocket` decodes the frames
+//! received before the EOF and then fails the read with `Error::Closed`, whether it also stops sending
+//! is controlled by `HalfClose`.
 
 use crate::inet::{FromSocketAddr, IntoNetworkInterface, ToSocketAddr};
+use crate::preset::{Preset, Tuning};
 use crate::service::select::Selectable;
+use crate::stream::proxy::Proxy;
 use pnet::datalink::NetworkInterface;
 use socket2::{Domain, Protocol, Socket, Type};
 use std::fmt::{Display, Formatter};
 use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
 use std::{io, vec};
 use url::{ParseError, Url};
 
 pub mod buffer;
+#[cfg(all(target_os = "linux", feature = "ebpf"))]
+pub mod ebpf;
 pub mod file;
+#[cfg(target_os = "linux")]
+pub mod handover;
 #[cfg(all(target_os = "linux", feature = "ktls"))]
 pub mod ktls;
+pub mod layer;
 #[cfg(feature = "mio")]
 pub mod mio;
+pub mod pcap;
+pub mod proxy;
 pub mod record;
 pub mod replay;
+pub mod staging;
 pub mod tcp;
 #[cfg(all(target_os = "linux", feature = "timestamping"))]
 pub mod timestamping;
 #[cfg(any(feature = "rustls", feature = "openssl"))]
 pub mod tls;
+pub mod transport;
 
 #[cfg(target_os = "linux")]
 const EINPROGRESS: i32 = 115;
@@ -159,6 +184,8 @@ impl BindAndConnect for TcpStream {
             Type::STREAM,
             Some(Protocol::TCP),
         )?;
+        #[cfg(unix)]
+        debug_assert!(crate::util::is_cloexec(std::os::fd::AsRawFd::as_raw_fd(&socket))?);
         socket.set_nonblocking(true)?;
         socket.set_nodelay(true)?;
         socket.set_keepalive(true)?;
@@ -171,10 +198,16 @@ impl BindAndConnect for TcpStream {
             socket.bind(&addr.into())?;
         }
 
-        // optionally set rx cpu affinity (only on linux)
+        // optionally set rx cpu affinity (only on linux, skipped in restricted mode)
         #[cfg(target_os = "linux")]
         if let Some(cpu_affinity) = cpu {
-            socket.set_cpu_affinity(cpu_affinity)?;
+            if crate::syscalls::is_restricted() {
+                log::debug!("restricted mode: skipping SO_INCOMING_CPU for cpu {cpu_affinity}");
+            } else {
+                socket
+                    .set_cpu_affinity(cpu_affinity)
+                    .map_err(|err| crate::syscalls::blocked("setsockopt(SO_INCOMING_CPU)", err))?;
+            }
         }
 
         // connect to the remote endpoint
@@ -199,6 +232,21 @@ impl Selectable for TcpStream {
     fn make_readable(&mut self) -> io::Result<()> {
         Ok(())
     }
+
+    /// Reads of the std stream are not observed, wrap it in [`tcp::TcpStream`] to use a read timeout.
+    fn take_read_activity(&mut self) -> bool {
+        false
+    }
+}
+
+impl PendingWrites for TcpStream {
+    fn has_pending_writes(&self) -> bool {
+        false
+    }
+
+    fn drive_writes(&mut self) -> io::Result<bool> {
+        Ok(true)
+    }
 }
 
 pub trait ConnectionInfoProvider {
@@ -217,6 +265,17 @@ pub trait RxTimestamped {
     fn take_last_rx_timestamps(&mut self) -> Option<RxTimestamps>;
 }
 
+/// Streams that can hold written bytes back before handing them to the kernel (user space buffers,
+/// TLS records not yet written to the socket or writes buffered until the handshake completes).
+pub trait PendingWrites {
+    /// Checks if any of the bytes accepted by `write` have not been handed to the kernel yet.
+    fn has_pending_writes(&self) -> bool;
+
+    /// Push the pending bytes towards the kernel without blocking, returns `true` once nothing is
+    /// pending. Some streams (e.g. during the TLS handshake) only make progress on read.
+    fn drive_writes(&mut self) -> io::Result<bool>;
+}
+
 /// TCP stream connection info.
 #[derive(Debug, Clone, Default)]
 pub struct ConnectionInfo {
@@ -226,13 +285,18 @@ pub struct ConnectionInfo {
     net_iface_name: Option<String>,
     cpu: Option<usize>,
     socket_config: Option<fn(&Socket) -> io::Result<()>>,
+    tuning: Option<Tuning>,
+    proxy: Option<Proxy>,
+    read_timeout: Option<Duration>,
 }
 
+/// Resolves the address the socket connects to, see [`ConnectionInfo::connect_address`].
 impl ToSocketAddrs for ConnectionInfo {
     type Iter = vec::IntoIter<SocketAddr>;
 
     fn to_socket_addrs(&self) -> io::Result<Self::Iter> {
-        format!("{}:{}", self.host, self.port).to_socket_addrs()
+        let (host, port) = self.connect_address();
+        format!("{host}:{port}").to_socket_addrs()
     }
 }
 
@@ -258,6 +322,9 @@ impl TryFrom<Url> for ConnectionInfo {
             net_iface_name: None,
             cpu: None,
             socket_config: None,
+            tuning: None,
+            proxy: None,
+            read_timeout: None,
         })
     }
 }
@@ -290,6 +357,18 @@ impl ConnectionInfo {
             net_iface_name: None,
             cpu: None,
             socket_config: None,
+            tuning: None,
+            proxy: None,
+            read_timeout: None,
+        }
+    }
+
+    /// Same connection settings for another `host` and `port`, e.g. the target of a redirect.
+    pub fn with_address(self, host: &str, port: u16) -> Self {
+        Self {
+            host: host.to_owned(),
+            port,
+            ..self
         }
     }
 
@@ -329,6 +408,63 @@ impl ConnectionInfo {
         }
     }
 
+    /// Apply tuning settings from the [`Preset`]. Use [`ConnectionInfo::with_tuning`] to override
+    /// individual knobs.
+    pub fn with_preset(self, preset: Preset) -> Self {
+        self.with_tuning(preset.tuning())
+    }
+
+    /// Apply custom [`Tuning`] to the socket. The tuning is applied before any user provided
+    /// socket config so that it can still be overridden.
+    pub fn with_tuning(self, tuning: Tuning) -> Self {
+        Self {
+            tuning: Some(tuning),
+            ..self
+        }
+    }
+
+    /// Tunnel the connection through the HTTP or SOCKS5 [`Proxy`], see the [`proxy`] module.
+    pub fn with_proxy(self, proxy: Proxy) -> Self {
+        Self {
+            proxy: Some(proxy),
+            ..self
+        }
+    }
+
+    /// Abort the connection once no bytes have been received for the `timeout`, enforced by the
+    /// [`IOService`](crate::service::IOService) that manages the endpoint. The connection is then recreated
+    /// as for any other [`DisconnectReason::ReadTimeout`](crate::service::endpoint::DisconnectReason::ReadTimeout).
+    pub fn with_read_timeout(self, timeout: Duration) -> Self {
+        Self {
+            read_timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Get proxy if any has been configured.
+    pub fn proxy(&self) -> Option<&Proxy> {
+        self.proxy.as_ref()
+    }
+
+    /// Host and port the socket connects to, this is the proxy when one has been configured and
+    /// the remote host otherwise.
+    pub fn connect_address(&self) -> (&str, u16) {
+        match self.proxy.as_ref() {
+            Some(proxy) => (proxy.host(), proxy.port()),
+            None => (&self.host, self.port),
+        }
+    }
+
+    /// Get tuning if any has been configured.
+    pub fn tuning(&self) -> Option<&Tuning> {
+        self.tuning.as_ref()
+    }
+
+    /// Get read timeout if any has been configured.
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout
+    }
+
     /// Get host.
     pub fn host(&self) -> &str {
         &self.host
@@ -349,27 +485,97 @@ impl ConnectionInfo {
         self.net_iface_name.as_deref()
     }
 
-    /// Convert to tcp stream. This will perform DNS address resolution.
+    fn configure_socket(&self, socket: &Socket) -> io::Result<()> {
+        if let Some(tuning) = self.tuning.as_ref() {
+            tuning.apply(socket)?;
+        }
+        match self.socket_config {
+            Some(f) => f(socket),
+            None => Ok(()),
+        }
+    }
+
+    /// Convert to tcp stream. This will perform DNS address resolution (of the proxy if one has
+    /// been configured).
     pub fn into_tcp_stream(self) -> io::Result<tcp::TcpStream> {
-        let stream =
-            TcpStream::bind_and_connect_with_socket_config(&self, self.net_iface, self.cpu, |socket| {
-                match self.socket_config {
-                    Some(f) => f(socket),
-                    None => Ok(()),
-                }
-            })?;
-        Ok(tcp::TcpStream::new(stream, self))
+        let stream = TcpStream::bind_and_connect_with_socket_config(&self, self.net_iface, self.cpu, |socket| {
+            self.configure_socket(socket)
+        })?;
+        Ok(tcp::TcpStream::new(stream, self).with_tunnel())
     }
 
-    /// Convert to tcp stream using already resolved address.
+    /// Convert to tcp stream using already resolved address (of the proxy if one has been configured).
     pub fn into_tcp_stream_with_addr(self, addr: SocketAddr) -> io::Result<tcp::TcpStream> {
-        let stream =
-            TcpStream::bind_and_connect_with_socket_config(addr, self.net_iface, self.cpu, |socket| {
-                match self.socket_config {
-                    Some(f) => f(socket),
-                    None => Ok(()),
-                }
-            })?;
-        Ok(tcp::TcpStream::new(stream, self))
+        let stream = TcpStream::bind_and_connect_with_socket_config(addr, self.net_iface, self.cpu, |socket| {
+            self.configure_socket(socket)
+        })?;
+        Ok(tcp::TcpStream::new(stream, self).with_tunnel())
+    }
+
+    fn tunnel(&self) -> Option<proxy::Tunnel> {
+        self.proxy
+            .as_ref()
+            .map(|proxy| proxy::Tunnel::new(proxy, &self.host, self.port))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::util::is_cloexec;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::os::fd::AsRawFd;
+
+    #[test]
+    fn should_create_socket_with_cloexec() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stream = TcpStream::bind_and_connect(addr, None, None).unwrap();
+        assert!(is_cloexec(stream.as_raw_fd()).unwrap());
+    }
+
+    #[test]
+    fn should_tunnel_through_proxy() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let proxy = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            while !request.ends_with("\r\n\r\n") {
+                reader.read_line(&mut request).unwrap();
+            }
+            let mut stream = stream;
+            stream
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                .unwrap();
+            let mut ping = [0u8; 4];
+            reader.read_exact(&mut ping).unwrap();
+            stream.write_all(b"pong").unwrap();
+            (request, ping)
+        });
+
+        let mut stream = ConnectionInfo::new("stream.binance.com", 9443)
+            .with_proxy(Proxy::new("127.0.0.1", port))
+            .into_tcp_stream()
+            .unwrap();
+        assert_eq!(4, stream.write(b"ping").unwrap());
+
+        let mut pong = vec![];
+        let mut buf = [0u8; 16];
+        while pong.len() < 4 {
+            match stream.read(&mut buf) {
+                Ok(read) => pong.extend_from_slice(&buf[..read]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => std::thread::yield_now(),
+                Err(err) => panic!("{err}"),
+            }
+            stream.drive_writes().unwrap();
+        }
+        let (request, ping) = proxy.join().unwrap();
+        assert_eq!(b"pong", pong.as_slice());
+        assert_eq!(b"ping", &ping);
+        assert!(request.starts_with("CONNECT stream.binance.com:9443 HTTP/1.1\r\n"));
+        assert_eq!(("127.0.0.1", port), stream.connection_info().connect_address());
     }
 }
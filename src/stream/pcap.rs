@@ -0,0 +1,889 @@
+//! Import of captured exchange traffic (pcap) into recordings that can be driven by [`ReplayStream`].
+//!
+//! The importer reads a classic pcap file, selects a single TCP connection, reassembles both
+//! directions and writes them using the same layout as [`Recorder`]. Captures of TLS 1.3
+//! connections can be decrypted when the session secrets are provided via a key log file
+//! (`SSLKEYLOGFILE` format), this requires the `openssl` feature.
+//!
+//! Supported link types are Ethernet (with 802.1Q), Linux cooked capture (v1 and v2), BSD loopback
+//! and raw IP. Only IPv4 and IPv6 without extension headers are supported, fragmented packets are ignored.
+//!
+//! ## Examples
+//! ```no_run
+//! use boomnet::stream::pcap::PcapImport;
+//! use boomnet::stream::replay::ReplayStream;
+//!
+//! let summary = PcapImport::new()
+//!     .with_server_port(9443)
+//!     .with_key_log_file("/tmp/sslkeys.log")
+//!     .import("capture.pcap", "binance")
+//!     .unwrap();
+//! println!("{summary:?}");
+//!
+//! let replay = ReplayStream::from_file("binance_inbound").unwrap();
+//! ```
+//!
+//! [`ReplayStream`]: crate::stream::replay::ReplayStream
+
+use crate::stream::record::Recorder;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, ErrorKind, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+const TLS_HANDSHAKE: u8 = 22;
+const TLS_HEADER_LEN: usize = 5;
+/// Upper bound of the packet size used when the file header does not specify the snaplen (libpcap maximum).
+const MAX_SNAPLEN: usize = 262144;
+
+/// Decrypted inbound and outbound chunks together with the number of decrypted records.
+type Decrypted = (Vec<Vec<u8>>, Vec<Vec<u8>>, usize);
+
+/// Outcome of [`PcapImport::import`].
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct ImportSummary {
+    /// Number of packets in the capture.
+    pub packets: usize,
+    /// Number of TCP segments with payload that belong to the selected connection.
+    pub segments: usize,
+    /// Number of packets of other connections that were captured only partially (snaplen) and skipped.
+    pub truncated: usize,
+    /// Client endpoint of the selected connection.
+    pub client: Option<SocketAddr>,
+    /// Server endpoint of the selected connection.
+    pub server: Option<SocketAddr>,
+    /// Number of (plaintext) inbound bytes written to the recording.
+    pub inbound_bytes: usize,
+    /// Number of (plaintext) outbound bytes written to the recording.
+    pub outbound_bytes: usize,
+    /// Number of decrypted TLS records.
+    pub tls_records: usize,
+    /// Number of websocket frames found in the inbound data.
+    pub ws_frames: usize,
+}
+
+/// Builder for the pcap import.
+#[derive(Debug, Clone, Default)]
+pub struct PcapImport {
+    server: Option<SocketAddr>,
+    server_port: Option<u16>,
+    key_log_file: Option<PathBuf>,
+}
+
+impl PcapImport {
+    /// Create importer that will pick the first connection found in the capture.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Select the connection by the server address.
+    pub fn with_server(self, server: SocketAddr) -> Self {
+        Self {
+            server: Some(server),
+            ..self
+        }
+    }
+
+    /// Select the connection by the server port.
+    pub fn with_server_port(self, port: u16) -> Self {
+        Self {
+            server_port: Some(port),
+            ..self
+        }
+    }
+
+    /// Key log file (`SSLKEYLOGFILE` format) used to decrypt TLS 1.3 connections.
+    pub fn with_key_log_file(self, path: impl AsRef<Path>) -> Self {
+        Self {
+            key_log_file: Some(path.as_ref().to_path_buf()),
+            ..self
+        }
+    }
+
+    /// Import `pcap` file and write `{recording_name}_inbound.rec`, `{recording_name}_inbound_seq.rec`
+    /// and `{recording_name}_outbound.rec` files. The inbound recording can be replayed with
+    /// `ReplayStream::from_file("{recording_name}_inbound")`.
+    pub fn import(&self, pcap: impl AsRef<Path>, recording_name: impl AsRef<str>) -> io::Result<ImportSummary> {
+        let mut reader = PcapReader::new(BufReader::new(File::open(pcap)?))?;
+        let mut summary = ImportSummary::default();
+        let mut connection: Option<(SocketAddr, SocketAddr)> = None;
+        let mut inbound = Reassembler::default();
+        let mut outbound = Reassembler::default();
+
+        let mut packet = Vec::new();
+        while let Some(captured) = reader.next_packet(&mut packet)? {
+            summary.packets += 1;
+            let Some(segment) = reader.decode_segment(&packet) else {
+                summary.truncated += usize::from(captured.is_truncated());
+                continue;
+            };
+            let selected = match connection {
+                Some(connection) => Some(connection),
+                // truncated packet can not start the connection as its payload is incomplete
+                None if captured.is_truncated() => None,
+                None => self.select(&segment),
+            };
+            let Some((client, server)) = selected else {
+                summary.truncated += usize::from(captured.is_truncated());
+                continue;
+            };
+            let reassembler = if segment.src == server && segment.dst == client {
+                &mut inbound
+            } else if segment.src == client && segment.dst == server {
+                &mut outbound
+            } else {
+                summary.truncated += usize::from(captured.is_truncated());
+                continue;
+            };
+            if let Captured::Truncated { captured, original } = captured {
+                // a gap in the reassembled stream would corrupt the recording
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "packet {} of the connection is truncated ({captured} of {original} bytes captured)",
+                        summary.packets
+                    ),
+                ));
+            }
+            connection = Some((client, server));
+            if !segment.payload.is_empty() {
+                summary.segments += 1;
+            }
+            reassembler.on_segment(segment.seq, segment.syn, segment.payload);
+        }
+
+        let (client, server) = connection
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "no matching tcp connection in the capture"))?;
+        summary.client = Some(client);
+        summary.server = Some(server);
+
+        let (inbound, outbound) = match self.key_log_file.as_deref() {
+            Some(key_log_file) => {
+                let (inbound, outbound, records) = decrypt(key_log_file, inbound.chunks, outbound.chunks)?;
+                summary.tls_records = records;
+                (inbound, outbound)
+            }
+            None => {
+                if inbound.chunks.first().and_then(|chunk| chunk.first()) == Some(&TLS_HANDSHAKE) {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidInput,
+                        "connection appears to use TLS, provide the key log file to decrypt it",
+                    ));
+                }
+                (inbound.chunks, outbound.chunks)
+            }
+        };
+
+        let mut recorder = Recorder::new(recording_name)?;
+        for (seq, chunk) in inbound.iter().enumerate() {
+            recorder.record_inbound(chunk, seq)?;
+            summary.inbound_bytes += chunk.len();
+        }
+        for chunk in &outbound {
+            recorder.record_outbound(chunk)?;
+            summary.outbound_bytes += chunk.len();
+        }
+        summary.ws_frames = count_ws_frames(&inbound.concat());
+
+        Ok(summary)
+    }
+
+    fn select(&self, segment: &Segment) -> Option<(SocketAddr, SocketAddr)> {
+        let is_server = |addr: &SocketAddr| {
+            self.server.is_none_or(|server| server == *addr) && self.server_port.is_none_or(|port| port == addr.port())
+        };
+        let filtered = self.server.is_some() || self.server_port.is_some();
+        if filtered {
+            if is_server(&segment.dst) {
+                return Some((segment.src, segment.dst));
+            }
+            if is_server(&segment.src) {
+                return Some((segment.dst, segment.src));
+            }
+            return None;
+        }
+        if segment.syn && !segment.ack {
+            return Some((segment.src, segment.dst));
+        }
+        if segment.payload.is_empty() {
+            return None;
+        }
+        // capture started mid-connection, assume the server is listening on the lower port
+        match segment.src.port() < segment.dst.port() {
+            true => Some((segment.dst, segment.src)),
+            false => Some((segment.src, segment.dst)),
+        }
+    }
+}
+
+struct Segment<'a> {
+    src: SocketAddr,
+    dst: SocketAddr,
+    seq: u32,
+    syn: bool,
+    ack: bool,
+    payload: &'a [u8],
+}
+
+/// Length information of the packet returned by [`PcapReader::next_packet`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Captured {
+    Complete,
+    Truncated { captured: usize, original: usize },
+}
+
+impl Captured {
+    #[inline]
+    const fn is_truncated(self) -> bool {
+        matches!(self, Captured::Truncated { .. })
+    }
+}
+
+struct PcapReader<R> {
+    reader: R,
+    swapped: bool,
+    link_type: u32,
+    snaplen: usize,
+}
+
+impl<R: Read> PcapReader<R> {
+    fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0u8; 24];
+        reader.read_exact(&mut header)?;
+        let magic = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let swapped = match magic {
+            0xa1b2c3d4 | 0xa1b23c4d => false,
+            0xd4c3b2a1 | 0x4d3cb2a1 => true,
+            0x0a0d0d0a => return Err(io::Error::new(ErrorKind::Unsupported, "pcapng format is not supported")),
+            _ => return Err(io::Error::new(ErrorKind::InvalidData, "not a pcap file")),
+        };
+        let mut reader = Self {
+            reader,
+            swapped,
+            link_type: 0,
+            snaplen: 0,
+        };
+        reader.snaplen = match reader.u32(&header[16..20]) as usize {
+            0 => MAX_SNAPLEN,
+            snaplen => snaplen,
+        };
+        reader.link_type = reader.u32(&header[20..24]);
+        Ok(reader)
+    }
+
+    #[inline]
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = bytes.try_into().unwrap();
+        match self.swapped {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        }
+    }
+
+    /// Read next packet into `packet`, returns `None` at the end of the file. The captured length is
+    /// bounded by the file snaplen so that a corrupted record header can not trigger a huge allocation.
+    fn next_packet(&mut self, packet: &mut Vec<u8>) -> io::Result<Option<Captured>> {
+        let mut header = [0u8; 16];
+        match self.reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        }
+        let captured = self.u32(&header[8..12]) as usize;
+        let original = self.u32(&header[12..16]) as usize;
+        if captured > self.snaplen {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("captured packet length {captured} exceeds the snaplen {}", self.snaplen),
+            ));
+        }
+        packet.resize(captured, 0);
+        self.reader.read_exact(packet)?;
+        match captured < original {
+            true => Ok(Some(Captured::Truncated { captured, original })),
+            false => Ok(Some(Captured::Complete)),
+        }
+    }
+
+    fn decode_segment<'a>(&self, packet: &'a [u8]) -> Option<Segment<'a>> {
+        let ip = match self.link_type {
+            // ethernet
+            1 => {
+                let mut offset = 12;
+                let mut ether_type = u16::from_be_bytes(packet.get(offset..offset + 2)?.try_into().ok()?);
+                while ether_type == 0x8100 || ether_type == 0x88a8 {
+                    offset += 4;
+                    ether_type = u16::from_be_bytes(packet.get(offset..offset + 2)?.try_into().ok()?);
+                }
+                packet.get(offset + 2..)?
+            }
+            // bsd loopback
+            0 => packet.get(4..)?,
+            // raw ip
+            12 | 101 | 228 | 229 => packet,
+            // linux cooked capture
+            113 => packet.get(16..)?,
+            276 => packet.get(20..)?,
+            _ => return None,
+        };
+        decode_ip(ip)
+    }
+}
+
+fn decode_ip(ip: &[u8]) -> Option<Segment<'_>> {
+    match ip.first()? >> 4 {
+        4 => {
+            let header_len = ((ip[0] & 0x0f) as usize) * 4;
+            let total_len = u16::from_be_bytes(ip.get(2..4)?.try_into().ok()?) as usize;
+            let fragment = u16::from_be_bytes(ip.get(6..8)?.try_into().ok()?);
+            if *ip.get(9)? != 6 || fragment & 0x3fff != 0 {
+                return None;
+            }
+            let src = IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(ip.get(12..16)?).ok()?));
+            let dst = IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(ip.get(16..20)?).ok()?));
+            decode_tcp(src, dst, ip.get(header_len..total_len.min(ip.len()))?)
+        }
+        6 => {
+            let payload_len = u16::from_be_bytes(ip.get(4..6)?.try_into().ok()?) as usize;
+            if *ip.get(6)? != 6 {
+                return None;
+            }
+            let src = IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(ip.get(8..24)?).ok()?));
+            let dst = IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(ip.get(24..40)?).ok()?));
+            decode_tcp(src, dst, ip.get(40..(40 + payload_len).min(ip.len()))?)
+        }
+        _ => None,
+    }
+}
+
+fn decode_tcp(src: IpAddr, dst: IpAddr, tcp: &[u8]) -> Option<Segment<'_>> {
+    let src_port = u16::from_be_bytes(tcp.get(0..2)?.try_into().ok()?);
+    let dst_port = u16::from_be_bytes(tcp.get(2..4)?.try_into().ok()?);
+    let seq = u32::from_be_bytes(tcp.get(4..8)?.try_into().ok()?);
+    let data_offset = ((tcp.get(12)? >> 4) as usize) * 4;
+    let flags = *tcp.get(13)?;
+    Some(Segment {
+        src: SocketAddr::new(src, src_port),
+        dst: SocketAddr::new(dst, dst_port),
+        seq,
+        syn: flags & 0x02 != 0,
+        ack: flags & 0x10 != 0,
+        payload: tcp.get(data_offset..)?,
+    })
+}
+
+/// Reassembles one direction of the TCP connection. Every chunk corresponds to the data that became
+/// available to the reader upon arrival of a single segment.
+#[derive(Default)]
+struct Reassembler {
+    base: Option<u32>,
+    delivered: u64,
+    pending: BTreeMap<u64, Vec<u8>>,
+    chunks: Vec<Vec<u8>>,
+}
+
+impl Reassembler {
+    fn on_segment(&mut self, seq: u32, syn: bool, payload: &[u8]) {
+        if syn {
+            self.base = Some(seq.wrapping_add(1));
+            return;
+        }
+        if payload.is_empty() {
+            return;
+        }
+        let base = *self.base.get_or_insert(seq);
+        let offset = seq.wrapping_sub(base) as u64;
+        if offset > self.delivered {
+            let entry = self.pending.entry(offset).or_default();
+            if payload.len() > entry.len() {
+                *entry = payload.to_vec();
+            }
+            return;
+        }
+        let mut chunk = Vec::new();
+        self.append(offset, payload, &mut chunk);
+        while let Some(entry) = self.pending.first_entry() {
+            if *entry.key() > self.delivered {
+                break;
+            }
+            let (offset, payload) = entry.remove_entry();
+            self.append(offset, &payload, &mut chunk);
+        }
+        if !chunk.is_empty() {
+            self.chunks.push(chunk);
+        }
+    }
+
+    fn append(&mut self, offset: u64, payload: &[u8], chunk: &mut Vec<u8>) {
+        let end = offset + payload.len() as u64;
+        if end > self.delivered {
+            chunk.extend_from_slice(&payload[(self.delivered - offset) as usize..]);
+            self.delivered = end;
+        }
+    }
+}
+
+fn count_ws_frames(inbound: &[u8]) -> usize {
+    let Some(header_end) = inbound.windows(4).position(|w| w == b"\r\n\r\n") else {
+        return 0;
+    };
+    let mut frames = 0;
+    let mut pos = header_end + 4;
+    while pos + 2 <= inbound.len() {
+        let masked = inbound[pos + 1] & 0x80 != 0;
+        let (len, header_len) = match inbound[pos + 1] & 0x7f {
+            126 if pos + 4 <= inbound.len() => (u16::from_be_bytes([inbound[pos + 2], inbound[pos + 3]]) as usize, 4),
+            127 if pos + 10 <= inbound.len() => {
+                (u64::from_be_bytes(inbound[pos + 2..pos + 10].try_into().unwrap()) as usize, 10)
+            }
+            126 | 127 => break,
+            len => (len as usize, 2),
+        };
+        let end = pos + header_len + if masked { 4 } else { 0 } + len;
+        if end > inbound.len() {
+            break;
+        }
+        frames += 1;
+        pos = end;
+    }
+    frames
+}
+
+/// Split the reassembled stream into TLS records (header included).
+#[cfg_attr(not(feature = "openssl"), allow(dead_code))]
+fn tls_records(chunks: &[Vec<u8>]) -> io::Result<Vec<Vec<u8>>> {
+    let stream = chunks.concat();
+    let mut records = Vec::new();
+    let mut pos = 0;
+    while pos + TLS_HEADER_LEN <= stream.len() {
+        let len = u16::from_be_bytes([stream[pos + 3], stream[pos + 4]]) as usize;
+        let end = pos + TLS_HEADER_LEN + len;
+        if end > stream.len() {
+            break;
+        }
+        records.push(stream[pos..end].to_vec());
+        pos = end;
+    }
+    if records.is_empty() && !stream.is_empty() {
+        return Err(io::Error::new(ErrorKind::InvalidData, "no complete tls records found"));
+    }
+    Ok(records)
+}
+
+#[cfg(not(feature = "openssl"))]
+fn decrypt(_key_log_file: &Path, _inbound: Vec<Vec<u8>>, _outbound: Vec<Vec<u8>>) -> io::Result<Decrypted> {
+    Err(io::Error::new(ErrorKind::Unsupported, "tls decryption requires the `openssl` feature"))
+}
+
+#[cfg(feature = "openssl")]
+fn decrypt(key_log_file: &Path, inbound: Vec<Vec<u8>>, outbound: Vec<Vec<u8>>) -> io::Result<Decrypted> {
+    use tls13::{CipherSuite, KeyLog};
+
+    let key_log = KeyLog::from_file(key_log_file)?;
+    let inbound = tls_records(&inbound)?;
+    let outbound = tls_records(&outbound)?;
+
+    let client_random = outbound
+        .iter()
+        .find(|record| record[0] == TLS_HANDSHAKE)
+        .and_then(|record| tls13::client_random(&record[TLS_HEADER_LEN..]))
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "client hello not found"))?;
+    let suite = inbound
+        .iter()
+        .find(|record| record[0] == TLS_HANDSHAKE)
+        .and_then(|record| tls13::server_cipher_suite(&record[TLS_HEADER_LEN..]))
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "server hello not found"))?;
+    let suite = CipherSuite::from_id(suite).ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::Unsupported,
+            format!("unsupported cipher suite 0x{suite:04x}, only tls 1.3 is supported"),
+        )
+    })?;
+
+    let secret = |label: &str| {
+        key_log.secret(label, client_random).ok_or_else(|| {
+            io::Error::new(ErrorKind::NotFound, format!("{label} not found in the key log for the captured session"))
+        })
+    };
+
+    let (inbound, inbound_records) = tls13::decrypt_records(
+        suite,
+        &inbound,
+        secret("SERVER_HANDSHAKE_TRAFFIC_SECRET")?,
+        secret("SERVER_TRAFFIC_SECRET_0")?,
+    )?;
+    let (outbound, outbound_records) = tls13::decrypt_records(
+        suite,
+        &outbound,
+        secret("CLIENT_HANDSHAKE_TRAFFIC_SECRET")?,
+        secret("CLIENT_TRAFFIC_SECRET_0")?,
+    )?;
+    Ok((inbound, outbound, inbound_records + outbound_records))
+}
+
+#[cfg(feature = "openssl")]
+mod tls13 {
+    use super::TLS_HEADER_LEN;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::PKey;
+    use openssl::sign::Signer;
+    use openssl::symm::{Cipher, decrypt_aead};
+    use std::collections::HashMap;
+    use std::io;
+    use std::io::ErrorKind;
+    use std::path::Path;
+
+    const TAG_LEN: usize = 16;
+    const CHANGE_CIPHER_SPEC: u8 = 20;
+    const ALERT: u8 = 21;
+    const APPLICATION_DATA: u8 = 23;
+
+    #[derive(Debug, Copy, Clone)]
+    pub(super) enum CipherSuite {
+        Aes128GcmSha256,
+        Aes256GcmSha384,
+        Chacha20Poly1305Sha256,
+    }
+
+    impl CipherSuite {
+        pub(super) fn from_id(id: u16) -> Option<Self> {
+            match id {
+                0x1301 => Some(Self::Aes128GcmSha256),
+                0x1302 => Some(Self::Aes256GcmSha384),
+                0x1303 => Some(Self::Chacha20Poly1305Sha256),
+                _ => None,
+            }
+        }
+
+        fn digest(self) -> MessageDigest {
+            match self {
+                Self::Aes256GcmSha384 => MessageDigest::sha384(),
+                _ => MessageDigest::sha256(),
+            }
+        }
+
+        fn cipher(self) -> Cipher {
+            match self {
+                Self::Aes128GcmSha256 => Cipher::aes_128_gcm(),
+                Self::Aes256GcmSha384 => Cipher::aes_256_gcm(),
+                Self::Chacha20Poly1305Sha256 => Cipher::chacha20_poly1305(),
+            }
+        }
+
+        fn key_len(self) -> usize {
+            match self {
+                Self::Aes128GcmSha256 => 16,
+                _ => 32,
+            }
+        }
+    }
+
+    pub(super) struct KeyLog {
+        secrets: HashMap<(String, Vec<u8>), Vec<u8>>,
+    }
+
+    impl KeyLog {
+        pub(super) fn from_file(path: &Path) -> io::Result<Self> {
+            let mut secrets = HashMap::new();
+            for line in std::fs::read_to_string(path)?.lines() {
+                let mut parts = line.split_whitespace();
+                if let (Some(label), Some(client_random), Some(secret)) = (parts.next(), parts.next(), parts.next()) {
+                    if let (Some(client_random), Some(secret)) = (from_hex(client_random), from_hex(secret)) {
+                        secrets.insert((label.to_owned(), client_random), secret);
+                    }
+                }
+            }
+            Ok(Self { secrets })
+        }
+
+        pub(super) fn secret(&self, label: &str, client_random: &[u8]) -> Option<&[u8]> {
+            self.secrets
+                .get(&(label.to_owned(), client_random.to_vec()))
+                .map(|secret| secret.as_slice())
+        }
+    }
+
+    fn from_hex(hex: &str) -> Option<Vec<u8>> {
+        if !hex.len().is_multiple_of(2) {
+            return None;
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect()
+    }
+
+    /// Extract client random from the `ClientHello` handshake message.
+    pub(super) fn client_random(handshake: &[u8]) -> Option<&[u8]> {
+        if *handshake.first()? != 1 {
+            return None;
+        }
+        handshake.get(6..38)
+    }
+
+    /// Extract selected cipher suite from the `ServerHello` handshake message.
+    pub(super) fn server_cipher_suite(handshake: &[u8]) -> Option<u16> {
+        if *handshake.first()? != 2 {
+            return None;
+        }
+        let session_id_len = *handshake.get(38)? as usize;
+        let offset = 39 + session_id_len;
+        Some(u16::from_be_bytes(handshake.get(offset..offset + 2)?.try_into().ok()?))
+    }
+
+    pub(super) fn hkdf_expand_label(
+        digest: MessageDigest,
+        secret: &[u8],
+        label: &str,
+        len: usize,
+    ) -> io::Result<Vec<u8>> {
+        let mut info = Vec::with_capacity(10 + label.len());
+        info.extend_from_slice(&(len as u16).to_be_bytes());
+        info.push((6 + label.len()) as u8);
+        info.extend_from_slice(b"tls13 ");
+        info.extend_from_slice(label.as_bytes());
+        info.push(0);
+
+        let key = PKey::hmac(secret).map_err(io::Error::other)?;
+        let mut output = Vec::with_capacity(len);
+        let mut block = Vec::new();
+        let mut counter = 1u8;
+        while output.len() < len {
+            let mut signer = Signer::new(digest, &key).map_err(io::Error::other)?;
+            signer.update(&block).map_err(io::Error::other)?;
+            signer.update(&info).map_err(io::Error::other)?;
+            signer.update(&[counter]).map_err(io::Error::other)?;
+            block = signer.sign_to_vec().map_err(io::Error::other)?;
+            output.extend_from_slice(&block);
+            counter += 1;
+        }
+        output.truncate(len);
+        Ok(output)
+    }
+
+    struct TrafficKeys {
+        suite: CipherSuite,
+        key: Vec<u8>,
+        iv: Vec<u8>,
+        seq: u64,
+    }
+
+    impl TrafficKeys {
+        fn new(suite: CipherSuite, secret: &[u8]) -> io::Result<Self> {
+            Ok(Self {
+                suite,
+                key: hkdf_expand_label(suite.digest(), secret, "key", suite.key_len())?,
+                iv: hkdf_expand_label(suite.digest(), secret, "iv", 12)?,
+                seq: 0,
+            })
+        }
+
+        fn decrypt(&mut self, record: &[u8]) -> Option<Vec<u8>> {
+            let (header, payload) = record.split_at(TLS_HEADER_LEN);
+            let (data, tag) = payload.split_at(payload.len().checked_sub(TAG_LEN)?);
+            let mut nonce = self.iv.clone();
+            for (n, s) in nonce[4..].iter_mut().zip(self.seq.to_be_bytes()) {
+                *n ^= s;
+            }
+            let plaintext = decrypt_aead(self.suite.cipher(), &self.key, Some(&nonce), header, data, tag).ok()?;
+            self.seq += 1;
+            Some(plaintext)
+        }
+    }
+
+    /// Decrypt records of one direction. Returns application data (one chunk per record) and
+    /// the number of decrypted records.
+    pub(super) fn decrypt_records(
+        suite: CipherSuite,
+        records: &[Vec<u8>],
+        handshake_secret: &[u8],
+        application_secret: &[u8],
+    ) -> io::Result<(Vec<Vec<u8>>, usize)> {
+        let mut keys = TrafficKeys::new(suite, handshake_secret)?;
+        let mut application = false;
+        let mut chunks = Vec::new();
+        let mut decrypted = 0;
+        for record in records {
+            match record[0] {
+                APPLICATION_DATA => {}
+                CHANGE_CIPHER_SPEC => continue,
+                _ if decrypted == 0 => continue,
+                _ => break,
+            }
+            let mut plaintext = keys.decrypt(record);
+            if plaintext.is_none() && !application {
+                // handshake is complete, switch to the application traffic keys
+                keys = TrafficKeys::new(suite, application_secret)?;
+                application = true;
+                plaintext = keys.decrypt(record);
+            }
+            let mut plaintext =
+                plaintext.ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "failed to decrypt tls record"))?;
+            decrypted += 1;
+
+            // strip padding, the last non zero byte is the inner content type
+            let Some(content_type_pos) = plaintext.iter().rposition(|b| *b != 0) else {
+                continue;
+            };
+            let content_type = plaintext[content_type_pos];
+            plaintext.truncate(content_type_pos);
+            match content_type {
+                APPLICATION_DATA if !plaintext.is_empty() => chunks.push(plaintext),
+                ALERT => break,
+                _ => {}
+            }
+        }
+        Ok((chunks, decrypted))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn tcp_packet(src_port: u16, dst_port: u16, seq: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0u8; 12];
+        packet.extend_from_slice(&0x0800u16.to_be_bytes());
+        let total_len = (20 + 20 + payload.len()) as u16;
+        packet.extend_from_slice(&[0x45, 0]);
+        packet.extend_from_slice(&total_len.to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0]);
+        let (src, dst) = if src_port == 443 { (2, 1) } else { (1, 2) };
+        packet.extend_from_slice(&[10, 0, 0, src]);
+        packet.extend_from_slice(&[10, 0, 0, dst]);
+        packet.extend_from_slice(&src_port.to_be_bytes());
+        packet.extend_from_slice(&dst_port.to_be_bytes());
+        packet.extend_from_slice(&seq.to_be_bytes());
+        packet.extend_from_slice(&0u32.to_be_bytes());
+        packet.extend_from_slice(&[0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    fn write_pcap(path: &Path, packets: &[Vec<u8>]) {
+        let packets = packets
+            .iter()
+            .map(|packet| (packet.clone(), packet.len()))
+            .collect::<Vec<_>>();
+        write_pcap_truncated(path, 65535, &packets);
+    }
+
+    fn write_pcap_truncated(path: &Path, snaplen: u32, packets: &[(Vec<u8>, usize)]) {
+        let mut file = File::create(path).unwrap();
+        file.write_all(&0xa1b2c3d4u32.to_le_bytes()).unwrap();
+        file.write_all(&[2, 0, 4, 0]).unwrap();
+        file.write_all(&[0u8; 8]).unwrap();
+        file.write_all(&snaplen.to_le_bytes()).unwrap();
+        file.write_all(&1u32.to_le_bytes()).unwrap();
+        for (packet, original) in packets {
+            file.write_all(&[0u8; 8]).unwrap();
+            file.write_all(&(packet.len() as u32).to_le_bytes()).unwrap();
+            file.write_all(&(*original as u32).to_le_bytes()).unwrap();
+            file.write_all(packet).unwrap();
+        }
+    }
+
+    #[test]
+    fn should_reassemble_out_of_order_and_retransmitted_segments() {
+        let mut reassembler = Reassembler::default();
+        reassembler.on_segment(99, true, &[]);
+        reassembler.on_segment(100, false, b"abc");
+        reassembler.on_segment(106, false, b"ghi");
+        reassembler.on_segment(100, false, b"abc");
+        reassembler.on_segment(102, false, b"cdef");
+        assert_eq!(vec![b"abc".to_vec(), b"defghi".to_vec()], reassembler.chunks);
+    }
+
+    #[test]
+    fn should_import_plaintext_websocket_capture() {
+        let dir = std::env::temp_dir().join(format!("boomnet_pcap_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let pcap = dir.join("capture.pcap");
+        let response = b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\r\n";
+        write_pcap(
+            &pcap,
+            &[
+                tcp_packet(50000, 443, 1000, 0x02, &[]),
+                tcp_packet(443, 50000, 5000, 0x12, &[]),
+                tcp_packet(50000, 443, 1001, 0x18, b"GET /ws HTTP/1.1\r\n\r\n"),
+                tcp_packet(443, 50000, 5001, 0x18, response),
+                tcp_packet(443, 50000, 5001 + response.len() as u32 + 3, 0x18, b"\x81\x02hi"),
+                tcp_packet(443, 50000, 5001 + response.len() as u32, 0x18, b"\x81\x01a"),
+            ],
+        );
+
+        let recording = dir.join("plain");
+        let summary = PcapImport::new().import(&pcap, recording.to_str().unwrap()).unwrap();
+
+        assert_eq!(6, summary.packets);
+        assert_eq!(4, summary.segments);
+        assert_eq!(Some("10.0.0.2:443".parse().unwrap()), summary.server);
+        assert_eq!(response.len() + 7, summary.inbound_bytes);
+        assert_eq!(20, summary.outbound_bytes);
+        assert_eq!(2, summary.ws_frames);
+
+        let inbound = std::fs::read(dir.join("plain_inbound.rec")).unwrap();
+        assert_eq!([&response[..], b"\x81\x01a\x81\x02hi"].concat(), inbound);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn should_bound_packet_length_by_snaplen_and_reject_truncated_segments() {
+        let dir = std::env::temp_dir().join(format!("boomnet_pcap_snaplen_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let pcap = dir.join("capture.pcap");
+        let packet = tcp_packet(443, 50000, 5001, 0x18, &[0u8; 100]);
+
+        // captured length above the snaplen
+        write_pcap_truncated(&pcap, 64, &[(packet.clone(), packet.len())]);
+        let err = PcapImport::new()
+            .import(&pcap, dir.join("rec").to_str().unwrap())
+            .unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, err.kind());
+
+        // truncated packets of other connections are skipped and counted
+        let other = tcp_packet(50001, 8080, 1, 0x18, b"other");
+        let truncated = (other[..other.len() - 2].to_vec(), other.len());
+        write_pcap_truncated(&pcap, 65535, &[truncated, (packet.clone(), packet.len())]);
+        let summary = PcapImport::new()
+            .with_server_port(443)
+            .import(&pcap, dir.join("rec").to_str().unwrap())
+            .unwrap();
+        assert_eq!(1, summary.truncated);
+        assert_eq!(100, summary.inbound_bytes);
+
+        // truncated segment of the selected connection would leave a gap in the recording
+        let truncated = (packet[..packet.len() - 10].to_vec(), packet.len());
+        write_pcap_truncated(&pcap, 65535, &[(packet.clone(), packet.len()), truncated]);
+        let err = PcapImport::new()
+            .with_server_port(443)
+            .import(&pcap, dir.join("rec").to_str().unwrap())
+            .unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, err.kind());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "openssl")]
+    #[test]
+    fn should_derive_tls13_traffic_keys() {
+        // RFC 8448, simple 1-RTT handshake, server handshake traffic keys
+        let secret = tls13_hex("b67b7d690cc16c4e75e54213cb2d37b4e9c912bcded9105d42befd59d391ad38");
+        let digest = openssl::hash::MessageDigest::sha256();
+        assert_eq!(
+            tls13_hex("3fce516009c21727d0f2e4e86ee403bc"),
+            tls13::hkdf_expand_label(digest, &secret, "key", 16).unwrap()
+        );
+        assert_eq!(tls13_hex("5d313eb2671276ee13000b30"), tls13::hkdf_expand_label(digest, &secret, "iv", 12).unwrap());
+    }
+
+    #[cfg(feature = "openssl")]
+    fn tls13_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+}
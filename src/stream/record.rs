@@ -1,11 +1,13 @@
 //! Stream that will also record incoming and outgoing data to a file.
 //!
 
-use crate::stream::{ConnectionInfo, ConnectionInfoProvider};
+use crate::service::select::Selectable;
+use crate::stream::{ConnectionInfo, ConnectionInfoProvider, PendingWrites};
 use std::fmt::{Debug, Formatter};
 use std::fs::File;
 use std::io;
 use std::io::{BufWriter, Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
 
 const DEFAULT_RECORDING_NAME: &str = "plain";
 
@@ -31,7 +33,7 @@ impl Recorder {
             outbound,
         })
     }
-    fn record_inbound(&mut self, buf: &[u8], seq: usize) -> io::Result<()> {
+    pub(crate) fn record_inbound(&mut self, buf: &[u8], seq: usize) -> io::Result<()> {
         self.inbound.write_all(buf)?;
         self.inbound.flush()?;
         self.inbound_seq.write_all(&seq.to_le_bytes())?;
@@ -39,7 +41,7 @@ impl Recorder {
         self.inbound_seq.flush()?;
         Ok(())
     }
-    fn record_outbound(&mut self, buf: &[u8]) -> io::Result<()> {
+    pub(crate) fn record_outbound(&mut self, buf: &[u8]) -> io::Result<()> {
         self.outbound.write_all(buf)?;
         self.outbound.flush()
     }
@@ -91,6 +93,52 @@ impl<S: Read + Write> Write for RecordedStream<S> {
     }
 }
 
+impl<S: AsRawFd> AsRawFd for RecordedStream<S> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+impl<S: PendingWrites> PendingWrites for RecordedStream<S> {
+    fn has_pending_writes(&self) -> bool {
+        self.inner.has_pending_writes()
+    }
+
+    fn drive_writes(&mut self) -> io::Result<bool> {
+        self.inner.drive_writes()
+    }
+}
+
+impl<S: Selectable> Selectable for RecordedStream<S> {
+    fn connected(&mut self) -> io::Result<bool> {
+        self.inner.connected()
+    }
+
+    fn make_writable(&mut self) -> io::Result<()> {
+        self.inner.make_writable()
+    }
+
+    fn make_readable(&mut self) -> io::Result<()> {
+        self.inner.make_readable()
+    }
+
+    fn take_read_activity(&mut self) -> bool {
+        self.inner.take_read_activity()
+    }
+
+    fn take_bytes_read(&mut self) -> u64 {
+        self.inner.take_bytes_read()
+    }
+
+    fn take_bytes_written(&mut self) -> u64 {
+        self.inner.take_bytes_written()
+    }
+
+    fn set_read_paused(&mut self, paused: bool) {
+        self.inner.set_read_paused(paused)
+    }
+}
+
 impl<S: ConnectionInfoProvider> ConnectionInfoProvider for RecordedStream<S> {
     fn connection_info(&self) -> &ConnectionInfo {
         self.inner.connection_info()
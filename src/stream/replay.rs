@@ -1,6 +1,7 @@
 //! Stream that uses file replay.
 
-use crate::stream::{ConnectionInfo, ConnectionInfoProvider};
+use crate::service::select::Selectable;
+use crate::stream::{ConnectionInfo, ConnectionInfoProvider, PendingWrites};
 use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
 use std::fs::File;
@@ -15,6 +16,8 @@ pub struct ReplayStream<S> {
     seq: Sequence,
     last_seq: Sequence,
     bytes_read: HashMap<Sequence, usize>,
+    // replayed since the last `take_read_activity`
+    received: bool,
 }
 
 impl<S> Debug for ReplayStream<S> {
@@ -42,6 +45,7 @@ impl ReplayStream<BufReader<File>> {
             seq: 0,
             bytes_read,
             last_seq,
+            received: false,
         })
     }
 }
@@ -63,6 +67,7 @@ impl<S: Read> Read for ReplayStream<S> {
         while actual_read != read {
             actual_read += self.inner.read(buf[actual_read..read].as_mut())?;
         }
+        self.received = true;
 
         Ok(actual_read)
     }
@@ -78,6 +83,34 @@ impl<S> Write for ReplayStream<S> {
     }
 }
 
+impl<S> PendingWrites for ReplayStream<S> {
+    fn has_pending_writes(&self) -> bool {
+        false
+    }
+
+    fn drive_writes(&mut self) -> io::Result<bool> {
+        Ok(true)
+    }
+}
+
+impl<S> Selectable for ReplayStream<S> {
+    fn connected(&mut self) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    fn make_writable(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn make_readable(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn take_read_activity(&mut self) -> bool {
+        std::mem::take(&mut self.received)
+    }
+}
+
 impl<S> ConnectionInfoProvider for ReplayStream<S> {
     fn connection_info(&self) -> &ConnectionInfo {
         Box::leak(Box::new(ConnectionInfo::default()))
@@ -0,0 +1,372 @@
+//! Preallocated (and when possible pinned) staging ring for the write path.
+//!
+//! The ring is meant to sit between the TLS stream and the socket so that every encrypted record is
+//! copied into warm memory that has been faulted in and locked (`mlock`) upfront. Whatever the socket
+//! can not accept straight away stays in the ring and is drained on the next write, flush or read, so
+//! large bursts never trigger allocator activity in the crate. The ring never grows, once it is full
+//! writes fail with [`ErrorKind::WouldBlock`] until the socket drains it.
+//!
+//! Locking the memory is best effort: it is skipped in [restricted](crate::syscalls) mode and a failure
+//! (typically `RLIMIT_MEMLOCK`) is logged, leaving the ring usable but pageable.
+//!
+//! ## Examples
+//! ```no_run
+//! use boomnet::stream::ConnectionInfo;
+//! use boomnet::stream::staging::IntoStagingStream;
+//! use boomnet::stream::tls::IntoTlsStream;
+//! use boomnet::ws::IntoWebsocket;
+//!
+//! let mut ws = ConnectionInfo::new("stream.binance.com", 9443)
+//!  .into_tcp_stream().unwrap()
+//!  .into_staging_stream(8)
+//!  .into_tls_stream().unwrap()
+//!  .into_websocket("/ws");
+//! ```
+
+use crate::service::select::Selectable;
+use crate::stream::{ConnectionInfo, ConnectionInfoProvider, PendingWrites, RxTimestamped, RxTimestamps};
+#[cfg(feature = "mio")]
+use mio::{Interest, Registry, Token, event::Source};
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::io::ErrorKind::{WouldBlock, WriteZero};
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+
+/// Largest TLS record on the wire: 5 byte header, 2^14 bytes of plaintext and up to 256 bytes of expansion.
+pub const TLS_RECORD_SIZE: usize = 5 + (1 << 14) + 256;
+
+const PAGE_SIZE: usize = 4096;
+
+/// Fixed capacity byte ring made of `slots` TLS record sized buffers.
+pub struct StagingRing {
+    memory: Box<[u8]>,
+    head: usize,
+    len: usize,
+    locked: bool,
+}
+
+impl StagingRing {
+    /// Allocate ring that can hold `slots` full TLS records. The memory is faulted in and locked
+    /// (best effort) so that the first burst does not hit page faults.
+    pub fn new(slots: usize) -> StagingRing {
+        let mut memory = vec![0u8; slots.max(1) * TLS_RECORD_SIZE].into_boxed_slice();
+        // touch every page so the memory is resident before the first write
+        for offset in (0..memory.len()).step_by(PAGE_SIZE) {
+            // SAFETY: offset is within the allocation
+            unsafe { std::ptr::write_volatile(memory.as_mut_ptr().add(offset), 0) };
+        }
+        let locked = lock(&memory);
+        Self {
+            memory,
+            head: 0,
+            len: 0,
+            locked,
+        }
+    }
+
+    /// Total number of bytes the ring can hold.
+    #[inline]
+    pub const fn capacity(&self) -> usize {
+        self.memory.len()
+    }
+
+    /// Number of bytes staged but not yet written to the underlying stream.
+    #[inline]
+    pub const fn pending(&self) -> usize {
+        self.len
+    }
+
+    /// Checks if the ring memory has been successfully locked.
+    #[inline]
+    pub const fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Copy as much of `buf` as fits and return the number of bytes staged.
+    fn push(&mut self, buf: &[u8]) -> usize {
+        let capacity = self.capacity();
+        let len = buf.len().min(capacity - self.len);
+        let tail = (self.head + self.len) % capacity;
+        let first = len.min(capacity - tail);
+        self.memory[tail..tail + first].copy_from_slice(&buf[..first]);
+        self.memory[..len - first].copy_from_slice(&buf[first..len]);
+        self.len += len;
+        len
+    }
+
+    /// Contiguous view of the oldest staged bytes.
+    #[inline]
+    fn front(&self) -> &[u8] {
+        let end = (self.head + self.len).min(self.capacity());
+        &self.memory[self.head..end]
+    }
+
+    #[inline]
+    fn consume(&mut self, len: usize) {
+        self.head = (self.head + len) % self.capacity();
+        self.len -= len;
+    }
+}
+
+impl Debug for StagingRing {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StagingRing")
+            .field("capacity", &self.capacity())
+            .field("pending", &self.len)
+            .field("locked", &self.locked)
+            .finish()
+    }
+}
+
+impl Drop for StagingRing {
+    fn drop(&mut self) {
+        if self.locked {
+            // SAFETY: the range was locked by `lock` and is still owned by us
+            unsafe { libc::munlock(self.memory.as_ptr() as *const libc::c_void, self.memory.len()) };
+        }
+    }
+}
+
+fn lock(memory: &[u8]) -> bool {
+    if crate::syscalls::is_restricted() {
+        log::debug!("restricted mode: skipping mlock for {} byte staging ring", memory.len());
+        return false;
+    }
+    // SAFETY: the range is a valid allocation owned by the caller
+    let rc = unsafe { libc::mlock(memory.as_ptr() as *const libc::c_void, memory.len()) };
+    if rc != 0 {
+        let err = crate::syscalls::blocked("mlock", io::Error::last_os_error());
+        log::warn!("staging ring memory is not pinned: {err}");
+        return false;
+    }
+    true
+}
+
+/// Stream that stages every write in a [`StagingRing`] before passing it to the underlying stream.
+#[derive(Debug)]
+pub struct StagingStream<S> {
+    inner: S,
+    ring: StagingRing,
+}
+
+impl<S> StagingStream<S> {
+    /// Wrap `inner` stream using ring with `slots` TLS record sized buffers.
+    pub fn new(inner: S, slots: usize) -> StagingStream<S> {
+        Self {
+            inner,
+            ring: StagingRing::new(slots),
+        }
+    }
+
+    /// Get reference to the staging ring.
+    pub const fn ring(&self) -> &StagingRing {
+        &self.ring
+    }
+}
+
+impl<S: Write> StagingStream<S> {
+    /// Write staged bytes to the underlying stream until it would block.
+    fn drain(&mut self) -> io::Result<()> {
+        while self.ring.pending() > 0 {
+            match self.inner.write(self.ring.front()) {
+                Ok(0) => return Err(io::Error::new(WriteZero, "unable to drain staging ring")),
+                Ok(len) => self.ring.consume(len),
+                Err(err) if err.kind() == WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S: Read + Write> Read for StagingStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.ring.pending() > 0 {
+            self.drain()?;
+        }
+        self.inner.read(buf)
+    }
+}
+
+impl<S: Write> Write for StagingStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut staged = self.ring.push(buf);
+        self.drain()?;
+        if staged < buf.len() {
+            staged += self.ring.push(&buf[staged..]);
+        }
+        if staged == 0 && !buf.is_empty() {
+            return Err(io::Error::from(WouldBlock));
+        }
+        Ok(staged)
+    }
+
+    /// Drains as much as the underlying stream accepts without blocking, anything left is drained
+    /// on the next read or write.
+    fn flush(&mut self) -> io::Result<()> {
+        self.drain()?;
+        self.inner.flush()
+    }
+}
+
+impl<S: Write + PendingWrites> PendingWrites for StagingStream<S> {
+    fn has_pending_writes(&self) -> bool {
+        self.ring.pending() > 0 || self.inner.has_pending_writes()
+    }
+
+    fn drive_writes(&mut self) -> io::Result<bool> {
+        self.drain()?;
+        if self.ring.pending() > 0 {
+            return Ok(false);
+        }
+        self.inner.drive_writes()
+    }
+}
+
+impl<S: ConnectionInfoProvider> ConnectionInfoProvider for StagingStream<S> {
+    fn connection_info(&self) -> &ConnectionInfo {
+        self.inner.connection_info()
+    }
+}
+
+impl<S: RxTimestamped> RxTimestamped for StagingStream<S> {
+    fn last_rx_timestamps(&self) -> Option<RxTimestamps> {
+        self.inner.last_rx_timestamps()
+    }
+
+    fn take_last_rx_timestamps(&mut self) -> Option<RxTimestamps> {
+        self.inner.take_last_rx_timestamps()
+    }
+}
+
+impl<S: AsRawFd> AsRawFd for StagingStream<S> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+impl<S: Selectable> Selectable for StagingStream<S> {
+    fn connected(&mut self) -> io::Result<bool> {
+        self.inner.connected()
+    }
+
+    fn make_writable(&mut self) -> io::Result<()> {
+        self.inner.make_writable()
+    }
+
+    fn make_readable(&mut self) -> io::Result<()> {
+        self.inner.make_readable()
+    }
+
+    fn take_read_activity(&mut self) -> bool {
+        self.inner.take_read_activity()
+    }
+
+    fn take_bytes_read(&mut self) -> u64 {
+        self.inner.take_bytes_read()
+    }
+
+    fn take_bytes_written(&mut self) -> u64 {
+        self.inner.take_bytes_written()
+    }
+
+    fn set_read_paused(&mut self, paused: bool) {
+        self.inner.set_read_paused(paused)
+    }
+}
+
+#[cfg(feature = "mio")]
+impl<S: Source> Source for StagingStream<S> {
+    fn register(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+        registry.register(&mut self.inner, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+        registry.reregister(&mut self.inner, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        registry.deregister(&mut self.inner)
+    }
+}
+
+/// Trait to convert any stream into [`StagingStream`].
+pub trait IntoStagingStream {
+    /// Convert into [`StagingStream`] using ring with `slots` TLS record sized buffers.
+    fn into_staging_stream(self, slots: usize) -> StagingStream<Self>
+    where
+        Self: Sized,
+    {
+        StagingStream::new(self, slots)
+    }
+}
+
+impl<T: Read + Write> IntoStagingStream for T {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct ThrottledStream {
+        accept: usize,
+        written: Vec<u8>,
+    }
+
+    impl Write for ThrottledStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.accept == 0 {
+                return Err(io::Error::from(WouldBlock));
+            }
+            let len = buf.len().min(self.accept);
+            self.accept -= len;
+            self.written.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_stage_what_the_stream_can_not_accept() {
+        let inner = ThrottledStream {
+            accept: 3,
+            written: vec![],
+        };
+        let mut stream = StagingStream::new(inner, 1);
+        assert_eq!(6, stream.write(b"foobar").unwrap());
+        assert_eq!(b"foo", stream.inner.written.as_slice());
+        assert_eq!(3, stream.ring().pending());
+
+        stream.inner.accept = usize::MAX;
+        stream.flush().unwrap();
+        assert_eq!(b"foobar", stream.inner.written.as_slice());
+        assert_eq!(0, stream.ring().pending());
+    }
+
+    #[test]
+    fn should_wrap_around_and_block_when_full() {
+        let inner = ThrottledStream {
+            accept: 0,
+            written: vec![],
+        };
+        let mut stream = StagingStream::new(inner, 1);
+        let capacity = stream.ring().capacity();
+        let first = (0..capacity - 10).map(|i| i as u8).collect::<Vec<_>>();
+        let second = [0xAB; 30];
+
+        assert_eq!(capacity - 10, stream.write(&first).unwrap());
+        stream.inner.accept = 20;
+        stream.flush().unwrap();
+        // fills the 10 bytes at the end and wraps around into the 20 drained bytes
+        assert_eq!(30, stream.write(&second).unwrap());
+        assert_eq!(capacity, stream.ring().pending());
+        assert_eq!(WouldBlock, stream.write(b"x").unwrap_err().kind());
+
+        stream.inner.accept = usize::MAX;
+        stream.flush().unwrap();
+        assert_eq!([first.as_slice(), &second].concat(), stream.inner.written);
+    }
+}
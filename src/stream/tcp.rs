@@ -1,9 +1,10 @@
 //! Wrapper over `std::net::TcpStream`.
 
 use crate::service::select::Selectable;
-use crate::stream::{ConnectionInfo, ConnectionInfoProvider};
+use crate::stream::proxy::Tunnel;
+use crate::stream::{ConnectionInfo, ConnectionInfoProvider, PendingWrites};
 use std::io;
-use std::io::{Read, Write};
+use std::io::{IoSlice, Read, Write};
 use std::net::SocketAddr;
 use std::os::fd::{AsRawFd, RawFd};
 
@@ -12,6 +13,11 @@ use std::os::fd::{AsRawFd, RawFd};
 pub struct TcpStream {
     inner: std::net::TcpStream,
     connection_info: ConnectionInfo,
+    received: bool,
+    bytes_read: u64,
+    bytes_written: u64,
+    read_paused: bool,
+    tunnel: Option<Tunnel>,
 }
 
 impl AsRawFd for TcpStream {
@@ -73,6 +79,19 @@ impl TcpStream {
         Self {
             inner: stream,
             connection_info,
+            received: false,
+            bytes_read: 0,
+            bytes_written: 0,
+            read_paused: false,
+            tunnel: None,
+        }
+    }
+
+    /// Open the tunnel through the proxy configured on the connection info (if any).
+    pub(crate) fn with_tunnel(self) -> Self {
+        Self {
+            tunnel: self.connection_info.tunnel(),
+            ..self
         }
     }
 
@@ -84,13 +103,41 @@ impl TcpStream {
 
 impl Read for TcpStream {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.inner.read(buf)
+        if self.read_paused {
+            return Err(io::Error::from(io::ErrorKind::WouldBlock));
+        }
+        if let Some(tunnel) = self.tunnel.as_mut() {
+            if !tunnel.drive(&mut self.inner)? {
+                return Err(io::Error::from(io::ErrorKind::WouldBlock));
+            }
+            let forwarded = tunnel.read_forwarded(buf);
+            if tunnel.is_done() {
+                self.tunnel = None;
+            }
+            if forwarded > 0 {
+                self.received = true;
+                self.bytes_read += forwarded as u64;
+                return Ok(forwarded);
+            }
+        }
+        let read = self.inner.read(buf)?;
+        self.received |= read > 0;
+        self.bytes_read += read as u64;
+        Ok(read)
     }
 }
 
 impl Write for TcpStream {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.inner.write(buf)
+        let written = self.write_through(buf)?;
+        self.bytes_written += written as u64;
+        Ok(written)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let written = self.write_vectored_through(bufs)?;
+        self.bytes_written += written as u64;
+        Ok(written)
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
@@ -98,6 +145,28 @@ impl Write for TcpStream {
     }
 }
 
+impl TcpStream {
+    #[inline]
+    fn write_through(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(tunnel) = self.tunnel.as_mut() {
+            if !tunnel.drive(&mut self.inner)? || tunnel.has_pending_writes() {
+                return Ok(tunnel.buffer(buf));
+            }
+        }
+        self.inner.write(buf)
+    }
+
+    #[inline]
+    fn write_vectored_through(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        if let Some(tunnel) = self.tunnel.as_mut() {
+            if !tunnel.drive(&mut self.inner)? || tunnel.has_pending_writes() {
+                return Ok(bufs.iter().map(|buf| tunnel.buffer(buf)).sum());
+            }
+        }
+        self.inner.write_vectored(bufs)
+    }
+}
+
 impl Selectable for TcpStream {
     fn connected(&mut self) -> io::Result<bool> {
         Ok(true)
@@ -110,6 +179,36 @@ impl Selectable for TcpStream {
     fn make_readable(&mut self) -> io::Result<()> {
         Ok(())
     }
+
+    fn take_read_activity(&mut self) -> bool {
+        std::mem::take(&mut self.received)
+    }
+
+    fn take_bytes_read(&mut self) -> u64 {
+        std::mem::take(&mut self.bytes_read)
+    }
+
+    fn take_bytes_written(&mut self) -> u64 {
+        std::mem::take(&mut self.bytes_written)
+    }
+
+    fn set_read_paused(&mut self, paused: bool) {
+        self.read_paused = paused;
+    }
+}
+
+impl PendingWrites for TcpStream {
+    fn has_pending_writes(&self) -> bool {
+        self.tunnel.as_ref().is_some_and(Tunnel::has_pending_writes)
+    }
+
+    /// Writes buffered until the proxy tunnel is established are flushed as the tunnel is driven.
+    fn drive_writes(&mut self) -> io::Result<bool> {
+        match self.tunnel.as_mut() {
+            Some(tunnel) => Ok(tunnel.drive(&mut self.inner)? && !tunnel.has_pending_writes()),
+            None => Ok(true),
+        }
+    }
 }
 
 impl ConnectionInfoProvider for TcpStream {
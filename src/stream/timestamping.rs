@@ -2,12 +2,13 @@
 #![cfg(target_os = "linux")]
 
 use crate::service::select::Selectable;
-use crate::stream::{ConnectionInfo, ConnectionInfoProvider, RxTimestamped, RxTimestamps};
+use crate::stream::{ConnectionInfo, ConnectionInfoProvider, PendingWrites, RxTimestamped, RxTimestamps};
+use crate::syscalls;
 #[cfg(feature = "mio")]
 use mio::event::Source;
 #[cfg(feature = "mio")]
 use mio::{Interest, Registry, Token};
-use std::io::{self, Read, Write};
+use std::io::{self, IoSlice, Read, Write};
 use std::mem;
 use std::os::fd::{AsRawFd, RawFd};
 use std::ptr;
@@ -55,12 +56,14 @@ fn last_err() -> io::Error {
 }
 
 // --- CMSG helpers ---
+// NOTE: `msg_controllen` and `cmsg_len` are not `usize` on every libc target (e.g. musl).
 #[inline]
 fn cmsg_align(len: usize) -> usize {
     let a = mem::size_of::<libc::c_long>();
     (len + a - 1) & !(a - 1)
 }
 
+#[allow(clippy::unnecessary_cast)]
 unsafe fn cmsg_firsthdr(msg: *const libc::msghdr) -> *mut libc::cmsghdr {
     unsafe {
         if (*msg).msg_controllen as usize >= mem::size_of::<libc::cmsghdr>() {
@@ -71,6 +74,7 @@ unsafe fn cmsg_firsthdr(msg: *const libc::msghdr) -> *mut libc::cmsghdr {
     }
 }
 
+#[allow(clippy::unnecessary_cast)]
 unsafe fn cmsg_nxthdr(msg: *const libc::msghdr, cmsg: *const libc::cmsghdr) -> *mut libc::cmsghdr {
     unsafe {
         if cmsg.is_null() {
@@ -98,8 +102,9 @@ unsafe fn cmsg_data(cmsg: *const libc::cmsghdr) -> *const u8 {
     unsafe { (cmsg as *const u8).add(cmsg_align(mem::size_of::<libc::cmsghdr>())) }
 }
 
-/// Enable RX timestamping on an already-created socket.
+/// Enable RX timestamping on an already-created socket. Not allowed in restricted mode.
 pub fn enable_rx_timestamping(fd: RawFd) -> io::Result<()> {
+    syscalls::ensure_unrestricted("setsockopt(SO_TIMESTAMPING)")?;
     let flags: libc::c_int = SOF_TIMESTAMPING_RX_HARDWARE
         | SOF_TIMESTAMPING_RAW_HARDWARE;
 
@@ -113,14 +118,16 @@ pub fn enable_rx_timestamping(fd: RawFd) -> io::Result<()> {
         )
     };
     if rc < 0 {
-        Err(last_err())
+        Err(syscalls::blocked("setsockopt(SO_TIMESTAMPING)", last_err()))
     } else {
         Ok(())
     }
 }
 
-/// Try to enable hardware RX timestamping at the driver level for a given interface.
+/// Try to enable hardware RX timestamping at the driver level for a given interface. Requires
+/// `CAP_NET_ADMIN` and is not allowed in restricted mode.
 pub fn configure_hwtstamp(fd: RawFd, iface: &str) -> io::Result<()> {
+    syscalls::ensure_unrestricted("ioctl(SIOCSHWTSTAMP)")?;
     if iface.is_empty() || iface.len() >= libc::IFNAMSIZ {
         return Err(io::Error::new(io::ErrorKind::InvalidInput, "bad iface name"));
     }
@@ -140,7 +147,7 @@ pub fn configure_hwtstamp(fd: RawFd, iface: &str) -> io::Result<()> {
         ifr.ifr_ifru.ifru_data = (&mut cfg as *mut HwtstampConfig).cast::<libc::c_char>();
         let rc = libc::ioctl(fd, SIOCSHWTSTAMP, &mut ifr);
         if rc < 0 {
-            return Err(last_err());
+            return Err(syscalls::blocked("ioctl(SIOCSHWTSTAMP)", last_err()));
         }
     }
     Ok(())
@@ -246,11 +253,25 @@ impl<S: Write> Write for TimestampingStream<S> {
         self.inner.write(buf)
     }
 
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.inner.write_vectored(bufs)
+    }
+
     fn flush(&mut self) -> io::Result<()> {
         self.inner.flush()
     }
 }
 
+impl<S: PendingWrites> PendingWrites for TimestampingStream<S> {
+    fn has_pending_writes(&self) -> bool {
+        self.inner.has_pending_writes()
+    }
+
+    fn drive_writes(&mut self) -> io::Result<bool> {
+        self.inner.drive_writes()
+    }
+}
+
 impl<S: ConnectionInfoProvider> ConnectionInfoProvider for TimestampingStream<S> {
     fn connection_info(&self) -> &ConnectionInfo {
         self.inner.connection_info()
@@ -269,6 +290,22 @@ impl<S: Selectable> Selectable for TimestampingStream<S> {
     fn make_readable(&mut self) -> io::Result<()> {
         self.inner.make_readable()
     }
+
+    fn take_read_activity(&mut self) -> bool {
+        self.inner.take_read_activity()
+    }
+
+    fn take_bytes_read(&mut self) -> u64 {
+        self.inner.take_bytes_read()
+    }
+
+    fn take_bytes_written(&mut self) -> u64 {
+        self.inner.take_bytes_written()
+    }
+
+    fn set_read_paused(&mut self, paused: bool) {
+        self.inner.set_read_paused(paused)
+    }
 }
 
 #[cfg(feature = "mio")]
@@ -1,7 +1,15 @@
 //! Provides TLS stream implementation for different backends.
+//!
+//! ## Plaintext path
+//! Protocol decoders (e.g. websocket) read from [`TlsStream`] straight into the spare capacity of their
+//! own [`ReadBuffer`](crate::buffer::ReadBuffer). The `rustls` backend decrypts the records in its own
+//! buffer and copies the plaintext twice (into an owned chunk and then into the decoder buffer), with
+//! `TlsConfigExt::with_inline_records` an established TLS 1.3 session is instead handed over to an
+//! inline record layer that decrypts each record in place and copies the plaintext once, directly into
+//! the decoder buffer.
 
 use crate::service::select::Selectable;
-use crate::stream::{ConnectionInfo, ConnectionInfoProvider, RxTimestamped};
+use crate::stream::{ConnectionInfo, ConnectionInfoProvider, PendingWrites, RxTimestamped};
 #[cfg(feature = "openssl")]
 pub use __openssl::TlsStream;
 #[cfg(all(feature = "rustls", not(feature = "openssl")))]
@@ -12,9 +20,15 @@ use mio::{Interest, Registry, Token, event::Source};
 use openssl::ssl::{SslConnectorBuilder, SslVerifyMode};
 #[cfg(all(feature = "rustls", not(feature = "openssl")))]
 use rustls::ClientConfig;
-use std::fmt::Debug;
+use std::fmt::{Debug, Display, Formatter};
 use std::io;
-use std::io::{Read, Write};
+use std::io::ErrorKind::{WouldBlock, WriteZero};
+use std::io::{IoSlice, Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+/// Largest plaintext carried by a single TLS record.
+pub const MAX_RECORD_SIZE: usize = 1 << 14;
 
 /// Used to configure TLS backend.
 pub struct TlsConfig {
@@ -22,19 +36,29 @@ pub struct TlsConfig {
     rustls_config: ClientConfig,
     #[cfg(feature = "openssl")]
     openssl_config: SslConnectorBuilder,
+    record_sizing: RecordSizing,
+    #[cfg(all(feature = "rustls", not(feature = "openssl")))]
+    inline_records: bool,
 }
 
 #[cfg(feature = "openssl")]
 impl From<SslConnectorBuilder> for TlsConfig {
     fn from(config: SslConnectorBuilder) -> Self {
-        Self { openssl_config: config }
+        Self {
+            openssl_config: config,
+            record_sizing: RecordSizing::default(),
+        }
     }
 }
 
 #[cfg(all(feature = "rustls", not(feature = "openssl")))]
 impl From<ClientConfig> for TlsConfig {
     fn from(config: ClientConfig) -> Self {
-        Self { rustls_config: config }
+        Self {
+            rustls_config: config,
+            record_sizing: RecordSizing::default(),
+            inline_records: false,
+        }
     }
 }
 
@@ -57,6 +81,16 @@ pub trait TlsConfigExt {
     ///
     /// NOTE: cargo leaks these env vars when running the binary under it.
     fn with_default_cert_paths(&mut self);
+
+    /// Control the size of the TLS records written by [`TlsStream`], see [`RecordSizing`].
+    fn with_record_sizing(&mut self, record_sizing: RecordSizing);
+
+    #[cfg(all(feature = "rustls", not(feature = "openssl")))]
+    /// Once a TLS 1.3 handshake has completed, take the session keys over from `rustls` and decrypt the
+    /// records in place in the stream's own buffer, so that the plaintext is copied only once (into the
+    /// buffer passed to `read`). Key updates are followed, session tickets are ignored. Has no effect if
+    /// TLS 1.2 is negotiated.
+    fn with_inline_records(&mut self);
 }
 
 impl TlsConfig {
@@ -101,6 +135,15 @@ impl TlsConfigExt for TlsConfig {
         self.openssl_config.set_verify(SslVerifyMode::NONE);
     }
 
+    fn with_record_sizing(&mut self, record_sizing: RecordSizing) {
+        self.record_sizing = record_sizing;
+    }
+
+    #[cfg(all(feature = "rustls", not(feature = "openssl")))]
+    fn with_inline_records(&mut self) {
+        self.inline_records = true;
+    }
+
     #[cfg(feature = "openssl")]
     fn with_default_cert_paths(&mut self) {
         use log::warn;
@@ -132,11 +175,950 @@ impl TlsConfigExt for TlsConfig {
     }
 }
 
+/// Size of the TLS records written by [`TlsStream`] (not applied by `ktls`).
+///
+/// By default every write is encrypted straight away into records of up to [`MAX_RECORD_SIZE`] bytes.
+/// Busy outbound paths that send many small messages can instead coalesce the plaintext and trade a
+/// tiny batching delay for fewer records (and syscalls): the writes are then held back until either
+/// `max_bytes` have accumulated or the oldest of them has waited for `window`. The window is checked on
+/// every write, flush and read, [`PendingWrites::drive_writes`] releases the held back plaintext
+/// regardless of the window.
+///
+/// ## Examples
+/// ```no_run
+/// use std::time::Duration;
+/// use boomnet::stream::tcp::TcpStream;
+/// use boomnet::stream::tls::{IntoTlsStream, RecordSizing, TlsConfigExt};
+///
+/// let tls = TcpStream::try_from(("127.0.0.1", 4222)).unwrap().into_tls_stream_with_config(|config| {
+///     config.with_record_sizing(
+///         RecordSizing::new()
+///             .with_max_record_size(4096)
+///             .with_coalescing(1024, Duration::from_micros(20)),
+///     );
+/// });
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecordSizing {
+    max_record_size: Option<usize>,
+    coalescing: Option<(usize, Duration)>,
+}
+
+impl RecordSizing {
+    /// Records of up to [`MAX_RECORD_SIZE`] bytes, written immediately.
+    pub const fn new() -> RecordSizing {
+        Self {
+            max_record_size: None,
+            coalescing: None,
+        }
+    }
+
+    /// Maximum plaintext size of a single record, capped at [`MAX_RECORD_SIZE`].
+    pub const fn with_max_record_size(self, max_record_size: usize) -> RecordSizing {
+        Self {
+            max_record_size: Some(max_record_size),
+            ..self
+        }
+    }
+
+    /// Hold back the writes until `max_bytes` have accumulated or the oldest of them has waited for `window`.
+    pub const fn with_coalescing(self, max_bytes: usize, window: Duration) -> RecordSizing {
+        Self {
+            coalescing: Some((max_bytes, window)),
+            ..self
+        }
+    }
+
+    /// Maximum plaintext size of a single record.
+    #[inline]
+    pub fn max_record_size(&self) -> usize {
+        self.max_record_size
+            .unwrap_or(MAX_RECORD_SIZE)
+            .clamp(1, MAX_RECORD_SIZE)
+    }
+}
+
+/// Applies [`RecordSizing`] to the plaintext written to the TLS backend.
+#[derive(Debug)]
+pub(crate) struct Coalescer {
+    sizing: RecordSizing,
+    buffer: Vec<u8>,
+    // time of the oldest held back write
+    since: Option<Instant>,
+}
+
+impl Coalescer {
+    pub(crate) fn new(sizing: RecordSizing) -> Self {
+        let capacity = sizing
+            .coalescing
+            .map_or(0, |(max_bytes, _)| max_bytes + sizing.max_record_size());
+        Self {
+            sizing,
+            buffer: Vec::with_capacity(capacity),
+            since: None,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn max_record_size(&self) -> usize {
+        self.sizing.max_record_size()
+    }
+
+    #[inline]
+    pub(crate) fn has_pending_writes(&self) -> bool {
+        !self.buffer.is_empty()
+    }
+
+    pub(crate) fn write<W: Write>(&mut self, tls: &mut W, buf: &[u8]) -> io::Result<usize> {
+        if self.sizing.coalescing.is_none() {
+            return tls.write(&buf[..buf.len().min(self.max_record_size())]);
+        }
+        self.hold(buf);
+        self.release_if_due(tls)?;
+        Ok(buf.len())
+    }
+
+    pub(crate) fn write_vectored<W: Write>(&mut self, tls: &mut W, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        if self.sizing.coalescing.is_none() {
+            if self.sizing.max_record_size.is_none() {
+                return tls.write_vectored(bufs);
+            }
+            let buf = bufs.iter().find(|buf| !buf.is_empty()).map_or(&[][..], |buf| &**buf);
+            return self.write(tls, buf);
+        }
+        bufs.iter().for_each(|buf| self.hold(buf));
+        self.release_if_due(tls)?;
+        Ok(bufs.iter().map(|buf| buf.len()).sum())
+    }
+
+    /// Flush the backend once the held back writes have been released.
+    pub(crate) fn flush<W: Write>(&mut self, tls: &mut W) -> io::Result<()> {
+        self.release_if_due(tls)?;
+        match self.buffer.is_empty() {
+            true => tls.flush(),
+            false => Ok(()),
+        }
+    }
+
+    fn hold(&mut self, buf: &[u8]) {
+        if self.buffer.is_empty() && !buf.is_empty() {
+            self.since = Some(Instant::now());
+        }
+        self.buffer.extend_from_slice(buf);
+    }
+
+    /// Release the held back writes if enough of them have accumulated or the window has passed.
+    pub(crate) fn release_if_due<W: Write>(&mut self, tls: &mut W) -> io::Result<()> {
+        let due = match (self.sizing.coalescing, self.since) {
+            (Some((max_bytes, window)), Some(since)) => self.buffer.len() >= max_bytes || since.elapsed() >= window,
+            _ => false,
+        };
+        if due { self.release(tls) } else { Ok(()) }
+    }
+
+    /// Write all held back plaintext to the backend, one record at a time.
+    pub(crate) fn release<W: Write>(&mut self, tls: &mut W) -> io::Result<()> {
+        let max_record_size = self.max_record_size();
+        let mut written = 0;
+        let result = loop {
+            if written == self.buffer.len() {
+                break Ok(());
+            }
+            let end = self.buffer.len().min(written + max_record_size);
+            match tls.write(&self.buffer[written..end]) {
+                Ok(0) => break Err(io::Error::from(WriteZero)),
+                Ok(n) => written += n,
+                Err(err) if err.kind() == WouldBlock => break Ok(()),
+                Err(err) => break Err(err),
+            }
+        };
+        self.buffer.drain(..written);
+        if self.buffer.is_empty() {
+            self.since = None;
+        }
+        result
+    }
+}
+
+/// TLS parameters negotiated during the handshake. Names are normalised across backends so the same
+/// expectation (e.g. `TLSv1.3` with `TLS_AES_128_GCM_SHA256`) can be checked regardless of the backend.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TlsParameters {
+    /// Protocol version, e.g. `TLSv1.3`.
+    pub version: Option<&'static str>,
+    /// IANA name of the cipher suite, e.g. `TLS_AES_128_GCM_SHA256`.
+    pub cipher: Option<&'static str>,
+    /// Key exchange group, e.g. `X25519`. Only reported by the `ktls` backend.
+    pub kx_group: Option<&'static str>,
+}
+
+impl TlsParameters {
+    /// Checks if TLS 1.3 has been negotiated.
+    pub fn is_tls13(&self) -> bool {
+        self.version == Some("TLSv1.3")
+    }
+}
+
+/// Renders `version/cipher/kx_group` (with `unknown` for missing values), suitable as a metric label.
+impl Display for TlsParameters {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/{}/{}",
+            self.version.unwrap_or("unknown"),
+            self.cipher.unwrap_or("unknown"),
+            self.kx_group.unwrap_or("unknown")
+        )
+    }
+}
+
+/// Streams that can expose the TLS parameters negotiated for the current connection.
+pub trait TlsParametersProvider {
+    /// Returns `None` until the handshake has completed or if the stream is not encrypted.
+    fn tls_parameters(&self) -> Option<TlsParameters>;
+}
+
+#[cfg(feature = "openssl")]
+pub(crate) fn openssl_parameters(ssl: &openssl::ssl::SslRef) -> TlsParameters {
+    let cipher = ssl
+        .current_cipher()
+        .map(|cipher| cipher.standard_name().unwrap_or(cipher.name()));
+
+    #[cfg(not(feature = "ktls"))]
+    let kx_group = None;
+
+    #[cfg(feature = "ktls")]
+    let kx_group = {
+        use foreign_types::ForeignTypeRef;
+        const SSL_CTRL_GET_NEGOTIATED_GROUP: std::ffi::c_int = 134;
+        // SAFETY: SSL_get_negotiated_group is a macro over SSL_ctrl that does not use `parg`
+        let nid =
+            unsafe { openssl_sys::SSL_ctrl(ssl.as_ptr(), SSL_CTRL_GET_NEGOTIATED_GROUP, 0, std::ptr::null_mut()) };
+        openssl::nid::Nid::from_raw(nid as std::ffi::c_int).short_name().ok()
+    };
+
+    TlsParameters {
+        version: Some(ssl.version_str()),
+        cipher,
+        kx_group,
+    }
+}
+
+/// Opt-in writing of TLS session secrets in the NSS key log format so that captures can be decrypted
+/// (e.g. by Wireshark). Requires the `keylog` feature and the `SSLKEYLOGFILE` environment variable
+/// to be set. Never enable in production as anyone with access to the file can decrypt the traffic.
+#[cfg(feature = "keylog")]
+mod key_log {
+    use log::{error, warn};
+    use std::fs::{File, OpenOptions};
+    use std::io::Write;
+    use std::sync::{Mutex, OnceLock};
+
+    const ENV_VAR: &str = "SSLKEYLOGFILE";
+
+    static KEY_LOG_FILE: OnceLock<Option<Mutex<File>>> = OnceLock::new();
+
+    fn key_log_file() -> Option<&'static Mutex<File>> {
+        KEY_LOG_FILE
+            .get_or_init(|| {
+                let path = std::env::var_os(ENV_VAR)?;
+                match OpenOptions::new().create(true).append(true).open(&path) {
+                    Ok(file) => {
+                        warn!(
+                            "!!! {ENV_VAR} is set, TLS session secrets will be written to {} - \
+                             anyone with access to this file can decrypt the traffic, never use it in production !!!",
+                            path.to_string_lossy()
+                        );
+                        Some(Mutex::new(file))
+                    }
+                    Err(err) => {
+                        error!("unable to open {ENV_VAR} file {}: {err}", path.to_string_lossy());
+                        None
+                    }
+                }
+            })
+            .as_ref()
+    }
+
+    /// Returns `true` if the key log file is configured and could be opened.
+    pub(crate) fn enabled() -> bool {
+        key_log_file().is_some()
+    }
+
+    /// Append single line to the key log file.
+    pub(crate) fn write_line(line: &str) {
+        if let Some(file) = key_log_file() {
+            let mut file = file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if let Err(err) = writeln!(file, "{line}") {
+                error!("unable to write to {ENV_VAR} file: {err}");
+            }
+        }
+    }
+
+    #[cfg(all(feature = "rustls", not(feature = "openssl")))]
+    #[derive(Debug)]
+    pub(crate) struct RustlsKeyLog;
+
+    #[cfg(all(feature = "rustls", not(feature = "openssl")))]
+    impl rustls::KeyLog for RustlsKeyLog {
+        fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+            use std::fmt::Write;
+
+            let mut line = String::with_capacity(label.len() + 2 * (client_random.len() + secret.len()) + 2);
+            line.push_str(label);
+            line.push(' ');
+            client_random.iter().for_each(|b| write!(line, "{b:02x}").unwrap());
+            line.push(' ');
+            secret.iter().for_each(|b| write!(line, "{b:02x}").unwrap());
+            write_line(&line);
+        }
+    }
+}
+
+/// Inline TLS 1.3 record layer of the `rustls` backend, see `TlsConfigExt::with_inline_records`.
+///
+/// During the handshake the ciphertext is passed to `rustls` one record at a time, so that it stops
+/// right after the server `Finished` and the application records are left in our own buffer. The
+/// session keys are then extracted and the records are decrypted in place (RFC 8446 section 5).
+#[cfg(all(feature = "rustls", not(feature = "openssl")))]
+mod inline {
+    use crate::stream::tls::{MAX_RECORD_SIZE, TlsParameters};
+    use crate::util::NoBlock;
+    use ring::aead::{AES_128_GCM, AES_256_GCM, Aad, Algorithm, CHACHA20_POLY1305, LessSafeKey, NONCE_LEN, Nonce};
+    use ring::aead::{MAX_TAG_LEN, UnboundKey};
+    use ring::hkdf;
+    use ring::hkdf::KeyType;
+    use rustls::{CipherSuite, ClientConfig, ClientConnection, ConnectionTrafficSecrets, KeyLog, ProtocolVersion};
+    use std::io;
+    use std::io::ErrorKind::{InvalidData, WouldBlock};
+    use std::io::{Read, Write};
+    use std::ops::Range;
+    use std::sync::{Arc, Mutex};
+
+    const HEADER_LEN: usize = 5;
+    const MAX_CIPHERTEXT_LEN: usize = MAX_RECORD_SIZE + 256;
+    const MAX_RECORD_LEN: usize = HEADER_LEN + MAX_CIPHERTEXT_LEN;
+    const BUFFER_SIZE: usize = 4 * MAX_RECORD_LEN;
+
+    const ALERT: u8 = 21;
+    const HANDSHAKE: u8 = 22;
+    const APPLICATION_DATA: u8 = 23;
+
+    const NEW_SESSION_TICKET: u8 = 4;
+    const KEY_UPDATE: u8 = 24;
+    const CLOSE_NOTIFY: u8 = 0;
+
+    /// Records encrypted under the same key before it is updated, well within the AES-GCM limit
+    /// (RFC 8446 section 5.5).
+    const KEY_UPDATE_RECORDS: u64 = 1 << 24;
+
+    const CLIENT_TRAFFIC_SECRET: &str = "CLIENT_TRAFFIC_SECRET_0";
+    const SERVER_TRAFFIC_SECRET: &str = "SERVER_TRAFFIC_SECRET_0";
+
+    /// Ciphertext read from the stream, consumed one record at a time.
+    struct Incoming {
+        buf: Box<[u8]>,
+        start: usize,
+        end: usize,
+    }
+
+    impl Incoming {
+        fn new() -> Self {
+            Self {
+                buf: vec![0; BUFFER_SIZE].into_boxed_slice(),
+                start: 0,
+                end: 0,
+            }
+        }
+
+        /// Length (including the header) of the complete record at the front of the buffer, if any.
+        fn next_record(&self) -> io::Result<Option<usize>> {
+            let available = &self.buf[self.start..self.end];
+            if available.len() < HEADER_LEN {
+                return Ok(None);
+            }
+            let len = u16::from_be_bytes([available[3], available[4]]) as usize;
+            if len > MAX_CIPHERTEXT_LEN {
+                return Err(io::Error::new(InvalidData, format!("tls record too long: {len}")));
+            }
+            Ok((available.len() >= HEADER_LEN + len).then_some(HEADER_LEN + len))
+        }
+
+        /// Read more ciphertext from the stream, making sure that a whole record fits into the buffer.
+        fn fill<S: Read>(&mut self, stream: &mut S) -> io::Result<usize> {
+            if self.start == self.end {
+                self.start = 0;
+                self.end = 0;
+            } else if self.buf.len() - self.start < MAX_RECORD_LEN {
+                self.buf.copy_within(self.start..self.end, 0);
+                self.end -= self.start;
+                self.start = 0;
+            }
+            let read = stream.read(&mut self.buf[self.end..])?;
+            self.end += read;
+            Ok(read)
+        }
+    }
+
+    /// Keeps track of the handshake until the session can be handed over to the [`RecordLayer`].
+    pub(super) struct Handover {
+        incoming: Incoming,
+        secrets: Arc<TrafficSecrets>,
+    }
+
+    impl Handover {
+        /// Enables secret extraction for the connection to be created with `config`.
+        pub(super) fn new(config: &mut ClientConfig) -> Self {
+            let secrets = Arc::new(TrafficSecrets {
+                key_log: config.key_log.clone(),
+                secrets: Mutex::default(),
+            });
+            config.enable_secret_extraction = true;
+            config.key_log = secrets.clone();
+            Self {
+                incoming: Incoming::new(),
+                secrets,
+            }
+        }
+
+        /// Pass the next record to `rustls` while handshaking, the application records of a TLS 1.3
+        /// session are held back until [`Handover::into_record_layer`]. Once TLS 1.2 has been negotiated
+        /// the buffered ciphertext is passed as it is, followed by the stream.
+        pub(super) fn read_tls<S: Read>(&mut self, tls: &mut ClientConnection, stream: &mut S) -> io::Result<usize> {
+            let incoming = &mut self.incoming;
+            if !tls.is_handshaking() {
+                if tls.protocol_version() == Some(ProtocolVersion::TLSv1_3) {
+                    return Err(io::Error::from(WouldBlock));
+                }
+                if incoming.start == incoming.end {
+                    return tls.read_tls(stream);
+                }
+                let read = tls.read_tls(&mut &incoming.buf[incoming.start..incoming.end])?;
+                incoming.start += read;
+                return Ok(read);
+            }
+            let len = match incoming.next_record()? {
+                Some(len) => len,
+                None => {
+                    if incoming.fill(stream)? == 0 {
+                        // let rustls record the EOF
+                        return tls.read_tls(&mut &[][..]);
+                    }
+                    incoming.next_record()?.ok_or(io::Error::from(WouldBlock))?
+                }
+            };
+            let mut record = &incoming.buf[incoming.start..incoming.start + len];
+            while !record.is_empty() {
+                tls.read_tls(&mut record)?;
+            }
+            incoming.start += len;
+            Ok(len)
+        }
+
+        /// Returns `true` once a TLS 1.3 handshake has completed and all its records have been written.
+        pub(super) fn is_due(&self, tls: &ClientConnection) -> bool {
+            !tls.is_handshaking() && !tls.wants_write() && tls.protocol_version() == Some(ProtocolVersion::TLSv1_3)
+        }
+
+        pub(super) fn into_record_layer(
+            self,
+            tls: ClientConnection,
+            parameters: TlsParameters,
+        ) -> io::Result<RecordLayer> {
+            let hkdf = match tls.negotiated_cipher_suite().map(|suite| suite.suite()) {
+                Some(CipherSuite::TLS13_AES_256_GCM_SHA384) => hkdf::HKDF_SHA384,
+                _ => hkdf::HKDF_SHA256,
+            };
+            let [client_secret, server_secret] = std::mem::take(&mut *self.secrets.lock());
+            let secrets = tls.dangerous_extract_secrets().map_err(io::Error::other)?;
+            let (rx_seq, rx) = secrets.rx;
+            let (tx_seq, tx) = secrets.tx;
+            Ok(RecordLayer {
+                incoming: self.incoming,
+                plaintext: 0..0,
+                rx: Direction::from_secrets(rx, rx_seq, server_secret)?,
+                tx: Direction::from_secrets(tx, tx_seq, client_secret)?,
+                hkdf,
+                outgoing: Vec::with_capacity(MAX_RECORD_LEN),
+                written: 0,
+                closed: false,
+                parameters,
+            })
+        }
+    }
+
+    /// Captures the application traffic secrets (needed to follow key updates) and passes everything on
+    /// to the configured key log.
+    #[derive(Debug)]
+    struct TrafficSecrets {
+        key_log: Arc<dyn KeyLog>,
+        secrets: Mutex<[Vec<u8>; 2]>,
+    }
+
+    impl TrafficSecrets {
+        fn lock(&self) -> std::sync::MutexGuard<'_, [Vec<u8>; 2]> {
+            self.secrets.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+        }
+    }
+
+    impl KeyLog for TrafficSecrets {
+        fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+            match label {
+                CLIENT_TRAFFIC_SECRET => self.lock()[0] = secret.to_vec(),
+                SERVER_TRAFFIC_SECRET => self.lock()[1] = secret.to_vec(),
+                _ => {}
+            }
+            if self.key_log.will_log(label) {
+                self.key_log.log(label, client_random, secret);
+            }
+        }
+
+        fn will_log(&self, label: &str) -> bool {
+            label == CLIENT_TRAFFIC_SECRET || label == SERVER_TRAFFIC_SECRET || self.key_log.will_log(label)
+        }
+    }
+
+    /// Keys of a single direction.
+    struct Direction {
+        key: LessSafeKey,
+        iv: [u8; NONCE_LEN],
+        seq: u64,
+        // current application traffic secret, the next one is derived from it on key update
+        secret: Vec<u8>,
+    }
+
+    impl Direction {
+        fn new(algorithm: &'static Algorithm, key: &[u8], iv: &[u8], seq: u64, secret: Vec<u8>) -> io::Result<Self> {
+            let key = UnboundKey::new(algorithm, key).map_err(|_| io::Error::other("invalid tls traffic key"))?;
+            Ok(Self {
+                key: LessSafeKey::new(key),
+                iv: iv.try_into().map_err(|_| io::Error::other("invalid tls traffic iv"))?,
+                seq,
+                secret,
+            })
+        }
+
+        fn from_secrets(secrets: ConnectionTrafficSecrets, seq: u64, secret: Vec<u8>) -> io::Result<Self> {
+            match secrets {
+                ConnectionTrafficSecrets::Aes128Gcm { key, iv } => {
+                    Self::new(&AES_128_GCM, key.as_ref(), iv.as_ref(), seq, secret)
+                }
+                ConnectionTrafficSecrets::Aes256Gcm { key, iv } => {
+                    Self::new(&AES_256_GCM, key.as_ref(), iv.as_ref(), seq, secret)
+                }
+                ConnectionTrafficSecrets::Chacha20Poly1305 { key, iv } => {
+                    Self::new(&CHACHA20_POLY1305, key.as_ref(), iv.as_ref(), seq, secret)
+                }
+                _ => Err(io::Error::other("unsupported tls cipher suite")),
+            }
+        }
+
+        /// Per record nonce, the sequence number XORed with the IV.
+        fn next_nonce(&mut self) -> Nonce {
+            let mut nonce = self.iv;
+            nonce[NONCE_LEN - 8..]
+                .iter_mut()
+                .zip(self.seq.to_be_bytes())
+                .for_each(|(nonce, seq)| *nonce ^= seq);
+            self.seq += 1;
+            Nonce::assume_unique_for_key(nonce)
+        }
+
+        /// Move to the next application traffic secret (RFC 8446 section 7.2).
+        fn update(&mut self, hkdf: hkdf::Algorithm) -> io::Result<()> {
+            if self.secret.is_empty() {
+                return Err(io::Error::other("tls traffic secret not captured, unable to update keys"));
+            }
+            let algorithm = self.key.algorithm();
+            let secret = expand_label(hkdf, &self.secret, b"traffic upd", hkdf.len());
+            let key = expand_label(hkdf, &secret, b"key", algorithm.key_len());
+            let iv = expand_label(hkdf, &secret, b"iv", NONCE_LEN);
+            *self = Self::new(algorithm, &key, &iv, 0, secret)?;
+            Ok(())
+        }
+    }
+
+    /// `HKDF-Expand-Label` with an empty context (RFC 8446 section 7.1).
+    fn expand_label(hkdf: hkdf::Algorithm, secret: &[u8], label: &[u8], len: usize) -> Vec<u8> {
+        struct Len(usize);
+
+        impl KeyType for Len {
+            fn len(&self) -> usize {
+                self.0
+            }
+        }
+
+        let length = (len as u16).to_be_bytes();
+        let label_len = [(b"tls13 ".len() + label.len()) as u8];
+        let info: [&[u8]; 5] = [&length, &label_len, b"tls13 ", label, &[0]];
+        let mut out = vec![0; len];
+        hkdf::Prk::new_less_safe(hkdf, secret)
+            .expand(&info, Len(len))
+            .and_then(|okm| okm.fill(&mut out))
+            .expect("output length within the hkdf limit");
+        out
+    }
+
+    /// TLS 1.3 record layer of an established session.
+    pub(super) struct RecordLayer {
+        incoming: Incoming,
+        // decrypted plaintext of the current record that has not been read yet
+        plaintext: Range<usize>,
+        rx: Direction,
+        tx: Direction,
+        hkdf: hkdf::Algorithm,
+        // sealed records, written up to `written`
+        outgoing: Vec<u8>,
+        written: usize,
+        closed: bool,
+        parameters: TlsParameters,
+    }
+
+    impl RecordLayer {
+        pub(super) const fn parameters(&self) -> TlsParameters {
+            self.parameters
+        }
+
+        /// Copy the plaintext of the next application record into `buf`, reading from the stream
+        /// (once) if no complete record is buffered.
+        pub(super) fn read<S: Read + Write>(&mut self, stream: &mut S, buf: &mut [u8]) -> io::Result<usize> {
+            let mut filled = false;
+            loop {
+                if !self.plaintext.is_empty() || buf.is_empty() {
+                    let len = self.plaintext.len().min(buf.len());
+                    buf[..len].copy_from_slice(&self.incoming.buf[self.plaintext.start..][..len]);
+                    self.plaintext.start += len;
+                    return Ok(len);
+                }
+                if self.closed {
+                    return Ok(0);
+                }
+                match self.incoming.next_record()? {
+                    Some(len) => self.open(stream, len)?,
+                    None if filled => return Err(io::Error::from(WouldBlock)),
+                    None => {
+                        // peer closed the connection without `close_notify`, reported as any other EOF
+                        if self.incoming.fill(stream)? == 0 {
+                            return Ok(0);
+                        }
+                        filled = true;
+                    }
+                }
+            }
+        }
+
+        /// Decrypt the record of `len` bytes at the front of the incoming buffer in place.
+        fn open<S: Write>(&mut self, stream: &mut S, len: usize) -> io::Result<()> {
+            let start = self.incoming.start;
+            self.incoming.start += len;
+            let (header, payload) = self.incoming.buf[start..start + len].split_at_mut(HEADER_LEN);
+            if header[0] != APPLICATION_DATA {
+                return Err(io::Error::new(InvalidData, format!("unexpected tls record type: {}", header[0])));
+            }
+            let nonce = self.rx.next_nonce();
+            let plaintext = self
+                .rx
+                .key
+                .open_in_place(nonce, Aad::from(&*header), payload)
+                .map_err(|_| io::Error::new(InvalidData, "unable to decrypt tls record"))?;
+            // the content type is the last non zero byte, followed by the padding
+            let end = plaintext
+                .iter()
+                .rposition(|&b| b != 0)
+                .ok_or_else(|| io::Error::new(InvalidData, "tls record without content type"))?;
+            let content = start + HEADER_LEN..start + HEADER_LEN + end;
+            match plaintext[end] {
+                APPLICATION_DATA => self.plaintext = content,
+                HANDSHAKE => self.on_handshake(stream, content)?,
+                ALERT => self.on_alert(content)?,
+                content_type => {
+                    return Err(io::Error::new(InvalidData, format!("unexpected tls content type: {content_type}")));
+                }
+            }
+            Ok(())
+        }
+
+        fn on_handshake<S: Write>(&mut self, stream: &mut S, content: Range<usize>) -> io::Result<()> {
+            let mut messages = &self.incoming.buf[content];
+            let mut key_update = None;
+            while !messages.is_empty() {
+                let (msg_type, len) = match messages {
+                    [msg_type, a, b, c, ..] => (*msg_type, u32::from_be_bytes([0, *a, *b, *c]) as usize),
+                    _ => return Err(io::Error::new(InvalidData, "fragmented tls handshake message")),
+                };
+                let body = messages
+                    .get(4..4 + len)
+                    .ok_or_else(|| io::Error::new(InvalidData, "fragmented tls handshake message"))?;
+                match (msg_type, body) {
+                    // session resumption is not used
+                    (NEW_SESSION_TICKET, _) => {}
+                    (KEY_UPDATE, [request_update]) => key_update = Some(*request_update == 1),
+                    _ => {
+                        return Err(io::Error::new(
+                            InvalidData,
+                            format!("unexpected tls handshake message: {msg_type}"),
+                        ));
+                    }
+                }
+                messages = &messages[4 + len..];
+            }
+            if let Some(update_requested) = key_update {
+                self.rx.update(self.hkdf)?;
+                if update_requested {
+                    // acknowledged with our own key update (update_not_requested)
+                    self.seal(HANDSHAKE, &[KEY_UPDATE, 0, 0, 1, 0])?;
+                    self.tx.update(self.hkdf)?;
+                    self.write_records(stream)?;
+                }
+            }
+            Ok(())
+        }
+
+        fn on_alert(&mut self, content: Range<usize>) -> io::Result<()> {
+            match self.incoming.buf[content] {
+                [_, CLOSE_NOTIFY] => {
+                    self.closed = true;
+                    Ok(())
+                }
+                [_, description] => Err(io::Error::other(format!("received tls alert: {description}"))),
+                _ => Err(io::Error::new(InvalidData, "invalid tls alert")),
+            }
+        }
+
+        /// Encrypt `content` as a single record.
+        fn seal(&mut self, content_type: u8, content: &[u8]) -> io::Result<()> {
+            let len = content.len() + 1 + MAX_TAG_LEN;
+            let header = [APPLICATION_DATA, 0x03, 0x03, (len >> 8) as u8, len as u8];
+            let start = self.outgoing.len() + HEADER_LEN;
+            self.outgoing.extend_from_slice(&header);
+            self.outgoing.extend_from_slice(content);
+            self.outgoing.push(content_type);
+            let nonce = self.tx.next_nonce();
+            let tag = self
+                .tx
+                .key
+                .seal_in_place_separate_tag(nonce, Aad::from(header), &mut self.outgoing[start..])
+                .map_err(|_| io::Error::other("unable to encrypt tls record"))?;
+            self.outgoing.extend_from_slice(tag.as_ref());
+            Ok(())
+        }
+
+        /// Encrypt up to [`MAX_RECORD_SIZE`] bytes as a single record and write it as far as the stream
+        /// does not block.
+        pub(super) fn write<S: Write>(&mut self, stream: &mut S, buf: &[u8]) -> io::Result<usize> {
+            let len = buf.len().min(MAX_RECORD_SIZE);
+            if len > 0 {
+                if self.tx.seq >= KEY_UPDATE_RECORDS {
+                    self.update_keys()?;
+                }
+                self.seal(APPLICATION_DATA, &buf[..len])?;
+                self.write_records(stream)?;
+            }
+            Ok(len)
+        }
+
+        /// Update our keys and ask the peer to update its own (RFC 8446 section 4.6.3).
+        fn update_keys(&mut self) -> io::Result<()> {
+            self.seal(HANDSHAKE, &[KEY_UPDATE, 0, 0, 1, 1])?;
+            self.tx.update(self.hkdf)
+        }
+
+        pub(super) fn has_pending_writes(&self) -> bool {
+            self.written < self.outgoing.len()
+        }
+
+        /// Write the sealed records to the stream, returns `false` if it would block.
+        pub(super) fn write_records<S: Write>(&mut self, stream: &mut S) -> io::Result<bool> {
+            while self.has_pending_writes() {
+                match stream.write(&self.outgoing[self.written..]).no_block()? {
+                    0 => return Ok(false),
+                    written => self.written += written,
+                }
+            }
+            self.outgoing.clear();
+            self.written = 0;
+            Ok(true)
+        }
+    }
+
+    /// Plaintext sink of the [`RecordLayer`].
+    pub(super) struct Sealer<'a, S> {
+        pub(super) records: &'a mut RecordLayer,
+        pub(super) stream: &'a mut S,
+    }
+
+    impl<S: Write> Write for Sealer<'_, S> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.records.write(self.stream, buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.records.write_records(self.stream).map(|_| ())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::stream::tls::{TlsConfigExt, TlsParametersProvider, TlsStream};
+        use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+        use rustls::version::{TLS12, TLS13};
+        use rustls::{RootCertStore, ServerConfig, ServerConnection, StreamOwned, SupportedProtocolVersion};
+        use std::os::unix::net::UnixStream;
+        use std::thread::JoinHandle;
+
+        // self signed ed25519 certificate for `localhost`
+        const CERT: &str = "\
+            3082015530820107a003020102021405c974e47c9208e8b148f124abf420c1e814b36d300506032b6570301431123010\
+            06035504030c096c6f63616c686f73743020170d3236313031363132313632315a180f32313236303932323132313632\
+            315a30143112301006035504030c096c6f63616c686f7374302a300506032b6570032100f125fb95f74c6502e12c8b2d\
+            16cef4e7ef2d68ba593775333394cf549dc16134a3693067301d0603551d0e04160414592f09fb963911e0cdf3524cac\
+            39b97f6644feac301f0603551d23041830168014592f09fb963911e0cdf3524cac39b97f6644feac300f0603551d1301\
+            01ff040530030101ff30140603551d11040d300b82096c6f63616c686f7374300506032b6570034100fb689d153de65f\
+            eaf88650fca474020652170e40d4b98ee5010d64a4177115895e05b89a5e70e16c09adab79f73f8b566e3c7986c81d98\
+            ccd72ea1ac22913604";
+        const KEY: &str =
+            "302e020100300506032b657004220420ed7a5793e0c5a7a65db085d5bb95bf75713c60e7437893d7d3a3e84dca82efcf";
+
+        const LEN: usize = 40_000;
+
+        fn unhex(hex: &str) -> Vec<u8> {
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+                .collect()
+        }
+
+        fn payload() -> Vec<u8> {
+            (0..LEN).map(|i| i as u8).collect()
+        }
+
+        /// Echoes `LEN` bytes and closes the session with `close_notify`.
+        fn echo_server(stream: UnixStream, versions: &[&'static SupportedProtocolVersion]) -> JoinHandle<()> {
+            let config = ServerConfig::builder_with_protocol_versions(versions)
+                .with_no_client_auth()
+                .with_single_cert(vec![CertificateDer::from(unhex(CERT))], PrivateKeyDer::Pkcs8(unhex(KEY).into()))
+                .unwrap();
+            std::thread::spawn(move || {
+                let tls = ServerConnection::new(Arc::new(config)).unwrap();
+                let mut tls = StreamOwned::new(tls, stream);
+                let mut buf = vec![0; LEN];
+                tls.read_exact(&mut buf).unwrap();
+                tls.write_all(&buf).unwrap();
+                tls.conn.send_close_notify();
+                tls.conn.complete_io(&mut tls.sock).unwrap();
+            })
+        }
+
+        fn echo<S: Read + Write>(stream: &mut S) -> Vec<u8> {
+            stream.write_all(&payload()).unwrap();
+            let mut received = Vec::new();
+            let mut buf = vec![0; 4096];
+            loop {
+                match stream.read(&mut buf) {
+                    Ok(0) => return received,
+                    Ok(read) => received.extend_from_slice(&buf[..read]),
+                    Err(err) if err.kind() == WouldBlock => {}
+                    Err(err) => panic!("{err}"),
+                }
+            }
+        }
+
+        fn tls_stream(stream: UnixStream) -> TlsStream<UnixStream> {
+            TlsStream::new_with_config(stream, "localhost", |config| {
+                config.with_no_cert_verification();
+                config.with_inline_records();
+            })
+            .unwrap()
+        }
+
+        #[test]
+        fn should_echo_through_inline_records() {
+            let (client, server) = UnixStream::pair().unwrap();
+            let server = echo_server(server, &[&TLS13]);
+            let mut stream = tls_stream(client);
+            assert_eq!(payload(), echo(&mut stream));
+            assert!(stream.tls_parameters().unwrap().is_tls13());
+            server.join().unwrap();
+        }
+
+        #[test]
+        fn should_fall_back_to_rustls_for_tls12() {
+            let (client, server) = UnixStream::pair().unwrap();
+            let server = echo_server(server, &[&TLS12]);
+            let mut stream = tls_stream(client);
+            assert_eq!(payload(), echo(&mut stream));
+            assert_eq!(Some("TLSv1.2"), stream.tls_parameters().unwrap().version);
+            server.join().unwrap();
+        }
+
+        /// Plain stream on top of the record layer.
+        struct Records {
+            records: RecordLayer,
+            stream: UnixStream,
+        }
+
+        impl Read for Records {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.records.read(&mut self.stream, buf)
+            }
+        }
+
+        impl Write for Records {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.records.write(&mut self.stream, buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        #[test]
+        fn should_follow_key_updates() {
+            let (mut client, server) = UnixStream::pair().unwrap();
+            let server = echo_server(server, &[&TLS13]);
+
+            let mut config = ClientConfig::builder()
+                .with_root_certificates(RootCertStore::empty())
+                .with_no_client_auth();
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(crate::stream::tls::__rustls::NoCertVerification));
+            let mut handover = Handover::new(&mut config);
+            let mut tls = ClientConnection::new(Arc::new(config), "localhost".try_into().unwrap()).unwrap();
+            while !handover.is_due(&tls) {
+                while tls.wants_write() {
+                    tls.write_tls(&mut client).unwrap();
+                }
+                if tls.is_handshaking() {
+                    handover.read_tls(&mut tls, &mut client).unwrap();
+                    tls.process_new_packets().unwrap();
+                }
+            }
+            let mut records = handover.into_record_layer(tls, TlsParameters::default()).unwrap();
+            let secrets = (records.tx.secret.clone(), records.rx.secret.clone());
+
+            // the server acknowledges with its own key update before echoing
+            records.update_keys().unwrap();
+            let mut stream = Records {
+                records,
+                stream: client,
+            };
+            assert_eq!(payload(), echo(&mut stream));
+            assert_ne!(secrets.0, stream.records.tx.secret);
+            assert_ne!(secrets.1, stream.records.rx.secret);
+            server.join().unwrap();
+        }
+    }
+}
+
 #[cfg(all(feature = "rustls", not(feature = "openssl")))]
 mod __rustls {
     use crate::service::select::Selectable;
-    use crate::stream::tls::TlsConfig;
-    use crate::stream::{ConnectionInfo, ConnectionInfoProvider, RxTimestamped};
+    use crate::stream::tls::inline::{Handover, RecordLayer, Sealer};
+    use crate::stream::tls::{Coalescer, TlsConfig, TlsParameters, TlsParametersProvider};
+    use crate::stream::{ConnectionInfo, ConnectionInfoProvider, PendingWrites, RxTimestamped};
     use crate::util::NoBlock;
     #[cfg(feature = "mio")]
     use mio::{Interest, Registry, Token, event::Source};
@@ -147,14 +1129,73 @@ mod __rustls {
     };
     use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
     use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
-    use rustls::{ClientConfig, ClientConnection, DigitallySignedStruct, Error, RootCertStore, SignatureScheme};
+    use rustls::{
+        CipherSuite, ClientConfig, ClientConnection, DigitallySignedStruct, Error, ProtocolVersion, RootCertStore,
+        SignatureScheme,
+    };
     use std::fmt::Debug;
     use std::io;
-    use std::io::{Read, Write};
+    use std::io::ErrorKind::{UnexpectedEof, WouldBlock};
+    use std::io::{IoSlice, Read, Write};
+    use std::os::fd::{AsRawFd, RawFd};
 
     pub struct TlsStream<S> {
         inner: S,
-        tls: ClientConnection,
+        session: Session,
+        coalescer: Coalescer,
+    }
+
+    #[allow(clippy::large_enum_variant)]
+    enum Session {
+        Rustls {
+            tls: ClientConnection,
+            // present until the session is handed over to the inline record layer
+            handover: Option<Handover>,
+        },
+        Inline(RecordLayer),
+        // the hand over to the inline record layer has failed
+        Failed,
+    }
+
+    fn failed() -> io::Error {
+        io::Error::other("tls session could not be handed over to the inline record layer")
+    }
+
+    /// Plaintext sink of the current session.
+    enum Plaintext<'a, S> {
+        Rustls(rustls::Writer<'a>),
+        Inline(Sealer<'a, S>),
+    }
+
+    fn plaintext<'a, S>(session: &'a mut Session, stream: &'a mut S) -> io::Result<Plaintext<'a, S>> {
+        match session {
+            Session::Rustls { tls, .. } => Ok(Plaintext::Rustls(tls.writer())),
+            Session::Inline(records) => Ok(Plaintext::Inline(Sealer { records, stream })),
+            Session::Failed => Err(failed()),
+        }
+    }
+
+    impl<S: Write> Write for Plaintext<'_, S> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            match self {
+                Plaintext::Rustls(writer) => writer.write(buf),
+                Plaintext::Inline(sealer) => sealer.write(buf),
+            }
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+            match self {
+                Plaintext::Rustls(writer) => writer.write_vectored(bufs),
+                Plaintext::Inline(sealer) => sealer.write_vectored(bufs),
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            match self {
+                Plaintext::Rustls(writer) => writer.flush(),
+                Plaintext::Inline(sealer) => sealer.flush(),
+            }
+        }
     }
 
     #[cfg(feature = "mio")]
@@ -172,6 +1213,12 @@ mod __rustls {
         }
     }
 
+    impl<S: AsRawFd> AsRawFd for TlsStream<S> {
+        fn as_raw_fd(&self) -> RawFd {
+            self.inner.as_raw_fd()
+        }
+    }
+
     impl<S: Selectable> Selectable for TlsStream<S> {
         fn connected(&mut self) -> io::Result<bool> {
             self.inner.connected()
@@ -184,22 +1231,55 @@ mod __rustls {
         fn make_readable(&mut self) -> io::Result<()> {
             self.inner.make_readable()
         }
+
+        fn take_read_activity(&mut self) -> bool {
+            self.inner.take_read_activity()
+        }
+
+        fn take_bytes_read(&mut self) -> u64 {
+            self.inner.take_bytes_read()
+        }
+
+        fn take_bytes_written(&mut self) -> u64 {
+            self.inner.take_bytes_written()
+        }
+
+        fn set_read_paused(&mut self, paused: bool) {
+            self.inner.set_read_paused(paused)
+        }
     }
 
     impl<S: Read + Write> Read for TlsStream<S> {
         fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.coalescer
+                .release_if_due(&mut plaintext(&mut self.session, &mut self.inner)?)?;
             let (_, _) = self.complete_io()?;
-            self.tls.reader().read(buf)
+            match &mut self.session {
+                Session::Rustls { tls, .. } => match tls.reader().read(buf) {
+                    // peer closed the connection without `close_notify`, reported as any other EOF
+                    Err(err) if err.kind() == UnexpectedEof => Ok(0),
+                    result => result,
+                },
+                Session::Inline(records) => records.read(&mut self.inner, buf),
+                Session::Failed => Err(failed()),
+            }
         }
     }
 
     impl<S: Read + Write> Write for TlsStream<S> {
         fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-            self.tls.writer().write(buf)
+            self.coalescer
+                .write(&mut plaintext(&mut self.session, &mut self.inner)?, buf)
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+            self.coalescer
+                .write_vectored(&mut plaintext(&mut self.session, &mut self.inner)?, bufs)
         }
 
         fn flush(&mut self) -> io::Result<()> {
-            self.tls.writer().flush()
+            self.coalescer
+                .flush(&mut plaintext(&mut self.session, &mut self.inner)?)
         }
     }
 
@@ -224,18 +1304,31 @@ mod __rustls {
                 }
             }
 
-            let config = ClientConfig::builder()
+            #[allow(unused_mut)]
+            let mut config = ClientConfig::builder()
                 .with_root_certificates(root_store)
                 .with_no_client_auth();
 
-            let mut config = TlsConfig { rustls_config: config };
+            #[cfg(feature = "keylog")]
+            if super::key_log::enabled() {
+                config.key_log = std::sync::Arc::new(super::key_log::RustlsKeyLog);
+            }
+
+            let mut config = TlsConfig::from(config);
             builder(&mut config);
 
-            let config = std::sync::Arc::new(config.rustls_config);
+            let coalescer = Coalescer::new(config.record_sizing);
+            let mut rustls_config = config.rustls_config;
+            let handover = config.inline_records.then(|| Handover::new(&mut rustls_config));
             let server_name = server_name.to_owned().try_into().map_err(io::Error::other)?;
-            let tls = ClientConnection::new(config, server_name).map_err(io::Error::other)?;
+            let tls =
+                ClientConnection::new(std::sync::Arc::new(rustls_config), server_name).map_err(io::Error::other)?;
 
-            Ok(Self { inner: stream, tls })
+            Ok(Self {
+                inner: stream,
+                session: Session::Rustls { tls, handover },
+                coalescer,
+            })
         }
 
         pub fn new(stream: S, server_name: &str) -> io::Result<TlsStream<S>> {
@@ -243,24 +1336,54 @@ mod __rustls {
         }
 
         fn complete_io(&mut self) -> io::Result<(usize, usize)> {
-            let wrote = if self.tls.wants_write() {
-                self.tls.write_tls(&mut self.inner)?
+            let Session::Rustls { tls, handover } = &mut self.session else {
+                return Ok((0, 0));
+            };
+
+            let wrote = if tls.wants_write() {
+                tls.write_tls(&mut self.inner)?
             } else {
                 0
             };
 
-            let read = if self.tls.wants_read() {
-                let read = self.tls.read_tls(&mut self.inner).no_block()?;
+            let read = if tls.wants_read() {
+                // EOF (zero read) is recorded by rustls and surfaced by the plaintext reader
+                let read = match handover {
+                    Some(handover) => handover.read_tls(tls, &mut self.inner),
+                    None => tls.read_tls(&mut self.inner),
+                };
+                let read = match read {
+                    Ok(read) => read,
+                    Err(err) if err.kind() == WouldBlock => 0,
+                    Err(err) => return Err(err),
+                };
                 if read > 0 {
-                    self.tls.process_new_packets().map_err(io::Error::other)?;
+                    tls.process_new_packets().map_err(io::Error::other)?;
                 }
                 read
             } else {
                 0
             };
 
+            if handover.as_ref().is_some_and(|handover| handover.is_due(tls)) {
+                self.hand_over()?;
+            }
+
             Ok((read, wrote))
         }
+
+        /// Hand the established TLS 1.3 session over to the inline record layer.
+        fn hand_over(&mut self) -> io::Result<()> {
+            if let Session::Rustls {
+                tls,
+                handover: Some(handover),
+            } = std::mem::replace(&mut self.session, Session::Failed)
+            {
+                let parameters = rustls_parameters(&tls).unwrap_or_default();
+                self.session = Session::Inline(handover.into_record_layer(tls, parameters)?);
+            }
+            Ok(())
+        }
     }
 
     impl<S: ConnectionInfoProvider> ConnectionInfoProvider for TlsStream<S> {
@@ -269,6 +1392,77 @@ mod __rustls {
         }
     }
 
+    impl<S> TlsParametersProvider for TlsStream<S> {
+        fn tls_parameters(&self) -> Option<TlsParameters> {
+            match &self.session {
+                Session::Rustls { tls, .. } => rustls_parameters(tls),
+                Session::Inline(records) => Some(records.parameters()),
+                Session::Failed => None,
+            }
+        }
+    }
+
+    fn rustls_parameters(tls: &ClientConnection) -> Option<TlsParameters> {
+        if tls.is_handshaking() {
+            return None;
+        }
+        let version = tls.protocol_version().map(|version| match version {
+            ProtocolVersion::TLSv1_3 => "TLSv1.3",
+            ProtocolVersion::TLSv1_2 => "TLSv1.2",
+            version => version.as_str().unwrap_or("unknown"),
+        });
+        // rustls prefixes TLS 1.3 suites with `TLS13_` instead of the IANA `TLS_`
+        let cipher = tls.negotiated_cipher_suite().and_then(|suite| match suite.suite() {
+            CipherSuite::TLS13_AES_128_GCM_SHA256 => Some("TLS_AES_128_GCM_SHA256"),
+            CipherSuite::TLS13_AES_256_GCM_SHA384 => Some("TLS_AES_256_GCM_SHA384"),
+            CipherSuite::TLS13_CHACHA20_POLY1305_SHA256 => Some("TLS_CHACHA20_POLY1305_SHA256"),
+            suite => suite.as_str(),
+        });
+        // the negotiated key exchange group is not exposed by rustls 0.22
+        Some(TlsParameters {
+            version,
+            cipher,
+            kx_group: None,
+        })
+    }
+
+    impl<S: Write + PendingWrites> PendingWrites for TlsStream<S> {
+        fn has_pending_writes(&self) -> bool {
+            let session = match &self.session {
+                Session::Rustls { tls, .. } => tls.is_handshaking() || tls.wants_write(),
+                Session::Inline(records) => records.has_pending_writes(),
+                Session::Failed => false,
+            };
+            session || self.coalescer.has_pending_writes() || self.inner.has_pending_writes()
+        }
+
+        /// Writes the TLS records that are ready (including the coalesced plaintext), plaintext written
+        /// during the handshake is only encrypted once the handshake (driven by reads) completes.
+        fn drive_writes(&mut self) -> io::Result<bool> {
+            self.coalescer
+                .release(&mut plaintext(&mut self.session, &mut self.inner)?)?;
+            match &mut self.session {
+                Session::Rustls { tls, .. } => {
+                    while tls.wants_write() {
+                        if tls.write_tls(&mut self.inner).no_block()? == 0 {
+                            return Ok(false);
+                        }
+                    }
+                    if tls.is_handshaking() {
+                        return Ok(false);
+                    }
+                }
+                Session::Inline(records) => {
+                    if !records.write_records(&mut self.inner)? {
+                        return Ok(false);
+                    }
+                }
+                Session::Failed => return Err(failed()),
+            }
+            self.inner.drive_writes()
+        }
+    }
+
     impl<S: RxTimestamped> RxTimestamped for TlsStream<S> {
         fn last_rx_timestamps(&self) -> Option<crate::stream::RxTimestamps> {
             self.inner.last_rx_timestamps()
@@ -335,8 +1529,8 @@ mod __rustls {
 #[cfg(feature = "openssl")]
 mod __openssl {
     use crate::service::select::Selectable;
-    use crate::stream::tls::TlsConfig;
-    use crate::stream::{ConnectionInfo, ConnectionInfoProvider, RxTimestamped};
+    use crate::stream::tls::{Coalescer, TlsConfig, TlsParameters, TlsParametersProvider, openssl_parameters};
+    use crate::stream::{ConnectionInfo, ConnectionInfoProvider, PendingWrites, RxTimestamped};
     #[cfg(feature = "mio")]
     use mio::{Interest, Registry, Token, event::Source};
     use openssl::ssl::{
@@ -344,37 +1538,31 @@ mod __openssl {
     };
     use openssl::x509::X509VerifyResult;
     use std::fmt::Debug;
-    use std::fs::OpenOptions;
     use std::io;
     use std::io::ErrorKind::WouldBlock;
-    use std::io::{Read, Write};
+    use std::io::{IoSlice, Read, Write};
+    use std::os::fd::{AsRawFd, RawFd};
 
     trait SslConnectionBuilderExt {
         fn setup_default_keylog_policy(&mut self);
     }
 
     impl SslConnectionBuilderExt for SslConnectorBuilder {
+        #[cfg(feature = "keylog")]
         fn setup_default_keylog_policy(&mut self) {
-            fn default_key_log_callback(_ssl: &SslRef, line: &str) {
-                let path = std::env::var("SSLKEYLOGFILE").expect("SSLKEYLOGFILE not set");
-                let mut file = OpenOptions::new()
-                    .create(true)
-                    .append(true)
-                    .open(path)
-                    .expect("Failed to open SSL key log file");
-
-                writeln!(file, "{line}").expect("Failed to write to SSL key log file");
-            }
-
-            if std::env::var("SSLKEYLOGFILE").is_ok() {
-                self.set_keylog_callback(default_key_log_callback)
+            if super::key_log::enabled() {
+                self.set_keylog_callback(|_ssl, line| super::key_log::write_line(line))
             }
         }
+
+        #[cfg(not(feature = "keylog"))]
+        fn setup_default_keylog_policy(&mut self) {}
     }
 
     #[derive(Debug)]
     pub struct TlsStream<S> {
         state: State<S>,
+        coalescer: Coalescer,
     }
 
     #[derive(Debug)]
@@ -398,6 +1586,22 @@ mod __openssl {
                 State::Stream(stream) => Ok(stream.get_mut()),
             }
         }
+
+        fn get_ref(&self) -> Option<&S> {
+            match self {
+                State::Handshake(stream_and_buf) => stream_and_buf.as_ref().map(|(stream, _)| stream.get_ref()),
+                State::Drain(stream_and_buf) => stream_and_buf.as_ref().map(|(stream, ..)| stream.get_ref()),
+                State::Stream(stream) => Some(stream.get_ref()),
+            }
+        }
+
+        fn ssl(&self) -> Option<&SslRef> {
+            match self {
+                State::Handshake(_) => None,
+                State::Drain(stream_and_buf) => stream_and_buf.as_ref().map(|(stream, ..)| stream.ssl()),
+                State::Stream(stream) => Some(stream.ssl()),
+            }
+        }
     }
 
     impl<S: ConnectionInfoProvider> ConnectionInfoProvider for State<S> {
@@ -410,6 +1614,32 @@ mod __openssl {
         }
     }
 
+    impl<S: Read + Write + PendingWrites> PendingWrites for TlsStream<S> {
+        fn has_pending_writes(&self) -> bool {
+            if self.coalescer.has_pending_writes() {
+                return true;
+            }
+            match &self.state {
+                State::Handshake(stream_and_buf) => {
+                    stream_and_buf.as_ref().is_some_and(|(_, buffer)| !buffer.is_empty())
+                }
+                State::Drain(stream_and_buf) => stream_and_buf
+                    .as_ref()
+                    .is_some_and(|(_, buffer, written)| *written < buffer.len()),
+                State::Stream(stream) => stream.get_ref().has_pending_writes(),
+            }
+        }
+
+        /// Writes buffered during the handshake are drained on read.
+        fn drive_writes(&mut self) -> io::Result<bool> {
+            self.coalescer.release(&mut self.state)?;
+            match &mut self.state {
+                State::Stream(stream) => stream.get_mut().drive_writes(),
+                _ => Ok(!self.has_pending_writes()),
+            }
+        }
+    }
+
     impl<S: RxTimestamped> RxTimestamped for TlsStream<S> {
         fn last_rx_timestamps(&self) -> Option<crate::stream::RxTimestamps> {
             match &self.state {
@@ -451,6 +1681,13 @@ mod __openssl {
         }
     }
 
+    impl<S: AsRawFd> AsRawFd for TlsStream<S> {
+        /// Descriptor of the underlying stream, `-1` once a failed handshake has consumed it.
+        fn as_raw_fd(&self) -> RawFd {
+            self.state.get_ref().map_or(-1, AsRawFd::as_raw_fd)
+        }
+    }
+
     impl<S: Selectable> Selectable for TlsStream<S> {
         fn connected(&mut self) -> io::Result<bool> {
             self.state.get_mut()?.connected()
@@ -463,10 +1700,40 @@ mod __openssl {
         fn make_readable(&mut self) -> io::Result<()> {
             self.state.get_mut()?.make_readable()
         }
+
+        fn take_read_activity(&mut self) -> bool {
+            match self.state.get_mut() {
+                Ok(stream) => stream.take_read_activity(),
+                // the error will be surfaced by the next read
+                Err(_) => true,
+            }
+        }
+
+        fn take_bytes_read(&mut self) -> u64 {
+            match self.state.get_mut() {
+                Ok(stream) => stream.take_bytes_read(),
+                Err(_) => 0,
+            }
+        }
+
+        fn take_bytes_written(&mut self) -> u64 {
+            match self.state.get_mut() {
+                Ok(stream) => stream.take_bytes_written(),
+                Err(_) => 0,
+            }
+        }
+
+        fn set_read_paused(&mut self, paused: bool) {
+            if let Ok(stream) = self.state.get_mut() {
+                stream.set_read_paused(paused)
+            }
+        }
     }
 
     impl<S: Read + Write> Read for TlsStream<S> {
         fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.coalescer.release_if_due(&mut self.state)?;
+            let max_record_size = self.coalescer.max_record_size();
             match &mut self.state {
                 State::Handshake(stream_and_buf) => {
                     if let Some((mid_handshake, buffer)) = stream_and_buf.take() {
@@ -504,7 +1771,7 @@ mod __openssl {
                         stream.flush()?;
                         self.state = State::Stream(stream);
                     } else {
-                        from += stream.write(remaining)?;
+                        from += stream.write(&remaining[..remaining.len().min(max_record_size)])?;
                         self.state = State::Drain(Some((stream, buffer, from)));
                     }
                     Err(io::Error::from(WouldBlock))
@@ -516,7 +1783,21 @@ mod __openssl {
 
     impl<S: Read + Write> Write for TlsStream<S> {
         fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-            match &mut self.state {
+            self.coalescer.write(&mut self.state, buf)
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+            self.coalescer.write_vectored(&mut self.state, bufs)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.coalescer.flush(&mut self.state)
+        }
+    }
+
+    impl<S: Read + Write> Write for State<S> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            match self {
                 State::Handshake(stream_and_buf) => {
                     let (_, buffer) = stream_and_buf.as_mut().unwrap();
                     buffer.extend_from_slice(buf);
@@ -532,7 +1813,7 @@ mod __openssl {
         }
 
         fn flush(&mut self) -> io::Result<()> {
-            match &mut self.state {
+            match self {
                 State::Handshake(_) => Ok(()),
                 State::Drain(_) => Ok(()),
                 State::Stream(stream) => stream.flush(),
@@ -548,19 +1829,20 @@ mod __openssl {
             let mut builder = SslConnector::builder(SslMethod::tls_client()).map_err(io::Error::other)?;
             builder.setup_default_keylog_policy();
 
-            let mut tls_config = TlsConfig {
-                openssl_config: builder,
-            };
+            let mut tls_config = TlsConfig::from(builder);
 
             configure(&mut tls_config);
 
+            let coalescer = Coalescer::new(tls_config.record_sizing);
             let connector = tls_config.openssl_config.build();
             match connector.connect(server_name, stream) {
                 Ok(stream) => Ok(Self {
                     state: State::Stream(stream),
+                    coalescer,
                 }),
                 Err(HandshakeError::WouldBlock(mid_handshake)) => Ok(Self {
                     state: State::Handshake(Some((mid_handshake, Vec::with_capacity(4096)))),
+                    coalescer,
                 }),
                 Err(e) => Err(io::Error::other(e.to_string())),
             }
@@ -576,6 +1858,12 @@ mod __openssl {
             self.state.connection_info()
         }
     }
+
+    impl<S> TlsParametersProvider for TlsStream<S> {
+        fn tls_parameters(&self) -> Option<TlsParameters> {
+            self.state.ssl().map(openssl_parameters)
+        }
+    }
 }
 
 /// Trait to convert underlying stream into [TlsStream].
@@ -659,6 +1947,13 @@ impl<S: Read + Write> Write for TlsReadyStream<S> {
         }
     }
 
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        match self {
+            TlsReadyStream::Plain(stream) => stream.write_vectored(bufs),
+            TlsReadyStream::Tls(stream) => stream.write_vectored(bufs),
+        }
+    }
+
     fn flush(&mut self) -> io::Result<()> {
         match self {
             TlsReadyStream::Plain(stream) => stream.flush(),
@@ -700,6 +1995,15 @@ impl<S: Source> Source for TlsReadyStream<S> {
     }
 }
 
+impl<S: AsRawFd> AsRawFd for TlsReadyStream<S> {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            TlsReadyStream::Plain(stream) => stream.as_raw_fd(),
+            TlsReadyStream::Tls(stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
 impl<S: Selectable> Selectable for TlsReadyStream<S> {
     fn connected(&mut self) -> io::Result<bool> {
         match self {
@@ -721,6 +2025,50 @@ impl<S: Selectable> Selectable for TlsReadyStream<S> {
             TlsReadyStream::Tls(stream) => stream.make_readable(),
         }
     }
+
+    fn take_read_activity(&mut self) -> bool {
+        match self {
+            TlsReadyStream::Plain(stream) => stream.take_read_activity(),
+            TlsReadyStream::Tls(stream) => stream.take_read_activity(),
+        }
+    }
+
+    fn take_bytes_read(&mut self) -> u64 {
+        match self {
+            TlsReadyStream::Plain(stream) => stream.take_bytes_read(),
+            TlsReadyStream::Tls(stream) => stream.take_bytes_read(),
+        }
+    }
+
+    fn take_bytes_written(&mut self) -> u64 {
+        match self {
+            TlsReadyStream::Plain(stream) => stream.take_bytes_written(),
+            TlsReadyStream::Tls(stream) => stream.take_bytes_written(),
+        }
+    }
+
+    fn set_read_paused(&mut self, paused: bool) {
+        match self {
+            TlsReadyStream::Plain(stream) => stream.set_read_paused(paused),
+            TlsReadyStream::Tls(stream) => stream.set_read_paused(paused),
+        }
+    }
+}
+
+impl<S: Read + Write + PendingWrites> PendingWrites for TlsReadyStream<S> {
+    fn has_pending_writes(&self) -> bool {
+        match self {
+            TlsReadyStream::Plain(stream) => stream.has_pending_writes(),
+            TlsReadyStream::Tls(stream) => stream.has_pending_writes(),
+        }
+    }
+
+    fn drive_writes(&mut self) -> io::Result<bool> {
+        match self {
+            TlsReadyStream::Plain(stream) => stream.drive_writes(),
+            TlsReadyStream::Tls(stream) => stream.drive_writes(),
+        }
+    }
 }
 
 impl<S: RxTimestamped> RxTimestamped for TlsReadyStream<S> {
@@ -738,3 +2086,75 @@ impl<S: RxTimestamped> RxTimestamped for TlsReadyStream<S> {
         }
     }
 }
+
+impl<S> TlsParametersProvider for TlsReadyStream<S> {
+    fn tls_parameters(&self) -> Option<TlsParameters> {
+        match self {
+            TlsReadyStream::Plain(_) => None,
+            TlsReadyStream::Tls(stream) => stream.tls_parameters(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records every write as a separate TLS record.
+    #[derive(Default)]
+    struct Records(Vec<Vec<u8>>);
+
+    impl Write for Records {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_split_writes_into_records_of_max_size() {
+        let mut coalescer = Coalescer::new(RecordSizing::new().with_max_record_size(4));
+        let mut records = Records::default();
+        let mut buf = &b"0123456789"[..];
+        while !buf.is_empty() {
+            let n = coalescer.write(&mut records, buf).unwrap();
+            buf = &buf[n..];
+        }
+        assert_eq!(vec![b"0123".to_vec(), b"4567".to_vec(), b"89".to_vec()], records.0);
+        assert_eq!(MAX_RECORD_SIZE, RecordSizing::new().with_max_record_size(1 << 20).max_record_size());
+    }
+
+    #[test]
+    fn should_coalesce_writes_until_max_bytes_or_window() {
+        let sizing = RecordSizing::new()
+            .with_max_record_size(4)
+            .with_coalescing(6, Duration::from_secs(3600));
+        let mut coalescer = Coalescer::new(sizing);
+        let mut records = Records::default();
+
+        coalescer.write(&mut records, b"abc").unwrap();
+        coalescer.flush(&mut records).unwrap();
+        assert!(records.0.is_empty());
+        assert!(coalescer.has_pending_writes());
+
+        coalescer
+            .write_vectored(&mut records, &[IoSlice::new(b"de"), IoSlice::new(b"f")])
+            .unwrap();
+        assert_eq!(vec![b"abcd".to_vec(), b"ef".to_vec()], records.0);
+        assert!(!coalescer.has_pending_writes());
+
+        // released regardless of the window when driven
+        coalescer.write(&mut records, b"g").unwrap();
+        coalescer.release(&mut records).unwrap();
+        assert_eq!(b"g".to_vec(), records.0[2]);
+
+        // released once the window has passed
+        let mut coalescer = Coalescer::new(sizing.with_coalescing(6, Duration::ZERO));
+        coalescer.write(&mut records, b"h").unwrap();
+        assert_eq!(b"h".to_vec(), records.0[3]);
+    }
+}
@@ -0,0 +1,243 @@
+//! Streams established outside the crate.
+//!
+//! Any `Read + Write` transport to the remote host (e.g. a channel forwarded over SSH, a custom tunnel
+//! or an in-memory pipe in tests) can be layered with TLS and upgraded to a websocket once it is paired
+//! with the [`ConnectionInfo`] of the remote host by [`IntoTransport::into_transport`]. The host is
+//! what the TLS server name and the websocket `Host` header are derived from, it does not have to be
+//! the address the transport is connected to. The [`Transport`] forwards the stream traits the
+//! transport implements, so it can also be driven by the `IOService` if it is [`Selectable`].
+//!
+//! ## Examples
+//! ```no_run
+//! use std::os::unix::net::UnixStream;
+//! use boomnet::stream::transport::IntoTransport;
+//! use boomnet::stream::tls::IntoTlsStream;
+//! use boomnet::ws::IntoWebsocket;
+//!
+//! // e.g. forwarded to the venue with `ssh -L /tmp/venue.sock:stream.binance.com:9443 jump.example.com`
+//! let tunnel = UnixStream::connect("/tmp/venue.sock").unwrap();
+//! let mut ws = tunnel
+//!     .into_transport(("stream.binance.com", 9443))
+//!     .into_tls_stream()
+//!     .unwrap()
+//!     .into_websocket("/ws");
+//! ```
+
+use crate::service::select::Selectable;
+#[cfg(any(feature = "rustls", feature = "openssl"))]
+use crate::stream::tls::{TlsParameters, TlsParametersProvider};
+use crate::stream::{ConnectionInfo, ConnectionInfoProvider, PendingWrites, RxTimestamped, RxTimestamps};
+#[cfg(feature = "mio")]
+use mio::{Interest, Registry, Token, event::Source};
+use std::io;
+use std::io::{IoSlice, Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+
+/// User supplied transport paired with the connection info of the remote host, see the
+/// [module](self) documentation.
+#[derive(Debug)]
+pub struct Transport<S> {
+    inner: S,
+    connection_info: ConnectionInfo,
+}
+
+impl<S> Transport<S> {
+    /// Pair the `inner` transport with the `connection_info` of the remote host it leads to.
+    pub fn new(inner: S, connection_info: impl Into<ConnectionInfo>) -> Transport<S> {
+        Self {
+            inner,
+            connection_info: connection_info.into(),
+        }
+    }
+
+    /// Reference to the underlying transport.
+    pub const fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Mutable reference to the underlying transport, bytes exchanged through it bypass any layer
+    /// applied on top of the [`Transport`].
+    pub const fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Unwrap the underlying transport.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: Read> Read for Transport<S> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<S: Write> Write for Transport<S> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    #[inline]
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.inner.write_vectored(bufs)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S> ConnectionInfoProvider for Transport<S> {
+    fn connection_info(&self) -> &ConnectionInfo {
+        &self.connection_info
+    }
+}
+
+impl<S: PendingWrites> PendingWrites for Transport<S> {
+    fn has_pending_writes(&self) -> bool {
+        self.inner.has_pending_writes()
+    }
+
+    fn drive_writes(&mut self) -> io::Result<bool> {
+        self.inner.drive_writes()
+    }
+}
+
+impl<S: RxTimestamped> RxTimestamped for Transport<S> {
+    fn last_rx_timestamps(&self) -> Option<RxTimestamps> {
+        self.inner.last_rx_timestamps()
+    }
+
+    fn take_last_rx_timestamps(&mut self) -> Option<RxTimestamps> {
+        self.inner.take_last_rx_timestamps()
+    }
+}
+
+#[cfg(any(feature = "rustls", feature = "openssl"))]
+impl<S: TlsParametersProvider> TlsParametersProvider for Transport<S> {
+    fn tls_parameters(&self) -> Option<TlsParameters> {
+        self.inner.tls_parameters()
+    }
+}
+
+impl<S: AsRawFd> AsRawFd for Transport<S> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+impl<S: Selectable> Selectable for Transport<S> {
+    fn connected(&mut self) -> io::Result<bool> {
+        self.inner.connected()
+    }
+
+    fn make_writable(&mut self) -> io::Result<()> {
+        self.inner.make_writable()
+    }
+
+    fn make_readable(&mut self) -> io::Result<()> {
+        self.inner.make_readable()
+    }
+
+    fn take_read_activity(&mut self) -> bool {
+        self.inner.take_read_activity()
+    }
+
+    fn take_bytes_read(&mut self) -> u64 {
+        self.inner.take_bytes_read()
+    }
+
+    fn take_bytes_written(&mut self) -> u64 {
+        self.inner.take_bytes_written()
+    }
+
+    fn set_read_paused(&mut self, paused: bool) {
+        self.inner.set_read_paused(paused)
+    }
+}
+
+#[cfg(feature = "mio")]
+impl<S: Source> Source for Transport<S> {
+    fn register(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+        registry.register(&mut self.inner, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+        registry.reregister(&mut self.inner, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        registry.deregister(&mut self.inner)
+    }
+}
+
+/// Trait to pair any transport with the connection info of the remote host.
+pub trait IntoTransport {
+    /// Convert into [`Transport`] leading to the remote host described by the `connection_info`.
+    fn into_transport(self, connection_info: impl Into<ConnectionInfo>) -> Transport<Self>
+    where
+        Self: Sized;
+}
+
+impl<T> IntoTransport for T
+where
+    T: Read + Write,
+{
+    fn into_transport(self, connection_info: impl Into<ConnectionInfo>) -> Transport<Self> {
+        Transport::new(self, connection_info)
+    }
+}
+
+#[cfg(all(test, feature = "ws"))]
+mod tests {
+    use super::*;
+    use crate::ws::{IntoWebsocket, WebsocketFrame};
+
+    /// In-memory pipe standing in for a tunnel, answers the upgrade with the canned response.
+    struct Pipe {
+        inbound: io::Cursor<Vec<u8>>,
+        outbound: Vec<u8>,
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.inbound.read(buf)? {
+                0 => Err(io::ErrorKind::WouldBlock.into()),
+                read => Ok(read),
+            }
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.outbound.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_upgrade_user_supplied_transport() {
+        let mut inbound = b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n".to_vec();
+        inbound.extend_from_slice(b"Sec-WebSocket-Accept: ignored\r\n\r\n\x81\x05hello");
+        let pipe = Pipe {
+            inbound: io::Cursor::new(inbound),
+            outbound: vec![],
+        };
+        let mut ws = pipe.into_transport(("stream.example.com", 443)).into_websocket("/ws");
+
+        while !ws.handshake_complete() {
+            assert!(ws.read_batch().unwrap().receive_next().is_none());
+        }
+        let mut batch = ws.read_batch().unwrap();
+        assert!(matches!(batch.receive_next(), Some(Ok(WebsocketFrame::Text(true, b"hello")))));
+        let request = String::from_utf8_lossy(&ws.stream().get_ref().outbound).into_owned();
+        assert!(request.starts_with("GET /ws HTTP/1.1\r\nHost: stream.example.com\r\n"));
+    }
+}
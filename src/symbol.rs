@@ -0,0 +1,187 @@
+//! Interned symbol table mapping venue symbol strings to dense `u32` ids.
+//!
+//! Symbols are interned once (typically on the IO thread when subscribing) and from then on
+//! referred to by [`SymbolId`], avoiding repeated string hashing and comparison in the hot loop.
+//! The table has a single writer ([`SymbolTable`]) and any number of lock-free readers
+//! ([`SymbolReader`]) that can resolve ids back to names from other threads.
+//!
+//! ## Examples
+//! ```
+//! use boomnet::symbol::SymbolTable;
+//!
+//! let mut symbols = SymbolTable::with_capacity(1024);
+//! let btc = symbols.intern("BTCUSDT").unwrap();
+//! assert_eq!(btc, symbols.intern("BTCUSDT").unwrap());
+//! assert_eq!(Some(btc), symbols.get(b"BTCUSDT"));
+//!
+//! let reader = symbols.reader();
+//! std::thread::spawn(move || assert_eq!(Some("BTCUSDT"), reader.resolve(btc)))
+//!     .join()
+//!     .unwrap();
+//! ```
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+
+/// Dense id of the interned symbol. Ids are assigned sequentially starting from zero and remain
+/// stable for the lifetime of the table.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct SymbolId(u32);
+
+impl SymbolId {
+    /// Numeric value of the id, suitable for indexing into dense arrays.
+    pub const fn as_u32(self) -> u32 {
+        self.0
+    }
+
+    /// Numeric value of the id as `usize`.
+    pub const fn as_usize(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl Display for SymbolId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+struct Store {
+    names: Box<[OnceLock<Box<str>>]>,
+    len: AtomicUsize,
+}
+
+impl Store {
+    #[inline]
+    fn resolve(&self, id: SymbolId) -> Option<&str> {
+        self.names.get(id.as_usize())?.get().map(|name| name.as_ref())
+    }
+}
+
+/// Writer side of the symbol table. Not `Sync`, intended to be owned by the IO thread.
+pub struct SymbolTable {
+    ids: HashMap<Box<[u8]>, SymbolId>,
+    store: Arc<Store>,
+}
+
+impl SymbolTable {
+    /// Create table that can hold up to `capacity` symbols.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let capacity = capacity.min(u32::MAX as usize);
+        Self {
+            ids: HashMap::with_capacity(capacity),
+            store: Arc::new(Store {
+                names: (0..capacity).map(|_| OnceLock::new()).collect(),
+                len: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Intern `name` returning its id. Returns existing id if the symbol is already present
+    /// or an error if the table is full.
+    pub fn intern(&mut self, name: &str) -> io::Result<SymbolId> {
+        if let Some(id) = self.ids.get(name.as_bytes()) {
+            return Ok(*id);
+        }
+        let index = self.ids.len();
+        let slot = self
+            .store
+            .names
+            .get(index)
+            .ok_or_else(|| io::Error::other(format!("symbol table is full (capacity {})", self.capacity())))?;
+        let _ = slot.set(name.into());
+        self.store.len.store(index + 1, Ordering::Release);
+        let id = SymbolId(index as u32);
+        self.ids.insert(name.as_bytes().into(), id);
+        Ok(id)
+    }
+
+    /// Lookup id of the interned symbol.
+    #[inline]
+    pub fn get(&self, name: impl AsRef<[u8]>) -> Option<SymbolId> {
+        self.ids.get(name.as_ref()).copied()
+    }
+
+    /// Resolve `id` back to the symbol name.
+    #[inline]
+    pub fn resolve(&self, id: SymbolId) -> Option<&str> {
+        self.store.resolve(id)
+    }
+
+    /// Number of interned symbols.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Returns `true` if no symbols have been interned.
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Maximum number of symbols the table can hold.
+    pub fn capacity(&self) -> usize {
+        self.store.names.len()
+    }
+
+    /// Create reader that can be sent to other threads.
+    pub fn reader(&self) -> SymbolReader {
+        SymbolReader {
+            store: self.store.clone(),
+        }
+    }
+}
+
+/// Lock-free reader side of the symbol table.
+#[derive(Clone)]
+pub struct SymbolReader {
+    store: Arc<Store>,
+}
+
+impl SymbolReader {
+    /// Resolve `id` back to the symbol name. Returns `None` if the id has not been interned (yet).
+    #[inline]
+    pub fn resolve(&self, id: SymbolId) -> Option<&str> {
+        self.store.resolve(id)
+    }
+
+    /// Number of symbols interned so far.
+    pub fn len(&self) -> usize {
+        self.store.len.load(Ordering::Acquire)
+    }
+
+    /// Returns `true` if no symbols have been interned so far.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterate over all symbols interned so far.
+    pub fn iter(&self) -> impl Iterator<Item = (SymbolId, &str)> {
+        (0..self.len()).filter_map(|index| {
+            let id = SymbolId(index as u32);
+            self.resolve(id).map(|name| (id, name))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_assign_dense_ids() {
+        let mut symbols = SymbolTable::with_capacity(2);
+        assert_eq!(0, symbols.intern("BTCUSDT").unwrap().as_u32());
+        assert_eq!(1, symbols.intern("ETHUSDT").unwrap().as_u32());
+        assert_eq!(0, symbols.intern("BTCUSDT").unwrap().as_u32());
+        assert!(symbols.intern("SOLUSDT").is_err());
+        assert_eq!(None, symbols.get("SOLUSDT"));
+
+        let reader = symbols.reader();
+        assert_eq!(2, reader.len());
+        assert_eq!(vec![(SymbolId(0), "BTCUSDT"), (SymbolId(1), "ETHUSDT")], reader.iter().collect::<Vec<_>>());
+        assert_eq!(None, reader.resolve(SymbolId(2)));
+    }
+}
@@ -0,0 +1,220 @@
+//! Inventory of system calls issued by the crate and `restricted` mode for tight seccomp profiles.
+//!
+//! [`INVENTORY`] lists every system call the crate may issue together with the feature that pulls it
+//! in and whether it is optional. Optional calls are only used for tuning (busy polling, cpu affinity,
+//! hardware timestamping, host checks) and are skipped when [restricted](set_restricted) mode is enabled,
+//! so the service can run with a seccomp profile that allows only the required set.
+//!
+//! When an optional call is issued and blocked (by seccomp or missing capabilities) the returned error
+//! names the call and the likely cause instead of a bare `EPERM`.
+//!
+//! ## Examples
+//! ```
+//! use boomnet::syscalls;
+//!
+//! // generate seccomp allow list for the enabled features
+//! syscalls::set_restricted(true);
+//! let allow = syscalls::enabled().filter(|s| !s.optional).map(|s| s.name).collect::<Vec<_>>();
+//! assert!(allow.contains(&"connect"));
+//! assert!(!allow.contains(&"sched_setaffinity"));
+//!
+//! // or dump the whole inventory as json
+//! println!("{}", syscalls::to_json());
+//! ```
+
+use std::fmt::Write;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static RESTRICTED: AtomicBool = AtomicBool::new(false);
+
+/// Single system call used by the crate.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Syscall {
+    /// System call name as used by seccomp (e.g. `setsockopt`).
+    pub name: &'static str,
+    /// Crate feature that pulls in the call, `None` if always used.
+    pub feature: Option<&'static str>,
+    /// Optional calls are skipped in restricted mode.
+    pub optional: bool,
+    /// What the call is used for.
+    pub purpose: &'static str,
+}
+
+const fn required(name: &'static str, feature: Option<&'static str>, purpose: &'static str) -> Syscall {
+    Syscall {
+        name,
+        feature,
+        optional: false,
+        purpose,
+    }
+}
+
+const fn optional(name: &'static str, feature: Option<&'static str>, purpose: &'static str) -> Syscall {
+    Syscall {
+        name,
+        feature,
+        optional: true,
+        purpose,
+    }
+}
+
+/// All system calls the crate may issue (on linux).
+pub const INVENTORY: &[Syscall] = &[
+    required("socket", None, "create tcp socket"),
+    required("connect", None, "connect tcp socket"),
+    required("bind", None, "bind socket to the network interface"),
+    required("fcntl", None, "set and query non-blocking mode"),
+    required("ioctl", None, "FIONBIO non-blocking mode"),
+    required("setsockopt", None, "TCP_NODELAY, SO_KEEPALIVE, SO_RCVBUF, SO_SNDBUF, SO_RCVTIMEO"),
+    required("getsockopt", None, "SO_ERROR after non-blocking connect, SO_RCVTIMEO"),
+    required("read", None, "read from socket"),
+    required("recvfrom", None, "read from socket"),
+    required("write", None, "write to socket"),
+    required("sendto", None, "write to socket"),
+    required("close", None, "close descriptors"),
+    required("getpeername", None, "connected socket address"),
+    required("getsockname", None, "local socket address"),
+    required("openat", None, "recording, replay and key log files"),
+    required("clock_gettime", None, "time source"),
+    required("socketpair", None, "dns resolution (libc resolver)"),
+    required("sendmmsg", None, "dns resolution (libc resolver)"),
+    required("poll", None, "dns resolution (libc resolver), bounded websocket reads"),
+    required("clone3", None, "async dns resolver worker thread"),
+    required("sched_getaffinity", None, "async dns resolver available cpu set"),
+    required("epoll_create1", Some("mio"), "mio selector"),
+    required("epoll_ctl", Some("mio"), "mio selector registration"),
+    required("epoll_wait", Some("mio"), "mio selector polling"),
+    required("recvmsg", Some("timestamping"), "read with SCM_TIMESTAMPING control messages"),
+    required("getrandom", Some("ws"), "websocket masking keys and handshake nonce"),
+    required("getrandom", Some("openssl"), "tls session randomness"),
+    required("getrandom", Some("rustls"), "tls session randomness"),
+    optional("setsockopt", None, "SO_BUSY_POLL, TCP_QUICKACK and SO_INCOMING_CPU tuning"),
+    optional("sched_setaffinity", None, "pin async dns resolver worker thread"),
+    optional("setsockopt", Some("timestamping"), "SO_TIMESTAMPING"),
+    optional("ioctl", Some("timestamping"), "SIOCSHWTSTAMP driver timestamping configuration"),
+    optional("ioctl", None, "SIOCETHTOOL timestamping capability query (host check, capability report)"),
+    optional("adjtimex", None, "clock synchronisation status (host check)"),
+    optional("capget", None, "capability detection (privileged setup, capability report)"),
+    optional("capset", None, "drop capabilities (privileged setup)"),
+    optional(
+        "socket",
+        Some("timestamping"),
+        "udp socket for SIOCSHWTSTAMP (privileged setup) and SO_TIMESTAMPING probe (capability report)",
+    ),
+    optional("mlock", None, "pin staging ring memory"),
+    optional("munlock", None, "unpin staging ring memory"),
+    optional("ioctl", None, "FIONREAD socket backlog for outlier capture"),
+    optional("getrusage", None, "context switch accounting of the IO thread (sched monitor)"),
+    optional("pread64", None, "/proc/thread-self/sched migration counter (sched monitor)"),
+    optional("mlockall", Some("timestamping"), "lock latency benchmark memory"),
+    optional("sched_setscheduler", Some("timestamping"), "SCHED_FIFO for latency benchmark"),
+    optional("bpf", Some("ebpf"), "load and attach socket latency tracer programs (tc, sockops), access their map"),
+];
+
+/// Enable or disable restricted mode (process wide). In restricted mode the crate does not issue any
+/// optional system call, tuning that requires them is skipped and explicit requests fail with
+/// [`io::ErrorKind::PermissionDenied`].
+pub fn set_restricted(restricted: bool) {
+    RESTRICTED.store(restricted, Ordering::Relaxed);
+}
+
+/// Returns `true` if restricted mode is enabled.
+#[inline]
+pub fn is_restricted() -> bool {
+    RESTRICTED.load(Ordering::Relaxed)
+}
+
+/// System calls for the features the crate was compiled with. In restricted mode optional calls are excluded.
+pub fn enabled() -> impl Iterator<Item = &'static Syscall> {
+    let restricted = is_restricted();
+    INVENTORY
+        .iter()
+        .filter(|syscall| syscall.feature.is_none_or(feature_enabled))
+        .filter(move |syscall| !(restricted && syscall.optional))
+}
+
+/// Render [`INVENTORY`] as a json array, marking which entries are enabled in this build.
+pub fn to_json() -> String {
+    let mut json = String::from("[");
+    for (i, syscall) in INVENTORY.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        let _ = write!(
+            json,
+            r#"{{"name":"{}","feature":{},"optional":{},"enabled":{},"purpose":"{}"}}"#,
+            syscall.name,
+            syscall.feature.map(|f| format!("\"{f}\"")).as_deref().unwrap_or("null"),
+            syscall.optional,
+            syscall.feature.is_none_or(feature_enabled),
+            syscall.purpose,
+        );
+    }
+    json.push(']');
+    json
+}
+
+fn feature_enabled(feature: &str) -> bool {
+    (feature == "mio" && cfg!(feature = "mio"))
+        || (feature == "timestamping" && cfg!(feature = "timestamping"))
+        || (feature == "ws" && cfg!(feature = "ws"))
+        || (feature == "openssl" && cfg!(feature = "openssl"))
+        || (feature == "rustls" && cfg!(feature = "rustls"))
+        || (feature == "ebpf" && cfg!(feature = "ebpf"))
+}
+
+/// Fails with [`io::ErrorKind::PermissionDenied`] if restricted mode is enabled.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub(crate) fn ensure_unrestricted(syscall: &str) -> io::Result<()> {
+    if is_restricted() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{syscall} is not allowed in restricted mode"),
+        ));
+    }
+    Ok(())
+}
+
+/// Map error of the optional `syscall` to a descriptive one if it has been blocked.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub(crate) fn blocked(syscall: &str, err: io::Error) -> io::Error {
+    match err.raw_os_error() {
+        Some(libc::EPERM) | Some(libc::EACCES) => io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "{syscall} was denied ({err}): the process lacks the required capability (e.g. CAP_NET_ADMIN) \
+                 or the call is blocked by seccomp, enable restricted mode to skip it"
+            ),
+        ),
+        Some(libc::ENOSYS) => io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "{syscall} is not available ({err}): it is likely blocked by seccomp, enable restricted mode to skip it"
+            ),
+        ),
+        _ => err,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_describe_blocked_syscall() {
+        let err = blocked("setsockopt(SO_BUSY_POLL)", io::Error::from_raw_os_error(libc::EPERM));
+        assert_eq!(io::ErrorKind::PermissionDenied, err.kind());
+        assert!(err.to_string().starts_with("setsockopt(SO_BUSY_POLL) was denied"));
+
+        let err = blocked("ioctl(SIOCSHWTSTAMP)", io::Error::from_raw_os_error(libc::EINVAL));
+        assert_eq!(Some(libc::EINVAL), err.raw_os_error());
+    }
+
+    #[test]
+    fn should_render_inventory_as_json() {
+        let json = to_json();
+        assert!(json.starts_with(r#"[{"name":"socket","feature":null,"optional":false,"enabled":true"#));
+        assert_eq!(INVENTORY.len(), json.matches("\"name\"").count());
+    }
+}
@@ -0,0 +1,32 @@
+//! Statically defined tracing (USDT) probes at the key stages of the pipeline.
+//!
+//! When the `usdt` feature is enabled every probe compiles down to a single `nop` plus an entry in the
+//! `.note.stapsdt` ELF section, so `perf`, `bpftrace` or `bcc` can attach to them at runtime without
+//! recompiling the application. Arguments are only evaluated while a tracer is attached. Without the
+//! feature the probes expand to nothing.
+//!
+//! All probes belong to the `boomnet` provider:
+//!
+//! | probe | arguments | fired when |
+//! |-------|-----------|------------|
+//! | `frame_start` | - | websocket starts decoding next frame |
+//! | `frame_dispatch` | op code, payload length | decoded frame is handed to the user |
+//! | `poll_done` | endpoints, frames, disconnected | `IOService` completes one poll iteration |
+//!
+//! ## Examples
+//! ```text
+//! $ perf probe -x ./target/release/app sdt_boomnet:frame_dispatch
+//! $ perf record -e sdt_boomnet:frame_start -e sdt_boomnet:frame_dispatch -p $(pidof app)
+//!
+//! $ bpftrace -e 'usdt:./target/release/app:boomnet:frame_dispatch { @len = hist(arg1); }'
+//! ```
+
+/// Fire `boomnet:$name` probe with optional integer arguments.
+macro_rules! probe {
+    ($name:ident $(, $arg:expr)* $(,)?) => {
+        #[cfg(feature = "usdt")]
+        ::probe::probe_lazy!(boomnet, $name $(, $arg)*);
+    };
+}
+
+pub(crate) use probe;
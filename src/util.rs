@@ -1,7 +1,6 @@
 use std::io;
 use std::io::ErrorKind::{UnexpectedEof, WouldBlock};
-use std::mem::MaybeUninit;
-use std::ptr::copy_nonoverlapping;
+use std::time::Duration;
 
 pub trait NoBlock {
     type Value;
@@ -34,8 +33,104 @@ impl NoBlock for io::Result<()> {
     }
 }
 
+/// Returns `true` if the close-on-exec flag is set on the file descriptor.
+#[cfg(unix)]
+pub(crate) fn is_cloexec(fd: std::os::fd::RawFd) -> io::Result<bool> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(flags & libc::FD_CLOEXEC != 0)
+}
+
+/// Returns `true` if the `O_NONBLOCK` flag is set on the file descriptor.
+#[cfg(unix)]
+#[cfg_attr(not(feature = "ws"), allow(dead_code))]
+pub(crate) fn is_nonblocking(fd: std::os::fd::RawFd) -> io::Result<bool> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(flags & libc::O_NONBLOCK != 0)
+}
+
+/// Wait with `poll` until any of the `events` is ready on the file descriptor or the `timeout` (forever
+/// if `None`) has passed, returns `false` on timeout. An interrupted wait returns `true`, the caller
+/// retries the operation and waits again if it still can not make progress.
+#[cfg(unix)]
+#[cfg_attr(not(feature = "ws"), allow(dead_code))]
+pub(crate) fn wait_ready(fd: std::os::fd::RawFd, events: libc::c_short, timeout: Option<Duration>) -> io::Result<bool> {
+    // round up, waking before the deadline would only make the caller wait again
+    let timeout_ms = match timeout {
+        Some(timeout) => timeout.as_nanos().div_ceil(1_000_000).min(libc::c_int::MAX as u128) as libc::c_int,
+        None => -1,
+    };
+    let mut poll_fd = libc::pollfd { fd, events, revents: 0 };
+    match unsafe { libc::poll(&mut poll_fd, 1, timeout_ms) } {
+        0 => Ok(false),
+        n if n > 0 => Ok(true),
+        _ => match io::Error::last_os_error() {
+            err if err.kind() == io::ErrorKind::Interrupted => Ok(true),
+            err => Err(err),
+        },
+    }
+}
+
+/// Run `f` with `SO_RCVTIMEO` of the (blocking) socket set to the `timeout`, the previous timeout is
+/// restored afterwards. A read that times out fails with [`WouldBlock`].
+#[cfg(unix)]
+#[cfg_attr(not(feature = "ws"), allow(dead_code))]
+pub(crate) fn with_read_timeout<T>(fd: std::os::fd::RawFd, timeout: Duration, f: impl FnOnce() -> T) -> io::Result<T> {
+    let mut previous = libc::timeval { tv_sec: 0, tv_usec: 0 };
+    let mut len = size_of::<libc::timeval>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_RCVTIMEO,
+            &mut previous as *mut _ as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // round up, a timeout below one microsecond would otherwise disable it
+    let micros = timeout.as_nanos().div_ceil(1_000).max(1);
+    let timeout = libc::timeval {
+        tv_sec: (micros / 1_000_000).min(libc::time_t::MAX as u128) as libc::time_t,
+        tv_usec: (micros % 1_000_000) as libc::suseconds_t,
+    };
+    set_read_timeout(fd, &timeout)?;
+    let result = f();
+    set_read_timeout(fd, &previous)?;
+    Ok(result)
+}
+
+#[cfg(unix)]
+#[cfg_attr(not(feature = "ws"), allow(dead_code))]
+fn set_read_timeout(fd: std::os::fd::RawFd, timeout: &libc::timeval) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_RCVTIMEO,
+            timeout as *const _ as *const libc::c_void,
+            size_of::<libc::timeval>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
 #[inline]
+#[cfg(feature = "ws")]
 pub const unsafe fn into_array<const N: usize>(slice: &[u8]) -> [u8; N] {
+    use std::mem::MaybeUninit;
+    use std::ptr::copy_nonoverlapping;
+
     unsafe {
         let array = MaybeUninit::<[u8; N]>::uninit();
         copy_nonoverlapping(slice.as_ptr(), array.as_ptr() as *mut u8, slice.len());
@@ -0,0 +1,119 @@
+//! Data frames coalesced into a single write.
+//!
+//! While the websocket is corked every data frame sent (e.g. several small orders) is encoded into
+//! the cork buffer rather than written to the stream, [`Websocket::flush_batch`] then hands all of
+//! them to the stream with a single write and flush, so a few messages cost one syscall and one TLS
+//! record instead of one each. Control frames and the urgent messages (see
+//! [`Websocket::send_text_urgent`]) are never corked. The cork buffer is also flushed before the close
+//! frame.
+//!
+//! ## Examples
+//! ```no_run
+//! use std::io::{Read, Write};
+//! use boomnet::ws::Websocket;
+//!
+//! fn place_all<S: Read + Write>(ws: &mut Websocket<S>, orders: &[&[u8]]) -> Result<(), boomnet::ws::Error> {
+//!     ws.set_corked(true)?;
+//!     for order in orders {
+//!         ws.send_text(true, Some(order))?;
+//!     }
+//!     ws.flush_batch()
+//! }
+//! ```
+
+use crate::ws::send_queue::FrameSink;
+use crate::ws::{Error, Websocket};
+use std::io::{Read, Write};
+
+impl<S> Websocket<S> {
+    /// Cork the websocket from the start, see [`Websocket::set_corked`].
+    pub fn with_corked_sends(mut self) -> Websocket<S> {
+        self.cork = Some(Vec::new());
+        self
+    }
+
+    /// Checks if the data frames are coalesced until [`Websocket::flush_batch`].
+    pub const fn is_corked(&self) -> bool {
+        self.cork.is_some()
+    }
+
+    /// Number of bytes of the corked frames not handed to the stream yet.
+    pub fn corked_bytes(&self) -> usize {
+        self.cork.as_ref().map_or(0, Vec::len)
+    }
+}
+
+impl<S: Read + Write> Websocket<S> {
+    /// Coalesce the data frames sent from now on until [`Websocket::flush_batch`], or flush the frames
+    /// corked so far and write every data frame straight away again.
+    pub fn set_corked(&mut self, corked: bool) -> Result<(), Error> {
+        match (corked, self.cork.is_some()) {
+            (true, false) => self.cork = Some(Vec::new()),
+            (false, true) => {
+                self.flush_batch()?;
+                self.cork = None;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Write the corked frames to the stream with a single write and flush, the websocket stays
+    /// corked. Does nothing if not corked.
+    pub fn flush_batch(&mut self) -> Result<(), Error> {
+        self.ensure_not_closed()?;
+        let Some(cork) = self.cork.as_mut().filter(|cork| !cork.is_empty()) else {
+            return Ok(());
+        };
+        let mut stream = self.send_queue.spill(&mut self.stream);
+        if let Err(err) = stream
+            .encode_frame(false, |stream| stream.write_all(cork))
+            .and_then(|()| stream.flush())
+        {
+            self.closed = true;
+            Err(err)?
+        }
+        cork.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ws::Websocket;
+    use crate::ws::tests::ThrottledStream;
+
+    #[test]
+    fn should_coalesce_corked_frames() {
+        let stream = ThrottledStream::unthrottled();
+        let (sent, writes, flushes) = (stream.written.clone(), stream.writes.clone(), stream.flushes.clone());
+        let mut ws = Websocket::new_with_handshake_complete(stream).with_corked_sends();
+        ws.send_text(true, Some(b"one")).unwrap();
+        ws.send_binary(true, Some(b"two")).unwrap();
+        ws.send_text_vectored(true, &[b"thr", b"ee"]).unwrap();
+        assert_eq!(0, writes.get());
+        // 6 bytes header of a client frame masked with the zero key
+        assert_eq!(3 * 6 + 11, ws.corked_bytes());
+
+        // control frames are never corked
+        ws.send_ping(Some(b"ping")).unwrap();
+        assert_eq!(b"ping", &sent.borrow()[6..]);
+        sent.borrow_mut().clear();
+        writes.set(0);
+        flushes.set(0);
+
+        ws.flush_batch().unwrap();
+        let batch = std::mem::take(&mut *sent.borrow_mut());
+        assert_eq!((1, 1), (writes.get(), flushes.get()));
+        assert_eq!(b"one", &batch[6..9]);
+        assert_eq!(b"two", &batch[15..18]);
+        assert_eq!(b"three", &batch[24..]);
+        assert_eq!(0, ws.corked_bytes());
+
+        ws.send_text(true, Some(b"four")).unwrap();
+        ws.set_corked(false).unwrap();
+        assert_eq!(b"four", &sent.borrow()[6..]);
+        ws.send_text(true, Some(b"five")).unwrap();
+        assert!(sent.borrow().ends_with(b"five"));
+    }
+}
@@ -1,9 +1,102 @@
 use crate::buffer::{BufferPoolRef, OwnedReadBuffer};
 use crate::util::into_array;
+#[cfg(feature = "deflate")]
+use crate::ws::deflate::Inflater;
+use crate::ws::error::Violation;
+use crate::ws::utf8::Utf8Validator;
 use crate::ws::{Error, WebsocketFrame, protocol};
 use std::io;
 use std::io::Read;
 
+/// Strategy applied by the frame decoder when it encounters a malformed frame header.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Recovery {
+    /// Fail the connection on the first malformed header (default).
+    #[default]
+    Strict,
+    /// Drop the offending bytes and scan forward for a plausible next frame header, giving up
+    /// with a protocol error once more than `max_scan` bytes have been discarded without a match.
+    Resync { max_scan: usize },
+}
+
+/// How closely incoming frames are checked against RFC 6455.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Validation {
+    /// Reject only what the decoder can not make sense of (RSV bits, reserved op codes, masked server
+    /// frames) with [`Error::Protocol`] (default).
+    #[default]
+    Lenient,
+    /// Additionally reject fragmented or oversized control frames, continuation frames out of sequence,
+    /// text messages and close reasons that are not valid UTF-8, invalid close codes and unmasked
+    /// client frames (server side), reporting every failure as a typed [`Error::Violation`]. Meant for
+    /// conformance testing (e.g. the Autobahn test suite) as the UTF-8 of every text message is checked.
+    Strict,
+}
+
+/// Side of the connection the websocket is on, client frames are masked while server frames are not.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Role {
+    #[default]
+    Client,
+    Server,
+}
+
+/// Upper bounds of the received data, exceeding any of them fails the websocket with
+/// [`Error::Protocol`] before the data is buffered. All of them are unlimited by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum payload of a single frame, also applied to the inflated payload of compressed frames.
+    pub max_frame_size: usize,
+    /// Maximum size of a data message summed over all of its fragments.
+    pub max_message_size: usize,
+    /// Maximum size of the handshake response (status line and headers).
+    pub max_handshake_response_size: usize,
+}
+
+impl Limits {
+    pub const UNLIMITED: Limits = Limits {
+        max_frame_size: usize::MAX,
+        max_message_size: usize::MAX,
+        max_handshake_response_size: usize::MAX,
+    };
+
+    pub const fn with_max_frame_size(self, max_frame_size: usize) -> Self {
+        Self { max_frame_size, ..self }
+    }
+
+    pub const fn with_max_message_size(self, max_message_size: usize) -> Self {
+        Self {
+            max_message_size,
+            ..self
+        }
+    }
+
+    pub const fn with_max_handshake_response_size(self, max_handshake_response_size: usize) -> Self {
+        Self {
+            max_handshake_response_size,
+            ..self
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::UNLIMITED
+    }
+}
+
+/// Decoder settings that can be chosen before the websocket handshake has completed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DecoderConfig {
+    pub recovery: Recovery,
+    pub validation: Validation,
+    pub role: Role,
+    pub limits: Limits,
+    /// `permessage-deflate` has been offered in the handshake request.
+    #[cfg(feature = "deflate")]
+    pub deflate: bool,
+}
+
 #[derive(Debug)]
 pub struct Decoder {
     buffer: OwnedReadBuffer<4096>,
@@ -12,6 +105,25 @@ pub struct Decoder {
     payload_length: usize,
     op_code: u8,
     needs_more_data: bool,
+    recovery: Recovery,
+    validation: Validation,
+    role: Role,
+    limits: Limits,
+    // size of the data message received so far, including the current frame
+    message_size: usize,
+    // a data message is missing its final fragment
+    fragmented: bool,
+    // the current data message is text, only validated with `Validation::Strict`
+    text: bool,
+    utf8: Utf8Validator,
+    masked: bool,
+    masking_key: [u8; 4],
+    resyncs: u64,
+    #[cfg(feature = "deflate")]
+    inflater: Option<Inflater>,
+    // the current message has been compressed by the server
+    #[cfg(feature = "deflate")]
+    compressed: bool,
 }
 
 #[derive(Debug)]
@@ -20,11 +132,13 @@ enum DecodeState {
     ReadingPayloadLength,
     ReadingExtendedPayloadLength2,
     ReadingExtendedPayloadLength8,
+    ReadingMaskingKey,
     ReadingPayload,
+    Resyncing { scanned: usize },
 }
 
 impl Decoder {
-    pub fn new(pool: &mut BufferPoolRef) -> Self {
+    pub fn new(pool: &mut BufferPoolRef, config: DecoderConfig) -> Self {
         Self {
             buffer: pool.acquire(),
             decode_state: DecodeState::ReadingHeader,
@@ -32,14 +146,78 @@ impl Decoder {
             op_code: 0,
             payload_length: 0,
             needs_more_data: true,
+            recovery: config.recovery,
+            validation: config.validation,
+            role: config.role,
+            limits: config.limits,
+            message_size: 0,
+            fragmented: false,
+            text: false,
+            utf8: Utf8Validator::default(),
+            masked: false,
+            masking_key: [0; 4],
+            resyncs: 0,
+            #[cfg(feature = "deflate")]
+            inflater: None,
+            #[cfg(feature = "deflate")]
+            compressed: false,
         }
     }
 
+    /// Inflate compressed messages once `permessage-deflate` has been negotiated.
+    #[cfg(feature = "deflate")]
+    pub fn with_inflater(self, inflater: Option<Inflater>) -> Self {
+        Self { inflater, ..self }
+    }
+
+    #[inline]
+    pub const fn set_recovery(&mut self, recovery: Recovery) {
+        self.recovery = recovery;
+    }
+
+    #[inline]
+    pub const fn set_validation(&mut self, validation: Validation) {
+        self.validation = validation;
+    }
+
+    #[inline]
+    pub const fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    #[inline]
+    pub const fn validation(&self) -> Validation {
+        self.validation
+    }
+
+    #[inline]
+    pub const fn role(&self) -> Role {
+        self.role
+    }
+
+    /// Number of times the decoder had to resync after a malformed frame header.
+    #[inline]
+    pub const fn resyncs(&self) -> u64 {
+        self.resyncs
+    }
+
+    /// Returns `true` if the next read will go to the network (and invalidate the decoded frames).
+    #[inline]
+    pub const fn needs_more_data(&self) -> bool {
+        self.needs_more_data
+    }
+
     #[inline]
     pub fn read<S: Read>(&mut self, stream: &mut S) -> io::Result<()> {
         if self.needs_more_data {
+            #[cfg(feature = "deflate")]
+            if let Some(inflater) = self.inflater.as_mut() {
+                inflater.recycle();
+            }
+            let available = self.buffer.available();
             self.buffer.read_all_from(stream)?;
-            self.needs_more_data = false;
+            // nothing has been read if the stream would block, the frame is still incomplete
+            self.needs_more_data = self.buffer.available() == available;
         }
         Ok(())
     }
@@ -54,14 +232,22 @@ impl Decoder {
                         // SAFETY: available > 0
                         let b = unsafe { self.buffer.consume_next_byte_unchecked() };
                         let fin = ((b & protocol::FIN_MASK) >> 7) == 1;
-                        let rsv1 = (b & protocol::RSV1_MASK) >> 6;
-                        let rsv2 = (b & protocol::RSV2_MASK) >> 5;
-                        let rsv3 = (b & protocol::RSV3_MASK) >> 4;
-                        if rsv1 + rsv2 + rsv3 != 0 {
-                            return Err(Error::Protocol("non zero RSV value received"));
+                        let op_code = b & protocol::OP_CODE_MASK;
+                        if !self.accept_reserved_bits(b, op_code) {
+                            self.malformed(Violation::ReservedBits)?;
+                            continue;
                         }
                         self.fin = fin;
-                        let op_code = b & protocol::OP_CODE_MASK;
+                        if !protocol::op::is_known(op_code) {
+                            self.malformed(Violation::ReservedOpCode(op_code))?;
+                            continue;
+                        }
+                        if self.validation == Validation::Strict {
+                            if let Some(violation) = self.out_of_sequence(op_code) {
+                                self.malformed(violation)?;
+                                continue;
+                            }
+                        }
                         self.op_code = op_code;
                         self.decode_state = DecodeState::ReadingPayloadLength
                     } else {
@@ -72,14 +258,32 @@ impl Decoder {
                     if available > 0 {
                         // SAFETY: available > 0
                         let b = unsafe { self.buffer.consume_next_byte_unchecked() };
-                        let mask = (b & protocol::MASK_MASK) >> 7;
-                        if mask == 1 {
-                            return Err(Error::Protocol("masking bit set on the server frame"));
+                        let masked = (b & protocol::MASK_MASK) >> 7 == 1;
+                        match self.role {
+                            Role::Client if masked => {
+                                self.malformed(Violation::MaskedServerFrame)?;
+                                continue;
+                            }
+                            Role::Server if !masked && self.validation == Validation::Strict => {
+                                self.malformed(Violation::UnmaskedClientFrame)?;
+                                continue;
+                            }
+                            _ => self.masked = masked,
                         }
                         let payload_length = b & protocol::PAYLOAD_LENGTH_MASK;
+                        if self.validation == Validation::Strict && protocol::op::is_control(self.op_code) {
+                            if !self.fin {
+                                self.malformed(Violation::FragmentedControlFrame)?;
+                                continue;
+                            }
+                            if payload_length > 125 {
+                                self.malformed(Violation::ControlFrameTooLarge(payload_length as usize))?;
+                                continue;
+                            }
+                        }
                         self.payload_length = payload_length as usize;
                         match payload_length {
-                            0..=125 => self.decode_state = DecodeState::ReadingPayload,
+                            0..=125 => self.payload_length_known()?,
                             126 => self.decode_state = DecodeState::ReadingExtendedPayloadLength2,
                             127 => self.decode_state = DecodeState::ReadingExtendedPayloadLength8,
                             // we only use 7 bits
@@ -96,7 +300,7 @@ impl Decoder {
                         // SAFETY: we know bytes length is 2
                         let payload_length = u16::from_be_bytes(unsafe { into_array(bytes) });
                         self.payload_length = payload_length as usize;
-                        self.decode_state = DecodeState::ReadingPayload;
+                        self.payload_length_known()?;
                     } else {
                         break;
                     }
@@ -107,7 +311,22 @@ impl Decoder {
                         let bytes = unsafe { self.buffer.consume_next_unchecked(8) };
                         // SAFETY: we know bytes length is 8
                         let payload_length = u64::from_be_bytes(unsafe { into_array(bytes) });
+                        if payload_length >> 63 != 0 {
+                            self.malformed(Violation::PayloadLengthOverflow)?;
+                            continue;
+                        }
                         self.payload_length = payload_length as usize;
+                        self.payload_length_known()?;
+                    } else {
+                        break;
+                    }
+                }
+                DecodeState::ReadingMaskingKey => {
+                    if available >= 4 {
+                        // SAFETY: available >= 4
+                        let bytes = unsafe { self.buffer.consume_next_unchecked(4) };
+                        // SAFETY: we know bytes length is 4
+                        self.masking_key = unsafe { into_array(bytes) };
                         self.decode_state = DecodeState::ReadingPayload;
                     } else {
                         break;
@@ -116,8 +335,25 @@ impl Decoder {
                 DecodeState::ReadingPayload => {
                     let payload_length = self.payload_length;
                     if available >= payload_length {
-                        // SAFETY: available >= payload_length
-                        let payload = unsafe { self.buffer.consume_next_unchecked(payload_length) };
+                        let payload: &'static [u8] = if self.masked {
+                            // SAFETY: available >= payload_length
+                            let payload = unsafe { self.buffer.consume_next_mut_unchecked(payload_length) };
+                            unmask(payload, self.masking_key);
+                            payload
+                        } else {
+                            // SAFETY: available >= payload_length
+                            unsafe { self.buffer.consume_next_unchecked(payload_length) }
+                        };
+                        #[cfg(feature = "deflate")]
+                        let payload = match self.inflater.as_mut() {
+                            Some(inflater) if self.compressed && !protocol::op::is_control(self.op_code) => {
+                                inflater.inflate(payload, self.fin, self.limits.max_frame_size)?
+                            }
+                            _ => payload,
+                        };
+                        if !protocol::op::is_control(self.op_code) {
+                            self.data_frame(payload)?;
+                        }
                         let frame = match self.op_code {
                             protocol::op::TEXT_FRAME => WebsocketFrame::Text(self.fin, payload),
                             protocol::op::BINARY_FRAME => WebsocketFrame::Binary(self.fin, payload),
@@ -133,6 +369,30 @@ impl Decoder {
                         break;
                     }
                 }
+                DecodeState::Resyncing { scanned } => {
+                    let Recovery::Resync { max_scan } = self.recovery else {
+                        return Err(Error::Protocol("resync attempted in strict mode"));
+                    };
+                    let budget = max_scan.saturating_sub(scanned);
+                    let view = self.buffer.view();
+                    let candidate = view
+                        .windows(2)
+                        .take(budget)
+                        .position(|header| plausible_header(header[0], header[1], self.compression(), self.role));
+                    // the last byte stays in the buffer as it can still start a header
+                    let skip = candidate.unwrap_or_else(|| view.len().saturating_sub(1).min(budget));
+                    // SAFETY: skip <= available
+                    let _ = unsafe { self.buffer.consume_next_unchecked(skip) };
+                    let scanned = scanned + skip;
+                    if candidate.is_some() {
+                        self.decode_state = DecodeState::ReadingHeader;
+                    } else if scanned >= max_scan {
+                        return Err(Error::Protocol("unable to resync after malformed frame header"));
+                    } else {
+                        self.decode_state = DecodeState::Resyncing { scanned };
+                        break;
+                    }
+                }
             }
         }
 
@@ -141,3 +401,412 @@ impl Decoder {
         Ok(None)
     }
 }
+
+impl Decoder {
+    /// Append the decoder state (settings, partially decoded frame header and the bytes not decoded
+    /// yet) so that another process can resume decoding the same connection.
+    pub fn export_state(&self, buf: &mut Vec<u8>) -> Result<(), Error> {
+        #[cfg(feature = "deflate")]
+        if self.inflater.is_some() {
+            return Err(Error::Protocol("permessage-deflate context can not be exported"));
+        }
+        if self.fragmented && self.validation == Validation::Strict {
+            return Err(Error::Protocol("fragmented message can not be exported in strict validation"));
+        }
+        let (decode_state, scanned) = match self.decode_state {
+            DecodeState::ReadingHeader => (0, 0),
+            DecodeState::ReadingPayloadLength => (1, 0),
+            DecodeState::ReadingExtendedPayloadLength2 => (2, 0),
+            DecodeState::ReadingExtendedPayloadLength8 => (3, 0),
+            DecodeState::ReadingMaskingKey => (4, 0),
+            DecodeState::ReadingPayload => (5, 0),
+            DecodeState::Resyncing { scanned } => (6, scanned),
+        };
+        let (recovery, max_scan) = match self.recovery {
+            Recovery::Strict => (0, 0),
+            Recovery::Resync { max_scan } => (1, max_scan),
+        };
+        buf.push(decode_state);
+        buf.extend_from_slice(&(scanned as u64).to_le_bytes());
+        buf.push(self.fin as u8);
+        buf.push(self.op_code);
+        buf.extend_from_slice(&(self.payload_length as u64).to_le_bytes());
+        buf.push(self.masked as u8);
+        buf.extend_from_slice(&self.masking_key);
+        buf.push(self.role as u8);
+        buf.push(self.validation as u8);
+        buf.push(recovery);
+        buf.extend_from_slice(&(max_scan as u64).to_le_bytes());
+        buf.extend_from_slice(self.buffer.view());
+        Ok(())
+    }
+
+    /// Create decoder from the state exported by [`Decoder::export_state`], the bytes not decoded yet
+    /// are decoded before the next network read.
+    pub fn import_state(pool: &mut BufferPoolRef, state: &[u8]) -> Result<Self, Error> {
+        const INVALID: Error = Error::Protocol("invalid decoder state");
+        let Some((header, mut buffered)) = state.split_first_chunk::<35>() else {
+            return Err(INVALID);
+        };
+        let u64_at = |offset: usize| u64::from_le_bytes(header[offset..offset + 8].try_into().unwrap()) as usize;
+        let decode_state = match header[0] {
+            0 => DecodeState::ReadingHeader,
+            1 => DecodeState::ReadingPayloadLength,
+            2 => DecodeState::ReadingExtendedPayloadLength2,
+            3 => DecodeState::ReadingExtendedPayloadLength8,
+            4 => DecodeState::ReadingMaskingKey,
+            5 => DecodeState::ReadingPayload,
+            6 => DecodeState::Resyncing { scanned: u64_at(1) },
+            _ => return Err(INVALID),
+        };
+        let role = match header[24] {
+            0 => Role::Client,
+            1 => Role::Server,
+            _ => return Err(INVALID),
+        };
+        let validation = match header[25] {
+            0 => Validation::Lenient,
+            1 => Validation::Strict,
+            _ => return Err(INVALID),
+        };
+        let recovery = match header[26] {
+            0 => Recovery::Strict,
+            1 => Recovery::Resync { max_scan: u64_at(27) },
+            _ => return Err(INVALID),
+        };
+        let config = DecoderConfig {
+            recovery,
+            validation,
+            role,
+            ..Default::default()
+        };
+        let mut decoder = Self::new(pool, config);
+        decoder.decode_state = decode_state;
+        decoder.fin = header[9] == 1;
+        decoder.op_code = header[10];
+        decoder.payload_length = u64_at(11);
+        decoder.masked = header[19] == 1;
+        decoder.masking_key = header[20..24].try_into().unwrap();
+        while !buffered.is_empty() {
+            decoder.buffer.read_all_from(&mut buffered)?;
+        }
+        decoder.needs_more_data = false;
+        Ok(decoder)
+    }
+
+    /// Move on to the masking key (or payload) once the payload length is known, fails if the frame
+    /// or the message it belongs to exceeds the limits so that the payload is never buffered.
+    #[inline]
+    fn payload_length_known(&mut self) -> Result<(), Error> {
+        if self.payload_length > self.limits.max_frame_size {
+            return Err(Error::Protocol("frame payload exceeds the maximum size"));
+        }
+        if !protocol::op::is_control(self.op_code) {
+            let message_size = match self.op_code {
+                protocol::op::CONTINUATION_FRAME => self.message_size.saturating_add(self.payload_length),
+                _ => self.payload_length,
+            };
+            if message_size > self.limits.max_message_size {
+                return Err(Error::Protocol("message exceeds the maximum size"));
+            }
+            self.message_size = if self.fin { 0 } else { message_size };
+        }
+        self.decode_state = self.after_payload_length();
+        Ok(())
+    }
+
+    /// Checks the data frame `op_code` continues the fragmented message if there is one, and only then.
+    #[inline]
+    const fn out_of_sequence(&self, op_code: u8) -> Option<Violation> {
+        match op_code {
+            protocol::op::CONTINUATION_FRAME if !self.fragmented => Some(Violation::UnexpectedContinuation),
+            protocol::op::TEXT_FRAME | protocol::op::BINARY_FRAME if self.fragmented => {
+                Some(Violation::ExpectedContinuation)
+            }
+            _ => None,
+        }
+    }
+
+    /// Track the fragmented message the data frame belongs to, the UTF-8 of the text messages is
+    /// validated with [`Validation::Strict`].
+    #[inline]
+    fn data_frame(&mut self, payload: &[u8]) -> Result<(), Error> {
+        if self.op_code != protocol::op::CONTINUATION_FRAME {
+            self.text = self.op_code == protocol::op::TEXT_FRAME;
+        }
+        self.fragmented = !self.fin;
+        if self.validation == Validation::Strict && self.text && !self.utf8.validate(payload, self.fin) {
+            self.decode_state = DecodeState::ReadingHeader;
+            return Err(Error::Violation(Violation::InvalidUtf8));
+        }
+        Ok(())
+    }
+
+    #[inline]
+    const fn after_payload_length(&self) -> DecodeState {
+        match self.masked {
+            true => DecodeState::ReadingMaskingKey,
+            false => DecodeState::ReadingPayload,
+        }
+    }
+
+    /// Checks the RSV bits of the frame header, RSV1 marks the first frame of a compressed message
+    /// once `permessage-deflate` has been negotiated.
+    #[inline]
+    #[cfg_attr(not(feature = "deflate"), allow(unused_variables))]
+    fn accept_reserved_bits(&mut self, b: u8, op_code: u8) -> bool {
+        let rsv = b & (protocol::RSV1_MASK | protocol::RSV2_MASK | protocol::RSV3_MASK);
+        #[cfg(feature = "deflate")]
+        if self.inflater.is_some() && matches!(op_code, protocol::op::TEXT_FRAME | protocol::op::BINARY_FRAME) {
+            self.compressed = rsv == protocol::RSV1_MASK;
+            return rsv & !protocol::RSV1_MASK == 0;
+        }
+        rsv == 0
+    }
+
+    #[inline]
+    const fn compression(&self) -> bool {
+        #[cfg(feature = "deflate")]
+        let compression = self.inflater.is_some();
+        #[cfg(not(feature = "deflate"))]
+        let compression = false;
+        compression
+    }
+
+    #[cold]
+    fn malformed(&mut self, violation: Violation) -> Result<(), Error> {
+        match (self.recovery, self.validation) {
+            (Recovery::Strict, Validation::Strict) => Err(Error::Violation(violation)),
+            (Recovery::Strict, Validation::Lenient) => Err(Error::Protocol(lenient_reason(violation))),
+            (Recovery::Resync { .. }, _) => {
+                self.resyncs += 1;
+                self.decode_state = DecodeState::Resyncing { scanned: 0 };
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Checks the close frame `payload` carries either no status or a status code allowed on the wire
+/// followed by the UTF-8 reason.
+pub fn validate_close(payload: &[u8]) -> Result<(), Violation> {
+    match payload.len() {
+        0 => Ok(()),
+        1 => Err(Violation::InvalidClosePayload(1)),
+        _ => {
+            let status_code = u16::from_be_bytes([payload[0], payload[1]]);
+            match status_code {
+                1000..=1003 | 1007..=1014 | 3000..=4999 => {}
+                _ => return Err(Violation::InvalidCloseCode(status_code)),
+            }
+            match std::str::from_utf8(&payload[2..]) {
+                Ok(_) => Ok(()),
+                Err(_) => Err(Violation::InvalidUtf8),
+            }
+        }
+    }
+}
+
+#[inline]
+const fn lenient_reason(violation: Violation) -> &'static str {
+    match violation {
+        Violation::ReservedBits => "non zero RSV value received",
+        Violation::ReservedOpCode(_) => "unknown op_code",
+        Violation::MaskedServerFrame => "masking bit set on the server frame",
+        Violation::UnmaskedClientFrame => "masking bit not set on the client frame",
+        Violation::PayloadLengthOverflow => "most significant bit of the payload length set",
+        _ => "malformed frame header",
+    }
+}
+
+/// Unmask the client frame `payload` in place (RFC 6455 section 5.3).
+#[inline]
+fn unmask(payload: &mut [u8], masking_key: [u8; 4]) {
+    if masking_key == [0; 4] {
+        return;
+    }
+    for (index, byte) in payload.iter_mut().enumerate() {
+        *byte ^= masking_key[index & 3];
+    }
+}
+
+/// Checks if the two bytes can start a valid frame header sent to the `role`, with `compression` the
+/// first frame of a data message can have RSV1 set.
+#[inline]
+const fn plausible_header(b0: u8, b1: u8, compression: bool, role: Role) -> bool {
+    let op_code = b0 & protocol::OP_CODE_MASK;
+    let rsv = b0 & (protocol::RSV1_MASK | protocol::RSV2_MASK | protocol::RSV3_MASK);
+    let compressed = compression
+        && rsv == protocol::RSV1_MASK
+        && matches!(op_code, protocol::op::TEXT_FRAME | protocol::op::BINARY_FRAME);
+    if rsv != 0 && !compressed {
+        return false;
+    }
+    let masked = b1 & protocol::MASK_MASK != 0;
+    if !protocol::op::is_known(op_code) || (masked && matches!(role, Role::Client)) {
+        return false;
+    }
+    // control frames can not be fragmented and carry at most 125 bytes
+    !protocol::op::is_control(op_code) || (b0 & protocol::FIN_MASK != 0 && b1 & protocol::PAYLOAD_LENGTH_MASK <= 125)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::default_buffer_pool_ref;
+
+    fn decoder(recovery: Recovery, bytes: &[u8]) -> Decoder {
+        decoder_with(
+            DecoderConfig {
+                recovery,
+                ..Default::default()
+            },
+            bytes,
+        )
+    }
+
+    fn decoder_with(config: DecoderConfig, mut bytes: &[u8]) -> Decoder {
+        let mut decoder = Decoder::new(&mut default_buffer_pool_ref(), config);
+        decoder.read(&mut bytes).unwrap();
+        decoder
+    }
+
+    #[test]
+    fn should_fail_on_malformed_header_in_strict_mode() {
+        let mut decoder = decoder(Recovery::Strict, &[0x71, 0x03, b'f', b'o', b'o']);
+        assert!(matches!(decoder.decode_next(), Err(Error::Protocol(_))));
+    }
+
+    #[test]
+    fn should_resync_to_next_frame() {
+        let bytes = [0x71, 0x03, 0xFF, 0xFF, 0x81, 0x03, b'f', b'o', b'o'];
+        let mut decoder = decoder(Recovery::Resync { max_scan: 16 }, &bytes);
+        match decoder.decode_next() {
+            Ok(Some(WebsocketFrame::Text(true, payload))) => assert_eq!(b"foo", payload),
+            _ => panic!("expected text frame"),
+        }
+        assert_eq!(1, decoder.resyncs());
+    }
+
+    #[test]
+    fn should_give_up_resync_after_max_scan() {
+        let bytes = [0x71, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x81, 0x03, b'f', b'o', b'o'];
+        let mut decoder = decoder(Recovery::Resync { max_scan: 4 }, &bytes);
+        assert!(matches!(decoder.decode_next(), Err(Error::Protocol(_))));
+    }
+
+    #[test]
+    fn should_report_typed_violations_in_strict_validation() {
+        let strict = DecoderConfig {
+            validation: Validation::Strict,
+            ..Default::default()
+        };
+        let mut decoder = decoder_with(strict, &[0x71, 0x00]);
+        assert!(matches!(decoder.decode_next(), Err(Error::Violation(Violation::ReservedBits))));
+
+        let mut decoder = decoder_with(strict, &[0x09, 0x00]);
+        assert!(matches!(decoder.decode_next(), Err(Error::Violation(Violation::FragmentedControlFrame))));
+
+        let mut decoder = decoder_with(strict, &[0x89, 0x7E, 0x00, 0x80]);
+        assert!(matches!(decoder.decode_next(), Err(Error::Violation(Violation::ControlFrameTooLarge(126)))));
+    }
+
+    #[test]
+    fn should_validate_fragmented_text_in_strict_validation() {
+        let strict = DecoderConfig {
+            validation: Validation::Strict,
+            ..Default::default()
+        };
+        // "€" split across the fragments with a ping in between
+        let bytes = [0x01, 0x02, b'a', 0xE2, 0x89, 0x00, 0x80, 0x02, 0x82, 0xAC];
+        let mut strict_decoder = decoder_with(strict, &bytes);
+        assert!(matches!(strict_decoder.decode_next(), Ok(Some(WebsocketFrame::Text(false, b"a\xE2")))));
+        assert!(matches!(strict_decoder.decode_next(), Ok(Some(WebsocketFrame::Ping(_)))));
+        assert!(matches!(strict_decoder.decode_next(), Ok(Some(WebsocketFrame::Continuation(true, _)))));
+
+        let mut strict_decoder = decoder_with(strict, &[0x81, 0x02, 0xC0, 0xAF]);
+        assert!(matches!(strict_decoder.decode_next(), Err(Error::Violation(Violation::InvalidUtf8))));
+
+        // binary messages are not validated
+        let mut strict_decoder = decoder_with(strict, &[0x82, 0x02, 0xC0, 0xAF]);
+        assert!(matches!(strict_decoder.decode_next(), Ok(Some(WebsocketFrame::Binary(true, _)))));
+
+        let mut strict_decoder = decoder_with(strict, &[0x80, 0x00]);
+        assert!(matches!(strict_decoder.decode_next(), Err(Error::Violation(Violation::UnexpectedContinuation))));
+
+        let mut strict_decoder = decoder_with(strict, &[0x01, 0x00, 0x81, 0x00]);
+        assert!(matches!(strict_decoder.decode_next(), Ok(Some(WebsocketFrame::Text(false, _)))));
+        assert!(matches!(strict_decoder.decode_next(), Err(Error::Violation(Violation::ExpectedContinuation))));
+
+        // the lenient validation does not check the text
+        let mut lenient_decoder = decoder(Recovery::Strict, &[0x81, 0x02, 0xC0, 0xAF]);
+        assert!(matches!(lenient_decoder.decode_next(), Ok(Some(WebsocketFrame::Text(true, _)))));
+    }
+
+    #[test]
+    fn should_accept_oversized_control_frame_in_lenient_validation() {
+        let mut bytes = vec![0x89, 0x7E, 0x00, 0x80];
+        bytes.extend_from_slice(&[0u8; 128]);
+        let mut decoder = decoder(Recovery::Strict, &bytes);
+        assert!(matches!(decoder.decode_next(), Ok(Some(WebsocketFrame::Ping(payload))) if payload.len() == 128));
+    }
+
+    #[test]
+    fn should_fail_frames_and_messages_over_the_limits() {
+        let limited = DecoderConfig {
+            limits: Limits::UNLIMITED.with_max_frame_size(4).with_max_message_size(6),
+            ..Default::default()
+        };
+        // the frame is rejected from its header, before the payload arrives
+        let mut decoder = decoder_with(limited, &[0x81, 0x05]);
+        assert!(matches!(decoder.decode_next(), Err(Error::Protocol("frame payload exceeds the maximum size"))));
+
+        // control frames interleaved with the fragments do not count towards the message
+        let bytes = [
+            0x01, 0x03, b'a', b'b', b'c', 0x89, 0x04, 0, 0, 0, 0, 0x80, 0x03, b'd', b'e', b'f',
+        ];
+        let mut decoder = decoder_with(limited, &bytes);
+        assert!(matches!(decoder.decode_next(), Ok(Some(WebsocketFrame::Text(false, b"abc")))));
+        assert!(matches!(decoder.decode_next(), Ok(Some(WebsocketFrame::Ping(_)))));
+        assert!(matches!(decoder.decode_next(), Ok(Some(WebsocketFrame::Continuation(true, b"def")))));
+
+        let bytes = [
+            0x01, 0x03, b'a', b'b', b'c', 0x00, 0x03, b'd', b'e', b'f', 0x80, 0x01, b'g',
+        ];
+        let mut decoder = decoder_with(limited, &bytes);
+        assert!(matches!(decoder.decode_next(), Ok(Some(WebsocketFrame::Text(false, b"abc")))));
+        assert!(matches!(decoder.decode_next(), Ok(Some(WebsocketFrame::Continuation(false, b"def")))));
+        assert!(matches!(decoder.decode_next(), Err(Error::Protocol("message exceeds the maximum size"))));
+    }
+
+    #[test]
+    #[cfg(feature = "deflate")]
+    fn should_inflate_compressed_message() {
+        use crate::ws::deflate::negotiate;
+
+        // compressed "Hello" followed by uncompressed "Hello" (RFC 7692 section 7.2.3)
+        let bytes = [0xc1, 0x07, 0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00, 0x81, 0x05];
+        let bytes = [&bytes[..], b"Hello"].concat();
+        let inflater = negotiate(true, Some("permessage-deflate")).unwrap();
+        let mut decoder = decoder_with(DecoderConfig::default(), &bytes).with_inflater(inflater);
+        for _ in 0..2 {
+            assert!(matches!(decoder.decode_next(), Ok(Some(WebsocketFrame::Text(true, b"Hello")))));
+        }
+
+        // RSV1 is only allowed on the first frame of a data message
+        let inflater = negotiate(true, Some("permessage-deflate")).unwrap();
+        let mut decoder = decoder_with(DecoderConfig::default(), &[0xc9, 0x00]).with_inflater(inflater);
+        assert!(matches!(decoder.decode_next(), Err(Error::Protocol(_))));
+    }
+
+    #[test]
+    fn should_validate_close_codes() {
+        assert_eq!(Ok(()), validate_close(&[]));
+        assert_eq!(Ok(()), validate_close(&1000u16.to_be_bytes()));
+        assert_eq!(Ok(()), validate_close(&4000u16.to_be_bytes()));
+        assert_eq!(Err(Violation::InvalidClosePayload(1)), validate_close(&[0x03]));
+        assert_eq!(Err(Violation::InvalidCloseCode(1005)), validate_close(&1005u16.to_be_bytes()));
+        assert_eq!(Err(Violation::InvalidCloseCode(999)), validate_close(&999u16.to_be_bytes()));
+        assert_eq!(Err(Violation::InvalidUtf8), validate_close(&[0x03, 0xE8, 0xC0, 0xAF]));
+    }
+}
@@ -0,0 +1,153 @@
+//! RFC 7692 `permessage-deflate` extension, inbound side only.
+//!
+//! The extension is offered in the handshake request and, if the server accepts it, compressed messages
+//! (RSV1 set on the first frame) are inflated by the decoder before they are handed out. Outbound frames
+//! are always sent uncompressed, which the RFC permits, so the send path stays copy free.
+
+use crate::ws::Error;
+use flate2::{Decompress, FlushDecompress};
+use std::ptr;
+
+/// Extension offer added to the handshake request. The client does not offer `client_max_window_bits`
+/// as it never compresses, the server window is inflated with the maximum (32KB) window.
+pub const OFFER: &str = "permessage-deflate";
+
+// appended to every message as the sender removes it (RFC 7692 section 7.2.2)
+const TRAILER: [u8; 4] = [0x00, 0x00, 0xFF, 0xFF];
+
+/// Inflates compressed messages into buffers that stay valid until the next network read, just
+/// like the payload views into the decoder buffer.
+#[derive(Debug)]
+pub struct Inflater {
+    decompress: Decompress,
+    no_context_takeover: bool,
+    // buffers handed out during the current batch
+    batch: Vec<Vec<u8>>,
+    spare: Vec<Vec<u8>>,
+}
+
+impl Inflater {
+    fn new(no_context_takeover: bool) -> Self {
+        Self {
+            decompress: Decompress::new(false),
+            no_context_takeover,
+            batch: Vec::new(),
+            spare: Vec::new(),
+        }
+    }
+
+    /// Inflate the frame `payload` of compressed message, `fin` marks the last frame of the message.
+    /// Fails as soon as the inflated payload grows beyond `max_size` bytes.
+    #[inline]
+    pub fn inflate(&mut self, payload: &[u8], fin: bool, max_size: usize) -> Result<&'static [u8], Error> {
+        let mut out = self.spare.pop().unwrap_or_default();
+        out.clear();
+        self.feed(payload, &mut out, max_size)?;
+        if fin {
+            self.feed(&TRAILER, &mut out, max_size)?;
+            if self.no_context_takeover {
+                self.decompress.reset(false);
+            }
+        }
+        // SAFETY: the heap buffer does not move when the batch grows and is only reused after the
+        // next network read, which invalidates the frames of the current batch anyway
+        let inflated = unsafe { &*ptr::slice_from_raw_parts(out.as_ptr(), out.len()) };
+        self.batch.push(out);
+        Ok(inflated)
+    }
+
+    /// Reclaim the buffers of the previous batch, must be called before the next network read.
+    #[inline]
+    pub fn recycle(&mut self) {
+        self.spare.append(&mut self.batch);
+    }
+
+    fn feed(&mut self, mut input: &[u8], out: &mut Vec<u8>, max_size: usize) -> Result<(), Error> {
+        loop {
+            if out.capacity() - out.len() < 1024 {
+                out.reserve((input.len() * 4).max(4096));
+            }
+            let total_in = self.decompress.total_in();
+            self.decompress
+                .decompress_vec(input, out, FlushDecompress::Sync)
+                .map_err(|_| Error::Protocol("invalid deflate stream"))?;
+            input = &input[(self.decompress.total_in() - total_in) as usize..];
+            if out.len() > max_size {
+                return Err(Error::Protocol("inflated frame payload exceeds the maximum size"));
+            }
+            // spare capacity left means the output has been fully flushed
+            if input.is_empty() && out.len() < out.capacity() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Validate the `Sec-WebSocket-Extensions` response header against the offer, returns the inflater
+/// if the server has accepted `permessage-deflate`.
+pub fn negotiate(offered: bool, extensions: Option<&str>) -> Result<Option<Inflater>, Error> {
+    let Some(extensions) = extensions else {
+        return Ok(None);
+    };
+    let mut inflater = None;
+    for extension in extensions.split(',') {
+        let mut params = extension.split(';').map(str::trim);
+        if params.next() != Some(OFFER) || !offered || inflater.is_some() {
+            return Err(Error::Protocol("server accepted extension that has not been offered"));
+        }
+        let mut no_context_takeover = false;
+        for param in params {
+            let (name, value) = param.split_once('=').unwrap_or((param, ""));
+            match (name.trim(), value.trim().trim_matches('"')) {
+                ("server_no_context_takeover", "") => no_context_takeover = true,
+                ("client_no_context_takeover", "") => {}
+                ("server_max_window_bits", bits) if matches!(bits.parse(), Ok(8..=15u8)) => {}
+                _ => return Err(Error::Protocol("invalid permessage-deflate parameter")),
+            }
+        }
+        inflater = Some(Inflater::new(no_context_takeover));
+    }
+    Ok(inflater)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_negotiate_deflate() {
+        assert!(negotiate(true, None).unwrap().is_none());
+        let inflater = negotiate(true, Some("permessage-deflate; server_no_context_takeover")).unwrap();
+        assert!(inflater.unwrap().no_context_takeover);
+        let inflater = negotiate(true, Some("permessage-deflate; server_max_window_bits=10")).unwrap();
+        assert!(!inflater.unwrap().no_context_takeover);
+
+        assert!(negotiate(false, Some("permessage-deflate")).is_err());
+        assert!(negotiate(true, Some("x-webkit-deflate-frame")).is_err());
+        assert!(negotiate(true, Some("permessage-deflate; client_max_window_bits=10")).is_err());
+        assert!(negotiate(true, Some("permessage-deflate; server_max_window_bits=16")).is_err());
+    }
+
+    #[test]
+    fn should_inflate_with_shared_context() {
+        // examples from RFC 7692 section 7.2.3
+        let mut inflater = negotiate(true, Some("permessage-deflate")).unwrap().unwrap();
+        let first = inflater
+            .inflate(&[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00], true, usize::MAX)
+            .unwrap();
+        let second = inflater
+            .inflate(&[0xf2, 0x00, 0x11, 0x00, 0x00], true, usize::MAX)
+            .unwrap();
+        assert_eq!(b"Hello", first);
+        assert_eq!(b"Hello", second);
+
+        inflater.recycle();
+        let head = inflater
+            .inflate(&[0xf2, 0x48, 0xcd], false, usize::MAX)
+            .unwrap()
+            .to_vec();
+        let tail = inflater.inflate(&[0xc9, 0xc9, 0x07, 0x00], true, usize::MAX).unwrap();
+        assert_eq!(b"Hello", [head.as_slice(), tail].concat().as_slice());
+        assert!(inflater.inflate(&[0xf2, 0x00, 0x11, 0x00, 0x00], true, 4).is_err());
+    }
+}
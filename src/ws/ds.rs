@@ -1,4 +1,4 @@
-use crate::ws::{Error, State, Websocket, WebsocketFrame};
+use crate::ws::{Error, Websocket, WebsocketFrame};
 use std::io;
 
 pub trait DataSource {
@@ -24,11 +24,7 @@ impl<D: DataSource> Websocket<DataSourceStream<D>> {
 
 impl<D: DataSource> Websocket<D> {
     pub fn from_data_source(data_source: D) -> io::Result<Websocket<DataSourceStream<D>>> {
-        Ok(Websocket {
-            stream: data_source.into_stream(),
-            closed: false,
-            state: State::connection(Default::default()),
-        })
+        Ok(Websocket::new_with_handshake_complete(data_source.into_stream()))
     }
 }
 
@@ -1,44 +1,154 @@
 use std::io;
-use std::io::Write;
+use std::io::{ErrorKind, IoSlice, Write};
 
 use crate::ws::protocol;
 
-#[inline]
+/// Maximum number of payload parts accepted by [`send_vectored_masked`], one more slice is taken by the header.
+pub const MAX_VECTORED_PARTS: usize = 15;
+
+// 1 byte of flags and op code, 1 byte of payload length, up to 8 bytes of extended payload length
+// and 4 bytes of masking key
+const MAX_HEADER_LEN: usize = 14;
+
+// payload masked with non-zero key is written in chunks of this size, multiple of the key length
+const MASK_CHUNK_LEN: usize = 4096;
+
+const ZERO_KEY: [u8; 4] = [0; 4];
+
+/// Client frame masked with the zero key, as sent by default.
+#[cfg(test)]
 pub fn send<S: Write>(stream: &mut S, fin: bool, op_code: u8, body: Option<&[u8]>) -> io::Result<()> {
-    let mut header = 0u8;
-    if fin {
-        header |= protocol::FIN_MASK;
-    }
-    header |= op_code;
-    stream.write_all(&header.to_be_bytes())?;
-    let mut payload_length = 0u8;
-    payload_length |= protocol::MASK_MASK;
-    if let Some(body) = body {
-        let len = body.len();
-        if len <= 125 {
-            payload_length |= len as u8;
-            stream.write_all(&payload_length.to_be_bytes())?;
-        } else if len <= u16::MAX as usize {
-            payload_length |= 126;
-            let extended_payload_length = len as u16;
-            stream.write_all(&payload_length.to_be_bytes())?;
-            stream.write_all(&extended_payload_length.to_be_bytes())?;
-        } else if len <= u64::MAX as usize {
-            payload_length |= 127;
-            let extended_payload_length = len as u64;
-            stream.write_all(&payload_length.to_be_bytes())?;
-            stream.write_all(&extended_payload_length.to_be_bytes())?;
-        }
-    } else {
-        stream.write_all(&payload_length.to_be_bytes())?;
-    }
-    let masking_key = 0u32;
-    stream.write_all(&masking_key.to_be_bytes())?;
-    if let Some(body) = body {
+    send_masked(stream, Some(ZERO_KEY), fin, op_code, body)
+}
+
+/// Send frame masked with the `key`, or unmasked if there is none (server frames are never masked,
+/// RFC 6455 section 5.1).
+#[inline]
+pub fn send_masked<S: Write>(
+    stream: &mut S,
+    key: Option<[u8; 4]>,
+    fin: bool,
+    op_code: u8,
+    body: Option<&[u8]>,
+) -> io::Result<()> {
+    let body = body.unwrap_or_default();
+    let (header, header_len) = header(key, fin, op_code, body.len());
+    stream.write_all(&header[..header_len])?;
+    match key {
+        Some(key) if key != ZERO_KEY => write_masked(stream, key, 0, body)?,
         // we can send plain text as masking key is set to zero on purpose
         // this is done for performance reason as it will make XOR no-op
-        stream.write_all(body)?;
+        _ => stream.write_all(body)?,
     }
     stream.flush()?;
     Ok(())
 }
+
+/// Send frame masked with the `key` with the header and each of the payload `parts` passed to the
+/// stream as separate slices of a single vectored write, so the payload is never copied into a
+/// contiguous buffer (unless masked with non-zero key). At most [`MAX_VECTORED_PARTS`] parts are
+/// accepted.
+#[inline]
+pub fn send_vectored_masked<S: Write>(
+    stream: &mut S,
+    key: Option<[u8; 4]>,
+    fin: bool,
+    op_code: u8,
+    parts: &[&[u8]],
+) -> io::Result<()> {
+    if parts.len() > MAX_VECTORED_PARTS {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "too many payload parts"));
+    }
+    let len = parts.iter().map(|part| part.len()).sum();
+    let (header, header_len) = header(key, fin, op_code, len);
+    if let Some(key) = key.filter(|key| *key != ZERO_KEY) {
+        // the parts are copied in order to be masked anyway
+        stream.write_all(&header[..header_len])?;
+        let mut offset = 0;
+        for part in parts {
+            write_masked(stream, key, offset, part)?;
+            offset += part.len();
+        }
+        stream.flush()?;
+        return Ok(());
+    }
+    let mut slices = [IoSlice::new(&[]); MAX_VECTORED_PARTS + 1];
+    slices[0] = IoSlice::new(&header[..header_len]);
+    for (slice, part) in slices[1..].iter_mut().zip(parts) {
+        *slice = IoSlice::new(part);
+    }
+    write_all_vectored(stream, &mut slices[..parts.len() + 1])?;
+    stream.flush()?;
+    Ok(())
+}
+
+#[inline]
+fn header(key: Option<[u8; 4]>, fin: bool, op_code: u8, len: usize) -> ([u8; MAX_HEADER_LEN], usize) {
+    let mut header = [0u8; MAX_HEADER_LEN];
+    header[0] = op_code;
+    if fin {
+        header[0] |= protocol::FIN_MASK;
+    }
+    if key.is_some() {
+        header[1] |= protocol::MASK_MASK;
+    }
+    let mut header_len = 2;
+    if len <= 125 {
+        header[1] |= len as u8;
+    } else if len <= u16::MAX as usize {
+        header[1] |= 126;
+        header[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+        header_len += 2;
+    } else {
+        header[1] |= 127;
+        header[2..10].copy_from_slice(&(len as u64).to_be_bytes());
+        header_len += 8;
+    }
+    if let Some(key) = key {
+        header[header_len..header_len + 4].copy_from_slice(&key);
+        header_len += 4;
+    }
+    (header, header_len)
+}
+
+/// Write the `payload` masked with the `key`, the `offset` is the position of the payload within the
+/// frame payload (RFC 6455 section 5.3).
+#[inline]
+fn write_masked<S: Write>(stream: &mut S, key: [u8; 4], offset: usize, payload: &[u8]) -> io::Result<()> {
+    let key = rotate(key, offset);
+    let mut chunk = [0u8; MASK_CHUNK_LEN];
+    for part in payload.chunks(MASK_CHUNK_LEN) {
+        for (index, (masked, byte)) in chunk.iter_mut().zip(part).enumerate() {
+            *masked = byte ^ key[index & 3];
+        }
+        stream.write_all(&chunk[..part.len()])?;
+    }
+    Ok(())
+}
+
+/// Key aligned to the payload starting at the `offset`.
+#[inline]
+const fn rotate(key: [u8; 4], offset: usize) -> [u8; 4] {
+    let shift = offset & 3;
+    [
+        key[shift],
+        key[(shift + 1) & 3],
+        key[(shift + 2) & 3],
+        key[(shift + 3) & 3],
+    ]
+}
+
+// equivalent of the unstable `Write::write_all_vectored`
+#[inline]
+fn write_all_vectored<S: Write>(stream: &mut S, mut slices: &mut [IoSlice<'_>]) -> io::Result<()> {
+    IoSlice::advance_slices(&mut slices, 0);
+    while !slices.is_empty() {
+        match stream.write_vectored(slices) {
+            Ok(0) => return Err(io::Error::new(ErrorKind::WriteZero, "failed to write whole frame")),
+            Ok(written) => IoSlice::advance_slices(&mut slices, written),
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
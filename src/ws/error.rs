@@ -1,5 +1,6 @@
 use std::array::TryFromSliceError;
 use std::io;
+use std::time::Duration;
 use thiserror::Error;
 use url::ParseError;
 
@@ -9,8 +10,22 @@ pub enum Error {
     ReceivedCloseFrame(u16, String),
     #[error("websocket protocol error: {0}")]
     Protocol(&'static str),
+    #[error("websocket protocol violation: {0}")]
+    Violation(#[from] Violation),
     #[error("the websocket is closed and can be dropped")]
     Closed,
+    #[error("the websocket is closing after flushing pending frames, no more frames can be sent")]
+    Closing,
+    #[error("no pong received for {0} consecutive keepalive intervals")]
+    KeepaliveTimeout(u32),
+    #[error("websocket handshake not completed within {0:?}")]
+    HandshakeTimeout(Duration),
+    #[error("no data received before the read deadline")]
+    ReadTimeout,
+    #[error("formatted message exceeds the {0} bytes format buffer")]
+    FmtBufferFull(usize),
+    #[error("websocket upgrade redirected to {0}")]
+    Redirect(String),
     #[error("IO error: {0}")]
     IO(#[from] io::Error),
     #[error("url parse error: {0}")]
@@ -24,3 +39,43 @@ impl From<Error> for io::Error {
         io::Error::other(value)
     }
 }
+
+/// RFC 6455 violations reported when the websocket runs with [`Validation::Strict`](crate::ws::Validation::Strict).
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    #[error("non zero RSV value received")]
+    ReservedBits,
+    #[error("reserved op_code {0:#x} received")]
+    ReservedOpCode(u8),
+    #[error("masking bit set on the server frame")]
+    MaskedServerFrame,
+    #[error("masking bit not set on the client frame")]
+    UnmaskedClientFrame,
+    #[error("most significant bit of the payload length set")]
+    PayloadLengthOverflow,
+    #[error("control frame payload of {0} bytes exceeds 125 bytes")]
+    ControlFrameTooLarge(usize),
+    #[error("fragmented control frame received")]
+    FragmentedControlFrame,
+    #[error("invalid close frame payload of {0} bytes")]
+    InvalidClosePayload(usize),
+    #[error("invalid close status code {0}")]
+    InvalidCloseCode(u16),
+    #[error("continuation frame received with no fragmented message in progress")]
+    UnexpectedContinuation,
+    #[error("new data frame received while a fragmented message is in progress")]
+    ExpectedContinuation,
+    #[error("text message or close reason is not valid UTF-8")]
+    InvalidUtf8,
+}
+
+impl Violation {
+    /// Status code of the close frame that fails the connection, `1007` for the invalid UTF-8 and
+    /// `1002` for everything else.
+    pub const fn status_code(&self) -> u16 {
+        match self {
+            Violation::InvalidUtf8 => 1007,
+            _ => 1002,
+        }
+    }
+}
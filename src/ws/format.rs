@@ -0,0 +1,113 @@
+//! Text messages formatted straight into a buffer owned by the websocket.
+//!
+//! [`Websocket::send_text_fmt`] takes the [`format_args!`] of the message (e.g. a JSON subscription
+//! or order) and formats it into the format buffer of the websocket, which is reused by every message,
+//! so no intermediate `String` is allocated on the hot path once the buffer has grown to the largest
+//! message. [`Websocket::try_send_text_fmt`] never grows the buffer and fails instead, the buffer is
+//! then sized upfront with [`Websocket::with_fmt_buffer_capacity`].
+//!
+//! ## Examples
+//! ```no_run
+//! use std::io::{Read, Write};
+//! use boomnet::ws::Websocket;
+//!
+//! fn place<S: Read + Write>(ws: &mut Websocket<S>, id: u64, price: f64) -> Result<(), boomnet::ws::Error> {
+//!     ws.send_text_fmt(format_args!(r#"{{"id":{id},"method":"order.place","params":{{"price":"{price}"}}}}"#))
+//! }
+//! ```
+
+use crate::ws::{Error, Websocket};
+use std::fmt;
+use std::io::{Read, Write};
+
+impl<S> Websocket<S> {
+    /// Reserve `capacity` bytes for the messages formatted by [`Websocket::send_text_fmt`] and
+    /// [`Websocket::try_send_text_fmt`] (the buffer is empty by default).
+    pub fn with_fmt_buffer_capacity(mut self, capacity: usize) -> Websocket<S> {
+        self.fmt_buffer = Vec::with_capacity(capacity);
+        self
+    }
+}
+
+impl<S: Read + Write> Websocket<S> {
+    /// Send complete text message formatted from the `args` into the format buffer, which grows if
+    /// the message does not fit.
+    #[inline]
+    pub fn send_text_fmt(&mut self, args: fmt::Arguments<'_>) -> Result<(), Error> {
+        self.send_fmt(args, true)
+    }
+
+    /// Same as [`Websocket::send_text_fmt`] without ever growing the format buffer, fails with
+    /// [`Error::FmtBufferFull`] (without sending anything) if the message does not fit its capacity.
+    #[inline]
+    pub fn try_send_text_fmt(&mut self, args: fmt::Arguments<'_>) -> Result<(), Error> {
+        self.send_fmt(args, false)
+    }
+
+    #[inline]
+    fn send_fmt(&mut self, args: fmt::Arguments<'_>, grow: bool) -> Result<(), Error> {
+        // message without arguments needs no formatting
+        if let Some(message) = args.as_str() {
+            if !grow && message.len() > self.fmt_buffer.capacity() {
+                return Err(Error::FmtBufferFull(self.fmt_buffer.capacity()));
+            }
+            return self.send_text(true, Some(message.as_bytes()));
+        }
+        // taken for the duration of the send, moving the buffer does not allocate
+        let mut buffer = std::mem::take(&mut self.fmt_buffer);
+        buffer.clear();
+        let capacity = buffer.capacity();
+        let mut writer = FmtBuffer { buffer, grow };
+        let result = match fmt::write(&mut writer, args) {
+            Ok(()) => self.send_text(true, Some(&writer.buffer)),
+            Err(_) => Err(Error::FmtBufferFull(capacity)),
+        };
+        self.fmt_buffer = writer.buffer;
+        result
+    }
+}
+
+/// Formatting target that only grows the buffer if allowed to.
+struct FmtBuffer {
+    buffer: Vec<u8>,
+    grow: bool,
+}
+
+impl fmt::Write for FmtBuffer {
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if !self.grow && self.buffer.len() + s.len() > self.buffer.capacity() {
+            return Err(fmt::Error);
+        }
+        self.buffer.extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ws::tests::ThrottledStream;
+    use crate::ws::{Error, Websocket};
+
+    #[test]
+    fn should_send_formatted_text() {
+        let stream = ThrottledStream::unthrottled();
+        let sent = stream.written.clone();
+        let mut ws = Websocket::new_with_handshake_complete(stream).with_fmt_buffer_capacity(16);
+        let (id, price) = (7, 100.5);
+        ws.send_text_fmt(format_args!(r#"{{"id":{id},"price":{price}}}"#))
+            .unwrap();
+        // masked with the zero key, so the payload follows the 6 bytes header as is
+        assert_eq!(br#"{"id":7,"price":100.5}"#, &sent.borrow()[6..]);
+
+        sent.borrow_mut().clear();
+        ws.try_send_text_fmt(format_args!("{id}")).unwrap();
+        assert_eq!(b"7", &sent.borrow()[6..]);
+
+        // the buffer has grown to fit the first message but not this one
+        sent.borrow_mut().clear();
+        let long = "x".repeat(64);
+        assert!(matches!(ws.try_send_text_fmt(format_args!("{long}")), Err(Error::FmtBufferFull(_))));
+        assert!(sent.borrow().is_empty());
+    }
+}
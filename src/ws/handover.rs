@@ -0,0 +1,146 @@
+//! Export and import of the websocket protocol state, used to hand a live connection over to another
+//! process (see [`handover`](crate::stream::handover)).
+
+use crate::buffer::default_buffer_pool_ref;
+use crate::ws::Error::{Closing, Protocol};
+use crate::ws::decoder::Decoder;
+use crate::ws::{Error, State, Websocket};
+use std::io::{Read, Write};
+
+// version 1 had no subprotocol, it is still accepted from a process running the previous release
+const VERSION: u8 = 2;
+
+impl<S: Read + Write> Websocket<S> {
+    /// Export the protocol state so that another process can resume the connection with
+    /// [`Websocket::import_state`] on the same socket. Flushes the pending pong and the corked frames
+    /// (see [`Websocket::flush_batch`]), the frames not decoded yet are part of the state. Fails if the
+    /// handshake is pending, the websocket is closing, a fragmented message is being reassembled, frames
+    /// are queued in the priority lanes or the send queue (once drained as far as the stream allows) or
+    /// `permessage-deflate` has been negotiated (the inflate context can not be exported). The websocket
+    /// must not be used once the state has been exported.
+    ///
+    /// ## Examples
+    /// ```no_run
+    /// use boomnet::stream::handover::HandoverSender;
+    /// use boomnet::stream::tcp::TcpStream;
+    /// use boomnet::ws::Websocket;
+    ///
+    /// fn hand_over(mut ws: Websocket<TcpStream>, sender: &mut HandoverSender) -> std::io::Result<()> {
+    ///     let state = ws.export_state()?;
+    ///     sender.send("binance-spot", ws.stream(), &state)
+    /// }
+    /// ```
+    pub fn export_state(&mut self) -> Result<Vec<u8>, Error> {
+        self.ensure_not_closed()?;
+        if self.closing {
+            return Err(Closing);
+        }
+        self.flush_batch()?;
+        self.flush_pong()?;
+        self.drain_send_queue()?;
+        let State::Connection(decoder) = &self.state else {
+            return Err(Protocol("websocket handshake is pending"));
+        };
+        if self
+            .reassembler
+            .as_ref()
+            .is_some_and(|reassembler| reassembler.in_progress())
+        {
+            return Err(Protocol("partially reassembled message can not be exported"));
+        }
+        if !self.outbound.is_empty() || !self.send_queue.is_empty() {
+            return Err(Protocol("queued frames can not be exported"));
+        }
+        let protocol = self.protocol.as_deref().unwrap_or_default();
+        let Ok(len) = u8::try_from(protocol.len()) else {
+            return Err(Protocol("websocket subprotocol too long to export"));
+        };
+        let mut state = vec![VERSION, len];
+        state.extend_from_slice(protocol.as_bytes());
+        decoder.export_state(&mut state)?;
+        Ok(state)
+    }
+}
+
+impl<S> Websocket<S> {
+    /// Resume the websocket from the `state` exported by the previous process on the handed over
+    /// `stream`. Settings that are not part of the protocol state (e.g. keepalive, half-close,
+    /// reassembly, size limits, masking key provider or middleware) have to be applied again.
+    pub fn import_state(stream: S, state: &[u8]) -> Result<Websocket<S>, Error> {
+        let (protocol, state) = match state.split_first() {
+            Some((&1, state)) => (None, state),
+            Some((&VERSION, [len, state @ ..])) if state.len() >= *len as usize => {
+                let (protocol, state) = state.split_at(*len as usize);
+                let protocol = std::str::from_utf8(protocol).map_err(|_| Protocol("invalid websocket subprotocol"))?;
+                ((!protocol.is_empty()).then(|| protocol.to_owned()), state)
+            }
+            _ => return Err(Protocol("unknown websocket state version")),
+        };
+        let decoder = Decoder::import_state(&mut default_buffer_pool_ref(), state)?;
+        Ok(Websocket {
+            protocol,
+            ..Self::from_state(stream, State::Connection(decoder))
+        })
+    }
+
+    /// Underlying stream, e.g. to hand its socket over to another process.
+    pub const fn stream(&self) -> &S {
+        &self.stream
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ws::{Websocket, WebsocketFrame, protocol};
+    use std::io;
+    use std::io::ErrorKind::WouldBlock;
+    use std::io::{Read, Write};
+
+    struct Socket(io::Cursor<Vec<u8>>);
+
+    impl Read for Socket {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.read(buf)? {
+                0 => Err(io::Error::from(WouldBlock)),
+                read => Ok(read),
+            }
+        }
+    }
+
+    impl Write for Socket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_resume_decoding_in_new_process() {
+        let text = protocol::FIN_MASK | protocol::op::TEXT_FRAME;
+        // second frame split across the handover, third one not decoded yet
+        let inbound = [&[text, 1, b'a', text, 3, b'b'][..], &[text, 1, b'c']].concat();
+        let mut ws = Websocket::new_with_handshake_complete(Socket(io::Cursor::new(inbound[..6].to_vec())));
+        let mut batch = ws.read_batch().unwrap();
+        assert!(matches!(batch.receive_next(), Some(Ok(WebsocketFrame::Text(true, b"a")))));
+        assert!(batch.receive_next().is_none());
+        let state = ws.export_state().unwrap();
+
+        // the rest of the second frame arrives on the socket after the handover
+        let socket = Socket(io::Cursor::new([&b"bb"[..], &inbound[6..]].concat()));
+        let mut ws = Websocket::import_state(socket, &state).unwrap();
+        let mut texts = vec![];
+        // the first batch only holds the partial frame carried over, the second one reads the socket
+        for _ in 0..2 {
+            for frame in ws.read_batch().unwrap() {
+                if let WebsocketFrame::Text(_, body) = frame.unwrap() {
+                    texts.push(body.to_vec());
+                }
+            }
+        }
+        assert_eq!(vec![b"bbb".to_vec(), b"c".to_vec()], texts);
+        assert!(Websocket::import_state(Socket(io::Cursor::new(vec![])), &[2]).is_err());
+    }
+}
@@ -1,4 +1,5 @@
 use crate::buffer::{BufferPoolRef, OwnedReadBuffer};
+use crate::service::time::TimeSource;
 use crate::ws::Error;
 use crate::ws::handshake::HandshakeState::{Completed, NotStarted, PendingResponse};
 use HandshakeState::PendingRequest;
@@ -7,10 +8,11 @@ use base64::engine::general_purpose;
 use http::StatusCode;
 use httparse::Response;
 use rand::{Rng, rng};
-use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
 use std::io;
-use std::io::ErrorKind::WouldBlock;
+use std::io::ErrorKind::{InvalidData, InvalidInput, WouldBlock};
 use std::io::{Cursor, Read, Write};
+use std::time::Duration;
 
 #[derive(Debug)]
 pub struct Handshaker {
@@ -18,9 +20,16 @@ pub struct Handshaker {
     outbound_buffer: Cursor<Vec<u8>>,
     bytes_sent: usize,
     state: HandshakeState,
+    // the last read has received part of the response
+    received: bool,
     server_name: String,
     endpoint: String,
-    pending_msg_buffer: VecDeque<(u8, bool, Option<Vec<u8>>)>,
+    headers: Vec<(String, String)>,
+    protocols: Vec<String>,
+    extensions: Option<String>,
+    protocol: Option<String>,
+    response: Option<HandshakeResponse>,
+    max_response_size: usize,
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -38,16 +47,79 @@ impl Handshaker {
             outbound_buffer: Cursor::new(Vec::with_capacity(1024)),
             bytes_sent: 0,
             state: NotStarted,
+            received: false,
             server_name: server_name.to_string(),
             endpoint: endpoint.to_string(),
-            pending_msg_buffer: VecDeque::with_capacity(256),
+            headers: Vec::new(),
+            protocols: Vec::new(),
+            extensions: None,
+            protocol: None,
+            response: None,
+            max_response_size: usize::MAX,
         }
     }
 
+    /// Additional request header, only takes effect if the request has not been prepared yet.
+    pub fn add_header(&mut self, name: &str, value: &str) {
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+
+    /// Subprotocol offered in the request, in order of preference. Only takes effect if the request has
+    /// not been prepared yet.
+    pub fn add_protocol(&mut self, protocol: &str) {
+        self.protocols.push(protocol.to_string());
+    }
+
+    /// Fail the handshake once the response grows beyond `max_response_size` bytes.
+    pub fn set_max_response_size(&mut self, max_response_size: usize) {
+        self.max_response_size = max_response_size;
+    }
+
+    /// Subprotocol selected by the server (`Sec-WebSocket-Protocol` response header), if any.
+    pub fn take_protocol(&mut self) -> Option<String> {
+        self.protocol.take()
+    }
+
+    /// Response to the upgrade request, available once the handshake has completed or the server has
+    /// rejected the upgrade.
+    pub fn response(&self) -> Option<&HandshakeResponse> {
+        self.response.as_ref()
+    }
+
+    /// Request the `endpoint` instead of the one the handshake has been created with, only takes effect
+    /// if the request has not been prepared yet.
+    pub fn set_endpoint(&mut self, endpoint: &str) {
+        self.endpoint = endpoint.to_string();
+    }
+
+    /// Path and query requested in the upgrade.
+    #[cfg(feature = "http")]
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    pub fn take_response(&mut self) -> Option<HandshakeResponse> {
+        self.response.take()
+    }
+
+    /// Extensions accepted by the server (`Sec-WebSocket-Extensions` response header), if any.
+    #[cfg(feature = "deflate")]
+    pub fn extensions(&self) -> Option<&str> {
+        self.extensions.as_deref()
+    }
+
+    /// Checks if the handshake can only progress once more of the response has been received, i.e. the
+    /// request has been sent and the last read has not received anything.
+    pub const fn needs_more_data(&self) -> bool {
+        matches!(self.state, PendingResponse) && !self.received
+    }
+
     #[cold]
     pub fn read<S: Read>(&mut self, stream: &mut S) -> io::Result<()> {
         if self.state == PendingResponse {
+            let available = self.inbound_buffer.available();
             self.inbound_buffer.read_from(stream)?;
+            self.received = self.inbound_buffer.available() > available;
         }
         Ok(())
     }
@@ -74,15 +146,43 @@ impl Handshaker {
             }
             PendingResponse => {
                 let available = self.inbound_buffer.available();
-                if available >= 4 && self.inbound_buffer.view_last(4) == b"\r\n\r\n" {
-                    // decode http response
-                    let mut headers = [httparse::EMPTY_HEADER; 64];
-                    let mut response = Response::new(&mut headers);
-                    response.parse(self.inbound_buffer.view()).map_err(io::Error::other)?;
-                    if response.code.unwrap() != StatusCode::SWITCHING_PROTOCOLS.as_u16() {
-                        let reason = response.reason.unwrap_or_default();
-                        return Err(io::Error::other(format!("unable to switch protocols, reason: {}", reason)));
+                if available > self.max_response_size {
+                    return Err(io::Error::new(InvalidData, "handshake response exceeds the maximum size"));
+                }
+                // decode http response once the header has been received
+                let view = self.inbound_buffer.view();
+                let mut headers = [httparse::EMPTY_HEADER; 64];
+                let mut response = Response::new(&mut headers);
+                let header_len = match response.parse(view).map_err(io::Error::other)? {
+                    httparse::Status::Complete(header_len) => header_len,
+                    httparse::Status::Partial => return Err(io::Error::from(WouldBlock)),
+                };
+                let status = response.code.unwrap();
+                if status != StatusCode::SWITCHING_PROTOCOLS.as_u16() {
+                    // the body explains the rejection, wait for all of it if its length is known
+                    let content_length = response
+                        .headers
+                        .iter()
+                        .find(|header| header.name.eq_ignore_ascii_case("Content-Length"))
+                        .and_then(|header| std::str::from_utf8(header.value).ok()?.trim().parse::<usize>().ok());
+                    let body_end = content_length.map_or(view.len(), |len| header_len.saturating_add(len));
+                    if body_end > view.len() {
+                        return Err(io::Error::from(WouldBlock));
                     }
+                    let reason = response.reason.unwrap_or_default();
+                    let error = format!("unable to switch protocols, status: {status}, reason: {reason}");
+                    self.response = Some(HandshakeResponse::new(&response, &view[header_len..body_end]));
+                    return Err(io::Error::other(error));
+                }
+                if header_len == view.len() {
+                    self.extensions = response
+                        .headers
+                        .iter()
+                        .filter(|header| header.name.eq_ignore_ascii_case("Sec-WebSocket-Extensions"))
+                        .map(|header| String::from_utf8_lossy(header.value).into_owned())
+                        .reduce(|extensions, extension| format!("{extensions}, {extension}"));
+                    self.protocol = select_protocol(&self.protocols, response.headers)?;
+                    self.response = Some(HandshakeResponse::new(&response, &[]));
                     self.state = Completed;
                 }
                 Err(io::Error::from(WouldBlock))
@@ -91,24 +191,6 @@ impl Handshaker {
         }
     }
 
-    #[cold]
-    pub fn buffer_message(&mut self, fin: bool, op: u8, body: Option<&[u8]>) {
-        let body = body.map(|body| body.to_vec());
-        self.pending_msg_buffer.push_back((op, fin, body))
-    }
-
-    #[cold]
-    pub fn drain_pending_message_buffer<S, F>(&mut self, stream: &mut S, mut send: F) -> Result<(), Error>
-    where
-        S: Write,
-        F: FnMut(&mut S, bool, u8, Option<&[u8]>) -> io::Result<()>,
-    {
-        while let Some((op, fin, body)) = self.pending_msg_buffer.pop_front() {
-            send(stream, fin, op, body.as_deref())?;
-        }
-        Ok(())
-    }
-
     fn prepare_handshake_request(&mut self) -> io::Result<()> {
         let outbound = &mut self.outbound_buffer;
         outbound.write_all(format!("GET {} HTTP/1.1\r\n", self.endpoint).as_bytes())?;
@@ -117,14 +199,296 @@ impl Handshaker {
         outbound.write_all(b"Connection: upgrade\r\n")?;
         outbound.write_all(format!("Sec-WebSocket-Key: {}\r\n", generate_nonce()).as_bytes())?;
         outbound.write_all(b"Sec-WebSocket-Version: 13\r\n")?;
+        for (name, value) in &self.headers {
+            validate_header(name, value)?;
+            outbound.write_all(format!("{name}: {value}\r\n").as_bytes())?;
+        }
+        if !self.protocols.is_empty() {
+            for protocol in &self.protocols {
+                validate_protocol(protocol)?;
+            }
+            outbound.write_all(format!("Sec-WebSocket-Protocol: {}\r\n", self.protocols.join(", ")).as_bytes())?;
+        }
         outbound.write_all(b"\r\n")?;
         self.state = PendingRequest;
         Ok(())
     }
 }
 
+/// Status line, headers and body of the HTTP response to the upgrade request, e.g. to read the rate
+/// limit or session identifiers sent by the venue.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct HandshakeResponse {
+    status: u16,
+    reason: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl HandshakeResponse {
+    fn new(response: &Response, body: &[u8]) -> Self {
+        Self {
+            status: response.code.unwrap_or_default(),
+            reason: response.reason.unwrap_or_default().to_owned(),
+            headers: response
+                .headers
+                .iter()
+                .map(|header| (header.name.to_owned(), String::from_utf8_lossy(header.value).into_owned()))
+                .collect(),
+            body: body.to_vec(),
+        }
+    }
+
+    /// Status code, `101` if the upgrade has been accepted.
+    pub const fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Reason phrase of the status line.
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    /// Headers as `(name, value)` pairs in the order they were received.
+    pub fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// Value of the first header with the `name` (case insensitive).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    /// Target of the `301`, `302` or `307` redirect (`Location` header), `None` for any other response.
+    pub fn redirect(&self) -> Option<&str> {
+        match self.status {
+            301 | 302 | 307 => self
+                .header("Location")
+                .map(str::trim)
+                .filter(|location| !location.is_empty()),
+            _ => None,
+        }
+    }
+
+    /// Body of the response rejecting the upgrade, empty if the upgrade has been accepted.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+}
+
+/// Deadline of the upgrade, measured from the first read of the websocket.
+pub struct HandshakeDeadline {
+    timeout: Duration,
+    deadline_ns: Option<u64>,
+    time_source: Box<dyn TimeSource>,
+}
+
+impl Debug for HandshakeDeadline {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HandshakeDeadline")
+            .field("timeout", &self.timeout)
+            .field("deadline_ns", &self.deadline_ns)
+            .finish()
+    }
+}
+
+impl HandshakeDeadline {
+    pub fn new(timeout: Duration, time_source: Box<dyn TimeSource>) -> Self {
+        Self {
+            timeout,
+            deadline_ns: None,
+            time_source,
+        }
+    }
+
+    /// Fails with [`Error::HandshakeTimeout`] once the deadline has passed.
+    #[cold]
+    pub fn check(&mut self) -> Result<(), Error> {
+        match self.remaining() {
+            Some(_) => Ok(()),
+            None => Err(Error::HandshakeTimeout(self.timeout)),
+        }
+    }
+
+    /// Time left until the deadline, `None` once it has passed.
+    pub fn remaining(&mut self) -> Option<Duration> {
+        let now = self.time_source.current_time_nanos();
+        let deadline_ns = *self
+            .deadline_ns
+            .get_or_insert_with(|| now.saturating_add(self.timeout.as_nanos() as u64));
+        match now > deadline_ns {
+            true => None,
+            false => Some(Duration::from_nanos(deadline_ns - now)),
+        }
+    }
+}
+
+// headers the upgrade request is built from, overriding them would break the handshake
+const RESERVED_HEADERS: [&str; 6] = [
+    "Host",
+    "Upgrade",
+    "Connection",
+    "Sec-WebSocket-Key",
+    "Sec-WebSocket-Version",
+    "Sec-WebSocket-Protocol",
+];
+
+fn validate_header(name: &str, value: &str) -> io::Result<()> {
+    let invalid = |reason: &str| Err(io::Error::new(InvalidInput, format!("invalid header {name:?}: {reason}")));
+    if name.is_empty() || !name.bytes().all(|b| b.is_ascii_graphic() && b != b':') {
+        return invalid("malformed name");
+    }
+    if value.bytes().any(|b| b == b'\r' || b == b'\n') {
+        return invalid("line break in value");
+    }
+    if RESERVED_HEADERS
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(name))
+    {
+        return invalid("set by the handshake");
+    }
+    Ok(())
+}
+
+/// Subprotocols are non-empty tokens (RFC 6455 section 4.1).
+fn validate_protocol(protocol: &str) -> io::Result<()> {
+    const SEPARATORS: &[u8] = b"()<>@,;:\\\"/[]?={}";
+    if protocol.is_empty()
+        || !protocol
+            .bytes()
+            .all(|b| b.is_ascii_graphic() && !SEPARATORS.contains(&b))
+    {
+        return Err(io::Error::new(InvalidInput, format!("invalid subprotocol {protocol:?}")));
+    }
+    Ok(())
+}
+
+/// The server may select at most one of the `offered` subprotocols, anything else fails the handshake.
+fn select_protocol(offered: &[String], headers: &[httparse::Header]) -> io::Result<Option<String>> {
+    let mut selected = None;
+    for header in headers {
+        if !header.name.eq_ignore_ascii_case("Sec-WebSocket-Protocol") {
+            continue;
+        }
+        let protocol = String::from_utf8_lossy(header.value).trim().to_string();
+        if selected.is_some() {
+            return Err(io::Error::other("server selected more than one subprotocol"));
+        }
+        if !offered.contains(&protocol) {
+            return Err(io::Error::other(format!(
+                "server selected subprotocol {protocol:?} that has not been offered"
+            )));
+        }
+        selected = Some(protocol);
+    }
+    Ok(selected)
+}
+
 fn generate_nonce() -> String {
     let mut rng = rng();
     let nonce_bytes: [u8; 16] = rng.random();
     general_purpose::STANDARD.encode(nonce_bytes)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::default_buffer_pool_ref;
+
+    fn request(headers: &[(&str, &str)]) -> io::Result<String> {
+        let mut handshaker = Handshaker::new("example.com", "/ws", &mut default_buffer_pool_ref());
+        for (name, value) in headers {
+            handshaker.add_header(name, value);
+        }
+        handshaker.prepare_handshake_request()?;
+        Ok(String::from_utf8(handshaker.outbound_buffer.into_inner()).unwrap())
+    }
+
+    #[test]
+    fn should_add_custom_headers_to_upgrade_request() {
+        let upgrade = request(&[("Authorization", "Bearer token"), ("User-Agent", "boomnet")]).unwrap();
+        assert!(
+            upgrade
+                .ends_with("Sec-WebSocket-Version: 13\r\nAuthorization: Bearer token\r\nUser-Agent: boomnet\r\n\r\n")
+        );
+
+        assert_eq!(InvalidInput, request(&[("X-Key", "a\r\nHost: evil")]).unwrap_err().kind());
+        assert_eq!(InvalidInput, request(&[("X Key", "a")]).unwrap_err().kind());
+        assert_eq!(InvalidInput, request(&[("host", "evil.com")]).unwrap_err().kind());
+    }
+
+    #[test]
+    fn should_offer_and_validate_subprotocol() {
+        let mut handshaker = Handshaker::new("example.com", "/graphql", &mut default_buffer_pool_ref());
+        handshaker.add_protocol("graphql-transport-ws");
+        handshaker.add_protocol("graphql-ws");
+        handshaker.prepare_handshake_request().unwrap();
+        let upgrade = String::from_utf8(handshaker.outbound_buffer.into_inner()).unwrap();
+        assert!(upgrade.ends_with("Sec-WebSocket-Protocol: graphql-transport-ws, graphql-ws\r\n\r\n"));
+
+        let offered = ["graphql-transport-ws".to_string(), "graphql-ws".to_string()];
+        let header = |value: &'static str| httparse::Header {
+            name: "Sec-WebSocket-Protocol",
+            value: value.as_bytes(),
+        };
+        assert_eq!(None, select_protocol(&offered, &[]).unwrap());
+        assert_eq!(Some("graphql-ws".to_string()), select_protocol(&offered, &[header("graphql-ws")]).unwrap());
+        assert!(select_protocol(&offered, &[header("stomp")]).is_err());
+        assert!(select_protocol(&offered, &[header("graphql-ws"), header("graphql-ws")]).is_err());
+        assert!(select_protocol(&[], &[header("graphql-ws")]).is_err());
+
+        assert_eq!(InvalidInput, request(&[("Sec-WebSocket-Protocol", "stomp")]).unwrap_err().kind());
+        assert_eq!(InvalidInput, validate_protocol("v1, v2").unwrap_err().kind());
+    }
+
+    #[test]
+    fn should_keep_upgrade_response() {
+        let respond = |response: &[u8]| {
+            let mut handshaker = Handshaker::new("example.com", "/ws", &mut default_buffer_pool_ref());
+            handshaker.state = PendingResponse;
+            let mut response = response;
+            let result = loop {
+                handshaker.read(&mut response).unwrap();
+                match handshaker.perform_handshake(&mut io::empty()) {
+                    Err(err) if err.kind() == WouldBlock && !response.is_empty() => continue,
+                    result => break result,
+                }
+            };
+            (result, handshaker.take_response())
+        };
+
+        let (result, response) =
+            respond(b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nX-Session-Id: abc\r\n\r\n");
+        assert!(result.is_err());
+        let response = response.unwrap();
+        assert_eq!((101, "Switching Protocols"), (response.status(), response.reason()));
+        assert_eq!(Some("abc"), response.header("x-session-id"));
+        assert_eq!(vec![("Upgrade", "websocket"), ("X-Session-Id", "abc")], response.headers().collect::<Vec<_>>());
+        assert!(response.body().is_empty());
+
+        let (result, response) =
+            respond(b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: 30\r\nContent-Length: 13\r\n\r\nslow down pls");
+        assert!(result.unwrap_err().to_string().contains("429"));
+        let response = response.unwrap();
+        assert_eq!((429, Some("30")), (response.status(), response.header("Retry-After")));
+        assert_eq!(b"slow down pls", response.body());
+    }
+
+    #[test]
+    fn should_fail_oversized_handshake_response() {
+        let mut handshaker = Handshaker::new("example.com", "/ws", &mut default_buffer_pool_ref());
+        handshaker.set_max_response_size(16);
+        handshaker.state = PendingResponse;
+        let mut response = &b"HTTP/1.1 101 Switching Protocols\r\n"[..];
+        let err = loop {
+            handshaker.read(&mut response).unwrap();
+            match handshaker.perform_handshake(&mut io::empty()) {
+                Err(err) if err.kind() == WouldBlock => continue,
+                result => break result.unwrap_err(),
+            }
+        };
+        assert_eq!(InvalidData, err.kind());
+    }
+}
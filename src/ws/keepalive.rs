@@ -0,0 +1,204 @@
+//! Automatic ping/pong keepalive, see [`Websocket::with_keepalive`](crate::ws::Websocket::with_keepalive),
+//! and the round trip time of the pings.
+//!
+//! Every keepalive ping carries its send time (big endian nanos) as the payload, the pings sent with
+//! [`Websocket::ping_with_payload`](crate::ws::Websocket::ping_with_payload) carry the payload of the
+//! caller. Both are tracked by the same [`RttTracker`] that keeps the payload and send time of every
+//! ping in flight until the pong echoing the payload is received. Pings sent before the matched one
+//! are dropped, as RFC 6455 allows the peer to answer only the most recent ping.
+
+use crate::metrics::{RttEstimator, RttStats};
+use crate::service::time::{SystemTimeClockSource, TimeSource};
+use crate::stream::RxTimestamps;
+use crate::ws::Error;
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+use std::time::Duration;
+
+/// Maximum payload of a control frame.
+pub(crate) const MAX_PING_PAYLOAD: usize = 125;
+/// Maximum number of pings awaiting their pong, the oldest one is dropped above it.
+const MAX_IN_FLIGHT: usize = 16;
+
+/// Sends a ping every interval and counts the intervals that have passed without a pong.
+#[derive(Debug)]
+pub struct Keepalive {
+    interval_ns: u64,
+    max_missed: u32,
+    // start of the current interval, set once the handshake has completed
+    last_ping_ns: Option<u64>,
+    awaiting_pong: bool,
+    missed: u32,
+}
+
+impl Keepalive {
+    pub fn new(interval: Duration, max_missed: u32) -> Self {
+        Self {
+            interval_ns: interval.as_nanos() as u64,
+            max_missed: max_missed.max(1),
+            last_ping_ns: None,
+            awaiting_pong: false,
+            missed: 0,
+        }
+    }
+
+    /// Returns the ping payload if the ping is due at `now`, fails with [`Error::KeepaliveTimeout`]
+    /// once `max_missed` consecutive intervals have passed without a pong.
+    #[inline]
+    pub fn poll(&mut self, now: u64) -> Result<Option<[u8; 8]>, Error> {
+        let Some(last_ping_ns) = self.last_ping_ns else {
+            // the first interval starts once connected
+            self.last_ping_ns = Some(now);
+            return Ok(None);
+        };
+        if now.saturating_sub(last_ping_ns) < self.interval_ns {
+            return Ok(None);
+        }
+        if self.awaiting_pong {
+            self.missed += 1;
+            if self.missed >= self.max_missed {
+                return Err(Error::KeepaliveTimeout(self.missed));
+            }
+        }
+        self.last_ping_ns = Some(now);
+        self.awaiting_pong = true;
+        Ok(Some(now.to_be_bytes()))
+    }
+
+    /// Any pong proves the peer is alive, including a late one or an unsolicited one (which RFC 6455
+    /// allows as a unidirectional heartbeat).
+    #[inline]
+    pub fn on_pong(&mut self) {
+        self.awaiting_pong = false;
+        self.missed = 0;
+    }
+
+    /// Number of consecutive intervals that have passed without a pong.
+    #[inline]
+    pub const fn missed(&self) -> u32 {
+        self.missed
+    }
+}
+
+/// Round trip time of a single ping.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PingRtt {
+    pub rtt_ns: u64,
+    /// The pong receive time is the hardware RX timestamp of its read rather than the time source.
+    pub hardware: bool,
+}
+
+struct InFlight {
+    payload: [u8; MAX_PING_PAYLOAD],
+    len: usize,
+    sent_ns: u64,
+}
+
+/// Matches the pongs with the pings in flight.
+pub struct RttTracker {
+    in_flight: VecDeque<InFlight>,
+    hardware: bool,
+    last: Option<PingRtt>,
+    rtt: RttEstimator,
+    // system clock unless a custom time source has been set
+    time_source: Option<Box<dyn TimeSource>>,
+}
+
+impl Debug for RttTracker {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RttTracker")
+            .field("in_flight", &self.in_flight.len())
+            .field("hardware", &self.hardware)
+            .field("last", &self.last)
+            .field("rtt", &self.rtt)
+            .finish()
+    }
+}
+
+impl RttTracker {
+    pub fn new() -> Self {
+        Self {
+            in_flight: VecDeque::new(),
+            hardware: false,
+            last: None,
+            rtt: RttEstimator::new(),
+            time_source: None,
+        }
+    }
+
+    #[inline]
+    pub fn set_time_source(&mut self, time_source: Box<dyn TimeSource>) {
+        self.time_source = Some(time_source);
+    }
+
+    #[inline]
+    pub fn set_hardware(&mut self, hardware: bool) {
+        self.hardware = hardware;
+    }
+
+    /// Current time of the time source.
+    #[inline]
+    pub fn now(&self) -> u64 {
+        match self.time_source.as_ref() {
+            Some(time_source) => time_source.current_time_nanos(),
+            None => SystemTimeClockSource.current_time_nanos(),
+        }
+    }
+
+    /// Record the ping with the `payload` (at most 125 bytes) as sent now.
+    #[inline]
+    pub fn on_ping(&mut self, payload: &[u8]) {
+        if self.in_flight.len() == MAX_IN_FLIGHT {
+            self.in_flight.pop_front();
+        }
+        let mut ping = InFlight {
+            payload: [0; MAX_PING_PAYLOAD],
+            len: payload.len(),
+            sent_ns: self.now(),
+        };
+        ping.payload[..payload.len()].copy_from_slice(payload);
+        self.in_flight.push_back(ping);
+    }
+
+    /// Measure the pong if its `payload` matches a ping in flight. With hardware timestamps enabled the
+    /// pong is received at the hardware timestamp `rx` of its read (if the stream has captured one).
+    #[inline]
+    pub fn on_pong(&mut self, payload: &[u8], rx: RxTimestamps) -> Option<PingRtt> {
+        let index = self
+            .in_flight
+            .iter()
+            .position(|ping| ping.payload[..ping.len] == *payload)?;
+        let sent_ns = self.in_flight[index].sent_ns;
+        self.in_flight.drain(..=index);
+        let hardware = self.hardware && rx.hw_raw_ns != 0;
+        let received_ns = match hardware {
+            true => rx.hw_raw_ns,
+            false => self.now(),
+        };
+        let sample = PingRtt {
+            rtt_ns: received_ns.saturating_sub(sent_ns),
+            hardware,
+        };
+        self.rtt.record(sample.rtt_ns);
+        self.last = Some(sample);
+        Some(sample)
+    }
+
+    /// Round trip time of the most recently answered ping.
+    #[inline]
+    pub const fn last(&self) -> Option<PingRtt> {
+        self.last
+    }
+
+    /// Round trip time statistics of the answered pings, `None` until the first pong.
+    #[inline]
+    pub const fn rtt(&self) -> Option<RttStats> {
+        self.rtt.stats()
+    }
+
+    /// Number of pings awaiting their pong.
+    #[inline]
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+}
@@ -0,0 +1,211 @@
+//! Masking keys of the outbound client frames.
+//!
+//! RFC 6455 requires every frame sent by the client to be masked. By default the websocket sets the
+//! masking bit with the all zero key, which makes the XOR a no-op so the payload is written as is.
+//! A [`MaskingKeyProvider`] set with [`Websocket::with_masking_key_provider`] supplies the keys
+//! instead: [`RandomKey`] for a fresh key per frame from a fast PRNG, [`ConstantKey`] for
+//! deterministic frames in replay tests, or [`Unmasked`] to send the frames without the masking bit
+//! to (non-compliant) internal servers that do not accept masked frames. The frames masked with
+//! non-zero key are copied through a stack buffer while being masked. The server side websocket
+//! never masks its frames.
+//!
+//! ## Examples
+//! ```no_run
+//! use boomnet::ws::TryIntoTlsReadyWebsocket;
+//! use boomnet::ws::mask::RandomKey;
+//!
+//! let ws = "wss://stream.binance.com/ws"
+//!     .try_into_tls_ready_websocket()
+//!     .unwrap()
+//!     .with_masking_key_provider(RandomKey::new());
+//! ```
+
+use crate::ws::Websocket;
+use crate::ws::decoder::Role;
+use rand::{Rng, rng};
+use std::fmt::{Debug, Formatter};
+
+const ZERO_KEY: [u8; 4] = [0; 4];
+
+/// Supplies the masking key of every outbound client frame.
+pub trait MaskingKeyProvider {
+    /// Masking key of the next frame, `None` to send the frame unmasked.
+    fn next_key(&mut self) -> Option<[u8; 4]>;
+}
+
+impl<F> MaskingKeyProvider for F
+where
+    F: FnMut() -> Option<[u8; 4]>,
+{
+    #[inline]
+    fn next_key(&mut self) -> Option<[u8; 4]> {
+        self()
+    }
+}
+
+/// All zero key, the default. The frames are flagged as masked but the payload is left as is.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ZeroKey;
+
+impl MaskingKeyProvider for ZeroKey {
+    #[inline]
+    fn next_key(&mut self) -> Option<[u8; 4]> {
+        Some(ZERO_KEY)
+    }
+}
+
+/// The same key for every frame, e.g. to produce byte identical frames in replay tests.
+#[derive(Debug, Copy, Clone)]
+pub struct ConstantKey(pub [u8; 4]);
+
+impl MaskingKeyProvider for ConstantKey {
+    #[inline]
+    fn next_key(&mut self) -> Option<[u8; 4]> {
+        Some(self.0)
+    }
+}
+
+/// Frames are sent without the masking bit, which violates RFC 6455 and is only meant for internal
+/// servers that expect it.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Unmasked;
+
+impl MaskingKeyProvider for Unmasked {
+    #[inline]
+    fn next_key(&mut self) -> Option<[u8; 4]> {
+        None
+    }
+}
+
+/// Fresh key for every frame from a xorshift generator seeded once from the thread local RNG. It is
+/// fast but not cryptographically secure.
+#[derive(Debug, Clone)]
+pub struct RandomKey {
+    state: u64,
+}
+
+impl RandomKey {
+    pub fn new() -> Self {
+        Self::with_seed(rng().random())
+    }
+
+    /// Generator with the `seed`, producing the same sequence of keys every time.
+    pub const fn with_seed(seed: u64) -> Self {
+        // xorshift gets stuck on zero
+        let state = if seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { seed };
+        Self { state }
+    }
+}
+
+impl Default for RandomKey {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MaskingKeyProvider for RandomKey {
+    #[inline]
+    fn next_key(&mut self) -> Option<[u8; 4]> {
+        // xorshift64*
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let random = self.state.wrapping_mul(0x2545_f491_4f6c_dd1d);
+        Some(((random >> 32) as u32).to_ne_bytes())
+    }
+}
+
+/// Masking applied by the websocket, client frames are masked with the zero key unless a provider
+/// has been set.
+#[derive(Default)]
+pub(crate) struct Masking {
+    provider: Option<Box<dyn MaskingKeyProvider>>,
+}
+
+impl Masking {
+    /// Masking key of the next frame sent on behalf of the `role`.
+    #[inline]
+    pub(crate) fn next_key(&mut self, role: Role) -> Option<[u8; 4]> {
+        match (role, self.provider.as_mut()) {
+            (Role::Server, _) => None,
+            (Role::Client, None) => Some(ZERO_KEY),
+            (Role::Client, Some(provider)) => provider.next_key(),
+        }
+    }
+}
+
+impl Debug for Masking {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Masking")
+            .field("provider", &self.provider.is_some())
+            .finish()
+    }
+}
+
+impl<S> Websocket<S> {
+    /// Take the masking key of every outbound frame from the `provider` instead of using the zero key,
+    /// see the [module](self) documentation. Has no effect on the server side websocket.
+    pub fn with_masking_key_provider<P>(mut self, provider: P) -> Websocket<S>
+    where
+        P: MaskingKeyProvider + 'static,
+    {
+        self.masking = Masking {
+            provider: Some(Box::new(provider)),
+        };
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::protocol;
+    use std::io;
+    use std::io::{Read, Write};
+
+    #[derive(Default)]
+    struct Socket {
+        written: Vec<u8>,
+    }
+
+    impl Read for Socket {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::from(io::ErrorKind::WouldBlock))
+        }
+    }
+
+    impl Write for Socket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_mask_frames_with_provided_keys() {
+        let text = protocol::FIN_MASK | protocol::op::TEXT_FRAME;
+
+        let mut ws = Websocket::new_with_handshake_complete(Socket::default())
+            .with_masking_key_provider(ConstantKey([1, 2, 3, 4]));
+        ws.send_text(true, Some(b"abcde")).unwrap();
+        ws.send_text_vectored(true, &[b"ab", b"cde"]).unwrap();
+        let masked = [
+            &[text, 0x85, 1, 2, 3, 4][..],
+            &[b'a' ^ 1, b'b' ^ 2, b'c' ^ 3, b'd' ^ 4, b'e' ^ 1],
+        ]
+        .concat();
+        assert_eq!([&masked[..], &masked[..]].concat(), ws.stream().written);
+
+        let mut ws = Websocket::new_with_handshake_complete(Socket::default()).with_masking_key_provider(Unmasked);
+        ws.send_text(true, Some(b"abc")).unwrap();
+        assert_eq!([&[text, 3][..], b"abc"].concat(), ws.stream().written);
+
+        let mut keys = RandomKey::with_seed(42);
+        let first = keys.next_key();
+        assert_ne!(first, keys.next_key());
+        assert_eq!(first, RandomKey::with_seed(42).next_key());
+    }
+}
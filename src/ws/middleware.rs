@@ -0,0 +1,188 @@
+//! Frame processing middleware applied by the websocket before frames are handed out.
+//!
+//! Cross-cutting frame processing (filtering, de-duplication, metrics or mutating frames in tests) can
+//! be written once as a [`Middleware`] and installed on any websocket with [`Websocket::with_middleware`]
+//! instead of being baked into every handler. Layers of the [`Chain`] run in the order they have been
+//! added, every layer sees the frame as delivered (or replaced) by the previous one and a dropped frame
+//! is not seen by the following layers. Pings (unless the automatic pongs are disabled) and close frames
+//! are answered by the websocket itself and never reach the chain.
+//!
+//! ## Examples
+//! ```no_run
+//! use boomnet::ws::middleware::{Action, Chain};
+//! use boomnet::ws::{TryIntoTlsReadyWebsocket, WebsocketFrame};
+//!
+//! let mut frames = 0u64;
+//! let chain = Chain::new()
+//!     // drop the venue heartbeats
+//!     .with(|frame: &WebsocketFrame| match frame {
+//!         WebsocketFrame::Text(_, payload) if payload.starts_with(br#"{"event":"heartbeat""#) => Action::Drop,
+//!         _ => Action::Deliver,
+//!     })
+//!     // count what is left
+//!     .with(move |_: &WebsocketFrame| {
+//!         frames += 1;
+//!         Action::Deliver
+//!     });
+//!
+//! let ws = "wss://ws.kraken.com/v2".try_into_tls_ready_websocket().unwrap().with_middleware(chain);
+//! ```
+
+use crate::checksum::{ChecksumComparator, Path};
+use crate::stream::layer::LayerCounters;
+#[cfg(doc)]
+use crate::ws::Websocket;
+use crate::ws::WebsocketFrame;
+use std::cell::RefCell;
+use std::fmt::{Debug, Formatter};
+use std::ptr;
+use std::rc::Rc;
+
+/// What happens to the frame after the middleware has seen it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Pass the frame on unchanged.
+    Deliver,
+    /// Discard the frame.
+    Drop,
+    /// Pass the frame on with the payload replaced, the frame type and `fin` flag are kept.
+    Replace(Vec<u8>),
+}
+
+/// Processing step applied to every frame.
+pub trait Middleware {
+    fn on_frame(&mut self, frame: &WebsocketFrame) -> Action;
+}
+
+impl<F: FnMut(&WebsocketFrame) -> Action> Middleware for F {
+    fn on_frame(&mut self, frame: &WebsocketFrame) -> Action {
+        self(frame)
+    }
+}
+
+/// Compares the payload of every delivered data frame with the other path of the shared
+/// [`ChecksumComparator`], to detect divergent content between redundant connections. Install it as
+/// the last layer so that it sees the frames as delivered to the handler.
+///
+/// ## Examples
+/// ```no_run
+/// use std::cell::RefCell;
+/// use std::rc::Rc;
+/// use boomnet::checksum::{ChecksumComparator, Path};
+/// use boomnet::ws::middleware::{Chain, Checksum};
+/// use boomnet::ws::TryIntoTlsReadyWebsocket;
+///
+/// let comparator = Rc::new(RefCell::new(ChecksumComparator::new()));
+/// let primary = "wss://ws.kraken.com/v2"
+///     .try_into_tls_ready_websocket()
+///     .unwrap()
+///     .with_middleware(Chain::new().with(Checksum::new(&comparator, Path::Primary)));
+/// let backup = "wss://ws.kraken.com/v2"
+///     .try_into_tls_ready_websocket()
+///     .unwrap()
+///     .with_middleware(Chain::new().with(Checksum::new(&comparator, Path::Backup)));
+/// ```
+#[derive(Debug)]
+pub struct Checksum {
+    comparator: Rc<RefCell<ChecksumComparator>>,
+    path: Path,
+}
+
+impl Checksum {
+    /// Compare the frames of this websocket as the `path` of the `comparator`.
+    pub fn new(comparator: &Rc<RefCell<ChecksumComparator>>, path: Path) -> Self {
+        Self {
+            comparator: comparator.clone(),
+            path,
+        }
+    }
+}
+
+impl Middleware for Checksum {
+    fn on_frame(&mut self, frame: &WebsocketFrame) -> Action {
+        if let WebsocketFrame::Text(_, payload)
+        | WebsocketFrame::Binary(_, payload)
+        | WebsocketFrame::Continuation(_, payload) = frame
+        {
+            self.comparator.borrow_mut().compare(self.path, payload);
+        }
+        Action::Deliver
+    }
+}
+
+/// Counts the frames received and their payload bytes as the reads of the [`LayerCounters`], install
+/// it as the first layer to see every frame.
+impl Middleware for LayerCounters {
+    fn on_frame(&mut self, frame: &WebsocketFrame) -> Action {
+        if let WebsocketFrame::Text(_, payload)
+        | WebsocketFrame::Binary(_, payload)
+        | WebsocketFrame::Continuation(_, payload) = frame
+        {
+            self.on_read(&Ok(payload.len()));
+        }
+        Action::Deliver
+    }
+}
+
+/// Ordered chain of [`Middleware`] layers.
+#[derive(Default)]
+pub struct Chain {
+    layers: Vec<Box<dyn Middleware>>,
+    // replaced payloads handed out since the last network read
+    batch: Vec<Vec<u8>>,
+}
+
+impl Debug for Chain {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Chain").field("layers", &self.layers.len()).finish()
+    }
+}
+
+impl Chain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append the `middleware` to the chain.
+    pub fn with(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.layers.push(Box::new(middleware));
+        self
+    }
+
+    /// Run the `frame` through all layers, returns `None` if it has been dropped. Replaced payloads stay
+    /// valid until the next network read, just like the payload views into the decoder buffer.
+    #[inline]
+    pub fn apply(&mut self, mut frame: WebsocketFrame) -> Option<WebsocketFrame> {
+        for layer in self.layers.iter_mut() {
+            match layer.on_frame(&frame) {
+                Action::Deliver => {}
+                Action::Drop => return None,
+                Action::Replace(payload) => {
+                    // SAFETY: the heap buffer does not move when the batch grows and is only released
+                    // by the next network read, which invalidates the frames of the current batch anyway
+                    let replaced = unsafe { &*ptr::slice_from_raw_parts(payload.as_ptr(), payload.len()) };
+                    self.batch.push(payload);
+                    frame = with_payload(frame, replaced);
+                }
+            }
+        }
+        Some(frame)
+    }
+
+    /// Release the replaced payloads, called by the websocket before the next network read.
+    #[inline]
+    pub(crate) fn recycle(&mut self) {
+        self.batch.clear();
+    }
+}
+
+const fn with_payload(frame: WebsocketFrame, payload: &'static [u8]) -> WebsocketFrame {
+    match frame {
+        WebsocketFrame::Ping(_) => WebsocketFrame::Ping(payload),
+        WebsocketFrame::Pong(_) => WebsocketFrame::Pong(payload),
+        WebsocketFrame::Text(fin, _) => WebsocketFrame::Text(fin, payload),
+        WebsocketFrame::Binary(fin, _) => WebsocketFrame::Binary(fin, payload),
+        WebsocketFrame::Continuation(fin, _) => WebsocketFrame::Continuation(fin, payload),
+        WebsocketFrame::Close(_) => WebsocketFrame::Close(payload),
+    }
+}
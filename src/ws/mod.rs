@@ -55,37 +55,104 @@
 //! ```
 
 use crate::buffer::{BufferPoolRef, default_buffer_pool_ref};
+use crate::metrics::RttStats;
+use crate::preset::Tuning;
+#[cfg(feature = "profile")]
+use crate::profile::{Profiler, Stage};
 use crate::service::select::Selectable;
+use crate::service::time::{SystemTimeClockSource, TimeSource};
 use crate::stream::tcp::TcpStream;
 #[cfg(any(feature = "rustls", feature = "openssl"))]
-use crate::stream::tls::{IntoTlsStream, TlsReadyStream, TlsStream};
-use crate::stream::{BindAndConnect, ConnectionInfoProvider, RxTimestamped, RxTimestamps};
-use crate::util::NoBlock;
-use crate::ws::Error::{Closed, ReceivedCloseFrame};
-use crate::ws::decoder::Decoder;
-pub use crate::ws::error::Error;
-use crate::ws::handshake::Handshaker;
+use crate::stream::tls::{IntoTlsStream, TlsParameters, TlsParametersProvider, TlsReadyStream, TlsStream};
+use crate::stream::{BindAndConnect, ConnectionInfoProvider, PendingWrites, RxTimestamped, RxTimestamps};
+use crate::usdt::probe;
+use crate::util::{NoBlock, is_nonblocking, wait_ready, with_read_timeout};
+use crate::ws::Error::{Closed, Closing, ReceivedCloseFrame};
+use crate::ws::decoder::{Decoder, DecoderConfig, Role, validate_close};
+pub use crate::ws::decoder::{Limits, Recovery, Validation};
+pub use crate::ws::encoder::MAX_VECTORED_PARTS;
+pub use crate::ws::error::{Error, Violation};
+pub use crate::ws::handshake::HandshakeResponse;
+use crate::ws::handshake::{HandshakeDeadline, Handshaker};
+pub use crate::ws::keepalive::PingRtt;
+use crate::ws::keepalive::{Keepalive, MAX_PING_PAYLOAD, RttTracker};
+use crate::ws::mask::Masking;
+use crate::ws::middleware::Chain;
+use crate::ws::outbound::{Lane, Outbound};
+use crate::ws::reassembly::Reassembler;
+pub use crate::ws::reconnect::ReconnectingWebsocket;
+use crate::ws::send_queue::{FrameSink, SendQueue};
+use crate::ws::sender::Mailbox;
+pub use crate::ws::sender::WebsocketSender;
 #[cfg(feature = "mio")]
 use mio::{Interest, Registry, Token, event::Source};
 use std::fmt::Debug;
 use std::io;
-use std::io::ErrorKind::WouldBlock;
+use std::io::ErrorKind::{UnexpectedEof, WouldBlock};
 use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 use url::Url;
 
+mod cork;
 mod decoder;
+#[cfg(feature = "deflate")]
+mod deflate;
 pub mod ds;
 mod encoder;
 mod error;
+mod format;
+mod handover;
 mod handshake;
+mod keepalive;
+pub mod mask;
+pub mod middleware;
+mod outbound;
 mod protocol;
+mod reassembly;
+mod reconnect;
+mod send_queue;
+mod sender;
+pub mod server;
+mod utf8;
 pub mod util;
+pub mod writer;
+
+const DEFAULT_CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What happens to the write side of the websocket once the peer has closed its side of the
+/// connection (TCP FIN, with or without TLS `close_notify`). In both cases the frames received before
+/// the EOF are still decoded and reading then fails with [`Error::Closed`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HalfClose {
+    /// Close the websocket, sending fails with [`Error::Closed`] (default).
+    #[default]
+    Close,
+    /// Keep sending (e.g. cancels or [`Websocket::close_after_flush`]) until the websocket is closed
+    /// by [`Websocket::poll_close`] or an IO error.
+    Flush,
+}
+
+/// Order in which the frames are written once the stream would block, i.e. while the send queue holds
+/// the bytes of the frames sent earlier (see [`Websocket::queued_bytes`]).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SendOrder {
+    /// The control frames (close, ping and pong) jump ahead of the queued data frames, they are written
+    /// as soon as the frame being written completes so that a backlog does not delay the pongs and
+    /// the keepalive pings (default). The close frame sent by [`Websocket::close`] drops the data
+    /// frames still queued behind it.
+    #[default]
+    ControlFirst,
+    /// Every frame is written in the order it has been sent.
+    Fifo,
+}
 
 /// Supported web socket frame variants.
 pub enum WebsocketFrame {
-    /// Server has sent ping frame that will generate automatic pong response. This frame is not
-    /// exposed to the user.
+    /// Server has sent ping frame that will generate automatic pong response, sent at the end of the
+    /// current batch at the latest. This frame is only exposed to the user once the automatic pongs
+    /// have been disabled (see [`Websocket::with_auto_pong`]), the pong is then up to the user.
     Ping(&'static [u8]),
     Pong(&'static [u8]),
     Text(bool, &'static [u8]),
@@ -96,12 +163,121 @@ pub enum WebsocketFrame {
     Close(&'static [u8]),
 }
 
+#[cfg(feature = "usdt")]
+impl WebsocketFrame {
+    /// Op code and payload of the frame.
+    #[inline]
+    const fn parts(&self) -> (u8, &'static [u8]) {
+        match *self {
+            WebsocketFrame::Ping(payload) => (protocol::op::PING, payload),
+            WebsocketFrame::Pong(payload) => (protocol::op::PONG, payload),
+            WebsocketFrame::Text(_, payload) => (protocol::op::TEXT_FRAME, payload),
+            WebsocketFrame::Binary(_, payload) => (protocol::op::BINARY_FRAME, payload),
+            WebsocketFrame::Continuation(_, payload) => (protocol::op::CONTINUATION_FRAME, payload),
+            WebsocketFrame::Close(payload) => (protocol::op::CONNECTION_CLOSE, payload),
+        }
+    }
+}
+
+impl WebsocketFrame {
+    /// Copy the payload into a [`WebsocketFrameOwned`] that is detached from the read batch, so it can
+    /// be stored or handed to another thread.
+    #[inline]
+    pub fn to_owned(&self) -> WebsocketFrameOwned {
+        match *self {
+            WebsocketFrame::Ping(payload) => WebsocketFrameOwned::Ping(payload.to_vec()),
+            WebsocketFrame::Pong(payload) => WebsocketFrameOwned::Pong(payload.to_vec()),
+            WebsocketFrame::Text(fin, payload) => WebsocketFrameOwned::Text(fin, payload.to_vec()),
+            WebsocketFrame::Binary(fin, payload) => WebsocketFrameOwned::Binary(fin, payload.to_vec()),
+            WebsocketFrame::Continuation(fin, payload) => WebsocketFrameOwned::Continuation(fin, payload.to_vec()),
+            WebsocketFrame::Close(payload) => WebsocketFrameOwned::Close(payload.to_vec()),
+        }
+    }
+}
+
+/// Websocket frame that owns its payload, created with [`WebsocketFrame::to_owned`]. Unlike the
+/// borrowed frame it outlives the next read from the websocket and is `Send`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WebsocketFrameOwned {
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Text(bool, Vec<u8>),
+    Binary(bool, Vec<u8>),
+    Continuation(bool, Vec<u8>),
+    Close(Vec<u8>),
+}
+
+impl WebsocketFrameOwned {
+    /// Payload of the frame.
+    #[inline]
+    pub fn payload(&self) -> &[u8] {
+        match self {
+            WebsocketFrameOwned::Ping(payload)
+            | WebsocketFrameOwned::Pong(payload)
+            | WebsocketFrameOwned::Text(_, payload)
+            | WebsocketFrameOwned::Binary(_, payload)
+            | WebsocketFrameOwned::Continuation(_, payload)
+            | WebsocketFrameOwned::Close(payload) => payload,
+        }
+    }
+
+    /// Consume the frame and return its payload.
+    #[inline]
+    pub fn into_payload(self) -> Vec<u8> {
+        match self {
+            WebsocketFrameOwned::Ping(payload)
+            | WebsocketFrameOwned::Pong(payload)
+            | WebsocketFrameOwned::Text(_, payload)
+            | WebsocketFrameOwned::Binary(_, payload)
+            | WebsocketFrameOwned::Continuation(_, payload)
+            | WebsocketFrameOwned::Close(payload) => payload,
+        }
+    }
+}
+
+impl From<WebsocketFrame> for WebsocketFrameOwned {
+    fn from(frame: WebsocketFrame) -> Self {
+        frame.to_owned()
+    }
+}
+
 /// Websocket client that owns underlying stream.
 #[derive(Debug)]
 pub struct Websocket<S> {
     stream: S,
     closed: bool,
+    closing: bool,
+    // all frames including the close frame have been handed to the kernel
+    flushed: bool,
     state: State,
+    pong: PendingPong,
+    masking: Masking,
+    // frames sent while the handshake is pending or queued behind the frames of their lane
+    outbound: Outbound,
+    // bytes the stream has not accepted yet
+    send_queue: SendQueue,
+    // the peer has closed its side of the connection
+    eof: bool,
+    half_close: HalfClose,
+    keepalive: Option<Keepalive>,
+    handshake_deadline: Option<HandshakeDeadline>,
+    rtt: RttTracker,
+    close_timeout: Duration,
+    middleware: Option<Chain>,
+    reassembler: Option<Reassembler>,
+    // subprotocol selected by the server
+    protocol: Option<String>,
+    handshake_response: Option<HandshakeResponse>,
+    // reused by the formatted text messages
+    fmt_buffer: Vec<u8>,
+    // encoded data frames coalesced into a single write until the batch is flushed, only when corked
+    cork: Option<Vec<u8>>,
+    // frames enqueued by the senders on other threads
+    mailbox: Option<Mailbox>,
+    // captured by the last network read that received data, the frames decoded since then are attributed to it
+    rx_timestamps: RxTimestamps,
+    #[cfg(feature = "profile")]
+    profiler: Option<Profiler>,
 }
 
 impl<S> Websocket<S> {
@@ -114,37 +290,611 @@ impl<S> Websocket<S> {
     {
         let connection_info = stream.connection_info().clone();
         let server_name = connection_info.host();
-        Self {
-            stream,
-            closed: false,
-            state: State::handshake(server_name, endpoint, default_buffer_pool_ref()),
+        let websocket = Self::from_state(stream, State::handshake(server_name, endpoint, default_buffer_pool_ref()));
+        match connection_info.tuning() {
+            Some(tuning) => websocket.with_tuning(tuning),
+            None => websocket,
         }
     }
 
+    /// Apply the websocket knobs of the connection [`Tuning`] (batching and compression).
+    fn with_tuning(mut self, tuning: &Tuning) -> Websocket<S> {
+        if let Some(max_frames) = tuning.batch_limit() {
+            self = self.with_bulk_flush_limit(max_frames);
+        }
+        #[cfg(feature = "deflate")]
+        if tuning.compression() {
+            self = self.with_deflate();
+        }
+        self
+    }
+
+    /// Same as [`Websocket::new`] with extra `headers` (e.g. `Authorization`, API key or `User-Agent`)
+    /// included in the upgrade request. Headers that would break the request (malformed name, line
+    /// break in the value, or one of the headers the handshake sets itself) fail the handshake.
+    ///
+    /// ## Examples
+    /// ```no_run
+    /// use boomnet::stream::{BindAndConnect, ConnectionInfo};
+    /// use boomnet::stream::tls::IntoTlsStream;
+    /// use boomnet::ws::Websocket;
+    ///
+    /// let stream = ConnectionInfo::new("stream.example.com", 443)
+    ///     .into_tcp_stream()
+    ///     .unwrap()
+    ///     .into_tls_stream()
+    ///     .unwrap();
+    /// let ws = Websocket::new_with_headers(stream, "/private", &[("X-API-KEY", "key"), ("User-Agent", "feed/1.0")]);
+    /// ```
+    pub fn new_with_headers(stream: S, endpoint: &str, headers: &[(&str, &str)]) -> Websocket<S>
+    where
+        S: ConnectionInfoProvider,
+    {
+        headers
+            .iter()
+            .fold(Self::new(stream, endpoint), |ws, (name, value)| ws.with_header(name, value))
+    }
+
     /// Crate a new websocket by wrapping a stream that has already performed handshake. It is the
     /// user's responsibility to make sure the handshake has been completed. Otherwise, can result
     /// in undefined behaviour.
     pub fn new_with_handshake_complete(stream: S) -> Websocket<S> {
+        Self::from_state(stream, State::connection(default_buffer_pool_ref(), DecoderConfig::default()))
+    }
+
+    /// Create server side websocket on the `stream` the handshake has been accepted on, see
+    /// [`WebsocketAcceptor`](server::WebsocketAcceptor).
+    fn new_accepted(stream: S, config: DecoderConfig) -> Websocket<S> {
+        Self::from_state(stream, State::connection(default_buffer_pool_ref(), config))
+    }
+
+    /// Websocket in the `state` with every setting at its default, every constructor goes through it.
+    fn from_state(stream: S, state: State) -> Websocket<S> {
         Self {
             stream,
             closed: false,
-            state: State::connection(default_buffer_pool_ref()),
+            closing: false,
+            flushed: false,
+            state,
+            pong: PendingPong::new(),
+            masking: Masking::default(),
+            outbound: Outbound::new(),
+            send_queue: SendQueue::new(),
+            eof: false,
+            half_close: HalfClose::default(),
+            keepalive: None,
+            handshake_deadline: None,
+            rtt: RttTracker::new(),
+            close_timeout: DEFAULT_CLOSE_TIMEOUT,
+            middleware: None,
+            rx_timestamps: RxTimestamps::default(),
+            reassembler: None,
+            protocol: None,
+            handshake_response: None,
+            fmt_buffer: Vec::new(),
+            cork: None,
+            mailbox: None,
+            #[cfg(feature = "profile")]
+            profiler: None,
+        }
+    }
+
+    /// Set the strategy used by the frame decoder when it encounters a malformed frame header. The
+    /// default is [`Recovery::Strict`] which closes the websocket with a protocol error.
+    ///
+    /// ## Examples
+    ///
+    /// Tolerate a buggy middlebox by scanning up to 1KB for the next frame boundary.
+    /// ```no_run
+    /// use boomnet::ws::{Recovery, TryIntoTlsReadyWebsocket};
+    ///
+    /// let ws = "wss://stream.binance.com/ws"
+    ///     .try_into_tls_ready_websocket()
+    ///     .unwrap()
+    ///     .with_recovery(Recovery::Resync { max_scan: 1024 });
+    /// ```
+    pub fn with_recovery(mut self, recovery: Recovery) -> Websocket<S> {
+        match &mut self.state {
+            State::Handshake(_, _, config) => config.recovery = recovery,
+            State::Connection(decoder) => decoder.set_recovery(recovery),
+        }
+        self
+    }
+
+    /// Add `name: value` header to the handshake request (e.g. session resume token or credentials).
+    /// Has no effect once the handshake request has been sent.
+    pub fn with_header(mut self, name: &str, value: &str) -> Websocket<S> {
+        if let State::Handshake(handshaker, _, _) = &mut self.state {
+            handshaker.add_header(name, value);
+        }
+        self
+    }
+
+    /// Add the `Cookie` header with the cookies of the `jar` that apply to the endpoint to the handshake
+    /// request, e.g. the session established with the [`HttpClient`](crate::http::HttpClient). Has no
+    /// effect once the handshake request has been sent.
+    ///
+    /// ## Examples
+    /// ```no_run
+    /// use boomnet::http::CookieJar;
+    /// use boomnet::ws::TryIntoTlsReadyWebsocket;
+    ///
+    /// let jar = CookieJar::new();
+    /// jar.insert("session", "abc");
+    /// let ws = "wss://stream.example.com/ws"
+    ///     .try_into_tls_ready_websocket()
+    ///     .unwrap()
+    ///     .with_cookies(&jar);
+    /// ```
+    #[cfg(feature = "http")]
+    pub fn with_cookies(mut self, jar: &crate::http::CookieJar) -> Websocket<S> {
+        if let State::Handshake(handshaker, _, _) = &mut self.state {
+            if let Some(cookie) = jar.header(handshaker.endpoint()) {
+                handshaker.add_header("Cookie", &cookie);
+            }
+        }
+        self
+    }
+
+    /// Request the `endpoint` (path and query) in the upgrade instead of the one the websocket has been
+    /// created with, e.g. to follow a redirect. Has no effect once the handshake request has been sent.
+    pub fn with_endpoint(mut self, endpoint: &str) -> Websocket<S> {
+        if let State::Handshake(handshaker, _, _) = &mut self.state {
+            handshaker.set_endpoint(endpoint);
+        }
+        self
+    }
+
+    /// Offer the `protocol` (e.g. `graphql-transport-ws`) in the `Sec-WebSocket-Protocol` header of the
+    /// handshake request, call it repeatedly to offer several in order of preference. The handshake
+    /// fails if a subprotocol is not a valid token or the server selects one that has not been offered,
+    /// the selected one is available from [`Websocket::protocol`]. Has no effect once the handshake
+    /// request has been sent.
+    ///
+    /// ## Examples
+    /// ```no_run
+    /// use boomnet::ws::TryIntoTlsReadyWebsocket;
+    ///
+    /// let ws = "wss://api.example.com/graphql"
+    ///     .try_into_tls_ready_websocket()
+    ///     .unwrap()
+    ///     .with_protocol("graphql-transport-ws")
+    ///     .with_protocol("graphql-ws");
+    /// ```
+    pub fn with_protocol(mut self, protocol: &str) -> Websocket<S> {
+        if let State::Handshake(handshaker, _, _) = &mut self.state {
+            handshaker.add_protocol(protocol);
+        }
+        self
+    }
+
+    /// Offer the RFC 7692 `permessage-deflate` extension in the handshake request, required by venues
+    /// that only serve compressed streams. If the server accepts it compressed messages are inflated
+    /// before they are handed out (the payload is then a view into the inflate buffer with the same
+    /// validity as any other frame), outbound frames are always sent uncompressed. Has no effect once
+    /// the handshake request has been sent.
+    ///
+    /// ## Examples
+    /// ```no_run
+    /// use boomnet::ws::TryIntoTlsReadyWebsocket;
+    ///
+    /// let ws = "wss://ws.okx.com:8443/ws/v5/public"
+    ///     .try_into_tls_ready_websocket()
+    ///     .unwrap()
+    ///     .with_deflate();
+    /// ```
+    #[cfg(feature = "deflate")]
+    pub fn with_deflate(mut self) -> Websocket<S> {
+        if let State::Handshake(handshaker, _, config) = &mut self.state {
+            if !config.deflate {
+                handshaker.add_header("Sec-WebSocket-Extensions", deflate::OFFER);
+                config.deflate = true;
+            }
+        }
+        self
+    }
+
+    /// Set what happens to the write side once the peer has closed its side of the connection, the
+    /// default is [`HalfClose::Close`].
+    pub fn with_half_close(self, half_close: HalfClose) -> Websocket<S> {
+        Self { half_close, ..self }
+    }
+
+    /// Set how long [`Websocket::close`] waits for the peer to answer the close frame, the default is one second.
+    pub fn with_close_timeout(self, close_timeout: Duration) -> Websocket<S> {
+        Self { close_timeout, ..self }
+    }
+
+    /// Answer the pings automatically (default) or hand them out as [`WebsocketFrame::Ping`] and leave
+    /// the pong to [`Websocket::send_pong`], e.g. to defer it until after the latency critical work or
+    /// to reply with a custom payload. The peer may drop the connection if the pong is never sent.
+    pub fn with_auto_pong(mut self, auto_pong: bool) -> Websocket<S> {
+        self.set_auto_pong(auto_pong);
+        self
+    }
+
+    /// Enable or disable the automatic pongs at any time, see [`Websocket::with_auto_pong`]. The pong
+    /// to a ping received while enabled is still sent.
+    pub const fn set_auto_pong(&mut self, auto_pong: bool) {
+        self.pong.manual = !auto_pong;
+    }
+
+    /// Checks if the pings are answered automatically.
+    pub const fn auto_pong(&self) -> bool {
+        !self.pong.manual
+    }
+
+    /// Write at most `max_frames` of the queued data frames per flush (the end of every batch), so that
+    /// a large backlog (e.g. the frames sent while the handshake was pending) does not delay the control
+    /// frames and the urgent messages (see [`Websocket::send_text_urgent`]) sent in the meantime. The
+    /// default is to write all of them at once.
+    pub fn with_bulk_flush_limit(mut self, max_frames: usize) -> Websocket<S> {
+        self.outbound.set_max_bulk_per_flush(max_frames);
+        self
+    }
+
+    /// Set whether the control frames are written ahead of the data frames queued while the stream
+    /// would block, the default is [`SendOrder::ControlFirst`].
+    ///
+    /// ## Examples
+    /// ```no_run
+    /// use boomnet::ws::{SendOrder, TryIntoTlsReadyWebsocket};
+    ///
+    /// // the venue expects the frames in the order they have been sent
+    /// let ws = "wss://stream.binance.com/ws"
+    ///     .try_into_tls_ready_websocket()
+    ///     .unwrap()
+    ///     .with_send_order(SendOrder::Fifo);
+    /// ```
+    pub fn with_send_order(mut self, order: SendOrder) -> Websocket<S> {
+        self.send_queue.set_order(order);
+        self
+    }
+
+    /// Order in which the frames are written while the stream would block, see [`Websocket::with_send_order`].
+    pub const fn send_order(&self) -> SendOrder {
+        self.send_queue.order()
+    }
+
+    /// Set the watermarks of the send queue, which holds the bytes the non-blocking stream has not
+    /// accepted yet (the peer or the network is not draining them). The websocket is
+    /// [backpressured](Websocket::is_backpressured) once the queue reaches `high` bytes and until it
+    /// drains down to `low` bytes. The defaults are 16 KiB and 64 KiB.
+    pub fn with_write_watermarks(mut self, low: usize, high: usize) -> Websocket<S> {
+        self.send_queue.set_watermarks(low, high);
+        self
+    }
+
+    /// Call the `handler` with `true` when the websocket becomes backpressured and with `false` once
+    /// the send queue has drained down to the low watermark, see [`Websocket::with_write_watermarks`].
+    ///
+    /// ## Examples
+    /// ```no_run
+    /// use std::cell::Cell;
+    /// use std::rc::Rc;
+    /// use boomnet::ws::TryIntoTlsReadyWebsocket;
+    ///
+    /// let throttled = Rc::new(Cell::new(false));
+    /// let ws = "wss://stream.binance.com/ws"
+    ///     .try_into_tls_ready_websocket()
+    ///     .unwrap()
+    ///     .with_write_watermarks(64 * 1024, 256 * 1024)
+    ///     .with_backpressure_handler({
+    ///         let throttled = throttled.clone();
+    ///         move |backpressured| throttled.set(backpressured)
+    ///     });
+    /// ```
+    pub fn with_backpressure_handler<F>(mut self, handler: F) -> Websocket<S>
+    where
+        F: FnMut(bool) + 'static,
+    {
+        self.send_queue.set_handler(Box::new(handler));
+        self
+    }
+
+    /// Set how closely incoming frames are checked against RFC 6455. The default is
+    /// [`Validation::Lenient`]. With [`Validation::Strict`] fragmented or oversized control frames,
+    /// continuation frames out of sequence, text messages that are not valid UTF-8, reserved bits and op
+    /// codes and invalid close frames fail the websocket with [`Error::Violation`], after sending the
+    /// close frame with the [status code](Violation::status_code) of the violation.
+    /// Frames received by the client must never be masked, frames received by the server side
+    /// websocket (see [`server`]) must always be masked, which is only enforced with [`Validation::Strict`].
+    pub fn with_validation(mut self, validation: Validation) -> Websocket<S> {
+        match &mut self.state {
+            State::Handshake(_, _, config) => config.validation = validation,
+            State::Connection(decoder) => decoder.set_validation(validation),
+        }
+        self
+    }
+
+    /// Bound the size of the received frames, messages and handshake response, see [`Limits`]. The
+    /// websocket fails with [`Error::Protocol`] (or an [`io::ErrorKind::InvalidData`] error during the
+    /// handshake) as soon as a frame header announces more than allowed, so the payload is never buffered.
+    /// Unlike [`Websocket::with_reassembly`] the message limit also applies to the fragments handed out
+    /// as they arrive.
+    ///
+    /// ## Examples
+    /// ```no_run
+    /// use boomnet::ws::{Limits, TryIntoTlsReadyWebsocket};
+    ///
+    /// let ws = "wss://stream.binance.com/ws"
+    ///     .try_into_tls_ready_websocket()
+    ///     .unwrap()
+    ///     .with_limits(
+    ///         Limits::UNLIMITED
+    ///             .with_max_frame_size(1 << 20)
+    ///             .with_max_message_size(16 << 20)
+    ///             .with_max_handshake_response_size(8192),
+    ///     );
+    /// ```
+    pub fn with_limits(mut self, limits: Limits) -> Websocket<S> {
+        match &mut self.state {
+            State::Handshake(handshaker, _, config) => {
+                handshaker.set_max_response_size(limits.max_handshake_response_size);
+                config.limits = limits;
+            }
+            State::Connection(decoder) => decoder.set_limits(limits),
+        }
+        self
+    }
+
+    /// Send a ping every `interval` once the handshake has completed and fail the websocket with
+    /// [`Error::KeepaliveTimeout`] (closing it) when `max_missed` consecutive intervals pass without
+    /// a pong. The check runs on every [`Websocket::read_batch`], so it is only as precise as the
+    /// read loop is frequent. Pongs are still handed out as [`WebsocketFrame::Pong`].
+    ///
+    /// Pings carry their send time, so the pongs also measure the application level round trip time
+    /// and its jitter (see [`Websocket::rtt`]) together with the pings sent with
+    /// [`Websocket::ping_with_payload`].
+    ///
+    /// ## Examples
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use boomnet::ws::TryIntoTlsReadyWebsocket;
+    ///
+    /// let ws = "wss://stream.binance.com/ws"
+    ///     .try_into_tls_ready_websocket()
+    ///     .unwrap()
+    ///     .with_keepalive(Duration::from_secs(10), 3);
+    /// ```
+    pub fn with_keepalive(self, interval: Duration, max_missed: u32) -> Websocket<S> {
+        self.with_keepalive_time_source(interval, max_missed, SystemTimeClockSource)
+    }
+
+    /// Same as [`Websocket::with_keepalive`] using custom [`TimeSource`], which also becomes the
+    /// round trip time clock (see [`Websocket::with_rtt_time_source`]).
+    pub fn with_keepalive_time_source<TS>(self, interval: Duration, max_missed: u32, time_source: TS) -> Websocket<S>
+    where
+        TS: TimeSource + 'static,
+    {
+        Self {
+            keepalive: Some(Keepalive::new(interval, max_missed)),
+            ..self
+        }
+        .with_rtt_time_source(time_source)
+    }
+
+    /// Use custom [`TimeSource`] for the send (and receive) time of the pings, the system clock is used
+    /// by default. The keepalive interval is measured with the same clock.
+    pub fn with_rtt_time_source<TS>(mut self, time_source: TS) -> Websocket<S>
+    where
+        TS: TimeSource + 'static,
+    {
+        self.rtt.set_time_source(Box::new(time_source));
+        self
+    }
+
+    /// Take the receive time of the pongs from the hardware RX timestamp of their read, which leaves the
+    /// time spent in the kernel and in the read loop out of the round trip time. Only pongs read with
+    /// [`Websocket::read_batch_ts`] carry the timestamp, the others fall back to the time source. The
+    /// time source must run in the clock domain of the NIC (e.g. the system clock with the NIC clock
+    /// synchronised to it by `phc2sys`).
+    pub fn with_hardware_rtt(mut self) -> Websocket<S> {
+        self.rtt.set_hardware(true);
+        self
+    }
+
+    /// Fail the websocket with [`Error::HandshakeTimeout`] (closing it) if the upgrade has not completed
+    /// within the `timeout`, measured from the first read. A stalled or black-holed handshake then
+    /// surfaces as an error (and the endpoint is recreated by the `IOService`) instead of holding the
+    /// connection slot forever. The check runs on every [`Websocket::read_batch`], which can not bound a
+    /// read that blocks, [`Websocket::wait_for_handshake`] and [`Websocket::read_batch_deadline`] also
+    /// wake up the waiting thread once the time is up.
+    ///
+    /// ## Examples
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use boomnet::ws::TryIntoTlsReadyWebsocket;
+    ///
+    /// let mut ws = "wss://stream.binance.com/ws"
+    ///     .try_into_tls_ready_websocket()
+    ///     .unwrap()
+    ///     .with_handshake_timeout(Duration::from_secs(5));
+    /// ws.wait_for_handshake().unwrap();
+    /// ```
+    pub fn with_handshake_timeout(self, timeout: Duration) -> Websocket<S> {
+        self.with_handshake_timeout_time_source(timeout, SystemTimeClockSource)
+    }
+
+    /// Same as [`Websocket::with_handshake_timeout`] using custom [`TimeSource`].
+    pub fn with_handshake_timeout_time_source<TS>(self, timeout: Duration, time_source: TS) -> Websocket<S>
+    where
+        TS: TimeSource + 'static,
+    {
+        Self {
+            handshake_deadline: Some(HandshakeDeadline::new(timeout, Box::new(time_source))),
+            ..self
+        }
+    }
+
+    /// Run every received frame through the [`middleware`] chain before it is handed out, frames
+    /// dropped by the chain are skipped. Pings (unless the automatic pongs are disabled) and close
+    /// frames are handled by the websocket and never reach the chain, pongs are accounted by the
+    /// keepalive before they do.
+    ///
+    /// ## Examples
+    /// ```no_run
+    /// use boomnet::ws::middleware::{Action, Chain};
+    /// use boomnet::ws::{TryIntoTlsReadyWebsocket, WebsocketFrame};
+    ///
+    /// let ws = "wss://stream.binance.com:9443/ws"
+    ///     .try_into_tls_ready_websocket()
+    ///     .unwrap()
+    ///     .with_middleware(Chain::new().with(|frame: &WebsocketFrame| match frame {
+    ///         WebsocketFrame::Pong(_) => Action::Drop,
+    ///         _ => Action::Deliver,
+    ///     }));
+    /// ```
+    pub fn with_middleware(self, middleware: Chain) -> Websocket<S> {
+        Self {
+            middleware: Some(middleware),
+            ..self
+        }
+    }
+
+    /// Reassemble fragmented messages: instead of the [`WebsocketFrame::Continuation`] frames, every
+    /// message is handed out as a single text or binary frame (with `fin` set) once its final fragment
+    /// has arrived, before reaching the [`middleware`] chain. Unfragmented messages are not copied.
+    /// A message that grows over `max_message_size` bytes fails the websocket with [`Error::Protocol`].
+    ///
+    /// ## Examples
+    /// ```no_run
+    /// use boomnet::ws::TryIntoTlsReadyWebsocket;
+    ///
+    /// let ws = "wss://ws.kraken.com/v2"
+    ///     .try_into_tls_ready_websocket()
+    ///     .unwrap()
+    ///     .with_reassembly(16 * 1024 * 1024);
+    /// ```
+    pub fn with_reassembly(self, max_message_size: usize) -> Websocket<S> {
+        Self {
+            reassembler: Some(Reassembler::new(max_message_size)),
+            ..self
+        }
+    }
+
+    /// Account the time spent decoding frames to [`Stage::Decode`](crate::profile::Stage::Decode)
+    /// of the provided `profiler`.
+    #[cfg(feature = "profile")]
+    pub fn with_profiler(self, profiler: &Profiler) -> Websocket<S> {
+        Self {
+            profiler: Some(profiler.clone()),
+            ..self
+        }
+    }
+
+    /// Number of times the decoder had to resync after a malformed frame header. Always `0`
+    /// when using [`Recovery::Strict`].
+    pub const fn resyncs(&self) -> u64 {
+        match &self.state {
+            State::Handshake(_, _, _) => 0,
+            State::Connection(decoder) => decoder.resyncs(),
+        }
+    }
+
+    /// Subprotocol selected by the server, `None` until the handshake has completed or if the server
+    /// has not selected any of the offered ones (see [`Websocket::with_protocol`]).
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    /// Status line, headers and body of the HTTP response to the upgrade request, available once the
+    /// handshake has completed or the server has rejected the upgrade (in which case the handshake
+    /// fails with an IO error, or with [`Error::Redirect`] if the server has redirected it). `None` for
+    /// websockets that have not performed the client handshake.
+    ///
+    /// ## Examples
+    /// ```no_run
+    /// use boomnet::ws::TryIntoTlsReadyWebsocket;
+    ///
+    /// let mut ws = "wss://stream.binance.com/ws".try_into_tls_ready_websocket().unwrap();
+    /// let result = ws.wait_for_handshake();
+    /// if let Some(response) = ws.handshake_response() {
+    ///     println!("{} {:?}", response.status(), response.header("x-mbx-used-weight"));
+    /// }
+    /// ```
+    pub fn handshake_response(&self) -> Option<&HandshakeResponse> {
+        match &self.state {
+            State::Handshake(handshake, _, _) => handshake.response(),
+            State::Connection(_) => self.handshake_response.as_ref(),
         }
     }
 
-    /// Checks if the websocket is closed. This can be result of an IO error or the other side
-    /// sending `WebsocketFrame::Closed`.
+    /// Number of consecutive keepalive intervals that have passed without a pong, always `0` when
+    /// the keepalive is not enabled.
+    pub fn missed_pongs(&self) -> u32 {
+        self.keepalive.as_ref().map_or(0, Keepalive::missed)
+    }
+
+    /// Round trip time measured by the keepalive pings and the pings sent with
+    /// [`Websocket::ping_with_payload`], `None` until the first of them has been answered. Unlike the
+    /// time since the last frame it does not depend on how active the subscribed streams are, which
+    /// makes it suitable to compare gateways (see [`HostSet::on_rtt`](crate::service::failover::HostSet::on_rtt)).
+    pub fn rtt(&self) -> Option<RttStats> {
+        self.rtt.rtt()
+    }
+
+    /// Round trip time of the most recently answered ping.
+    pub fn last_rtt(&self) -> Option<PingRtt> {
+        self.rtt.last()
+    }
+
+    /// Number of pings still awaiting their pong.
+    pub fn pings_in_flight(&self) -> usize {
+        self.rtt.in_flight()
+    }
+
+    /// Checks if the websocket is closed. This can be result of an IO error, the other side
+    /// sending `WebsocketFrame::Closed` or closing the connection (see [`HalfClose`]).
     pub const fn closed(&self) -> bool {
         self.closed
     }
 
+    /// Checks if the peer has closed its side of the connection.
+    pub const fn eof(&self) -> bool {
+        self.eof
+    }
+
+    /// Checks if [`Websocket::close_after_flush`] has been requested, no more frames can be sent.
+    pub const fn closing(&self) -> bool {
+        self.closing
+    }
+
+    /// Number of outbound frames not yet written to the stream, these are the frames sent while the
+    /// handshake is pending or queued behind a backlog (see [`Websocket::with_bulk_flush_limit`]) or
+    /// behind the send queue. Bytes held back by the stream itself (buffering, TLS) are reported by
+    /// [`Websocket::has_pending_writes`].
+    pub fn pending_frames(&self) -> usize {
+        self.outbound.len()
+    }
+
+    /// Number of bytes held in the send queue because the stream would block, they are written on
+    /// the next read, send or at the end of the batch.
+    pub const fn queued_bytes(&self) -> usize {
+        self.send_queue.len()
+    }
+
+    /// Checks if the send queue has crossed the high watermark and not yet drained down to the low
+    /// one, see [`Websocket::with_write_watermarks`]. The frames sent in the meantime are queued.
+    pub const fn is_backpressured(&self) -> bool {
+        self.send_queue.backpressured()
+    }
+
+    /// Checks if any bytes written by the websocket are still held back by the send queue or the stream.
+    pub fn has_pending_writes(&self) -> bool
+    where
+        S: PendingWrites,
+    {
+        !self.send_queue.is_empty() || self.stream.has_pending_writes()
+    }
+
     /// Checks if the handshake has completed successfully. If attempt is made to send a message
     /// while the handshake is pending the message will be buffered and dispatched once handshake
     /// has finished.
     #[inline]
     pub const fn handshake_complete(&self) -> bool {
         match self.state {
-            State::Handshake(_, _) => false,
+            State::Handshake(_, _, _) => false,
             State::Connection(_) => true,
         }
     }
@@ -190,35 +940,171 @@ impl<S: Read + Write> Websocket<S> {
     /// ```
     #[inline]
     pub fn read_batch(&mut self) -> Result<Batch<'_, S>, Error> {
-        match self.state.read(&mut self.stream).no_block() {
-            Ok(()) => Ok(Batch { websocket: self }),
-            Err(err) => {
-                self.closed = true;
-                Err(err)?
-            }
-        }
+        self.ensure_not_closed()?;
+        self.handshake_deadline()?;
+        self.flush_pong()?;
+        self.keepalive()?;
+        self.drain_senders()?;
+        self.read()?;
+        Ok(Batch { websocket: self })
     }
 
+    /// Same as [`Websocket::read_batch`], every frame of the batch is paired with the RX timestamps of
+    /// the network read that completed it (the kernel reports the timestamp of the last TCP segment of
+    /// a read). Frames left in the batch when the next one is read keep the timestamps of their read,
+    /// the timestamps are zero if the stream has not captured any.
+    ///
+    /// ## Examples
+    /// ```no_run
+    /// use std::io::{Read, Write};
+    /// use boomnet::stream::RxTimestamped;
+    /// use boomnet::ws::{Websocket, WebsocketFrame};
+    ///
+    /// fn process<S: Read + Write + RxTimestamped>(ws: &mut Websocket<S>) -> std::io::Result<()> {
+    ///     for frame in ws.read_batch_ts()? {
+    ///         if let (WebsocketFrame::Text(_, data), rx) = frame? {
+    ///             println!("{} {}", rx.hw_raw_ns, String::from_utf8_lossy(data));
+    ///         }
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
     #[inline]
     pub fn read_batch_ts(&mut self) -> Result<BatchTs<'_, S>, Error>
     where
         S: RxTimestamped,
     {
-        match self.state.read(&mut self.stream).no_block() {
-            Ok(()) => {
-                let rx = self.stream.take_last_rx_timestamps();
-                Ok(BatchTs {
-                    batch: Batch { websocket: self },
-                    rx,
-                })
+        self.ensure_not_closed()?;
+        self.handshake_deadline()?;
+        self.flush_pong()?;
+        self.keepalive()?;
+        self.drain_senders()?;
+        let network_read = self.state.needs_more_data();
+        let rx = match self.read()? && network_read {
+            true => self.stream.take_last_rx_timestamps(),
+            false => None,
+        };
+        if let Some(rx) = rx {
+            self.rx_timestamps = rx;
+        }
+        Ok(BatchTs {
+            batch: Batch { websocket: self },
+            rx,
+        })
+    }
+
+    /// Same as [`Websocket::read_batch`], but waits until some data has been read (or is still buffered
+    /// from the previous read) and fails with [`Error::ReadTimeout`] if none has arrived before the
+    /// `deadline`, so the blocking-style consumers still get to their periodic housekeeping. The thread
+    /// sleeps in the meantime: a non-blocking stream is polled for readiness and the read of a blocking
+    /// one is bounded by the socket read timeout (`SO_RCVTIMEO`) for the duration of the call. The batch
+    /// can be empty if the data read is not a complete frame yet, the websocket remains usable after the
+    /// timeout.
+    ///
+    /// ## Examples
+    /// ```no_run
+    /// use std::io::{Read, Write};
+    /// use std::os::fd::AsRawFd;
+    /// use std::time::Duration;
+    /// use boomnet::ws::{Error, Websocket, WebsocketFrame};
+    ///
+    /// fn process<S: Read + Write + AsRawFd>(ws: &mut Websocket<S>) -> Result<(), Error> {
+    ///     loop {
+    ///         match ws.read_batch_timeout(Duration::from_millis(100)) {
+    ///             Ok(batch) => {
+    ///                 for frame in batch {
+    ///                     if let WebsocketFrame::Text(_, data) = frame? {
+    ///                         println!("{}", String::from_utf8_lossy(data));
+    ///                     }
+    ///                 }
+    ///             }
+    ///             Err(Error::ReadTimeout) => {}
+    ///             Err(err) => return Err(err),
+    ///         }
+    ///         // housekeeping, at least every 100ms
+    ///     }
+    /// }
+    /// ```
+    pub fn read_batch_deadline(&mut self, deadline: Instant) -> Result<Batch<'_, S>, Error>
+    where
+        S: AsRawFd,
+    {
+        let fd = self.stream.as_raw_fd();
+        let blocking = !is_nonblocking(fd)?;
+        loop {
+            self.ensure_not_closed()?;
+            self.handshake_deadline()?;
+            self.flush_pong()?;
+            self.keepalive()?;
+            self.drain_senders()?;
+            // wake up in time to fail the stalled handshake
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let timeout = self
+                .handshake_remaining()
+                .map_or(timeout, |remaining| remaining.min(timeout));
+            let read = match blocking {
+                true => with_read_timeout(fd, timeout, || self.read())??,
+                false => self.read()?,
+            };
+            // the read reports `false` once the stream has reached EOF, the decoder needs no more data
+            // if some has been read or frames are left from the previous batch
+            if !read || !self.state.needs_more_data() {
+                return Ok(Batch { websocket: self });
             }
-            Err(err) => {
-                self.closed = true;
-                Err(err)?
+            if Instant::now() >= deadline {
+                return Err(Error::ReadTimeout);
+            }
+            if !blocking {
+                wait_ready(fd, libc::POLLIN, Some(timeout))?;
             }
         }
     }
 
+    /// Same as [`Websocket::read_batch_deadline`] with the deadline `timeout` from now.
+    #[inline]
+    pub fn read_batch_timeout(&mut self, timeout: Duration) -> Result<Batch<'_, S>, Error>
+    where
+        S: AsRawFd,
+    {
+        self.read_batch_deadline(Instant::now() + timeout)
+    }
+
+    /// Drive the upgrade until the handshake has completed, blocking the current thread. Fails with
+    /// [`Error::HandshakeTimeout`] once the [`Websocket::with_handshake_timeout`] has passed, without
+    /// it only an IO error or the server rejecting the upgrade ends the wait. The thread sleeps while
+    /// waiting for the server: a non-blocking stream is polled for readiness and the reads of a blocking
+    /// one are bounded by the socket read timeout (`SO_RCVTIMEO`) set to the time left.
+    pub fn wait_for_handshake(&mut self) -> Result<(), Error>
+    where
+        S: AsRawFd,
+    {
+        let fd = self.stream.as_raw_fd();
+        let blocking = !is_nonblocking(fd)?;
+        while !self.handshake_complete() {
+            let timeout = self.handshake_remaining();
+            let mut step = || match self.read_batch() {
+                Ok(mut batch) => batch.receive_next().and_then(Result::err),
+                Err(err) => Some(err),
+            };
+            let err = match (blocking, timeout) {
+                (true, Some(timeout)) => with_read_timeout(fd, timeout, step)?,
+                _ => step(),
+            };
+            if let Some(err) = err {
+                return Err(err);
+            }
+            if !blocking && !self.handshake_complete() {
+                // the request is sent once the connection has been established
+                let events = match self.state.needs_more_data() {
+                    true => libc::POLLIN,
+                    false => libc::POLLOUT,
+                };
+                wait_ready(fd, events, self.handshake_remaining())?;
+            }
+        }
+        Ok(())
+    }
+
     #[inline]
     pub fn receive_next(&mut self) -> Option<Result<WebsocketFrame, Error>> {
         match self.read_batch() {
@@ -237,6 +1123,59 @@ impl<S: Read + Write> Websocket<S> {
         self.send(fin, protocol::op::BINARY_FRAME, body)
     }
 
+    /// Send text frame with the payload gathered from the `parts` (at most [`MAX_VECTORED_PARTS`]),
+    /// the frame header and the parts are handed to the stream in a single vectored write instead of
+    /// being copied into a contiguous buffer first. Meant for large messages on the hot path, e.g. an
+    /// order batch assembled from the individually encoded orders.
+    ///
+    /// ## Examples
+    /// ```no_run
+    /// use std::io::{Read, Write};
+    /// use boomnet::ws::Websocket;
+    ///
+    /// fn send_batch<S: Read + Write>(ws: &mut Websocket<S>, orders: &[u8]) -> Result<(), boomnet::ws::Error> {
+    ///     ws.send_text_vectored(true, &[br#"{"op":"order.create-batch","args":["#, orders, b"]}"])
+    /// }
+    /// ```
+    #[inline]
+    pub fn send_text_vectored(&mut self, fin: bool, parts: &[&[u8]]) -> Result<(), Error> {
+        self.send_vectored(fin, protocol::op::TEXT_FRAME, parts)
+    }
+
+    /// Send binary frame with the payload gathered from the `parts` in a single vectored write, see
+    /// [`Websocket::send_text_vectored`].
+    #[inline]
+    pub fn send_binary_vectored(&mut self, fin: bool, parts: &[&[u8]]) -> Result<(), Error> {
+        self.send_vectored(fin, protocol::op::BINARY_FRAME, parts)
+    }
+
+    /// Send complete text message ahead of the queued data frames, e.g. a cancel that must not wait
+    /// for a backlog of orders. The message still waits for a fragmented message being written to
+    /// complete, as data frames can not be interleaved. Control frames (ping, pong and close) always
+    /// take this priority lane. Bytes already handed to the stream keep their order.
+    ///
+    /// ## Examples
+    /// ```no_run
+    /// use std::io::{Read, Write};
+    /// use boomnet::ws::Websocket;
+    ///
+    /// fn cancel_all<S: Read + Write>(ws: &mut Websocket<S>) -> Result<(), boomnet::ws::Error> {
+    ///     ws.send_text_urgent(br#"{"method":"order.cancelAll"}"#)
+    /// }
+    /// ```
+    #[inline]
+    pub fn send_text_urgent(&mut self, body: &[u8]) -> Result<(), Error> {
+        self.send_on(Lane::Urgent, true, protocol::op::TEXT_FRAME, Some(body))
+    }
+
+    /// Send complete binary message ahead of the queued data frames, see [`Websocket::send_text_urgent`].
+    #[inline]
+    pub fn send_binary_urgent(&mut self, body: &[u8]) -> Result<(), Error> {
+        self.send_on(Lane::Urgent, true, protocol::op::BINARY_FRAME, Some(body))
+    }
+
+    /// Send pong with the `body`, the automatic pongs (see [`Websocket::with_auto_pong`]) echo the
+    /// payload of the ping.
     #[inline]
     pub fn send_pong(&mut self, body: Option<&[u8]>) -> Result<(), Error> {
         self.send(true, protocol::op::PONG, body)
@@ -247,28 +1186,497 @@ impl<S: Read + Write> Websocket<S> {
         self.send(true, protocol::op::PING, body)
     }
 
+    /// Send a ping and measure its round trip time once the pong echoing the `payload` (at most 125
+    /// bytes) is received, see [`Websocket::rtt`] and [`Websocket::last_rtt`]. The payload should be
+    /// unique among the pings in flight (e.g. a sequence number), up to 16 of them (including the
+    /// keepalive pings) are tracked.
+    ///
+    /// ## Examples
+    /// ```no_run
+    /// use std::io::{Read, Write};
+    /// use boomnet::ws::Websocket;
+    ///
+    /// fn probe<S: Read + Write>(ws: &mut Websocket<S>, seq: u64) -> Result<(), boomnet::ws::Error> {
+    ///     ws.ping_with_payload(&seq.to_be_bytes())?;
+    ///     for frame in ws.read_batch()? {
+    ///         frame?;
+    ///     }
+    ///     if let Some(rtt) = ws.rtt() {
+    ///         println!("venue path rtt: {rtt}");
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn ping_with_payload(&mut self, payload: &[u8]) -> Result<(), Error> {
+        if payload.len() > MAX_PING_PAYLOAD {
+            return Err(Error::Protocol("ping payload exceeds 125 bytes"));
+        }
+        self.send(true, protocol::op::PING, Some(payload))?;
+        self.rtt.on_ping(payload);
+        Ok(())
+    }
+
+    /// Perform the RFC 6455 closing handshake: send the close frame with the status `code` and `reason`
+    /// and wait (up to [`Websocket::with_close_timeout`]) for the peer to answer with its own close frame,
+    /// which is returned as `(code, reason)`. Data frames received in the meantime are discarded. Returns
+    /// `None` if the peer has not answered in time or has dropped the connection instead, the websocket
+    /// is closed in any case. The `code` must be one that can be sent on the wire and the `reason` must
+    /// not exceed 123 bytes. Like [`Websocket::read_batch_deadline`], the thread sleeps while waiting for the
+    /// peer: a non-blocking stream is polled for readiness and the reads of a blocking one are bounded by
+    /// the socket read timeout.
+    ///
+    /// ## Examples
+    /// ```no_run
+    /// use std::io::{Read, Write};
+    /// use std::os::fd::AsRawFd;
+    /// use boomnet::ws::Websocket;
+    ///
+    /// fn shutdown<S: Read + Write + AsRawFd>(ws: &mut Websocket<S>) -> Result<(), boomnet::ws::Error> {
+    ///     match ws.close(1001, "going away")? {
+    ///         Some((code, reason)) => println!("peer closed with {code}: {reason}"),
+    ///         None => println!("peer did not answer the close frame"),
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn close(&mut self, code: u16, reason: &str) -> Result<Option<(u16, String)>, Error>
+    where
+        S: AsRawFd,
+    {
+        self.ensure_not_closed()?;
+        if self.closing {
+            return Err(Closing);
+        }
+        let payload = [&code.to_be_bytes()[..], reason.as_bytes()].concat();
+        if payload.len() > 125 {
+            return Err(Error::Protocol("close reason exceeds 123 bytes"));
+        }
+        validate_close(&payload)?;
+        if !self.handshake_complete() {
+            // nothing to close on the protocol level yet
+            self.closed = true;
+            return Ok(None);
+        }
+        self.flush_batch()?;
+        self.flush_pong()?;
+        // the urgent frames still go out, no data frame can follow the close frame
+        if let Err(err) = self.outbound.flush_urgent(
+            &mut self.send_queue.spill(&mut self.stream),
+            &mut self.masking,
+            self.state.role(),
+        ) {
+            self.closed = true;
+            Err(err)?
+        }
+        self.outbound.clear();
+        self.write_frame(Lane::Urgent, true, protocol::op::CONNECTION_CLOSE, Some(&payload))?;
+        self.send_queue.truncate_after_control();
+        self.closing = true;
+        let deadline = Instant::now() + self.close_timeout;
+        let fd = self.stream.as_raw_fd();
+        let blocking = !is_nonblocking(fd)?;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let read = match blocking {
+                true => with_read_timeout(fd, timeout, || self.read())??,
+                false => self.read()?,
+            };
+            loop {
+                match self.next() {
+                    Ok(Some(_)) => {}
+                    Ok(None) => break,
+                    Err(ReceivedCloseFrame(code, reason)) => return Ok(Some((code, reason))),
+                    Err(Closed) => {
+                        self.closed = true;
+                        return Ok(None);
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            if !read || Instant::now() >= deadline {
+                self.closed = true;
+                return Ok(None);
+            }
+            if !blocking {
+                wait_ready(fd, libc::POLLIN, Some(timeout))?;
+            }
+        }
+    }
+
+    /// Queue the close frame (status `1000`) behind any pending frames and stop accepting new ones,
+    /// the websocket is closed once [`Websocket::poll_close`] has flushed everything to the kernel.
+    /// Meant for emergency shutdowns where e.g. cancels must be written before teardown.
+    ///
+    /// ## Examples
+    /// ```no_run
+    /// use std::io::{Read, Write};
+    /// use boomnet::stream::PendingWrites;
+    /// use boomnet::ws::Websocket;
+    ///
+    /// fn shutdown<S: Read + Write + PendingWrites>(ws: &mut Websocket<S>) -> Result<(), boomnet::ws::Error> {
+    ///     ws.send_text(true, Some(br#"{"method":"order.cancelAll"}"#))?;
+    ///     ws.close_after_flush()?;
+    ///     while !ws.poll_close()? {}
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn close_after_flush(&mut self) -> Result<(), Error> {
+        self.ensure_not_closed()?;
+        if self.closing {
+            return Ok(());
+        }
+        self.flush_batch()?;
+        self.flush_pong()?;
+        let normal_closure = 1000u16.to_be_bytes();
+        self.write_frame(Lane::Bulk, true, protocol::op::CONNECTION_CLOSE, Some(&normal_closure))?;
+        self.closing = true;
+        Ok(())
+    }
+
+    /// Drive the pending frames towards the kernel without blocking (completing the handshake first
+    /// if needed), returns `true` once everything including the close frame has been written and the
+    /// websocket is closed. Starts [`Websocket::close_after_flush`] if not requested yet. An error
+    /// means some of the pending frames may have not been written.
+    pub fn poll_close(&mut self) -> Result<bool, Error>
+    where
+        S: PendingWrites,
+    {
+        if self.flushed {
+            return Ok(true);
+        }
+        self.close_after_flush()?;
+        if !self.handshake_complete() {
+            // the pending frames are written as soon as the handshake completes
+            self.read_batch()?;
+            self.next()?;
+            if !self.handshake_complete() {
+                return Ok(false);
+            }
+        }
+        self.drain_send_queue()?;
+        if !self.send_queue.is_empty() {
+            return Ok(false);
+        }
+        let role = self.state.role();
+        if let Err(err) =
+            self.outbound
+                .flush(&mut self.send_queue.spill(&mut self.stream), &mut self.masking, role, false)
+        {
+            self.closed = true;
+            Err(err)?
+        }
+        if !self.send_queue.is_empty() {
+            return Ok(false);
+        }
+        match self.stream.drive_writes() {
+            Ok(flushed) => {
+                self.flushed = flushed;
+                self.closed = flushed;
+                Ok(flushed)
+            }
+            Err(err) => {
+                self.closed = true;
+                Err(err)?
+            }
+        }
+    }
+
+    /// Single network read unless the peer has already closed its side, returns `true` if the read
+    /// has been performed. The EOF is only reported once the frames received before it are decoded.
+    #[inline]
+    fn read(&mut self) -> Result<bool, Error> {
+        if self.eof {
+            return Ok(false);
+        }
+        self.drain_send_queue()?;
+        if self.state.needs_more_data() {
+            if let Some(middleware) = self.middleware.as_mut() {
+                middleware.recycle();
+            }
+            if let Some(reassembler) = self.reassembler.as_mut() {
+                reassembler.recycle();
+            }
+        }
+        match self.state.read(&mut self.stream).no_block() {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == UnexpectedEof => {
+                self.eof = true;
+                Ok(false)
+            }
+            Err(err) => {
+                self.closed = true;
+                Err(err)?
+            }
+        }
+    }
+
     #[inline]
     fn next(&mut self) -> Result<Option<WebsocketFrame>, Error> {
+        loop {
+            let Some(mut frame) = self.decode_next()? else {
+                return Ok(None);
+            };
+            if let Some(reassembler) = self.reassembler.as_mut() {
+                match reassembler.apply(frame) {
+                    Ok(Some(message)) => frame = message,
+                    // fragment of an incomplete message, carry on with the next frame of the batch
+                    Ok(None) => continue,
+                    Err(err) => {
+                        self.closed = true;
+                        return Err(err);
+                    }
+                }
+            }
+            if let Some(middleware) = self.middleware.as_mut() {
+                match middleware.apply(frame) {
+                    Some(delivered) => frame = delivered,
+                    // dropped, carry on with the next frame of the batch
+                    None => continue,
+                }
+            }
+            return Ok(Some(frame));
+        }
+    }
+
+    #[inline]
+    fn decode_next(&mut self) -> Result<Option<WebsocketFrame>, Error> {
+        self.ensure_not_closed()?;
+        probe!(frame_start);
+        // the peer close is only answered if we have not sent ours already
+        let echo_close = !self.closing;
+        #[cfg(feature = "profile")]
+        let span = self.profiler.as_ref().map(|profiler| profiler.span(Stage::Decode));
+        let result = self.state.next(
+            &mut self.send_queue.spill(&mut self.stream),
+            &mut self.pong,
+            &mut self.masking,
+            &mut self.protocol,
+            &mut self.handshake_response,
+            echo_close,
+        );
+        #[cfg(feature = "profile")]
+        drop(span);
+        match result {
+            Ok(None) if self.eof => {
+                // nothing left to decode, the write side stays open with `HalfClose::Flush`
+                self.closed |= self.half_close == HalfClose::Close;
+                Err(Closed)
+            }
+            Ok(None) => {
+                // end of the batch (or the handshake has just completed)
+                self.flush_outbound()?;
+                Ok(None)
+            }
+            Ok(frame) => {
+                if let Some(WebsocketFrame::Pong(payload)) = &frame {
+                    if let Some(keepalive) = self.keepalive.as_mut() {
+                        keepalive.on_pong();
+                    }
+                    self.rtt.on_pong(payload, self.rx_timestamps);
+                }
+                #[cfg(feature = "usdt")]
+                if let Some((op_code, payload)) = frame.as_ref().map(WebsocketFrame::parts) {
+                    probe!(frame_dispatch, op_code, payload.len());
+                }
+                Ok(frame)
+            }
+            Err(err) => {
+                if echo_close && matches!(err, ReceivedCloseFrame(_, _) | Error::Violation(_)) {
+                    // the close frame has been written ahead of the queued data frames
+                    self.send_queue.truncate_after_control();
+                }
+                self.closed = true;
+                Err(err)?
+            }
+        }
+    }
+
+    #[inline]
+    fn send(&mut self, fin: bool, op_code: u8, body: Option<&[u8]>) -> Result<(), Error> {
+        let control_first = self.send_queue.order() == SendOrder::ControlFirst;
+        let lane = match protocol::op::is_control(op_code) && control_first {
+            true => Lane::Urgent,
+            false => Lane::Bulk,
+        };
+        self.send_on(lane, fin, op_code, body)
+    }
+
+    #[inline]
+    fn send_on(&mut self, lane: Lane, fin: bool, op_code: u8, body: Option<&[u8]>) -> Result<(), Error> {
+        self.ensure_not_closed()?;
+        if self.closing {
+            return Err(Closing);
+        }
+        self.flush_pong()?;
+        self.write_frame(lane, fin, op_code, body)
+    }
+
+    /// Write the frame straight away (through the send queue) or queue it behind the frames of its
+    /// `lane` (or until the handshake completes).
+    #[inline]
+    fn write_frame(&mut self, lane: Lane, fin: bool, op_code: u8, body: Option<&[u8]>) -> Result<(), Error> {
+        if !self.handshake_complete() || !self.outbound.bypass(lane, op_code) {
+            self.outbound.push(lane, fin, op_code, body.unwrap_or_default());
+            return Ok(());
+        }
+        let role = self.state.role();
+        let key = self.masking.next_key(role);
+        // coalesced until the batch is flushed, the urgent lane and the control frames are never corked
+        if let Some(cork) = self
+            .cork
+            .as_mut()
+            .filter(|_| lane == Lane::Bulk && !protocol::op::is_control(op_code))
+        {
+            encoder::send_masked(cork, key, fin, op_code, body)?;
+            self.outbound.written(cork, &mut self.masking, role, fin, op_code)?;
+            return Ok(());
+        }
+        // only the urgent control frames jump ahead, the close frame of `close_after_flush` stays behind
+        let mut stream = self.send_queue.spill(&mut self.stream);
+        let result = stream
+            .encode_frame(lane == Lane::Urgent && protocol::op::is_control(op_code), |stream| {
+                encoder::send_masked(stream, key, fin, op_code, body)
+            })
+            .and_then(|()| {
+                self.outbound
+                    .written(&mut stream, &mut self.masking, role, fin, op_code)
+            });
+        if let Err(err) = result {
+            self.closed = true;
+            Err(err)?
+        }
+        Ok(())
+    }
+
+    /// Drain the send queue, then write the queued frames (bounded by [`Websocket::with_bulk_flush_limit`])
+    /// once the handshake has completed and the send queue is empty.
+    #[inline]
+    fn flush_outbound(&mut self) -> Result<(), Error> {
+        self.drain_send_queue()?;
+        if self.outbound.is_empty() || !self.handshake_complete() || !self.send_queue.is_empty() {
+            return Ok(());
+        }
+        let role = self.state.role();
+        if let Err(err) =
+            self.outbound
+                .flush(&mut self.send_queue.spill(&mut self.stream), &mut self.masking, role, true)
+        {
+            self.closed = true;
+            Err(err)?
+        }
+        Ok(())
+    }
+
+    /// Write the bytes held in the send queue until the stream would block.
+    #[inline]
+    fn drain_send_queue(&mut self) -> Result<(), Error> {
+        if self.send_queue.is_empty() {
+            return Ok(());
+        }
+        if let Err(err) = self.send_queue.drain(&mut self.stream) {
+            self.closed = true;
+            Err(err)?
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn send_vectored(&mut self, fin: bool, op_code: u8, parts: &[&[u8]]) -> Result<(), Error> {
         self.ensure_not_closed()?;
-        match self.state.next(&mut self.stream) {
-            Ok(frame) => Ok(frame),
+        if self.closing {
+            return Err(Closing);
+        }
+        if parts.len() > MAX_VECTORED_PARTS {
+            return Err(Error::Protocol("too many payload parts"));
+        }
+        self.flush_pong()?;
+        if !self.handshake_complete() || !self.outbound.bypass(Lane::Bulk, op_code) {
+            self.outbound.push(Lane::Bulk, fin, op_code, &parts.concat());
+            return Ok(());
+        }
+        let role = self.state.role();
+        let key = self.masking.next_key(role);
+        if let Some(cork) = self.cork.as_mut() {
+            encoder::send_vectored_masked(cork, key, fin, op_code, parts)?;
+            self.outbound.written(cork, &mut self.masking, role, fin, op_code)?;
+            return Ok(());
+        }
+        let mut stream = self.send_queue.spill(&mut self.stream);
+        let result = stream
+            .encode_frame(false, |stream| encoder::send_vectored_masked(stream, key, fin, op_code, parts))
+            .and_then(|()| {
+                self.outbound
+                    .written(&mut stream, &mut self.masking, role, fin, op_code)
+            });
+        if let Err(err) = result {
+            self.closed = true;
+            Err(err)?
+        }
+        Ok(())
+    }
+
+    /// Time left until the handshake timeout, `None` without the timeout or once the handshake has
+    /// completed.
+    fn handshake_remaining(&mut self) -> Option<Duration> {
+        match self.handshake_complete() {
+            true => None,
+            // zero once the deadline has passed, the next check fails the websocket
+            false => Some(self.handshake_deadline.as_mut()?.remaining().unwrap_or_default()),
+        }
+    }
+
+    /// Enforce the handshake timeout (if any) until the handshake has completed.
+    #[inline]
+    fn handshake_deadline(&mut self) -> Result<(), Error> {
+        if self.handshake_complete() {
+            self.handshake_deadline = None;
+        }
+        let Some(deadline) = self.handshake_deadline.as_mut() else {
+            return Ok(());
+        };
+        if let Err(err) = deadline.check() {
+            self.closed = true;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Send the keepalive ping if due, the websocket is closed once too many pongs have been missed.
+    #[inline]
+    fn keepalive(&mut self) -> Result<(), Error> {
+        if self.closed || self.closing || !self.handshake_complete() {
+            return Ok(());
+        }
+        let Some(keepalive) = self.keepalive.as_mut() else {
+            return Ok(());
+        };
+        match keepalive.poll(self.rtt.now()) {
+            Ok(Some(payload)) => {
+                self.send(true, protocol::op::PING, Some(&payload))?;
+                self.rtt.on_ping(&payload);
+                Ok(())
+            }
+            Ok(None) => Ok(()),
             Err(err) => {
                 self.closed = true;
-                Err(err)?
+                Err(err)
             }
         }
     }
 
+    /// Send the pong queued during the previous batch (if any).
     #[inline]
-    fn send(&mut self, fin: bool, op_code: u8, body: Option<&[u8]>) -> Result<(), Error> {
-        self.ensure_not_closed()?;
-        match self.state.send(&mut self.stream, fin, op_code, body) {
-            Ok(()) => Ok(()),
-            Err(err) => {
+    fn flush_pong(&mut self) -> Result<(), Error> {
+        if self.pong.pending {
+            if let Err(err) =
+                self.pong
+                    .flush(&mut self.send_queue.spill(&mut self.stream), &mut self.masking, self.state.role())
+            {
                 self.closed = true;
                 Err(err)?
             }
         }
+        Ok(())
     }
 
     #[inline]
@@ -295,6 +1703,26 @@ impl<S: Source> Source for Websocket<S> {
     }
 }
 
+/// Exposes the TLS parameters negotiated by the underlying stream, e.g. to verify a venue connection
+/// is on TLS 1.3 with the expected cipher.
+///
+/// ## Examples
+/// ```no_run
+/// use boomnet::stream::tls::TlsParametersProvider;
+/// use boomnet::ws::TryIntoTlsReadyWebsocket;
+///
+/// let ws = "wss://stream.binance.com/ws".try_into_tls_ready_websocket().unwrap();
+/// if let Some(params) = ws.tls_parameters() {
+///     assert!(params.is_tls13(), "unexpected tls parameters: {params}");
+/// }
+/// ```
+#[cfg(any(feature = "rustls", feature = "openssl"))]
+impl<S: TlsParametersProvider> TlsParametersProvider for Websocket<S> {
+    fn tls_parameters(&self) -> Option<TlsParameters> {
+        self.stream.tls_parameters()
+    }
+}
+
 impl<S: Selectable> Selectable for Websocket<S> {
     fn connected(&mut self) -> io::Result<bool> {
         self.stream.connected()
@@ -307,22 +1735,114 @@ impl<S: Selectable> Selectable for Websocket<S> {
     fn make_readable(&mut self) -> io::Result<()> {
         self.stream.make_readable()
     }
+
+    fn take_read_activity(&mut self) -> bool {
+        self.stream.take_read_activity()
+    }
+
+    fn take_bytes_read(&mut self) -> u64 {
+        self.stream.take_bytes_read()
+    }
+
+    fn take_bytes_written(&mut self) -> u64 {
+        self.stream.take_bytes_written()
+    }
+
+    fn set_read_paused(&mut self, paused: bool) {
+        self.stream.set_read_paused(paused)
+    }
 }
 
 #[derive(Debug)]
 #[allow(clippy::large_enum_variant)]
 enum State {
-    Handshake(Handshaker, BufferPoolRef),
+    Handshake(Handshaker, BufferPoolRef, DecoderConfig),
     Connection(Decoder),
 }
 
 impl State {
     pub fn handshake(server_name: &str, endpoint: &str, mut pool: BufferPoolRef) -> Self {
-        Self::Handshake(Handshaker::new(server_name, endpoint, &mut pool), pool)
+        Self::Handshake(Handshaker::new(server_name, endpoint, &mut pool), pool, DecoderConfig::default())
+    }
+
+    pub fn connection(mut pool: BufferPoolRef, config: DecoderConfig) -> Self {
+        Self::Connection(Decoder::new(&mut pool, config))
+    }
+
+    #[inline]
+    const fn role(&self) -> Role {
+        match self {
+            State::Handshake(_, _, _) => Role::Client,
+            State::Connection(decoder) => decoder.role(),
+        }
+    }
+
+    #[inline]
+    const fn needs_more_data(&self) -> bool {
+        match self {
+            State::Handshake(handshake, _, _) => handshake.needs_more_data(),
+            State::Connection(decoder) => decoder.needs_more_data(),
+        }
+    }
+}
+
+/// Reply to the most recent ping, held until the next safe point: the end of the batch, the next
+/// `read_batch` or the next frame sent. RFC 6455 allows to only answer the most recent ping when
+/// several arrive before the pong has been sent.
+#[derive(Debug)]
+struct PendingPong {
+    payload: Vec<u8>,
+    pending: bool,
+    // pings are handed out and answered by the user
+    manual: bool,
+}
+
+/// Fail the connection on the `violation` (RFC 6455 section 7.1.7), the close frame with its status
+/// code is sent on a best effort basis unless the websocket is already closing.
+#[cold]
+fn fail<S: FrameSink>(
+    stream: &mut S,
+    masking: &mut Masking,
+    role: Role,
+    echo_close: bool,
+    violation: Violation,
+) -> Error {
+    if echo_close {
+        let status_code = violation.status_code().to_be_bytes();
+        let key = masking.next_key(role);
+        let _ = stream.encode_frame(true, |stream| {
+            encoder::send_masked(stream, key, true, protocol::op::CONNECTION_CLOSE, Some(&status_code))
+        });
+    }
+    Error::Violation(violation)
+}
+
+impl PendingPong {
+    fn new() -> Self {
+        Self {
+            payload: Vec::with_capacity(125),
+            pending: false,
+            manual: false,
+        }
     }
 
-    pub fn connection(mut pool: BufferPoolRef) -> Self {
-        Self::Connection(Decoder::new(&mut pool))
+    #[inline]
+    fn queue(&mut self, payload: &[u8]) {
+        self.payload.clear();
+        self.payload.extend_from_slice(payload);
+        self.pending = true;
+    }
+
+    #[inline]
+    fn flush<S: FrameSink>(&mut self, stream: &mut S, masking: &mut Masking, role: Role) -> io::Result<()> {
+        if self.pending {
+            self.pending = false;
+            let key = masking.next_key(role);
+            stream.encode_frame(true, |stream| {
+                encoder::send_masked(stream, key, true, protocol::op::PONG, Some(&self.payload))
+            })?;
+        }
+        Ok(())
     }
 }
 
@@ -330,54 +1850,81 @@ impl State {
     #[inline]
     fn read<S: Read>(&mut self, stream: &mut S) -> io::Result<()> {
         match self {
-            State::Handshake(handshake, _) => handshake.read(stream),
+            State::Handshake(handshake, _, _) => handshake.read(stream),
             State::Connection(decoder) => decoder.read(stream),
         }
     }
 
     #[inline]
-    fn next<S: Read + Write>(&mut self, stream: &mut S) -> Result<Option<WebsocketFrame>, Error> {
+    fn next<S: Read + FrameSink>(
+        &mut self,
+        stream: &mut S,
+        pong: &mut PendingPong,
+        masking: &mut Masking,
+        subprotocol: &mut Option<String>,
+        response: &mut Option<HandshakeResponse>,
+        echo_close: bool,
+    ) -> Result<Option<WebsocketFrame>, Error> {
         match self {
-            State::Handshake(handshake, pool) => match handshake.perform_handshake(stream) {
+            State::Handshake(handshake, pool, config) => match handshake.perform_handshake(stream) {
                 Ok(()) => {
-                    handshake.drain_pending_message_buffer(stream, encoder::send)?;
-                    *self = State::connection(pool.clone());
+                    *subprotocol = handshake.take_protocol();
+                    *response = handshake.take_response();
+                    let decoder = Decoder::new(pool, *config);
+                    #[cfg(feature = "deflate")]
+                    let decoder = decoder.with_inflater(deflate::negotiate(config.deflate, handshake.extensions())?);
+                    *self = State::Connection(decoder);
                     Ok(None)
                 }
                 Err(err) if err.kind() == WouldBlock => Ok(None),
-                Err(err) => Err(err)?,
+                Err(err) => match handshake.response().and_then(HandshakeResponse::redirect) {
+                    Some(location) => Err(Error::Redirect(location.to_owned())),
+                    None => Err(err)?,
+                },
             },
-            State::Connection(decoder) => match decoder.decode_next() {
-                Ok(Some(WebsocketFrame::Ping(payload))) => {
-                    self.send(stream, true, protocol::op::PONG, Some(payload))?;
-                    Ok(None)
-                }
-                Ok(Some(WebsocketFrame::Close(payload))) => {
-                    let _ = self.send(stream, true, protocol::op::CONNECTION_CLOSE, Some(payload));
-                    let (status_code, body) = payload.split_at(std::mem::size_of::<u16>());
-                    let status_code = u16::from_be_bytes(status_code.try_into()?);
-                    let body = String::from_utf8_lossy(body).to_string();
-                    Err(ReceivedCloseFrame(status_code, body))
+            State::Connection(decoder) => loop {
+                match decoder.decode_next() {
+                    Ok(Some(WebsocketFrame::Ping(payload))) if !pong.manual => {
+                        // keep iterating the batch, the pong goes out at the next safe point
+                        pong.queue(payload);
+                    }
+                    Ok(Some(WebsocketFrame::Close(payload))) => {
+                        if decoder.validation() == Validation::Strict {
+                            if let Err(violation) = validate_close(payload) {
+                                return Err(fail(stream, masking, decoder.role(), echo_close, violation));
+                            }
+                        }
+                        let _ = pong.flush(stream, masking, decoder.role());
+                        if echo_close {
+                            let op_code = protocol::op::CONNECTION_CLOSE;
+                            let key = masking.next_key(decoder.role());
+                            let _ = stream.encode_frame(true, |stream| {
+                                encoder::send_masked(stream, key, true, op_code, Some(payload))
+                            });
+                        }
+                        if payload.len() < std::mem::size_of::<u16>() {
+                            // no status code present
+                            return Err(ReceivedCloseFrame(1005, String::new()));
+                        }
+                        let (status_code, body) = payload.split_at(std::mem::size_of::<u16>());
+                        let status_code = u16::from_be_bytes(status_code.try_into()?);
+                        let body = String::from_utf8_lossy(body).to_string();
+                        return Err(ReceivedCloseFrame(status_code, body));
+                    }
+                    Ok(None) => {
+                        // end of the batch
+                        pong.flush(stream, masking, decoder.role())?;
+                        return Ok(None);
+                    }
+                    Ok(frame) => return Ok(frame),
+                    Err(Error::Violation(violation)) => {
+                        return Err(fail(stream, masking, decoder.role(), echo_close, violation));
+                    }
+                    Err(err) => return Err(err)?,
                 }
-                Ok(frame) => Ok(frame),
-                Err(err) => Err(err)?,
             },
         }
     }
-
-    #[inline]
-    fn send<S: Write>(&mut self, stream: &mut S, fin: bool, op_code: u8, body: Option<&[u8]>) -> Result<(), Error> {
-        match self {
-            State::Handshake(handshake, _) => {
-                handshake.buffer_message(fin, op_code, body);
-                Ok(())
-            }
-            State::Connection(_) => {
-                encoder::send(stream, fin, op_code, body)?;
-                Ok(())
-            }
-        }
-    }
 }
 
 /// Represents a batch of 0 to N websocket frames since the last network read that are ready to be decoded.
@@ -392,18 +1939,22 @@ pub struct BatchTs<'a, S> {
 }
 
 impl<'a, S> BatchTs<'a, S> {
+    /// RX timestamps captured by the network read of this batch, `None` if there has been no read
+    /// (or the stream has not captured any).
     pub fn rx_timestamps(&self) -> Option<RxTimestamps> {
         self.rx
     }
 }
 
 impl<'a, S: Read + Write> BatchTs<'a, S> {
-    pub fn iter(self) -> BatchIter<'a, S> {
-        self.batch.into_iter()
+    pub fn iter(self) -> BatchTsIter<'a, S> {
+        self.into_iter()
     }
 
-    pub fn receive_next(&mut self) -> Option<Result<WebsocketFrame, Error>> {
-        self.batch.receive_next()
+    /// Try to decode next frame, paired with the RX timestamps of the network read that completed it.
+    pub fn receive_next(&mut self) -> Option<Result<(WebsocketFrame, RxTimestamps), Error>> {
+        let frame = self.batch.receive_next()?;
+        Some(frame.map(|frame| (frame, self.batch.websocket.rx_timestamps)))
     }
 }
 
@@ -417,11 +1968,11 @@ impl<'a, S: Read + Write> IntoIterator for Batch<'a, S> {
 }
 
 impl<'a, S: Read + Write> IntoIterator for BatchTs<'a, S> {
-    type Item = Result<WebsocketFrame, Error>;
-    type IntoIter = BatchIter<'a, S>;
+    type Item = Result<(WebsocketFrame, RxTimestamps), Error>;
+    type IntoIter = BatchTsIter<'a, S>;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.batch.into_iter()
+        BatchTsIter { batch: self }
     }
 }
 
@@ -447,10 +1998,37 @@ impl<S: Read + Write> Iterator for BatchIter<'_, S> {
     }
 }
 
+/// Iterator that owns the current `BatchTs`, yields every frame with its RX timestamps.
+pub struct BatchTsIter<'a, S> {
+    batch: BatchTs<'a, S>,
+}
+
+impl<S: Read + Write> Iterator for BatchTsIter<'_, S> {
+    type Item = Result<(WebsocketFrame, RxTimestamps), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.batch.receive_next()
+    }
+}
+
+/// Upgrade the stream to a websocket, any `Read + Write` transport established outside the crate (e.g.
+/// forwarded over SSH) can be upgraded once paired with the remote host, see
+/// [`IntoTransport`](crate::stream::transport::IntoTransport).
 pub trait IntoWebsocket {
     fn into_websocket(self, endpoint: &str) -> Websocket<Self>
     where
         Self: Sized;
+
+    /// Same as [`IntoWebsocket::into_websocket`] with extra `headers` included in the upgrade request,
+    /// see [`Websocket::new_with_headers`].
+    fn into_websocket_with_headers(self, endpoint: &str, headers: &[(&str, &str)]) -> Websocket<Self>
+    where
+        Self: Sized,
+    {
+        headers
+            .iter()
+            .fold(self.into_websocket(endpoint), |ws, (name, value)| ws.with_header(name, value))
+    }
 }
 
 impl<T> IntoWebsocket for T
@@ -526,3 +2104,871 @@ where
         Ok(Websocket::new(tls_ready_stream, &endpoint))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::staging::IntoStagingStream;
+    use std::cell::{Cell, RefCell};
+    use std::os::fd::RawFd;
+    use std::rc::Rc;
+
+    /// Non-blocking socket that never becomes readable, polled in place of the in-memory test streams.
+    fn idle_fd() -> RawFd {
+        static IDLE: std::sync::OnceLock<(std::os::unix::net::UnixStream, std::os::unix::net::UnixStream)> =
+            std::sync::OnceLock::new();
+        let (idle, _) = IDLE.get_or_init(|| {
+            let (idle, peer) = std::os::unix::net::UnixStream::pair().unwrap();
+            idle.set_nonblocking(true).unwrap();
+            (idle, peer)
+        });
+        idle.as_raw_fd()
+    }
+
+    /// Stream with nothing to read that accepts at most `accept` bytes (none by default), shared by
+    /// the tests of the websocket modules. Counts the writes that have accepted some bytes and the
+    /// flushes.
+    #[derive(Default)]
+    pub(super) struct ThrottledStream {
+        pub(super) accept: Rc<Cell<usize>>,
+        pub(super) written: Rc<RefCell<Vec<u8>>>,
+        pub(super) writes: Rc<Cell<usize>>,
+        pub(super) flushes: Rc<Cell<usize>>,
+    }
+
+    impl ThrottledStream {
+        /// Stream that accepts every write in full.
+        pub(super) fn unthrottled() -> Self {
+            let stream = Self::default();
+            stream.accept.set(usize::MAX);
+            stream
+        }
+    }
+
+    impl Read for ThrottledStream {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::from(WouldBlock))
+        }
+    }
+
+    impl Write for ThrottledStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.accept.get() == 0 {
+                return Err(io::Error::from(WouldBlock));
+            }
+            let len = buf.len().min(self.accept.get());
+            self.accept.set(self.accept.get() - len);
+            self.written.borrow_mut().extend_from_slice(&buf[..len]);
+            self.writes.set(self.writes.get() + 1);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushes.set(self.flushes.get() + 1);
+            Ok(())
+        }
+    }
+
+    impl AsRawFd for ThrottledStream {
+        fn as_raw_fd(&self) -> RawFd {
+            idle_fd()
+        }
+    }
+
+    impl PendingWrites for ThrottledStream {
+        fn has_pending_writes(&self) -> bool {
+            false
+        }
+
+        fn drive_writes(&mut self) -> io::Result<bool> {
+            Ok(true)
+        }
+    }
+
+    #[test]
+    fn should_close_after_flushing_pending_frames() {
+        let stream = ThrottledStream::default();
+        let (accept, written) = (stream.accept.clone(), stream.written.clone());
+        let mut ws = Websocket::new_with_handshake_complete(stream.into_staging_stream(1));
+
+        ws.send_text(true, Some(b"cancel")).unwrap();
+        ws.close_after_flush().unwrap();
+        assert!(ws.closing());
+        assert!(matches!(ws.send_text(true, Some(b"order")), Err(Closing)));
+        assert!(ws.has_pending_writes());
+        assert!(!ws.poll_close().unwrap());
+        assert!(!ws.closed());
+
+        accept.set(usize::MAX);
+        assert!(ws.poll_close().unwrap());
+        assert!(ws.closed());
+        assert!(!ws.has_pending_writes());
+        let expected = [
+            &[0x81, 0x86, 0, 0, 0, 0][..],
+            b"cancel",
+            &[0x88, 0x82, 0, 0, 0, 0, 0x03, 0xe8],
+        ]
+        .concat();
+        assert_eq!(expected, *written.borrow());
+    }
+
+    #[test]
+    fn should_send_frame_with_vectored_write() {
+        let stream = ThrottledStream::default();
+        let (accept, written) = (stream.accept.clone(), stream.written.clone());
+        accept.set(usize::MAX);
+        let mut ws = Websocket::new_with_handshake_complete(stream);
+
+        // the default `write_vectored` takes one slice per call, so the frame is written across several calls
+        let orders = vec![b'o'; 300];
+        ws.send_text_vectored(true, &[b"[", &orders, b"]"]).unwrap();
+        ws.send_binary_vectored(true, &[]).unwrap();
+        let mut expected = vec![];
+        encoder::send(&mut expected, true, protocol::op::TEXT_FRAME, Some(&[b"[", &orders[..], b"]"].concat()))
+            .unwrap();
+        encoder::send(&mut expected, true, protocol::op::BINARY_FRAME, None).unwrap();
+        assert_eq!(expected, *written.borrow());
+
+        let parts = [&b"o"[..]; MAX_VECTORED_PARTS + 1];
+        assert!(matches!(ws.send_text_vectored(true, &parts), Err(Error::Protocol(_))));
+        assert!(!ws.closed());
+    }
+
+    #[test]
+    fn should_queue_bytes_until_the_stream_drains() {
+        let stream = ThrottledStream::default();
+        let (accept, written) = (stream.accept.clone(), stream.written.clone());
+        let transitions = Rc::new(RefCell::new(vec![]));
+        let mut ws = Websocket::new_with_handshake_complete(stream)
+            .with_write_watermarks(8, 16)
+            .with_backpressure_handler({
+                let transitions = transitions.clone();
+                move |backpressured| transitions.borrow_mut().push(backpressured)
+            });
+
+        // the stream cuts the first frame short, the rest of it and the next frame are queued
+        accept.set(4);
+        ws.send_text(true, Some(b"order-1")).unwrap();
+        assert_eq!(9, ws.queued_bytes());
+        assert!(!ws.is_backpressured());
+        ws.send_text(true, Some(b"order-2")).unwrap();
+        assert_eq!(22, ws.queued_bytes());
+        assert!(ws.is_backpressured());
+        assert!(ws.has_pending_writes());
+        assert!(!ws.closed());
+
+        accept.set(usize::MAX);
+        for frame in ws.read_batch().unwrap() {
+            frame.unwrap();
+        }
+        assert_eq!(0, ws.queued_bytes());
+        assert!(!ws.is_backpressured());
+        assert_eq!(vec![true, false], *transitions.borrow());
+        let mut expected = vec![];
+        encoder::send(&mut expected, true, protocol::op::TEXT_FRAME, Some(b"order-1")).unwrap();
+        encoder::send(&mut expected, true, protocol::op::TEXT_FRAME, Some(b"order-2")).unwrap();
+        assert_eq!(expected, *written.borrow());
+    }
+
+    #[test]
+    fn should_write_control_frames_ahead_of_queued_data_frames() {
+        let frames = |frames: &[(u8, &[u8])]| {
+            let mut expected = vec![];
+            for (op_code, payload) in frames {
+                encoder::send(&mut expected, true, *op_code, Some(payload)).unwrap();
+            }
+            expected
+        };
+        let (text, ping, pong) = (protocol::op::TEXT_FRAME, protocol::op::PING, protocol::op::PONG);
+        for order in [SendOrder::ControlFirst, SendOrder::Fifo] {
+            let stream = ThrottledStream::default();
+            let (accept, written) = (stream.accept.clone(), stream.written.clone());
+            let mut ws = Websocket::new_with_handshake_complete(stream).with_send_order(order);
+            assert_eq!(order, ws.send_order());
+
+            // the first frame is cut short, the control frames can only follow it
+            accept.set(4);
+            for order in [&b"order-1"[..], b"order-2", b"order-3"] {
+                ws.send_text(true, Some(order)).unwrap();
+            }
+            ws.send_ping(Some(b"hb")).unwrap();
+            ws.send_pong(Some(b"ka")).unwrap();
+            accept.set(usize::MAX);
+            for frame in ws.read_batch().unwrap() {
+                frame.unwrap();
+            }
+            let expected = match order {
+                SendOrder::ControlFirst => frames(&[
+                    (text, b"order-1"),
+                    (ping, b"hb"),
+                    (pong, b"ka"),
+                    (text, b"order-2"),
+                    (text, b"order-3"),
+                ]),
+                SendOrder::Fifo => frames(&[
+                    (text, b"order-1"),
+                    (text, b"order-2"),
+                    (text, b"order-3"),
+                    (ping, b"hb"),
+                    (pong, b"ka"),
+                ]),
+            };
+            assert_eq!(expected, *written.borrow());
+        }
+
+        // the close frame drops the data frames queued behind it
+        let stream = ThrottledStream::default();
+        let (accept, written) = (stream.accept.clone(), stream.written.clone());
+        let mut ws = Websocket::new_with_handshake_complete(stream).with_close_timeout(Duration::ZERO);
+        accept.set(4);
+        ws.send_text(true, Some(b"order-1")).unwrap();
+        ws.send_text(true, Some(b"order-2")).unwrap();
+        assert_eq!(None, ws.close(1000, "").unwrap());
+        accept.set(usize::MAX);
+        ws.drain_send_queue().unwrap();
+        let close = protocol::op::CONNECTION_CLOSE;
+        assert_eq!(frames(&[(text, b"order-1"), (close, &1000u16.to_be_bytes())]), *written.borrow());
+    }
+
+    struct ScriptedStream {
+        inbound: io::Cursor<Vec<u8>>,
+        written: Vec<u8>,
+        // peer closes the connection once the inbound frames are consumed
+        eof: bool,
+    }
+
+    impl ScriptedStream {
+        fn new(frames: &[(u8, &[u8])]) -> Self {
+            let mut inbound = vec![];
+            for (op_code, payload) in frames {
+                inbound.extend_from_slice(&[protocol::FIN_MASK | op_code, payload.len() as u8]);
+                inbound.extend_from_slice(payload);
+            }
+            Self {
+                inbound: io::Cursor::new(inbound),
+                written: vec![],
+                eof: false,
+            }
+        }
+
+        fn with_eof(self) -> Self {
+            Self { eof: true, ..self }
+        }
+    }
+
+    impl Read for ScriptedStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.inbound.read(buf)? {
+                0 if self.eof => Ok(0),
+                0 => Err(io::Error::from(WouldBlock)),
+                read => Ok(read),
+            }
+        }
+    }
+
+    impl Write for ScriptedStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl AsRawFd for ScriptedStream {
+        fn as_raw_fd(&self) -> RawFd {
+            idle_fd()
+        }
+    }
+
+    /// Delivers one segment per read, together with its RX timestamp.
+    struct SegmentedStream {
+        segments: std::collections::VecDeque<(Vec<u8>, u64)>,
+        last: Option<RxTimestamps>,
+    }
+
+    impl Read for SegmentedStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let (segment, hw_raw_ns) = self.segments.pop_front().ok_or(io::Error::from(WouldBlock))?;
+            buf[..segment.len()].copy_from_slice(&segment);
+            self.last = Some(RxTimestamps { hw_raw_ns });
+            Ok(segment.len())
+        }
+    }
+
+    impl Write for SegmentedStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl RxTimestamped for SegmentedStream {
+        fn last_rx_timestamps(&self) -> Option<RxTimestamps> {
+            self.last
+        }
+
+        fn take_last_rx_timestamps(&mut self) -> Option<RxTimestamps> {
+            self.last.take()
+        }
+    }
+
+    #[test]
+    fn should_pair_frames_with_rx_timestamps_of_their_read() {
+        let stream = SegmentedStream {
+            segments: [
+                (b"\x81\x01a\x81\x02b".to_vec(), 100),
+                (b"b\x81\x01c".to_vec(), 200),
+                (b"\x81\x01d".to_vec(), 300),
+            ]
+            .into(),
+            last: None,
+        };
+        let mut ws = Websocket::new_with_handshake_complete(stream);
+        let next = |batch: &mut BatchTs<SegmentedStream>| match batch.receive_next() {
+            Some(Ok((WebsocketFrame::Text(_, body), rx))) => Some((body.to_vec(), rx.hw_raw_ns)),
+            Some(_) => panic!("unexpected frame"),
+            None => None,
+        };
+
+        let mut batch = ws.read_batch_ts().unwrap();
+        assert_eq!(Some(100), batch.rx_timestamps().map(|rx| rx.hw_raw_ns));
+        assert_eq!(Some((b"a".to_vec(), 100)), next(&mut batch));
+        assert_eq!(None, next(&mut batch));
+
+        // the frame is attributed to the read that completed it, the rest of the batch is abandoned
+        let mut batch = ws.read_batch_ts().unwrap();
+        assert_eq!(Some((b"bb".to_vec(), 200)), next(&mut batch));
+
+        // no network read, the leftover frame keeps the timestamps of its read
+        let mut batch = ws.read_batch_ts().unwrap();
+        assert!(batch.rx_timestamps().is_none());
+        assert_eq!(Some((b"c".to_vec(), 200)), next(&mut batch));
+        assert_eq!(None, next(&mut batch));
+
+        let frames = ws
+            .read_batch_ts()
+            .unwrap()
+            .iter()
+            .map(|frame| frame.unwrap().1.hw_raw_ns);
+        assert_eq!(vec![300], frames.collect::<Vec<_>>());
+    }
+
+    fn pong(payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![];
+        encoder::send(&mut frame, true, protocol::op::PONG, Some(payload)).unwrap();
+        frame
+    }
+
+    fn texts(ws: &mut Websocket<ScriptedStream>) -> Vec<Vec<u8>> {
+        let mut texts = vec![];
+        for frame in ws.read_batch().unwrap() {
+            if let WebsocketFrame::Text(_, body) = frame.unwrap() {
+                texts.push(body.to_vec());
+            }
+        }
+        texts
+    }
+
+    #[test]
+    fn should_answer_ping_without_interrupting_batch() {
+        use protocol::op::{PING, TEXT_FRAME};
+        let max_payload = [0xAB; 125];
+        let stream = ScriptedStream::new(&[
+            (TEXT_FRAME, b"a"),
+            (PING, b"first"),
+            (TEXT_FRAME, b"b"),
+            (PING, &max_payload),
+            (TEXT_FRAME, b"c"),
+        ]);
+        let mut ws = Websocket::new_with_handshake_complete(stream);
+
+        assert_eq!(vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()], texts(&mut ws));
+        // only the most recent ping is answered, once the batch is exhausted
+        assert_eq!(pong(&max_payload), ws.stream.written);
+    }
+
+    #[test]
+    fn should_hand_out_pings_when_auto_pong_disabled() {
+        use protocol::op::{PING, TEXT_FRAME};
+        let stream = ScriptedStream::new(&[(PING, b"first"), (TEXT_FRAME, b"a"), (PING, b"second")]);
+        let mut ws = Websocket::new_with_handshake_complete(stream).with_auto_pong(false);
+        assert!(!ws.auto_pong());
+
+        let mut batch = ws.read_batch().unwrap();
+        assert!(matches!(batch.receive_next(), Some(Ok(WebsocketFrame::Ping(b"first")))));
+        assert!(matches!(batch.receive_next(), Some(Ok(WebsocketFrame::Text(true, b"a")))));
+        assert!(matches!(batch.receive_next(), Some(Ok(WebsocketFrame::Ping(b"second")))));
+        assert!(batch.receive_next().is_none());
+        assert!(ws.stream.written.is_empty());
+
+        ws.send_pong(Some(b"custom")).unwrap();
+        assert_eq!(pong(b"custom"), ws.stream.written);
+    }
+
+    #[test]
+    fn should_flush_pong_before_next_frame_when_batch_abandoned() {
+        use protocol::op::{PING, TEXT_FRAME};
+        let stream = ScriptedStream::new(&[(PING, b"ping"), (TEXT_FRAME, b"a"), (TEXT_FRAME, b"b")]);
+        let mut ws = Websocket::new_with_handshake_complete(stream);
+
+        let mut batch = ws.read_batch().unwrap();
+        assert!(matches!(batch.receive_next(), Some(Ok(WebsocketFrame::Text(true, b"a")))));
+        assert!(ws.stream.written.is_empty());
+
+        ws.send_text(true, Some(b"order")).unwrap();
+        let mut expected = pong(b"ping");
+        encoder::send(&mut expected, true, protocol::op::TEXT_FRAME, Some(b"order")).unwrap();
+        assert_eq!(expected, ws.stream.written);
+        assert_eq!(vec![b"b".to_vec()], texts(&mut ws));
+        assert_eq!(expected, ws.stream.written);
+    }
+
+    #[test]
+    fn should_deliver_buffered_frames_before_reporting_eof() {
+        use protocol::op::TEXT_FRAME;
+        let stream = ScriptedStream::new(&[(TEXT_FRAME, b"a"), (TEXT_FRAME, b"b")]).with_eof();
+        let mut ws = Websocket::new_with_handshake_complete(stream);
+
+        assert_eq!(vec![b"a".to_vec(), b"b".to_vec()], texts(&mut ws));
+        assert!(!ws.eof());
+        // the next read hits the EOF
+        assert!(matches!(ws.receive_next(), Some(Err(Closed))));
+        assert!(ws.eof());
+        assert!(ws.closed());
+        assert!(matches!(ws.send_text(true, Some(b"order")), Err(Closed)));
+        assert!(matches!(ws.read_batch(), Err(Closed)));
+        assert!(ws.stream.written.is_empty());
+    }
+
+    #[test]
+    fn should_keep_sending_after_eof_with_flush_policy() {
+        use protocol::op::TEXT_FRAME;
+        let stream = ScriptedStream::new(&[(TEXT_FRAME, b"a")]).with_eof();
+        let mut ws = Websocket::new_with_handshake_complete(stream).with_half_close(HalfClose::Flush);
+
+        assert_eq!(vec![b"a".to_vec()], texts(&mut ws));
+        assert!(matches!(ws.receive_next(), Some(Err(Closed))));
+        assert!(matches!(ws.receive_next(), Some(Err(Closed))));
+        assert!(ws.eof());
+        assert!(!ws.closed());
+
+        ws.send_text(true, Some(b"cancel")).unwrap();
+        let mut expected = vec![];
+        encoder::send(&mut expected, true, protocol::op::TEXT_FRAME, Some(b"cancel")).unwrap();
+        assert_eq!(expected, ws.stream.written);
+    }
+
+    #[derive(Clone, Default)]
+    struct ManualClock(Rc<Cell<u64>>);
+
+    impl TimeSource for ManualClock {
+        fn current_time_nanos(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    #[test]
+    fn should_ping_and_fail_after_missed_pongs() {
+        use protocol::op::PONG;
+        const SECOND: u64 = 1_000_000_000;
+        let clock = ManualClock::default();
+        let mut ws = Websocket::new_with_handshake_complete(ScriptedStream::new(&[])).with_keepalive_time_source(
+            Duration::from_secs(1),
+            2,
+            clock.clone(),
+        );
+        let ping = |sent_ns: u64| {
+            let mut ping = vec![];
+            encoder::send(&mut ping, true, protocol::op::PING, Some(&sent_ns.to_be_bytes())).unwrap();
+            ping
+        };
+
+        // the first interval starts with the first read
+        assert!(texts(&mut ws).is_empty());
+        clock.0.set(SECOND);
+        assert!(texts(&mut ws).is_empty());
+        assert_eq!(ping(SECOND), ws.stream.written);
+
+        // pong resets the missed count
+        clock.0.set(2 * SECOND);
+        ws.stream
+            .inbound
+            .get_mut()
+            .extend_from_slice(&[protocol::FIN_MASK | PONG, 0]);
+        assert!(texts(&mut ws).is_empty());
+        assert_eq!(0, ws.missed_pongs());
+        assert_eq!([ping(SECOND), ping(2 * SECOND)].concat(), ws.stream.written);
+
+        clock.0.set(3 * SECOND);
+        assert!(texts(&mut ws).is_empty());
+        assert_eq!(0, ws.missed_pongs());
+        clock.0.set(4 * SECOND);
+        assert!(texts(&mut ws).is_empty());
+        assert_eq!(1, ws.missed_pongs());
+        let pings = (1..=4).map(|second| ping(second * SECOND)).collect::<Vec<_>>();
+        assert_eq!(pings.concat(), ws.stream.written);
+        clock.0.set(5 * SECOND);
+        assert!(matches!(ws.read_batch(), Err(Error::KeepaliveTimeout(2))));
+        assert!(ws.closed());
+        // the unsolicited pong is not a round trip sample
+        assert_eq!(None, ws.rtt());
+    }
+
+    #[test]
+    fn should_measure_rtt_with_pings() {
+        use protocol::op::PONG;
+        const SECOND: u64 = 1_000_000_000;
+        let clock = ManualClock::default();
+        let mut ws = Websocket::new_with_handshake_complete(ScriptedStream::new(&[])).with_keepalive_time_source(
+            Duration::from_secs(1),
+            2,
+            clock.clone(),
+        );
+        let pong = |ws: &mut Websocket<ScriptedStream>, sent_ns: u64, now_ns: u64| {
+            clock.0.set(now_ns);
+            let inbound = ws.stream.inbound.get_mut();
+            inbound.extend_from_slice(&[protocol::FIN_MASK | PONG, 8]);
+            inbound.extend_from_slice(&sent_ns.to_be_bytes());
+            assert!(texts(ws).is_empty());
+        };
+
+        assert!(texts(&mut ws).is_empty());
+        clock.0.set(SECOND);
+        assert!(texts(&mut ws).is_empty());
+        pong(&mut ws, SECOND, SECOND + 300_000);
+        clock.0.set(2 * SECOND);
+        assert!(texts(&mut ws).is_empty());
+        // pong to an older ping is not measured
+        pong(&mut ws, SECOND, 2 * SECOND + 100_000);
+        clock.0.set(3 * SECOND);
+        assert!(texts(&mut ws).is_empty());
+        pong(&mut ws, 3 * SECOND, 3 * SECOND + 200_000);
+
+        let rtt = ws.rtt().unwrap();
+        assert_eq!((2, 200_000, 200_000, 300_000), (rtt.samples, rtt.last, rtt.min, rtt.max));
+        assert_eq!(300_000 - 300_000 / 8 + 200_000 / 8, rtt.smoothed);
+        assert_eq!(100_000 / 16, rtt.jitter);
+
+        // pings with caller payload feed the same statistics
+        clock.0.set(3 * SECOND + 500_000);
+        ws.ping_with_payload(b"probe").unwrap();
+        clock.0.set(3 * SECOND + 600_000);
+        let inbound = ws.stream.inbound.get_mut();
+        inbound.extend_from_slice(&[protocol::FIN_MASK | PONG, 5]);
+        inbound.extend_from_slice(b"probe");
+        assert!(texts(&mut ws).is_empty());
+        assert_eq!(3, ws.rtt().unwrap().samples);
+        assert_eq!(100_000, ws.last_rtt().unwrap().rtt_ns);
+    }
+
+    #[test]
+    fn should_measure_rtt_of_pings_with_payload() {
+        use protocol::op::{PING, PONG};
+        let clock = ManualClock::default();
+        let mut ws =
+            Websocket::new_with_handshake_complete(ScriptedStream::new(&[])).with_rtt_time_source(clock.clone());
+        let pong = |ws: &mut Websocket<ScriptedStream>, payload: &[u8], now_ns: u64| {
+            clock.0.set(now_ns);
+            let inbound = ws.stream.inbound.get_mut();
+            inbound.extend_from_slice(&[protocol::FIN_MASK | PONG, payload.len() as u8]);
+            inbound.extend_from_slice(payload);
+            assert!(texts(ws).is_empty());
+        };
+
+        for (seq, sent_ns) in [(b"1", 1000), (b"2", 2000), (b"3", 3000)] {
+            clock.0.set(sent_ns);
+            ws.ping_with_payload(seq).unwrap();
+        }
+        assert!(ws.ping_with_payload(&[0; 126]).is_err());
+        assert_eq!(3, ws.pings_in_flight());
+        let mut expected = vec![];
+        for seq in [b"1", b"2", b"3"] {
+            encoder::send(&mut expected, true, PING, Some(seq)).unwrap();
+        }
+        assert_eq!(expected, ws.stream.written);
+
+        // the earlier ping is not answered anymore
+        pong(&mut ws, b"2", 2500);
+        assert_eq!(
+            Some(PingRtt {
+                rtt_ns: 500,
+                hardware: false
+            }),
+            ws.last_rtt()
+        );
+        assert_eq!(1, ws.pings_in_flight());
+        // unsolicited pong is not a sample
+        pong(&mut ws, b"x", 2600);
+        pong(&mut ws, b"3", 3100);
+
+        let rtt = ws.rtt().unwrap();
+        assert_eq!((2, 100, 100, 500), (rtt.samples, rtt.last, rtt.min, rtt.max));
+        assert_eq!(0, ws.pings_in_flight());
+    }
+
+    #[test]
+    fn should_measure_rtt_with_hardware_rx_timestamps() {
+        let clock = ManualClock::default();
+        let stream = SegmentedStream {
+            segments: [(b"\x8a\x01a".to_vec(), 1500), (b"\x8a\x01b".to_vec(), 0)].into(),
+            last: None,
+        };
+        let mut ws = Websocket::new_with_handshake_complete(stream)
+            .with_rtt_time_source(clock.clone())
+            .with_hardware_rtt();
+
+        clock.0.set(1000);
+        ws.ping_with_payload(b"a").unwrap();
+        ws.ping_with_payload(b"b").unwrap();
+        clock.0.set(9000);
+        for frame in ws.read_batch_ts().unwrap() {
+            assert!(matches!(frame, Ok((WebsocketFrame::Pong(_), _))));
+        }
+        assert_eq!(
+            Some(PingRtt {
+                rtt_ns: 500,
+                hardware: true
+            }),
+            ws.last_rtt()
+        );
+
+        // no hardware timestamp captured, the time source is used instead
+        for frame in ws.read_batch_ts().unwrap() {
+            assert!(frame.is_ok());
+        }
+        assert_eq!(
+            Some(PingRtt {
+                rtt_ns: 8000,
+                hardware: false
+            }),
+            ws.last_rtt()
+        );
+    }
+
+    #[test]
+    fn should_fail_stalled_handshake_after_timeout() {
+        const SECOND: u64 = 1_000_000_000;
+        let clock = ManualClock::default();
+        let handshake = || State::handshake("localhost", "/ws", default_buffer_pool_ref());
+        let mut ws = Websocket::new_with_handshake_complete(ScriptedStream::new(&[]))
+            .with_handshake_timeout_time_source(Duration::from_secs(1), clock.clone());
+        ws.state = handshake();
+
+        // the deadline starts with the first read
+        clock.0.set(SECOND);
+        assert!(texts(&mut ws).is_empty());
+        clock.0.set(2 * SECOND);
+        assert!(texts(&mut ws).is_empty());
+        assert!(ws.stream.written.starts_with(b"GET /ws HTTP/1.1\r\n"));
+        clock.0.set(2 * SECOND + 1);
+        assert!(matches!(ws.read_batch(), Err(Error::HandshakeTimeout(timeout)) if timeout == Duration::from_secs(1)));
+        assert!(ws.closed());
+
+        // the deadline no longer applies once the handshake has completed
+        let mut ws = Websocket::new_with_handshake_complete(ScriptedStream::new(&[]))
+            .with_handshake_timeout_time_source(Duration::from_secs(1), clock.clone());
+        ws.state = handshake();
+        ws.stream
+            .inbound
+            .get_mut()
+            .extend_from_slice(b"HTTP/1.1 101 Switching Protocols\r\n\r\n");
+        while !ws.handshake_complete() {
+            assert!(texts(&mut ws).is_empty());
+        }
+        clock.0.set(10 * SECOND);
+        assert!(texts(&mut ws).is_empty());
+        assert!(!ws.closed());
+    }
+
+    #[test]
+    fn should_perform_closing_handshake() {
+        use protocol::op::{CONNECTION_CLOSE, TEXT_FRAME};
+        let peer_close = [&1001u16.to_be_bytes()[..], b"bye"].concat();
+        let stream = ScriptedStream::new(&[(TEXT_FRAME, b"a"), (CONNECTION_CLOSE, &peer_close)]);
+        let mut ws = Websocket::new_with_handshake_complete(stream);
+
+        assert!(matches!(ws.close(1005, ""), Err(Error::Violation(Violation::InvalidCloseCode(1005)))));
+        assert!(ws.close(1000, &"x".repeat(124)).is_err());
+        assert_eq!(Some((1001, "bye".to_owned())), ws.close(1000, "done").unwrap());
+        assert!(ws.closed());
+        // the peer close frame is not echoed back
+        let mut expected = vec![];
+        let payload = [&1000u16.to_be_bytes()[..], b"done"].concat();
+        encoder::send(&mut expected, true, CONNECTION_CLOSE, Some(&payload)).unwrap();
+        assert_eq!(expected, ws.stream.written);
+    }
+
+    #[test]
+    fn should_fail_read_after_deadline() {
+        use protocol::op::TEXT_FRAME;
+        use std::os::unix::net::UnixStream;
+
+        for nonblocking in [true, false] {
+            let (stream, mut peer) = UnixStream::pair().unwrap();
+            stream.set_nonblocking(nonblocking).unwrap();
+            let mut ws = Websocket::new_with_handshake_complete(stream);
+            let start = Instant::now();
+            assert!(matches!(ws.read_batch_timeout(Duration::from_millis(20)), Err(Error::ReadTimeout)));
+            assert!(start.elapsed() >= Duration::from_millis(20));
+            assert!(!ws.closed());
+
+            // the frame left in the buffer is returned without waiting
+            let text = protocol::FIN_MASK | TEXT_FRAME;
+            peer.write_all(&[text, 1, b'a', text, 1, b'b']).unwrap();
+            let deadline = Instant::now() + Duration::from_secs(60);
+            let mut batch = ws.read_batch_deadline(deadline).unwrap();
+            assert!(matches!(batch.receive_next(), Some(Ok(WebsocketFrame::Text(true, b"a")))));
+            let mut batch = ws.read_batch_deadline(deadline).unwrap();
+            assert!(matches!(batch.receive_next(), Some(Ok(WebsocketFrame::Text(true, b"b")))));
+            assert!(batch.receive_next().is_none());
+            assert!(Instant::now() < deadline);
+        }
+    }
+
+    #[test]
+    fn should_bound_handshake_wait_by_timeout() {
+        use std::os::unix::net::UnixStream;
+
+        for nonblocking in [true, false] {
+            let (stream, peer) = UnixStream::pair().unwrap();
+            stream.set_nonblocking(nonblocking).unwrap();
+            let mut ws =
+                Websocket::new_with_handshake_complete(stream).with_handshake_timeout(Duration::from_millis(20));
+            ws.state = State::handshake("localhost", "/ws", default_buffer_pool_ref());
+            let start = Instant::now();
+            assert!(matches!(ws.wait_for_handshake(), Err(Error::HandshakeTimeout(_))));
+            assert!(start.elapsed() >= Duration::from_millis(20));
+            assert!(ws.closed());
+            drop(peer);
+
+            // the response that arrives in time completes the handshake
+            let (stream, mut peer) = UnixStream::pair().unwrap();
+            stream.set_nonblocking(nonblocking).unwrap();
+            let mut ws = Websocket::new_with_handshake_complete(stream).with_handshake_timeout(Duration::from_secs(60));
+            ws.state = State::handshake("localhost", "/ws", default_buffer_pool_ref());
+            peer.write_all(b"HTTP/1.1 101 Switching Protocols\r\n\r\n").unwrap();
+            ws.wait_for_handshake().unwrap();
+            assert!(!ws.closed());
+        }
+    }
+
+    #[test]
+    fn should_give_up_closing_handshake_after_timeout() {
+        let mut ws =
+            Websocket::new_with_handshake_complete(ScriptedStream::new(&[])).with_close_timeout(Duration::ZERO);
+        assert_eq!(None, ws.close(1001, "going away").unwrap());
+        assert!(ws.closed());
+        assert!(matches!(ws.close(1000, ""), Err(Closed)));
+    }
+
+    #[test]
+    fn should_wait_for_peer_close_frame() {
+        use protocol::op::CONNECTION_CLOSE;
+        use std::os::unix::net::UnixStream;
+
+        for nonblocking in [true, false] {
+            let (stream, peer) = UnixStream::pair().unwrap();
+            stream.set_nonblocking(nonblocking).unwrap();
+            let mut ws = Websocket::new_with_handshake_complete(stream).with_close_timeout(Duration::from_millis(20));
+            let start = Instant::now();
+            assert_eq!(None, ws.close(1000, "").unwrap());
+            assert!(start.elapsed() >= Duration::from_millis(20));
+            assert!(ws.closed());
+            drop(peer);
+
+            // the close frame that arrives in time is returned
+            let (stream, mut peer) = UnixStream::pair().unwrap();
+            stream.set_nonblocking(nonblocking).unwrap();
+            let mut ws = Websocket::new_with_handshake_complete(stream).with_close_timeout(Duration::from_secs(60));
+            let answer = std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(10));
+                let close = protocol::FIN_MASK | CONNECTION_CLOSE;
+                peer.write_all(&[close, 5, 0x03, 0xe8, b'b', b'y', b'e']).unwrap();
+                peer
+            });
+            assert_eq!(Some((1000, "bye".to_owned())), ws.close(1000, "").unwrap());
+            answer.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn should_apply_middleware_chain_to_frames() {
+        use crate::ws::middleware::{Action, Chain};
+        use protocol::op::{PING, TEXT_FRAME};
+
+        let mut last = vec![];
+        let chain = Chain::new()
+            // dedup
+            .with(move |frame: &WebsocketFrame| match frame {
+                WebsocketFrame::Text(_, body) if *body == last.as_slice() => Action::Drop,
+                WebsocketFrame::Text(_, body) => {
+                    last = body.to_vec();
+                    Action::Deliver
+                }
+                _ => Action::Deliver,
+            })
+            .with(|frame: &WebsocketFrame| match frame {
+                WebsocketFrame::Text(_, b"b") => Action::Replace(b"B".to_vec()),
+                _ => Action::Deliver,
+            });
+        let frames: [(u8, &[u8]); 5] = [
+            (TEXT_FRAME, b"a"),
+            (TEXT_FRAME, b"a"),
+            (PING, b""),
+            (TEXT_FRAME, b"b"),
+            (TEXT_FRAME, b"b"),
+        ];
+        let mut ws = Websocket::new_with_handshake_complete(ScriptedStream::new(&frames)).with_middleware(chain);
+        assert_eq!(vec![b"a".to_vec(), b"B".to_vec()], texts(&mut ws));
+        // the ping is still answered
+        assert_eq!(pong(b""), ws.stream.written);
+    }
+
+    #[test]
+    fn should_reassemble_fragmented_messages() {
+        use protocol::op::{CONTINUATION_FRAME, PING, TEXT_FRAME};
+        let frame = |fin: bool, op_code: u8, payload: &[u8]| {
+            let fin = if fin { protocol::FIN_MASK } else { 0 };
+            [&[fin | op_code, payload.len() as u8][..], payload].concat()
+        };
+        // the message is completed by the second network read
+        let first = [
+            frame(false, TEXT_FRAME, br#"{"a":"#),
+            frame(true, PING, b""),
+            frame(false, CONTINUATION_FRAME, b"1"),
+        ]
+        .concat();
+        let second = [frame(true, CONTINUATION_FRAME, b"}"), frame(true, TEXT_FRAME, b"x")].concat();
+        let mut ws = Websocket::new_with_handshake_complete(ScriptedStream::new(&[])).with_reassembly(8);
+        ws.stream.inbound = io::Cursor::new(first);
+        assert!(texts(&mut ws).is_empty());
+        assert_eq!(pong(b""), ws.stream.written);
+        ws.stream.inbound = io::Cursor::new(second);
+        assert_eq!(vec![br#"{"a":1}"#.to_vec(), b"x".to_vec()], texts(&mut ws));
+
+        let oversized = [
+            frame(false, TEXT_FRAME, b"12345"),
+            frame(true, CONTINUATION_FRAME, b"6789"),
+        ]
+        .concat();
+        ws.stream.inbound = io::Cursor::new(oversized);
+        assert!(matches!(ws.read_batch().unwrap().receive_next(), Some(Err(Error::Protocol(_)))));
+        assert!(ws.closed());
+    }
+
+    #[test]
+    fn should_detach_frame_from_read_batch() {
+        let mut ws = Websocket::new_with_handshake_complete(ScriptedStream::new(&[(protocol::op::TEXT_FRAME, b"abc")]));
+        let frame = ws.read_batch().unwrap().receive_next().unwrap().unwrap().to_owned();
+        // the frame outlives the batch and can be sent to another thread
+        let frame = std::thread::spawn(move || frame).join().unwrap();
+        assert_eq!(WebsocketFrameOwned::Text(true, b"abc".to_vec()), frame);
+        assert_eq!(b"abc", frame.payload());
+        assert_eq!(b"abc".to_vec(), frame.into_payload());
+    }
+}
@@ -0,0 +1,223 @@
+use crate::ws::decoder::Role;
+use crate::ws::encoder;
+use crate::ws::mask::Masking;
+use crate::ws::protocol::op::is_control;
+use crate::ws::send_queue::FrameSink;
+use std::collections::VecDeque;
+use std::io;
+
+/// Priority lane of an outbound frame.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Lane {
+    /// Control frames and urgent messages (e.g. cancels), written ahead of the queued bulk frames.
+    Urgent,
+    /// Data frames, written in order and at most `max_bulk_per_flush` per flush.
+    Bulk,
+}
+
+#[derive(Debug)]
+struct QueuedFrame {
+    fin: bool,
+    op_code: u8,
+    payload: Vec<u8>,
+}
+
+/// Frames held back by the websocket, either because the handshake is pending or because they have
+/// been queued behind the frames of their lane.
+#[derive(Debug)]
+pub(crate) struct Outbound {
+    urgent: VecDeque<QueuedFrame>,
+    bulk: VecDeque<QueuedFrame>,
+    max_bulk_per_flush: usize,
+    // the last data frame written did not complete its message, data frames of other messages must
+    // not be interleaved with its fragments (RFC 6455 section 5.4)
+    fragmented: bool,
+}
+
+impl Outbound {
+    pub(crate) fn new() -> Self {
+        Self {
+            urgent: VecDeque::new(),
+            bulk: VecDeque::with_capacity(256),
+            max_bulk_per_flush: usize::MAX,
+            fragmented: false,
+        }
+    }
+
+    pub(crate) fn set_max_bulk_per_flush(&mut self, max_bulk_per_flush: usize) {
+        self.max_bulk_per_flush = max_bulk_per_flush.max(1);
+    }
+
+    /// Number of queued frames.
+    pub(crate) fn len(&self) -> usize {
+        self.urgent.len() + self.bulk.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.urgent.is_empty() && self.bulk.is_empty()
+    }
+
+    /// Checks if the frame can be written straight away (once the handshake has completed) rather
+    /// than being queued behind the frames of its `lane`.
+    #[inline]
+    pub(crate) fn bypass(&self, lane: Lane, op_code: u8) -> bool {
+        match lane {
+            Lane::Urgent if is_control(op_code) => true,
+            Lane::Urgent => !self.fragmented && self.urgent.is_empty(),
+            Lane::Bulk => self.bulk.is_empty(),
+        }
+    }
+
+    #[cold]
+    pub(crate) fn push(&mut self, lane: Lane, fin: bool, op_code: u8, payload: &[u8]) {
+        let frame = QueuedFrame {
+            fin,
+            op_code,
+            payload: payload.to_vec(),
+        };
+        match lane {
+            Lane::Urgent => self.urgent.push_back(frame),
+            Lane::Bulk => self.bulk.push_back(frame),
+        }
+    }
+
+    /// Track the frame written straight away, releases the urgent frames held back by a fragmented
+    /// message once its final fragment has been written.
+    #[inline]
+    pub(crate) fn written<S: FrameSink>(
+        &mut self,
+        stream: &mut S,
+        masking: &mut Masking,
+        role: Role,
+        fin: bool,
+        op_code: u8,
+    ) -> io::Result<()> {
+        if !is_control(op_code) {
+            self.fragmented = !fin;
+        }
+        if self.urgent.is_empty() {
+            return Ok(());
+        }
+        self.flush_urgent(stream, masking, role)
+    }
+
+    /// Write the urgent frames followed by at most `max_bulk_per_flush` bulk frames (all of them if
+    /// not `bounded`).
+    #[cold]
+    pub(crate) fn flush<S: FrameSink>(
+        &mut self,
+        stream: &mut S,
+        masking: &mut Masking,
+        role: Role,
+        bounded: bool,
+    ) -> io::Result<()> {
+        self.flush_urgent(stream, masking, role)?;
+        let mut budget = if bounded { self.max_bulk_per_flush } else { usize::MAX };
+        while budget > 0 {
+            let Some(frame) = self.bulk.pop_front() else {
+                break;
+            };
+            self.write(stream, masking, role, Lane::Bulk, frame)?;
+            budget -= 1;
+            self.flush_urgent(stream, masking, role)?;
+        }
+        Ok(())
+    }
+
+    /// Write the urgent frames that can be sent, the data frames wait for the fragmented message (if
+    /// any) to complete.
+    pub(crate) fn flush_urgent<S: FrameSink>(
+        &mut self,
+        stream: &mut S,
+        masking: &mut Masking,
+        role: Role,
+    ) -> io::Result<()> {
+        while let Some(frame) = self.urgent.front() {
+            if self.fragmented && !is_control(frame.op_code) {
+                break;
+            }
+            let frame = self.urgent.pop_front().unwrap();
+            self.write(stream, masking, role, Lane::Urgent, frame)?;
+        }
+        Ok(())
+    }
+
+    /// Drop the queued frames, e.g. once the close frame has been sent.
+    pub(crate) fn clear(&mut self) {
+        self.urgent.clear();
+        self.bulk.clear();
+    }
+
+    #[inline]
+    fn write<S: FrameSink>(
+        &mut self,
+        stream: &mut S,
+        masking: &mut Masking,
+        role: Role,
+        lane: Lane,
+        frame: QueuedFrame,
+    ) -> io::Result<()> {
+        let key = masking.next_key(role);
+        // the control frames of the bulk lane (e.g. the close frame) stay behind the data frames
+        let control = lane == Lane::Urgent && is_control(frame.op_code);
+        stream.encode_frame(control, |stream| {
+            encoder::send_masked(stream, key, frame.fin, frame.op_code, Some(&frame.payload))
+        })?;
+        if !is_control(frame.op_code) {
+            self.fragmented = !frame.fin;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::protocol::op::{BINARY_FRAME, CONNECTION_CLOSE, CONTINUATION_FRAME, PING, TEXT_FRAME};
+
+    #[test]
+    fn should_write_urgent_frames_ahead_of_bulk_ones() {
+        let mut outbound = Outbound::new();
+        outbound.set_max_bulk_per_flush(2);
+        outbound.push(Lane::Bulk, false, BINARY_FRAME, b"snap");
+        outbound.push(Lane::Bulk, true, CONTINUATION_FRAME, b"shot");
+        outbound.push(Lane::Bulk, true, TEXT_FRAME, b"order");
+        outbound.push(Lane::Urgent, true, TEXT_FRAME, b"cancel");
+        outbound.push(Lane::Urgent, true, PING, b"");
+        assert_eq!(5, outbound.len());
+        assert!(!outbound.bypass(Lane::Bulk, TEXT_FRAME));
+
+        let (mut written, mut masking) = (vec![], Masking::default());
+        outbound.flush(&mut written, &mut masking, Role::Client, true).unwrap();
+        assert_eq!(1, outbound.len());
+        // a fragmented message holds back the urgent data frames but not the control ones
+        outbound
+            .written(&mut written, &mut masking, Role::Client, false, TEXT_FRAME)
+            .unwrap();
+        assert!(outbound.bypass(Lane::Urgent, PING));
+        assert!(!outbound.bypass(Lane::Urgent, TEXT_FRAME));
+        outbound.push(Lane::Urgent, true, TEXT_FRAME, b"cancel");
+        outbound.flush_urgent(&mut written, &mut masking, Role::Client).unwrap();
+        assert_eq!(2, outbound.len());
+        outbound
+            .written(&mut written, &mut masking, Role::Client, true, CONTINUATION_FRAME)
+            .unwrap();
+        outbound.push(Lane::Bulk, true, CONNECTION_CLOSE, &1000u16.to_be_bytes());
+        outbound.flush(&mut written, &mut masking, Role::Client, false).unwrap();
+        assert!(outbound.is_empty());
+
+        let mut expected = vec![];
+        for (fin, op_code, payload) in [
+            (true, TEXT_FRAME, &b"cancel"[..]),
+            (true, PING, b""),
+            (false, BINARY_FRAME, b"snap"),
+            (true, CONTINUATION_FRAME, b"shot"),
+            (true, TEXT_FRAME, b"cancel"),
+            (true, TEXT_FRAME, b"order"),
+            (true, CONNECTION_CLOSE, &1000u16.to_be_bytes()),
+        ] {
+            encoder::send(&mut expected, fin, op_code, Some(payload)).unwrap();
+        }
+        assert_eq!(expected, written);
+    }
+}
@@ -13,4 +13,14 @@ pub mod op {
     pub const CONNECTION_CLOSE: u8 = 0x8;
     pub const PING: u8 = 0x9;
     pub const PONG: u8 = 0xA;
+
+    #[inline]
+    pub const fn is_known(op_code: u8) -> bool {
+        matches!(op_code, CONTINUATION_FRAME | TEXT_FRAME | BINARY_FRAME | CONNECTION_CLOSE | PING | PONG)
+    }
+
+    #[inline]
+    pub const fn is_control(op_code: u8) -> bool {
+        op_code & 0x8 != 0
+    }
 }
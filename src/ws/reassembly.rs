@@ -0,0 +1,95 @@
+use crate::ws::{Error, WebsocketFrame};
+use std::mem;
+use std::ptr;
+
+/// Joins the fragments of a message into a single text or binary frame, unfragmented messages and
+/// control frames are passed through without copying.
+#[derive(Debug)]
+pub(crate) struct Reassembler {
+    max_size: usize,
+    // the message being reassembled is a text one
+    text: Option<bool>,
+    buffer: Vec<u8>,
+    // reassembled messages handed out since the last network read
+    batch: Vec<Vec<u8>>,
+    spare: Vec<Vec<u8>>,
+}
+
+impl Reassembler {
+    pub(crate) const fn new(max_size: usize) -> Self {
+        Self {
+            max_size,
+            text: None,
+            buffer: Vec::new(),
+            batch: Vec::new(),
+            spare: Vec::new(),
+        }
+    }
+
+    /// Complete message (or control frame), `None` if the `frame` is a fragment of a message that has
+    /// not been completed yet.
+    #[inline]
+    pub(crate) fn apply(&mut self, frame: WebsocketFrame) -> Result<Option<WebsocketFrame>, Error> {
+        match (frame, self.text) {
+            (frame @ (WebsocketFrame::Text(true, _) | WebsocketFrame::Binary(true, _)), None) => Ok(Some(frame)),
+            (WebsocketFrame::Text(false, payload), None) => self.start(true, payload),
+            (WebsocketFrame::Binary(false, payload), None) => self.start(false, payload),
+            (WebsocketFrame::Text(..) | WebsocketFrame::Binary(..), Some(_)) => {
+                Err(Error::Protocol("new message received before the fragmented one has been completed"))
+            }
+            (WebsocketFrame::Continuation(_, _), None) => {
+                Err(Error::Protocol("continuation frame received without a fragmented message"))
+            }
+            (WebsocketFrame::Continuation(fin, payload), Some(text)) => {
+                self.append(payload)?;
+                if !fin {
+                    return Ok(None);
+                }
+                Ok(Some(self.complete(text)))
+            }
+            (frame, _) => Ok(Some(frame)),
+        }
+    }
+
+    /// Checks if a fragmented message is being reassembled.
+    pub(crate) const fn in_progress(&self) -> bool {
+        self.text.is_some()
+    }
+
+    /// Release the messages handed out, called by the websocket before the next network read.
+    #[inline]
+    pub(crate) fn recycle(&mut self) {
+        for mut buffer in self.batch.drain(..) {
+            buffer.clear();
+            self.spare.push(buffer);
+        }
+    }
+
+    fn start(&mut self, text: bool, payload: &[u8]) -> Result<Option<WebsocketFrame>, Error> {
+        self.text = Some(text);
+        self.append(payload)?;
+        Ok(None)
+    }
+
+    #[inline]
+    fn append(&mut self, payload: &[u8]) -> Result<(), Error> {
+        if self.buffer.len() + payload.len() > self.max_size {
+            return Err(Error::Protocol("reassembled message exceeds the maximum size"));
+        }
+        self.buffer.extend_from_slice(payload);
+        Ok(())
+    }
+
+    fn complete(&mut self, text: bool) -> WebsocketFrame {
+        self.text = None;
+        let message = mem::replace(&mut self.buffer, self.spare.pop().unwrap_or_default());
+        // SAFETY: the heap buffer does not move when the batch grows and is only released by the next
+        // network read, which invalidates the frames of the current batch anyway
+        let payload = unsafe { &*ptr::slice_from_raw_parts(message.as_ptr(), message.len()) };
+        self.batch.push(message);
+        match text {
+            true => WebsocketFrame::Text(true, payload),
+            false => WebsocketFrame::Binary(true, payload),
+        }
+    }
+}
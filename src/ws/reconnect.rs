@@ -0,0 +1,477 @@
+//! Websocket that re-dials and re-runs the upgrade after a disconnect, see [`ReconnectingWebsocket`].
+
+use crate::service::time::{SystemTimeClockSource, TimeSource};
+use crate::stream::{ConnectionInfo, ConnectionInfoProvider};
+use crate::ws::{Error, Websocket, WebsocketFrame};
+use log::{info, warn};
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::io::{Read, Write};
+use std::time::Duration;
+use url::Url;
+
+const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+type Connect<S> = Box<dyn FnMut(ConnectionInfo) -> io::Result<Websocket<S>>>;
+type Resubscribe<S> = Box<dyn FnMut(&mut Websocket<S>) -> Result<(), Error>>;
+
+/// Websocket that is re-created from the stored [`ConnectionInfo`] whenever it fails (IO error,
+/// close frame, missed keepalive pongs, etc.).
+///
+/// The `connect` function dials the connection and applies the protocol layers (TLS, buffering,
+/// websocket options), the resubscribe hook is then invoked with every new websocket to replay the
+/// subscriptions, the frames it sends are held until the upgrade completes. Failed attempts are retried
+/// with exponential backoff (100ms doubling up to 30s by default), which is reset once a frame has been
+/// received on the new connection.
+///
+/// The error that caused the disconnect is still returned from [`ReconnectingWebsocket::receive_next`]
+/// so that the application can invalidate the state derived from the previous connection (e.g. order
+/// books), the next call dials again once the backoff has elapsed.
+///
+/// With [`ReconnectingWebsocket::with_redirects`] the `301`, `302` and `307` responses to the upgrade
+/// are followed by dialling the redirected host and requesting its path straight away, without
+/// reporting an error. The scheme of the redirect is ignored, TLS is up to the `connect` function.
+///
+/// ## Examples
+/// ```no_run
+/// use std::time::Duration;
+/// use boomnet::stream::ConnectionInfo;
+/// use boomnet::stream::tls::IntoTlsStream;
+/// use boomnet::ws::{IntoWebsocket, ReconnectingWebsocket, WebsocketFrame};
+///
+/// let mut ws = ReconnectingWebsocket::new(ConnectionInfo::new("stream.binance.com", 9443), |connection_info| {
+///     Ok(connection_info.into_tcp_stream()?.into_tls_stream()?.into_websocket("/ws"))
+/// })
+/// .with_resubscribe(|ws| ws.send_text(true, Some(br#"{"method":"SUBSCRIBE","params":["btcusdt@trade"],"id":1}"#)))
+/// .with_backoff(Duration::from_millis(250), Duration::from_secs(10));
+///
+/// loop {
+///     match ws.receive_next() {
+///         Some(Ok(WebsocketFrame::Text(fin, data))) => println!("({fin}) {}", String::from_utf8_lossy(data)),
+///         Some(Err(err)) => println!("disconnected: {err}"),
+///         _ => {}
+///     }
+/// }
+/// ```
+pub struct ReconnectingWebsocket<S> {
+    connection_info: ConnectionInfo,
+    connect: Connect<S>,
+    resubscribe: Option<Resubscribe<S>>,
+    websocket: Option<Websocket<S>>,
+    initial_backoff_ns: u64,
+    max_backoff_ns: u64,
+    // consecutive attempts that did not receive any frame
+    failures: u32,
+    next_attempt_ns: u64,
+    connects: u64,
+    max_redirects: u8,
+    // redirects followed since the last frame received or failure
+    redirects: u8,
+    // target of the last redirect followed and the endpoint to request from it
+    redirect: Option<(ConnectionInfo, String)>,
+    time_source: Box<dyn TimeSource>,
+}
+
+impl<S: Debug> Debug for ReconnectingWebsocket<S> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReconnectingWebsocket")
+            .field("connection_info", &self.connection_info)
+            .field("websocket", &self.websocket)
+            .field("initial_backoff_ns", &self.initial_backoff_ns)
+            .field("max_backoff_ns", &self.max_backoff_ns)
+            .field("failures", &self.failures)
+            .field("next_attempt_ns", &self.next_attempt_ns)
+            .field("connects", &self.connects)
+            .field("max_redirects", &self.max_redirects)
+            .field("redirect", &self.redirect)
+            .finish()
+    }
+}
+
+impl<S> ReconnectingWebsocket<S> {
+    /// Create websocket that uses `connect` to dial the `connection_info`. The first connection is
+    /// made on the first [`ReconnectingWebsocket::receive_next`].
+    pub fn new<F>(connection_info: ConnectionInfo, connect: F) -> ReconnectingWebsocket<S>
+    where
+        F: FnMut(ConnectionInfo) -> io::Result<Websocket<S>> + 'static,
+    {
+        Self {
+            connection_info,
+            connect: Box::new(connect),
+            resubscribe: None,
+            websocket: None,
+            initial_backoff_ns: DEFAULT_INITIAL_BACKOFF.as_nanos() as u64,
+            max_backoff_ns: DEFAULT_MAX_BACKOFF.as_nanos() as u64,
+            failures: 0,
+            next_attempt_ns: 0,
+            connects: 0,
+            max_redirects: 0,
+            redirects: 0,
+            redirect: None,
+            time_source: Box::new(SystemTimeClockSource),
+        }
+    }
+
+    /// Invoke `resubscribe` with every new websocket, an error fails the connection attempt.
+    pub fn with_resubscribe<F>(self, resubscribe: F) -> ReconnectingWebsocket<S>
+    where
+        F: FnMut(&mut Websocket<S>) -> Result<(), Error> + 'static,
+    {
+        Self {
+            resubscribe: Some(Box::new(resubscribe)),
+            ..self
+        }
+    }
+
+    /// Wait `initial` before the first reconnect attempt, doubling the wait after every attempt that
+    /// did not receive any frame up to `max`.
+    pub fn with_backoff(self, initial: Duration, max: Duration) -> ReconnectingWebsocket<S> {
+        Self {
+            initial_backoff_ns: initial.as_nanos() as u64,
+            max_backoff_ns: max.max(initial).as_nanos() as u64,
+            ..self
+        }
+    }
+
+    /// Follow up to `max_hops` consecutive redirects of the upgrade (disabled by default), a failure
+    /// to connect or upgrade after a redirect dials the original host again once the backoff has
+    /// elapsed.
+    pub fn with_redirects(self, max_hops: u8) -> ReconnectingWebsocket<S> {
+        Self {
+            max_redirects: max_hops,
+            ..self
+        }
+    }
+
+    /// Specify custom [`TimeSource`] instead of the default system time source.
+    pub fn with_time_source<TS>(self, time_source: TS) -> ReconnectingWebsocket<S>
+    where
+        TS: TimeSource + 'static,
+    {
+        Self {
+            time_source: Box::new(time_source),
+            ..self
+        }
+    }
+
+    /// Current websocket, `None` while waiting to reconnect.
+    pub fn websocket(&self) -> Option<&Websocket<S>> {
+        self.websocket.as_ref()
+    }
+
+    /// Current websocket (e.g. to send frames), `None` while waiting to reconnect.
+    pub fn websocket_mut(&mut self) -> Option<&mut Websocket<S>> {
+        self.websocket.as_mut()
+    }
+
+    /// Checks if the websocket is connected (the upgrade may still be in progress).
+    pub const fn is_connected(&self) -> bool {
+        self.websocket.is_some()
+    }
+
+    /// Number of successful connects, including the first one.
+    pub const fn connects(&self) -> u64 {
+        self.connects
+    }
+
+    /// Drop the current websocket and schedule the reconnect.
+    fn disconnected(&mut self, err: &Error) {
+        self.websocket = None;
+        let now_ns = self.time_source.current_time_nanos();
+        self.schedule(now_ns, err);
+    }
+
+    fn schedule(&mut self, now_ns: u64, err: &Error) {
+        let backoff_ns = self
+            .initial_backoff_ns
+            .saturating_mul(1 << self.failures.min(32))
+            .min(self.max_backoff_ns);
+        self.failures = self.failures.saturating_add(1);
+        self.redirects = 0;
+        self.redirect = None;
+        self.next_attempt_ns = now_ns.saturating_add(backoff_ns);
+        warn!(
+            "websocket {} failed: {err}, reconnecting in {:?}",
+            self.connection_info,
+            Duration::from_nanos(backoff_ns)
+        );
+    }
+
+    /// Dial if the backoff has elapsed, returns `true` if a new websocket has been created.
+    fn reconnect(&mut self) -> Result<bool, Error> {
+        let now_ns = self.time_source.current_time_nanos();
+        if now_ns < self.next_attempt_ns {
+            return Ok(false);
+        }
+        let (connection_info, endpoint) = match &self.redirect {
+            Some((connection_info, endpoint)) => (connection_info.clone(), Some(endpoint.as_str())),
+            None => (self.connection_info.clone(), None),
+        };
+        let result = (self.connect)(connection_info)
+            .map(|websocket| match endpoint {
+                Some(endpoint) => websocket.with_endpoint(endpoint),
+                None => websocket,
+            })
+            .map_err(Error::from)
+            .and_then(|mut websocket| match self.resubscribe.as_mut() {
+                Some(resubscribe) => resubscribe(&mut websocket).map(|_| websocket),
+                None => Ok(websocket),
+            });
+        match result {
+            Ok(websocket) => {
+                self.websocket = Some(websocket);
+                self.connects += 1;
+                if self.connects > 1 {
+                    info!("websocket {} reconnected", self.connection_info);
+                }
+                Ok(true)
+            }
+            Err(err) => {
+                self.schedule(now_ns, &err);
+                Err(err)
+            }
+        }
+    }
+
+    /// Drop the current websocket and dial the redirect `location` straight away.
+    fn follow(&mut self, location: &str) -> Result<(), Error> {
+        let (current, endpoint) = match &self.redirect {
+            Some((connection_info, endpoint)) => (connection_info, endpoint.as_str()),
+            None => (&self.connection_info, "/"),
+        };
+        let base = Url::parse(&format!("ws://{}:{}{endpoint}", current.host(), current.port()))?;
+        let target = base.join(location)?;
+        let host = target.host_str().ok_or(Error::Protocol("redirect without host"))?;
+        let port = target
+            .port_or_known_default()
+            .ok_or(Error::Protocol("redirect without port"))?;
+        let endpoint = match target.query() {
+            Some(query) => format!("{}?{query}", target.path()),
+            None => target.path().to_owned(),
+        };
+        info!("websocket {} redirected to {host}:{port}{endpoint}", self.connection_info);
+        self.redirect = Some((current.clone().with_address(host, port), endpoint));
+        self.redirects += 1;
+        self.websocket = None;
+        self.next_attempt_ns = 0;
+        Ok(())
+    }
+}
+
+impl<S: Read + Write> ReconnectingWebsocket<S> {
+    /// Same as [`Websocket::receive_next`], dials first if not connected and the backoff has elapsed
+    /// (returning `None` otherwise). The error that failed the connection attempt or the websocket is
+    /// returned once, the websocket is then dropped.
+    pub fn receive_next(&mut self) -> Option<Result<WebsocketFrame, Error>> {
+        if self.websocket.is_none() {
+            match self.reconnect() {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        match self.websocket.as_mut()?.receive_next() {
+            Some(Ok(frame)) => {
+                self.failures = 0;
+                self.redirects = 0;
+                Some(Ok(frame))
+            }
+            Some(Err(Error::Redirect(location))) if self.redirects < self.max_redirects => match self.follow(&location)
+            {
+                Ok(()) => None,
+                Err(err) => {
+                    self.disconnected(&err);
+                    Some(Err(err))
+                }
+            },
+            Some(Err(err)) => {
+                self.disconnected(&err);
+                Some(Err(err))
+            }
+            None => None,
+        }
+    }
+}
+
+impl<S> ConnectionInfoProvider for ReconnectingWebsocket<S> {
+    fn connection_info(&self) -> &ConnectionInfo {
+        &self.connection_info
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct ManualClock(Rc<Cell<u64>>);
+
+    impl TimeSource for ManualClock {
+        fn current_time_nanos(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    /// Delivers a single text frame and then the end of stream.
+    struct OneFrameStream {
+        inbound: Cursor<Vec<u8>>,
+        written: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl Read for OneFrameStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.inbound.read(buf)
+        }
+    }
+
+    impl Write for OneFrameStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_reconnect_with_backoff_and_resubscribe() {
+        const MILLIS: u64 = 1_000_000;
+        let clock = ManualClock::default();
+        let written = Rc::new(RefCell::new(vec![]));
+        let dials = Rc::new(Cell::new(0));
+        let mut ws = ReconnectingWebsocket::new(ConnectionInfo::new("127.0.0.1", 9000), {
+            let (written, dials) = (written.clone(), dials.clone());
+            move |_| {
+                dials.set(dials.get() + 1);
+                if dials.get() == 2 {
+                    return Err(io::Error::from(io::ErrorKind::ConnectionRefused));
+                }
+                let stream = OneFrameStream {
+                    inbound: Cursor::new(vec![0x81, 1, b'a']),
+                    written: written.clone(),
+                };
+                Ok(Websocket::new_with_handshake_complete(stream))
+            }
+        })
+        .with_resubscribe(|ws| ws.send_text(true, Some(b"sub")))
+        .with_backoff(Duration::from_millis(100), Duration::from_millis(150))
+        .with_time_source(clock.clone());
+
+        let next_text = |ws: &mut ReconnectingWebsocket<OneFrameStream>| match ws.receive_next() {
+            Some(Ok(WebsocketFrame::Text(_, data))) => Some(Ok(data.to_vec())),
+            Some(Ok(_)) => panic!("unexpected frame"),
+            Some(Err(err)) => Some(Err(err)),
+            None => None,
+        };
+
+        assert_eq!(Some(b"a".to_vec()), next_text(&mut ws).map(Result::unwrap));
+        assert!(next_text(&mut ws).is_none());
+        assert!(matches!(next_text(&mut ws), Some(Err(Error::Closed))));
+        assert!(!ws.is_connected());
+
+        // the first reconnect waits for the initial backoff and fails
+        assert!(next_text(&mut ws).is_none());
+        clock.0.set(100 * MILLIS);
+        assert!(matches!(next_text(&mut ws), Some(Err(Error::IO(_)))));
+        // the second one waits for the doubled backoff capped at 150ms
+        clock.0.set(249 * MILLIS);
+        assert!(next_text(&mut ws).is_none());
+        clock.0.set(250 * MILLIS);
+        assert_eq!(Some(b"a".to_vec()), next_text(&mut ws).map(Result::unwrap));
+
+        assert_eq!((3, 2), (dials.get(), ws.connects()));
+        // the subscription has been replayed on both connections (2 masked frames of 9 bytes)
+        assert_eq!(18, written.borrow().len());
+    }
+
+    /// Serves the scripted reads one at a time (split if they do not fit the buffer).
+    struct ScriptedStream {
+        connection_info: ConnectionInfo,
+        reads: Vec<Vec<u8>>,
+        written: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl Read for ScriptedStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.reads.is_empty() {
+                return Err(io::Error::from(io::ErrorKind::WouldBlock));
+            }
+            let len = buf.len().min(self.reads[0].len());
+            buf[..len].copy_from_slice(&self.reads[0][..len]);
+            self.reads[0].drain(..len);
+            if self.reads[0].is_empty() {
+                self.reads.remove(0);
+            }
+            Ok(len)
+        }
+    }
+
+    impl Write for ScriptedStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl ConnectionInfoProvider for ScriptedStream {
+        fn connection_info(&self) -> &ConnectionInfo {
+            &self.connection_info
+        }
+    }
+
+    #[test]
+    fn should_follow_upgrade_redirect() {
+        let written = Rc::new(RefCell::new(vec![]));
+        let connect = {
+            let written = written.clone();
+            move |connection_info: ConnectionInfo| {
+                let reads = match connection_info.host() {
+                    "origin.example.com" => vec![
+                        b"HTTP/1.1 301 Moved Permanently\r\nLocation: wss://edge.example.com/v2/ws?id=7\r\n\r\n"
+                            .to_vec(),
+                    ],
+                    _ => vec![
+                        b"HTTP/1.1 101 Switching Protocols\r\n\r\n".to_vec(),
+                        vec![0x81, 1, b'a'],
+                    ],
+                };
+                written.borrow_mut().clear();
+                let stream = ScriptedStream {
+                    connection_info,
+                    reads,
+                    written: written.clone(),
+                };
+                Ok(Websocket::new(stream, "/ws"))
+            }
+        };
+        let next = |ws: &mut ReconnectingWebsocket<ScriptedStream>| {
+            (0..1000).find_map(|_| {
+                ws.receive_next()
+                    .map(|result| result.map(|frame| frame.to_owned().into_payload()))
+            })
+        };
+
+        let mut ws = ReconnectingWebsocket::new(ConnectionInfo::new("origin.example.com", 9000), connect.clone())
+            .with_redirects(1);
+        assert_eq!(b"a".to_vec(), next(&mut ws).unwrap().unwrap());
+        let request = String::from_utf8(written.borrow().clone()).unwrap();
+        assert!(request.starts_with("GET /v2/ws?id=7 HTTP/1.1\r\nHost: edge.example.com\r\n"));
+
+        // not followed by default
+        let mut ws = ReconnectingWebsocket::new(ConnectionInfo::new("origin.example.com", 9000), connect);
+        assert!(matches!(
+            next(&mut ws),
+            Some(Err(Error::Redirect(location))) if location == "wss://edge.example.com/v2/ws?id=7"
+        ));
+    }
+}
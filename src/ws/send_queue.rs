@@ -0,0 +1,274 @@
+use crate::ws::SendOrder;
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::io::ErrorKind::{WouldBlock, WriteZero};
+use std::io::{IoSlice, Read, Write};
+
+pub(crate) const DEFAULT_LOW_WATERMARK: usize = 16 * 1024;
+pub(crate) const DEFAULT_HIGH_WATERMARK: usize = 64 * 1024;
+
+type BackpressureHandler = Box<dyn FnMut(bool)>;
+
+/// Encoded bytes the stream has not accepted yet (it would block), written ahead of anything sent
+/// later. Crossing the high watermark flags the websocket as backpressured until the queue drains
+/// down to the low watermark. With [`SendOrder::ControlFirst`] the control frames are inserted at the
+/// end of the frame being written rather than behind all the queued bytes.
+pub(crate) struct SendQueue {
+    bytes: Vec<u8>,
+    head: usize,
+    low_watermark: usize,
+    high_watermark: usize,
+    backpressured: bool,
+    handler: Option<BackpressureHandler>,
+    order: SendOrder,
+    // offsets (in `bytes`) at which the queued frames end, only tracked with `SendOrder::ControlFirst`
+    boundaries: VecDeque<usize>,
+    // end of the last control frame inserted, the next one follows it
+    control_tail: Option<usize>,
+}
+
+impl SendQueue {
+    pub(crate) fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            head: 0,
+            low_watermark: DEFAULT_LOW_WATERMARK,
+            high_watermark: DEFAULT_HIGH_WATERMARK,
+            backpressured: false,
+            handler: None,
+            order: SendOrder::default(),
+            boundaries: VecDeque::new(),
+            control_tail: None,
+        }
+    }
+
+    pub(crate) fn set_watermarks(&mut self, low: usize, high: usize) {
+        self.high_watermark = high.max(1);
+        self.low_watermark = low.min(self.high_watermark - 1);
+    }
+
+    pub(crate) fn set_handler(&mut self, handler: BackpressureHandler) {
+        self.handler = Some(handler);
+    }
+
+    pub(crate) const fn set_order(&mut self, order: SendOrder) {
+        self.order = order;
+    }
+
+    #[inline]
+    pub(crate) const fn order(&self) -> SendOrder {
+        self.order
+    }
+
+    /// Number of queued bytes.
+    #[inline]
+    pub(crate) const fn len(&self) -> usize {
+        self.bytes.len() - self.head
+    }
+
+    #[inline]
+    pub(crate) const fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[inline]
+    pub(crate) const fn backpressured(&self) -> bool {
+        self.backpressured
+    }
+
+    /// Writer that passes the bytes to the `stream` and queues whatever it does not accept.
+    #[inline]
+    pub(crate) const fn spill<'a, S>(&'a mut self, stream: &'a mut S) -> Spill<'a, S> {
+        Spill {
+            queue: self,
+            stream,
+            control: false,
+        }
+    }
+
+    /// Drop the queued bytes that follow the last control frame inserted ahead of them (if any), used
+    /// once that frame is a close frame as no data frame can follow it.
+    pub(crate) fn truncate_after_control(&mut self) {
+        let Some(tail) = self.control_tail.filter(|&tail| tail >= self.head) else {
+            return;
+        };
+        self.bytes.truncate(tail);
+        self.boundaries.retain(|&boundary| boundary <= tail);
+        if self.backpressured && self.len() <= self.low_watermark {
+            self.backpressured = false;
+            self.notify();
+        }
+    }
+
+    /// Write the queued bytes to the `stream` until it would block.
+    pub(crate) fn drain<S: Write>(&mut self, stream: &mut S) -> io::Result<()> {
+        while !self.is_empty() {
+            match stream.write(&self.bytes[self.head..]) {
+                Ok(0) => return Err(io::Error::new(WriteZero, "unable to drain send queue")),
+                Ok(len) => self.head += len,
+                Err(err) if err.kind() == WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        while self.boundaries.front().is_some_and(|&boundary| boundary < self.head) {
+            self.boundaries.pop_front();
+        }
+        if self.is_empty() {
+            self.bytes.clear();
+            self.head = 0;
+            self.boundaries.clear();
+            self.control_tail = None;
+        } else if self.head >= self.bytes.len() / 2 {
+            self.bytes.drain(..self.head);
+            self.boundaries.iter_mut().for_each(|boundary| *boundary -= self.head);
+            self.control_tail = self
+                .control_tail
+                .filter(|&tail| tail >= self.head)
+                .map(|tail| tail - self.head);
+            self.head = 0;
+        }
+        if self.backpressured && self.len() <= self.low_watermark {
+            self.backpressured = false;
+            self.notify();
+        }
+        Ok(())
+    }
+
+    #[cold]
+    fn push(&mut self, bufs: &[IoSlice<'_>], control: bool) {
+        if control {
+            let at = self.control_insertion_point();
+            let len = bufs.iter().map(|buf| buf.len()).sum::<usize>();
+            self.bytes
+                .splice(at..at, bufs.iter().flat_map(|buf| buf.iter().copied()));
+            self.boundaries
+                .iter_mut()
+                .filter(|boundary| **boundary > at)
+                .for_each(|boundary| *boundary += len);
+            self.control_tail = Some(at + len);
+        } else {
+            for buf in bufs {
+                self.bytes.extend_from_slice(buf);
+            }
+        }
+        if !self.backpressured && self.len() >= self.high_watermark {
+            self.backpressured = true;
+            self.notify();
+        }
+    }
+
+    /// Behind the control frames inserted earlier, otherwise at the end of the frame being written.
+    /// The end of the queue is a frame boundary as the frames are always written whole.
+    #[inline]
+    fn control_insertion_point(&self) -> usize {
+        self.control_tail
+            .filter(|&tail| tail >= self.head)
+            .or_else(|| self.boundaries.front().copied())
+            .unwrap_or(self.bytes.len())
+    }
+
+    /// Record the end of the frame just written.
+    #[inline]
+    fn mark_boundary(&mut self) {
+        if self.order == SendOrder::ControlFirst
+            && !self.is_empty()
+            && self.boundaries.back() != Some(&self.bytes.len())
+        {
+            self.boundaries.push_back(self.bytes.len());
+        }
+    }
+
+    fn notify(&mut self) {
+        if let Some(handler) = self.handler.as_mut() {
+            handler(self.backpressured);
+        }
+    }
+}
+
+impl Debug for SendQueue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SendQueue")
+            .field("len", &self.len())
+            .field("low_watermark", &self.low_watermark)
+            .field("high_watermark", &self.high_watermark)
+            .field("backpressured", &self.backpressured)
+            .field("order", &self.order)
+            .finish()
+    }
+}
+
+/// See [`SendQueue::spill`], the writes never block.
+pub(crate) struct Spill<'a, S> {
+    queue: &'a mut SendQueue,
+    stream: &'a mut S,
+    // the frame being encoded is a control frame that can be written ahead of the queued bytes
+    control: bool,
+}
+
+/// Destination of the encoded frames. Writing whole frames through it lets the send queue know where
+/// the frames it holds end, so that the control frames can be inserted between them.
+pub(crate) trait FrameSink: Write {
+    /// Write the frame produced by `encode`, `control` if its op code is a control one.
+    #[inline]
+    fn encode_frame<F>(&mut self, _control: bool, encode: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut Self) -> io::Result<()>,
+    {
+        encode(self)
+    }
+}
+
+impl FrameSink for Vec<u8> {}
+
+impl<S: Write> FrameSink for Spill<'_, S> {
+    #[inline]
+    fn encode_frame<F>(&mut self, control: bool, encode: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut Self) -> io::Result<()>,
+    {
+        self.control = control && self.queue.order == SendOrder::ControlFirst;
+        let result = encode(self);
+        self.control = false;
+        self.queue.mark_boundary();
+        result
+    }
+}
+
+impl<S: Read> Read for Spill<'_, S> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl<S: Write> Write for Spill<'_, S> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_vectored(&[IoSlice::new(buf)])
+    }
+
+    #[inline]
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        if !self.queue.is_empty() {
+            self.queue.drain(self.stream)?;
+        }
+        if self.queue.is_empty() {
+            match self.stream.write_vectored(bufs) {
+                Err(err) if err.kind() == WouldBlock => {}
+                result => return result,
+            }
+        }
+        // the bytes must follow the ones already queued, the control frames only the frame being written
+        self.queue.push(bufs, self.control);
+        Ok(bufs.iter().map(|buf| buf.len()).sum())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.queue.drain(self.stream)?;
+        if self.queue.is_empty() {
+            self.stream.flush()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+//! Frames sent from other threads through the IO thread.
+//!
+//! [`Websocket::sender`] hands out a [`WebsocketSender`] that can be cloned and moved to any number of
+//! threads (e.g. the strategy threads that must never touch the socket). The messages it enqueues are
+//! written by the thread that owns the websocket, on its next [`Websocket::read_batch`] (or explicitly
+//! with [`Websocket::drain_senders`]), in the order they have been enqueued. Every message goes through
+//! the regular send path, so corking, the handshake and the send queue apply as usual. Only complete
+//! messages can be enqueued, the fragments of the messages enqueued by different threads would be
+//! interleaved on the wire.
+//!
+//! ## Examples
+//! ```no_run
+//! use boomnet::stream::ConnectionInfo;
+//! use boomnet::ws::IntoWebsocket;
+//! use std::thread;
+//!
+//! let mut ws = ConnectionInfo::new("stream.example.com", 80).into_tcp_stream().unwrap().into_websocket("/ws");
+//! let sender = ws.sender();
+//! thread::spawn(move || {
+//!     sender.send_text(br#"{"method":"order.place"}"#).unwrap();
+//! });
+//!
+//! loop {
+//!     // the frames enqueued by the strategy thread are written before the read
+//!     for frame in ws.read_batch().unwrap() {
+//!         frame.unwrap();
+//!     }
+//! }
+//! ```
+
+use crate::ws::{Error, Websocket, protocol};
+use std::io::{Read, Write};
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+
+// upper bound of the frames written per poll, so that busy senders do not starve the reads
+const MAX_DRAINED_PER_POLL: usize = 1024;
+
+#[derive(Debug)]
+pub(crate) struct Mailbox {
+    sender: Sender<Enqueued>,
+    receiver: Receiver<Enqueued>,
+}
+
+#[derive(Debug)]
+struct Enqueued {
+    op_code: u8,
+    body: Vec<u8>,
+}
+
+/// Enqueues outbound frames from any thread, see the [module](self) documentation.
+#[derive(Debug, Clone)]
+pub struct WebsocketSender {
+    sender: Sender<Enqueued>,
+}
+
+impl WebsocketSender {
+    /// Enqueue the complete text message, fails with [`Error::Closed`] once the websocket has been dropped.
+    pub fn send_text(&self, body: &[u8]) -> Result<(), Error> {
+        self.enqueue(protocol::op::TEXT_FRAME, body.to_vec())
+    }
+
+    /// Enqueue the complete binary message, fails with [`Error::Closed`] once the websocket has been
+    /// dropped.
+    pub fn send_binary(&self, body: &[u8]) -> Result<(), Error> {
+        self.enqueue(protocol::op::BINARY_FRAME, body.to_vec())
+    }
+
+    /// Same as [`WebsocketSender::send_text`] with the body moved into the queue rather than copied.
+    pub fn send_text_owned(&self, body: Vec<u8>) -> Result<(), Error> {
+        self.enqueue(protocol::op::TEXT_FRAME, body)
+    }
+
+    /// Same as [`WebsocketSender::send_binary`] with the body moved into the queue rather than copied.
+    pub fn send_binary_owned(&self, body: Vec<u8>) -> Result<(), Error> {
+        self.enqueue(protocol::op::BINARY_FRAME, body)
+    }
+
+    #[inline]
+    fn enqueue(&self, op_code: u8, body: Vec<u8>) -> Result<(), Error> {
+        self.sender.send(Enqueued { op_code, body }).map_err(|_| Error::Closed)
+    }
+}
+
+impl<S> Websocket<S> {
+    /// Handle that enqueues the messages from other threads, the messages are written by the thread
+    /// owning the websocket. Every handle feeds the same queue.
+    pub fn sender(&mut self) -> WebsocketSender {
+        let mailbox = self.mailbox.get_or_insert_with(|| {
+            let (sender, receiver) = mpsc::channel();
+            Mailbox { sender, receiver }
+        });
+        WebsocketSender {
+            sender: mailbox.sender.clone(),
+        }
+    }
+}
+
+impl<S: Read + Write> Websocket<S> {
+    /// Write the messages enqueued by the [`WebsocketSender`]s (at most 1024 per call), returns the
+    /// number of messages written. Called by [`Websocket::read_batch`], the messages are left in the
+    /// queue once the websocket is closing.
+    pub fn drain_senders(&mut self) -> Result<usize, Error> {
+        if self.closing {
+            return Ok(0);
+        }
+        let mut drained = 0;
+        while drained < MAX_DRAINED_PER_POLL {
+            let Some(mailbox) = self.mailbox.as_ref() else {
+                break;
+            };
+            let enqueued = match mailbox.receiver.try_recv() {
+                Ok(enqueued) => enqueued,
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => break,
+            };
+            self.send(true, enqueued.op_code, Some(&enqueued.body))?;
+            drained += 1;
+        }
+        Ok(drained)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ws::tests::ThrottledStream;
+    use crate::ws::{Error, Websocket, encoder, protocol};
+    use std::thread;
+
+    #[test]
+    fn should_write_messages_enqueued_from_other_threads() {
+        let stream = ThrottledStream::unthrottled();
+        let written = stream.written.clone();
+        let mut ws = Websocket::new_with_handshake_complete(stream);
+        let sender = ws.sender();
+        let handles = (0..2)
+            .map(|id| {
+                let sender = sender.clone();
+                thread::spawn(move || sender.send_text(format!("order-{id}").as_bytes()).unwrap())
+            })
+            .collect::<Vec<_>>();
+        handles.into_iter().for_each(|handle| handle.join().unwrap());
+        ws.sender().send_binary_owned(b"cancel".to_vec()).unwrap();
+        assert!(written.borrow().is_empty());
+
+        assert!(ws.read_batch().unwrap().receive_next().is_none());
+        assert_eq!(0, ws.drain_senders().unwrap());
+        let mut expected = vec![];
+        encoder::send(&mut expected, true, protocol::op::BINARY_FRAME, Some(b"cancel")).unwrap();
+        assert!(written.borrow().ends_with(&expected));
+        // two text messages of 7 bytes and the binary message, 6 bytes header each
+        assert_eq!(3 * 6 + 2 * 7 + 6, written.borrow().len());
+
+        drop(ws);
+        assert!(matches!(sender.send_text(b"late"), Err(Error::Closed)));
+    }
+}
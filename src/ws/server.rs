@@ -0,0 +1,408 @@
+//! Server side of the websocket protocol.
+//!
+//! [`WebsocketAcceptor`] performs the server side of the RFC 6455 handshake on an already accepted
+//! stream (plain TCP or TLS) and produces a [`Websocket`] in server mode: inbound client frames are
+//! unmasked and outbound frames are sent without the mask. This makes it possible to build internal
+//! gateways and test servers on top of the same zero-copy frame decoder as the client.
+//!
+//! The handshake reads the request one byte at a time so that frames the client pipelines straight after
+//! the request are left in the stream for the websocket. Extensions and subprotocols requested by the
+//! client are not negotiated.
+//!
+//! ## Examples
+//! ```no_run
+//! use std::net::TcpListener;
+//! use boomnet::ws::WebsocketFrame;
+//! use boomnet::ws::server::WebsocketAcceptor;
+//!
+//! let listener = TcpListener::bind("127.0.0.1:9001").unwrap();
+//! let acceptor = WebsocketAcceptor::new();
+//! let (stream, _) = listener.accept().unwrap();
+//! stream.set_nonblocking(true).unwrap();
+//!
+//! let mut handshake = acceptor.handshake(stream);
+//! while !handshake.poll().unwrap() {}
+//! println!("client connected to {}", handshake.path().unwrap_or("/"));
+//!
+//! let mut ws = handshake.into_websocket().unwrap();
+//! loop {
+//!     while let Some(frame) = ws.receive_next() {
+//!         if let WebsocketFrame::Text(fin, body) = frame.unwrap() {
+//!             ws.send_text(fin, Some(body)).unwrap();
+//!         }
+//!     }
+//! }
+//! ```
+
+use crate::util::NoBlock;
+use crate::ws::decoder::{DecoderConfig, Role};
+use crate::ws::{Error, Limits, Recovery, Validation, Websocket};
+use base64::Engine;
+use base64::engine::general_purpose;
+use httparse::Request;
+use std::io;
+use std::io::ErrorKind::WriteZero;
+use std::io::{Read, Write};
+
+// appended to the client key before hashing (RFC 6455 section 1.3)
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const MAX_REQUEST_SIZE: usize = 8192;
+const BAD_REQUEST: &[u8] = b"HTTP/1.1 400 Bad Request\r\nSec-WebSocket-Version: 13\r\nContent-Length: 0\r\n\r\n";
+
+/// Accepts websocket connections on streams accepted by the listener.
+#[derive(Debug, Clone, Copy)]
+pub struct WebsocketAcceptor {
+    config: DecoderConfig,
+}
+
+impl WebsocketAcceptor {
+    /// Create acceptor with the default decoder settings.
+    pub fn new() -> Self {
+        Self {
+            config: DecoderConfig {
+                role: Role::Server,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Strategy used by the frame decoder of the accepted websockets, see [`Websocket::with_recovery`].
+    pub fn with_recovery(self, recovery: Recovery) -> Self {
+        Self {
+            config: DecoderConfig {
+                recovery,
+                ..self.config
+            },
+        }
+    }
+
+    /// Validation of the frames received by the accepted websockets, see [`Websocket::with_validation`].
+    /// With [`Validation::Strict`] unmasked client frames are rejected.
+    pub fn with_validation(self, validation: Validation) -> Self {
+        Self {
+            config: DecoderConfig {
+                validation,
+                ..self.config
+            },
+        }
+    }
+
+    /// Size limits of the frames and messages received by the accepted websockets, see
+    /// [`Websocket::with_limits`]. The handshake request is always bounded by its own limit.
+    pub fn with_limits(self, limits: Limits) -> Self {
+        Self {
+            config: DecoderConfig { limits, ..self.config },
+        }
+    }
+
+    /// Start the handshake on the accepted `stream`, it is driven by [`ServerHandshake::poll`].
+    pub fn handshake<S>(&self, stream: S) -> ServerHandshake<S> {
+        ServerHandshake {
+            stream,
+            config: self.config,
+            state: HandshakeState::ReadingRequest,
+            request: Vec::with_capacity(1024),
+            response: Vec::new(),
+            bytes_sent: 0,
+            path: None,
+            headers: Vec::new(),
+        }
+    }
+
+    /// Perform the whole handshake on the accepted `stream`, spinning if the stream is non-blocking.
+    pub fn accept<S: Read + Write>(&self, stream: S) -> Result<Websocket<S>, Error> {
+        let mut handshake = self.handshake(stream);
+        while !handshake.poll()? {}
+        handshake.into_websocket()
+    }
+}
+
+impl Default for WebsocketAcceptor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum HandshakeState {
+    ReadingRequest,
+    SendingResponse,
+    Completed,
+}
+
+/// Server side handshake in progress on the accepted stream.
+#[derive(Debug)]
+pub struct ServerHandshake<S> {
+    stream: S,
+    config: DecoderConfig,
+    state: HandshakeState,
+    request: Vec<u8>,
+    response: Vec<u8>,
+    bytes_sent: usize,
+    path: Option<String>,
+    headers: Vec<(String, String)>,
+}
+
+impl<S> ServerHandshake<S> {
+    /// Request target (e.g. `/ws?streams=btcusdt@trade`), available once the request has been received.
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    /// Value of the request header `name` (case-insensitive), e.g. to authenticate the client.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Checks if the handshake response has been sent.
+    pub const fn completed(&self) -> bool {
+        matches!(self.state, HandshakeState::Completed)
+    }
+
+    /// Convert into server side [`Websocket`] once the handshake has completed.
+    pub fn into_websocket(self) -> Result<Websocket<S>, Error> {
+        if !self.completed() {
+            return Err(Error::Protocol("websocket handshake has not completed"));
+        }
+        Ok(Websocket::new_accepted(self.stream, self.config))
+    }
+}
+
+impl<S: Read + Write> ServerHandshake<S> {
+    /// Drive the handshake without blocking, returns `true` once the response has been sent. An
+    /// invalid request is answered with `400 Bad Request` and reported as [`Error::Protocol`].
+    pub fn poll(&mut self) -> Result<bool, Error> {
+        loop {
+            match self.state {
+                HandshakeState::ReadingRequest => {
+                    let mut byte = [0u8; 1];
+                    if self.stream.read(&mut byte).no_block()? == 0 {
+                        return Ok(false);
+                    }
+                    self.request.push(byte[0]);
+                    if self.request.ends_with(b"\r\n\r\n") {
+                        self.prepare_response()?;
+                        self.state = HandshakeState::SendingResponse;
+                    } else if self.request.len() > MAX_REQUEST_SIZE {
+                        return Err(self.reject("websocket handshake request too large"));
+                    }
+                }
+                HandshakeState::SendingResponse => {
+                    let remaining = &self.response[self.bytes_sent..];
+                    if remaining.is_empty() {
+                        self.stream.flush().no_block()?;
+                        self.state = HandshakeState::Completed;
+                        continue;
+                    }
+                    match self.stream.write(remaining) {
+                        Ok(0) => Err(io::Error::from(WriteZero))?,
+                        Ok(len) => self.bytes_sent += len,
+                        Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                        Err(err) => Err(err)?,
+                    }
+                }
+                HandshakeState::Completed => return Ok(true),
+            }
+        }
+    }
+
+    fn prepare_response(&mut self) -> Result<(), Error> {
+        let parsed = {
+            let mut headers = [httparse::EMPTY_HEADER; 64];
+            let mut request = Request::new(&mut headers);
+            match request.parse(&self.request) {
+                Ok(status) if status.is_complete() && request.method == Some("GET") && request.version == Some(1) => {
+                    let headers = request
+                        .headers
+                        .iter()
+                        .map(|header| (header.name.to_owned(), String::from_utf8_lossy(header.value).into_owned()))
+                        .collect::<Vec<_>>();
+                    Some((request.path.map(str::to_owned), headers))
+                }
+                _ => None,
+            }
+        };
+        let Some((path, headers)) = parsed else {
+            return Err(self.reject("websocket handshake request must be GET over HTTP/1.1"));
+        };
+        self.path = path;
+        self.headers = headers;
+
+        if !self
+            .header("Upgrade")
+            .is_some_and(|value| has_token(value, "websocket"))
+            || !self
+                .header("Connection")
+                .is_some_and(|value| has_token(value, "upgrade"))
+        {
+            return Err(self.reject("missing websocket upgrade headers"));
+        }
+        if self.header("Sec-WebSocket-Version") != Some("13") {
+            return Err(self.reject("unsupported websocket version"));
+        }
+        let key = match self.header("Sec-WebSocket-Key") {
+            Some(key) if matches!(general_purpose::STANDARD.decode(key), Ok(nonce) if nonce.len() == 16) => key,
+            _ => return Err(self.reject("invalid Sec-WebSocket-Key")),
+        };
+        self.response = format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n\r\n",
+            accept_key(key)
+        )
+        .into_bytes();
+        Ok(())
+    }
+
+    /// Best effort `400 Bad Request` response.
+    #[cold]
+    fn reject(&mut self, reason: &'static str) -> Error {
+        let _ = self.stream.write_all(BAD_REQUEST);
+        let _ = self.stream.flush();
+        Error::Protocol(reason)
+    }
+}
+
+/// Checks if the comma separated header `value` contains the `token` (case-insensitive).
+fn has_token(value: &str, token: &str) -> bool {
+    value.split(',').any(|item| item.trim().eq_ignore_ascii_case(token))
+}
+
+/// `Sec-WebSocket-Accept` value for the client `key`.
+fn accept_key(key: &str) -> String {
+    general_purpose::STANDARD.encode(sha1(format!("{key}{GUID}").as_bytes()))
+}
+
+/// SHA-1 as required by the handshake (RFC 3174), not used for anything security sensitive.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (value, add) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(add);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (bytes, value) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::WebsocketFrame;
+    use std::io::ErrorKind::WouldBlock;
+
+    struct Duplex {
+        inbound: io::Cursor<Vec<u8>>,
+        outbound: Vec<u8>,
+    }
+
+    impl Duplex {
+        fn new(inbound: &[u8]) -> Self {
+            Self {
+                inbound: io::Cursor::new(inbound.to_vec()),
+                outbound: vec![],
+            }
+        }
+    }
+
+    impl Read for Duplex {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.inbound.read(buf)? {
+                0 => Err(io::Error::from(WouldBlock)),
+                read => Ok(read),
+            }
+        }
+    }
+
+    impl Write for Duplex {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.outbound.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn request(version: &str) -> String {
+        format!(
+            "GET /chat HTTP/1.1\r\nHost: server.example.com\r\nUpgrade: websocket\r\nConnection: keep-alive, Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: {version}\r\n\r\n"
+        )
+    }
+
+    #[test]
+    fn should_compute_accept_key() {
+        // example from RFC 6455 section 1.3
+        assert_eq!("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=", accept_key("dGhlIHNhbXBsZSBub25jZQ=="));
+    }
+
+    #[test]
+    fn should_accept_handshake_and_exchange_frames() {
+        // masked "Hello" pipelined straight after the request (RFC 6455 section 5.7)
+        let hello = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        let stream = Duplex::new(&[request("13").as_bytes(), &hello].concat());
+        let mut handshake = WebsocketAcceptor::new().handshake(stream);
+        assert!(handshake.poll().unwrap());
+        assert_eq!(Some("/chat"), handshake.path());
+        assert_eq!(Some("server.example.com"), handshake.header("host"));
+
+        let mut ws = handshake.into_websocket().unwrap();
+        assert!(matches!(ws.receive_next(), Some(Ok(WebsocketFrame::Text(true, b"Hello")))));
+        ws.send_text(true, Some(b"Hi")).unwrap();
+
+        let response = "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                        Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n";
+        // server frames are not masked
+        let expected = [response.as_bytes(), &[0x81, 0x02], b"Hi"].concat();
+        assert_eq!(expected, ws.stream.outbound);
+    }
+
+    #[test]
+    fn should_reject_unsupported_version() {
+        let mut handshake = WebsocketAcceptor::new().handshake(Duplex::new(request("8").as_bytes()));
+        assert!(matches!(handshake.poll(), Err(Error::Protocol(_))));
+        assert_eq!(BAD_REQUEST, handshake.stream.outbound);
+        assert!(handshake.into_websocket().is_err());
+    }
+}
@@ -0,0 +1,91 @@
+/// Validates the UTF-8 of a text message fragment by fragment, a character can be split across the
+/// fragments. Fails as soon as the received bytes can not start a valid character.
+#[derive(Debug, Default)]
+pub(crate) struct Utf8Validator {
+    // leading bytes of the character split across the fragments
+    pending: [u8; 4],
+    len: usize,
+}
+
+impl Utf8Validator {
+    /// Checks the next `fragment` of the message, the last one (`fin`) must not end with an
+    /// incomplete character.
+    pub(crate) fn validate(&mut self, fragment: &[u8], fin: bool) -> bool {
+        let mut fragment = fragment;
+        if self.len > 0 {
+            let width = width(self.pending[0]);
+            let take = (width - self.len).min(fragment.len());
+            self.pending[self.len..self.len + take].copy_from_slice(&fragment[..take]);
+            self.len += take;
+            fragment = &fragment[take..];
+            if self.len < width {
+                // still incomplete, but must be a valid prefix
+                let prefix =
+                    matches!(std::str::from_utf8(&self.pending[..self.len]), Err(err) if err.error_len().is_none());
+                return self.done(prefix && !fin);
+            }
+            if std::str::from_utf8(&self.pending[..width]).is_err() {
+                return self.done(false);
+            }
+            self.len = 0;
+        }
+        match std::str::from_utf8(fragment) {
+            Ok(_) => true,
+            Err(err) if err.error_len().is_none() && !fin => {
+                let rest = &fragment[err.valid_up_to()..];
+                self.pending[..rest.len()].copy_from_slice(rest);
+                self.len = rest.len();
+                true
+            }
+            Err(_) => self.done(false),
+        }
+    }
+
+    #[inline]
+    fn done(&mut self, valid: bool) -> bool {
+        if !valid {
+            self.len = 0;
+        }
+        valid
+    }
+}
+
+/// Number of bytes of the character starting with the `lead` byte, only called for the lead byte of
+/// an incomplete (so far valid) character.
+#[inline]
+const fn width(lead: u8) -> usize {
+    match lead {
+        0xF0.. => 4,
+        0xE0.. => 3,
+        _ => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_validate_characters_split_across_fragments() {
+        let message = "κόσμε €𝄞".as_bytes();
+        for split in 0..=message.len() {
+            let mut validator = Utf8Validator::default();
+            assert!(validator.validate(&message[..split], false), "split at {split}");
+            assert!(validator.validate(&message[split..], true), "split at {split}");
+        }
+
+        // incomplete at the end of the message
+        let mut validator = Utf8Validator::default();
+        assert!(validator.validate(&[0xE2, 0x82], false));
+        assert!(!validator.validate(&[], true));
+
+        // fails fast on the invalid continuation of the split character
+        let mut validator = Utf8Validator::default();
+        assert!(validator.validate(&[0xF0], false));
+        assert!(!validator.validate(&[0x28], false));
+
+        // surrogates and overlong encodings
+        assert!(!Utf8Validator::default().validate(&[0xED, 0xA0, 0x80], true));
+        assert!(!Utf8Validator::default().validate(&[0xC0, 0xAF], true));
+    }
+}
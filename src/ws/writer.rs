@@ -0,0 +1,177 @@
+//! Streaming of a large message as a sequence of fragments.
+//!
+//! A [`MessageWriter`] sends every fragment as soon as it has been written, the first one as a text
+//! (or binary) frame and the following ones as continuation frames, with the `fin` flag set only on the
+//! last one. The whole message never has to be held in memory, e.g. when uploading a large snapshot
+//! or a signed batch request that is serialized piece by piece. The writer borrows the websocket
+//! mutably, so no other data frame can be interleaved with the fragments (control frames such as the
+//! pending pong may be, as allowed by RFC 6455 section 5.4).
+//!
+//! ## Examples
+//! ```no_run
+//! use std::io::{Read, Write};
+//! use boomnet::ws::Websocket;
+//!
+//! fn upload<S: Read + Write>(ws: &mut Websocket<S>, levels: &[(f64, f64)]) -> std::io::Result<()> {
+//!     let mut writer = ws.text_writer();
+//!     writer.write_all(br#"{"op":"snapshot","levels":["#)?;
+//!     for (i, (price, quantity)) in levels.iter().enumerate() {
+//!         let separator = if i == 0 { "" } else { "," };
+//!         write!(writer, "{separator}[{price},{quantity}]")?;
+//!     }
+//!     writer.finish_with(b"]}")?;
+//!     Ok(())
+//! }
+//! ```
+
+use crate::ws::{Error, Websocket, protocol};
+use std::io;
+use std::io::{Read, Write};
+
+impl<S: Read + Write> Websocket<S> {
+    /// Stream a text message as a sequence of fragments, see [`MessageWriter`].
+    pub fn text_writer(&mut self) -> MessageWriter<'_, S> {
+        MessageWriter::new(self, protocol::op::TEXT_FRAME)
+    }
+
+    /// Stream a binary message as a sequence of fragments, see [`MessageWriter`].
+    pub fn binary_writer(&mut self) -> MessageWriter<'_, S> {
+        MessageWriter::new(self, protocol::op::BINARY_FRAME)
+    }
+}
+
+/// Writer sending every (non-empty) write as a separate fragment of the message, the message is
+/// completed by [`MessageWriter::finish`] or [`MessageWriter::finish_with`]. A writer dropped without
+/// being finished completes the message with an empty final fragment and ignores any error, finish it
+/// explicitly to handle them.
+pub struct MessageWriter<'a, S: Read + Write> {
+    websocket: &'a mut Websocket<S>,
+    // op code of the next fragment, continuation once the first fragment has been sent
+    op_code: u8,
+    finished: bool,
+}
+
+impl<'a, S: Read + Write> MessageWriter<'a, S> {
+    const fn new(websocket: &'a mut Websocket<S>, op_code: u8) -> Self {
+        Self {
+            websocket,
+            op_code,
+            finished: false,
+        }
+    }
+
+    /// Send the `fragment` of the message, empty fragments are not sent.
+    pub fn write_fragment(&mut self, fragment: &[u8]) -> Result<(), Error> {
+        if fragment.is_empty() {
+            return Ok(());
+        }
+        self.send(false, fragment)
+    }
+
+    /// Complete the message with an empty final fragment.
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.complete(&[])
+    }
+
+    /// Complete the message with the `last` fragment, saves the empty final frame sent by
+    /// [`MessageWriter::finish`].
+    pub fn finish_with(mut self, last: &[u8]) -> Result<(), Error> {
+        self.complete(last)
+    }
+
+    /// Checks if at least one fragment has been sent.
+    pub const fn started(&self) -> bool {
+        self.op_code == protocol::op::CONTINUATION_FRAME
+    }
+
+    #[inline]
+    fn complete(&mut self, last: &[u8]) -> Result<(), Error> {
+        self.finished = true;
+        self.send(true, last)
+    }
+
+    #[inline]
+    fn send(&mut self, fin: bool, fragment: &[u8]) -> Result<(), Error> {
+        self.websocket.send(fin, self.op_code, Some(fragment))?;
+        self.op_code = protocol::op::CONTINUATION_FRAME;
+        Ok(())
+    }
+}
+
+impl<S: Read + Write> Write for MessageWriter<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_fragment(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<S: Read + Write> Drop for MessageWriter<'_, S> {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.complete(&[]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ws::{Websocket, encoder, protocol};
+    use std::io;
+    use std::io::{Read, Write};
+
+    #[derive(Default)]
+    struct Socket {
+        written: Vec<u8>,
+    }
+
+    impl Read for Socket {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::from(io::ErrorKind::WouldBlock))
+        }
+    }
+
+    impl Write for Socket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_stream_message_as_fragments() {
+        use protocol::op::{BINARY_FRAME, CONTINUATION_FRAME, TEXT_FRAME};
+        let mut ws = Websocket::new_with_handshake_complete(Socket::default());
+
+        let mut writer = ws.text_writer();
+        assert!(!writer.started());
+        writer.write_all(b"[1,").unwrap();
+        writer.write_all(b"").unwrap();
+        write!(writer, "{}", 2).unwrap();
+        assert!(writer.started());
+        writer.finish_with(b"]").unwrap();
+        // dropped without finishing, completed with an empty final fragment
+        ws.binary_writer().write_fragment(b"blob").unwrap();
+        // nothing written, single final frame
+        ws.text_writer().finish().unwrap();
+
+        let mut expected = vec![];
+        for (fin, op_code, fragment) in [
+            (false, TEXT_FRAME, &b"[1,"[..]),
+            (false, CONTINUATION_FRAME, b"2"),
+            (true, CONTINUATION_FRAME, b"]"),
+            (false, BINARY_FRAME, b"blob"),
+            (true, CONTINUATION_FRAME, b""),
+            (true, TEXT_FRAME, b""),
+        ] {
+            encoder::send(&mut expected, fin, op_code, Some(fragment)).unwrap();
+        }
+        assert_eq!(expected, ws.stream().written);
+    }
+}